@@ -0,0 +1,520 @@
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::crypto::{HmacSha256, Signer, Verifier};
+use crate::state_dir::StatePaths;
+use crate::telemetry_router::sha256_hex;
+use crate::time::unix_time_ms;
+
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+#[derive(Clone)]
+pub struct AuditConfig {
+    pub path: PathBuf,
+    /// Size at which the active file is rotated to `<path>.1`.
+    pub max_bytes: u64,
+    /// Rotated files kept besides the active one.
+    pub max_rotated: usize,
+    /// Keys the chain and its head with HMAC-SHA256; without it links are plain SHA-256,
+    /// which anyone able to write the files can recompute.
+    pub key: Option<Vec<u8>>,
+}
+
+impl std::fmt::Debug for AuditConfig {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        formatter
+            .debug_struct("AuditConfig")
+            .field("path", &self.path)
+            .field("max_bytes", &self.max_bytes)
+            .field("max_rotated", &self.max_rotated)
+            .field("key", &self.key.as_ref().map(|_| "<redacted>"))
+            .finish()
+    }
+}
+
+impl AuditConfig {
+    pub fn from_env() -> Self {
+        let path = env::var("AGENT_AUDIT_LOG_PATH")
+            .ok()
+            .filter(|value| !value.trim().is_empty())
+            .map(PathBuf::from)
+            .unwrap_or_else(|| StatePaths::from_env().root.join("audit").join("audit.jsonl"));
+        let max_bytes = env::var("AGENT_AUDIT_MAX_BYTES")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .filter(|value| *value > 0)
+            .unwrap_or(10 * 1024 * 1024);
+        let max_rotated = env::var("AGENT_AUDIT_MAX_FILES")
+            .ok()
+            .and_then(|value| value.parse::<usize>().ok())
+            .unwrap_or(5);
+        let key = env::var("AGENT_AUDIT_KEY")
+            .ok()
+            .filter(|value| !value.is_empty())
+            .map(String::into_bytes);
+        Self {
+            path,
+            max_bytes,
+            max_rotated,
+            key,
+        }
+    }
+
+    /// Signed pointer to the newest record, kept next to the active file.
+    pub fn head_path(&self) -> PathBuf {
+        let mut name = self.path.as_os_str().to_os_string();
+        name.push(".head");
+        PathBuf::from(name)
+    }
+}
+
+/// Security-relevant decision worth keeping for forensic review.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuditEvent {
+    PolicyLoaded { version: String },
+    PolicyRejected { reason: String },
+    CommandRejected { command_id: String, reason: String },
+    ExecutionAccepted { command_id: String, action: String },
+    TrustBundleFailed { failures: Vec<String> },
+    UpdatePhase { version: String, phase: String, reason: Option<String> },
+}
+
+impl AuditEvent {
+    fn kind(&self) -> &'static str {
+        match self {
+            Self::PolicyLoaded { .. } => "policy_loaded",
+            Self::PolicyRejected { .. } => "policy_rejected",
+            Self::CommandRejected { .. } => "command_rejected",
+            Self::ExecutionAccepted { .. } => "execution_accepted",
+            Self::TrustBundleFailed { .. } => "trust_bundle_failed",
+            Self::UpdatePhase { .. } => "update_phase",
+        }
+    }
+
+    fn subject_and_detail(&self) -> (String, String) {
+        match self {
+            Self::PolicyLoaded { version } => (version.clone(), String::new()),
+            Self::PolicyRejected { reason } => (String::new(), reason.clone()),
+            Self::CommandRejected { command_id, reason } => (command_id.clone(), reason.clone()),
+            Self::ExecutionAccepted { command_id, action } => (command_id.clone(), action.clone()),
+            Self::TrustBundleFailed { failures } => (String::new(), failures.join("; ")),
+            Self::UpdatePhase { version, phase, reason } => (
+                version.clone(),
+                match reason {
+                    Some(reason) => format!("{}: {}", phase, reason),
+                    None => phase.clone(),
+                },
+            ),
+        }
+    }
+}
+
+/// The hashed part of a record. Field order is fixed by the struct, so re-serialising a
+/// parsed record reproduces the bytes that were hashed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct AuditBody {
+    seq: u64,
+    at_unix_ms: u64,
+    kind: String,
+    subject: String,
+    detail: String,
+    prev_hash: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct AuditRecord {
+    #[serde(flatten)]
+    body: AuditBody,
+    hash: String,
+}
+
+fn chain_hash(key: Option<&[u8]>, prev_hash: &str, body_json: &str) -> String {
+    let payload = format!("{}{}", prev_hash, body_json);
+    match key {
+        Some(key) => HmacSha256::new(key).sign(payload.as_bytes()),
+        None => sha256_hex(payload.as_bytes()),
+    }
+}
+
+fn chain_hash_matches(key: Option<&[u8]>, prev_hash: &str, body_json: &str, hash: &str) -> bool {
+    match key {
+        Some(key) => HmacSha256::new(key).verify(format!("{}{}", prev_hash, body_json).as_bytes(), hash),
+        None => chain_hash(None, prev_hash, body_json) == hash,
+    }
+}
+
+/// Newest record of the chain, rewritten after every append. Records removed from the tail
+/// leave the head pointing past the end of the files.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct AuditHead {
+    seq: u64,
+    hash: String,
+    mac: String,
+}
+
+impl AuditHead {
+    fn new(key: Option<&[u8]>, seq: u64, hash: &str) -> Self {
+        Self {
+            seq,
+            hash: hash.to_string(),
+            mac: chain_hash(key, hash, &seq.to_string()),
+        }
+    }
+
+    fn is_authentic(&self, key: Option<&[u8]>) -> bool {
+        chain_hash_matches(key, &self.hash, &self.seq.to_string(), &self.mac)
+    }
+}
+
+fn write_head(path: &Path, head: &AuditHead) -> Result<(), String> {
+    let json = serde_json::to_vec(head).map_err(|err| err.to_string())?;
+    let mut staging = path.as_os_str().to_os_string();
+    staging.push(".tmp");
+    let staging = PathBuf::from(staging);
+    let mut file = File::create(&staging).map_err(|err| format!("Unable to write {}: {}", staging.display(), err))?;
+    file.write_all(&json)
+        .and_then(|_| file.sync_data())
+        .map_err(|err| format!("Unable to write {}: {}", staging.display(), err))?;
+    fs::rename(&staging, path).map_err(|err| format!("Unable to move {} into place: {}", path.display(), err))
+}
+
+fn read_head(path: &Path) -> Result<Option<AuditHead>, String> {
+    match fs::read(path) {
+        Ok(raw) => serde_json::from_slice(&raw)
+            .map(Some)
+            .map_err(|err| format!("Malformed audit head {}: {}", path.display(), err)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(format!("Unable to read {}: {}", path.display(), err)),
+    }
+}
+
+/// Append-only JSON-lines log where each record hashes the previous record's hash with its
+/// own body. Rotated files continue the chain, so the set verifies as one sequence.
+#[derive(Debug)]
+pub struct AuditLog {
+    config: AuditConfig,
+    next_seq: u64,
+    last_hash: String,
+}
+
+impl AuditLog {
+    /// Open the log, resuming the chain from the newest valid record on disk. Malformed
+    /// lines at the end, such as one torn by a crash mid-append, are skipped; if they sit in
+    /// the active file, that file is rotated so new records start on a clean line.
+    pub fn open(config: AuditConfig) -> Result<Self, String> {
+        if let Some(parent) = config.path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent).map_err(|err| format!("Unable to create {}: {}", parent.display(), err))?;
+        }
+        let mut log = Self {
+            config,
+            next_seq: 0,
+            last_hash: GENESIS_HASH.to_string(),
+        };
+        for path in chain_files(&log.config.path).iter().rev() {
+            let tail = last_record(path)?;
+            if tail.torn {
+                warn!(path = %path.display(), "audit log ends in a malformed record; resuming after the last valid one");
+                if path == &log.config.path {
+                    log.rotate()?;
+                }
+            }
+            if let Some(record) = tail.record {
+                log.next_seq = record.body.seq + 1;
+                log.last_hash = record.hash;
+                break;
+            }
+        }
+        Ok(log)
+    }
+
+    pub fn record(&mut self, event: &AuditEvent) -> Result<(), String> {
+        let (subject, detail) = event.subject_and_detail();
+        let body = AuditBody {
+            seq: self.next_seq,
+            at_unix_ms: unix_time_ms(),
+            kind: event.kind().to_string(),
+            subject,
+            detail,
+            prev_hash: self.last_hash.clone(),
+        };
+        let body_json = serde_json::to_string(&body).map_err(|err| err.to_string())?;
+        let hash = chain_hash(self.config.key.as_deref(), &self.last_hash, &body_json);
+        let line = serde_json::to_string(&AuditRecord { body, hash: hash.clone() }).map_err(|err| err.to_string())?;
+
+        let current_len = fs::metadata(&self.config.path).map(|metadata| metadata.len()).unwrap_or(0);
+        if current_len > 0 && current_len + line.len() as u64 + 1 > self.config.max_bytes {
+            self.rotate()?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.config.path)
+            .map_err(|err| format!("Unable to open {}: {}", self.config.path.display(), err))?;
+        writeln!(file, "{}", line).map_err(|err| format!("Unable to append audit record: {}", err))?;
+        file.sync_data().map_err(|err| format!("Unable to sync audit log: {}", err))?;
+        write_head(&self.config.head_path(), &AuditHead::new(self.config.key.as_deref(), self.next_seq, &hash))?;
+        self.next_seq += 1;
+        self.last_hash = hash;
+        Ok(())
+    }
+
+    /// Shift `<path>.N` up by one, dropping the oldest, and move the active file to `.1`.
+    fn rotate(&self) -> Result<(), String> {
+        let path = &self.config.path;
+        if self.config.max_rotated == 0 {
+            return fs::remove_file(path).map_err(|err| format!("Unable to rotate audit log: {}", err));
+        }
+        let _ = fs::remove_file(rotated_path(path, self.config.max_rotated));
+        for index in (1..self.config.max_rotated).rev() {
+            let from = rotated_path(path, index);
+            if from.exists() {
+                fs::rename(&from, rotated_path(path, index + 1))
+                    .map_err(|err| format!("Unable to rotate audit log: {}", err))?;
+            }
+        }
+        fs::rename(path, rotated_path(path, 1)).map_err(|err| format!("Unable to rotate audit log: {}", err))
+    }
+}
+
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(format!(".{}", index));
+    PathBuf::from(name)
+}
+
+/// Existing files of the chain, oldest first, ending with the active file.
+fn chain_files(path: &Path) -> Vec<PathBuf> {
+    let mut files = (1..)
+        .map(|index| rotated_path(path, index))
+        .take_while(|candidate| candidate.exists())
+        .collect::<Vec<PathBuf>>();
+    files.reverse();
+    if path.exists() {
+        files.push(path.to_path_buf());
+    }
+    files
+}
+
+/// Non-empty lines of `path` with their 1-based line numbers.
+fn record_lines(path: &Path) -> Result<Vec<(usize, String)>, String> {
+    let file = File::open(path).map_err(|err| format!("Unable to read {}: {}", path.display(), err))?;
+    let mut lines = Vec::new();
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|err| format!("Unable to read {}: {}", path.display(), err))?;
+        if !line.trim().is_empty() {
+            lines.push((index + 1, line));
+        }
+    }
+    Ok(lines)
+}
+
+struct FileTail {
+    record: Option<AuditRecord>,
+    /// Lines after `record` did not parse.
+    torn: bool,
+}
+
+fn last_record(path: &Path) -> Result<FileTail, String> {
+    let mut lines = record_lines(path)?;
+    let mut torn = false;
+    while let Some((_, line)) = lines.pop() {
+        match serde_json::from_str::<AuditRecord>(&line) {
+            Ok(record) => return Ok(FileTail { record: Some(record), torn }),
+            Err(_) => torn = true,
+        }
+    }
+    Ok(FileTail { record: None, torn })
+}
+
+/// Verify the chain across `config.path` and its rotated files against the signed head.
+/// Returns the number of records checked, or the first place the chain breaks: an edited
+/// record, a removed record, a missing link between files, or records missing from the
+/// tail. The oldest kept record anchors the chain, since older files may have been rotated
+/// away. A malformed last line of a file is a torn write and is skipped, as long as the
+/// chain carries on from the record before it.
+pub fn verify_chain(config: &AuditConfig) -> Result<u64, String> {
+    let key = config.key.as_deref();
+    let head_path = config.head_path();
+    let head = read_head(&head_path)?;
+    if let Some(head) = &head {
+        if !head.is_authentic(key) {
+            return Err(format!("{}: head signature does not match", head_path.display()));
+        }
+    }
+    let mut head_found = false;
+    let mut expected: Option<(u64, String)> = None;
+    let mut checked = 0_u64;
+    for file_path in chain_files(&config.path) {
+        let lines = record_lines(&file_path)?;
+        let last_line = lines.last().map(|(line_number, _)| *line_number);
+        for (line_number, line) in lines {
+            let location = format!("{}:{}", file_path.display(), line_number);
+            let record = match serde_json::from_str::<AuditRecord>(&line) {
+                Ok(record) => record,
+                Err(_) if Some(line_number) == last_line => continue,
+                Err(err) => return Err(format!("{}: malformed record: {}", location, err)),
+            };
+            if let Some((seq, prev_hash)) = &expected {
+                if record.body.seq != *seq {
+                    return Err(format!("{}: expected seq {}, found {}", location, seq, record.body.seq));
+                }
+                if record.body.prev_hash != *prev_hash {
+                    return Err(format!("{}: previous hash does not match", location));
+                }
+            } else if record.body.seq == 0 && record.body.prev_hash != GENESIS_HASH {
+                return Err(format!("{}: first record does not start the chain", location));
+            }
+            let body_json = serde_json::to_string(&record.body).map_err(|err| err.to_string())?;
+            if !chain_hash_matches(key, &record.body.prev_hash, &body_json, &record.hash) {
+                return Err(format!("{}: record hash does not match its contents", location));
+            }
+            if let Some(head) = head.as_ref().filter(|head| head.seq == record.body.seq) {
+                if head.hash != record.hash {
+                    return Err(format!("{}: record does not match the head", location));
+                }
+                head_found = true;
+            }
+            expected = Some((record.body.seq + 1, record.hash));
+            checked += 1;
+        }
+    }
+
+    // The head is written after its record, so a crash in between leaves it one behind.
+    match (head, expected) {
+        (None, Some(_)) => Err(format!("{}: head is missing", head_path.display())),
+        (Some(head), Some((next_seq, _))) if !head_found && head.seq + 1 >= next_seq => Err(format!(
+            "log truncated: head points at seq {}, last record is seq {}",
+            head.seq,
+            next_seq - 1
+        )),
+        (Some(head), None) => Err(format!("log truncated: head points at seq {}, no records remain", head.seq)),
+        _ => Ok(checked),
+    }
+}
+
+fn installed() -> &'static Mutex<Option<AuditLog>> {
+    static AUDIT: OnceLock<Mutex<Option<AuditLog>>> = OnceLock::new();
+    AUDIT.get_or_init(|| Mutex::new(None))
+}
+
+/// Make `log` the agent-wide audit trail used by [`record`].
+pub fn install(log: AuditLog) {
+    let mut slot = installed().lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    *slot = Some(log);
+}
+
+/// Append `event` to the installed audit trail. Does nothing before [`install`], so unit
+/// tests that pass through decision points leave no files behind.
+pub fn record(event: AuditEvent) {
+    let mut slot = installed().lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    if let Some(log) = slot.as_mut() {
+        if let Err(err) = log.record(&event) {
+            warn!(error = %err, kind = event.kind(), "failed to append audit record");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    use super::{rotated_path, verify_chain, AuditConfig, AuditEvent, AuditLog};
+    use crate::time::unix_time_ms;
+
+    fn temp_config(label: &str, max_bytes: u64) -> AuditConfig {
+        let dir = std::env::temp_dir().join(format!("audit-{}-{}-{}", label, std::process::id(), unix_time_ms()));
+        AuditConfig {
+            path: dir.join("audit.jsonl"),
+            max_bytes,
+            max_rotated: 5,
+            key: Some(b"audit-test-key".to_vec()),
+        }
+    }
+
+    fn write_events(config: &AuditConfig, count: usize) {
+        let mut log = AuditLog::open(config.clone()).expect("open audit log");
+        for index in 0..count {
+            log.record(&AuditEvent::CommandRejected {
+                command_id: format!("cmd-{}", index),
+                reason: "Command outside its validity window".to_string(),
+            })
+            .expect("record event");
+        }
+    }
+
+    fn cleanup(path: &Path) {
+        let _ = fs::remove_dir_all(path.parent().map(PathBuf::from).expect("parent"));
+    }
+
+    #[test]
+    fn untouched_chain_verifies_across_reopen_and_rotation() {
+        let config = temp_config("intact", 600);
+        write_events(&config, 3);
+        write_events(&config, 3);
+
+        assert!(rotated_path(&config.path, 1).exists(), "small max_bytes forces rotation");
+        assert_eq!(verify_chain(&config), Ok(6));
+        cleanup(&config.path);
+    }
+
+    #[test]
+    fn detects_edited_and_deleted_middle_records() {
+        let config = temp_config("tampered", 1024 * 1024);
+        write_events(&config, 5);
+        let original = fs::read_to_string(&config.path).expect("read log");
+        let lines = original.lines().collect::<Vec<&str>>();
+
+        let edited = original.replacen("cmd-2", "cmd-9", 1);
+        fs::write(&config.path, edited).expect("write edited log");
+        let err = verify_chain(&config).expect_err("edit detected");
+        assert!(err.contains(":3: record hash does not match"), "{}", err);
+
+        let mut without_middle = lines.clone();
+        without_middle.remove(2);
+        fs::write(&config.path, format!("{}\n", without_middle.join("\n"))).expect("write truncated log");
+        let err = verify_chain(&config).expect_err("deletion detected");
+        assert!(err.contains("expected seq 2, found 3"), "{}", err);
+        cleanup(&config.path);
+    }
+
+    #[test]
+    fn detects_tail_truncation_and_rewrites_without_the_key() {
+        let config = temp_config("truncated", 1024 * 1024);
+        write_events(&config, 4);
+        let original = fs::read_to_string(&config.path).expect("read log");
+        let lines = original.lines().collect::<Vec<&str>>();
+
+        fs::write(&config.path, format!("{}\n", lines[..3].join("\n"))).expect("write truncated log");
+        let err = verify_chain(&config).expect_err("truncation detected");
+        assert!(err.contains("head points at seq 3, last record is seq 2"), "{}", err);
+
+        let unkeyed = AuditConfig { key: None, ..config.clone() };
+        fs::remove_file(&config.path).expect("remove log");
+        fs::remove_file(config.head_path()).expect("remove head");
+        write_events(&unkeyed, 2);
+        let err = verify_chain(&config).expect_err("unkeyed rewrite detected");
+        assert!(err.contains("head signature does not match"), "{}", err);
+        cleanup(&config.path);
+    }
+
+    #[test]
+    fn resumes_after_a_torn_last_line() {
+        let config = temp_config("torn", 1024 * 1024);
+        write_events(&config, 2);
+        let mut torn = fs::read_to_string(&config.path).expect("read log");
+        torn.push_str("{\"seq\":2,\"at_unix");
+        fs::write(&config.path, torn).expect("write torn log");
+
+        write_events(&config, 2);
+        assert!(rotated_path(&config.path, 1).exists(), "torn file is rotated away");
+        assert_eq!(verify_chain(&config), Ok(4));
+        cleanup(&config.path);
+    }
+}
@@ -0,0 +1,572 @@
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use base64::engine::general_purpose::STANDARD as BASE64_STANDARD;
+use base64::Engine as _;
+use sha2::{Digest, Sha256};
+use tracing::debug;
+
+use crate::time::days_from_civil;
+
+const PEM_BEGIN: &str = "-----BEGIN CERTIFICATE-----";
+const PEM_END: &str = "-----END CERTIFICATE-----";
+
+const OID_RSA_ENCRYPTION: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01];
+const OID_EC_PUBLIC_KEY: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+const OID_ED25519: &[u8] = &[0x2b, 0x65, 0x70];
+const OID_PRIME256V1: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+const OID_SECP384R1: &[u8] = &[0x2b, 0x81, 0x04, 0x00, 0x22];
+const OID_SECP521R1: &[u8] = &[0x2b, 0x81, 0x04, 0x00, 0x23];
+
+/// Certificate inventory over operator-configured files. Entries in `CERT_SCAN_PATHS` are
+/// files, directories (scanned one level deep) or a directory plus a `*`/`?` file-name pattern.
+#[derive(Debug, Clone)]
+pub struct CertificateConfig {
+    pub scan_paths: Vec<String>,
+    pub expiry_warning_days: u64,
+    pub min_rsa_bits: u32,
+    pub max_files: usize,
+    pub max_file_bytes: u64,
+}
+
+impl CertificateConfig {
+    pub fn from_env() -> Self {
+        let scan_paths = env::var("CERT_SCAN_PATHS")
+            .ok()
+            .map(|value| parse_csv(&value))
+            .unwrap_or_default();
+        let expiry_warning_days = env::var("CERT_EXPIRY_WARNING_DAYS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .unwrap_or(30);
+        let min_rsa_bits = env::var("CERT_MIN_RSA_BITS")
+            .ok()
+            .and_then(|value| value.parse::<u32>().ok())
+            .filter(|value| *value > 0)
+            .unwrap_or(2048);
+        let max_files = env::var("CERT_MAX_FILES")
+            .ok()
+            .and_then(|value| value.parse::<usize>().ok())
+            .filter(|value| *value > 0)
+            .unwrap_or(512);
+        let max_file_bytes = env::var("CERT_MAX_FILE_BYTES")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .filter(|value| *value > 0)
+            .unwrap_or(256 * 1024);
+
+        Self {
+            scan_paths,
+            expiry_warning_days,
+            min_rsa_bits,
+            max_files,
+            max_file_bytes,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyAlgorithm {
+    Rsa,
+    Ec,
+    Ed25519,
+    Other,
+}
+
+impl KeyAlgorithm {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            KeyAlgorithm::Rsa => "rsa",
+            KeyAlgorithm::Ec => "ec",
+            KeyAlgorithm::Ed25519 => "ed25519",
+            KeyAlgorithm::Other => "other",
+        }
+    }
+}
+
+/// Fields read from one X.509 certificate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CertificateInfo {
+    pub subject: String,
+    pub issuer: String,
+    pub not_before_unix_secs: i64,
+    pub not_after_unix_secs: i64,
+    pub key_algorithm: KeyAlgorithm,
+    /// `None` when the key size cannot be determined (unknown algorithm or curve).
+    pub key_bits: Option<u32>,
+    /// SHA-256 of the DER encoding.
+    pub fingerprint_sha256: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CertificateRecord {
+    pub path: PathBuf,
+    pub certificate: CertificateInfo,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CertificateIssue {
+    Expired,
+    ExpiringSoon { days_left: u64 },
+    WeakKey { bits: u32 },
+}
+
+impl fmt::Display for CertificateIssue {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CertificateIssue::Expired => write!(formatter, "expired"),
+            CertificateIssue::ExpiringSoon { days_left } => write!(formatter, "expires in {} days", days_left),
+            CertificateIssue::WeakKey { bits } => write!(formatter, "uses a {}-bit RSA key", bits),
+        }
+    }
+}
+
+/// Expiry and key-strength problems with `certificate` at `now_unix_ms`.
+pub fn certificate_issues(certificate: &CertificateInfo, config: &CertificateConfig, now_unix_ms: u64) -> Vec<CertificateIssue> {
+    let mut issues = Vec::new();
+    let now_secs = (now_unix_ms / 1000) as i64;
+    if certificate.not_after_unix_secs <= now_secs {
+        issues.push(CertificateIssue::Expired);
+    } else {
+        let days_left = ((certificate.not_after_unix_secs - now_secs) / 86_400) as u64;
+        if days_left < config.expiry_warning_days {
+            issues.push(CertificateIssue::ExpiringSoon { days_left });
+        }
+    }
+    if certificate.key_algorithm == KeyAlgorithm::Rsa {
+        if let Some(bits) = certificate.key_bits.filter(|bits| *bits < config.min_rsa_bits) {
+            issues.push(CertificateIssue::WeakKey { bits });
+        }
+    }
+    issues
+}
+
+/// Parse every certificate under the configured paths. Files that are not certificates,
+/// or blocks that fail to parse, are skipped.
+pub fn scan_certificates(config: &CertificateConfig) -> Vec<CertificateRecord> {
+    let mut records = Vec::new();
+    for path in candidate_files(config) {
+        let Ok(raw) = fs::read(&path) else {
+            continue;
+        };
+        for certificate in parse_certificates(&raw) {
+            records.push(CertificateRecord {
+                path: path.clone(),
+                certificate,
+            });
+        }
+    }
+    records
+}
+
+fn candidate_files(config: &CertificateConfig) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for entry in &config.scan_paths {
+        let path = PathBuf::from(entry);
+        let pattern = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .filter(|name| name.contains('*') || name.contains('?'))
+            .map(str::to_string);
+        let matches = match pattern {
+            Some(pattern) => {
+                let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+                list_files(dir, Some(&pattern))
+            }
+            None if path.is_dir() => list_files(&path, None),
+            None => vec![path],
+        };
+        for file in matches {
+            if files.len() >= config.max_files {
+                return files;
+            }
+            let within_limit = fs::metadata(&file)
+                .map(|metadata| metadata.is_file() && metadata.len() <= config.max_file_bytes)
+                .unwrap_or(false);
+            if within_limit && !files.contains(&file) {
+                files.push(file);
+            }
+        }
+    }
+    files
+}
+
+fn list_files(dir: &Path, pattern: Option<&str>) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut files = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| match pattern {
+            Some(pattern) => path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| wildcard_match(pattern, name)),
+            None => true,
+        })
+        .collect::<Vec<PathBuf>>();
+    files.sort();
+    files
+}
+
+/// `*` matches any run of characters and `?` a single one.
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<char>>();
+    let name = name.chars().collect::<Vec<char>>();
+    let (mut p, mut n) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, n));
+            p += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star + 1;
+            n = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|ch| *ch == '*')
+}
+
+/// Certificates in a PEM bundle, or the single certificate in a DER file.
+pub fn parse_certificates(raw: &[u8]) -> Vec<CertificateInfo> {
+    let text = String::from_utf8_lossy(raw);
+    if !text.contains(PEM_BEGIN) {
+        return match parse_der_certificate(raw) {
+            Ok(certificate) => vec![certificate],
+            Err(err) => {
+                debug!(error = %err, "file is not a DER certificate");
+                Vec::new()
+            }
+        };
+    }
+    let mut certificates = Vec::new();
+    let mut rest = text.as_ref();
+    while let Some(start) = rest.find(PEM_BEGIN) {
+        let body_start = start + PEM_BEGIN.len();
+        let Some(end) = rest[body_start..].find(PEM_END) else {
+            break;
+        };
+        let body = rest[body_start..body_start + end]
+            .chars()
+            .filter(|ch| !ch.is_whitespace())
+            .collect::<String>();
+        match BASE64_STANDARD
+            .decode(body.as_bytes())
+            .map_err(|err| err.to_string())
+            .and_then(|der| parse_der_certificate(&der))
+        {
+            Ok(certificate) => certificates.push(certificate),
+            Err(err) => debug!(error = %err, "skipping unparseable PEM certificate block"),
+        }
+        rest = &rest[body_start + end + PEM_END.len()..];
+    }
+    certificates
+}
+
+pub fn parse_der_certificate(der: &[u8]) -> Result<CertificateInfo, String> {
+    let (certificate, _) = read_expected(der, 0x30)?;
+    let (tbs, _) = read_expected(certificate, 0x30)?;
+    let mut rest = tbs;
+    if rest.first() == Some(&0xa0) {
+        rest = read_tlv(rest)?.2;
+    }
+    let (_serial, rest) = read_expected(rest, 0x02)?;
+    let (_signature, rest) = read_expected(rest, 0x30)?;
+    let (issuer, rest) = read_expected(rest, 0x30)?;
+    let (validity, rest) = read_expected(rest, 0x30)?;
+    let (subject, rest) = read_expected(rest, 0x30)?;
+    let (spki, _) = read_expected(rest, 0x30)?;
+
+    let (not_before_tag, not_before, validity) = read_tlv(validity)?;
+    let (not_after_tag, not_after, _) = read_tlv(validity)?;
+    let (key_algorithm, key_bits) = parse_public_key(spki)?;
+
+    Ok(CertificateInfo {
+        subject: render_name(subject)?,
+        issuer: render_name(issuer)?,
+        not_before_unix_secs: parse_time(not_before_tag, not_before)?,
+        not_after_unix_secs: parse_time(not_after_tag, not_after)?,
+        key_algorithm,
+        key_bits,
+        fingerprint_sha256: hex_encode(Sha256::digest(der)),
+    })
+}
+
+/// Split off one DER element: `(tag, content, rest)`.
+fn read_tlv(input: &[u8]) -> Result<(u8, &[u8], &[u8]), String> {
+    let (&tag, rest) = input.split_first().ok_or("truncated element")?;
+    let (&first, rest) = rest.split_first().ok_or("truncated length")?;
+    let (len, rest) = if first < 0x80 {
+        (first as usize, rest)
+    } else {
+        let count = (first & 0x7f) as usize;
+        if count == 0 || count > 4 || rest.len() < count {
+            return Err("unsupported length encoding".to_string());
+        }
+        let len = rest[..count].iter().fold(0usize, |len, byte| (len << 8) | *byte as usize);
+        (len, &rest[count..])
+    };
+    if rest.len() < len {
+        return Err("element overruns its container".to_string());
+    }
+    Ok((tag, &rest[..len], &rest[len..]))
+}
+
+fn read_expected(input: &[u8], expected: u8) -> Result<(&[u8], &[u8]), String> {
+    let (tag, content, rest) = read_tlv(input)?;
+    if tag != expected {
+        return Err(format!("expected tag {:#04x}, found {:#04x}", expected, tag));
+    }
+    Ok((content, rest))
+}
+
+fn parse_public_key(spki: &[u8]) -> Result<(KeyAlgorithm, Option<u32>), String> {
+    let (algorithm, rest) = read_expected(spki, 0x30)?;
+    let (oid, parameters) = read_expected(algorithm, 0x06)?;
+    if oid == OID_RSA_ENCRYPTION {
+        let (bits, _) = read_expected(rest, 0x03)?;
+        // Skip the unused-bits octet of the BIT STRING to reach RSAPublicKey.
+        let key = bits.get(1..).ok_or("empty public key")?;
+        let (rsa_key, _) = read_expected(key, 0x30)?;
+        let (modulus, _) = read_expected(rsa_key, 0x02)?;
+        let modulus = match modulus.iter().position(|byte| *byte != 0) {
+            Some(start) => &modulus[start..],
+            None => &[],
+        };
+        let bits = match modulus.first() {
+            Some(first) => (modulus.len() as u32 - 1) * 8 + (8 - first.leading_zeros()),
+            None => 0,
+        };
+        return Ok((KeyAlgorithm::Rsa, Some(bits)));
+    }
+    if oid == OID_EC_PUBLIC_KEY {
+        let curve_bits = read_expected(parameters, 0x06).ok().and_then(|(curve, _)| match curve {
+            OID_PRIME256V1 => Some(256),
+            OID_SECP384R1 => Some(384),
+            OID_SECP521R1 => Some(521),
+            _ => None,
+        });
+        return Ok((KeyAlgorithm::Ec, curve_bits));
+    }
+    if oid == OID_ED25519 {
+        return Ok((KeyAlgorithm::Ed25519, Some(256)));
+    }
+    Ok((KeyAlgorithm::Other, None))
+}
+
+/// `CN=host, O=Example` style rendering of an X.501 name.
+fn render_name(name: &[u8]) -> Result<String, String> {
+    let mut parts = Vec::new();
+    let mut sets = name;
+    while !sets.is_empty() {
+        let (set, rest) = read_expected(sets, 0x31)?;
+        sets = rest;
+        let mut attributes = set;
+        while !attributes.is_empty() {
+            let (attribute, rest) = read_expected(attributes, 0x30)?;
+            attributes = rest;
+            let (oid, value) = read_expected(attribute, 0x06)?;
+            let (_, value, _) = read_tlv(value)?;
+            parts.push(format!("{}={}", attribute_label(oid), String::from_utf8_lossy(value)));
+        }
+    }
+    Ok(parts.join(", "))
+}
+
+fn attribute_label(oid: &[u8]) -> String {
+    match oid {
+        [0x55, 0x04, 0x03] => "CN".to_string(),
+        [0x55, 0x04, 0x06] => "C".to_string(),
+        [0x55, 0x04, 0x07] => "L".to_string(),
+        [0x55, 0x04, 0x08] => "ST".to_string(),
+        [0x55, 0x04, 0x0a] => "O".to_string(),
+        [0x55, 0x04, 0x0b] => "OU".to_string(),
+        _ => dotted_oid(oid),
+    }
+}
+
+fn dotted_oid(oid: &[u8]) -> String {
+    let Some((&first, rest)) = oid.split_first() else {
+        return String::new();
+    };
+    let mut arcs = vec![(first / 40).min(2) as u64, (first as u64) - (first / 40).min(2) as u64 * 40];
+    let mut value = 0u64;
+    for byte in rest {
+        value = (value << 7) | (byte & 0x7f) as u64;
+        if byte & 0x80 == 0 {
+            arcs.push(value);
+            value = 0;
+        }
+    }
+    arcs.iter().map(u64::to_string).collect::<Vec<String>>().join(".")
+}
+
+/// UTCTime (`YYMMDDHHMMSSZ`) or GeneralizedTime (`YYYYMMDDHHMMSSZ`) to Unix seconds.
+fn parse_time(tag: u8, value: &[u8]) -> Result<i64, String> {
+    // Checked before slicing so a multibyte character can never split a field.
+    if !value.is_ascii() {
+        return Err("time is not ASCII".to_string());
+    }
+    let text = std::str::from_utf8(value).map_err(|_| "time is not ASCII".to_string())?;
+    let digits = text.strip_suffix('Z').ok_or("time is not UTC")?;
+    let (year, rest) = match tag {
+        0x17 if digits.len() == 12 => {
+            let year = parse_digits(&digits[..2])?;
+            (if year >= 50 { 1900 + year } else { 2000 + year }, &digits[2..])
+        }
+        0x18 if digits.len() == 14 => (parse_digits(&digits[..4])?, &digits[4..]),
+        _ => return Err(format!("unsupported time {}", text)),
+    };
+    let month = parse_digits(&rest[0..2])?;
+    let day = parse_digits(&rest[2..4])?;
+    let hour = parse_digits(&rest[4..6])?;
+    let minute = parse_digits(&rest[6..8])?;
+    let second = parse_digits(&rest[8..10])?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        return Err(format!("invalid time {}", text));
+    }
+    Ok(days_from_civil(year, month as u32, day as u32) * 86_400 + hour * 3_600 + minute * 60 + second)
+}
+
+fn parse_digits(value: &str) -> Result<i64, String> {
+    if !value.bytes().all(|byte| byte.is_ascii_digit()) {
+        return Err(format!("invalid digits {}", value));
+    }
+    value.parse::<i64>().map_err(|err| err.to_string())
+}
+
+fn hex_encode(bytes: impl AsRef<[u8]>) -> String {
+    bytes
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<Vec<String>>()
+        .join("")
+}
+
+fn parse_csv(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|entry| entry.trim().to_string())
+        .filter(|entry| !entry.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use base64::engine::general_purpose::STANDARD as BASE64_STANDARD;
+    use base64::Engine as _;
+
+    use super::{certificate_issues, parse_time, scan_certificates, CertificateConfig, CertificateIssue, KeyAlgorithm};
+    use crate::time::{format_date, unix_time_ms};
+
+    /// 2025-06-01T00:00:00Z.
+    const NOW_UNIX_MS: u64 = 1_748_736_000_000;
+
+    fn tlv(tag: u8, content: &[u8]) -> Vec<u8> {
+        let mut out = vec![tag];
+        if content.len() < 0x80 {
+            out.push(content.len() as u8);
+        } else {
+            out.push(0x82);
+            out.extend_from_slice(&(content.len() as u16).to_be_bytes());
+        }
+        out.extend_from_slice(content);
+        out
+    }
+
+    fn name(common_name: &str) -> Vec<u8> {
+        let attribute = [tlv(0x06, &[0x55, 0x04, 0x03]), tlv(0x0c, common_name.as_bytes())].concat();
+        tlv(0x30, &tlv(0x31, &tlv(0x30, &attribute)))
+    }
+
+    /// Self-signed certificate with an RSA modulus of `rsa_bits` bits. The signature is
+    /// filler: the scanner reads certificates, it does not verify them.
+    fn fixture_der(common_name: &str, not_after: (u8, &str), rsa_bits: usize) -> Vec<u8> {
+        let mut modulus = vec![0x00, 0xc5];
+        modulus.resize(rsa_bits / 8 + 1, 0x5a);
+        let rsa_key = tlv(0x30, &[tlv(0x02, &modulus), tlv(0x02, &[0x01, 0x00, 0x01])].concat());
+        let spki = tlv(
+            0x30,
+            &[
+                tlv(0x30, &[tlv(0x06, &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01]), tlv(0x05, &[])].concat()),
+                tlv(0x03, &[&[0x00][..], &rsa_key].concat()),
+            ]
+            .concat(),
+        );
+        let signature_algorithm = tlv(0x30, &[tlv(0x06, &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0b]), tlv(0x05, &[])].concat());
+        let validity = tlv(0x30, &[tlv(0x17, b"240101000000Z"), tlv(not_after.0, not_after.1.as_bytes())].concat());
+        let tbs = tlv(
+            0x30,
+            &[
+                tlv(0xa0, &tlv(0x02, &[0x02])),
+                tlv(0x02, &[0x01, 0x23]),
+                signature_algorithm.clone(),
+                name(common_name),
+                validity,
+                name(common_name),
+                spki,
+            ]
+            .concat(),
+        );
+        tlv(0x30, &[tbs, signature_algorithm, tlv(0x03, &[0x00, 0xab, 0xcd])].concat())
+    }
+
+    fn pem(der: &[u8]) -> String {
+        format!("-----BEGIN CERTIFICATE-----\n{}\n-----END CERTIFICATE-----\n", BASE64_STANDARD.encode(der))
+    }
+
+    #[test]
+    fn inventories_certificates_and_skips_garbage() {
+        let dir = std::env::temp_dir().join(format!("cert-scan-{}-{}", std::process::id(), unix_time_ms()));
+        fs::create_dir_all(&dir).expect("create dir");
+        let expired = fixture_der("expired.example", (0x17, "250101000000Z"), 2048);
+        let expiring = fixture_der("expiring.example", (0x18, "20250615120000Z"), 2048);
+        let weak = fixture_der("weak.example", (0x17, "270101000000Z"), 1024);
+        fs::write(dir.join("bundle.pem"), format!("{}{}", pem(&expired), pem(&weak))).expect("write bundle");
+        fs::write(dir.join("service.der"), &expiring).expect("write der");
+        fs::write(dir.join("garbage.pem"), "-----BEGIN CERTIFICATE-----\nnot base64 at all!\n-----END CERTIFICATE-----\n")
+            .expect("write garbage");
+        fs::write(dir.join("random.der"), [0x30, 0x84, 0xff, 0xff, 0xff, 0xff, 0x00]).expect("write random");
+        fs::write(dir.join("ignored.txt"), pem(&expired)).expect("write ignored");
+
+        let config = CertificateConfig {
+            scan_paths: vec![format!("{}/*.pem", dir.display()), format!("{}/*.der", dir.display())],
+            expiry_warning_days: 30,
+            min_rsa_bits: 2048,
+            max_files: 16,
+            max_file_bytes: 64 * 1024,
+        };
+        let records = scan_certificates(&config);
+        let subjects = records.iter().map(|record| record.certificate.subject.as_str()).collect::<Vec<_>>();
+        assert_eq!(subjects, vec!["CN=expired.example", "CN=weak.example", "CN=expiring.example"]);
+
+        let weak_cert = &records[1].certificate;
+        assert_eq!(weak_cert.issuer, "CN=weak.example");
+        assert_eq!(weak_cert.key_algorithm, KeyAlgorithm::Rsa);
+        assert_eq!(weak_cert.key_bits, Some(1024));
+        assert_eq!(format_date(weak_cert.not_after_unix_secs), "2027-01-01");
+
+        let issues = records
+            .iter()
+            .map(|record| certificate_issues(&record.certificate, &config, NOW_UNIX_MS))
+            .collect::<Vec<_>>();
+        assert_eq!(issues[0], vec![CertificateIssue::Expired]);
+        assert_eq!(issues[1], vec![CertificateIssue::WeakKey { bits: 1024 }]);
+        assert_eq!(issues[2], vec![CertificateIssue::ExpiringSoon { days_left: 14 }]);
+        assert!(parse_time(0x17, "2501011200\u{e9}Z".as_bytes()).is_err());
+        assert!(parse_time(0x18, "2025010112\u{e9}00Z".as_bytes()).is_err());
+        let _ = fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,226 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    Closed,
+    /// Requests are refused until the open period ends.
+    Open,
+    /// The open period has ended; one probe request decides whether to close again.
+    HalfOpen,
+}
+
+impl CircuitState {
+    pub fn as_gauge(self) -> u64 {
+        match self {
+            CircuitState::Closed => 0,
+            CircuitState::HalfOpen => 1,
+            CircuitState::Open => 2,
+        }
+    }
+}
+
+impl fmt::Display for CircuitState {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CircuitState::Closed => write!(formatter, "closed"),
+            CircuitState::Open => write!(formatter, "open"),
+            CircuitState::HalfOpen => write!(formatter, "half_open"),
+        }
+    }
+}
+
+/// Consecutive-failure breaker for a single endpoint.
+#[derive(Debug, Clone)]
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    open_ms: u64,
+    consecutive_failures: u32,
+    open_until_unix_ms: Option<u64>,
+    probe_in_flight: bool,
+}
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: u32, open_ms: u64) -> Self {
+        Self {
+            failure_threshold: failure_threshold.max(1),
+            open_ms,
+            consecutive_failures: 0,
+            open_until_unix_ms: None,
+            probe_in_flight: false,
+        }
+    }
+
+    pub fn state(&self, now_unix_ms: u64) -> CircuitState {
+        match self.open_until_unix_ms {
+            None => CircuitState::Closed,
+            Some(until) if now_unix_ms < until => CircuitState::Open,
+            Some(_) => CircuitState::HalfOpen,
+        }
+    }
+
+    fn can_acquire(&self, now_unix_ms: u64) -> bool {
+        match self.state(now_unix_ms) {
+            CircuitState::Closed => true,
+            CircuitState::Open => false,
+            CircuitState::HalfOpen => !self.probe_in_flight,
+        }
+    }
+
+    /// Whether a request may be sent now; in the half-open state only one probe is let
+    /// through until its result is recorded.
+    pub fn try_acquire(&mut self, now_unix_ms: u64) -> bool {
+        if !self.can_acquire(now_unix_ms) {
+            return false;
+        }
+        if self.state(now_unix_ms) == CircuitState::HalfOpen {
+            self.probe_in_flight = true;
+        }
+        true
+    }
+
+    pub fn record_success(&mut self) {
+        self.consecutive_failures = 0;
+        self.open_until_unix_ms = None;
+        self.probe_in_flight = false;
+    }
+
+    /// Hand back an acquired probe without a result, for a request that failed before the
+    /// endpoint could answer it.
+    pub fn release(&mut self) {
+        self.probe_in_flight = false;
+    }
+
+    /// Count a failure; the breaker opens at the threshold, or straight away when the
+    /// half-open probe fails.
+    pub fn record_failure(&mut self, now_unix_ms: u64) {
+        self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+        let probe_failed = self.probe_in_flight;
+        self.probe_in_flight = false;
+        if probe_failed || self.consecutive_failures >= self.failure_threshold {
+            self.open_until_unix_ms = Some(now_unix_ms.saturating_add(self.open_ms));
+        }
+    }
+}
+
+/// Breakers keyed by endpoint, shared between concurrent delivery tasks.
+#[derive(Debug, Clone)]
+pub struct EndpointBreakers {
+    failure_threshold: u32,
+    open_ms: u64,
+    breakers: Arc<Mutex<HashMap<String, CircuitBreaker>>>,
+}
+
+impl EndpointBreakers {
+    pub fn new(failure_threshold: u32, open_ms: u64) -> Self {
+        Self {
+            failure_threshold,
+            open_ms,
+            breakers: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Acquire every endpoint an item needs, or none of them.
+    pub fn try_acquire_all(&self, endpoints: &[&str], now_unix_ms: u64) -> bool {
+        let mut breakers = self.breakers.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let allowed = endpoints.iter().all(|endpoint| {
+            breakers
+                .get(*endpoint)
+                .map(|breaker| breaker.can_acquire(now_unix_ms))
+                .unwrap_or(true)
+        });
+        if allowed {
+            for endpoint in endpoints {
+                breakers
+                    .entry(endpoint.to_string())
+                    .or_insert_with(|| CircuitBreaker::new(self.failure_threshold, self.open_ms))
+                    .try_acquire(now_unix_ms);
+            }
+        }
+        allowed
+    }
+
+    /// Record a result and return `(from, to)` when it changed the breaker's state, so
+    /// callers can log the transition once instead of every refused item.
+    pub fn record(&self, endpoint: &str, reachable: bool, now_unix_ms: u64) -> Option<(CircuitState, CircuitState)> {
+        let mut breakers = self.breakers.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let breaker = breakers
+            .entry(endpoint.to_string())
+            .or_insert_with(|| CircuitBreaker::new(self.failure_threshold, self.open_ms));
+        let from = breaker.state(now_unix_ms);
+        if reachable {
+            breaker.record_success();
+        } else {
+            breaker.record_failure(now_unix_ms);
+        }
+        let to = breaker.state(now_unix_ms);
+        (from != to).then_some((from, to))
+    }
+
+    /// Release what `try_acquire_all` took for `endpoint` without recording a result.
+    pub fn release(&self, endpoint: &str) {
+        let mut breakers = self.breakers.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(breaker) = breakers.get_mut(endpoint) {
+            breaker.release();
+        }
+    }
+
+    pub fn snapshot(&self, now_unix_ms: u64) -> BTreeMap<String, CircuitState> {
+        let breakers = self.breakers.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        breakers
+            .iter()
+            .map(|(endpoint, breaker)| (endpoint.clone(), breaker.state(now_unix_ms)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CircuitBreaker, CircuitState, EndpointBreakers};
+
+    #[test]
+    fn opens_after_threshold_and_reopens_on_failed_probe() {
+        let mut breaker = CircuitBreaker::new(2, 1_000);
+        assert!(breaker.try_acquire(0));
+        breaker.record_failure(0);
+        assert_eq!(breaker.state(0), CircuitState::Closed);
+        breaker.record_failure(10);
+        assert_eq!(breaker.state(10), CircuitState::Open);
+        assert!(!breaker.try_acquire(500));
+
+        assert_eq!(breaker.state(1_010), CircuitState::HalfOpen);
+        assert!(breaker.try_acquire(1_010));
+        assert!(!breaker.try_acquire(1_011), "only one probe while half-open");
+        breaker.record_failure(1_020);
+        assert_eq!(breaker.state(1_500), CircuitState::Open);
+
+        assert!(breaker.try_acquire(2_020));
+        breaker.record_success();
+        assert_eq!(breaker.state(2_021), CircuitState::Closed);
+    }
+
+    #[test]
+    fn acquires_all_endpoints_or_none() {
+        let breakers = EndpointBreakers::new(1, 1_000);
+        assert_eq!(
+            breakers.record("http://down", false, 0),
+            Some((CircuitState::Closed, CircuitState::Open))
+        );
+        assert_eq!(breakers.record("http://up", true, 0), None);
+        assert!(!breakers.try_acquire_all(&["http://up", "http://down"], 10));
+        assert!(breakers.try_acquire_all(&["http://up"], 10));
+        let snapshot = breakers.snapshot(10);
+        assert_eq!(snapshot.get("http://down"), Some(&CircuitState::Open));
+        assert_eq!(snapshot.get("http://up"), Some(&CircuitState::Closed));
+
+        assert!(breakers.try_acquire_all(&["http://down"], 1_000));
+        assert!(!breakers.try_acquire_all(&["http://down"], 1_000), "probe already in flight");
+        breakers.release("http://down");
+        assert!(breakers.try_acquire_all(&["http://down"], 1_000), "released probe can be retaken");
+        assert_eq!(
+            breakers.record("http://down", true, 1_000),
+            Some((CircuitState::HalfOpen, CircuitState::Closed))
+        );
+    }
+}
@@ -0,0 +1,314 @@
+use std::env;
+use std::sync::{Mutex, OnceLock};
+
+use reqwest::header::{HeaderMap, DATE};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
+
+use crate::compliance::{ComplianceResult, ComplianceStatus};
+use crate::time::parse_http_date_ms;
+
+pub const DRIFT_CONTROL_ID: &str = "clock-drift";
+
+/// `Date` headers only carry whole seconds; on average the server's clock is half a second
+/// past the value it sent.
+const DATE_TRUNCATION_BIAS_MS: i64 = 500;
+
+/// Optional measurement of the local clock against the control plane's `Date` headers.
+/// The estimate is only reported; validation never applies it.
+#[derive(Debug, Clone)]
+pub struct DriftConfig {
+    pub enabled: bool,
+    pub max_drift_ms: u64,
+    /// EWMA weight of each new sample.
+    pub alpha: f64,
+    /// Responses slower than this say too little about the server's clock to be sampled.
+    pub max_round_trip_ms: u64,
+}
+
+impl DriftConfig {
+    pub fn from_env() -> Self {
+        let enabled = env::var("AGENT_CLOCK_DRIFT_PROBE")
+            .map(|value| matches!(value.trim().to_ascii_lowercase().as_str(), "1" | "true" | "yes"))
+            .unwrap_or(false);
+        let max_drift_ms = env::var("AGENT_MAX_CLOCK_DRIFT_MS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .unwrap_or(5_000);
+        let alpha = env::var("AGENT_CLOCK_DRIFT_EWMA_ALPHA")
+            .ok()
+            .and_then(|value| value.parse::<f64>().ok())
+            .filter(|value| *value > 0.0 && *value <= 1.0)
+            .unwrap_or(0.2);
+        let max_round_trip_ms = env::var("AGENT_CLOCK_DRIFT_MAX_RTT_MS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .unwrap_or(5_000);
+        Self {
+            enabled,
+            max_drift_ms,
+            alpha,
+            max_round_trip_ms,
+        }
+    }
+}
+
+/// Drift summary carried in the heartbeat. A positive offset means the control plane's
+/// clock is ahead of ours.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct DriftReport {
+    pub offset_ms: Option<i64>,
+    pub samples: u64,
+    pub last_sample_unix_ms: Option<u64>,
+    pub exceeded: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DriftTransition {
+    Unchanged,
+    Exceeded,
+    Recovered,
+}
+
+#[derive(Debug)]
+pub struct DriftEstimator {
+    config: DriftConfig,
+    ewma_ms: Option<f64>,
+    samples: u64,
+    last_sample_unix_ms: Option<u64>,
+    exceeded: bool,
+}
+
+impl DriftEstimator {
+    pub fn new(config: DriftConfig) -> Self {
+        Self {
+            config,
+            ewma_ms: None,
+            samples: 0,
+            last_sample_unix_ms: None,
+            exceeded: false,
+        }
+    }
+
+    /// Sample the `Date` header of a successful response. `sent_unix_ms` and
+    /// `received_unix_ms` bracket the request so the server's reading can be compared with
+    /// the midpoint of the round trip.
+    pub fn observe_headers(&mut self, headers: &HeaderMap, sent_unix_ms: u64, received_unix_ms: u64) -> DriftTransition {
+        let Some(server_ms) = headers
+            .get(DATE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_http_date_ms)
+        else {
+            return DriftTransition::Unchanged;
+        };
+        self.record(server_ms, sent_unix_ms, received_unix_ms)
+    }
+
+    pub fn record(&mut self, server_unix_ms: u64, sent_unix_ms: u64, received_unix_ms: u64) -> DriftTransition {
+        let round_trip_ms = received_unix_ms.saturating_sub(sent_unix_ms);
+        if received_unix_ms < sent_unix_ms || round_trip_ms > self.config.max_round_trip_ms {
+            return DriftTransition::Unchanged;
+        }
+        let midpoint = sent_unix_ms + round_trip_ms / 2;
+        let offset = server_unix_ms as i64 + DATE_TRUNCATION_BIAS_MS - midpoint as i64;
+        let ewma = match self.ewma_ms {
+            Some(previous) => previous + self.config.alpha * (offset as f64 - previous),
+            None => offset as f64,
+        };
+        self.ewma_ms = Some(ewma);
+        self.samples = self.samples.saturating_add(1);
+        self.last_sample_unix_ms = Some(received_unix_ms);
+
+        let exceeded = ewma.abs() > self.config.max_drift_ms as f64;
+        let transition = match (self.exceeded, exceeded) {
+            (false, true) => DriftTransition::Exceeded,
+            (true, false) => DriftTransition::Recovered,
+            _ => DriftTransition::Unchanged,
+        };
+        self.exceeded = exceeded;
+        transition
+    }
+
+    pub fn report(&self) -> DriftReport {
+        DriftReport {
+            offset_ms: self.ewma_ms.map(|value| value.round() as i64),
+            samples: self.samples,
+            last_sample_unix_ms: self.last_sample_unix_ms,
+            exceeded: self.exceeded,
+        }
+    }
+
+    /// Failed `clock-drift` control while the drift is over its bound.
+    pub fn compliance_result(&self, now_unix_ms: u64) -> Option<ComplianceResult> {
+        if !self.exceeded {
+            return None;
+        }
+        let report = self.report();
+        let finding = format!(
+            "local clock is {} ms off the control plane (bound {} ms, {} samples)",
+            report.offset_ms.unwrap_or_default(),
+            self.config.max_drift_ms,
+            report.samples
+        );
+        Some(ComplianceResult {
+            control_id: DRIFT_CONTROL_ID.to_string(),
+            control_title: "Agent clock agrees with the control plane".to_string(),
+            passed: false,
+            status: ComplianceStatus::Fail,
+            evidence_ref: Sha256::digest(finding.as_bytes())
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect(),
+            checked_at_unix_ms: now_unix_ms,
+            findings: vec![finding],
+            asserted_by: None,
+        })
+    }
+}
+
+fn system_estimator() -> &'static Mutex<DriftEstimator> {
+    static ESTIMATOR: OnceLock<Mutex<DriftEstimator>> = OnceLock::new();
+    ESTIMATOR.get_or_init(|| Mutex::new(DriftEstimator::new(DriftConfig::from_env())))
+}
+
+/// Feed a successful uplink response into the agent-wide estimate. Does nothing unless
+/// `AGENT_CLOCK_DRIFT_PROBE` is set.
+pub fn observe_uplink_response(headers: &HeaderMap, sent_unix_ms: u64, received_unix_ms: u64) {
+    let Ok(mut estimator) = system_estimator().lock() else {
+        return;
+    };
+    if !estimator.config.enabled {
+        return;
+    }
+    match estimator.observe_headers(headers, sent_unix_ms, received_unix_ms) {
+        DriftTransition::Unchanged => {}
+        DriftTransition::Exceeded => {
+            if let Some(result) = estimator.compliance_result(received_unix_ms) {
+                warn!(
+                    control_id = %result.control_id,
+                    evidence_ref = %result.evidence_ref,
+                    finding = %result.findings.join("; "),
+                    "clock drift against the control plane exceeds its bound"
+                );
+            }
+        }
+        DriftTransition::Recovered => {
+            info!(offset_ms = ?estimator.report().offset_ms, "clock drift back within bound");
+        }
+    }
+}
+
+/// Latest agent-wide estimate, for the heartbeat.
+pub fn drift_report() -> DriftReport {
+    system_estimator()
+        .lock()
+        .map(|estimator| estimator.report())
+        .unwrap_or_default()
+}
+
+/// Failed `clock-drift` control while the agent-wide estimate is over its bound.
+pub fn drift_compliance_result(now_unix_ms: u64) -> Option<ComplianceResult> {
+    system_estimator()
+        .lock()
+        .ok()
+        .and_then(|estimator| estimator.compliance_result(now_unix_ms))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    use super::{DriftConfig, DriftEstimator, DriftTransition, DRIFT_CONTROL_ID};
+    use crate::time::unix_time_ms;
+
+    fn config() -> DriftConfig {
+        DriftConfig {
+            enabled: true,
+            max_drift_ms: 5_000,
+            alpha: 0.5,
+            max_round_trip_ms: 5_000,
+        }
+    }
+
+    /// Answers every request with a `Date` header `skew_ms` away from the local clock.
+    fn serve_skewed(skew_ms: i64, requests: usize) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind listener");
+        let address = listener.local_addr().expect("local addr");
+        std::thread::spawn(move || {
+            for stream in listener.incoming().take(requests) {
+                let Ok(mut stream) = stream else { continue };
+                let mut buffer = [0_u8; 4096];
+                let _ = stream.read(&mut buffer);
+                let date = http_date((unix_time_ms() as i64 + skew_ms) as u64);
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\ndate: {}\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+                    date
+                );
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+        format!("http://{}", address)
+    }
+
+    fn http_date(unix_ms: u64) -> String {
+        const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+        const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+        let rfc3339 = crate::time::format_rfc3339_ms(unix_ms);
+        let (year, rest) = rfc3339.split_at(4);
+        let month = rest[1..3].parse::<usize>().expect("month");
+        format!(
+            "{}, {} {} {} {} GMT",
+            DAYS[(unix_ms / 86_400_000 % 7) as usize],
+            &rest[4..6],
+            MONTHS[month - 1],
+            year,
+            &rest[7..15]
+        )
+    }
+
+    #[test]
+    fn smooths_offsets_and_flags_the_bound() {
+        let mut estimator = DriftEstimator::new(config());
+        // Server reads 10.0 s (10.5 s after bias) at the midpoint of a 1 s round trip.
+        assert_eq!(estimator.record(10_000, 0, 1_000), DriftTransition::Exceeded);
+        assert_eq!(estimator.report().offset_ms, Some(10_000));
+        assert_eq!(estimator.record(100_000, 100_000, 100_000), DriftTransition::Unchanged);
+        assert_eq!(estimator.report().offset_ms, Some(5_250));
+        assert_eq!(estimator.record(200_000, 200_000, 200_000), DriftTransition::Recovered);
+        assert_eq!(estimator.report().offset_ms, Some(2_875));
+        assert!(estimator.compliance_result(200_000).is_none());
+
+        // Slow round trips are ignored.
+        assert_eq!(estimator.record(900_000, 0, 60_000), DriftTransition::Unchanged);
+        assert_eq!(estimator.report().samples, 3);
+    }
+
+    #[tokio::test]
+    async fn measures_skewed_date_headers_from_the_server() {
+        let endpoint = serve_skewed(60_000, 3);
+        let client = reqwest::Client::new();
+        let mut estimator = DriftEstimator::new(config());
+        let mut transitions = Vec::new();
+        for _ in 0..3 {
+            let sent = unix_time_ms();
+            let response = client.get(&endpoint).send().await.expect("skewed response");
+            transitions.push(estimator.observe_headers(response.headers(), sent, unix_time_ms()));
+        }
+
+        assert_eq!(
+            transitions,
+            vec![DriftTransition::Exceeded, DriftTransition::Unchanged, DriftTransition::Unchanged]
+        );
+        let report = estimator.report();
+        assert_eq!(report.samples, 3);
+        assert!(report.exceeded);
+        let offset = report.offset_ms.expect("offset");
+        assert!((58_500..=61_500).contains(&offset), "offset {}", offset);
+        let result = estimator.compliance_result(unix_time_ms()).expect("drift finding");
+        assert_eq!(result.control_id, DRIFT_CONTROL_ID);
+        assert!(!result.passed);
+        assert_eq!(result.evidence_ref.len(), 64);
+    }
+}
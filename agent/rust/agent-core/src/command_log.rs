@@ -0,0 +1,455 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::crypto::Signer;
+use crate::policy::PolicyBundle;
+use crate::rmm::{record_outcome, result_signer_from_env, ExecutionOutcome, ExecutionRequest, Termination};
+use crate::seen_commands::SeenCommandCache;
+use crate::time::unix_time_ms;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CommandStatus {
+    Accepted,
+    Executing,
+    Completed,
+    Failed,
+}
+
+impl CommandStatus {
+    pub fn is_terminal(self) -> bool {
+        matches!(self, CommandStatus::Completed | CommandStatus::Failed)
+    }
+}
+
+/// One line of the log. The `accepted` line carries the request so it can be resumed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct LogEntry {
+    command_id: String,
+    status: CommandStatus,
+    at_unix_ms: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    request: Option<ExecutionRequest>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoggedCommand {
+    pub request: ExecutionRequest,
+    pub status: CommandStatus,
+    pub updated_at_unix_ms: u64,
+    pub reason: Option<String>,
+}
+
+/// What a restart found in the log: commands to run again and commands that can no longer
+/// run and must be reported.
+#[derive(Debug, Default)]
+pub struct CommandReplay {
+    pub resume: Vec<ExecutionRequest>,
+    pub orphaned: Vec<ExecutionOutcome>,
+}
+
+/// Append-only record of accepted RMM commands and their status transitions, synced after
+/// every line so a command accepted before a crash is resumed or reported after it.
+/// Execution is at-least-once: a command that was running when the agent stopped runs
+/// again if it is still within its validity window.
+#[derive(Debug)]
+pub struct CommandLog {
+    path: PathBuf,
+    commands: HashMap<String, LoggedCommand>,
+}
+
+impl CommandLog {
+    /// `RMM_COMMAND_LOG` names the log file; unset disables it.
+    pub fn from_env() -> Option<Self> {
+        let path = env::var("RMM_COMMAND_LOG")
+            .ok()
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())?;
+        match Self::open(Path::new(&path)) {
+            Ok(log) => Some(log),
+            Err(err) => {
+                warn!(error = %err, "rmm command log unavailable; accepted commands are not persisted");
+                None
+            }
+        }
+    }
+
+    /// Read the log, skipping lines that do not parse (a write torn by the crash) and
+    /// transitions for commands it has no `accepted` line for.
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let raw = match fs::read_to_string(path) {
+            Ok(raw) => raw,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(format!("Unable to read {}: {}", path.display(), err)),
+        };
+        let mut commands = HashMap::new();
+        let mut skipped = 0;
+        for line in raw.lines().filter(|line| !line.trim().is_empty()) {
+            let Ok(entry) = serde_json::from_str::<LogEntry>(line) else {
+                skipped += 1;
+                continue;
+            };
+            match (entry.request, commands.get_mut(&entry.command_id)) {
+                (Some(request), None) => {
+                    commands.insert(
+                        entry.command_id,
+                        LoggedCommand {
+                            request,
+                            status: entry.status,
+                            updated_at_unix_ms: entry.at_unix_ms,
+                            reason: entry.reason,
+                        },
+                    );
+                }
+                (None, Some(command)) => {
+                    command.status = entry.status;
+                    command.updated_at_unix_ms = entry.at_unix_ms;
+                    command.reason = entry.reason;
+                }
+                _ => skipped += 1,
+            }
+        }
+        if skipped > 0 {
+            warn!(path = %path.display(), skipped, "skipped unreadable rmm command log lines");
+        }
+        let log = Self {
+            path: path.to_path_buf(),
+            commands,
+        };
+        // Close a torn final line so the next entry does not run into it.
+        if !raw.is_empty() && !raw.ends_with('\n') {
+            log.append_raw("\n")?;
+        }
+        Ok(log)
+    }
+
+    pub fn get(&self, command_id: &str) -> Option<&LoggedCommand> {
+        self.commands.get(command_id)
+    }
+
+    /// Record `request` as accepted. Returns `Ok(false)` without writing when the command
+    /// is already logged or the seen-command cache refuses it, so re-delivered commands are
+    /// not run twice.
+    pub fn accept(&mut self, request: &ExecutionRequest, seen: &mut SeenCommandCache, now_unix_ms: u64) -> Result<bool, String> {
+        if self.commands.contains_key(&request.command_id) || seen.check(&request.command_id, request.expires_at_unix_ms, now_unix_ms).is_err() {
+            return Ok(false);
+        }
+        self.append(&[LogEntry {
+            command_id: request.command_id.clone(),
+            status: CommandStatus::Accepted,
+            at_unix_ms: now_unix_ms,
+            request: Some(request.clone()),
+            reason: None,
+        }])?;
+        let _ = seen.insert(&request.command_id, request.expires_at_unix_ms, now_unix_ms);
+        self.commands.insert(
+            request.command_id.clone(),
+            LoggedCommand {
+                request: request.clone(),
+                status: CommandStatus::Accepted,
+                updated_at_unix_ms: now_unix_ms,
+                reason: None,
+            },
+        );
+        Ok(true)
+    }
+
+    pub fn mark_executing(&mut self, command_id: &str, now_unix_ms: u64) -> Result<(), String> {
+        self.transition(command_id, CommandStatus::Executing, None, now_unix_ms)
+    }
+
+    pub fn mark_finished(&mut self, outcome: &ExecutionOutcome, now_unix_ms: u64) -> Result<(), String> {
+        if outcome.success {
+            self.transition(&outcome.command_id, CommandStatus::Completed, None, now_unix_ms)
+        } else {
+            let reason = match (outcome.termination, outcome.exit_code) {
+                (Termination::Exited, Some(code)) => format!("exited with code {}", code),
+                (termination, _) => serde_json::to_value(termination)
+                    .ok()
+                    .and_then(|value| value.as_str().map(str::to_string))
+                    .unwrap_or_default(),
+            };
+            self.transition(&outcome.command_id, CommandStatus::Failed, Some(&reason), now_unix_ms)
+        }
+    }
+
+    fn transition(&mut self, command_id: &str, status: CommandStatus, reason: Option<&str>, now_unix_ms: u64) -> Result<(), String> {
+        if !self.commands.contains_key(command_id) {
+            return Err(format!("Command {} is not in the command log", command_id));
+        }
+        self.append(&[LogEntry {
+            command_id: command_id.to_string(),
+            status,
+            at_unix_ms: now_unix_ms,
+            request: None,
+            reason: reason.map(str::to_string),
+        }])?;
+        if let Some(command) = self.commands.get_mut(command_id) {
+            command.status = status;
+            command.updated_at_unix_ms = now_unix_ms;
+            command.reason = reason.map(str::to_string);
+        }
+        Ok(())
+    }
+
+    /// Seed `seen` with every logged command and sort unfinished ones into those that can
+    /// resume and those that expired while the agent was down. Expired ones are marked
+    /// failed here, so each is reported once.
+    pub fn replay(&mut self, seen: &mut SeenCommandCache, now_unix_ms: u64) -> Result<CommandReplay, String> {
+        let mut replay = CommandReplay::default();
+        let mut unfinished = self
+            .commands
+            .values()
+            .filter(|command| !command.status.is_terminal())
+            .map(|command| command.request.clone())
+            .collect::<Vec<ExecutionRequest>>();
+        unfinished.sort_by(|left, right| {
+            left.requested_at_unix_ms
+                .cmp(&right.requested_at_unix_ms)
+                .then_with(|| left.command_id.cmp(&right.command_id))
+        });
+        for command in self.commands.values() {
+            let _ = seen.insert(&command.request.command_id, command.request.expires_at_unix_ms, now_unix_ms);
+        }
+        for request in unfinished {
+            if request.expires_at_unix_ms > now_unix_ms {
+                replay.resume.push(request);
+            } else {
+                self.transition(
+                    &request.command_id,
+                    CommandStatus::Failed,
+                    Some("expired before the agent restarted"),
+                    now_unix_ms,
+                )?;
+                replay.orphaned.push(ExecutionOutcome::interrupted(&request, now_unix_ms));
+            }
+        }
+        Ok(replay)
+    }
+
+    /// Rewrite the log without finished commands whose validity window has closed; they
+    /// can no longer be re-delivered, so nothing needs them. Returns how many were dropped.
+    pub fn compact(&mut self, now_unix_ms: u64) -> Result<usize, String> {
+        let before = self.commands.len();
+        self.commands
+            .retain(|_, command| !command.status.is_terminal() || command.request.expires_at_unix_ms > now_unix_ms);
+        let dropped = before - self.commands.len();
+        if dropped == 0 {
+            return Ok(0);
+        }
+        let mut commands = self.commands.values().collect::<Vec<&LoggedCommand>>();
+        commands.sort_by_key(|command| (command.request.requested_at_unix_ms, command.request.command_id.clone()));
+        let mut raw = String::new();
+        for command in commands {
+            let mut entries = vec![LogEntry {
+                command_id: command.request.command_id.clone(),
+                status: CommandStatus::Accepted,
+                at_unix_ms: command.updated_at_unix_ms,
+                request: Some(command.request.clone()),
+                reason: None,
+            }];
+            if command.status != CommandStatus::Accepted {
+                entries.push(LogEntry {
+                    command_id: command.request.command_id.clone(),
+                    status: command.status,
+                    at_unix_ms: command.updated_at_unix_ms,
+                    request: None,
+                    reason: command.reason.clone(),
+                });
+            }
+            for entry in entries {
+                raw.push_str(&serde_json::to_string(&entry).map_err(|err| err.to_string())?);
+                raw.push('\n');
+            }
+        }
+        let staging = self.path.with_extension("tmp");
+        fs::write(&staging, raw).map_err(|err| format!("Unable to write {}: {}", staging.display(), err))?;
+        fs::rename(&staging, &self.path)
+            .map_err(|err| format!("Unable to move {} into place: {}", self.path.display(), err))?;
+        Ok(dropped)
+    }
+
+    /// Startup pass: replay the log, report orphaned commands, log the newly `loaded`
+    /// commands and compact. Returns resumed commands followed by newly accepted ones;
+    /// re-delivered commands are dropped.
+    pub async fn reconcile(
+        &mut self,
+        loaded: Vec<ExecutionRequest>,
+        seen_commands: &Mutex<SeenCommandCache>,
+        policy: &PolicyBundle,
+        queue_dir: &Path,
+    ) -> Vec<ExecutionRequest> {
+        let now_unix_ms = unix_time_ms();
+        let (replay, mut requests) = {
+            let mut seen = seen_commands.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            let replay = match self.replay(&mut seen, now_unix_ms) {
+                Ok(replay) => replay,
+                Err(err) => {
+                    warn!(error = %err, "rmm command log replay failed");
+                    CommandReplay::default()
+                }
+            };
+            let mut accepted = Vec::new();
+            for request in loaded {
+                match self.accept(&request, &mut seen, now_unix_ms) {
+                    Ok(true) => accepted.push(request),
+                    Ok(false) => info!(command_id = %request.command_id, "dropping re-delivered rmm command"),
+                    Err(err) => warn!(error = %err, command_id = %request.command_id, "failed to log accepted rmm command"),
+                }
+            }
+            (replay, accepted)
+        };
+        if !replay.resume.is_empty() || !replay.orphaned.is_empty() {
+            info!(
+                resumed = replay.resume.len(),
+                orphaned = replay.orphaned.len(),
+                "replayed rmm command log"
+            );
+        }
+        let signer = result_signer_from_env();
+        for outcome in &replay.orphaned {
+            if let Err(err) = record_outcome(outcome, policy, signer.as_ref().map(|signer| signer as &dyn Signer), queue_dir).await {
+                warn!(error = %err, command_id = %outcome.command_id, "failed to report orphaned rmm command");
+            }
+        }
+        if let Err(err) = self.compact(now_unix_ms) {
+            warn!(error = %err, "rmm command log compaction failed");
+        }
+        let mut resumed = replay.resume;
+        resumed.append(&mut requests);
+        resumed
+    }
+
+    fn append(&self, entries: &[LogEntry]) -> Result<(), String> {
+        if let Some(parent) = self.path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent).map_err(|err| format!("Unable to create {}: {}", parent.display(), err))?;
+        }
+        let mut raw = String::new();
+        for entry in entries {
+            raw.push_str(&serde_json::to_string(entry).map_err(|err| err.to_string())?);
+            raw.push('\n');
+        }
+        self.append_raw(&raw)
+    }
+
+    fn append_raw(&self, raw: &str) -> Result<(), String> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|err| format!("Unable to open {}: {}", self.path.display(), err))?;
+        file.write_all(raw.as_bytes())
+            .and_then(|_| file.sync_data())
+            .map_err(|err| format!("Unable to append to {}: {}", self.path.display(), err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    use super::{CommandLog, CommandStatus};
+    use crate::rmm::{ExecutionOutcome, ExecutionRequest, Termination};
+    use crate::seen_commands::SeenCommandCache;
+    use crate::time::unix_time_ms;
+
+    fn log_path(label: &str) -> PathBuf {
+        std::env::temp_dir()
+            .join(format!("command-log-{}-{}-{}", label, std::process::id(), unix_time_ms()))
+            .join("commands.log")
+    }
+
+    fn request(command_id: &str, requested_at_unix_ms: u64, expires_at_unix_ms: u64) -> ExecutionRequest {
+        ExecutionRequest {
+            command_id: command_id.to_string(),
+            signed_payload: "signed".to_string(),
+            signature: String::new(),
+            action: "script-run".to_string(),
+            arguments: vec!["--check".to_string()],
+            requested_at_unix_ms,
+            expires_at_unix_ms,
+            earliest_start_unix_ms: None,
+            latest_start_unix_ms: None,
+            source: "command-queue".to_string(),
+        }
+    }
+
+    #[test]
+    fn replays_interrupted_commands_after_a_crash() {
+        let path = log_path("replay");
+        let mut seen = SeenCommandCache::new(16, 0);
+        let mut log = CommandLog::open(&path).expect("new log");
+        let running = request("cmd-running", 1_000, 100_000);
+        let finished = request("cmd-finished", 1_100, 100_000);
+        let stale = request("cmd-stale", 1_200, 5_000);
+        for request in [&running, &finished, &stale] {
+            assert_eq!(log.accept(request, &mut seen, 2_000), Ok(true));
+        }
+        log.mark_executing("cmd-running", 2_100).expect("executing");
+        log.mark_executing("cmd-stale", 2_100).expect("executing");
+        log.mark_executing("cmd-finished", 2_100).expect("executing");
+        let outcome = ExecutionOutcome::new(&finished, Some(0), 2_100, 2_200, b"ok", b"", 64);
+        log.mark_finished(&outcome, 2_200).expect("completed");
+        drop(log);
+        // The crash tore the last write in half.
+        let mut file = OpenOptions::new().append(true).open(&path).expect("open log");
+        file.write_all(br#"{"command_id":"cmd-running","sta"#).expect("torn write");
+
+        let mut seen = SeenCommandCache::new(16, 0);
+        let mut log = CommandLog::open(&path).expect("reopen log");
+        let replay = log.replay(&mut seen, 10_000).expect("replay");
+        assert_eq!(replay.resume, vec![running.clone()]);
+        assert_eq!(replay.orphaned.len(), 1);
+        assert_eq!(replay.orphaned[0].command_id, "cmd-stale");
+        assert_eq!(replay.orphaned[0].termination, Termination::Interrupted);
+        assert!(!replay.orphaned[0].success);
+        assert_eq!(log.get("cmd-finished").map(|command| command.status), Some(CommandStatus::Completed));
+
+        // The stale command is reported once, not on every restart.
+        let mut log = CommandLog::open(&path).expect("reopen again");
+        assert_eq!(log.get("cmd-stale").map(|command| command.status), Some(CommandStatus::Failed));
+        let replay = log.replay(&mut SeenCommandCache::new(16, 0), 10_000).expect("second replay");
+        assert_eq!(replay.resume, vec![running]);
+        assert!(replay.orphaned.is_empty());
+        let _ = fs::remove_dir_all(path.parent().expect("log dir"));
+    }
+
+    #[test]
+    fn re_accepting_a_command_is_a_no_op() {
+        let path = log_path("idempotent");
+        let command = request("cmd-1", 1_000, 100_000);
+        let mut seen = SeenCommandCache::new(16, 0);
+        let mut log = CommandLog::open(&path).expect("new log");
+        assert_eq!(log.accept(&command, &mut seen, 1_000), Ok(true));
+        assert_eq!(log.accept(&command, &mut seen, 1_100), Ok(false));
+        let lines = fs::read_to_string(&path).expect("read log").lines().count();
+        assert_eq!(lines, 1);
+
+        // After a restart the replayed log seeds a fresh cache, so the command is still
+        // refused when it is delivered again.
+        let mut seen = SeenCommandCache::new(16, 0);
+        let mut log = CommandLog::open(&path).expect("reopen log");
+        log.replay(&mut seen, 2_000).expect("replay");
+        assert!(seen.contains("cmd-1", 2_000));
+        assert_eq!(log.accept(&command, &mut seen, 2_000), Ok(false));
+        assert_eq!(fs::read_to_string(&path).expect("read log").lines().count(), lines);
+
+        // Once finished and expired, compaction drops the command.
+        let outcome = ExecutionOutcome::new(&command, Some(0), 2_000, 2_100, b"", b"", 64);
+        log.mark_finished(&outcome, 2_100).expect("completed");
+        assert_eq!(log.compact(200_000), Ok(1));
+        assert!(CommandLog::open(&path).expect("compacted log").get("cmd-1").is_none());
+        let _ = fs::remove_dir_all(path.parent().expect("log dir"));
+    }
+}
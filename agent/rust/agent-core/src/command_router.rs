@@ -1,57 +1,294 @@
-use crate::policy::PolicyBundle;
-use crate::security::{validate_bounded_string, ValidationLimits};
+use std::fmt;
+use std::sync::Arc;
+
+use crate::crypto::{HmacSha256, Verifier};
+use crate::policy::{PolicyBundle, PolicyValidationOptions};
+use crate::security::{argument_hazards, validate_bounded_string, ArgumentHazard, ValidationLimits};
+use crate::time::{clock_skew_tolerance_ms_from_env, not_before_widening_ms, within_window};
 
 #[derive(Debug, Clone)]
 pub struct SignedCommand {
     pub command_id: String,
     pub signed_payload: String,
+    /// Base64 signature over `command_signing_message`.
+    pub signature: String,
     pub action: String,
     pub arguments: Vec<String>,
     pub not_before_unix_time_ms: u64,
     pub not_after_unix_time_ms: u64,
 }
 
+/// Individual policy checks applied to a signed command, in evaluation order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandCheck {
+    CommandId,
+    Signature,
+    ActionAllowed,
+    Arguments,
+    ArgumentSafety,
+    TimeWindow,
+}
+
+#[derive(Debug, Clone)]
+pub struct CheckResult {
+    pub check: CommandCheck,
+    pub passed: bool,
+    pub reason: Option<String>,
+}
+
+/// Outcome of evaluating every check for a command without short-circuiting.
+#[derive(Debug, Clone)]
+pub struct CommandDecision {
+    pub command_id: String,
+    pub results: Vec<CheckResult>,
+}
+
+impl CommandDecision {
+    pub fn allowed(&self) -> bool {
+        self.results.iter().all(|result| result.passed)
+    }
+
+    pub fn failed_checks(&self) -> Vec<CommandCheck> {
+        self.results
+            .iter()
+            .filter(|result| !result.passed)
+            .map(|result| result.check)
+            .collect()
+    }
+}
+
+/// Router settings that are local to the agent rather than carried in the signed policy.
+#[derive(Clone)]
+pub struct CommandRouteConfig {
+    pub clock_skew_tolerance_ms: u64,
+    /// Added to the tolerance before `not_before` only, after the clock was set back.
+    pub not_before_widening_ms: u64,
+    /// Checks command signatures. Without one, every command is refused.
+    pub verifier: Option<Arc<dyn Verifier>>,
+}
+
+impl fmt::Debug for CommandRouteConfig {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_struct("CommandRouteConfig")
+            .field("clock_skew_tolerance_ms", &self.clock_skew_tolerance_ms)
+            .field("not_before_widening_ms", &self.not_before_widening_ms)
+            .field("verifier", &self.verifier.as_ref().map(|verifier| verifier.algorithm()))
+            .finish()
+    }
+}
+
+impl CommandRouteConfig {
+    /// Commands are verified with `AGENT_POLICY_SIGNING_KEY`, the key that signs policy
+    /// bundles and command results.
+    pub fn from_env() -> Self {
+        let verifier = PolicyValidationOptions::from_env()
+            .signing_key
+            .filter(|key| !key.is_empty())
+            .map(|key| Arc::new(HmacSha256::new(key.as_bytes())) as Arc<dyn Verifier>);
+        Self {
+            clock_skew_tolerance_ms: clock_skew_tolerance_ms_from_env(),
+            not_before_widening_ms: not_before_widening_ms(),
+            verifier,
+        }
+    }
+
+    pub fn with_verifier(mut self, verifier: Arc<dyn Verifier>) -> Self {
+        self.verifier = Some(verifier);
+        self
+    }
+}
+
+/// `command_id=<id>|action=<action>|arguments=<arguments JSON>|payload=<signed_payload>`.
+/// The validity window is left out because the agent fills in a missing expiry itself;
+/// re-delivery is caught by the command id instead.
+pub fn command_signing_message(command: &SignedCommand) -> String {
+    format!(
+        "command_id={}|action={}|arguments={}|payload={}",
+        command.command_id,
+        command.action,
+        serde_json::to_string(&command.arguments).unwrap_or_default(),
+        command.signed_payload
+    )
+}
+
+type CheckFn = fn(&SignedCommand, &PolicyBundle, &CommandRouteConfig, u64) -> Result<(), String>;
+
+const CHECKS: [(CommandCheck, CheckFn); 6] = [
+    (CommandCheck::CommandId, check_command_id),
+    (CommandCheck::Signature, check_signature),
+    (CommandCheck::ActionAllowed, check_action),
+    (CommandCheck::Arguments, check_arguments),
+    (CommandCheck::ArgumentSafety, check_argument_safety),
+    (CommandCheck::TimeWindow, check_time_window),
+];
+
 pub fn route_command(command: SignedCommand, policy: &PolicyBundle, now_unix_time_ms: u64) -> bool {
-    // TODO: Verify signature against trust bundle and enforcement keys.
+    route_command_with_config(command, policy, &CommandRouteConfig::from_env(), now_unix_time_ms)
+}
+
+pub fn route_command_with_config(
+    command: SignedCommand,
+    policy: &PolicyBundle,
+    config: &CommandRouteConfig,
+    now_unix_time_ms: u64,
+) -> bool {
+    CHECKS
+        .iter()
+        .all(|(_, check)| check(&command, policy, config, now_unix_time_ms).is_ok())
+}
+
+/// What-if evaluation: run every check and report each result so a single call surfaces
+/// all problems with a command. Nothing is queued or executed.
+pub fn route_command_explain(command: &SignedCommand, policy: &PolicyBundle, now_unix_time_ms: u64) -> CommandDecision {
+    route_command_explain_with_config(command, policy, &CommandRouteConfig::from_env(), now_unix_time_ms)
+}
+
+pub fn route_command_explain_with_config(
+    command: &SignedCommand,
+    policy: &PolicyBundle,
+    config: &CommandRouteConfig,
+    now_unix_time_ms: u64,
+) -> CommandDecision {
+    let results = CHECKS
+        .iter()
+        .map(|(kind, check)| {
+            let outcome = check(command, policy, config, now_unix_time_ms);
+            CheckResult {
+                check: *kind,
+                passed: outcome.is_ok(),
+                reason: outcome.err(),
+            }
+        })
+        .collect();
+
+    CommandDecision {
+        command_id: command.command_id.clone(),
+        results,
+    }
+}
+
+fn check_command_id(command: &SignedCommand, _policy: &PolicyBundle, _config: &CommandRouteConfig, _now_unix_time_ms: u64) -> Result<(), String> {
     let limits = ValidationLimits::default_limits();
     if !validate_bounded_string(&command.command_id, limits.max_command_id_len) {
-        return false;
+        return Err("Command identifier empty or too long".to_string());
     }
+    Ok(())
+}
+
+fn check_signature(command: &SignedCommand, _policy: &PolicyBundle, config: &CommandRouteConfig, _now_unix_time_ms: u64) -> Result<(), String> {
+    let limits = ValidationLimits::default_limits();
     if !validate_bounded_string(&command.signed_payload, limits.max_payload_len) {
-        return false;
+        return Err("Signed payload empty or too long".to_string());
     }
+    verify_command_signature(command, config)
+}
+
+/// Check `command.signature` with the configured verifier; an error without a key.
+pub fn verify_command_signature(command: &SignedCommand, config: &CommandRouteConfig) -> Result<(), String> {
+    let Some(verifier) = &config.verifier else {
+        return Err("No command signing key configured".to_string());
+    };
+    if !verifier.verify(command_signing_message(command).as_bytes(), &command.signature) {
+        return Err("Command signature does not verify".to_string());
+    }
+    Ok(())
+}
+
+fn check_action(command: &SignedCommand, policy: &PolicyBundle, _config: &CommandRouteConfig, _now_unix_time_ms: u64) -> Result<(), String> {
+    let limits = ValidationLimits::default_limits();
     if !validate_bounded_string(&command.action, limits.max_command_id_len) {
-        return false;
+        return Err("Action name empty or too long".to_string());
     }
     if !policy.allows_action(&command.action) {
-        return false;
+        return Err(format!("Action {} not permitted by policy", command.action));
     }
+    Ok(())
+}
+
+fn check_arguments(command: &SignedCommand, policy: &PolicyBundle, _config: &CommandRouteConfig, _now_unix_time_ms: u64) -> Result<(), String> {
     if command.arguments.len() > policy.execution.max_arguments {
-        return false;
+        return Err(format!(
+            "Argument count {} exceeds policy maximum of {}",
+            command.arguments.len(),
+            policy.execution.max_arguments
+        ));
     }
     if !command
         .arguments
         .iter()
         .all(|arg| validate_bounded_string(arg, policy.execution.max_argument_length))
     {
-        return false;
+        return Err("Argument empty or exceeds policy length limit".to_string());
     }
+    Ok(())
+}
+
+/// Reject arguments carrying shell, environment-expansion, or traversal tokens unless the
+/// policy's rules for this action allow that hazard class.
+fn check_argument_safety(command: &SignedCommand, policy: &PolicyBundle, _config: &CommandRouteConfig, _now_unix_time_ms: u64) -> Result<(), String> {
+    let rules = policy.argument_rules_for(&command.action);
+    for (index, argument) in command.arguments.iter().enumerate() {
+        for hazard in argument_hazards(argument) {
+            let allowed = match hazard {
+                ArgumentHazard::ShellMetacharacter => rules.allow_shell_metacharacters,
+                ArgumentHazard::EnvExpansion => rules.allow_env_expansion,
+                ArgumentHazard::PathTraversal => rules.allow_path_traversal,
+            };
+            if !allowed {
+                return Err(format!("Argument {} contains {:?} not permitted for {}", index, hazard, command.action));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn check_time_window(command: &SignedCommand, _policy: &PolicyBundle, config: &CommandRouteConfig, now_unix_time_ms: u64) -> Result<(), String> {
     if command.not_before_unix_time_ms > command.not_after_unix_time_ms {
-        return false;
+        return Err("Command validity window is inverted".to_string());
     }
-    if now_unix_time_ms < command.not_before_unix_time_ms
-        || now_unix_time_ms > command.not_after_unix_time_ms
-    {
-        return false;
+    if !within_window(
+        now_unix_time_ms,
+        command.not_before_unix_time_ms.saturating_sub(config.not_before_widening_ms),
+        command.not_after_unix_time_ms,
+        config.clock_skew_tolerance_ms,
+    ) {
+        return Err("Command outside its validity window".to_string());
     }
-
-    true
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{route_command, SignedCommand};
-    use crate::policy::{ExecutionPolicy, PolicyBundle};
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+
+    use super::{
+        command_signing_message, route_command_explain_with_config, route_command_with_config, CommandCheck,
+        CommandDecision, CommandRouteConfig, SignedCommand,
+    };
+    use crate::crypto::{HmacSha256, Signer};
+    use crate::policy::{ArgumentRules, ExecutionPolicy, PolicyBundle};
+
+    const KEY: &[u8] = b"unit-test-command-key";
+
+    fn keyed() -> CommandRouteConfig {
+        CommandRouteConfig::from_env().with_verifier(Arc::new(HmacSha256::new(KEY)))
+    }
+
+    fn signed(mut command: SignedCommand) -> SignedCommand {
+        command.signature = HmacSha256::new(KEY).sign(command_signing_message(&command).as_bytes());
+        command
+    }
+
+    /// Sign `command` as issued, then route it.
+    fn route_command(command: SignedCommand, policy: &PolicyBundle, now: u64) -> bool {
+        route_command_with_config(signed(command), policy, &keyed(), now)
+    }
+
+    fn route_command_explain(command: &SignedCommand, policy: &PolicyBundle, now: u64) -> CommandDecision {
+        route_command_explain_with_config(&signed(command.clone()), policy, &keyed(), now)
+    }
 
     fn build_policy() -> PolicyBundle {
         PolicyBundle {
@@ -65,8 +302,12 @@ mod tests {
                 allowed_actions: vec!["patch-apply".to_string(), "script-run".to_string()],
                 max_arguments: 2,
                 max_argument_length: 8,
+                argument_rules: BTreeMap::new(),
+                file_destinations: Vec::new(),
             },
             telemetry_streams: vec!["agent".to_string(), "sensor".to_string()],
+            stream_categories: std::collections::BTreeMap::new(),
+            evidence_profiles: std::collections::BTreeMap::new(),
         }
     }
 
@@ -74,6 +315,7 @@ mod tests {
         SignedCommand {
             command_id: "cmd-1".to_string(),
             signed_payload: "signed".to_string(),
+            signature: String::new(),
             action: "script-run".to_string(),
             arguments: vec!["-v".to_string()],
             not_before_unix_time_ms: 10,
@@ -108,8 +350,154 @@ mod tests {
     fn rejects_time_window() {
         let policy = build_policy();
         let mut command = build_command();
-        command.not_before_unix_time_ms = 30;
-        command.not_after_unix_time_ms = 40;
+        command.not_before_unix_time_ms = 120_000;
+        command.not_after_unix_time_ms = 180_000;
         assert!(!route_command(command, &policy, 20));
     }
+
+    #[test]
+    fn explain_reports_every_failed_check() {
+        let policy = build_policy();
+        let mut command = build_command();
+        command.action = "forbidden".to_string();
+        command.not_before_unix_time_ms = 120_000;
+        command.not_after_unix_time_ms = 180_000;
+
+        let decision = route_command_explain(&command, &policy, 15);
+        assert!(!decision.allowed());
+        assert_eq!(decision.failed_checks(), vec![CommandCheck::ActionAllowed, CommandCheck::TimeWindow]);
+        assert_eq!(decision.results.len(), 6);
+        assert!(decision
+            .results
+            .iter()
+            .filter(|result| !result.passed)
+            .all(|result| result.reason.is_some()));
+        assert!(!route_command(command, &policy, 15));
+    }
+
+    #[test]
+    fn explain_allows_valid_command() {
+        let policy = build_policy();
+        let decision = route_command_explain(&build_command(), &policy, 15);
+        assert!(decision.allowed());
+        assert!(decision.failed_checks().is_empty());
+    }
+
+    fn failed_checks_for(argument: &str, policy: &PolicyBundle) -> Vec<CommandCheck> {
+        let mut command = build_command();
+        command.arguments = vec![argument.to_string()];
+        route_command_explain(&command, policy, 15).failed_checks()
+    }
+
+    #[test]
+    fn rejects_path_traversal_argument() {
+        let mut policy = build_policy();
+        policy.execution.max_argument_length = 64;
+        assert_eq!(failed_checks_for("..\\..\\secrets", &policy), vec![CommandCheck::ArgumentSafety]);
+        assert_eq!(failed_checks_for("--out=../etc/passwd", &policy), vec![CommandCheck::ArgumentSafety]);
+    }
+
+    #[test]
+    fn rejects_command_substitution_and_env_expansion() {
+        let mut policy = build_policy();
+        policy.execution.max_argument_length = 64;
+        assert_eq!(failed_checks_for("$(rm -rf /)", &policy), vec![CommandCheck::ArgumentSafety]);
+        assert_eq!(failed_checks_for("`whoami`", &policy), vec![CommandCheck::ArgumentSafety]);
+        assert_eq!(failed_checks_for("a && b", &policy), vec![CommandCheck::ArgumentSafety]);
+        assert_eq!(failed_checks_for("${HOME}", &policy), vec![CommandCheck::ArgumentSafety]);
+        assert_eq!(failed_checks_for("%APPDATA%\\x", &policy), vec![CommandCheck::ArgumentSafety]);
+        for argument in ["a% b%PATH%", "!USERPROFILE!\\x", "100% of 5%"] {
+            assert_eq!(failed_checks_for(argument, &policy), vec![CommandCheck::ArgumentSafety], "{}", argument);
+        }
+    }
+
+    #[test]
+    fn accepts_benign_arguments() {
+        let mut policy = build_policy();
+        policy.execution.max_argument_length = 64;
+        for argument in ["-version", "C:\\Tools\\app.exe", "/opt/app/run.sh", "50%", "v1.2..3", "$5"] {
+            assert!(failed_checks_for(argument, &policy).is_empty(), "{} should pass", argument);
+        }
+    }
+
+    #[test]
+    fn action_rules_relax_argument_scan() {
+        let mut policy = build_policy();
+        policy.execution.max_argument_length = 64;
+        policy.execution.argument_rules.insert(
+            "script-run".to_string(),
+            ArgumentRules {
+                allow_path_traversal: true,
+                ..ArgumentRules::default()
+            },
+        );
+        assert!(failed_checks_for("../shared/tool", &policy).is_empty());
+        assert_eq!(failed_checks_for("$(id)", &policy), vec![CommandCheck::ArgumentSafety]);
+    }
+
+    #[test]
+    fn tolerates_clock_skew_at_window_edges() {
+        let policy = build_policy();
+        let config = CommandRouteConfig {
+            clock_skew_tolerance_ms: 5_000,
+            not_before_widening_ms: 0,
+            ..keyed()
+        };
+        let mut command = build_command();
+        command.not_before_unix_time_ms = 1_000_000;
+        command.not_after_unix_time_ms = 1_300_000;
+        let command = signed(command);
+
+        assert!(route_command_with_config(command.clone(), &policy, &config, 999_000));
+        assert!(route_command_with_config(command.clone(), &policy, &config, 1_301_000));
+        assert!(!route_command_with_config(command.clone(), &policy, &config, 940_000));
+        assert!(!route_command_with_config(command.clone(), &policy, &config, 1_360_000));
+
+        let strict = CommandRouteConfig {
+            clock_skew_tolerance_ms: 0,
+            not_before_widening_ms: 0,
+            ..keyed()
+        };
+        assert!(!route_command_with_config(command.clone(), &policy, &strict, 999_000));
+
+        let set_back = CommandRouteConfig {
+            clock_skew_tolerance_ms: 5_000,
+            not_before_widening_ms: 60_000,
+            ..keyed()
+        };
+        assert!(route_command_with_config(command.clone(), &policy, &set_back, 940_000));
+        assert!(!route_command_with_config(command, &policy, &set_back, 1_360_000));
+    }
+
+    #[test]
+    fn refuses_unsigned_tampered_and_unverifiable_commands() {
+        let policy = build_policy();
+        let issued = signed(build_command());
+        assert!(route_command_explain_with_config(&issued, &policy, &keyed(), 15).allowed());
+
+        let unsigned = build_command();
+        let decision = route_command_explain_with_config(&unsigned, &policy, &keyed(), 15);
+        assert_eq!(decision.failed_checks(), vec![CommandCheck::Signature]);
+
+        let mut retargeted = issued.clone();
+        retargeted.arguments = vec!["-x".to_string()];
+        assert_eq!(
+            route_command_explain_with_config(&retargeted, &policy, &keyed(), 15).failed_checks(),
+            vec![CommandCheck::Signature]
+        );
+        let mut other_action = issued.clone();
+        other_action.action = "patch-apply".to_string();
+        assert!(!route_command_with_config(other_action, &policy, &keyed(), 15));
+
+        let other_key = CommandRouteConfig::from_env().with_verifier(Arc::new(HmacSha256::new(b"other-key")));
+        assert!(!route_command_with_config(issued.clone(), &policy, &other_key, 15));
+
+        let no_key = CommandRouteConfig {
+            verifier: None,
+            ..keyed()
+        };
+        let decision = route_command_explain_with_config(&issued, &policy, &no_key, 15);
+        assert_eq!(decision.failed_checks(), vec![CommandCheck::Signature]);
+        assert_eq!(decision.results[1].reason.as_deref(), Some("No command signing key configured"));
+    }
 }
@@ -1,12 +1,24 @@
+use std::collections::{BTreeMap, HashMap};
 use std::env;
-use std::path::PathBuf;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
+use serde::Serialize;
 use sha2::{Digest, Sha256};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
 
-use crate::time::unix_time_ms;
+use crate::certificates::{certificate_issues, scan_certificates, CertificateConfig};
+use crate::policy::{PolicyBundle, PolicyValidationOptions};
+use crate::time::{format_date, unix_time_ms};
+use crate::uplink::enqueue_rmm_item;
+
+const COMPLIANCE_PATH: &str = "/compliance";
 
 /// Outcome of a compliance check, including an immutable evidence reference.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct ComplianceResult {
     pub control_id: String,
     pub control_title: String,
@@ -15,9 +27,12 @@ pub struct ComplianceResult {
     pub evidence_ref: String,
     pub checked_at_unix_ms: u64,
     pub findings: Vec<String>,
+    /// Service that asserted this result over IPC; `None` for checks run by the core.
+    pub asserted_by: Option<String>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum ComplianceStatus {
     Pass,
     Fail,
@@ -38,6 +53,11 @@ enum ComplianceCheckKind {
     PathExists { path: PathBuf, must_be_file: bool, must_be_dir: bool },
     NumericMax { name: String, max_value: u64 },
     NumericMin { name: String, min_value: u64 },
+    /// The policy from `AGENT_POLICY_PATH`/`AGENT_POLICY_JSON` is signature-verified and
+    /// within its validity window.
+    PolicyValid,
+    /// No certificate under `CERT_SCAN_PATHS` is expired, close to expiry or weak.
+    CertificatesHealthy,
 }
 
 #[derive(Debug, Clone)]
@@ -46,6 +66,10 @@ pub struct ComplianceConfig {
     pub required_paths: Vec<PathBuf>,
     pub max_payload_bytes: Option<u64>,
     pub min_payload_bytes: Option<u64>,
+    /// Set when `CERT_SCAN_PATHS` is configured.
+    pub check_certificates: bool,
+    /// Pause between compliance reports, see [`run_compliance_loop`].
+    pub report_interval_secs: u64,
 }
 
 impl ComplianceConfig {
@@ -75,11 +99,20 @@ impl ComplianceConfig {
             .ok()
             .and_then(|value| value.parse::<u64>().ok());
 
+        let check_certificates = !CertificateConfig::from_env().scan_paths.is_empty();
+        let report_interval_secs = env::var("COMPLIANCE_REPORT_INTERVAL_SECS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .filter(|value| *value > 0)
+            .unwrap_or(3600);
+
         Self {
             required_env,
             required_paths,
             max_payload_bytes,
             min_payload_bytes,
+            check_certificates,
+            report_interval_secs,
         }
     }
 }
@@ -99,6 +132,197 @@ pub fn run_self_audit_with_config(config: &ComplianceConfig) -> Vec<ComplianceRe
         .collect()
 }
 
+/// Run the local checks, then append the assertions collected since the last report.
+pub fn run_self_audit_with_assertions(config: &ComplianceConfig, assertions: &mut AssertionLedger) -> Vec<ComplianceResult> {
+    let mut results = run_self_audit_with_config(config);
+    results.extend(assertions.drain());
+    results
+}
+
+/// Queue `results` as one report for the `/compliance` endpoint, logging each failed control.
+pub async fn report_compliance(
+    queue_dir: &Path,
+    asset_id: &str,
+    results: &[ComplianceResult],
+    now_unix_ms: u64,
+) -> Result<(), String> {
+    for result in results.iter().filter(|result| matches!(result.status, ComplianceStatus::Fail)) {
+        warn!(
+            control_id = %result.control_id,
+            evidence_ref = %result.evidence_ref,
+            finding = %result.findings.join("; "),
+            "compliance control failed"
+        );
+    }
+    let payload_json = serde_json::json!({
+        "asset_id": asset_id,
+        "generated_at_unix_ms": now_unix_ms,
+        "results": results,
+    })
+    .to_string();
+    enqueue_rmm_item(queue_dir, COMPLIANCE_PATH, &payload_json, &format!("compliance-{}", now_unix_ms)).await?;
+    info!(controls = results.len(), "compliance report queued");
+    Ok(())
+}
+
+/// Control evaluated outside the self-audit on every report, such as feed freshness or clock
+/// drift; receives the report time in unix milliseconds and yields a result only when it fails.
+pub type ComplianceSource = Box<dyn Fn(u64) -> Option<ComplianceResult> + Send + Sync>;
+
+/// The local checks, the assertions accepted since the last report and every failing source.
+pub fn collect_compliance(
+    config: &ComplianceConfig,
+    assertions: &Mutex<AssertionLedger>,
+    sources: &[ComplianceSource],
+    now_unix_ms: u64,
+) -> Vec<ComplianceResult> {
+    let mut results = run_self_audit_with_assertions(
+        config,
+        &mut assertions.lock().unwrap_or_else(|poisoned| poisoned.into_inner()),
+    );
+    results.extend(sources.iter().filter_map(|source| source(now_unix_ms)));
+    results
+}
+
+/// Queue a compliance report every `report_interval_secs` until shutdown. The checks read
+/// files and certificates, so they run on the blocking pool.
+pub async fn run_compliance_loop(
+    config: ComplianceConfig,
+    assertions: Arc<Mutex<AssertionLedger>>,
+    sources: Vec<ComplianceSource>,
+    queue_dir: PathBuf,
+    asset_id: String,
+    token: CancellationToken,
+) {
+    let interval = Duration::from_secs(config.report_interval_secs);
+    let config = Arc::new(config);
+    let sources = Arc::new(sources);
+    loop {
+        let (config, assertions, sources) = (Arc::clone(&config), Arc::clone(&assertions), Arc::clone(&sources));
+        let now = unix_time_ms();
+        match tokio::task::spawn_blocking(move || collect_compliance(&config, &assertions, &sources, now)).await {
+            Ok(results) => {
+                if let Err(err) = report_compliance(&queue_dir, &asset_id, &results, now).await {
+                    warn!(error = %err, "failed to queue compliance report");
+                }
+            }
+            Err(err) => warn!(error = %err, "compliance checks failed"),
+        }
+        tokio::select! {
+            _ = token.cancelled() => break,
+            _ = tokio::time::sleep(interval) => {}
+        }
+    }
+}
+
+/// Result for a known control reported by another agent service.
+#[derive(Debug, Clone)]
+pub struct ExternalAssertion {
+    pub control_id: String,
+    pub passed: bool,
+    pub evidence_ref: String,
+    pub evaluated_at_unix_ms: u64,
+    pub source_service: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AssertionRejection {
+    UnknownControl,
+    MalformedEvidenceRef,
+}
+
+impl AssertionRejection {
+    pub fn metric_label(&self) -> &'static str {
+        match self {
+            AssertionRejection::UnknownControl => "unknown_control",
+            AssertionRejection::MalformedEvidenceRef => "malformed_evidence_ref",
+        }
+    }
+}
+
+impl fmt::Display for AssertionRejection {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AssertionRejection::UnknownControl => write!(formatter, "control is not a known compliance check"),
+            AssertionRejection::MalformedEvidenceRef => write!(formatter, "evidence_ref is not a SHA-256 hex digest"),
+        }
+    }
+}
+
+/// Assertions accepted since the last report, at most one per control.
+#[derive(Debug, Clone)]
+pub struct AssertionLedger {
+    /// Known control ids and their titles.
+    controls: HashMap<String, String>,
+    assertions: BTreeMap<String, ExternalAssertion>,
+}
+
+impl AssertionLedger {
+    pub fn from_config(config: &ComplianceConfig) -> Self {
+        Self {
+            controls: build_checks(config)
+                .into_iter()
+                .map(|check| (check.id, check.title))
+                .collect(),
+            assertions: BTreeMap::new(),
+        }
+    }
+
+    /// Accept an assertion for a known control with a SHA-256 evidence reference. A later
+    /// assertion for the same control replaces the earlier one unless it was evaluated
+    /// before it.
+    pub fn record(&mut self, assertion: ExternalAssertion) -> Result<(), AssertionRejection> {
+        if !self.controls.contains_key(&assertion.control_id) {
+            return Err(AssertionRejection::UnknownControl);
+        }
+        if !is_sha256_hex(&assertion.evidence_ref) {
+            return Err(AssertionRejection::MalformedEvidenceRef);
+        }
+        let superseded = self
+            .assertions
+            .get(&assertion.control_id)
+            .map(|existing| existing.evaluated_at_unix_ms > assertion.evaluated_at_unix_ms)
+            .unwrap_or(false);
+        if !superseded {
+            self.assertions.insert(assertion.control_id.clone(), assertion);
+        }
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.assertions.len()
+    }
+
+    /// Take the pending assertions as compliance results, leaving the ledger empty.
+    pub fn drain(&mut self) -> Vec<ComplianceResult> {
+        std::mem::take(&mut self.assertions)
+            .into_values()
+            .map(|assertion| ComplianceResult {
+                control_title: self
+                    .controls
+                    .get(&assertion.control_id)
+                    .cloned()
+                    .unwrap_or_default(),
+                control_id: assertion.control_id,
+                passed: assertion.passed,
+                status: if assertion.passed {
+                    ComplianceStatus::Pass
+                } else {
+                    ComplianceStatus::Fail
+                },
+                evidence_ref: assertion.evidence_ref,
+                checked_at_unix_ms: assertion.evaluated_at_unix_ms,
+                findings: vec![format!("Asserted by {}.", assertion.source_service)],
+                asserted_by: Some(assertion.source_service),
+            })
+            .collect()
+    }
+}
+
+fn is_sha256_hex(value: &str) -> bool {
+    value.len() == 64 && value.bytes().all(|byte| byte.is_ascii_hexdigit())
+}
+
 fn build_checks(config: &ComplianceConfig) -> Vec<ComplianceCheck> {
     let mut checks = Vec::new();
 
@@ -150,6 +374,22 @@ fn build_checks(config: &ComplianceConfig) -> Vec<ComplianceCheck> {
         });
     }
 
+    checks.push(ComplianceCheck {
+        id: "CMP-POLICY-VALID".to_string(),
+        title: "Signed, unexpired policy loaded".to_string(),
+        description: "The agent must run under a signature-verified policy bundle within its validity window.".to_string(),
+        kind: ComplianceCheckKind::PolicyValid,
+    });
+
+    if config.check_certificates {
+        checks.push(ComplianceCheck {
+            id: "CMP-CERT-HEALTH".to_string(),
+            title: "Service certificates valid and strong".to_string(),
+            description: "Certificates on the endpoint must be unexpired, not close to expiry and use strong keys.".to_string(),
+            kind: ComplianceCheckKind::CertificatesHealthy,
+        });
+    }
+
     checks
 }
 
@@ -216,6 +456,18 @@ fn evaluate_check(check: &ComplianceCheck, checked_at_unix_ms: u64) -> Complianc
                 false
             }
         },
+        ComplianceCheckKind::PolicyValid => {
+            findings.extend(policy_findings(
+                &PolicyBundle::from_env(),
+                &PolicyValidationOptions::from_env(),
+                checked_at_unix_ms,
+            ));
+            findings.is_empty()
+        }
+        ComplianceCheckKind::CertificatesHealthy => {
+            findings.extend(certificate_findings(&CertificateConfig::from_env(), checked_at_unix_ms));
+            findings.is_empty()
+        }
     };
 
     let status = if passed {
@@ -232,9 +484,42 @@ fn evaluate_check(check: &ComplianceCheck, checked_at_unix_ms: u64) -> Complianc
         evidence_ref: build_evidence_ref(check, checked_at_unix_ms, &findings),
         checked_at_unix_ms,
         findings,
+        asserted_by: None,
     }
 }
 
+/// Findings for the policy check; empty only when the bundle validates with a configured
+/// signing key. `AGENT_POLICY_ALLOW_UNSIGNED` lets the agent start but never passes this check.
+fn policy_findings(policy: &PolicyBundle, options: &PolicyValidationOptions, now_unix_ms: u64) -> Vec<String> {
+    let mut findings = Vec::new();
+    if options.signing_key.is_none() {
+        findings.push("AGENT_POLICY_SIGNING_KEY is not set; policy signature cannot be verified.".to_string());
+    }
+    if let Err(err) = policy.check(now_unix_ms, options) {
+        findings.push(format!("Policy {} failed validation: {}.", policy.version, err));
+    }
+    findings
+}
+
+fn certificate_findings(config: &CertificateConfig, now_unix_ms: u64) -> Vec<String> {
+    scan_certificates(config)
+        .iter()
+        .flat_map(|record| {
+            certificate_issues(&record.certificate, config, now_unix_ms)
+                .into_iter()
+                .map(move |issue| {
+                    format!(
+                        "{} ({}, not after {}) {}.",
+                        record.certificate.subject,
+                        record.path.display(),
+                        format_date(record.certificate.not_after_unix_secs),
+                        issue
+                    )
+                })
+        })
+        .collect()
+}
+
 fn build_evidence_ref(check: &ComplianceCheck, checked_at_unix_ms: u64, findings: &[String]) -> String {
     let mut hasher = Sha256::new();
     hasher.update(check.id.as_bytes());
@@ -263,3 +548,150 @@ fn parse_csv(value: String) -> Vec<String> {
         .filter(|entry| !entry.is_empty())
         .collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::{
+        collect_compliance, policy_findings, AssertionLedger, AssertionRejection, ComplianceConfig, ComplianceResult,
+        ComplianceSource, ComplianceStatus, ExternalAssertion,
+    };
+    use crate::policy::{PolicyBundle, PolicyValidationOptions};
+
+    const SIGNING_KEY: &str = "compliance-test-key";
+
+    fn options(signing_key: Option<&str>, allow_unsigned: bool) -> PolicyValidationOptions {
+        PolicyValidationOptions {
+            signing_key: signing_key.map(str::to_string),
+            expected_key_id: None,
+            allow_unsigned,
+            clock_skew_tolerance_ms: 0,
+            not_before_widening_ms: 0,
+        }
+    }
+
+    fn signed_policy(expires_at_unix_time_ms: u64) -> PolicyBundle {
+        let mut policy = PolicyBundle::placeholder();
+        policy.execution.allowed_actions.sort();
+        policy.telemetry_streams.sort();
+        policy.expires_at_unix_time_ms = expires_at_unix_time_ms;
+        assert!(policy.sign_with_key(SIGNING_KEY));
+        policy
+    }
+
+    #[test]
+    fn passes_for_signed_unexpired_policy() {
+        let policy = signed_policy(10_000);
+        assert!(policy_findings(&policy, &options(Some(SIGNING_KEY), false), 5_000).is_empty());
+    }
+
+    #[test]
+    fn fails_for_expired_or_unsigned_policy() {
+        let policy = signed_policy(10_000);
+        let expired = policy_findings(&policy, &options(Some(SIGNING_KEY), false), 20_000);
+        assert_eq!(expired, vec!["Policy policy-placeholder failed validation: policy expired at 10000.".to_string()]);
+
+        let mut tampered = policy.clone();
+        tampered.execution.max_arguments = 64;
+        let findings = policy_findings(&tampered, &options(Some(SIGNING_KEY), false), 5_000);
+        assert_eq!(findings.len(), 1);
+        assert!(findings[0].ends_with("policy signature did not verify."));
+
+        let unsigned = policy_findings(&PolicyBundle::placeholder(), &options(None, true), 5_000);
+        assert!(!unsigned.is_empty());
+        assert!(unsigned[0].contains("AGENT_POLICY_SIGNING_KEY is not set"));
+    }
+
+    fn config() -> ComplianceConfig {
+        ComplianceConfig {
+            required_env: vec!["AGENT_ID".to_string()],
+            required_paths: Vec::new(),
+            max_payload_bytes: Some(1024),
+            min_payload_bytes: None,
+            check_certificates: false,
+            report_interval_secs: 3600,
+        }
+    }
+
+    fn ledger() -> AssertionLedger {
+        AssertionLedger::from_config(&config())
+    }
+
+    fn assertion(control_id: &str, passed: bool, evaluated_at_unix_ms: u64) -> ExternalAssertion {
+        ExternalAssertion {
+            control_id: control_id.to_string(),
+            passed,
+            evidence_ref: "ab".repeat(32),
+            evaluated_at_unix_ms,
+            source_service: "agent-sensor".to_string(),
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_controls_and_malformed_evidence() {
+        let mut ledger = ledger();
+        assert_eq!(
+            ledger.record(assertion("CMP-NOT-A-CHECK", true, 1)),
+            Err(AssertionRejection::UnknownControl)
+        );
+        let mut malformed = assertion("CMP-MAX-PAYLOAD", true, 1);
+        malformed.evidence_ref = "not-a-hash".to_string();
+        assert_eq!(ledger.record(malformed), Err(AssertionRejection::MalformedEvidenceRef));
+        assert_eq!(ledger.len(), 0);
+
+        assert_eq!(ledger.record(assertion("CMP-ENV-AGENT_ID", true, 1)), Ok(()));
+        let results = ledger.drain();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].control_title, "Environment variable AGENT_ID configured");
+        assert_eq!(results[0].asserted_by.as_deref(), Some("agent-sensor"));
+        assert_eq!(ledger.len(), 0);
+    }
+
+    #[test]
+    fn keeps_most_recent_assertion_per_control() {
+        let mut ledger = ledger();
+        ledger.record(assertion("CMP-MAX-PAYLOAD", true, 10)).expect("first");
+        ledger.record(assertion("CMP-MAX-PAYLOAD", false, 20)).expect("newer");
+        ledger.record(assertion("CMP-MAX-PAYLOAD", true, 15)).expect("stale but valid");
+
+        let results = ledger.drain();
+        assert_eq!(results.len(), 1);
+        assert!(!results[0].passed);
+        assert_eq!(results[0].checked_at_unix_ms, 20);
+    }
+
+    #[test]
+    fn each_report_carries_new_assertions_and_failing_sources() {
+        let assertions = Mutex::new(ledger());
+        let sources: Vec<ComplianceSource> = vec![
+            Box::new(|now| {
+                (now >= 2_000).then(|| ComplianceResult {
+                    control_id: "clock-drift".to_string(),
+                    control_title: "Agent clock agrees with the control plane".to_string(),
+                    passed: false,
+                    status: ComplianceStatus::Fail,
+                    evidence_ref: "0".repeat(64),
+                    checked_at_unix_ms: now,
+                    findings: Vec::new(),
+                    asserted_by: None,
+                })
+            }),
+        ];
+        assertions
+            .lock()
+            .expect("ledger")
+            .record(assertion("CMP-MAX-PAYLOAD", true, 1))
+            .expect("assertion");
+
+        let ids = |results: Vec<ComplianceResult>| {
+            results
+                .into_iter()
+                .filter(|result| result.asserted_by.is_some() || result.control_id == "clock-drift")
+                .map(|result| result.control_id)
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(ids(collect_compliance(&config(), &assertions, &sources, 1_000)), vec!["CMP-MAX-PAYLOAD"]);
+        assert_eq!(ids(collect_compliance(&config(), &assertions, &sources, 2_000)), vec!["clock-drift"]);
+    }
+}
@@ -0,0 +1,150 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use flate2::read::GzDecoder;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// True when the bytes carry the gzip magic header or the path ends in `.gz`.
+pub fn is_gzip(bytes: &[u8], path: &Path) -> bool {
+    bytes.starts_with(&GZIP_MAGIC)
+        || path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.eq_ignore_ascii_case("gz"))
+            .unwrap_or(false)
+}
+
+/// Stream at most `max_bytes` from `reader`, failing as soon as one byte more is available.
+/// At most `max_bytes + 1` bytes are ever buffered, however large the source is.
+pub fn read_bounded(reader: impl Read, max_bytes: u64) -> Result<Vec<u8>, BoundedReadError> {
+    let mut buffer = Vec::new();
+    reader
+        .take(max_bytes.saturating_add(1))
+        .read_to_end(&mut buffer)
+        .map_err(BoundedReadError::Io)?;
+    if buffer.len() as u64 > max_bytes {
+        return Err(BoundedReadError::TooLarge { max_bytes });
+    }
+    Ok(buffer)
+}
+
+/// Async counterpart of `read_bounded` for tokio readers.
+pub async fn read_bounded_async(reader: impl AsyncRead + Unpin, max_bytes: u64) -> Result<Vec<u8>, BoundedReadError> {
+    let mut buffer = Vec::new();
+    reader
+        .take(max_bytes.saturating_add(1))
+        .read_to_end(&mut buffer)
+        .await
+        .map_err(BoundedReadError::Io)?;
+    if buffer.len() as u64 > max_bytes {
+        return Err(BoundedReadError::TooLarge { max_bytes });
+    }
+    Ok(buffer)
+}
+
+#[derive(Debug)]
+pub enum BoundedReadError {
+    Io(std::io::Error),
+    TooLarge { max_bytes: u64 },
+}
+
+impl std::fmt::Display for BoundedReadError {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(err) => write!(formatter, "{}", err),
+            Self::TooLarge { max_bytes } => write!(formatter, "exceeds {} bytes", max_bytes),
+        }
+    }
+}
+
+/// Read a file of at most `max_bytes`, transparently gunzipping it. Decompressed output is
+/// also capped at `max_bytes` so a small archive cannot expand without bound.
+pub fn read_file_bounded(path: &Path, max_bytes: u64) -> Result<Vec<u8>, String> {
+    let file = File::open(path).map_err(|err| format!("Unable to open {}: {}", path.display(), err))?;
+    let raw = read_bounded(file, max_bytes).map_err(|err| format!("Unable to read {}: {}", path.display(), err))?;
+
+    if is_gzip(&raw, path) {
+        gunzip_bounded(&raw, max_bytes)
+    } else {
+        Ok(raw)
+    }
+}
+
+/// Async file read capped at `max_bytes`; no decompression is applied.
+pub async fn read_file_bounded_async(path: &Path, max_bytes: u64) -> Result<Vec<u8>, String> {
+    let file = tokio::fs::File::open(path)
+        .await
+        .map_err(|err| format!("Unable to open {}: {}", path.display(), err))?;
+    read_bounded_async(file, max_bytes)
+        .await
+        .map_err(|err| format!("Unable to read {}: {}", path.display(), err))
+}
+
+pub fn gunzip_bounded(compressed: &[u8], max_bytes: u64) -> Result<Vec<u8>, String> {
+    read_bounded(GzDecoder::new(compressed), max_bytes).map_err(|err| match err {
+        BoundedReadError::Io(err) => format!("Invalid gzip data: {}", err),
+        BoundedReadError::TooLarge { max_bytes } => format!("Decompressed data exceeds {} bytes", max_bytes),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::path::Path;
+
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    use super::{gunzip_bounded, is_gzip, read_bounded, read_bounded_async, BoundedReadError};
+
+    fn gzip(bytes: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(bytes).expect("compress");
+        encoder.finish().expect("finish")
+    }
+
+    #[test]
+    fn detects_gzip_by_magic_or_extension() {
+        assert!(is_gzip(&gzip(b"{}"), Path::new("policy.json")));
+        assert!(is_gzip(b"{}", Path::new("policy.json.gz")));
+        assert!(!is_gzip(b"{}", Path::new("policy.json")));
+    }
+
+    #[test]
+    fn rejects_output_over_limit() {
+        let compressed = gzip(&vec![b'a'; 64 * 1024]);
+        assert!(compressed.len() < 1024);
+        assert!(gunzip_bounded(&compressed, 1024).is_err());
+        assert_eq!(gunzip_bounded(&compressed, 64 * 1024).expect("within limit").len(), 64 * 1024);
+    }
+
+    #[test]
+    fn stops_reading_unbounded_source_at_limit() {
+        // `repeat` never ends, so this only returns if the reader stops after max + 1 bytes.
+        let result = read_bounded(std::io::repeat(b'a'), 4096);
+        assert!(matches!(result, Err(BoundedReadError::TooLarge { max_bytes: 4096 })));
+        assert_eq!(read_bounded(&b"abc"[..], 3).expect("at limit"), b"abc");
+    }
+
+    #[tokio::test]
+    async fn stops_reading_unbounded_async_source_at_limit() {
+        let result = read_bounded_async(tokio::io::repeat(b'a'), 4096).await;
+        assert!(matches!(result, Err(BoundedReadError::TooLarge { max_bytes: 4096 })));
+    }
+
+    #[test]
+    fn rejects_gzip_bomb_without_full_expansion() {
+        // 64 MiB of zeros compresses to well under 1 MiB; decoding must stop at the cap.
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+        let chunk = vec![0_u8; 1024 * 1024];
+        for _ in 0..64 {
+            encoder.write_all(&chunk).expect("compress");
+        }
+        let bomb = encoder.finish().expect("finish");
+        assert!(bomb.len() < 1024 * 1024);
+        assert!(gunzip_bounded(&bomb, 1024 * 1024).is_err());
+    }
+}
@@ -1,8 +1,14 @@
 use std::env;
+use std::fmt;
+use std::fs;
+use std::path::Path;
 
 use serde::Deserialize;
+use serde_json::Value;
 
+/// Fields missing from a config file fall back to [`CoreConfig::placeholder`].
 #[derive(Debug, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
 pub struct CoreConfig {
     pub asset_id: String,
     pub agent_id: String,
@@ -20,6 +26,15 @@ impl CoreConfig {
         }
     }
 
+    /// Load a JSON config file, expanding `${VAR}` references in string values from the
+    /// environment. An undefined variable is an error rather than a literal `${VAR}`.
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let raw = fs::read_to_string(path).map_err(|err| ConfigError::Read(format!("{}: {}", path.display(), err)))?;
+        let mut value = serde_json::from_str::<Value>(&raw).map_err(|err| ConfigError::Parse(err.to_string()))?;
+        interpolate_value(&mut value, &|name| env::var(name).ok())?;
+        serde_json::from_value(value).map_err(|err| ConfigError::Parse(err.to_string()))
+    }
+
     pub fn from_env() -> Self {
         let placeholder = Self::placeholder();
         let asset_id = env::var("AGENT_ASSET_ID").unwrap_or(placeholder.asset_id);
@@ -38,3 +53,147 @@ impl CoreConfig {
         }
     }
 }
+
+impl Default for CoreConfig {
+    fn default() -> Self {
+        Self::placeholder()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    Read(String),
+    Parse(String),
+    UndefinedVariable(String),
+    /// The variable's value itself contains a `${...}` reference.
+    RecursiveExpansion(String),
+    MalformedReference(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read(reason) => write!(formatter, "Unable to read config file {}", reason),
+            ConfigError::Parse(reason) => write!(formatter, "Invalid config file: {}", reason),
+            ConfigError::UndefinedVariable(name) => write!(formatter, "Config references undefined variable {}", name),
+            ConfigError::RecursiveExpansion(name) => {
+                write!(formatter, "Config variable {} expands to another variable reference", name)
+            }
+            ConfigError::MalformedReference(value) => write!(formatter, "Malformed variable reference in {:?}", value),
+        }
+    }
+}
+
+fn interpolate_value(value: &mut Value, lookup: &dyn Fn(&str) -> Option<String>) -> Result<(), ConfigError> {
+    match value {
+        Value::String(text) => *text = interpolate(text, lookup)?,
+        Value::Array(items) => {
+            for item in items {
+                interpolate_value(item, lookup)?;
+            }
+        }
+        Value::Object(fields) => {
+            for field in fields.values_mut() {
+                interpolate_value(field, lookup)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Expand `${NAME}` references in one pass. `$$` writes a literal `$` and a `$` not
+/// followed by `{` is kept as is. Expanded values are not rescanned, and a value that
+/// contains `${` is refused so references cannot chain or refer to themselves.
+fn interpolate(text: &str, lookup: &dyn Fn(&str) -> Option<String>) -> Result<String, ConfigError> {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(index) = rest.find('$') {
+        out.push_str(&rest[..index]);
+        let after = &rest[index + 1..];
+        if let Some(after) = after.strip_prefix('$') {
+            out.push('$');
+            rest = after;
+        } else if let Some(reference) = after.strip_prefix('{') {
+            let end = reference
+                .find('}')
+                .ok_or_else(|| ConfigError::MalformedReference(text.to_string()))?;
+            let name = &reference[..end];
+            if !is_variable_name(name) {
+                return Err(ConfigError::MalformedReference(text.to_string()));
+            }
+            let expanded = lookup(name).ok_or_else(|| ConfigError::UndefinedVariable(name.to_string()))?;
+            if expanded.contains("${") {
+                return Err(ConfigError::RecursiveExpansion(name.to_string()));
+            }
+            out.push_str(&expanded);
+            rest = &reference[end + 1..];
+        } else {
+            out.push('$');
+            rest = after;
+        }
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+fn is_variable_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+        && chars.all(|ch| ch.is_ascii_alphanumeric() || ch == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde_json::json;
+
+    use super::{interpolate, interpolate_value, ConfigError, CoreConfig};
+
+    fn lookup(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars = vars
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect::<HashMap<String, String>>();
+        move |name| vars.get(name).cloned()
+    }
+
+    #[test]
+    fn expands_defined_variables_in_string_values() {
+        let vars = lookup(&[("ASSET", "asset-7"), ("PIPE_HOST", "host-a")]);
+        let mut value = json!({
+            "asset_id": "${ASSET}",
+            "ipc_pipe_name": r"\\${PIPE_HOST}\pipe\tamsil",
+            "max_payload_bytes": 2048
+        });
+        interpolate_value(&mut value, &vars).expect("interpolate");
+        let config = serde_json::from_value::<CoreConfig>(value).expect("config");
+        assert_eq!(config.asset_id, "asset-7");
+        assert_eq!(config.ipc_pipe_name, r"\\host-a\pipe\tamsil");
+        assert_eq!(config.agent_id, "agent-core");
+        assert_eq!(config.max_payload_bytes, 2048);
+    }
+
+    #[test]
+    fn refuses_undefined_malformed_and_recursive_references() {
+        let vars = lookup(&[("SELF", "${SELF}"), ("HOST", "example.test")]);
+        assert_eq!(
+            interpolate("https://${MISSING}/api", &vars),
+            Err(ConfigError::UndefinedVariable("MISSING".to_string()))
+        );
+        assert_eq!(interpolate("${SELF}", &vars), Err(ConfigError::RecursiveExpansion("SELF".to_string())));
+        assert!(matches!(interpolate("https://${HOST", &vars), Err(ConfigError::MalformedReference(_))));
+        assert!(matches!(interpolate("${1HOST}", &vars), Err(ConfigError::MalformedReference(_))));
+    }
+
+    #[test]
+    fn keeps_literal_dollar_signs() {
+        let vars = lookup(&[("HOST", "example.test")]);
+        assert_eq!(interpolate("price $5 at ${HOST}", &vars), Ok("price $5 at example.test".to_string()));
+        assert_eq!(interpolate("trailing $", &vars), Ok("trailing $".to_string()));
+        assert_eq!(interpolate("escaped $${HOST}", &vars), Ok("escaped ${HOST}".to_string()));
+    }
+}
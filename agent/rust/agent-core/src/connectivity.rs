@@ -0,0 +1,319 @@
+use std::env;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::time::Duration;
+
+use serde::Serialize;
+use tokio::net::TcpStream;
+
+use crate::uplink::UplinkSummary;
+
+/// How reachable the control plane looks from the uplink worker's point of view.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Connectivity {
+    Online,
+    /// Recent cycles failed, but not enough of them to stop trying.
+    Degraded,
+    /// Delivery is suspended: batches stay in the disk buffer and the worker only probes.
+    Offline,
+}
+
+impl Connectivity {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Connectivity::Online => "online",
+            Connectivity::Degraded => "degraded",
+            Connectivity::Offline => "offline",
+        }
+    }
+
+    fn from_u8(value: u8) -> Self {
+        match value {
+            1 => Connectivity::Degraded,
+            2 => Connectivity::Offline,
+            _ => Connectivity::Online,
+        }
+    }
+
+    fn to_u8(self) -> u8 {
+        match self {
+            Connectivity::Online => 0,
+            Connectivity::Degraded => 1,
+            Connectivity::Offline => 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OfflineConfig {
+    /// Consecutive failed cycles before the uplink counts as degraded.
+    pub degraded_after_failures: u32,
+    /// Consecutive failed cycles before the uplink counts as offline.
+    pub offline_after_failures: u32,
+    /// The worker interval is multiplied by this while offline.
+    pub offline_interval_factor: u64,
+    pub max_offline_interval_secs: u64,
+    /// Probe the intake host with a TCP connect while offline instead of draining the queue.
+    pub probe_enabled: bool,
+    pub probe_timeout_ms: u64,
+}
+
+impl OfflineConfig {
+    pub fn from_env() -> Self {
+        let degraded_after_failures = env_u64("UPLINK_DEGRADED_AFTER_FAILURES").unwrap_or(1) as u32;
+        let offline_after_failures = (env_u64("UPLINK_OFFLINE_AFTER_FAILURES").unwrap_or(3) as u32).max(degraded_after_failures);
+        let probe_enabled = env::var("UPLINK_CONNECTIVITY_PROBE")
+            .map(|value| matches!(value.trim().to_ascii_lowercase().as_str(), "1" | "true" | "yes"))
+            .unwrap_or(false);
+        Self {
+            degraded_after_failures,
+            offline_after_failures,
+            offline_interval_factor: env_u64("UPLINK_OFFLINE_INTERVAL_FACTOR").unwrap_or(4),
+            max_offline_interval_secs: env_u64("UPLINK_OFFLINE_MAX_INTERVAL_SECS").unwrap_or(600),
+            probe_enabled,
+            probe_timeout_ms: env_u64("UPLINK_PROBE_TIMEOUT_MS").unwrap_or(3_000),
+        }
+    }
+}
+
+fn env_u64(name: &str) -> Option<u64> {
+    env::var(name)
+        .ok()
+        .and_then(|value| value.parse::<u64>().ok())
+        .filter(|value| *value > 0)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Transition {
+    pub from: Connectivity,
+    pub to: Connectivity,
+    pub consecutive_failures: u32,
+}
+
+/// Turns uplink cycle outcomes and probe results into a connectivity state. Each call
+/// returns a transition only when the state changes, so callers log once per change.
+#[derive(Debug)]
+pub struct OfflineDetector {
+    config: OfflineConfig,
+    state: Connectivity,
+    consecutive_failures: u32,
+}
+
+impl OfflineDetector {
+    pub fn new(config: OfflineConfig) -> Self {
+        Self {
+            config,
+            state: Connectivity::Online,
+            consecutive_failures: 0,
+        }
+    }
+
+    pub fn state(&self) -> Connectivity {
+        self.state
+    }
+
+    pub fn probe_enabled(&self) -> bool {
+        self.config.probe_enabled
+    }
+
+    pub fn probe_timeout(&self) -> Duration {
+        Duration::from_millis(self.config.probe_timeout_ms)
+    }
+
+    /// Any delivery in the cycle means the control plane is reachable; a cycle that
+    /// attempted items and delivered none is a failure. Empty cycles say nothing.
+    pub fn observe_summary(&mut self, summary: &UplinkSummary) -> Option<Transition> {
+        if summary.succeeded > 0 {
+            return self.observe(true);
+        }
+        if summary.failed > 0 || summary.deferred > 0 {
+            return self.observe(false);
+        }
+        None
+    }
+
+    /// A successful probe only proves the host answers, which is enough to try a catch-up
+    /// cycle; a failed probe extends the outage.
+    pub fn observe_probe(&mut self, reachable: bool) -> Option<Transition> {
+        self.observe(reachable)
+    }
+
+    fn observe(&mut self, reachable: bool) -> Option<Transition> {
+        if reachable {
+            self.consecutive_failures = 0;
+        } else {
+            self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+        }
+        let next = if self.consecutive_failures >= self.config.offline_after_failures {
+            Connectivity::Offline
+        } else if self.consecutive_failures >= self.config.degraded_after_failures {
+            Connectivity::Degraded
+        } else {
+            Connectivity::Online
+        };
+        if next == self.state {
+            return None;
+        }
+        let transition = Transition {
+            from: self.state,
+            to: next,
+            consecutive_failures: self.consecutive_failures,
+        };
+        self.state = next;
+        Some(transition)
+    }
+
+    /// Worker sleep for the current state: the base interval unless offline.
+    pub fn next_interval(&self, base_secs: u64) -> Duration {
+        let secs = match self.state {
+            Connectivity::Offline => base_secs
+                .saturating_mul(self.config.offline_interval_factor)
+                .min(self.config.max_offline_interval_secs.max(base_secs)),
+            Connectivity::Online | Connectivity::Degraded => base_secs,
+        };
+        Duration::from_secs(secs)
+    }
+}
+
+static CONNECTIVITY: AtomicU8 = AtomicU8::new(0);
+
+/// Agent-wide connectivity, as last published by the uplink worker.
+pub fn current() -> Connectivity {
+    Connectivity::from_u8(CONNECTIVITY.load(Ordering::Relaxed))
+}
+
+pub fn publish(state: Connectivity) {
+    CONNECTIVITY.store(state.to_u8(), Ordering::Relaxed);
+}
+
+pub fn is_offline() -> bool {
+    current() == Connectivity::Offline
+}
+
+/// `host:port` of an http(s) endpoint, for the TCP probe.
+pub fn probe_target(endpoint: &str) -> Option<String> {
+    let (scheme, rest) = endpoint.split_once("://")?;
+    let authority = rest.split(['/', '?', '#']).next()?.rsplit('@').next()?;
+    if authority.is_empty() {
+        return None;
+    }
+    let has_port = match authority.rfind(']') {
+        Some(bracket) => authority[bracket..].contains(':'),
+        None => authority.contains(':'),
+    };
+    if has_port {
+        return Some(authority.to_string());
+    }
+    let port = match scheme.to_ascii_lowercase().as_str() {
+        "http" => 80,
+        "https" => 443,
+        _ => return None,
+    };
+    Some(format!("{}:{}", authority, port))
+}
+
+/// Cheap reachability check: can a TCP connection to `target` be opened in time.
+pub async fn probe(target: &str, timeout: Duration) -> bool {
+    matches!(tokio::time::timeout(timeout, TcpStream::connect(target)).await, Ok(Ok(_)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::time::Duration;
+
+    use super::{probe, probe_target, Connectivity, OfflineConfig, OfflineDetector};
+    use crate::uplink::UplinkSummary;
+
+    fn config() -> OfflineConfig {
+        OfflineConfig {
+            degraded_after_failures: 1,
+            offline_after_failures: 3,
+            offline_interval_factor: 4,
+            max_offline_interval_secs: 100,
+            probe_enabled: false,
+            probe_timeout_ms: 500,
+        }
+    }
+
+    fn summary(succeeded: usize, failed: usize) -> UplinkSummary {
+        UplinkSummary {
+            processed: succeeded + failed,
+            succeeded,
+            failed,
+            quarantined: 0,
+            deferred: 0,
+            circuits: BTreeMap::new(),
+            tenants: BTreeMap::new(),
+            oldest_pending_age_ms: 0,
+            completed_at_unix_ms: 0,
+        }
+    }
+
+    #[test]
+    fn flapping_uplink_transitions_once_per_change_and_stretches_interval() {
+        let mut detector = OfflineDetector::new(config());
+        let cycles = [
+            summary(0, 2),
+            summary(0, 0),
+            summary(0, 1),
+            summary(0, 4),
+            summary(0, 1),
+            summary(3, 1),
+            summary(0, 1),
+            summary(2, 0),
+        ];
+        let mut transitions = Vec::new();
+        let mut intervals = Vec::new();
+        for cycle in &cycles {
+            if let Some(transition) = detector.observe_summary(cycle) {
+                transitions.push((transition.from, transition.to));
+            }
+            intervals.push(detector.next_interval(30).as_secs());
+        }
+
+        use Connectivity::{Degraded, Offline, Online};
+        assert_eq!(
+            transitions,
+            vec![
+                (Online, Degraded),
+                (Degraded, Offline),
+                (Offline, Online),
+                (Online, Degraded),
+                (Degraded, Online),
+            ]
+        );
+        // Offline stretches 30 s by 4, capped at 100 s.
+        assert_eq!(intervals, vec![30, 30, 30, 100, 100, 30, 30, 30]);
+    }
+
+    #[test]
+    fn probes_reconnect_an_offline_uplink() {
+        let mut detector = OfflineDetector::new(config());
+        for _ in 0..3 {
+            detector.observe_probe(false);
+        }
+        assert_eq!(detector.state(), Connectivity::Offline);
+        assert_eq!(detector.observe_probe(false), None);
+        let transition = detector.observe_probe(true).expect("reconnect");
+        assert_eq!((transition.from, transition.to), (Connectivity::Offline, Connectivity::Online));
+        assert_eq!(detector.next_interval(30), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn derives_probe_targets_from_endpoints() {
+        assert_eq!(probe_target("https://intake.example.com/api/v1").as_deref(), Some("intake.example.com:443"));
+        assert_eq!(probe_target("http://127.0.0.1:8081/ingest").as_deref(), Some("127.0.0.1:8081"));
+        assert_eq!(probe_target("http://[::1]/ingest").as_deref(), Some("[::1]:80"));
+        assert_eq!(probe_target("intake.example.com"), None);
+    }
+
+    #[tokio::test]
+    async fn probe_connects_to_listening_hosts_only() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind listener");
+        let address = listener.local_addr().expect("local addr").to_string();
+        assert!(probe(&address, Duration::from_millis(500)).await);
+        drop(listener);
+        assert!(!probe(&address, Duration::from_millis(500)).await);
+    }
+}
@@ -0,0 +1,175 @@
+use base64::engine::general_purpose::STANDARD as BASE64_STANDARD;
+use base64::Engine as _;
+use hmac::{Hmac, Mac};
+use ring::signature::{UnparsedPublicKey, ED25519};
+use sha2::Sha256;
+
+/// Produces the base64 signature carried in signed documents (policy bundles, commands,
+/// update manifests). Implementations name their algorithm so callers can record it.
+pub trait Signer: Send + Sync {
+    fn algorithm(&self) -> &'static str;
+    fn sign(&self, payload: &[u8]) -> String;
+}
+
+/// Checks a base64 signature over `payload`. Comparisons must be constant-time.
+pub trait Verifier: Send + Sync {
+    fn algorithm(&self) -> &'static str;
+    fn verify(&self, payload: &[u8], signature: &str) -> bool;
+}
+
+/// Shared-key HMAC-SHA256 with standard base64 output. Anything holding the key can sign,
+/// so documents published to the whole fleet use `Ed25519Verifier` instead.
+#[derive(Clone)]
+pub struct HmacSha256 {
+    key: Vec<u8>,
+}
+
+impl std::fmt::Debug for HmacSha256 {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        formatter.debug_struct("HmacSha256").finish_non_exhaustive()
+    }
+}
+
+impl HmacSha256 {
+    pub fn new(key: &[u8]) -> Self {
+        Self { key: key.to_vec() }
+    }
+
+    /// Raw MAC bytes, for callers that encode it themselves rather than as a signature.
+    pub fn mac(&self, payload: &[u8]) -> Vec<u8> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts keys of any length");
+        mac.update(payload);
+        mac.finalize().into_bytes().to_vec()
+    }
+}
+
+impl Signer for HmacSha256 {
+    fn algorithm(&self) -> &'static str {
+        "hmac-sha256"
+    }
+
+    fn sign(&self, payload: &[u8]) -> String {
+        BASE64_STANDARD.encode(self.mac(payload))
+    }
+}
+
+impl Verifier for HmacSha256 {
+    fn algorithm(&self) -> &'static str {
+        "hmac-sha256"
+    }
+
+    /// Compares the encoded form, so only the canonical base64 of the MAC is accepted.
+    fn verify(&self, payload: &[u8], signature: &str) -> bool {
+        constant_time_eq(signature.as_bytes(), self.sign(payload).as_bytes())
+    }
+}
+
+/// Ed25519 verification against a raw 32-byte public key. Agents only ever hold the public
+/// half, so a compromised agent cannot produce signatures the rest of the fleet accepts.
+#[derive(Debug, Clone)]
+pub struct Ed25519Verifier {
+    public_key: [u8; 32],
+}
+
+impl Ed25519Verifier {
+    /// Parse a standard base64 public key, as carried in config.
+    pub fn from_base64(value: &str) -> Result<Self, String> {
+        let bytes = BASE64_STANDARD
+            .decode(value.trim())
+            .map_err(|err| format!("Ed25519 public key is not base64: {}", err))?;
+        let public_key = <[u8; 32]>::try_from(bytes.as_slice())
+            .map_err(|_| format!("Ed25519 public key must be 32 bytes, got {}", bytes.len()))?;
+        Ok(Self { public_key })
+    }
+}
+
+impl Verifier for Ed25519Verifier {
+    fn algorithm(&self) -> &'static str {
+        "ed25519"
+    }
+
+    fn verify(&self, payload: &[u8], signature: &str) -> bool {
+        let Ok(signature) = BASE64_STANDARD.decode(signature) else {
+            return false;
+        };
+        UnparsedPublicKey::new(&ED25519, &self.public_key)
+            .verify(payload, &signature)
+            .is_ok()
+    }
+}
+
+/// Compare two byte strings without short-circuiting on the first difference. Length is
+/// not secret here: signatures of one algorithm always encode to the same length.
+pub fn constant_time_eq(left: &[u8], right: &[u8]) -> bool {
+    if left.len() != right.len() {
+        return false;
+    }
+    let mut diff = 0u8;
+    for (lhs, rhs) in left.iter().zip(right.iter()) {
+        diff |= lhs ^ rhs;
+    }
+    diff == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::engine::general_purpose::STANDARD as BASE64_STANDARD;
+    use base64::Engine as _;
+
+    use super::{constant_time_eq, Ed25519Verifier, HmacSha256, Signer, Verifier};
+
+    // RFC 4231 test case 2, base64-encoded.
+    const RFC4231_CASE_2: &str = "W9zBRr9gdU5qBCQmCJV1x1oAPwidJzmDnexYuWTsOEM=";
+
+    // RFC 8032 section 7.1, test 1 (empty message), hex-encoded.
+    const RFC8032_TEST_1_PUBLIC: &str = "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a";
+    const RFC8032_TEST_1_SIGNATURE: &str = "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e065224901555fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b";
+
+    fn hex_to_base64(hex: &str) -> String {
+        let bytes = (0..hex.len())
+            .step_by(2)
+            .map(|index| u8::from_str_radix(&hex[index..index + 2], 16).expect("hex"))
+            .collect::<Vec<u8>>();
+        BASE64_STANDARD.encode(bytes)
+    }
+
+    #[test]
+    fn hmac_sha256_matches_reference_vector() {
+        let backend = HmacSha256::new(b"Jefe");
+        assert_eq!(Signer::algorithm(&backend), "hmac-sha256");
+        assert_eq!(backend.sign(b"what do ya want for nothing?"), RFC4231_CASE_2);
+        assert!(backend.verify(b"what do ya want for nothing?", RFC4231_CASE_2));
+    }
+
+    #[test]
+    fn verify_rejects_tampering_through_trait_objects() {
+        let signer: Box<dyn Signer> = Box::new(HmacSha256::new(b"unit-test-key"));
+        let verifier: Box<dyn Verifier> = Box::new(HmacSha256::new(b"unit-test-key"));
+        let signature = signer.sign(b"payload");
+
+        assert!(verifier.verify(b"payload", &signature));
+        assert!(!verifier.verify(b"payload!", &signature));
+        assert!(!verifier.verify(b"payload", &signature[..signature.len() - 1]));
+        assert!(!verifier.verify(b"payload", ""));
+        assert!(!HmacSha256::new(b"other-key").verify(b"payload", &signature));
+    }
+
+    #[test]
+    fn ed25519_matches_reference_vector() {
+        let verifier = Ed25519Verifier::from_base64(&hex_to_base64(RFC8032_TEST_1_PUBLIC)).expect("public key");
+        let signature = hex_to_base64(RFC8032_TEST_1_SIGNATURE);
+        assert_eq!(verifier.algorithm(), "ed25519");
+        assert!(verifier.verify(b"", &signature));
+        assert!(!verifier.verify(b"x", &signature));
+        assert!(!verifier.verify(b"", "not base64"));
+        assert!(Ed25519Verifier::from_base64(&BASE64_STANDARD.encode([0u8; 31])).is_err());
+    }
+
+    #[test]
+    fn constant_time_eq_compares_whole_input() {
+        assert!(constant_time_eq(b"abc", b"abc"));
+        assert!(!constant_time_eq(b"abc", b"abd"));
+        assert!(!constant_time_eq(b"abc", b"ab"));
+        assert!(constant_time_eq(b"", b""));
+    }
+}
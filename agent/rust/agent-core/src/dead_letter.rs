@@ -0,0 +1,359 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::compression::read_file_bounded;
+use crate::state_dir::StatePaths;
+use crate::uplink::{ledger_path, reason_path, QUARANTINE_DIR};
+
+/// Maintenance triggers, looked for in the commands directory every uplink cycle.
+const LIST_TRIGGER: &str = "list-dead-letters.json";
+const REQUEUE_TRIGGER: &str = "requeue-dead-letters.json";
+
+/// A trigger holds one small filter object.
+const MAX_TRIGGER_BYTES: u64 = 16 * 1024;
+
+/// Dead-lettered items larger than this are listed with an `unknown` kind rather than parsed.
+const MAX_PARSED_ITEM_BYTES: u64 = 8 * 1024 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct DeadLetterConfig {
+    /// Directory the operator drops trigger files into.
+    pub commands_dir: PathBuf,
+    /// Most items a single requeue moves back to the live queue, whatever its filter asks.
+    pub max_requeue: usize,
+}
+
+impl DeadLetterConfig {
+    pub fn from_env() -> Self {
+        let max_requeue = std::env::var("RUST_UPLINK_REQUEUE_MAX_ITEMS")
+            .ok()
+            .and_then(|value| value.parse::<usize>().ok())
+            .filter(|value| *value > 0)
+            .unwrap_or(100);
+        Self {
+            commands_dir: StatePaths::from_env().root.join("commands"),
+            max_requeue,
+        }
+    }
+}
+
+/// A quarantined uplink item and why the worker gave up on it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DeadLetterItem {
+    pub file_name: String,
+    /// Queue item `kind`, or `unknown` when the file cannot be parsed.
+    pub kind: String,
+    pub size_bytes: u64,
+    pub dead_lettered_at_unix_ms: u64,
+    pub reason: String,
+}
+
+/// Which dead-lettered items a trigger applies to. Every field is optional; `{}` or an
+/// empty trigger file matches everything.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeadLetterFilter {
+    /// Item kinds to match; empty matches every kind.
+    pub kinds: Vec<String>,
+    /// Only items dead-lettered at least this long ago.
+    pub min_age_secs: Option<u64>,
+    /// Only items dead-lettered at most this long ago.
+    pub max_age_secs: Option<u64>,
+    /// Requeue at most this many; capped by `DeadLetterConfig::max_requeue`.
+    pub limit: Option<usize>,
+}
+
+impl DeadLetterFilter {
+    pub fn matches(&self, item: &DeadLetterItem, now_unix_ms: u64) -> bool {
+        let age_secs = now_unix_ms.saturating_sub(item.dead_lettered_at_unix_ms) / 1_000;
+        (self.kinds.is_empty() || self.kinds.iter().any(|kind| kind == &item.kind))
+            && self.min_age_secs.is_none_or(|min| age_secs >= min)
+            && self.max_age_secs.is_none_or(|max| age_secs <= max)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct RequeueSummary {
+    /// Items moved back to the live queue with a fresh retry ledger.
+    pub requeued: Vec<String>,
+    /// Matching items that could not be moved, such as a name already in the queue.
+    pub failed: Vec<String>,
+    /// Matching items left behind by the cap; a later trigger picks them up.
+    pub remaining: usize,
+}
+
+/// Dead-lettered items, oldest first.
+pub fn dead_letter_list(queue_dir: &Path) -> Vec<DeadLetterItem> {
+    let Ok(entries) = fs::read_dir(queue_dir.join(QUARANTINE_DIR)) else {
+        return Vec::new();
+    };
+    let mut items = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file() && path.extension().and_then(|ext| ext.to_str()) != Some("reason"))
+        .filter_map(|path| describe(&path))
+        .collect::<Vec<DeadLetterItem>>();
+    items.sort_by(|left, right| {
+        (left.dead_lettered_at_unix_ms, &left.file_name).cmp(&(right.dead_lettered_at_unix_ms, &right.file_name))
+    });
+    items
+}
+
+fn describe(path: &Path) -> Option<DeadLetterItem> {
+    let file_name = path.file_name()?.to_str()?.to_string();
+    let size_bytes = fs::metadata(path).ok()?.len();
+    let reason_file = reason_path(path);
+    // The reason file is written at quarantine time; the item keeps its original mtime.
+    let dead_lettered_at = fs::metadata(&reason_file)
+        .or_else(|_| fs::metadata(path))
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or(0);
+    Some(DeadLetterItem {
+        file_name,
+        kind: item_kind(path),
+        size_bytes,
+        dead_lettered_at_unix_ms: dead_lettered_at,
+        reason: fs::read_to_string(&reason_file).unwrap_or_default(),
+    })
+}
+
+fn item_kind(path: &Path) -> String {
+    #[derive(Deserialize)]
+    struct Kind {
+        kind: String,
+    }
+    read_file_bounded(path, MAX_PARSED_ITEM_BYTES)
+        .ok()
+        .and_then(|raw| serde_json::from_slice::<Kind>(&raw).ok())
+        .map(|item| item.kind)
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Move matching dead-lettered items back into the live queue, oldest first, at most
+/// `max_requeue` of them. Their reason files and any retry ledger are removed so they start
+/// again with no attempts recorded.
+pub fn dead_letter_requeue(
+    queue_dir: &Path,
+    filter: &DeadLetterFilter,
+    max_requeue: usize,
+    now_unix_ms: u64,
+) -> RequeueSummary {
+    let limit = filter.limit.unwrap_or(max_requeue).min(max_requeue);
+    let mut summary = RequeueSummary::default();
+    for item in dead_letter_list(queue_dir) {
+        if !filter.matches(&item, now_unix_ms) {
+            continue;
+        }
+        if summary.requeued.len() >= limit {
+            summary.remaining += 1;
+            continue;
+        }
+        let source = queue_dir.join(QUARANTINE_DIR).join(&item.file_name);
+        let target = queue_dir.join(&item.file_name);
+        if target.exists() {
+            warn!(file = %item.file_name, "dead-lettered item already in the uplink queue; not requeued");
+            summary.failed.push(item.file_name);
+            continue;
+        }
+        if let Err(err) = fs::rename(&source, &target) {
+            warn!(error = %err, file = %item.file_name, "failed to requeue dead-lettered item");
+            summary.failed.push(item.file_name);
+            continue;
+        }
+        let _ = fs::remove_file(reason_path(&source));
+        let _ = fs::remove_file(ledger_path(&target));
+        summary.requeued.push(item.file_name);
+    }
+    summary
+}
+
+/// Handle any trigger files in the commands directory: `list-dead-letters.json` writes the
+/// matching items to `list-dead-letters.result.json`, and `requeue-dead-letters.json`
+/// requeues them and writes its summary the same way. Each trigger holds a
+/// `DeadLetterFilter` and is removed once handled. Returns how many items were requeued.
+pub fn run_triggers(config: &DeadLetterConfig, queue_dir: &Path, now_unix_ms: u64) -> usize {
+    if let Some(filter) = take_trigger(config, LIST_TRIGGER) {
+        let result = filter.map(|filter| {
+            let items = dead_letter_list(queue_dir)
+                .into_iter()
+                .filter(|item| filter.matches(item, now_unix_ms))
+                .collect::<Vec<DeadLetterItem>>();
+            serde_json::json!({ "items": items })
+        });
+        write_result(config, LIST_TRIGGER, result);
+    }
+
+    let mut requeued = 0;
+    if let Some(filter) = take_trigger(config, REQUEUE_TRIGGER) {
+        let result = filter.map(|filter| {
+            let summary = dead_letter_requeue(queue_dir, &filter, config.max_requeue, now_unix_ms);
+            info!(
+                requeued = summary.requeued.len(),
+                failed = summary.failed.len(),
+                remaining = summary.remaining,
+                "requeued dead-lettered uplink items"
+            );
+            requeued = summary.requeued.len();
+            serde_json::json!(summary)
+        });
+        write_result(config, REQUEUE_TRIGGER, result);
+    }
+    requeued
+}
+
+/// Read and remove a trigger. `None` when there is none; a trigger that cannot be read or
+/// parsed is removed too, so it is reported once rather than every cycle.
+fn take_trigger(config: &DeadLetterConfig, name: &str) -> Option<Result<DeadLetterFilter, String>> {
+    let path = config.commands_dir.join(name);
+    if !path.is_file() {
+        return None;
+    }
+    let parsed = read_file_bounded(&path, MAX_TRIGGER_BYTES).and_then(|raw| {
+        if raw.iter().all(u8::is_ascii_whitespace) {
+            return Ok(DeadLetterFilter::default());
+        }
+        serde_json::from_slice::<DeadLetterFilter>(&raw).map_err(|err| format!("invalid filter: {err}"))
+    });
+    if let Err(err) = fs::remove_file(&path) {
+        warn!(error = %err, path = %path.display(), "failed to remove dead-letter trigger");
+    }
+    if let Err(err) = &parsed {
+        warn!(error = %err, path = %path.display(), "ignoring dead-letter trigger");
+    }
+    Some(parsed)
+}
+
+fn write_result(config: &DeadLetterConfig, trigger: &str, result: Result<serde_json::Value, String>) {
+    let body = result.unwrap_or_else(|err| serde_json::json!({ "error": err }));
+    let path = config.commands_dir.join(trigger.replace(".json", ".result.json"));
+    let written = serde_json::to_vec_pretty(&body)
+        .map_err(|err| err.to_string())
+        .and_then(|raw| fs::write(&path, raw).map_err(|err| err.to_string()));
+    if let Err(err) = written {
+        warn!(error = %err, path = %path.display(), "failed to write dead-letter trigger result");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::time::{Duration, SystemTime};
+
+    use super::{dead_letter_list, dead_letter_requeue, run_triggers, DeadLetterConfig, DeadLetterFilter};
+    use crate::time::unix_time_ms;
+    use crate::uplink::{ledger_path, reason_path, QUARANTINE_DIR};
+
+    fn temp_queue_dir(label: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("dead-letter-{}-{}-{}", label, std::process::id(), unix_time_ms()));
+        fs::create_dir_all(dir.join(QUARANTINE_DIR)).expect("create quarantine dir");
+        dir
+    }
+
+    /// Dead-letter an item of `kind`, backdating it by `age_secs`.
+    fn dead_letter(queue_dir: &Path, name: &str, kind: &str, age_secs: u64) {
+        let path = queue_dir.join(QUARANTINE_DIR).join(name);
+        fs::write(&path, serde_json::json!({ "kind": kind, "payload_json": "{}" }).to_string()).expect("write item");
+        let reason = reason_path(&path);
+        fs::write(&reason, "uplink endpoint did not accept delivery").expect("write reason");
+        let quarantined_at = SystemTime::now() - Duration::from_secs(age_secs);
+        fs::File::options()
+            .write(true)
+            .open(&reason)
+            .and_then(|file| file.set_modified(quarantined_at))
+            .expect("backdate reason");
+    }
+
+    #[test]
+    fn requeues_by_kind_and_age_up_to_the_cap() {
+        let queue_dir = temp_queue_dir("filter");
+        dead_letter(&queue_dir, "patch-old-1.json", "patch", 7_200);
+        dead_letter(&queue_dir, "patch-old-2.json", "patch", 7_000);
+        dead_letter(&queue_dir, "patch-new.json", "patch", 10);
+        dead_letter(&queue_dir, "telemetry-old.json", "telemetry", 7_200);
+        fs::write(ledger_path(&queue_dir.join("patch-old-1.json")), "{}").expect("stale ledger");
+        let now = unix_time_ms();
+
+        let listed = dead_letter_list(&queue_dir);
+        assert_eq!(listed.len(), 4);
+        assert_eq!(listed[3].file_name, "patch-new.json");
+        assert_eq!(listed[3].kind, "patch");
+        assert_eq!(listed[3].reason, "uplink endpoint did not accept delivery");
+
+        let old_patches = DeadLetterFilter {
+            kinds: vec!["patch".to_string()],
+            min_age_secs: Some(3_600),
+            ..DeadLetterFilter::default()
+        };
+        let capped = dead_letter_requeue(&queue_dir, &old_patches, 1, now);
+        assert_eq!(capped.requeued, vec!["patch-old-1.json".to_string()]);
+        assert_eq!(capped.remaining, 1);
+        assert!(queue_dir.join("patch-old-1.json").is_file());
+        assert!(!ledger_path(&queue_dir.join("patch-old-1.json")).exists());
+        assert!(!reason_path(&queue_dir.join(QUARANTINE_DIR).join("patch-old-1.json")).exists());
+
+        let rest = dead_letter_requeue(&queue_dir, &old_patches, 10, now);
+        assert_eq!(rest.requeued, vec!["patch-old-2.json".to_string()]);
+        assert_eq!(rest.remaining, 0);
+
+        let recent = DeadLetterFilter {
+            max_age_secs: Some(60),
+            ..DeadLetterFilter::default()
+        };
+        assert_eq!(dead_letter_requeue(&queue_dir, &recent, 10, now).requeued, vec!["patch-new.json".to_string()]);
+        let remaining = dead_letter_list(&queue_dir);
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].kind, "telemetry");
+
+        let _ = fs::remove_dir_all(queue_dir);
+    }
+
+    #[test]
+    fn trigger_files_list_and_requeue_once() {
+        let queue_dir = temp_queue_dir("trigger");
+        for index in 0..3 {
+            dead_letter(&queue_dir, &format!("patch-{}.json", index), "patch", 600);
+        }
+        let config = DeadLetterConfig {
+            commands_dir: queue_dir.join("commands"),
+            max_requeue: 2,
+        };
+        fs::create_dir_all(&config.commands_dir).expect("create commands dir");
+        assert_eq!(run_triggers(&config, &queue_dir, unix_time_ms()), 0);
+
+        fs::write(config.commands_dir.join("list-dead-letters.json"), "").expect("list trigger");
+        fs::write(config.commands_dir.join("requeue-dead-letters.json"), r#"{"kinds":["patch"],"limit":5}"#)
+            .expect("requeue trigger");
+        assert_eq!(run_triggers(&config, &queue_dir, unix_time_ms()), 2);
+        assert!(!config.commands_dir.join("requeue-dead-letters.json").exists());
+        assert!(!config.commands_dir.join("list-dead-letters.json").exists());
+
+        let listed: serde_json::Value = serde_json::from_slice(
+            &fs::read(config.commands_dir.join("list-dead-letters.result.json")).expect("list result"),
+        )
+        .expect("list json");
+        assert_eq!(listed["items"].as_array().map(Vec::len), Some(3));
+        let requeued: serde_json::Value = serde_json::from_slice(
+            &fs::read(config.commands_dir.join("requeue-dead-letters.result.json")).expect("requeue result"),
+        )
+        .expect("requeue json");
+        assert_eq!(requeued["requeued"].as_array().map(Vec::len), Some(2));
+        assert_eq!(requeued["remaining"], 1);
+        assert_eq!(run_triggers(&config, &queue_dir, unix_time_ms()), 0);
+
+        fs::write(config.commands_dir.join("requeue-dead-letters.json"), r#"{"kind":"patch"}"#).expect("bad trigger");
+        assert_eq!(run_triggers(&config, &queue_dir, unix_time_ms()), 0);
+        let rejected = fs::read_to_string(config.commands_dir.join("requeue-dead-letters.result.json")).expect("result");
+        assert!(rejected.contains("invalid filter"));
+        assert_eq!(dead_letter_list(&queue_dir).len(), 1);
+
+        let _ = fs::remove_dir_all(queue_dir);
+    }
+}
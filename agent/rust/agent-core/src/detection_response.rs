@@ -0,0 +1,506 @@
+use std::collections::HashMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::Serialize;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use crate::edr::{
+    detections_to_telemetry, evaluate_rules, DetectionDedup, DetectionSummary, DetectionTracker, EdrConfig, EdrRule,
+};
+use crate::evidence::{
+    package_evidence_with_config, EvidenceConfig, EvidenceItem, EvidenceOutcome, EvidenceRecord, EvidenceStatus,
+};
+use crate::metrics::{AgentMetrics, MetricsHandle};
+use crate::policy::{EvidenceProfile, PolicyBundle};
+use crate::siem::{prepare_telemetry_batch_from_events, TelemetryConfig};
+use crate::state_dir::StatePaths;
+use crate::telemetry_buffer::buffer_batch;
+use crate::time::{format_rfc3339_ms, monotonic_ms, unix_time_ms};
+use crate::uplink::{enqueue_evidence_content_item, enqueue_evidence_item, enqueue_rmm_item, EvidenceUpload};
+
+const DETECTIONS_PATH: &str = "/detections";
+const DETECTION_EVIDENCE_TYPE: &str = "detection_response";
+
+/// Detection and the evidence collected in response, delivered to `/detections` so the
+/// control plane can join the two.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DetectionEvidenceLink {
+    pub detection_id: String,
+    pub rule_id: String,
+    pub severity: u8,
+    pub title: String,
+    pub tenant_id: String,
+    pub asset_id: String,
+    pub profile: String,
+    pub evidence_id: String,
+    pub evidence_sha256: String,
+    pub evidence_status: &'static str,
+    pub items_collected: usize,
+    pub total_bytes: u64,
+    pub collected_at_unix_ms: u64,
+}
+
+/// Runs the policy's `evidence_profiles` for qualifying detections. Each rule collects at
+/// most once per its profile's cool-down, so a noisy rule cannot trigger a collection storm.
+#[derive(Debug)]
+pub struct DetectionResponder {
+    asset_id: String,
+    tenant_id: String,
+    queue_dir: PathBuf,
+    /// Telemetry buffer detection batches are submitted through.
+    buffer_dir: PathBuf,
+    /// Roots and path limits collections are held to; profiles supply paths and byte limits.
+    base: EvidenceConfig,
+    /// Where collected items are copied for upload; `None` queues only their records.
+    content_stage: Option<PathBuf>,
+    last_collected_unix_ms: HashMap<String, u64>,
+}
+
+impl DetectionResponder {
+    pub fn new(asset_id: &str, tenant_id: &str, queue_dir: &Path, buffer_dir: &Path, base: EvidenceConfig) -> Self {
+        Self {
+            asset_id: asset_id.to_string(),
+            tenant_id: tenant_id.to_string(),
+            queue_dir: queue_dir.to_path_buf(),
+            buffer_dir: buffer_dir.to_path_buf(),
+            base,
+            content_stage: None,
+            last_collected_unix_ms: HashMap::new(),
+        }
+    }
+
+    /// Upload the content of collected items too, from copies staged in `dir`. It must sit
+    /// beneath the uplink file staging root.
+    pub fn with_content_stage(mut self, dir: &Path) -> Self {
+        self.content_stage = Some(dir.to_path_buf());
+        self
+    }
+
+    pub fn from_env(asset_id: &str, queue_dir: &Path, paths: &StatePaths) -> Self {
+        let tenant_id = env::var("AGENT_TENANT_ID").unwrap_or_default();
+        Self::new(asset_id, &tenant_id, queue_dir, &paths.telemetry_buffer(), EvidenceConfig::from_env())
+            .with_content_stage(&paths.evidence_stage.join("detection"))
+    }
+
+    /// Profile for `detection`: one naming its rule wins over a catch-all profile, and
+    /// either must be met by the detection's severity.
+    pub fn select<'a>(detection: &DetectionSummary, policy: &'a PolicyBundle) -> Option<(&'a str, &'a EvidenceProfile)> {
+        let named = policy
+            .evidence_profiles
+            .iter()
+            .find(|(_, profile)| profile.rule_ids.contains(&detection.rule_id));
+        let (name, profile) = named.or_else(|| {
+            policy
+                .evidence_profiles
+                .iter()
+                .find(|(_, profile)| profile.rule_ids.is_empty() && detection.severity >= profile.min_severity)
+        })?;
+        (detection.severity >= profile.min_severity).then_some((name.as_str(), profile))
+    }
+
+    fn cooling_down(&self, rule_id: &str, cooldown_secs: u64, now_unix_ms: u64) -> bool {
+        self.last_collected_unix_ms
+            .get(rule_id)
+            .map(|last| now_unix_ms.saturating_sub(*last) < cooldown_secs.saturating_mul(1_000))
+            .unwrap_or(false)
+    }
+
+    /// Collect evidence for `detection` when a profile applies and the rule is not cooling
+    /// down, then queue the collected items and the detection link for uplink.
+    pub async fn respond(
+        &mut self,
+        detection: &DetectionSummary,
+        policy: &PolicyBundle,
+        now_unix_ms: u64,
+    ) -> Option<DetectionEvidenceLink> {
+        let (name, profile) = Self::select(detection, policy)?;
+        if self.cooling_down(&detection.rule_id, profile.cooldown_secs, now_unix_ms) {
+            info!(rule_id = %detection.rule_id, profile = name, "evidence collection cooling down");
+            return None;
+        }
+
+        let record = package_evidence_with_config(&self.collection_config(detection, profile));
+        if matches!(record.status, EvidenceStatus::Busy) {
+            // Leave the rule out of cooldown so the next detection can collect.
+            info!(rule_id = %detection.rule_id, profile = name, "evidence collection busy; skipping");
+            return None;
+        }
+        self.last_collected_unix_ms.insert(detection.rule_id.clone(), now_unix_ms);
+        let link = self.link(detection, name, &record);
+
+        for (upload, item) in self.uploads(detection, &record) {
+            if let Err(err) = enqueue_evidence_item(&self.queue_dir, &upload) {
+                warn!(error = %err, evidence_id = %upload.evidence_id, "failed to queue detection evidence");
+                continue;
+            }
+            if let Some(stage) = &self.content_stage {
+                if let Err(err) = stage_content(&self.queue_dir, stage, &upload, item) {
+                    warn!(error = %err, evidence_id = %upload.evidence_id, "failed to queue detection evidence content");
+                }
+            }
+        }
+        match serde_json::to_string(&link) {
+            Ok(payload_json) => {
+                let item_name = format!("detection-{}-{}", sanitize(&link.detection_id), now_unix_ms);
+                if let Err(err) = enqueue_rmm_item(&self.queue_dir, DETECTIONS_PATH, &payload_json, &item_name).await {
+                    warn!(error = %err, detection_id = %link.detection_id, "failed to queue detection link");
+                }
+            }
+            Err(err) => warn!(error = %err, detection_id = %link.detection_id, "failed to encode detection link"),
+        }
+        info!(
+            detection_id = %link.detection_id,
+            evidence_id = %link.evidence_id,
+            profile = name,
+            items = link.items_collected,
+            "collected evidence for detection"
+        );
+        Some(link)
+    }
+
+    /// The base roots and limits with the profile's paths, plus the detection's own target
+    /// when the profile asks for it. Targets outside the evidence roots are rejected like
+    /// any other path.
+    fn collection_config(&self, detection: &DetectionSummary, profile: &EvidenceProfile) -> EvidenceConfig {
+        let mut config = self.base.clone();
+        config.evidence_paths = profile.paths.iter().map(PathBuf::from).collect();
+        let target = detection.target_path.trim();
+        if profile.include_target && !target.is_empty() {
+            config.evidence_paths.push(PathBuf::from(target));
+        }
+        config.max_item_bytes = profile.max_item_bytes;
+        config.max_total_bytes = profile.max_total_bytes;
+        config
+    }
+
+    pub async fn respond_all(&mut self, detections: &[DetectionSummary], policy: &PolicyBundle) -> Vec<DetectionEvidenceLink> {
+        if policy.evidence_profiles.is_empty() {
+            return Vec::new();
+        }
+        let mut links = Vec::new();
+        for detection in detections {
+            if let Some(link) = self.respond(detection, policy, unix_time_ms()).await {
+                links.push(link);
+            }
+        }
+        links
+    }
+
+    fn link(&self, detection: &DetectionSummary, profile: &str, record: &EvidenceRecord) -> DetectionEvidenceLink {
+        DetectionEvidenceLink {
+            detection_id: detection.detection_id.clone(),
+            rule_id: detection.rule_id.clone(),
+            severity: detection.severity,
+            title: detection.title.clone(),
+            tenant_id: self.tenant_id.clone(),
+            asset_id: self.asset_id.clone(),
+            profile: profile.to_string(),
+            evidence_id: record.evidence_id.clone(),
+            evidence_sha256: record.sha256.clone(),
+            evidence_status: match record.status {
+                EvidenceStatus::Collected => "collected",
+                EvidenceStatus::Partial => "partial",
+                EvidenceStatus::Empty => "empty",
+                EvidenceStatus::Busy => "busy",
+            },
+            items_collected: record
+                .items
+                .iter()
+                .filter(|item| matches!(item.outcome, EvidenceOutcome::Collected))
+                .count(),
+            total_bytes: record.total_bytes,
+            collected_at_unix_ms: record.collected_at_unix_ms,
+        }
+    }
+
+    /// One upload per collected item, each related to the detection that triggered it.
+    fn uploads<'a>(&self, detection: &DetectionSummary, record: &'a EvidenceRecord) -> Vec<(EvidenceUpload, &'a EvidenceItem)> {
+        record
+            .items
+            .iter()
+            .filter(|item| matches!(item.outcome, EvidenceOutcome::Collected))
+            .map(|item| {
+                let upload = EvidenceUpload {
+                    evidence_id: format!("{}-{}", record.evidence_id, item.item_id),
+                    tenant_id: self.tenant_id.clone(),
+                    asset_id: self.asset_id.clone(),
+                    source: format!("edr:{}", detection.rule_id),
+                    evidence_type: DETECTION_EVIDENCE_TYPE.to_string(),
+                    related_id: detection.detection_id.clone(),
+                    hash: item.sha256.clone(),
+                    storage_uri: format!("file://{}", item.path),
+                    captured_at: format_rfc3339_ms(item.collected_at_unix_ms),
+                };
+                (upload, item)
+            })
+            .collect()
+    }
+}
+
+/// One EDR pass after another: rules evaluated, repeats suppressed, recurring patterns
+/// escalated, evidence collected and detections queued as telemetry. The tracker, dedup
+/// and responder persist between passes so escalation windows, dedup TTLs and cool-downs
+/// apply to later events, not just the first pass.
+#[derive(Debug)]
+pub struct EdrCycle {
+    config: EdrConfig,
+    rules: Vec<EdrRule>,
+    tracker: DetectionTracker,
+    dedup: DetectionDedup,
+    responder: DetectionResponder,
+}
+
+impl EdrCycle {
+    pub fn new(config: EdrConfig, rules: Vec<EdrRule>, tracker: DetectionTracker, responder: DetectionResponder) -> Self {
+        let dedup = DetectionDedup::from_config(&config);
+        Self {
+            config,
+            rules,
+            tracker,
+            dedup,
+            responder,
+        }
+    }
+
+    /// Run one pass and return the detections it reported.
+    pub async fn run_once(&mut self, policy: &PolicyBundle, metrics: &AgentMetrics) -> Vec<DetectionSummary> {
+        let fresh = self.dedup.filter(evaluate_rules(&self.rules, &self.config), monotonic_ms());
+        let detections = self.tracker.observe(fresh, unix_time_ms());
+        metrics.record_detections(&detections);
+        self.responder.respond_all(&detections, policy).await;
+
+        let events = detections_to_telemetry(&detections, &self.config, unix_time_ms());
+        if !events.is_empty() {
+            let batch = prepare_telemetry_batch_from_events(&events, &TelemetryConfig::from_env());
+            metrics.record_telemetry_batch(&batch);
+            buffer_batch(&batch, &self.responder.buffer_dir, &self.responder.queue_dir);
+        }
+        detections
+    }
+}
+
+/// Run EDR passes every `cycle_interval_secs` until shutdown.
+pub async fn run_edr_loop(mut cycle: EdrCycle, policy: PolicyBundle, metrics: MetricsHandle, token: CancellationToken) {
+    let interval = Duration::from_secs(cycle.config.cycle_interval_secs);
+    loop {
+        let detections = cycle.run_once(&policy, &metrics).await;
+        if !detections.is_empty() {
+            info!(detections = detections.len(), "edr cycle reported detections");
+        }
+        tokio::select! {
+            _ = token.cancelled() => break,
+            _ = tokio::time::sleep(interval) => {}
+        }
+    }
+}
+
+/// Copy a collected item into `stage` and queue its content for upload. The worker streams
+/// the copy and deletes it once delivered, so the original may change or go away meanwhile.
+fn stage_content(queue_dir: &Path, stage: &Path, upload: &EvidenceUpload, item: &EvidenceItem) -> Result<(), String> {
+    std::fs::create_dir_all(stage).map_err(|err| format!("failed to create {}: {err}", stage.display()))?;
+    let copy = stage.join(sanitize(&upload.evidence_id));
+    let partial = copy.with_extension("partial");
+    std::fs::copy(&item.path, &partial).map_err(|err| format!("failed to copy {}: {err}", item.path))?;
+    std::fs::rename(&partial, &copy).map_err(|err| format!("failed to stage {}: {err}", copy.display()))?;
+    enqueue_evidence_content_item(queue_dir, &upload.hash, &copy, true)
+}
+
+fn sanitize(value: &str) -> String {
+    value
+        .chars()
+        .map(|ch| if ch.is_ascii_alphanumeric() || ch == '-' || ch == '_' { ch } else { '_' })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    use super::{DetectionResponder, EdrCycle};
+    use crate::edr::{load_rules, DetectionSummary, DetectionTracker, EdrConfig};
+    use crate::evidence::{EvidenceConfig, EvidenceRoot};
+    use crate::metrics::AgentMetrics;
+    use crate::path_guard::PathLimits;
+    use crate::policy::{EvidenceProfile, PolicyBundle};
+    use crate::time::unix_time_ms;
+
+    fn temp_dir(label: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("detection-response-{}-{}-{}", label, std::process::id(), unix_time_ms()));
+        fs::create_dir_all(dir.join("root")).expect("create root");
+        dir
+    }
+
+    fn responder(dir: &Path) -> DetectionResponder {
+        let base = EvidenceConfig {
+            roots: vec![EvidenceRoot {
+                root_dir: dir.join("root"),
+                allowed_extensions: vec!["log".to_string()],
+                max_total_bytes: 1024 * 1024,
+            }],
+            max_item_bytes: 1,
+            max_total_bytes: 1,
+            max_items: 16,
+            evidence_paths: Vec::new(),
+            max_duration_ms: None,
+            path_limits: PathLimits::default(),
+        };
+        DetectionResponder::new("asset-1", "tenant-1", &dir.join("queue"), &dir.join("buffer"), base).with_content_stage(&dir.join("stage"))
+    }
+
+    fn policy() -> PolicyBundle {
+        let mut policy = PolicyBundle::placeholder();
+        policy.evidence_profiles = BTreeMap::from([(
+            "high-severity".to_string(),
+            EvidenceProfile {
+                rule_ids: Vec::new(),
+                min_severity: 8,
+                paths: vec!["auth.log".to_string()],
+                max_item_bytes: 4096,
+                max_total_bytes: 8192,
+                cooldown_secs: 60,
+                include_target: false,
+            },
+        )]);
+        policy
+    }
+
+    fn detection(detection_id: &str, rule_id: &str, severity: u8) -> DetectionSummary {
+        DetectionSummary {
+            detection_id: detection_id.to_string(),
+            severity,
+            rule_id: rule_id.to_string(),
+            title: "Suspicious logon".to_string(),
+            description: String::new(),
+            event_id: "evt-1".to_string(),
+            confidence: 90,
+            pattern: rule_id.to_string(),
+            occurrences: 1,
+            technique: "T1003".to_string(),
+            category: "edr.detection.process".to_string(),
+            target_path: String::new(),
+        }
+    }
+
+    fn queued_items(dir: &Path) -> Vec<serde_json::Value> {
+        fs::read_dir(dir.join("queue"))
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .filter_map(|entry| fs::read(entry.path()).ok())
+                    .filter_map(|raw| serde_json::from_slice(&raw).ok())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    #[tokio::test]
+    async fn collects_and_links_evidence_for_high_severity_detections() {
+        let dir = temp_dir("link");
+        fs::write(dir.join("root").join("auth.log"), "failed logon").expect("write evidence");
+        let mut responder = responder(&dir);
+        let policy = policy();
+
+        assert!(responder.respond(&detection("det-low", "rule-a", 5), &policy, 1_000).await.is_none());
+        assert!(queued_items(&dir).is_empty());
+
+        let link = responder
+            .respond(&detection("det-high", "rule-a", 9), &policy, 2_000)
+            .await
+            .expect("collection triggered");
+        assert_eq!(link.detection_id, "det-high");
+        assert_eq!(link.profile, "high-severity");
+        assert_eq!(link.evidence_status, "collected");
+        assert_eq!(link.items_collected, 1);
+        assert!(link.evidence_id.starts_with("evd-"));
+
+        let items = queued_items(&dir);
+        let evidence = items.iter().find(|item| item["kind"] == "evidence").expect("evidence item");
+        assert_eq!(evidence["related_id"], "det-high");
+        assert_eq!(evidence["source"], "edr:rule-a");
+        assert!(evidence["evidence_id"].as_str().unwrap_or_default().starts_with(&link.evidence_id));
+        let queued_link = items.iter().find(|item| item["kind"] == "rmm").expect("detection link item");
+        assert_eq!(queued_link["path"], "/detections");
+        let payload: serde_json::Value =
+            serde_json::from_str(queued_link["payload_json"].as_str().expect("payload")).expect("link payload");
+        assert_eq!(payload["detection_id"], "det-high");
+        assert_eq!(payload["evidence_id"], link.evidence_id.as_str());
+        let content = items.iter().find(|item| item["kind"] == "rmm_file").expect("content item");
+        assert_eq!(content["path"], format!("/evidence/content/{}", evidence["hash"].as_str().unwrap_or_default()));
+        assert_eq!(content["remove_after_delivery"], true);
+        let staged = fs::read_to_string(content["body_file"].as_str().expect("body file")).expect("staged copy");
+        assert_eq!(staged, "failed logon");
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn cools_down_per_rule() {
+        let dir = temp_dir("cooldown");
+        fs::write(dir.join("root").join("auth.log"), "failed logon").expect("write evidence");
+        let mut responder = responder(&dir);
+        let policy = policy();
+
+        assert!(responder.respond(&detection("det-1", "rule-a", 9), &policy, 10_000).await.is_some());
+        assert!(responder.respond(&detection("det-2", "rule-a", 10), &policy, 40_000).await.is_none());
+        assert!(responder.respond(&detection("det-3", "rule-b", 9), &policy, 40_000).await.is_some());
+        assert!(responder.respond(&detection("det-4", "rule-a", 9), &policy, 70_000).await.is_some());
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn collects_the_detection_target_within_the_evidence_roots() {
+        let dir = temp_dir("target");
+        let target = dir.join("root").join("dropper.log");
+        fs::write(&target, "payload").expect("write target");
+        fs::write(dir.join("outside.log"), "secret").expect("write outside");
+        let mut responder = responder(&dir);
+        let mut policy = PolicyBundle::placeholder();
+        policy.evidence_profiles = BTreeMap::from([(
+            "dropper".to_string(),
+            EvidenceProfile {
+                rule_ids: vec!["rule-drop".to_string()],
+                min_severity: 8,
+                paths: Vec::new(),
+                max_item_bytes: 4096,
+                max_total_bytes: 8192,
+                cooldown_secs: 0,
+                include_target: true,
+            },
+        )]);
+
+        let mut triggering = detection("det-drop", "rule-drop", 9);
+        triggering.target_path = target.display().to_string();
+        let link = responder.respond(&triggering, &policy, 1_000).await.expect("collection triggered");
+        assert_eq!(link.items_collected, 1);
+        let items = queued_items(&dir);
+        let evidence = items.iter().find(|item| item["kind"] == "evidence").expect("evidence item");
+        assert_eq!(evidence["related_id"], "det-drop");
+        assert!(evidence["storage_uri"].as_str().unwrap_or_default().ends_with("dropper.log"));
+
+        let mut outside = detection("det-out", "rule-drop", 9);
+        outside.target_path = dir.join("outside.log").display().to_string();
+        let link = responder.respond(&outside, &policy, 2_000).await.expect("collection attempted");
+        assert_eq!(link.items_collected, 0);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn later_cycles_suppress_already_reported_detections() {
+        let dir = temp_dir("cycle");
+        let mut config = EdrConfig::from_env();
+        config.telemetry_categories = Vec::new();
+        let rules = load_rules(&config).expect("built-in rules");
+        let mut cycle = EdrCycle::new(config, rules, DetectionTracker::new(60_000, 3), responder(&dir));
+        let metrics = AgentMetrics::new_handle();
+        let policy = PolicyBundle::placeholder();
+
+        let first = cycle.run_once(&policy, &metrics).await;
+        assert!(!first.is_empty());
+        assert!(cycle.run_once(&policy, &metrics).await.is_empty());
+        let _ = fs::remove_dir_all(dir);
+    }
+}
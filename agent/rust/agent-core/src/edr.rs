@@ -1,5 +1,18 @@
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use serde::Deserialize;
+use tracing::warn;
+
+use crate::siem::{TelemetryEvent, TelemetryField, TelemetrySeverity};
+
+pub const PROCESS_DETECTION_CATEGORY: &str = "edr.detection.process";
+pub const FILE_DETECTION_CATEGORY: &str = "edr.detection.file";
+pub const NETWORK_DETECTION_CATEGORY: &str = "edr.detection.network";
+/// Stream detection telemetry is sent on.
+const DETECTION_STREAM: &str = "sensor";
 
 /// Summary of a detection surfaced by the EDR rules engine.
 #[derive(Debug, Clone)]
@@ -11,6 +24,17 @@ pub struct DetectionSummary {
     pub description: String,
     pub event_id: String,
     pub confidence: u8,
+    /// Rule id plus normalised target, shared by repeats of the same activity.
+    pub pattern: String,
+    /// Occurrences of `pattern` within the tracker window, including this one.
+    pub occurrences: u32,
+    /// MITRE ATT&CK technique id of the rule.
+    pub technique: String,
+    /// Telemetry category, `edr.detection.<process|file|network>`.
+    pub category: String,
+    /// Process image or file the event acted on, as the sensor reported it; empty for
+    /// network events.
+    pub target_path: String,
 }
 
 /// Runtime configuration for EDR evaluation, sourced from environment variables.
@@ -18,7 +42,21 @@ pub struct DetectionSummary {
 pub struct EdrConfig {
     pub max_detections_per_cycle: usize,
     pub suspicious_ports: Vec<u16>,
+    /// How event and configured paths are compared; see [`PathStyle`].
+    pub path_style: PathStyle,
+    /// Directories where an unsigned process start is a detection, normalised for `path_style`.
+    pub unsigned_exec_dirs: Vec<String>,
+    /// Directories where a file write is a detection, normalised for `path_style`.
     pub sensitive_paths: Vec<String>,
+    /// Optional JSON rule overrides, see [`load_rules`].
+    pub rules_path: Option<PathBuf>,
+    /// Detection ids remembered across cycles by [`DetectionDedup`].
+    pub dedup_capacity: usize,
+    pub dedup_ttl_ms: u64,
+    /// Detection categories forwarded to the SIEM as telemetry; others stay local.
+    pub telemetry_categories: Vec<String>,
+    /// Pause between EDR cycles, see [`crate::detection_response::run_edr_loop`].
+    pub cycle_interval_secs: u64,
 }
 
 impl EdrConfig {
@@ -36,7 +74,34 @@ impl EdrConfig {
                     .collect::<Vec<u16>>()
             })
             .unwrap_or_else(|| vec![4444, 1337, 3389, 5985, 5986]);
-        let sensitive_paths = env::var("EDR_SENSITIVE_PATHS")
+        let path_style = PathStyle::from_env();
+        let unsigned_exec_dirs = path_list_from_env("EDR_UNSIGNED_EXEC_DIRS", path_style)
+            .unwrap_or_else(|| path_style.normalise_all(path_style.default_unsigned_exec_dirs()));
+        let sensitive_paths = path_list_from_env("EDR_SENSITIVE_PATHS", path_style)
+            .unwrap_or_else(|| path_style.normalise_all(path_style.default_sensitive_paths()));
+
+        let rules_path = env::var("EDR_RULES_PATH")
+            .ok()
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+            .map(PathBuf::from);
+        let dedup_capacity = env::var("EDR_DEDUP_CAPACITY")
+            .ok()
+            .and_then(|value| value.parse::<usize>().ok())
+            .filter(|value| *value > 0)
+            .unwrap_or(4096);
+        let dedup_ttl_ms = env::var("EDR_DEDUP_TTL_SECS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .unwrap_or(3600)
+            .saturating_mul(1000);
+        let cycle_interval_secs = env::var("EDR_CYCLE_INTERVAL_SECS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .filter(|value| *value > 0)
+            .unwrap_or(60);
+
+        let telemetry_categories = env::var("EDR_TELEMETRY_CATEGORIES")
             .ok()
             .map(|value| {
                 value
@@ -46,23 +111,164 @@ impl EdrConfig {
                     .collect::<Vec<String>>()
             })
             .unwrap_or_else(|| {
-                vec![
-                    "c:/windows/system32".to_string(),
-                    "c:/windows/temp".to_string(),
-                    "/etc".to_string(),
-                    "/usr/bin".to_string(),
-                    "/tmp".to_string(),
-                ]
+                [PROCESS_DETECTION_CATEGORY, FILE_DETECTION_CATEGORY, NETWORK_DETECTION_CATEGORY]
+                    .iter()
+                    .map(|category| category.to_string())
+                    .collect()
             });
 
         Self {
             max_detections_per_cycle,
             suspicious_ports,
+            path_style,
+            unsigned_exec_dirs,
             sensitive_paths,
+            rules_path,
+            dedup_capacity,
+            dedup_ttl_ms,
+            telemetry_categories,
+            cycle_interval_secs,
+        }
+    }
+}
+
+/// Path conventions the EDR matchers compare under. `EDR_PATH_STYLE` (`windows` or `unix`,
+/// in any case) defaults to the host OS.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathStyle {
+    /// Backslashes are separators and case is ignored: `C:\Users` matches `c:/users`. The
+    /// `\\?\` and `\\.\` prefixes are dropped, and `\Device\HarddiskVolumeN\` paths are
+    /// compared without a drive letter.
+    Windows,
+    /// Paths are compared as written.
+    Unix,
+}
+
+impl PathStyle {
+    pub fn from_env() -> Self {
+        let host = if cfg!(windows) { Self::Windows } else { Self::Unix };
+        let Some(value) = env::var("EDR_PATH_STYLE").ok().filter(|value| !value.trim().is_empty()) else {
+            return host;
+        };
+        match value.trim().to_ascii_lowercase().as_str() {
+            "windows" => Self::Windows,
+            "unix" => Self::Unix,
+            _ => {
+                warn!(value = %value, default = ?host, "unknown EDR_PATH_STYLE; using the host path style");
+                host
+            }
+        }
+    }
+
+    fn default_unsigned_exec_dirs(self) -> &'static [&'static str] {
+        match self {
+            Self::Windows => &["c:/windows/temp", "c:/users"],
+            Self::Unix => &["/tmp", "/var/tmp", "/dev/shm"],
+        }
+    }
+
+    fn default_sensitive_paths(self) -> &'static [&'static str] {
+        match self {
+            Self::Windows => &["c:/windows/system32", "c:/windows/temp"],
+            Self::Unix => &["/etc", "/usr/bin", "/tmp"],
+        }
+    }
+
+    /// Trimmed, `/`-separated, with repeated separators, `.` and `..` collapsed and no
+    /// trailing separator; lowercased and without NT prefixes for Windows.
+    pub fn normalise(self, value: &str) -> String {
+        let trimmed = value.trim();
+        let path = match self {
+            Self::Windows => strip_nt_prefix(&trimmed.replace('\\', "/").to_lowercase()),
+            Self::Unix => trimmed.to_string(),
+        };
+        let (root, rest) = match path.strip_prefix("//") {
+            // UNC share: `\\server\share`.
+            Some(rest) if self == Self::Windows => ("//", rest),
+            _ if path.starts_with('/') => ("/", path.as_str()),
+            _ => ("", path.as_str()),
+        };
+
+        let mut segments: Vec<&str> = Vec::new();
+        for segment in rest.split('/') {
+            match segment {
+                "" | "." => {}
+                ".." => {
+                    let at_drive = self == Self::Windows && segments.len() == 1 && is_drive(segments[0]);
+                    if !at_drive {
+                        segments.pop();
+                    }
+                }
+                segment => segments.push(segment),
+            }
+        }
+        format!("{}{}", root, segments.join("/"))
+    }
+
+    fn normalise_all(self, values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| self.normalise(value)).collect()
+    }
+
+    /// Whether `path` is `dir` or lies beneath it, comparing whole path segments.
+    pub fn is_within(self, path: &str, dir: &str) -> bool {
+        let path = self.normalise(path);
+        let mut dir = self.normalise(dir);
+        if dir.is_empty() {
+            return false;
+        }
+        if self == Self::Windows && path.starts_with('/') && !path.starts_with("//") {
+            // A volume device path names no drive letter, so compare with the drive stripped.
+            if dir.get(..2).is_some_and(is_drive) {
+                dir = match &dir[2..] {
+                    "" => "/".to_string(),
+                    rest => rest.to_string(),
+                };
+            }
         }
+        path == dir
+            || (dir == "/" && path.starts_with('/'))
+            || path.strip_prefix(&dir).is_some_and(|rest| rest.starts_with('/'))
     }
 }
 
+/// `path` (lowercased, `/`-separated) without its `\\?\`, `\\.\` or `\??\` prefix. A
+/// `\Device\HarddiskVolumeN` prefix leaves a drive-less absolute path.
+fn strip_nt_prefix(path: &str) -> String {
+    if let Some(share) = path.strip_prefix("//?/unc/") {
+        return format!("//{}", share);
+    }
+    for prefix in ["//?/", "//./", "/??/"] {
+        if let Some(rest) = path.strip_prefix(prefix) {
+            return rest.to_string();
+        }
+    }
+    if let Some(rest) = path.strip_prefix("/device/harddiskvolume") {
+        let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+        if digits > 0 && (rest.len() == digits || rest[digits..].starts_with('/')) {
+            return match &rest[digits..] {
+                "" => "/".to_string(),
+                tail => tail.to_string(),
+            };
+        }
+    }
+    path.to_string()
+}
+
+fn is_drive(segment: &str) -> bool {
+    let bytes = segment.as_bytes();
+    bytes.len() == 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':'
+}
+
+fn path_list_from_env(name: &str, style: PathStyle) -> Option<Vec<String>> {
+    env::var(name).ok().map(|value| {
+        value
+            .split(',')
+            .map(|entry| style.normalise(entry))
+            .filter(|entry| !entry.is_empty())
+            .collect::<Vec<String>>()
+    })
+}
+
 /// Normalised events emitted by platform sensors for rule evaluation.
 #[derive(Debug, Clone)]
 pub enum EdrEventKind {
@@ -94,66 +300,69 @@ pub struct EdrEvent {
 }
 
 #[derive(Debug, Clone)]
-struct EdrRule {
+pub struct EdrRule {
     id: String,
     title: String,
     description: String,
     severity: u8,
+    technique: String,
     matcher: RuleMatcher,
 }
 
 #[derive(Debug, Clone)]
 enum RuleMatcher {
     ProcessCommandContains(Vec<&'static str>),
-    UnsignedExecutionFromDirs(Vec<&'static str>),
+    UnsignedExecutionFromDirs,
     NetworkPortIn(Vec<u16>),
     FileWriteToSensitiveDirs,
 }
 
 impl RuleMatcher {
+    fn category(&self) -> &'static str {
+        match self {
+            RuleMatcher::ProcessCommandContains(_) | RuleMatcher::UnsignedExecutionFromDirs => PROCESS_DETECTION_CATEGORY,
+            RuleMatcher::NetworkPortIn(_) => NETWORK_DETECTION_CATEGORY,
+            RuleMatcher::FileWriteToSensitiveDirs => FILE_DETECTION_CATEGORY,
+        }
+    }
+
     fn matches(&self, event: &EdrEvent, config: &EdrConfig) -> bool {
         match (self, &event.kind) {
             (RuleMatcher::ProcessCommandContains(tokens), EdrEventKind::ProcessStart { command_line, .. }) => {
                 let normalised = normalise_text(command_line);
                 tokens.iter().any(|token| normalised.contains(token))
             }
-            (RuleMatcher::UnsignedExecutionFromDirs(dirs), EdrEventKind::ProcessStart { image_path, is_signed, .. }) => {
-                if *is_signed {
-                    return false;
-                }
-                let path = normalise_path(image_path);
-                dirs.iter().any(|dir| path.starts_with(dir))
+            (RuleMatcher::UnsignedExecutionFromDirs, EdrEventKind::ProcessStart { image_path, is_signed, .. }) => {
+                !*is_signed
+                    && config
+                        .unsigned_exec_dirs
+                        .iter()
+                        .any(|dir| config.path_style.is_within(image_path, dir))
             }
             (RuleMatcher::NetworkPortIn(ports), EdrEventKind::NetworkConnection { destination_port, .. }) => {
                 ports.contains(destination_port)
             }
-            (RuleMatcher::FileWriteToSensitiveDirs, EdrEventKind::FileWrite { path, .. }) => {
-                let normalised = normalise_path(path);
-                config
-                    .sensitive_paths
-                    .iter()
-                    .map(|entry| normalise_path(entry))
-                    .any(|entry| normalised.starts_with(&entry))
-            }
+            (RuleMatcher::FileWriteToSensitiveDirs, EdrEventKind::FileWrite { path, .. }) => config
+                .sensitive_paths
+                .iter()
+                .any(|dir| config.path_style.is_within(path, dir)),
             _ => false,
         }
     }
 }
 
 /// Evaluate rules using a temporary sample event set (to be replaced by live telemetry).
-pub fn evaluate_rules() -> Vec<DetectionSummary> {
-    let config = EdrConfig::from_env();
+pub fn evaluate_rules(rules: &[EdrRule], config: &EdrConfig) -> Vec<DetectionSummary> {
     let events = sample_events();
-    evaluate_rules_for_events(&events, &config)
+    evaluate_rules_for_events(&events, rules, config)
 }
 
-pub fn evaluate_rules_for_events(events: &[EdrEvent], config: &EdrConfig) -> Vec<DetectionSummary> {
-    let rules = build_rules(config);
+pub fn evaluate_rules_for_events(events: &[EdrEvent], rules: &[EdrRule], config: &EdrConfig) -> Vec<DetectionSummary> {
     let mut detections = Vec::new();
     let mut seen = HashSet::new();
 
     for event in events {
-        for rule in &rules {
+        for rule in rules {
             if !rule.matcher.matches(event, config) {
                 continue;
             }
@@ -171,6 +380,11 @@ pub fn evaluate_rules_for_events(events: &[EdrEvent], config: &EdrConfig) -> Vec
                 description: rule.description.clone(),
                 event_id: event.event_id.clone(),
                 confidence: calculate_confidence(rule, event),
+                pattern: format!("{}|{}", rule.id, event_target(event)),
+                occurrences: 1,
+                technique: rule.technique.clone(),
+                category: rule.matcher.category().to_string(),
+                target_path: event_path(event),
             });
 
             if detections.len() >= config.max_detections_per_cycle {
@@ -182,6 +396,178 @@ pub fn evaluate_rules_for_events(events: &[EdrEvent], config: &EdrConfig) -> Vec
     detections
 }
 
+/// Remembers recent detection patterns across evaluation cycles and escalates the ones
+/// that keep recurring. Evaluation itself stays stateless.
+#[derive(Debug, Clone)]
+pub struct DetectionTracker {
+    window_ms: u64,
+    threshold: u32,
+    seen: HashMap<String, VecDeque<u64>>,
+}
+
+impl DetectionTracker {
+    pub fn new(window_ms: u64, threshold: u32) -> Self {
+        Self {
+            window_ms,
+            threshold: threshold.max(1),
+            seen: HashMap::new(),
+        }
+    }
+
+    pub fn from_env() -> Self {
+        let window_secs = env::var("EDR_ESCALATION_WINDOW_SECS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .filter(|value| *value > 0)
+            .unwrap_or(3600);
+        let threshold = env::var("EDR_ESCALATION_THRESHOLD")
+            .ok()
+            .and_then(|value| value.parse::<u32>().ok())
+            .filter(|value| *value > 0)
+            .unwrap_or(3);
+        Self::new(window_secs.saturating_mul(1000), threshold)
+    }
+
+    /// Record `detections` seen at `now_ms`. Patterns seen `threshold` times or more within
+    /// the window are raised by two severity points and 15 confidence.
+    pub fn observe(&mut self, detections: Vec<DetectionSummary>, now_ms: u64) -> Vec<DetectionSummary> {
+        let cutoff = now_ms.saturating_sub(self.window_ms);
+        for timestamps in self.seen.values_mut() {
+            while timestamps.front().is_some_and(|seen_at| *seen_at <= cutoff) {
+                timestamps.pop_front();
+            }
+        }
+        self.seen.retain(|_, timestamps| !timestamps.is_empty());
+
+        detections
+            .into_iter()
+            .map(|mut detection| {
+                let timestamps = self.seen.entry(detection.pattern.clone()).or_default();
+                timestamps.push_back(now_ms);
+                detection.occurrences = timestamps.len() as u32;
+                if detection.occurrences >= self.threshold {
+                    detection.severity = detection.severity.saturating_add(2).min(10);
+                    detection.confidence = detection.confidence.saturating_add(15).min(100);
+                }
+                detection
+            })
+            .collect()
+    }
+}
+
+/// Detection ids already reported, so re-evaluating the same events in a later cycle does not
+/// report them again. Holds at most `capacity` ids, evicting the least recently seen; an id
+/// is reported again once `ttl_ms` has passed since it was last reported.
+#[derive(Debug, Clone)]
+pub struct DetectionDedup {
+    capacity: usize,
+    ttl_ms: u64,
+    /// Detection id to (recency tick, reported at).
+    entries: HashMap<String, (u64, u64)>,
+    recency: BTreeMap<u64, String>,
+    next_tick: u64,
+}
+
+impl DetectionDedup {
+    pub fn new(capacity: usize, ttl_ms: u64) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            ttl_ms,
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            next_tick: 0,
+        }
+    }
+
+    pub fn from_config(config: &EdrConfig) -> Self {
+        Self::new(config.dedup_capacity, config.dedup_ttl_ms)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// True when `detection_id` should be reported: it is new, was evicted, or expired.
+    pub fn first_sighting(&mut self, detection_id: &str, now_ms: u64) -> bool {
+        let tick = self.next_tick;
+        self.next_tick += 1;
+        if let Some((old_tick, reported_at)) = self.entries.get(detection_id).copied() {
+            self.recency.remove(&old_tick);
+            let expired = now_ms.saturating_sub(reported_at) >= self.ttl_ms;
+            let reported_at = if expired { now_ms } else { reported_at };
+            self.entries.insert(detection_id.to_string(), (tick, reported_at));
+            self.recency.insert(tick, detection_id.to_string());
+            return expired;
+        }
+        while self.entries.len() >= self.capacity {
+            let Some((_, oldest)) = self.recency.pop_first() else {
+                break;
+            };
+            self.entries.remove(&oldest);
+        }
+        self.entries.insert(detection_id.to_string(), (tick, now_ms));
+        self.recency.insert(tick, detection_id.to_string());
+        true
+    }
+
+    pub fn filter(&mut self, detections: Vec<DetectionSummary>, now_ms: u64) -> Vec<DetectionSummary> {
+        detections
+            .into_iter()
+            .filter(|detection| self.first_sighting(&detection.detection_id, now_ms))
+            .collect()
+    }
+}
+
+/// Entry in the `EDR_RULES_PATH` file: `[{"id": "EDR-SUSP-PORT", "enabled": false}, ...]`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleOverride {
+    id: String,
+    #[serde(default = "default_enabled")]
+    enabled: bool,
+    #[serde(default)]
+    severity: Option<u8>,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+/// Built-in rules with the overrides from `EDR_RULES_PATH` applied. A file that cannot be
+/// read or parsed, names an unknown rule, sets a severity outside 1-10 or disables every
+/// rule is an error: EDR must not silently run with a rule set nobody asked for.
+pub fn load_rules(config: &EdrConfig) -> Result<Vec<EdrRule>, String> {
+    let mut rules = build_rules(config);
+    let path = match &config.rules_path {
+        Some(path) => path,
+        None => return Ok(rules),
+    };
+    let raw = fs::read_to_string(path).map_err(|err| format!("read EDR rules {}: {}", path.display(), err))?;
+    let overrides = serde_json::from_str::<Vec<RuleOverride>>(&raw)
+        .map_err(|err| format!("parse EDR rules {}: {}", path.display(), err))?;
+    let mut disabled = HashSet::new();
+    for entry in overrides {
+        let rule = rules
+            .iter_mut()
+            .find(|rule| rule.id == entry.id)
+            .ok_or_else(|| format!("EDR rules {}: unknown rule {}", path.display(), entry.id))?;
+        if let Some(severity) = entry.severity {
+            if !(1..=10).contains(&severity) {
+                return Err(format!("EDR rules {}: severity {} for {} is outside 1-10", path.display(), severity, entry.id));
+            }
+            rule.severity = severity;
+        }
+        if !entry.enabled {
+            disabled.insert(entry.id);
+        }
+    }
+    rules.retain(|rule| !disabled.contains(&rule.id));
+    if rules.is_empty() {
+        return Err(format!("EDR rules {} disable every rule", path.display()));
+    }
+    Ok(rules)
+}
+
 fn build_rules(config: &EdrConfig) -> Vec<EdrRule> {
     let mut rules = Vec::new();
 
@@ -191,6 +577,7 @@ fn build_rules(config: &EdrConfig) -> Vec<EdrRule> {
         description: "Process command line includes encoded PowerShell flags indicative of obfuscation."
             .to_string(),
         severity: 8,
+        technique: "T1059.001".to_string(),
         matcher: RuleMatcher::ProcessCommandContains(vec!["powershell", "-enc", "-encodedcommand"]),
     });
 
@@ -199,7 +586,8 @@ fn build_rules(config: &EdrConfig) -> Vec<EdrRule> {
         title: "Unsigned execution from temporary directory".to_string(),
         description: "Unsigned binary launched from common temporary locations.".to_string(),
         severity: 7,
-        matcher: RuleMatcher::UnsignedExecutionFromDirs(vec!["c:/windows/temp", "c:/users", "/tmp", "/var/tmp"]),
+        technique: "T1204.002".to_string(),
+        matcher: RuleMatcher::UnsignedExecutionFromDirs,
     });
 
     rules.push(EdrRule {
@@ -208,6 +596,7 @@ fn build_rules(config: &EdrConfig) -> Vec<EdrRule> {
         description: "Network connection targeting ports commonly abused for remote access or C2."
             .to_string(),
         severity: 6,
+        technique: "T1571".to_string(),
         matcher: RuleMatcher::NetworkPortIn(config.suspicious_ports.clone()),
     });
 
@@ -216,12 +605,56 @@ fn build_rules(config: &EdrConfig) -> Vec<EdrRule> {
         title: "Sensitive path file write".to_string(),
         description: "Process writing to system-sensitive directories.".to_string(),
         severity: 5,
+        technique: "T1574".to_string(),
         matcher: RuleMatcher::FileWriteToSensitiveDirs,
     });
 
     rules
 }
 
+/// Severity bands for the 1-10 rule scale, matching the CVSS bands used for vulnerabilities.
+pub fn telemetry_severity(severity: u8) -> TelemetrySeverity {
+    match severity {
+        9.. => TelemetrySeverity::Critical,
+        7..=8 => TelemetrySeverity::High,
+        4..=6 => TelemetrySeverity::Medium,
+        1..=3 => TelemetrySeverity::Low,
+        0 => TelemetrySeverity::Informational,
+    }
+}
+
+/// SIEM events for the detections whose category is in `telemetry_categories`. The event id
+/// is the detection id, so the SIEM can join it with linked evidence.
+pub fn detections_to_telemetry(detections: &[DetectionSummary], config: &EdrConfig, now_unix_ms: u64) -> Vec<TelemetryEvent> {
+    detections
+        .iter()
+        .filter(|detection| config.telemetry_categories.contains(&detection.category))
+        .map(|detection| {
+            let field = |key: &str, value: String| TelemetryField {
+                key: key.to_string(),
+                value,
+            };
+            TelemetryEvent {
+                event_id: detection.detection_id.clone(),
+                stream: DETECTION_STREAM.to_string(),
+                category: detection.category.clone(),
+                severity: telemetry_severity(detection.severity),
+                timestamp_unix_ms: now_unix_ms,
+                message: detection.title.clone(),
+                fields: vec![
+                    field("rule_id", detection.rule_id.clone()),
+                    field("title", detection.title.clone()),
+                    field("technique", detection.technique.clone()),
+                    field("severity_score", detection.severity.to_string()),
+                    field("confidence", detection.confidence.to_string()),
+                    field("source_event_id", detection.event_id.clone()),
+                    field("occurrences", detection.occurrences.to_string()),
+                ],
+            }
+        })
+        .collect()
+}
+
 fn calculate_confidence(rule: &EdrRule, event: &EdrEvent) -> u8 {
     match (&rule.matcher, &event.kind) {
         (RuleMatcher::ProcessCommandContains(_), EdrEventKind::ProcessStart { command_line, .. }) => {
@@ -231,7 +664,7 @@ fn calculate_confidence(rule: &EdrRule, event: &EdrEvent) -> u8 {
                 70
             }
         }
-        (RuleMatcher::UnsignedExecutionFromDirs(_), EdrEventKind::ProcessStart { is_signed, .. }) => {
+        (RuleMatcher::UnsignedExecutionFromDirs, EdrEventKind::ProcessStart { is_signed, .. }) => {
             if *is_signed { 30 } else { 80 }
         }
         (RuleMatcher::NetworkPortIn(_), EdrEventKind::NetworkConnection { destination_port, .. }) => {
@@ -242,6 +675,27 @@ fn calculate_confidence(rule: &EdrRule, event: &EdrEvent) -> u8 {
     }
 }
 
+/// What an event acted on, normalised so repeats from different events compare equal.
+fn event_target(event: &EdrEvent) -> String {
+    match &event.kind {
+        EdrEventKind::ProcessStart { image_path, .. } => normalise_path(image_path),
+        EdrEventKind::FileWrite { path, .. } => normalise_path(path),
+        EdrEventKind::NetworkConnection {
+            destination_ip,
+            destination_port,
+            ..
+        } => format!("{}:{}", destination_ip.trim().to_lowercase(), destination_port),
+    }
+}
+
+fn event_path(event: &EdrEvent) -> String {
+    match &event.kind {
+        EdrEventKind::ProcessStart { image_path, .. } => image_path.trim().to_string(),
+        EdrEventKind::FileWrite { path, .. } => path.trim().to_string(),
+        EdrEventKind::NetworkConnection { .. } => String::new(),
+    }
+}
+
 fn sample_events() -> Vec<EdrEvent> {
     vec![
         EdrEvent {
@@ -292,3 +746,240 @@ fn normalise_text(value: &str) -> String {
         .collect::<String>()
         .to_lowercase()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        detections_to_telemetry, evaluate_rules, evaluate_rules_for_events, load_rules, sample_events, telemetry_severity,
+        DetectionDedup, DetectionTracker, EdrConfig, EdrEventKind, PathStyle, NETWORK_DETECTION_CATEGORY,
+        PROCESS_DETECTION_CATEGORY,
+    };
+    use crate::pipeline::{ComponentHealth, HealthState, PipelineHealth};
+    use crate::siem::TelemetrySeverity;
+    use crate::time::unix_time_ms;
+
+    fn config_with_rules(label: &str, contents: &str) -> EdrConfig {
+        let path = std::env::temp_dir().join(format!("edr-rules-{}-{}-{}.json", label, std::process::id(), unix_time_ms()));
+        std::fs::write(&path, contents).expect("write rules");
+        EdrConfig {
+            max_detections_per_cycle: 64,
+            suspicious_ports: vec![4444],
+            path_style: PathStyle::Windows,
+            unsigned_exec_dirs: vec!["c:/windows/temp".to_string(), "c:/users".to_string()],
+            sensitive_paths: vec!["c:/windows/system32".to_string()],
+            rules_path: Some(path),
+            dedup_capacity: 16,
+            dedup_ttl_ms: 60_000,
+            telemetry_categories: vec![super::PROCESS_DETECTION_CATEGORY.to_string()],
+            cycle_interval_secs: 60,
+        }
+    }
+
+    #[test]
+    fn applies_rule_overrides() {
+        let config = config_with_rules("ok", r#"[{"id":"EDR-SUSP-PORT","enabled":false},{"id":"EDR-PSH-ENC","severity":10}]"#);
+        let rules = load_rules(&config).expect("rules load");
+        assert_eq!(rules.len(), 3);
+
+        let detections = evaluate_rules(&rules, &config);
+        assert!(detections.iter().all(|detection| detection.rule_id != "EDR-SUSP-PORT"));
+        let encoded = detections.iter().find(|detection| detection.rule_id == "EDR-PSH-ENC").expect("powershell detection");
+        assert_eq!(encoded.severity, 10);
+
+        let _ = std::fs::remove_file(config.rules_path.as_ref().expect("path"));
+    }
+
+    #[test]
+    fn routes_allowlisted_detections_as_telemetry() {
+        let severities = [0, 1, 3, 4, 6, 7, 8, 9, 10].map(telemetry_severity);
+        assert_eq!(
+            severities,
+            [
+                TelemetrySeverity::Informational,
+                TelemetrySeverity::Low,
+                TelemetrySeverity::Low,
+                TelemetrySeverity::Medium,
+                TelemetrySeverity::Medium,
+                TelemetrySeverity::High,
+                TelemetrySeverity::High,
+                TelemetrySeverity::Critical,
+                TelemetrySeverity::Critical,
+            ]
+        );
+
+        let mut config = config_with_rules("telemetry", "[]");
+        let rules = load_rules(&config).expect("rules load");
+        let detections = evaluate_rules(&rules, &config);
+        assert!(detections.iter().any(|detection| detection.category == NETWORK_DETECTION_CATEGORY));
+
+        let events = detections_to_telemetry(&detections, &config, 5_000);
+        assert!(!events.is_empty());
+        assert!(events.iter().all(|event| event.category == PROCESS_DETECTION_CATEGORY));
+        let encoded = events.iter().find(|event| event.event_id.starts_with("det-EDR-PSH-ENC")).expect("powershell event");
+        assert_eq!(encoded.severity, TelemetrySeverity::High);
+        assert_eq!(encoded.timestamp_unix_ms, 5_000);
+        assert_eq!(encoded.message, "Encoded PowerShell invocation");
+        let field = |key: &str| {
+            encoded
+                .fields
+                .iter()
+                .find(|field| field.key == key)
+                .map(|field| field.value.as_str())
+        };
+        assert_eq!(field("rule_id"), Some("EDR-PSH-ENC"));
+        assert_eq!(field("title"), Some("Encoded PowerShell invocation"));
+        assert_eq!(field("technique"), Some("T1059.001"));
+        assert_eq!(field("severity_score"), Some("8"));
+
+        config.telemetry_categories.clear();
+        assert!(detections_to_telemetry(&detections, &config, 5_000).is_empty());
+        let _ = std::fs::remove_file(config.rules_path.as_ref().expect("path"));
+    }
+
+    #[test]
+    fn invalid_rules_file_leaves_edr_not_ready() {
+        for (label, contents, reason) in [
+            ("syntax", "[{not json", "parse EDR rules"),
+            ("unknown", r#"[{"id":"EDR-NOPE"}]"#, "unknown rule EDR-NOPE"),
+            ("severity", r#"[{"id":"EDR-PSH-ENC","severity":11}]"#, "severity 11"),
+        ] {
+            let config = config_with_rules(label, contents);
+            let rules = load_rules(&config);
+            let mut health = PipelineHealth::new();
+            let edr_health = ComponentHealth::from_result(&rules);
+            health.register("edr", move |_| edr_health.clone());
+            health.register("siem", |_| ComponentHealth::ready());
+
+            let report = health.report(1);
+            assert_eq!(report.components[0].health.state, HealthState::Failed);
+            let reasons = report.not_ready_reasons();
+            assert_eq!(reasons.len(), 1, "{}", label);
+            assert!(reasons[0].starts_with("edr: "), "{}", reasons[0]);
+            assert!(reasons[0].contains(reason), "{}", reasons[0]);
+
+            let _ = std::fs::remove_file(config.rules_path.as_ref().expect("path"));
+        }
+    }
+
+    #[test]
+    fn recurring_detection_escalates_and_one_off_does_not() {
+        let config = config_with_rules("escalation", "[]");
+        let rules = load_rules(&config).expect("rules load");
+        let events = sample_events();
+        let port_events = events
+            .iter()
+            .filter(|event| matches!(event.kind, EdrEventKind::NetworkConnection { .. }))
+            .cloned()
+            .collect::<Vec<_>>();
+        let mut tracker = DetectionTracker::new(60_000, 3);
+
+        // Same connection reported by fresh events each cycle.
+        let mut escalated = Vec::new();
+        for cycle in 0..3_u64 {
+            let mut cycle_events = port_events.clone();
+            cycle_events[0].event_id = format!("evt-net-{}", cycle);
+            let detections = evaluate_rules_for_events(&cycle_events, &rules, &config);
+            escalated = tracker.observe(detections, 1_000 + cycle * 10_000);
+        }
+        let baseline = evaluate_rules_for_events(&port_events, &rules, &config);
+        assert_eq!(escalated[0].occurrences, 3);
+        assert_eq!(escalated[0].severity, (baseline[0].severity + 2).min(10));
+        assert_eq!(escalated[0].confidence, baseline[0].confidence + 15);
+
+        // A detection seen once, or again only after the window, is reported unchanged.
+        let file_events = events
+            .iter()
+            .filter(|event| matches!(event.kind, EdrEventKind::FileWrite { .. }))
+            .cloned()
+            .collect::<Vec<_>>();
+        let one_off = tracker.observe(evaluate_rules_for_events(&file_events, &rules, &config), 30_000);
+        assert_eq!(one_off[0].occurrences, 1);
+        let plain = evaluate_rules_for_events(&file_events, &rules, &config);
+        assert_eq!(one_off[0].severity, plain[0].severity);
+        let later = tracker.observe(baseline, 200_000);
+        assert_eq!(later[0].occurrences, 1);
+
+        let _ = std::fs::remove_file(config.rules_path.as_ref().expect("path"));
+    }
+
+    #[test]
+    fn matches_windows_and_unix_paths_against_configured_dirs() {
+        let windows = PathStyle::Windows;
+        assert!(windows.is_within("C:\\Users\\Bob\\Downloads\\run.exe", "c:/users"));
+        assert!(windows.is_within("c:/WINDOWS/Temp/", "C:\\Windows\\Temp\\"));
+        assert!(!windows.is_within("C:\\UsersShared\\run.exe", "c:/users"));
+        let unix = PathStyle::Unix;
+        assert!(unix.is_within("/tmp/payload", "/tmp/"));
+        assert!(!unix.is_within("/tmpfs/payload", "/tmp"));
+        assert!(!unix.is_within("/TMP/payload", "/tmp"));
+        assert!(unix.is_within("//tmp/x", "/tmp"));
+        assert!(unix.is_within("/var/tmp/../tmp/x", "/var/tmp"));
+        assert!(unix.is_within("/usr/lib/../../tmp/x", "/tmp"));
+        assert!(unix.is_within("/tmp/./x", "/tmp"));
+        assert!(!unix.is_within("/tmp/../etc/passwd", "/tmp"));
+        assert!(unix.is_within("/anything", "/"));
+        assert!(windows.is_within("\\\\?\\C:\\Users\\Bob\\run.exe", "c:/users"));
+        assert!(windows.is_within("\\??\\C:\\Windows\\Temp\\x.exe", "c:/windows/temp"));
+        assert!(windows.is_within("\\Device\\HarddiskVolume3\\Windows\\Temp\\x.exe", "c:/windows/temp"));
+        assert!(windows.is_within("C:\\Windows\\System32\\..\\Temp\\\\x.exe", "c:/windows/temp"));
+        assert!(!windows.is_within("C:\\..\\Users", "d:/users"));
+        assert_eq!(windows.normalise("\\\\?\\UNC\\Server\\Share\\x"), "//server/share/x");
+        assert_eq!(windows.normalise("C:\\"), "c:");
+
+        let mut config = config_with_rules("paths", "[]");
+        let rules = load_rules(&config).expect("rules load");
+        let unsigned = |image_path: &str| super::EdrEvent {
+            event_id: "evt-exec".to_string(),
+            timestamp_unix_ms: 1_700_000_000_000,
+            kind: EdrEventKind::ProcessStart {
+                image_path: image_path.to_string(),
+                command_line: String::new(),
+                parent_image: String::new(),
+                is_signed: false,
+            },
+        };
+        let detected = |config: &EdrConfig, image_path: &str| {
+            evaluate_rules_for_events(&[unsigned(image_path)], &rules, config)
+                .iter()
+                .any(|detection| detection.rule_id == "EDR-TEMP-UNSIGNED")
+        };
+        assert!(detected(&config, "C:\\Windows\\Temp\\dropper.exe"));
+        assert!(!detected(&config, "/tmp/dropper"));
+
+        config.path_style = PathStyle::Unix;
+        config.unsigned_exec_dirs = vec!["/tmp".to_string(), "/var/tmp".to_string()];
+        assert!(detected(&config, "/var/tmp/dropper"));
+        assert!(!detected(&config, "C:\\Windows\\Temp\\dropper.exe"));
+
+        let _ = std::fs::remove_file(config.rules_path.as_ref().expect("path"));
+    }
+
+    #[test]
+    fn suppresses_duplicates_within_capacity() {
+        let mut dedup = DetectionDedup::new(4, 60_000);
+
+        assert!(dedup.first_sighting("det-a", 1_000));
+        assert!(dedup.first_sighting("det-b", 1_000));
+        assert!(!dedup.first_sighting("det-a", 2_000));
+        assert!(!dedup.first_sighting("det-b", 30_000));
+        assert_eq!(dedup.len(), 2);
+        // Reported again once the TTL since the last report has passed.
+        assert!(dedup.first_sighting("det-a", 61_000));
+        assert!(!dedup.first_sighting("det-a", 62_000));
+    }
+
+    #[test]
+    fn evicts_least_recently_seen_beyond_capacity() {
+        let mut dedup = DetectionDedup::new(2, 60_000);
+
+        assert!(dedup.first_sighting("det-a", 1_000));
+        assert!(dedup.first_sighting("det-b", 1_000));
+        assert!(!dedup.first_sighting("det-a", 2_000));
+        assert!(dedup.first_sighting("det-c", 3_000));
+        assert_eq!(dedup.len(), 2);
+        // det-b was least recently seen, so it was evicted and is re-emitted.
+        assert!(dedup.first_sighting("det-b", 4_000));
+        assert!(!dedup.first_sighting("det-b", 5_000));
+        assert!(dedup.first_sighting("det-a", 6_000));
+    }
+}
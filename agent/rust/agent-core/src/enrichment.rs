@@ -0,0 +1,188 @@
+use std::collections::HashSet;
+use std::env;
+
+use crate::host_facts::HostFacts;
+use crate::identity::AgentIdentity;
+use crate::siem::{TelemetryEvent, TelemetryField};
+
+const PRIMARY_HOST_KEYS: [&str; 2] = ["host.hostname", "host.agent_version"];
+
+/// Stamps outgoing telemetry with agent identity, host context, and static labels so the
+/// backend can attribute each event without an inventory lookup.
+#[derive(Debug, Clone)]
+pub struct Enricher {
+    identity_fields: Vec<TelemetryField>,
+    labels: Vec<TelemetryField>,
+}
+
+impl Enricher {
+    pub fn new(identity: &AgentIdentity, labels: Vec<TelemetryField>) -> Self {
+        Self {
+            identity_fields: vec![
+                field("agent.asset_id", &identity.asset_id),
+                field("agent.agent_id", &identity.agent_id),
+            ],
+            labels,
+        }
+    }
+
+    pub fn from_env(identity: &AgentIdentity) -> Self {
+        let labels = env::var("TELEMETRY_LABELS")
+            .ok()
+            .map(|value| parse_labels(&value))
+            .unwrap_or_default();
+        Self::new(identity, labels)
+    }
+
+    /// Enrichment fields in priority order: identity, hostname and agent version, labels,
+    /// then the remaining host facts. Later duplicates of a key are discarded.
+    pub fn fields(&self, host: &HostFacts) -> Vec<TelemetryField> {
+        let (primary, secondary): (Vec<TelemetryField>, Vec<TelemetryField>) = host
+            .telemetry_fields()
+            .into_iter()
+            .partition(|field| PRIMARY_HOST_KEYS.contains(&field.key.as_str()));
+        let mut seen = HashSet::new();
+        self.identity_fields
+            .iter()
+            .cloned()
+            .chain(primary)
+            .chain(self.labels.iter().cloned())
+            .chain(secondary)
+            .filter(|field| seen.insert(field.key.clone()))
+            .collect()
+    }
+
+    /// Place enrichment fields ahead of event-supplied ones, dropping event fields that share
+    /// a key, and cap the result at `max_field_count`.
+    pub fn enrich(&self, events: &mut [TelemetryEvent], host: &HostFacts, max_field_count: usize) {
+        let enrichment = self.fields(host);
+        let keys = enrichment
+            .iter()
+            .map(|field| field.key.clone())
+            .collect::<HashSet<String>>();
+
+        for event in events {
+            let mut fields = enrichment.clone();
+            fields.extend(event.fields.drain(..).filter(|field| !keys.contains(&field.key)));
+            fields.truncate(max_field_count);
+            event.fields = fields;
+        }
+    }
+}
+
+/// Parse `k=v,k=v` labels, skipping entries with an empty key or value.
+pub fn parse_labels(raw: &str) -> Vec<TelemetryField> {
+    raw.split(',')
+        .filter_map(|entry| {
+            let (key, value) = entry.split_once('=')?;
+            let (key, value) = (key.trim(), value.trim());
+            if key.is_empty() || value.is_empty() {
+                return None;
+            }
+            Some(field(key, value))
+        })
+        .collect()
+}
+
+fn field(key: &str, value: &str) -> TelemetryField {
+    TelemetryField {
+        key: key.to_string(),
+        value: value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_labels, Enricher};
+    use crate::host_facts::HostFacts;
+    use crate::identity::AgentIdentity;
+    use crate::siem::{TelemetryEvent, TelemetryField, TelemetrySeverity};
+
+    fn build_host() -> HostFacts {
+        HostFacts {
+            os_name: "linux".to_string(),
+            os_version: "6.1".to_string(),
+            hostname: "host-01".to_string(),
+            primary_ip: "10.0.0.5".to_string(),
+            boot_time_unix_ms: None,
+            agent_version: "0.1.0".to_string(),
+            collected_at_unix_ms: 1,
+        }
+    }
+
+    fn build_event(fields: Vec<(&str, &str)>) -> TelemetryEvent {
+        TelemetryEvent {
+            event_id: "evt-1".to_string(),
+            stream: "sensor".to_string(),
+            category: "process".to_string(),
+            severity: TelemetrySeverity::Low,
+            timestamp_unix_ms: 1,
+            message: "event".to_string(),
+            fields: fields
+                .into_iter()
+                .map(|(key, value)| TelemetryField {
+                    key: key.to_string(),
+                    value: value.to_string(),
+                })
+                .collect(),
+        }
+    }
+
+    fn value_of<'a>(event: &'a TelemetryEvent, key: &str) -> Option<&'a str> {
+        event
+            .fields
+            .iter()
+            .find(|field| field.key == key)
+            .map(|field| field.value.as_str())
+    }
+
+    #[test]
+    fn enrichment_overrides_event_fields_with_same_key() {
+        let identity = AgentIdentity::new("asset-1".to_string(), "agent-1".to_string());
+        let enricher = Enricher::new(&identity, parse_labels("site=lon, env=prod"));
+        let mut events = vec![build_event(vec![
+            ("agent.asset_id", "spoofed"),
+            ("host.hostname", "other-host"),
+            ("site", "nyc"),
+            ("image", "cmd.exe"),
+        ])];
+
+        enricher.enrich(&mut events, &build_host(), 32);
+
+        let event = &events[0];
+        assert_eq!(value_of(event, "agent.asset_id"), Some("asset-1"));
+        assert_eq!(value_of(event, "agent.agent_id"), Some("agent-1"));
+        assert_eq!(value_of(event, "host.hostname"), Some("host-01"));
+        assert_eq!(value_of(event, "site"), Some("lon"));
+        assert_eq!(value_of(event, "env"), Some("prod"));
+        assert_eq!(value_of(event, "image"), Some("cmd.exe"));
+        assert_eq!(event.fields.iter().filter(|field| field.key == "site").count(), 1);
+    }
+
+    #[test]
+    fn enrichment_honours_field_count_cap() {
+        let identity = AgentIdentity::new("asset-1".to_string(), "agent-1".to_string());
+        let enricher = Enricher::new(&identity, parse_labels("site=lon"));
+        let mut events = vec![build_event(vec![("image", "cmd.exe"), ("pid", "42")])];
+
+        enricher.enrich(&mut events, &build_host(), 4);
+        assert_eq!(events[0].fields.len(), 4);
+
+        let keys = events[0]
+            .fields
+            .iter()
+            .map(|field| field.key.as_str())
+            .collect::<Vec<&str>>();
+        assert_eq!(keys, vec!["agent.asset_id", "agent.agent_id", "host.hostname", "host.agent_version"]);
+    }
+
+    #[test]
+    fn parses_labels_and_skips_malformed_entries() {
+        let labels = parse_labels("site=lon,=x,empty=,noequals, team = blue ");
+        let pairs = labels
+            .iter()
+            .map(|field| (field.key.as_str(), field.value.as_str()))
+            .collect::<Vec<(&str, &str)>>();
+        assert_eq!(pairs, vec![("site", "lon"), ("team", "blue")]);
+    }
+}
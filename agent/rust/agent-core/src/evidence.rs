@@ -1,10 +1,14 @@
+use std::collections::BTreeSet;
 use std::env;
 use std::fs::File;
-use std::io::{Read, Result as IoResult};
+use std::io::{self, Read, Result as IoResult};
 use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
 
 use sha2::{Digest, Sha256};
 
+use crate::path_guard::{canonicalize_bounded, resolve_under, PathLimits, PathRejection};
 use crate::time::unix_time_ms;
 
 /// Captured evidence with hashes to support tamper-proofing.
@@ -35,6 +39,8 @@ pub enum EvidenceStatus {
     Collected,
     Partial,
     Empty,
+    /// Another run over the same evidence roots was in progress; nothing was read.
+    Busy,
 }
 
 #[derive(Debug, Clone)]
@@ -43,15 +49,26 @@ pub enum EvidenceOutcome {
     Skipped { reason: String },
 }
 
+/// Directory evidence may be collected from, with its own extension and size limits.
+#[derive(Debug, Clone)]
+pub struct EvidenceRoot {
+    pub root_dir: PathBuf,
+    pub allowed_extensions: Vec<String>,
+    pub max_total_bytes: u64,
+}
+
 /// Configuration that controls which evidence items are collected and how much data is processed.
 #[derive(Debug, Clone)]
 pub struct EvidenceConfig {
-    pub root_dir: PathBuf,
+    /// Each evidence path must resolve beneath one of these roots.
+    pub roots: Vec<EvidenceRoot>,
     pub max_item_bytes: u64,
     pub max_total_bytes: u64,
     pub max_items: usize,
-    pub allowed_extensions: Vec<String>,
     pub evidence_paths: Vec<PathBuf>,
+    /// Wall-clock budget for one collection run; `None` collects until the size limits.
+    pub max_duration_ms: Option<u64>,
+    pub path_limits: PathLimits,
 }
 
 impl EvidenceConfig {
@@ -82,14 +99,126 @@ impl EvidenceConfig {
             .into_iter()
             .map(PathBuf::from)
             .collect::<Vec<PathBuf>>();
+        let max_duration_ms = env::var("EVIDENCE_MAX_DURATION_MS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .filter(|value| *value > 0);
+        let default_root = EvidenceRoot {
+            root_dir,
+            allowed_extensions,
+            max_total_bytes,
+        };
+        let roots = env::var("EVIDENCE_ROOTS")
+            .ok()
+            .map(|value| parse_roots(&value, &default_root))
+            .filter(|roots| !roots.is_empty())
+            .unwrap_or_else(|| vec![default_root]);
 
         Self {
-            root_dir,
+            roots,
             max_item_bytes,
             max_total_bytes,
             max_items,
-            allowed_extensions,
             evidence_paths,
+            max_duration_ms,
+            path_limits: PathLimits::from_env(),
+        }
+    }
+}
+
+/// Parse `dir|ext,ext|max_bytes` entries separated by `;`. Omitted extension or size
+/// fields fall back to `defaults`.
+fn parse_roots(value: &str, defaults: &EvidenceRoot) -> Vec<EvidenceRoot> {
+    value
+        .split(';')
+        .filter_map(|entry| {
+            let mut fields = entry.split('|').map(str::trim);
+            let root_dir = fields.next().filter(|dir| !dir.is_empty())?;
+            let allowed_extensions = fields
+                .next()
+                .filter(|extensions| !extensions.is_empty())
+                .map(|extensions| parse_csv(extensions.to_string()))
+                .unwrap_or_else(|| defaults.allowed_extensions.clone());
+            let max_total_bytes = fields
+                .next()
+                .and_then(|bytes| bytes.parse::<u64>().ok())
+                .unwrap_or(defaults.max_total_bytes);
+            Some(EvidenceRoot {
+                root_dir: PathBuf::from(root_dir),
+                allowed_extensions,
+                max_total_bytes,
+            })
+        })
+        .collect()
+}
+
+/// Elapsed-time bound shared by the collection loop and file hashing.
+#[derive(Debug, Clone, Copy)]
+pub struct TimeBudget {
+    deadline: Option<Instant>,
+}
+
+impl TimeBudget {
+    pub fn new(max_duration_ms: Option<u64>) -> Self {
+        Self {
+            deadline: max_duration_ms.map(|limit| Instant::now() + Duration::from_millis(limit)),
+        }
+    }
+
+    pub fn exhausted(&self) -> bool {
+        self.deadline.map(|deadline| Instant::now() >= deadline).unwrap_or(false)
+    }
+}
+
+/// Computes the SHA-256 of an evidence file. Implementations should give up with
+/// `io::ErrorKind::TimedOut` once `budget` is exhausted.
+pub trait FileHasher {
+    fn hash(&self, path: &Path, budget: &TimeBudget) -> IoResult<String>;
+}
+
+/// Streams the file through SHA-256, checking the budget between chunks.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Sha256FileHasher;
+
+impl FileHasher for Sha256FileHasher {
+    fn hash(&self, path: &Path, budget: &TimeBudget) -> IoResult<String> {
+        hash_file(path, budget)
+    }
+}
+
+const TIME_BUDGET_NOTE: &str = "Evidence collection time budget exhausted.";
+const BUSY_NOTE: &str = "Evidence collection already running for one of these roots.";
+
+/// Evidence roots with a collection run in flight. Scheduled and on-demand runs sharing a
+/// root would read the same files twice, so a run only starts when none of its roots is busy.
+fn active_collections() -> &'static Mutex<BTreeSet<PathBuf>> {
+    static ACTIVE: OnceLock<Mutex<BTreeSet<PathBuf>>> = OnceLock::new();
+    ACTIVE.get_or_init(|| Mutex::new(BTreeSet::new()))
+}
+
+/// Held for the duration of a run; dropping it (including while unwinding from a panic)
+/// frees the roots for the next run.
+struct CollectionGuard {
+    roots: BTreeSet<PathBuf>,
+}
+
+impl CollectionGuard {
+    fn try_acquire(config: &EvidenceConfig) -> Option<Self> {
+        let roots = config.roots.iter().map(|root| root.root_dir.clone()).collect::<BTreeSet<PathBuf>>();
+        let mut active = active_collections().lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if roots.iter().any(|root| active.contains(root)) {
+            return None;
+        }
+        active.extend(roots.iter().cloned());
+        Some(Self { roots })
+    }
+}
+
+impl Drop for CollectionGuard {
+    fn drop(&mut self) {
+        let mut active = active_collections().lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        for root in &self.roots {
+            active.remove(root);
         }
     }
 }
@@ -102,10 +231,31 @@ pub fn package_evidence() -> EvidenceRecord {
 }
 
 pub fn package_evidence_with_config(config: &EvidenceConfig) -> EvidenceRecord {
+    package_evidence_with_hasher(config, &Sha256FileHasher)
+}
+
+/// Collect evidence hashing files with `hasher`. When `max_duration_ms` runs out the run
+/// stops early and returns what it has as `Partial`. A run started while another is
+/// collecting from any of the same roots returns `Busy` without reading anything.
+pub fn package_evidence_with_hasher(config: &EvidenceConfig, hasher: &dyn FileHasher) -> EvidenceRecord {
+    let budget = TimeBudget::new(config.max_duration_ms);
     let collected_at_unix_ms = unix_time_ms();
     let evidence_id = format!("evd-{}", collected_at_unix_ms);
     let mut notes = Vec::new();
 
+    let Some(_guard) = CollectionGuard::try_acquire(config) else {
+        notes.push(BUSY_NOTE.to_string());
+        return EvidenceRecord {
+            evidence_id,
+            sha256: empty_hash(),
+            collected_at_unix_ms,
+            total_bytes: 0,
+            status: EvidenceStatus::Busy,
+            items: Vec::new(),
+            notes,
+        };
+    };
+
     if config.evidence_paths.is_empty() {
         notes.push("No evidence paths configured; set EVIDENCE_PATHS to collect artefacts.".to_string());
         return EvidenceRecord {
@@ -120,6 +270,7 @@ pub fn package_evidence_with_config(config: &EvidenceConfig) -> EvidenceRecord {
     }
 
     let mut total_bytes = 0_u64;
+    let mut root_bytes = vec![0_u64; config.roots.len()];
     let mut items = Vec::new();
     let mut collected_any = false;
 
@@ -128,13 +279,31 @@ pub fn package_evidence_with_config(config: &EvidenceConfig) -> EvidenceRecord {
             notes.push("Maximum evidence item count reached.".to_string());
             break;
         }
+        if budget.exhausted() {
+            notes.push(TIME_BUDGET_NOTE.to_string());
+            break;
+        }
 
-        match collect_item(path, config, collected_at_unix_ms, index) {
+        match collect_item(path, config, &mut root_bytes, hasher, &budget, collected_at_unix_ms, index) {
             Ok((item, bytes_written, was_collected)) => {
                 total_bytes = total_bytes.saturating_add(bytes_written);
                 collected_any |= was_collected;
                 items.push(item);
             }
+            Err(err) if err.kind() == io::ErrorKind::TimedOut => {
+                notes.push(TIME_BUDGET_NOTE.to_string());
+                items.push(EvidenceItem {
+                    item_id: format!("item-{}", index),
+                    path: path.display().to_string(),
+                    sha256: empty_hash(),
+                    size_bytes: 0,
+                    collected_at_unix_ms,
+                    outcome: EvidenceOutcome::Skipped {
+                        reason: "Time budget exhausted".to_string(),
+                    },
+                });
+                break;
+            }
             Err(err) => {
                 notes.push(format!("Failed to collect {}: {}", path.display(), err));
                 items.push(EvidenceItem {
@@ -182,16 +351,19 @@ pub fn package_evidence_with_config(config: &EvidenceConfig) -> EvidenceRecord {
 fn collect_item(
     path: &Path,
     config: &EvidenceConfig,
+    root_bytes: &mut [u64],
+    hasher: &dyn FileHasher,
+    budget: &TimeBudget,
     collected_at_unix_ms: u64,
     index: usize,
 ) -> IoResult<(EvidenceItem, u64, bool)> {
     let item_id = format!("item-{}", index);
-    let resolved = resolve_path(path, &config.root_dir);
-    let path_display = resolved.as_ref().map(|value| value.display().to_string()).unwrap_or_else(|| path.display().to_string());
+    let resolved = resolve_path(path, &config.roots, &config.path_limits);
+    let path_display = resolved.as_ref().map(|(value, _)| value.display().to_string()).unwrap_or_else(|_| path.display().to_string());
 
-    let resolved = match resolved {
-        Some(value) => value,
-        None => {
+    let (resolved, root_index) = match resolved {
+        Ok(value) => value,
+        Err(rejection) => {
             return Ok((
                 EvidenceItem {
                     item_id,
@@ -200,7 +372,10 @@ fn collect_item(
                     size_bytes: 0,
                     collected_at_unix_ms,
                     outcome: EvidenceOutcome::Skipped {
-                        reason: "Path outside evidence root".to_string(),
+                        reason: match rejection {
+                            PathRejection::OutsideRoot => "Path outside evidence root".to_string(),
+                            other => other.to_string(),
+                        },
                     },
                 },
                 0,
@@ -209,7 +384,8 @@ fn collect_item(
         }
     };
 
-    if !is_extension_allowed(&resolved, &config.allowed_extensions) {
+    let root = &config.roots[root_index];
+    if !is_extension_allowed(&resolved, &root.allowed_extensions) {
         return Ok((
             EvidenceItem {
                 item_id,
@@ -262,7 +438,25 @@ fn collect_item(
         ));
     }
 
-    let sha256 = hash_file(&resolved)?;
+    if root_bytes[root_index].saturating_add(size_bytes) > root.max_total_bytes {
+        return Ok((
+            EvidenceItem {
+                item_id,
+                path: path_display,
+                sha256: empty_hash(),
+                size_bytes,
+                collected_at_unix_ms,
+                outcome: EvidenceOutcome::Skipped {
+                    reason: "Evidence root size limit reached".to_string(),
+                },
+            },
+            0,
+            false,
+        ));
+    }
+
+    let sha256 = hasher.hash(&resolved, budget)?;
+    root_bytes[root_index] = root_bytes[root_index].saturating_add(size_bytes);
     Ok((
         EvidenceItem {
             item_id,
@@ -277,19 +471,34 @@ fn collect_item(
     ))
 }
 
-fn resolve_path(path: &Path, root: &Path) -> Option<PathBuf> {
-    let root = root.canonicalize().ok()?;
-    let resolved = if path.is_absolute() {
-        path.canonicalize().ok()?
-    } else {
-        root.join(path).canonicalize().ok()?
-    };
+/// Resolve `path` against the configured roots and return it with the index of the root
+/// it falls under. Relative paths are tried against each root in order; absolute paths
+/// match the most specific root containing them.
+fn resolve_path(path: &Path, roots: &[EvidenceRoot], limits: &PathLimits) -> Result<(PathBuf, usize), PathRejection> {
+    let canonical_roots = roots
+        .iter()
+        .enumerate()
+        .filter_map(|(index, root)| root.root_dir.canonicalize().ok().map(|dir| (index, dir)))
+        .collect::<Vec<(usize, PathBuf)>>();
+
+    if path.is_absolute() {
+        let resolved = canonicalize_bounded(path, limits)?;
+        let (index, _) = canonical_roots
+            .iter()
+            .filter(|(_, root)| resolved.starts_with(root))
+            .max_by_key(|(_, root)| root.components().count())
+            .ok_or(PathRejection::OutsideRoot)?;
+        return Ok((resolved, *index));
+    }
 
-    if resolved.starts_with(&root) {
-        Some(resolved)
-    } else {
-        None
+    let mut rejection = PathRejection::OutsideRoot;
+    for (index, root) in &canonical_roots {
+        match resolve_under(path, root, limits) {
+            Ok(resolved) => return Ok((resolved, *index)),
+            Err(err) => rejection = err,
+        }
     }
+    Err(rejection)
 }
 
 fn is_extension_allowed(path: &Path, allowed: &[String]) -> bool {
@@ -302,12 +511,15 @@ fn is_extension_allowed(path: &Path, allowed: &[String]) -> bool {
         .unwrap_or(false)
 }
 
-fn hash_file(path: &Path) -> IoResult<String> {
+fn hash_file(path: &Path, budget: &TimeBudget) -> IoResult<String> {
     let mut file = File::open(path)?;
     let mut hasher = Sha256::new();
     let mut buffer = vec![0u8; 8192];
 
     loop {
+        if budget.exhausted() {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "time budget exhausted"));
+        }
         let read_count = file.read(&mut buffer)?;
         if read_count == 0 {
             break;
@@ -349,3 +561,216 @@ fn parse_csv(value: String) -> Vec<String> {
         .filter(|entry| !entry.is_empty())
         .collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sleeps before each hash so a small budget runs out part-way through a run.
+    struct SlowHasher {
+        delay: Duration,
+    }
+
+    impl FileHasher for SlowHasher {
+        fn hash(&self, path: &Path, budget: &TimeBudget) -> IoResult<String> {
+            std::thread::sleep(self.delay);
+            if budget.exhausted() {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "time budget exhausted"));
+            }
+            hash_file(path, budget)
+        }
+    }
+
+    fn evidence_root(label: &str, files: usize) -> (PathBuf, EvidenceConfig) {
+        let root = std::env::temp_dir().join(format!("evidence-{}-{}-{}", label, std::process::id(), unix_time_ms()));
+        std::fs::create_dir_all(&root).expect("create evidence root");
+        let evidence_paths = (0..files)
+            .map(|index| {
+                let name = format!("item-{}.log", index);
+                std::fs::write(root.join(&name), format!("evidence {}", index)).expect("write evidence");
+                PathBuf::from(name)
+            })
+            .collect();
+        let config = EvidenceConfig {
+            roots: vec![EvidenceRoot {
+                root_dir: root.clone(),
+                allowed_extensions: vec!["log".to_string()],
+                max_total_bytes: 1024 * 1024,
+            }],
+            max_item_bytes: 1024,
+            max_total_bytes: 1024 * 1024,
+            max_items: 16,
+            evidence_paths,
+            max_duration_ms: None,
+            path_limits: PathLimits::default(),
+        };
+        (root, config)
+    }
+
+    #[test]
+    fn stops_collection_when_time_budget_is_exhausted() {
+        let (root, mut config) = evidence_root("budget", 5);
+        let slow = SlowHasher {
+            delay: Duration::from_millis(40),
+        };
+
+        let unbounded = package_evidence_with_hasher(&config, &slow);
+        assert!(matches!(unbounded.status, EvidenceStatus::Collected));
+        assert_eq!(unbounded.items.len(), 5);
+
+        config.max_duration_ms = Some(60);
+        let record = package_evidence_with_hasher(&config, &slow);
+        assert!(matches!(record.status, EvidenceStatus::Partial));
+        assert!(record.notes.iter().any(|note| note == TIME_BUDGET_NOTE));
+        assert!(record.items.len() < 5, "collected {} items", record.items.len());
+        assert!(matches!(record.items[0].outcome, EvidenceOutcome::Collected));
+        assert!(matches!(
+            record.items.last().map(|item| &item.outcome),
+            Some(EvidenceOutcome::Skipped { reason }) if reason == "Time budget exhausted"
+        ));
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn resolves_paths_only_under_configured_roots() {
+        let (root_a, mut config) = evidence_root("root-a", 1);
+        let (root_b, _) = evidence_root("root-b", 1);
+        let outside = root_b.join("item-0.log");
+
+        let (resolved, index) =
+            resolve_path(Path::new("item-0.log"), &config.roots, &config.path_limits).expect("under root a");
+        assert_eq!(resolved, root_a.join("item-0.log").canonicalize().expect("canonical"));
+        assert_eq!(index, 0);
+        assert_eq!(resolve_path(&outside, &config.roots, &config.path_limits), Err(PathRejection::OutsideRoot));
+
+        config.evidence_paths = vec![PathBuf::from("item-0.log"), outside.clone()];
+        let record = package_evidence_with_config(&config);
+        assert!(matches!(record.items[0].outcome, EvidenceOutcome::Collected));
+        assert!(matches!(
+            &record.items[1].outcome,
+            EvidenceOutcome::Skipped { reason } if reason == "Path outside evidence root"
+        ));
+
+        let _ = std::fs::remove_dir_all(&root_a);
+        let _ = std::fs::remove_dir_all(&root_b);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn skips_broken_symlinks_without_failing_the_run() {
+        let (root, mut config) = evidence_root("broken-link", 1);
+        std::os::unix::fs::symlink(root.join("gone.log"), root.join("broken.log")).expect("broken link");
+        config.evidence_paths = vec![PathBuf::from("broken.log"), PathBuf::from("item-0.log")];
+
+        let record = package_evidence_with_config(&config);
+        assert!(matches!(
+            &record.items[0].outcome,
+            EvidenceOutcome::Skipped { reason } if reason.starts_with("Path could not be resolved")
+        ));
+        assert!(matches!(record.items[1].outcome, EvidenceOutcome::Collected));
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn applies_limits_per_root() {
+        let (root_a, mut config) = evidence_root("limits-a", 2);
+        let (root_b, _) = evidence_root("limits-b", 2);
+        std::fs::write(root_b.join("notes.txt"), "text").expect("write evidence");
+        config.roots.push(EvidenceRoot {
+            root_dir: root_b.clone(),
+            allowed_extensions: vec!["txt".to_string(), "log".to_string()],
+            max_total_bytes: 12,
+        });
+        config.evidence_paths = vec![
+            root_a.join("item-0.log"),
+            root_a.join("item-1.log"),
+            root_b.join("notes.txt"),
+            root_b.join("item-0.log"),
+            root_b.join("item-1.log"),
+        ];
+
+        let record = package_evidence_with_config(&config);
+        let outcomes = record
+            .items
+            .iter()
+            .map(|item| match &item.outcome {
+                EvidenceOutcome::Collected => "collected",
+                EvidenceOutcome::Skipped { reason } => reason.as_str(),
+            })
+            .collect::<Vec<&str>>();
+        assert_eq!(
+            outcomes,
+            vec![
+                "collected",
+                "collected",
+                "collected",
+                "Evidence root size limit reached",
+                "Evidence root size limit reached",
+            ]
+        );
+        assert_eq!(parse_roots("/a|log|10;/b||", &config.roots[0]).len(), 2);
+
+        let _ = std::fs::remove_dir_all(&root_a);
+        let _ = std::fs::remove_dir_all(&root_b);
+    }
+
+    /// Signals when hashing starts, then waits to be released.
+    struct GatedHasher {
+        started: std::sync::mpsc::Sender<()>,
+        release: std::sync::Mutex<std::sync::mpsc::Receiver<()>>,
+    }
+
+    impl FileHasher for GatedHasher {
+        fn hash(&self, path: &Path, budget: &TimeBudget) -> IoResult<String> {
+            let _ = self.started.send(());
+            let _ = self.release.lock().expect("release lock").recv();
+            hash_file(path, budget)
+        }
+    }
+
+    struct PanickingHasher;
+
+    impl FileHasher for PanickingHasher {
+        fn hash(&self, _path: &Path, _budget: &TimeBudget) -> IoResult<String> {
+            panic!("hasher failed");
+        }
+    }
+
+    #[test]
+    fn concurrent_run_sharing_a_root_is_busy() {
+        let (root, config) = evidence_root("busy", 1);
+        let (started_tx, started_rx) = std::sync::mpsc::channel();
+        let (release_tx, release_rx) = std::sync::mpsc::channel();
+        let gated = GatedHasher {
+            started: started_tx,
+            release: std::sync::Mutex::new(release_rx),
+        };
+
+        let first = std::thread::scope(|scope| {
+            let running = scope.spawn(|| package_evidence_with_hasher(&config, &gated));
+            started_rx.recv().expect("first run started");
+            let second = package_evidence_with_hasher(&config, &Sha256FileHasher);
+            assert!(matches!(second.status, EvidenceStatus::Busy));
+            assert!(second.items.is_empty());
+            assert_eq!(second.notes, vec![BUSY_NOTE.to_string()]);
+            let mut overlapping = config.clone();
+            let mut other = overlapping.roots[0].clone();
+            other.root_dir = root.join("other");
+            overlapping.roots.push(other);
+            assert!(matches!(package_evidence_with_config(&overlapping).status, EvidenceStatus::Busy));
+            release_tx.send(()).expect("release first run");
+            running.join().expect("first run")
+        });
+        assert!(matches!(first.status, EvidenceStatus::Collected));
+
+        // A run that panics must not leave the roots locked.
+        let panicked = std::panic::catch_unwind(|| package_evidence_with_hasher(&config, &PanickingHasher));
+        assert!(panicked.is_err());
+        let after = package_evidence_with_config(&config);
+        assert!(matches!(after.status, EvidenceStatus::Collected));
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,552 @@
+use std::env;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::process::{Child, Command};
+use tokio::sync::Notify;
+
+use crate::command_router::{route_command_explain_with_config, CommandRouteConfig};
+use crate::policy::PolicyBundle;
+use crate::rmm::{CancelSignal, ExecutionOutcome, ExecutionRequest, RmmConfig, Termination};
+use crate::state_dir::StatePaths;
+use crate::time::unix_time_ms;
+
+/// The only action this executor runs.
+pub const SCRIPT_RUN_ACTION: &str = "script-run";
+/// Variables passed through to scripts when `RMM_SCRIPT_ENV_ALLOWLIST` is unset.
+const DEFAULT_ENV_ALLOWLIST: [&str; 5] = ["PATH", "LANG", "SYSTEMROOT", "TEMP", "TMP"];
+/// How long to wait for output pipes to close after the child has been reaped or killed.
+const PIPE_DRAIN_GRACE: Duration = Duration::from_secs(2);
+
+/// Constraints applied to every `script-run`. The interpreter comes from local config, never
+/// from the command, which only supplies its arguments.
+#[derive(Debug, Clone)]
+pub struct ScriptExecutorConfig {
+    pub interpreter: Option<PathBuf>,
+    pub working_dir: PathBuf,
+    pub timeout_ms: u64,
+    pub max_output_bytes: usize,
+    pub env_allowlist: Vec<String>,
+    /// Unix only: drop to this uid/gid before exec. Ignored on other platforms.
+    pub run_as_uid: Option<u32>,
+    pub run_as_gid: Option<u32>,
+    /// How long a cancelled script gets after SIGTERM before its process group is killed.
+    pub cancel_grace_ms: u64,
+}
+
+impl ScriptExecutorConfig {
+    pub fn from_env() -> Self {
+        let interpreter = env::var("RMM_SCRIPT_INTERPRETER")
+            .ok()
+            .filter(|value| !value.trim().is_empty())
+            .map(PathBuf::from);
+        let working_dir = env::var("RMM_SCRIPT_WORKDIR")
+            .ok()
+            .filter(|value| !value.trim().is_empty())
+            .map(PathBuf::from)
+            .unwrap_or_else(|| StatePaths::from_env().root.join("rmm_work"));
+        let timeout_ms = env::var("RMM_SCRIPT_TIMEOUT_SECS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .filter(|value| *value > 0)
+            .unwrap_or(300)
+            .saturating_mul(1000);
+        let env_allowlist = env::var("RMM_SCRIPT_ENV_ALLOWLIST")
+            .ok()
+            .map(|value| {
+                value
+                    .split(',')
+                    .map(|name| name.trim().to_string())
+                    .filter(|name| !name.is_empty())
+                    .collect()
+            })
+            .unwrap_or_else(|| DEFAULT_ENV_ALLOWLIST.iter().map(|name| name.to_string()).collect());
+        let run_as_uid = env::var("RMM_SCRIPT_UID").ok().and_then(|value| value.parse::<u32>().ok());
+        let run_as_gid = env::var("RMM_SCRIPT_GID").ok().and_then(|value| value.parse::<u32>().ok());
+        let cancel_grace_ms = env::var("RMM_CANCEL_GRACE_SECS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .unwrap_or(5)
+            .saturating_mul(1000);
+
+        Self {
+            interpreter,
+            working_dir,
+            timeout_ms,
+            max_output_bytes: RmmConfig::from_env().max_output_bytes,
+            env_allowlist,
+            run_as_uid,
+            run_as_gid,
+            cancel_grace_ms,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScriptExecutionError {
+    /// The request is for an action other than `script-run`.
+    UnsupportedAction(String),
+    /// The policy's command checks rejected the request; carries their reasons.
+    NotPermitted(String),
+    InterpreterUnavailable(String),
+    WorkingDirectory(String),
+    Spawn(String),
+}
+
+impl fmt::Display for ScriptExecutionError {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnsupportedAction(action) => write!(formatter, "action {} is not run by the script executor", action),
+            Self::NotPermitted(reasons) => write!(formatter, "command not permitted by policy: {}", reasons),
+            Self::InterpreterUnavailable(reason) => write!(formatter, "script interpreter unavailable: {}", reason),
+            Self::WorkingDirectory(reason) => write!(formatter, "script working directory unusable: {}", reason),
+            Self::Spawn(reason) => write!(formatter, "failed to start script: {}", reason),
+        }
+    }
+}
+
+/// Run a `script-run` request under `config`'s constraints. The request is re-checked against
+/// the policy here, so nothing reaches the interpreter that the router would refuse. Once
+/// `cancel` fires the script is stopped and reported `Cancelled` with the output so far.
+pub async fn run_script(
+    request: &ExecutionRequest,
+    policy: &PolicyBundle,
+    route: &CommandRouteConfig,
+    config: &ScriptExecutorConfig,
+    cancel: &CancelSignal,
+    now_unix_ms: u64,
+) -> Result<ExecutionOutcome, ScriptExecutionError> {
+    if request.action != SCRIPT_RUN_ACTION {
+        return Err(ScriptExecutionError::UnsupportedAction(request.action.clone()));
+    }
+    let decision = route_command_explain_with_config(&request.signed_command(), policy, route, now_unix_ms);
+    if !decision.allowed() {
+        let reasons = decision
+            .results
+            .iter()
+            .filter_map(|result| result.reason.clone())
+            .collect::<Vec<String>>();
+        return Err(ScriptExecutionError::NotPermitted(reasons.join("; ")));
+    }
+
+    let interpreter = checked_interpreter(config)?;
+    if cancel.is_cancelled() {
+        return Ok(ExecutionOutcome::cancelled(request, unix_time_ms()));
+    }
+    prepare_working_dir(config)?;
+
+    let mut process = Command::new(interpreter);
+    process
+        .args(&request.arguments)
+        .current_dir(&config.working_dir)
+        .env_clear()
+        .envs(config.env_allowlist.iter().filter_map(|name| env::var(name).ok().map(|value| (name, value))))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    #[cfg(unix)]
+    {
+        // Own process group so a timeout also kills anything the script spawned.
+        process.process_group(0);
+        if let Some(gid) = config.run_as_gid {
+            process.gid(gid);
+        }
+        if let Some(uid) = config.run_as_uid {
+            process.uid(uid);
+        }
+    }
+
+    let started_at_unix_ms = unix_time_ms();
+    let mut child = process
+        .spawn()
+        .map_err(|err| ScriptExecutionError::Spawn(err.to_string()))?;
+    let overflow = Arc::new(Notify::new());
+    let stdout = child
+        .stdout
+        .take()
+        .map(|pipe| tokio::spawn(read_capped(pipe, config.max_output_bytes, Arc::clone(&overflow))));
+    let stderr = child
+        .stderr
+        .take()
+        .map(|pipe| tokio::spawn(read_capped(pipe, config.max_output_bytes, Arc::clone(&overflow))));
+
+    let (termination, mut exit_code) = tokio::select! {
+        status = child.wait() => (Termination::Exited, status.ok().and_then(|status| status.code())),
+        _ = tokio::time::sleep(Duration::from_millis(config.timeout_ms)) => (Termination::TimedOut, None),
+        _ = overflow.notified() => (Termination::OutputLimit, None),
+        _ = cancel.cancelled() => (Termination::Cancelled, None),
+    };
+    match termination {
+        Termination::Exited => {}
+        Termination::Cancelled => {
+            terminate_tree(&mut child, config.cancel_grace_ms).await;
+            exit_code = None;
+        }
+        _ => {
+            kill_tree(&mut child).await;
+            exit_code = None;
+        }
+    }
+    let (stdout, stdout_dropped) = collect_output(stdout).await;
+    let (stderr, stderr_dropped) = collect_output(stderr).await;
+
+    let mut outcome = ExecutionOutcome::new(
+        request,
+        exit_code,
+        started_at_unix_ms,
+        unix_time_ms(),
+        &stdout,
+        &stderr,
+        config.max_output_bytes,
+    );
+    outcome.stdout_truncated_bytes += stdout_dropped;
+    outcome.stderr_truncated_bytes += stderr_dropped;
+    outcome.termination = termination;
+    outcome.success = outcome.success && termination == Termination::Exited;
+    Ok(outcome)
+}
+
+fn checked_interpreter(config: &ScriptExecutorConfig) -> Result<&Path, ScriptExecutionError> {
+    let interpreter = config
+        .interpreter
+        .as_deref()
+        .ok_or_else(|| ScriptExecutionError::InterpreterUnavailable("RMM_SCRIPT_INTERPRETER is not set".to_string()))?;
+    if !interpreter.is_absolute() {
+        return Err(ScriptExecutionError::InterpreterUnavailable(format!(
+            "{} is not an absolute path",
+            interpreter.display()
+        )));
+    }
+    if !interpreter.is_file() {
+        return Err(ScriptExecutionError::InterpreterUnavailable(format!(
+            "{} is not a file",
+            interpreter.display()
+        )));
+    }
+    Ok(interpreter)
+}
+
+/// Create the working directory, private to the agent (or to the run-as user on Unix).
+fn prepare_working_dir(config: &ScriptExecutorConfig) -> Result<(), ScriptExecutionError> {
+    std::fs::create_dir_all(&config.working_dir)
+        .map_err(|err| ScriptExecutionError::WorkingDirectory(err.to_string()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        std::fs::set_permissions(&config.working_dir, std::fs::Permissions::from_mode(0o700))
+            .map_err(|err| ScriptExecutionError::WorkingDirectory(err.to_string()))?;
+        if config.run_as_uid.is_some() || config.run_as_gid.is_some() {
+            std::os::unix::fs::chown(&config.working_dir, config.run_as_uid, config.run_as_gid)
+                .map_err(|err| ScriptExecutionError::WorkingDirectory(err.to_string()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Read a pipe to EOF keeping at most `max_bytes`; the first byte past the cap signals
+/// `overflow` and the remainder is only counted.
+async fn read_capped<R>(mut reader: R, max_bytes: usize, overflow: Arc<Notify>) -> (Vec<u8>, u64)
+where
+    R: AsyncRead + Unpin,
+{
+    let mut kept = Vec::new();
+    let mut dropped = 0_u64;
+    let mut chunk = [0_u8; 8192];
+    loop {
+        match reader.read(&mut chunk).await {
+            Ok(0) | Err(_) => break,
+            Ok(read) => {
+                let keep = max_bytes.saturating_sub(kept.len()).min(read);
+                kept.extend_from_slice(&chunk[..keep]);
+                if keep < read {
+                    dropped += (read - keep) as u64;
+                    overflow.notify_one();
+                }
+            }
+        }
+    }
+    (kept, dropped)
+}
+
+async fn collect_output(reader: Option<tokio::task::JoinHandle<(Vec<u8>, u64)>>) -> (Vec<u8>, u64) {
+    let Some(reader) = reader else {
+        return (Vec::new(), 0);
+    };
+    let abort = reader.abort_handle();
+    match tokio::time::timeout(PIPE_DRAIN_GRACE, reader).await {
+        Ok(Ok(output)) => output,
+        Ok(Err(_)) => (Vec::new(), 0),
+        Err(_) => {
+            abort.abort();
+            (Vec::new(), 0)
+        }
+    }
+}
+
+/// Ask the process group to stop, then kill whatever is left of it once the leader exits or
+/// `grace_ms` passes. The leader is only reaped after the group is killed: until then its
+/// zombie keeps the process group id from being reused by an unrelated group. Windows has no
+/// equivalent of SIGTERM for console processes, so it is killed at once.
+async fn terminate_tree(child: &mut Child, grace_ms: u64) {
+    #[cfg(unix)]
+    if let Some(pid) = child.id() {
+        // SAFETY: signalling the process group created for this child at spawn.
+        unsafe {
+            libc::kill(-(pid as libc::pid_t), libc::SIGTERM);
+        }
+        let deadline = tokio::time::Instant::now() + Duration::from_millis(grace_ms);
+        while tokio::time::Instant::now() < deadline && !leader_exited(pid) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = grace_ms;
+    kill_tree(child).await;
+}
+
+/// Whether the leader has exited, checked with `WNOWAIT` so it stays unreaped.
+#[cfg(unix)]
+fn leader_exited(pid: u32) -> bool {
+    // SAFETY: `info` is a plain C struct that waitid fills in; WNOWAIT leaves the child waitable.
+    unsafe {
+        let mut info: libc::siginfo_t = std::mem::zeroed();
+        let status = libc::waitid(
+            libc::P_PID,
+            pid as libc::id_t,
+            &mut info,
+            libc::WEXITED | libc::WNOHANG | libc::WNOWAIT,
+        );
+        status != 0 || info.si_pid() != 0
+    }
+}
+
+async fn kill_tree(child: &mut Child) {
+    #[cfg(unix)]
+    if let Some(pid) = child.id() {
+        // SAFETY: signalling the process group created for this child at spawn.
+        unsafe {
+            libc::kill(-(pid as libc::pid_t), libc::SIGKILL);
+        }
+    }
+    let _ = child.start_kill();
+    let _ = child.wait().await;
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::path::PathBuf;
+
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::{run_script, ScriptExecutionError, ScriptExecutorConfig};
+    use crate::command_router::{command_signing_message, CommandRouteConfig};
+    use crate::crypto::{HmacSha256, Signer};
+    use crate::policy::{ArgumentRules, PolicyBundle};
+    use crate::rmm::{CancelSignal, ExecutionRequest, Termination};
+    use crate::time::unix_time_ms;
+
+    fn build_config(label: &str, timeout_ms: u64, max_output_bytes: usize) -> ScriptExecutorConfig {
+        ScriptExecutorConfig {
+            interpreter: Some(PathBuf::from("/bin/sh")),
+            working_dir: std::env::temp_dir().join(format!(
+                "executor-{}-{}-{}",
+                label,
+                std::process::id(),
+                unix_time_ms()
+            )),
+            timeout_ms,
+            max_output_bytes,
+            env_allowlist: vec!["PATH".to_string()],
+            run_as_uid: None,
+            run_as_gid: None,
+            cancel_grace_ms: 200,
+        }
+    }
+
+    fn shell_policy() -> PolicyBundle {
+        let mut policy = PolicyBundle::placeholder();
+        policy.execution.argument_rules = BTreeMap::from([(
+            "script-run".to_string(),
+            ArgumentRules {
+                allow_shell_metacharacters: true,
+                allow_env_expansion: true,
+                ..ArgumentRules::default()
+            },
+        )]);
+        policy
+    }
+
+    const KEY: &[u8] = b"unit-test-command-key";
+
+    fn route() -> CommandRouteConfig {
+        CommandRouteConfig::from_env().with_verifier(Arc::new(HmacSha256::new(KEY)))
+    }
+
+    fn build_request(action: &str, script: &str) -> ExecutionRequest {
+        let mut request = ExecutionRequest {
+            command_id: "cmd-1".to_string(),
+            signed_payload: "signed".to_string(),
+            signature: String::new(),
+            action: action.to_string(),
+            arguments: vec!["-c".to_string(), script.to_string()],
+            requested_at_unix_ms: 0,
+            expires_at_unix_ms: u64::MAX,
+            earliest_start_unix_ms: None,
+            latest_start_unix_ms: None,
+            source: "test".to_string(),
+        };
+        request.signature = HmacSha256::new(KEY).sign(command_signing_message(&request.signed_command()).as_bytes());
+        request
+    }
+
+    #[tokio::test]
+    async fn kills_script_on_timeout() {
+        let config = build_config("timeout", 200, 1024);
+        let outcome = run_script(&build_request("script-run", "echo started; sleep 30"), &shell_policy(), &route(), &config, &CancelSignal::default(), 1_000)
+            .await
+            .expect("script runs");
+
+        assert_eq!(outcome.termination, Termination::TimedOut);
+        assert_eq!(outcome.exit_code, None);
+        assert!(!outcome.success);
+        assert_eq!(outcome.stdout, "started\n");
+        assert!(outcome.finished_at_unix_ms - outcome.started_at_unix_ms < 10_000);
+
+        let _ = std::fs::remove_dir_all(&config.working_dir);
+    }
+
+    #[tokio::test]
+    async fn cancels_running_script_keeping_partial_output() {
+        let config = build_config("cancel", 30_000, 1024);
+        for script in ["echo started; sleep 30", "trap '' TERM; echo started; sleep 30"] {
+            let cancel = CancelSignal::default();
+            let trigger = cancel.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(300)).await;
+                trigger.cancel();
+            });
+            let outcome = run_script(&build_request("script-run", script), &shell_policy(), &route(), &config, &cancel, 1_000)
+                .await
+                .expect("script runs");
+
+            assert_eq!(outcome.termination, Termination::Cancelled, "{}", script);
+            assert_eq!(outcome.exit_code, None);
+            assert!(!outcome.success);
+            assert_eq!(outcome.stdout, "started\n");
+            assert!(outcome.finished_at_unix_ms - outcome.started_at_unix_ms < 10_000);
+        }
+
+        let cancel = CancelSignal::default();
+        cancel.cancel();
+        let outcome = run_script(&build_request("script-run", "echo never"), &shell_policy(), &route(), &config, &cancel, 1_000)
+            .await
+            .expect("cancelled before start");
+        assert_eq!(outcome.termination, Termination::Cancelled);
+        assert_eq!(outcome.stdout, "");
+
+        let _ = std::fs::remove_dir_all(&config.working_dir);
+    }
+
+    #[tokio::test]
+    async fn cancelling_kills_descendants_that_ignore_sigterm() {
+        let config = build_config("descendants", 30_000, 1024);
+        let cancel = CancelSignal::default();
+        let trigger = cancel.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            trigger.cancel();
+        });
+        let script = "(trap '' TERM; sleep 30) & echo $! > straggler.pid; echo started; wait";
+        let outcome = run_script(&build_request("script-run", script), &shell_policy(), &route(), &config, &cancel, 1_000)
+            .await
+            .expect("script runs");
+        assert_eq!(outcome.termination, Termination::Cancelled);
+
+        let pid = std::fs::read_to_string(config.working_dir.join("straggler.pid"))
+            .expect("pid file")
+            .trim()
+            .parse::<libc::pid_t>()
+            .expect("pid");
+        let mut alive = true;
+        for _ in 0..100 {
+            // SAFETY: signal 0 only checks whether the process still exists.
+            alive = unsafe { libc::kill(pid, 0) } == 0;
+            if !alive {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(!alive, "background process {} outlived its group", pid);
+
+        let _ = std::fs::remove_dir_all(&config.working_dir);
+    }
+
+    #[tokio::test]
+    async fn kills_and_truncates_runaway_output() {
+        let config = build_config("output", 30_000, 4096);
+        let outcome = run_script(&build_request("script-run", "yes"), &shell_policy(), &route(), &config, &CancelSignal::default(), 1_000)
+            .await
+            .expect("script runs");
+
+        assert_eq!(outcome.termination, Termination::OutputLimit);
+        assert_eq!(outcome.stdout.len(), 4096);
+        assert!(outcome.stdout_truncated_bytes > 0);
+        assert!(!outcome.success);
+
+        let _ = std::fs::remove_dir_all(&config.working_dir);
+    }
+
+    #[tokio::test]
+    async fn scrubs_environment_and_sets_working_dir() {
+        let config = build_config("env", 30_000, 64 * 1024);
+        let outcome = run_script(&build_request("script-run", "env; pwd"), &shell_policy(), &route(), &config, &CancelSignal::default(), 1_000)
+            .await
+            .expect("script runs");
+
+        assert!(outcome.success);
+        assert_eq!(outcome.termination, Termination::Exited);
+        let lines = outcome.stdout.lines().collect::<Vec<&str>>();
+        assert!(lines.iter().any(|line| line.starts_with("PATH=")));
+        assert!(!lines.iter().any(|line| line.starts_with("HOME=") || line.starts_with("USER=")));
+        let working_dir = config.working_dir.canonicalize().expect("working dir");
+        assert_eq!(lines.last().map(PathBuf::from), Some(working_dir));
+
+        let _ = std::fs::remove_dir_all(&config.working_dir);
+    }
+
+    #[tokio::test]
+    async fn refuses_requests_the_policy_does_not_allow() {
+        let config = build_config("refused", 30_000, 1024);
+        let unsupported = run_script(&build_request("patch-apply", "true"), &shell_policy(), &route(), &config, &CancelSignal::default(), 1_000).await;
+        assert_eq!(unsupported, Err(ScriptExecutionError::UnsupportedAction("patch-apply".to_string())));
+
+        let strict = run_script(&build_request("script-run", "echo hi; true"), &PolicyBundle::placeholder(), &route(), &config, &CancelSignal::default(), 1_000).await;
+        assert!(matches!(strict, Err(ScriptExecutionError::NotPermitted(_))));
+
+        let mut revoked = shell_policy();
+        revoked.execution.allowed_actions = vec!["patch-apply".to_string()];
+        let refused = run_script(&build_request("script-run", "true"), &revoked, &route(), &config, &CancelSignal::default(), 1_000).await;
+        assert!(matches!(refused, Err(ScriptExecutionError::NotPermitted(reason)) if reason.contains("not permitted")));
+
+        let mut unsigned = build_request("script-run", "true");
+        unsigned.signature.clear();
+        let unsigned = run_script(&unsigned, &shell_policy(), &route(), &config, &CancelSignal::default(), 1_000).await;
+        assert!(matches!(unsigned, Err(ScriptExecutionError::NotPermitted(reason)) if reason.contains("signature")));
+
+        let relative = ScriptExecutorConfig {
+            interpreter: Some(PathBuf::from("sh")),
+            ..config.clone()
+        };
+        let rejected = run_script(&build_request("script-run", "true"), &shell_policy(), &route(), &relative, &CancelSignal::default(), 1_000).await;
+        assert!(matches!(rejected, Err(ScriptExecutionError::InterpreterUnavailable(_))));
+        assert!(!config.working_dir.exists());
+    }
+}
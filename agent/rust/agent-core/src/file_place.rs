@@ -0,0 +1,535 @@
+use std::env;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+
+use base64::engine::general_purpose::STANDARD as BASE64_STANDARD;
+use base64::Engine as _;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::command_router::{route_command_explain_with_config, CommandRouteConfig};
+use crate::policy::PolicyBundle;
+use crate::rmm::ExecutionRequest;
+
+pub const FILE_PLACE_ACTION: &str = "file-place";
+
+/// Placement carried in the signed payload of a `file-place` command. Exactly one of
+/// `source_url` and `content_base64` is set; inline content is bounded by the command
+/// payload limit, so larger files come from a URL.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FilePlacement {
+    #[serde(default)]
+    pub source_url: Option<String>,
+    #[serde(default)]
+    pub content_base64: Option<String>,
+    pub target_path: String,
+    /// Lowercase hex SHA-256 of the file content.
+    pub sha256: String,
+    /// Octal permission bits such as `"0644"`; Unix only.
+    #[serde(default)]
+    pub mode: Option<String>,
+    #[serde(default)]
+    pub owner_uid: Option<u32>,
+    #[serde(default)]
+    pub owner_gid: Option<u32>,
+    /// Keep a copy of an existing target as `<target>.bak-<unix ms>`.
+    #[serde(default)]
+    pub backup: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct FilePlaceConfig {
+    pub max_bytes: u64,
+    pub download_timeout_ms: u64,
+}
+
+impl FilePlaceConfig {
+    pub fn from_env() -> Self {
+        let max_bytes = env::var("FILE_PLACE_MAX_BYTES")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .filter(|value| *value > 0)
+            .unwrap_or(16 * 1024 * 1024);
+        let download_timeout_ms = env::var("FILE_PLACE_DOWNLOAD_TIMEOUT_SECS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .filter(|value| *value > 0)
+            .unwrap_or(60)
+            .saturating_mul(1000);
+        Self {
+            max_bytes,
+            download_timeout_ms,
+        }
+    }
+}
+
+/// Filesystem step that can fail after the backup is taken; swapped out in tests.
+pub trait PlacementFs: Send + Sync {
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StdPlacementFs;
+
+impl PlacementFs for StdPlacementFs {
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::rename(from, to)
+    }
+}
+
+/// Outcome reported for a completed placement.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PlacementReport {
+    pub command_id: String,
+    pub target_path: String,
+    pub sha256: String,
+    pub bytes: u64,
+    /// Whether a file already existed at the target.
+    pub replaced: bool,
+    pub backup_path: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FilePlaceError {
+    UnsupportedAction(String),
+    NotPermitted(String),
+    InvalidPlacement(String),
+    /// The target resolves outside every `execution.file_destinations` entry.
+    DestinationNotAllowed(String),
+    Fetch(String),
+    HashMismatch { expected: String, actual: String },
+    /// A filesystem step failed; anything written so far has been undone.
+    Io(String),
+}
+
+impl fmt::Display for FilePlaceError {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnsupportedAction(action) => write!(formatter, "action {} is not a file placement", action),
+            Self::NotPermitted(reasons) => write!(formatter, "command not permitted by policy: {}", reasons),
+            Self::InvalidPlacement(reason) => write!(formatter, "file placement invalid: {}", reason),
+            Self::DestinationNotAllowed(path) => write!(formatter, "destination {} is not allowed by policy", path),
+            Self::Fetch(reason) => write!(formatter, "failed to fetch file content: {}", reason),
+            Self::HashMismatch { expected, actual } => {
+                write!(formatter, "content sha256 {} does not match expected {}", actual, expected)
+            }
+            Self::Io(reason) => write!(formatter, "file placement failed: {}", reason),
+        }
+    }
+}
+
+/// Parse and validate the placement carried by a `file-place` request.
+pub fn parse_placement(request: &ExecutionRequest) -> Result<FilePlacement, FilePlaceError> {
+    let placement = serde_json::from_str::<FilePlacement>(&request.signed_payload)
+        .map_err(|err| FilePlaceError::InvalidPlacement(err.to_string()))?;
+    match (&placement.source_url, &placement.content_base64) {
+        (Some(url), None) => {
+            if !url.starts_with("https://") {
+                return Err(FilePlaceError::InvalidPlacement("source_url must use https".to_string()));
+            }
+        }
+        (None, Some(_)) => {}
+        _ => {
+            return Err(FilePlaceError::InvalidPlacement(
+                "exactly one of source_url and content_base64 is required".to_string(),
+            ))
+        }
+    }
+    if placement.sha256.len() != 64
+        || !placement
+            .sha256
+            .chars()
+            .all(|ch| ch.is_ascii_digit() || ('a'..='f').contains(&ch))
+    {
+        return Err(FilePlaceError::InvalidPlacement("sha256 must be 64 lowercase hex digits".to_string()));
+    }
+    let target = Path::new(&placement.target_path);
+    if !target.is_absolute()
+        || target.file_name().is_none()
+        || target
+            .components()
+            .any(|component| matches!(component, Component::CurDir | Component::ParentDir))
+    {
+        return Err(FilePlaceError::InvalidPlacement(format!(
+            "target_path {:?} must be an absolute file path without . or ..",
+            placement.target_path
+        )));
+    }
+    if let Some(mode) = &placement.mode {
+        parse_mode(mode)?;
+    }
+    Ok(placement)
+}
+
+/// Only the rwx bits may be set; setuid, setgid and sticky are refused so a placed file can
+/// never become a privileged binary.
+fn parse_mode(mode: &str) -> Result<u32, FilePlaceError> {
+    u32::from_str_radix(mode, 8)
+        .ok()
+        .filter(|bits| *bits <= 0o777)
+        .ok_or_else(|| FilePlaceError::InvalidPlacement(format!("mode {:?} is not octal rwx permission bits", mode)))
+}
+
+/// Resolve the target's directory through symlinks and require it to sit inside one of the
+/// policy's destinations, themselves resolved the same way.
+fn checked_target(policy: &PolicyBundle, target_path: &str) -> Result<PathBuf, FilePlaceError> {
+    let not_allowed = || FilePlaceError::DestinationNotAllowed(target_path.to_string());
+    let target = Path::new(target_path);
+    let parent = target
+        .parent()
+        .and_then(|parent| parent.canonicalize().ok())
+        .ok_or_else(not_allowed)?;
+    let allowed = policy
+        .execution
+        .file_destinations
+        .iter()
+        .filter_map(|destination| Path::new(destination).canonicalize().ok())
+        .any(|destination| parent.starts_with(destination));
+    if !allowed {
+        return Err(not_allowed());
+    }
+    let file_name = target.file_name().ok_or_else(not_allowed)?;
+    let resolved = parent.join(file_name);
+    if resolved.is_dir() || fs::symlink_metadata(&resolved).map(|meta| meta.file_type().is_symlink()).unwrap_or(false) {
+        return Err(FilePlaceError::InvalidPlacement(format!("target {} is a directory or symlink", target_path)));
+    }
+    Ok(resolved)
+}
+
+/// Place the file described by a `file-place` request: fetch or decode the content, verify
+/// its hash, stage it next to the target, back up any existing file and rename it into
+/// place. On failure the target is left as it was and staged or backup files are removed.
+pub async fn place_file(
+    request: &ExecutionRequest,
+    policy: &PolicyBundle,
+    route: &CommandRouteConfig,
+    config: &FilePlaceConfig,
+    placement_fs: &dyn PlacementFs,
+    now_unix_ms: u64,
+) -> Result<PlacementReport, FilePlaceError> {
+    if request.action != FILE_PLACE_ACTION {
+        return Err(FilePlaceError::UnsupportedAction(request.action.clone()));
+    }
+    let decision = route_command_explain_with_config(&request.signed_command(), policy, route, now_unix_ms);
+    if !decision.allowed() {
+        let reasons = decision
+            .results
+            .iter()
+            .filter_map(|result| result.reason.clone())
+            .collect::<Vec<String>>();
+        return Err(FilePlaceError::NotPermitted(reasons.join("; ")));
+    }
+    let placement = parse_placement(request)?;
+    let target = checked_target(policy, &placement.target_path)?;
+
+    let content = match (&placement.source_url, &placement.content_base64) {
+        (Some(url), _) => download(url, config).await?,
+        (None, Some(encoded)) => BASE64_STANDARD
+            .decode(encoded.trim())
+            .map_err(|err| FilePlaceError::InvalidPlacement(format!("content_base64: {}", err)))?,
+        (None, None) => unreachable!("parse_placement requires a source"),
+    };
+    if content.len() as u64 > config.max_bytes {
+        return Err(FilePlaceError::Fetch(format!("content exceeds {} bytes", config.max_bytes)));
+    }
+    let actual = hex_sha256(&content);
+    if actual != placement.sha256 {
+        return Err(FilePlaceError::HashMismatch {
+            expected: placement.sha256.clone(),
+            actual,
+        });
+    }
+
+    let file_name = target.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+    let staged = target.with_file_name(format!(".{}.file-place-{}.tmp", file_name, now_unix_ms));
+    if let Err(err) = stage(&staged, &content, &placement) {
+        let _ = fs::remove_file(&staged);
+        return Err(FilePlaceError::Io(format!("stage {}: {}", staged.display(), err)));
+    }
+
+    let replaced = target.exists();
+    let backup = if replaced && placement.backup {
+        let backup = target.with_file_name(format!("{}.bak-{}", file_name, now_unix_ms));
+        if let Err(err) = fs::copy(&target, &backup) {
+            let _ = fs::remove_file(&staged);
+            let _ = fs::remove_file(&backup);
+            return Err(FilePlaceError::Io(format!("back up {}: {}", target.display(), err)));
+        }
+        Some(backup)
+    } else {
+        None
+    };
+
+    // The original stays in place until the rename, so undoing means dropping what we added.
+    if let Err(err) = placement_fs.rename(&staged, &target) {
+        let _ = fs::remove_file(&staged);
+        if let Some(backup) = &backup {
+            let _ = fs::remove_file(backup);
+        }
+        return Err(FilePlaceError::Io(format!("rename into {}: {}", target.display(), err)));
+    }
+
+    Ok(PlacementReport {
+        command_id: request.command_id.clone(),
+        target_path: target.display().to_string(),
+        sha256: actual,
+        bytes: content.len() as u64,
+        replaced,
+        backup_path: backup.map(|backup| backup.display().to_string()),
+    })
+}
+
+/// Write, chmod/chown and fsync the staged copy; it is only renamed once fully on disk.
+fn stage(path: &Path, content: &[u8], placement: &FilePlacement) -> io::Result<()> {
+    let mut file = OpenOptions::new().write(true).create_new(true).open(path)?;
+    file.write_all(content)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        if let Some(mode) = placement.mode.as_deref().and_then(|mode| parse_mode(mode).ok()) {
+            file.set_permissions(fs::Permissions::from_mode(mode))?;
+        }
+        if placement.owner_uid.is_some() || placement.owner_gid.is_some() {
+            std::os::unix::fs::chown(path, placement.owner_uid, placement.owner_gid)?;
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = placement;
+    file.sync_all()?;
+    drop(file);
+    if let Some(parent) = path.parent() {
+        // Best effort: persist the directory entry where the platform allows opening dirs.
+        let _ = File::open(parent).and_then(|dir| dir.sync_all());
+    }
+    Ok(())
+}
+
+/// Fetch `url`, refusing bodies over `config.max_bytes` without buffering past the cap.
+async fn download(url: &str, config: &FilePlaceConfig) -> Result<Vec<u8>, FilePlaceError> {
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_millis(config.download_timeout_ms))
+        .build()
+        .map_err(|err| FilePlaceError::Fetch(err.to_string()))?;
+    let mut response = client
+        .get(url)
+        .send()
+        .await
+        .map_err(|err| FilePlaceError::Fetch(err.to_string()))?;
+    if !response.status().is_success() {
+        return Err(FilePlaceError::Fetch(format!("{} returned {}", url, response.status())));
+    }
+    let mut content = Vec::new();
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|err| FilePlaceError::Fetch(err.to_string()))?
+    {
+        if content.len() as u64 + chunk.len() as u64 > config.max_bytes {
+            return Err(FilePlaceError::Fetch(format!("content exceeds {} bytes", config.max_bytes)));
+        }
+        content.extend_from_slice(&chunk);
+    }
+    Ok(content)
+}
+
+fn hex_sha256(content: &[u8]) -> String {
+    Sha256::digest(content)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+
+    use base64::engine::general_purpose::STANDARD as BASE64_STANDARD;
+    use base64::Engine as _;
+
+    use super::{hex_sha256, parse_placement, place_file, FilePlaceConfig, FilePlaceError, PlacementFs, StdPlacementFs};
+    use crate::command_router::{command_signing_message, CommandRouteConfig};
+    use crate::crypto::{HmacSha256, Signer};
+    use crate::policy::PolicyBundle;
+    use crate::rmm::ExecutionRequest;
+    use crate::time::unix_time_ms;
+
+    struct FailingRename;
+
+    impl PlacementFs for FailingRename {
+        fn rename(&self, _from: &Path, _to: &Path) -> io::Result<()> {
+            Err(io::Error::other("simulated rename failure"))
+        }
+    }
+
+    fn temp_root(label: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("file-place-{}-{}-{}", label, std::process::id(), unix_time_ms()));
+        std::fs::create_dir_all(root.join("allowed")).expect("create allowed dir");
+        std::fs::create_dir_all(root.join("other")).expect("create other dir");
+        root
+    }
+
+    fn policy_for(root: &Path) -> PolicyBundle {
+        let mut policy = PolicyBundle::placeholder();
+        policy.execution.file_destinations = vec![root.join("allowed").display().to_string()];
+        policy
+    }
+
+    fn config() -> FilePlaceConfig {
+        FilePlaceConfig {
+            max_bytes: 1024,
+            download_timeout_ms: 1_000,
+        }
+    }
+
+    const KEY: &[u8] = b"unit-test-command-key";
+
+    fn route() -> CommandRouteConfig {
+        CommandRouteConfig::from_env().with_verifier(Arc::new(HmacSha256::new(KEY)))
+    }
+
+    fn build_request(target: &Path, content: &[u8], sha256: &str, backup: bool) -> ExecutionRequest {
+        let payload = serde_json::json!({
+            "content_base64": BASE64_STANDARD.encode(content),
+            "target_path": target.display().to_string(),
+            "sha256": sha256,
+            "mode": "0640",
+            "backup": backup,
+        });
+        let mut request = ExecutionRequest {
+            command_id: "cmd-file".to_string(),
+            signed_payload: payload.to_string(),
+            signature: String::new(),
+            action: "file-place".to_string(),
+            arguments: Vec::new(),
+            requested_at_unix_ms: 0,
+            expires_at_unix_ms: u64::MAX,
+            earliest_start_unix_ms: None,
+            latest_start_unix_ms: None,
+            source: "test".to_string(),
+        };
+        request.signature = HmacSha256::new(KEY).sign(command_signing_message(&request.signed_command()).as_bytes());
+        request
+    }
+
+    fn entries(dir: &Path) -> Vec<String> {
+        let mut names = std::fs::read_dir(dir)
+            .expect("read dir")
+            .flatten()
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .collect::<Vec<String>>();
+        names.sort();
+        names
+    }
+
+    #[tokio::test]
+    async fn places_file_and_backs_up_existing() {
+        let root = temp_root("ok");
+        let target = root.join("allowed").join("agent.conf");
+        std::fs::write(&target, b"old").expect("seed target");
+
+        let request = build_request(&target, b"new config", &hex_sha256(b"new config"), true);
+        let report = place_file(&request, &policy_for(&root), &route(), &config(), &StdPlacementFs, 5_000)
+            .await
+            .expect("placed");
+        assert!(report.replaced);
+        assert_eq!(report.bytes, 10);
+        assert_eq!(std::fs::read(&target).expect("target"), b"new config");
+        assert_eq!(std::fs::read(root.join("allowed").join("agent.conf.bak-5000")).expect("backup"), b"old");
+        assert_eq!(entries(&root.join("allowed")), vec!["agent.conf", "agent.conf.bak-5000"]);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&target).expect("metadata").permissions().mode();
+            assert_eq!(mode & 0o7777, 0o640);
+        }
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    async fn rejects_hash_mismatch_without_touching_target() {
+        let root = temp_root("hash");
+        let target = root.join("allowed").join("agent.conf");
+        std::fs::write(&target, b"old").expect("seed target");
+
+        let request = build_request(&target, b"tampered", &hex_sha256(b"expected"), true);
+        let result = place_file(&request, &policy_for(&root), &route(), &config(), &StdPlacementFs, 5_000).await;
+        assert!(matches!(result, Err(FilePlaceError::HashMismatch { .. })));
+        assert_eq!(std::fs::read(&target).expect("target"), b"old");
+        assert_eq!(entries(&root.join("allowed")), vec!["agent.conf"]);
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    async fn rejects_destinations_outside_policy() {
+        let root = temp_root("dest");
+        let policy = policy_for(&root);
+        let outside = root.join("other").join("agent.conf");
+        let request = build_request(&outside, b"x", &hex_sha256(b"x"), false);
+        let result = place_file(&request, &policy, &route(), &config(), &StdPlacementFs, 5_000).await;
+        assert_eq!(result, Err(FilePlaceError::DestinationNotAllowed(outside.display().to_string())));
+        assert!(entries(&root.join("other")).is_empty());
+
+        let traversal = PathBuf::from(format!("{}/allowed/../other/agent.conf", root.display()));
+        let request = build_request(&traversal, b"x", &hex_sha256(b"x"), false);
+        assert!(matches!(
+            place_file(&request, &policy, &route(), &config(), &StdPlacementFs, 5_000).await,
+            Err(FilePlaceError::InvalidPlacement(_))
+        ));
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(root.join("other"), root.join("allowed").join("escape")).expect("symlink");
+            let request = build_request(&root.join("allowed").join("escape").join("agent.conf"), b"x", &hex_sha256(b"x"), false);
+            assert!(matches!(
+                place_file(&request, &policy, &route(), &config(), &StdPlacementFs, 5_000).await,
+                Err(FilePlaceError::DestinationNotAllowed(_))
+            ));
+        }
+        let request = build_request(&root.join("allowed").join("agent.conf"), b"x", &hex_sha256(b"x"), false);
+        assert!(matches!(
+            place_file(&request, &PolicyBundle::placeholder(), &route(), &config(), &StdPlacementFs, 5_000).await,
+            Err(FilePlaceError::DestinationNotAllowed(_))
+        ));
+        assert!(entries(&root.join("other")).is_empty());
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn refuses_setuid_setgid_and_sticky_modes() {
+        let target = PathBuf::from("/opt/tamsil/tool");
+        for mode in ["4755", "2755", "1777", "7777", "10644"] {
+            let mut request = build_request(&target, b"x", &hex_sha256(b"x"), false);
+            request.signed_payload = request.signed_payload.replace("\"0640\"", &format!("\"{}\"", mode));
+            assert!(matches!(parse_placement(&request), Err(FilePlaceError::InvalidPlacement(_))), "{}", mode);
+        }
+        let request = build_request(&target, b"x", &hex_sha256(b"x"), false);
+        assert_eq!(parse_placement(&request).expect("rwx mode").mode.as_deref(), Some("0640"));
+    }
+
+    #[tokio::test]
+    async fn restores_original_state_when_rename_fails() {
+        let root = temp_root("rename");
+        let target = root.join("allowed").join("agent.conf");
+        std::fs::write(&target, b"old").expect("seed target");
+
+        let request = build_request(&target, b"new config", &hex_sha256(b"new config"), true);
+        let result = place_file(&request, &policy_for(&root), &route(), &config(), &FailingRename, 5_000).await;
+        match result {
+            Err(FilePlaceError::Io(reason)) => assert!(reason.contains("simulated rename failure")),
+            other => panic!("expected io failure, got {:?}", other),
+        }
+        assert_eq!(std::fs::read(&target).expect("target"), b"old");
+        assert_eq!(entries(&root.join("allowed")), vec!["agent.conf"]);
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
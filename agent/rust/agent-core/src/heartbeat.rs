@@ -0,0 +1,285 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::clock_drift::{drift_report, DriftReport};
+use crate::identity::AgentIdentity;
+use crate::metrics::MetricsHandle;
+use crate::pipeline::{HealthReport, HealthState};
+use crate::self_monitor::{DegradationState, SharedDegradation};
+use crate::time::unix_time_ms;
+use crate::uplink::{build_client, pending_item_count, post_or_enqueue_mtls_rmm, UplinkConfig};
+
+const HEARTBEAT_PATH: &str = "/heartbeat";
+/// Fixed queue item name, so an undelivered heartbeat replaces the one queued before it
+/// and an outage leaves only the latest in the queue.
+const HEARTBEAT_ITEM_NAME: &str = "heartbeat";
+
+#[derive(Debug, Clone)]
+pub struct HeartbeatConfig {
+    pub interval_secs: u64,
+}
+
+impl HeartbeatConfig {
+    pub fn from_env() -> Self {
+        let interval_secs = env::var("HEARTBEAT_INTERVAL_SECS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .filter(|value| *value > 0)
+            .unwrap_or(30);
+        Self { interval_secs }
+    }
+}
+
+/// Liveness document sent to the backend on every heartbeat tick.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AgentHeartbeat {
+    pub asset_id: String,
+    pub agent_id: String,
+    pub agent_version: String,
+    pub pipeline: HealthReport,
+    pub uptime_secs: u64,
+    pub queue_depth: usize,
+    pub last_uplink_success_unix_ms: Option<u64>,
+    pub resources: DegradationState,
+    /// Reported only; never applied to time-window checks.
+    pub clock_drift: DriftReport,
+    pub sent_at_unix_ms: u64,
+}
+
+pub fn build_heartbeat(
+    identity: &AgentIdentity,
+    pipeline: &HealthReport,
+    started_at_unix_ms: u64,
+    queue_depth: usize,
+    last_uplink_success_unix_ms: Option<u64>,
+    resources: &DegradationState,
+    now_unix_ms: u64,
+) -> AgentHeartbeat {
+    AgentHeartbeat {
+        asset_id: identity.asset_id.clone(),
+        agent_id: identity.agent_id.clone(),
+        agent_version: env!("CARGO_PKG_VERSION").to_string(),
+        pipeline: pipeline.clone(),
+        uptime_secs: now_unix_ms.saturating_sub(started_at_unix_ms) / 1000,
+        queue_depth,
+        last_uplink_success_unix_ms,
+        resources: resources.clone(),
+        clock_drift: DriftReport::default(),
+        sent_at_unix_ms: now_unix_ms,
+    }
+}
+
+/// Local liveness record written on every heartbeat tick so agent-watchdog can check the
+/// core without an IPC session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct LivenessRecord {
+    pub unix_time_ms: u64,
+    /// False once any pipeline component has failed; degraded components still count as
+    /// ready, since a restart would not fix an unreachable backend.
+    pub pipeline_ready: bool,
+}
+
+/// Replace the liveness file atomically, so the watchdog never reads a partial record.
+pub fn write_liveness_file(path: &Path, pipeline: &HealthReport, now_unix_ms: u64) -> io::Result<()> {
+    let record = LivenessRecord {
+        unix_time_ms: now_unix_ms,
+        pipeline_ready: !pipeline
+            .components
+            .iter()
+            .any(|report| report.health.state == HealthState::Failed),
+    };
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    let staging = path.with_extension("tmp");
+    fs::write(&staging, serde_json::to_vec(&record)?)?;
+    fs::rename(&staging, path)
+}
+
+/// Sends heartbeats over its own pooled uplink client; undelivered heartbeats are left in the
+/// uplink queue for the worker to retry.
+#[derive(Debug)]
+pub struct HeartbeatSender {
+    identity: AgentIdentity,
+    uplink: UplinkConfig,
+    client: reqwest::Client,
+    metrics: MetricsHandle,
+    started_at_unix_ms: u64,
+    resources: SharedDegradation,
+}
+
+impl HeartbeatSender {
+    pub fn new(identity: AgentIdentity, uplink: UplinkConfig, metrics: MetricsHandle, started_at_unix_ms: u64) -> Self {
+        let client = build_client(&uplink);
+        Self {
+            identity,
+            uplink,
+            client,
+            metrics,
+            started_at_unix_ms,
+            resources: SharedDegradation::default(),
+        }
+    }
+
+    /// Report the self-monitor's latest resource verdict with each heartbeat.
+    pub fn with_resources(mut self, resources: SharedDegradation) -> Self {
+        self.resources = resources;
+        self
+    }
+
+    pub async fn send(&self, pipeline: &HealthReport) -> bool {
+        let now = unix_time_ms();
+        let last_success = Some(self.metrics.uplink_last_success_unix_ms.get()).filter(|value| *value > 0);
+        let resources = self.resources.lock().map(|state| state.clone()).unwrap_or_default();
+        let mut heartbeat = build_heartbeat(
+            &self.identity,
+            pipeline,
+            self.started_at_unix_ms,
+            pending_item_count(&self.uplink.queue_dir),
+            last_success,
+            &resources,
+            now,
+        );
+        heartbeat.clock_drift = drift_report();
+        let payload_json = match serde_json::to_string(&heartbeat) {
+            Ok(value) => value,
+            Err(_) => return false,
+        };
+        let delivered = post_or_enqueue_mtls_rmm(
+            &self.client,
+            &self.uplink,
+            HEARTBEAT_PATH,
+            &payload_json,
+            HEARTBEAT_ITEM_NAME,
+        )
+        .await;
+        if delivered {
+            self.metrics.heartbeat_last_delivered_unix_ms.set(now);
+        }
+        delivered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{build_heartbeat, write_liveness_file, HeartbeatSender};
+    use crate::identity::AgentIdentity;
+    use crate::metrics::AgentMetrics;
+    use crate::pipeline::{ComponentHealth, PipelineHealth};
+    use crate::self_monitor::{evaluate, ResourceSample, SelfMonitorConfig};
+    use crate::state_dir::StatePaths;
+    use crate::time::unix_time_ms;
+    use crate::uplink::{pending_item_count, UplinkConfig, UplinkSummary};
+
+    #[test]
+    fn builds_heartbeat_payload() {
+        let identity = AgentIdentity::new("asset-1".to_string(), "agent-1".to_string());
+        let mut health = PipelineHealth::new();
+        health.register("policy", |_| ComponentHealth::ready());
+        health.register("uplink", |_| ComponentHealth::degraded("last uplink cycle 90000 ms ago"));
+        let pipeline = health.report(1_700_000_090_000);
+        let metrics = AgentMetrics::new_handle();
+        metrics.record_uplink_summary(&UplinkSummary {
+            processed: 2,
+            succeeded: 2,
+            failed: 0,
+            quarantined: 0,
+            deferred: 0,
+            circuits: std::collections::BTreeMap::new(),
+            tenants: std::collections::BTreeMap::new(),
+            oldest_pending_age_ms: 0,
+            completed_at_unix_ms: 1_700_000_050_000,
+        });
+
+        let resources = evaluate(
+            ResourceSample {
+                rss_bytes: Some(900 * 1024 * 1024),
+                ..ResourceSample::default()
+            },
+            &SelfMonitorConfig::from_env(),
+            1_700_000_090_000,
+        );
+
+        let heartbeat = build_heartbeat(
+            &identity,
+            &pipeline,
+            1_700_000_000_000,
+            3,
+            Some(metrics.uplink_last_success_unix_ms.get()),
+            &resources,
+            1_700_000_090_500,
+        );
+        let payload = serde_json::to_value(&heartbeat).expect("heartbeat json");
+
+        assert_eq!(payload["asset_id"], "asset-1");
+        assert_eq!(payload["agent_id"], "agent-1");
+        assert_eq!(payload["agent_version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(payload["pipeline"]["overall"], "degraded");
+        assert_eq!(payload["pipeline"]["components"][0]["component"], "policy");
+        assert_eq!(payload["pipeline"]["components"][1]["state"], "degraded");
+        assert_eq!(payload["uptime_secs"], 90);
+        assert_eq!(payload["queue_depth"], 3);
+        assert_eq!(payload["last_uplink_success_unix_ms"], 1_700_000_050_000_u64);
+        assert_eq!(payload["resources"]["breaches"][0]["metric"], "rss_bytes");
+        assert_eq!(payload["sent_at_unix_ms"], 1_700_000_090_500_u64);
+    }
+
+    #[tokio::test]
+    async fn queues_heartbeat_when_delivery_fails() {
+        let mut uplink = UplinkConfig::from_env_unchecked(&StatePaths::under(&std::env::temp_dir()));
+        uplink.rmm_mtls_base_endpoint = "http://127.0.0.1:1/mtls/rmm".to_string();
+        uplink.queue_dir = std::env::temp_dir().join(format!(
+            "heartbeat-queue-{}-{}",
+            std::process::id(),
+            unix_time_ms()
+        ));
+        let identity = AgentIdentity::new("asset-1".to_string(), "agent-1".to_string());
+        let sender = HeartbeatSender::new(identity, uplink.clone(), AgentMetrics::new_handle(), unix_time_ms());
+
+        assert!(!sender.send(&PipelineHealth::new().report(unix_time_ms())).await);
+        assert!(!sender.send(&PipelineHealth::new().report(unix_time_ms())).await);
+        assert_eq!(pending_item_count(&uplink.queue_dir), 1, "undelivered heartbeats coalesce");
+        let item = std::fs::read_dir(&uplink.queue_dir)
+            .expect("queue dir")
+            .flatten()
+            .next()
+            .expect("queued heartbeat");
+        let raw = std::fs::read_to_string(item.path()).expect("read item");
+        let value: serde_json::Value = serde_json::from_str(&raw).expect("item json");
+        assert_eq!(value["kind"], "mtls_rmm");
+        assert_eq!(value["path"], "/heartbeat");
+        let payload: serde_json::Value =
+            serde_json::from_str(value["payload_json"].as_str().expect("payload")).expect("payload json");
+        assert_eq!(payload["asset_id"], "asset-1");
+
+        let _ = std::fs::remove_dir_all(&uplink.queue_dir);
+    }
+
+    #[test]
+    fn rewrites_liveness_file_each_tick() {
+        let path = std::env::temp_dir()
+            .join(format!("heartbeat-liveness-{}-{}", std::process::id(), unix_time_ms()))
+            .join("heartbeat.json");
+        let mut health = PipelineHealth::new();
+        health.register("policy", |_| ComponentHealth::ready());
+        health.register("uplink", |_| ComponentHealth::degraded("backend unreachable"));
+
+        write_liveness_file(&path, &health.report(1_000), 1_000).expect("first write");
+        let first: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).expect("read liveness")).expect("liveness json");
+        assert_eq!(first["pipeline_ready"], true);
+        health.register("ipc", |_| ComponentHealth::failed("ipc listener not bound"));
+        write_liveness_file(&path, &health.report(31_000), 31_000).expect("second write");
+
+        let record: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).expect("read liveness")).expect("liveness json");
+        assert_eq!(record["unix_time_ms"], 31_000);
+        assert_eq!(record["pipeline_ready"], false);
+        assert!(!path.with_extension("tmp").exists());
+        let _ = std::fs::remove_dir_all(path.parent().expect("parent"));
+    }
+}
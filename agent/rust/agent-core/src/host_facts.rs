@@ -0,0 +1,229 @@
+use std::env;
+use std::fs;
+use std::net::{IpAddr, UdpSocket};
+use std::sync::{Mutex, OnceLock};
+
+use crate::security::{validate_bounded_string, ValidationLimits};
+use crate::siem::TelemetryField;
+use crate::time::unix_time_ms;
+
+/// Host context attached to outgoing telemetry and evidence so the control plane does not
+/// have to join against inventory to interpret an `asset_id`.
+#[derive(Debug, Clone)]
+pub struct HostFacts {
+    pub os_name: String,
+    pub os_version: String,
+    pub hostname: String,
+    pub primary_ip: String,
+    pub boot_time_unix_ms: Option<u64>,
+    pub agent_version: String,
+    pub collected_at_unix_ms: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct HostFactsConfig {
+    pub ttl_ms: u64,
+    pub max_fact_len: usize,
+}
+
+impl HostFactsConfig {
+    pub fn from_env() -> Self {
+        let limits = ValidationLimits::default_limits();
+        let ttl_ms = env::var("HOST_FACTS_TTL_MS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .unwrap_or(300_000);
+        let max_fact_len = env::var("HOST_FACTS_MAX_LEN")
+            .ok()
+            .and_then(|value| value.parse::<usize>().ok())
+            .filter(|value| *value > 0)
+            .unwrap_or(limits.max_command_id_len);
+
+        Self { ttl_ms, max_fact_len }
+    }
+}
+
+impl HostFacts {
+    pub fn telemetry_fields(&self) -> Vec<TelemetryField> {
+        let mut fields = vec![
+            field("host.os_name", &self.os_name),
+            field("host.os_version", &self.os_version),
+            field("host.hostname", &self.hostname),
+            field("host.primary_ip", &self.primary_ip),
+            field("host.agent_version", &self.agent_version),
+        ];
+        if let Some(boot_time) = self.boot_time_unix_ms {
+            fields.push(field("host.boot_time_unix_ms", &boot_time.to_string()));
+        }
+        fields
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "os_name": self.os_name,
+            "os_version": self.os_version,
+            "hostname": self.hostname,
+            "primary_ip": self.primary_ip,
+            "boot_time_unix_ms": self.boot_time_unix_ms,
+            "agent_version": self.agent_version,
+        })
+    }
+}
+
+/// Cached host facts that are recollected once the TTL lapses.
+#[derive(Debug)]
+pub struct HostFactsCache {
+    config: HostFactsConfig,
+    cached: Option<HostFacts>,
+}
+
+impl HostFactsCache {
+    pub fn new(config: HostFactsConfig) -> Self {
+        Self { config, cached: None }
+    }
+
+    pub fn get(&mut self, now_unix_ms: u64) -> HostFacts {
+        if let Some(facts) = &self.cached {
+            if now_unix_ms.saturating_sub(facts.collected_at_unix_ms) < self.config.ttl_ms {
+                return facts.clone();
+            }
+        }
+        let facts = collect_host_facts(&self.config, now_unix_ms);
+        self.cached = Some(facts.clone());
+        facts
+    }
+}
+
+/// Process-wide cached facts, refreshed according to `HOST_FACTS_TTL_MS`.
+pub fn current_host_facts() -> HostFacts {
+    static CACHE: OnceLock<Mutex<HostFactsCache>> = OnceLock::new();
+    let cache = CACHE.get_or_init(|| Mutex::new(HostFactsCache::new(HostFactsConfig::from_env())));
+    let mut cache = cache.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    cache.get(unix_time_ms())
+}
+
+pub fn collect_host_facts(config: &HostFactsConfig, now_unix_ms: u64) -> HostFacts {
+    HostFacts {
+        os_name: bounded_fact(env::consts::OS, config.max_fact_len),
+        os_version: bounded_fact(&read_os_version().unwrap_or_default(), config.max_fact_len),
+        hostname: bounded_fact(&read_hostname().unwrap_or_default(), config.max_fact_len),
+        primary_ip: bounded_fact(
+            &detect_primary_ip().map(|ip| ip.to_string()).unwrap_or_default(),
+            config.max_fact_len,
+        ),
+        boot_time_unix_ms: read_boot_time_unix_ms(),
+        agent_version: bounded_fact(env!("CARGO_PKG_VERSION"), config.max_fact_len),
+        collected_at_unix_ms: now_unix_ms,
+    }
+}
+
+fn read_os_version() -> Option<String> {
+    env::var("HOST_OS_VERSION")
+        .ok()
+        .or_else(|| fs::read_to_string("/proc/sys/kernel/osrelease").ok())
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+fn read_hostname() -> Option<String> {
+    env::var("COMPUTERNAME")
+        .ok()
+        .or_else(|| env::var("HOSTNAME").ok())
+        .or_else(|| fs::read_to_string("/proc/sys/kernel/hostname").ok())
+        .or_else(|| fs::read_to_string("/etc/hostname").ok())
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+/// Determine the source address the OS would route through; connecting a UDP socket sends
+/// no packets.
+fn detect_primary_ip() -> Option<IpAddr> {
+    let socket = UdpSocket::bind("0.0.0.0:0").ok()?;
+    socket.connect("192.0.2.1:80").ok()?;
+    let ip = socket.local_addr().ok()?.ip();
+    if ip.is_unspecified() {
+        None
+    } else {
+        Some(ip)
+    }
+}
+
+fn read_boot_time_unix_ms() -> Option<u64> {
+    let raw = fs::read_to_string("/proc/stat").ok()?;
+    raw.lines()
+        .find_map(|line| line.strip_prefix("btime "))
+        .and_then(|value| value.trim().parse::<u64>().ok())
+        .map(|seconds| seconds.saturating_mul(1000))
+}
+
+fn bounded_fact(value: &str, max_len: usize) -> String {
+    let trimmed = value.trim();
+    if validate_bounded_string(trimmed, max_len) && !trimmed.chars().any(|ch| ch.is_control()) {
+        return trimmed.to_string();
+    }
+
+    let mut bounded = String::new();
+    for ch in trimmed.chars().filter(|ch| !ch.is_control()) {
+        if bounded.len() + ch.len_utf8() > max_len {
+            break;
+        }
+        bounded.push(ch);
+    }
+    if bounded.is_empty() {
+        "unknown".chars().take(max_len).collect()
+    } else {
+        bounded
+    }
+}
+
+fn field(key: &str, value: &str) -> TelemetryField {
+    TelemetryField {
+        key: key.to_string(),
+        value: value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bounded_fact, collect_host_facts, HostFactsCache, HostFactsConfig};
+
+    #[test]
+    fn collects_host_facts() {
+        let config = HostFactsConfig {
+            ttl_ms: 1_000,
+            max_fact_len: 128,
+        };
+        let facts = collect_host_facts(&config, 10);
+        assert_eq!(facts.os_name, std::env::consts::OS);
+        assert!(!facts.hostname.is_empty());
+        assert!(!facts.primary_ip.is_empty());
+        assert_eq!(facts.agent_version, env!("CARGO_PKG_VERSION"));
+        assert!(facts.telemetry_fields().iter().any(|field| field.key == "host.hostname"));
+    }
+
+    #[test]
+    fn bounds_fact_lengths() {
+        let config = HostFactsConfig {
+            ttl_ms: 1_000,
+            max_fact_len: 4,
+        };
+        let facts = collect_host_facts(&config, 10);
+        for value in [&facts.os_name, &facts.os_version, &facts.hostname, &facts.primary_ip, &facts.agent_version] {
+            assert!(!value.is_empty());
+            assert!(value.len() <= 4);
+        }
+        assert_eq!(bounded_fact("host\u{0007}name", 6), "hostna");
+        assert_eq!(bounded_fact("   ", 16), "unknown");
+    }
+
+    #[test]
+    fn cache_refreshes_after_ttl() {
+        let mut cache = HostFactsCache::new(HostFactsConfig {
+            ttl_ms: 1_000,
+            max_fact_len: 128,
+        });
+        assert_eq!(cache.get(10).collected_at_unix_ms, 10);
+        assert_eq!(cache.get(500).collected_at_unix_ms, 10);
+        assert_eq!(cache.get(1_010).collected_at_unix_ms, 1_010);
+    }
+}
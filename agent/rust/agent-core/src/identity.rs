@@ -5,6 +5,7 @@ use std::path::{Path, PathBuf};
 
 use sha2::{Digest, Sha256};
 
+use crate::path_guard::{canonicalize_bounded, resolve_under, PathLimits, PathRejection};
 use crate::time::unix_time_ms;
 
 /// Identifies the local agent instance in telemetry and control-plane messages.
@@ -33,6 +34,7 @@ pub struct TrustBundleConfig {
     pub root_dir: PathBuf,
     pub anchors: Vec<TrustAnchor>,
     pub allow_missing: bool,
+    pub path_limits: PathLimits,
 }
 
 impl TrustBundleConfig {
@@ -60,6 +62,7 @@ impl TrustBundleConfig {
             root_dir,
             anchors,
             allow_missing,
+            path_limits: PathLimits::from_env(),
         }
     }
 }
@@ -97,11 +100,11 @@ pub fn verify_trust_bundle_with_config(config: &TrustBundleConfig) -> TrustBundl
     }
 
     for anchor in &config.anchors {
-        let resolved = resolve_anchor_path(&anchor.path, &config.root_dir);
+        let resolved = resolve_anchor_path(&anchor.path, &config.root_dir, &config.path_limits);
         let path_display = resolved
             .as_ref()
             .map(|value| value.display().to_string())
-            .unwrap_or_else(|| anchor.path.display().to_string());
+            .unwrap_or_else(|_| anchor.path.display().to_string());
         let mut status = TrustAnchorStatus {
             path: path_display,
             exists: false,
@@ -109,13 +112,24 @@ pub fn verify_trust_bundle_with_config(config: &TrustBundleConfig) -> TrustBundl
         };
 
         let resolved = match resolved {
-            Some(value) => value,
-            None => {
+            Ok(value) => value,
+            Err(PathRejection::OutsideRoot) => {
                 failures.push("Trust anchor path outside allowed root.".to_string());
                 anchors.push(status);
                 verified = false;
                 continue;
             }
+            // A missing anchor is judged by `allow_missing` below, like any other absent file.
+            Err(PathRejection::Unresolvable(_)) if config.allow_missing => {
+                anchors.push(status);
+                continue;
+            }
+            Err(rejection) => {
+                failures.push(format!("Trust anchor path rejected: {}.", rejection));
+                anchors.push(status);
+                verified = false;
+                continue;
+            }
         };
 
         match std::fs::metadata(&resolved) {
@@ -159,19 +173,9 @@ pub fn verify_trust_bundle_with_config(config: &TrustBundleConfig) -> TrustBundl
     }
 }
 
-fn resolve_anchor_path(path: &Path, root_dir: &Path) -> Option<PathBuf> {
-    let root = root_dir.canonicalize().ok()?;
-    let resolved = if path.is_absolute() {
-        path.canonicalize().ok()?
-    } else {
-        root.join(path).canonicalize().ok()?
-    };
-
-    if resolved.starts_with(&root) {
-        Some(resolved)
-    } else {
-        None
-    }
+fn resolve_anchor_path(path: &Path, root_dir: &Path, limits: &PathLimits) -> Result<PathBuf, PathRejection> {
+    let root = canonicalize_bounded(root_dir, limits)?;
+    resolve_under(path, &root, limits)
 }
 
 fn verify_anchor_hash(path: &Path, expected: Option<&String>) -> IoResult<bool> {
@@ -0,0 +1,324 @@
+//! Request bodies the uplink posts for evidence items.
+//!
+//! serde_json sorts object keys, so fields are declared in the order of their serialised
+//! names; the derived output then matches the bytes the backend has always received.
+
+use serde::Serialize;
+
+use crate::uplink::IntakeSchema;
+
+const DEFAULT_RISK_SCORE: f64 = 50.0;
+const DEFAULT_EVIDENCE_TYPE: &str = "agent_evidence";
+const RELATED_ENTITY: &str = "agent";
+
+/// Evidence item fields borrowed by the payload builders.
+pub struct EvidenceFields<'a> {
+    pub tenant_id: &'a str,
+    pub asset_id: &'a str,
+    pub source: &'a str,
+    pub evidence_type: &'a str,
+    pub evidence_id: &'a str,
+    pub related_id: &'a str,
+    pub hash: &'a str,
+    pub storage_uri: &'a str,
+    /// `evidence_id`, or the item's idempotency key when it has none.
+    pub reference_id: &'a str,
+}
+
+/// Kind of object a finding or its evidence is attached to.
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ObjectType {
+    Finding,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AssetCriticality {
+    Medium,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExposureLevel {
+    Internal,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimeSensitivity {
+    None,
+}
+
+/// `intake_endpoint` body in the v1 layout.
+#[derive(Debug, Serialize)]
+pub struct IntakePayload<'a> {
+    pub asset_criticality: AssetCriticality,
+    pub asset_id: String,
+    pub evidence: Vec<EvidenceEntry<'a>>,
+    pub exposure_level: ExposureLevel,
+    pub risk_score: f64,
+    pub source_reference_id: &'a str,
+    pub source_type: ObjectType,
+    pub system_recommendation: Option<String>,
+    pub tenant_id: String,
+    pub time_sensitivity: TimeSensitivity,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EvidenceEntry<'a> {
+    pub immutable_reference: String,
+    pub linked_object_id: &'a str,
+    pub linked_object_type: ObjectType,
+    pub payload: EvidencePayload<'a>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EvidencePayload<'a> {
+    pub hash: &'a str,
+    pub host: serde_json::Value,
+    pub stored_uri: &'a str,
+}
+
+/// `intake_endpoint` body in the v2 layout.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IntakePayloadV2<'a> {
+    pub asset_id: String,
+    pub evidence: Vec<EvidenceEntryV2<'a>>,
+    pub risk: RiskV2,
+    pub source: SourceV2<'a>,
+    pub system_recommendation: Option<String>,
+    pub tenant_id: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RiskV2 {
+    pub asset_criticality: AssetCriticality,
+    pub exposure_level: ExposureLevel,
+    pub score: f64,
+    pub time_sensitivity: TimeSensitivity,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SourceV2<'a> {
+    pub reference_id: &'a str,
+    #[serde(rename = "type")]
+    pub kind: ObjectType,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LinkedObjectV2<'a> {
+    pub id: &'a str,
+    #[serde(rename = "type")]
+    pub kind: ObjectType,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EvidenceEntryV2<'a> {
+    pub immutable_reference: String,
+    pub linked_object: LinkedObjectV2<'a>,
+    pub payload: EvidencePayloadV2<'a>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EvidencePayloadV2<'a> {
+    pub host: serde_json::Value,
+    pub sha256: &'a str,
+    pub storage_uri: &'a str,
+}
+
+/// `rmm_endpoint` body in the v1 layout. `tenant_id` is left out when unknown.
+#[derive(Debug, Serialize)]
+pub struct RmmEvidencePayload<'a> {
+    pub asset_id: &'a str,
+    pub evidence_type: &'a str,
+    pub hash: &'a str,
+    pub related_entity: &'static str,
+    pub related_id: &'a str,
+    pub storage_uri: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant_id: Option<&'a str>,
+}
+
+/// `rmm_endpoint` body in the v2 layout.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RmmEvidencePayloadV2<'a> {
+    pub asset_id: &'a str,
+    pub evidence_type: &'a str,
+    pub related_entity: &'static str,
+    pub related_id: &'a str,
+    pub sha256: &'a str,
+    pub storage_uri: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant_id: Option<&'a str>,
+}
+
+pub fn build_intake_payload(fields: &EvidenceFields<'_>, schema: IntakeSchema, host: serde_json::Value) -> String {
+    let asset_id = normalise_fallback(fields.asset_id, fields.source, "agent-local");
+    let tenant_id = normalise_fallback(fields.tenant_id, "", "tamsil-agent");
+    let linked_object_id = if fields.related_id.is_empty() {
+        fields.evidence_id
+    } else {
+        fields.related_id
+    };
+    let immutable_reference = if fields.evidence_id.is_empty() {
+        format!("ev-{linked_object_id}")
+    } else {
+        fields.evidence_id.to_string()
+    };
+
+    match schema {
+        IntakeSchema::V1 => to_json(&IntakePayload {
+            asset_criticality: AssetCriticality::Medium,
+            asset_id,
+            evidence: vec![EvidenceEntry {
+                immutable_reference,
+                linked_object_id,
+                linked_object_type: ObjectType::Finding,
+                payload: EvidencePayload {
+                    hash: fields.hash,
+                    host,
+                    stored_uri: fields.storage_uri,
+                },
+            }],
+            exposure_level: ExposureLevel::Internal,
+            risk_score: DEFAULT_RISK_SCORE,
+            source_reference_id: fields.reference_id,
+            source_type: ObjectType::Finding,
+            system_recommendation: None,
+            tenant_id,
+            time_sensitivity: TimeSensitivity::None,
+        }),
+        IntakeSchema::V2 => to_json(&IntakePayloadV2 {
+            asset_id,
+            evidence: vec![EvidenceEntryV2 {
+                immutable_reference,
+                linked_object: LinkedObjectV2 {
+                    id: linked_object_id,
+                    kind: ObjectType::Finding,
+                },
+                payload: EvidencePayloadV2 {
+                    host,
+                    sha256: fields.hash,
+                    storage_uri: fields.storage_uri,
+                },
+            }],
+            risk: RiskV2 {
+                asset_criticality: AssetCriticality::Medium,
+                exposure_level: ExposureLevel::Internal,
+                score: DEFAULT_RISK_SCORE,
+                time_sensitivity: TimeSensitivity::None,
+            },
+            source: SourceV2 {
+                reference_id: fields.reference_id,
+                kind: ObjectType::Finding,
+            },
+            system_recommendation: None,
+            tenant_id,
+        }),
+    }
+}
+
+pub fn build_rmm_payload(fields: &EvidenceFields<'_>, schema: IntakeSchema) -> String {
+    let evidence_type = if fields.evidence_type.is_empty() {
+        DEFAULT_EVIDENCE_TYPE
+    } else {
+        fields.evidence_type
+    };
+    let tenant_id = Some(fields.tenant_id).filter(|tenant| !tenant.trim().is_empty());
+    match schema {
+        IntakeSchema::V1 => to_json(&RmmEvidencePayload {
+            asset_id: fields.asset_id,
+            evidence_type,
+            hash: fields.hash,
+            related_entity: RELATED_ENTITY,
+            related_id: fields.related_id,
+            storage_uri: fields.storage_uri,
+            tenant_id,
+        }),
+        IntakeSchema::V2 => to_json(&RmmEvidencePayloadV2 {
+            asset_id: fields.asset_id,
+            evidence_type,
+            related_entity: RELATED_ENTITY,
+            related_id: fields.related_id,
+            sha256: fields.hash,
+            storage_uri: fields.storage_uri,
+            tenant_id,
+        }),
+    }
+}
+
+fn to_json<T: Serialize>(payload: &T) -> String {
+    serde_json::to_string(payload).expect("payload structs serialise to JSON")
+}
+
+fn normalise_fallback(value: &str, alternate: &str, fallback: &str) -> String {
+    if value.trim().len() >= 3 {
+        value.to_string()
+    } else if alternate.trim().len() >= 3 {
+        alternate.to_string()
+    } else {
+        fallback.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GOLDEN_INTAKE_V1: &str = r#"{"asset_criticality":"medium","asset_id":"asset-1","evidence":[{"immutable_reference":"evd-1","linked_object_id":"rel-1","linked_object_type":"finding","payload":{"hash":"abc123","host":{"hostname":"host-1"},"stored_uri":"s3://bucket/evidence"}}],"exposure_level":"internal","risk_score":50.0,"source_reference_id":"evd-1","source_type":"finding","system_recommendation":null,"tenant_id":"tenant-a","time_sensitivity":"none"}"#;
+    const GOLDEN_INTAKE_V2: &str = r#"{"assetId":"asset-1","evidence":[{"immutableReference":"evd-1","linkedObject":{"id":"rel-1","type":"finding"},"payload":{"host":{"hostname":"host-1"},"sha256":"abc123","storageUri":"s3://bucket/evidence"}}],"risk":{"assetCriticality":"medium","exposureLevel":"internal","score":50.0,"timeSensitivity":"none"},"source":{"referenceId":"evd-1","type":"finding"},"systemRecommendation":null,"tenantId":"tenant-a"}"#;
+    const GOLDEN_RMM_V1: &str = r#"{"asset_id":"asset-1","evidence_type":"agent_evidence","hash":"abc123","related_entity":"agent","related_id":"rel-1","storage_uri":"s3://bucket/evidence","tenant_id":"tenant-a"}"#;
+    const GOLDEN_RMM_V2: &str = r#"{"assetId":"asset-1","evidenceType":"agent_evidence","relatedEntity":"agent","relatedId":"rel-1","sha256":"abc123","storageUri":"s3://bucket/evidence","tenantId":"tenant-a"}"#;
+
+    fn fields(tenant_id: &str) -> EvidenceFields<'_> {
+        EvidenceFields {
+            tenant_id,
+            asset_id: "asset-1",
+            source: "agent",
+            evidence_type: "",
+            evidence_id: "evd-1",
+            related_id: "rel-1",
+            hash: "abc123",
+            storage_uri: "s3://bucket/evidence",
+            reference_id: "evd-1",
+        }
+    }
+
+    #[test]
+    fn serialises_payloads_byte_for_byte_as_the_golden_fixtures() {
+        let fields = fields("tenant-a");
+        let host = || serde_json::json!({"hostname": "host-1"});
+
+        assert_eq!(build_intake_payload(&fields, IntakeSchema::V1, host()), GOLDEN_INTAKE_V1);
+        assert_eq!(build_intake_payload(&fields, IntakeSchema::V2, host()), GOLDEN_INTAKE_V2);
+        assert_eq!(build_rmm_payload(&fields, IntakeSchema::V1), GOLDEN_RMM_V1);
+        assert_eq!(build_rmm_payload(&fields, IntakeSchema::V2), GOLDEN_RMM_V2);
+        assert_eq!(IntakeSchema::parse(" V2 "), Some(IntakeSchema::V2));
+        assert_eq!(IntakeSchema::parse("v3"), None);
+    }
+
+    #[test]
+    fn fills_placeholders_and_omits_an_unknown_tenant() {
+        let fields = EvidenceFields {
+            asset_id: "",
+            source: "",
+            evidence_id: "",
+            ..fields(" ")
+        };
+        let intake: serde_json::Value =
+            serde_json::from_str(&build_intake_payload(&fields, IntakeSchema::V1, serde_json::Value::Null)).expect("json");
+        assert_eq!(intake["tenant_id"], "tamsil-agent");
+        assert_eq!(intake["asset_id"], "agent-local");
+        assert_eq!(intake["evidence"][0]["immutable_reference"], "ev-rel-1");
+
+        let rmm: serde_json::Value = serde_json::from_str(&build_rmm_payload(&fields, IntakeSchema::V2)).expect("json");
+        assert!(rmm.get("tenantId").is_none());
+    }
+}
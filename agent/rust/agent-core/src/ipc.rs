@@ -1,11 +1,121 @@
+use std::collections::{HashSet, VecDeque};
+use std::env;
+use std::path::Path;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
+use agent_ipc::frame::{read_frame, write_frame};
+use agent_ipc::proto::agent_ipc::envelope::Payload;
+use agent_ipc::proto::agent_ipc::{AuthResponse, Envelope, EnvelopeAck};
+use agent_ipc::IPC_SCHEMA_VERSION;
+use prost::Message;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, warn};
+
+use crate::audit::{self, AuditEvent};
+use crate::compliance::{AssertionLedger, ComplianceConfig, ExternalAssertion};
+use crate::ipc_auth::{IpcAuthConfig, IpcAuthError, IpcAuthenticator, IpcSession};
 use crate::ipc_validation::{validate_payload_size, validate_proto_envelope, validate_schema_version, EnvelopeMeta};
+use crate::command_router::CommandRouteConfig;
 use crate::ipc_router::route_proto_envelope;
+use crate::metrics::MetricsHandle;
 use crate::policy::PolicyBundle;
 use crate::rate_limit::RateLimiter;
+use crate::seen_commands::{SeenCommandCache, SeenCommandRejection};
+use crate::sensor_evidence::{check_package, register_package, SensorEvidenceConfig};
+use crate::state_dir::StatePaths;
+use crate::telemetry_router::TelemetryRouter;
+
+/// Room for envelope metadata on top of `max_payload_bytes` when bounding a frame.
+const FRAME_OVERHEAD_BYTES: usize = 4096;
 
-pub const IPC_SCHEMA_VERSION: u32 = 1;
+#[derive(Debug, Clone)]
+pub struct IpcConnectionLimits {
+    pub max_concurrent_connections: usize,
+    pub idle_timeout_ms: u64,
+}
+
+impl IpcConnectionLimits {
+    pub fn from_env() -> Self {
+        let max_concurrent_connections = env::var("AGENT_IPC_MAX_CONNECTIONS")
+            .ok()
+            .and_then(|value| value.parse::<usize>().ok())
+            .filter(|value| *value > 0)
+            .unwrap_or(16);
+        let idle_timeout_ms = env::var("AGENT_IPC_IDLE_TIMEOUT_MS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .filter(|value| *value > 0)
+            .unwrap_or(30_000);
+        Self {
+            max_concurrent_connections,
+            idle_timeout_ms,
+        }
+    }
+}
+
+/// Envelope ids recently accepted from each client, so an envelope resent after a
+/// reconnect is acknowledged without being routed twice. The oldest ids are forgotten
+/// first once `capacity` are held.
+#[derive(Debug)]
+pub struct RecentEnvelopes {
+    capacity: usize,
+    order: VecDeque<String>,
+    ids: HashSet<String>,
+}
+
+impl RecentEnvelopes {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            order: VecDeque::new(),
+            ids: HashSet::new(),
+        }
+    }
+
+    pub fn from_env() -> Self {
+        let capacity = env::var("AGENT_IPC_RECENT_ENVELOPES")
+            .ok()
+            .and_then(|value| value.parse::<usize>().ok())
+            .unwrap_or(1024);
+        Self::new(capacity)
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.ids.contains(key)
+    }
+
+    pub fn insert(&mut self, key: String) {
+        if self.capacity == 0 || !self.ids.insert(key.clone()) {
+            return;
+        }
+        self.order.push_back(key);
+        while self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+    }
+}
+
+/// Held for the life of an admitted connection; releasing it frees a connection slot.
+#[derive(Debug)]
+pub struct ConnectionSlot {
+    permit: Option<OwnedSemaphorePermit>,
+    connections: Arc<Semaphore>,
+    limits: IpcConnectionLimits,
+    metrics: MetricsHandle,
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.permit.take();
+        let active = self.limits.max_concurrent_connections - self.connections.available_permits();
+        self.metrics.ipc_active_connections.set(active as u64);
+    }
+}
 
 #[derive(Debug)]
 pub struct IpcServer {
@@ -13,6 +123,20 @@ pub struct IpcServer {
     pub max_payload_bytes: usize,
     pub rate_limiter: Arc<Mutex<RateLimiter>>,
     pub policy: Arc<PolicyBundle>,
+    /// Signature key and clock tolerances execution commands are routed with.
+    pub command_route: CommandRouteConfig,
+    pub telemetry_router: Arc<Mutex<TelemetryRouter>>,
+    /// Execution command ids accepted on any connection, for replay protection.
+    pub seen_commands: Arc<Mutex<SeenCommandCache>>,
+    /// Compliance assertions from other services, folded into the next compliance report.
+    pub compliance_assertions: Arc<Mutex<AssertionLedger>>,
+    /// Staging root and limits for evidence files sensors hand over by path.
+    pub sensor_evidence: SensorEvidenceConfig,
+    pub metrics: MetricsHandle,
+    pub auth: IpcAuthenticator,
+    pub limits: IpcConnectionLimits,
+    connections: Arc<Semaphore>,
+    recent_envelopes: Arc<Mutex<RecentEnvelopes>>,
 }
 
 impl IpcServer {
@@ -21,42 +145,826 @@ impl IpcServer {
         max_payload_bytes: usize,
         rate_limiter: RateLimiter,
         policy: PolicyBundle,
+        metrics: MetricsHandle,
+        paths: &StatePaths,
     ) -> Self {
+        let limits = IpcConnectionLimits::from_env();
         Self {
             pipe_name,
             max_payload_bytes,
             rate_limiter: Arc::new(Mutex::new(rate_limiter)),
             policy: Arc::new(policy),
+            command_route: CommandRouteConfig::from_env(),
+            telemetry_router: Arc::new(Mutex::new(TelemetryRouter::from_env())),
+            seen_commands: Arc::new(Mutex::new(SeenCommandCache::from_env())),
+            compliance_assertions: Arc::new(Mutex::new(AssertionLedger::from_config(&ComplianceConfig::from_env()))),
+            sensor_evidence: SensorEvidenceConfig::from_env(paths),
+            metrics,
+            auth: IpcAuthenticator::new(IpcAuthConfig::from_env()),
+            connections: Arc::new(Semaphore::new(limits.max_concurrent_connections)),
+            limits,
+            recent_envelopes: Arc::new(Mutex::new(RecentEnvelopes::from_env())),
+        }
+    }
+
+    pub fn with_limits(mut self, limits: IpcConnectionLimits) -> Self {
+        self.connections = Arc::new(Semaphore::new(limits.max_concurrent_connections));
+        self.limits = limits;
+        self
+    }
+
+    pub fn active_connections(&self) -> usize {
+        self.limits.max_concurrent_connections - self.connections.available_permits()
+    }
+
+    /// Claim a connection slot, or `None` when `max_concurrent_connections` are open.
+    pub fn try_admit(&self) -> Option<ConnectionSlot> {
+        let permit = match Arc::clone(&self.connections).try_acquire_owned() {
+            Ok(permit) => permit,
+            Err(_) => {
+                self.metrics.envelopes_rejected.inc("connection_limit");
+                return None;
+            }
+        };
+        self.metrics.ipc_active_connections.set(self.active_connections() as u64);
+        Some(ConnectionSlot {
+            permit: Some(permit),
+            connections: Arc::clone(&self.connections),
+            limits: self.limits.clone(),
+            metrics: self.metrics.clone(),
+        })
+    }
+
+    /// Whether `pipe_name` is an endpoint this platform can serve: a local `\\.\pipe\`
+    /// name on Windows, an absolute socket path elsewhere.
+    pub fn endpoint_configured(&self) -> bool {
+        if cfg!(windows) {
+            self.pipe_name.to_ascii_lowercase().starts_with(r"\\.\pipe\")
+        } else {
+            Path::new(&self.pipe_name).is_absolute()
+        }
+    }
+
+    /// Bind `pipe_name` and serve clients until `shutdown`. A bind failure is logged and
+    /// leaves `ipc_listener_bound` at 0, which the pipeline reports as failed.
+    pub async fn run(self: Arc<Self>, shutdown: CancellationToken) {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::FileTypeExt;
+
+            let path = Path::new(&self.pipe_name);
+            // A socket left behind by an earlier run makes the bind fail; agent-core's
+            // instance lock means no live server owns it.
+            if std::fs::symlink_metadata(path).map(|meta| meta.file_type().is_socket()).unwrap_or(false) {
+                let _ = std::fs::remove_file(path);
+            }
+            match tokio::net::UnixListener::bind(path) {
+                Ok(listener) => self.serve_unix(listener, shutdown).await,
+                Err(err) => warn!(error = %err, pipe = %self.pipe_name, "failed to bind ipc socket"),
+            }
         }
+        #[cfg(windows)]
+        self.serve_named_pipe(shutdown).await;
+    }
+
+    /// Accept loop for a Unix socket listener. Connections over the limit are closed
+    /// immediately instead of being queued or spawned.
+    #[cfg(unix)]
+    pub async fn serve_unix(self: Arc<Self>, listener: tokio::net::UnixListener, shutdown: CancellationToken) {
+        self.metrics.ipc_listener_bound.set(1);
+        loop {
+            let accepted = tokio::select! {
+                _ = shutdown.cancelled() => {
+                    self.metrics.ipc_listener_bound.set(0);
+                    return;
+                }
+                accepted = listener.accept() => accepted,
+            };
+            match accepted {
+                Ok((stream, _)) => self.spawn_connection(stream),
+                Err(err) => warn!(error = %err, "ipc accept failed"),
+            }
+        }
+    }
+
+    /// Accept loop for a named pipe. A new pipe instance is created before each connected
+    /// one is handed off, so a client always has an instance to connect to.
+    #[cfg(windows)]
+    pub async fn serve_named_pipe(self: Arc<Self>, shutdown: CancellationToken) {
+        use tokio::net::windows::named_pipe::ServerOptions;
+
+        let mut server = match ServerOptions::new().first_pipe_instance(true).create(&self.pipe_name) {
+            Ok(server) => server,
+            Err(err) => {
+                warn!(error = %err, pipe = %self.pipe_name, "failed to create ipc pipe");
+                return;
+            }
+        };
+        self.metrics.ipc_listener_bound.set(1);
+        loop {
+            let connected = tokio::select! {
+                _ = shutdown.cancelled() => {
+                    self.metrics.ipc_listener_bound.set(0);
+                    return;
+                }
+                connected = server.connect() => connected,
+            };
+            if let Err(err) = connected {
+                warn!(error = %err, "ipc accept failed");
+                continue;
+            }
+            let next = match ServerOptions::new().create(&self.pipe_name) {
+                Ok(next) => next,
+                Err(err) => {
+                    warn!(error = %err, pipe = %self.pipe_name, "failed to create ipc pipe instance");
+                    self.metrics.ipc_listener_bound.set(0);
+                    return;
+                }
+            };
+            self.spawn_connection(std::mem::replace(&mut server, next));
+        }
+    }
+
+    /// Serve an accepted client on its own task, or close it at once when
+    /// `max_concurrent_connections` are open.
+    fn spawn_connection<S>(self: &Arc<Self>, stream: S)
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let slot = match self.try_admit() {
+            Some(slot) => slot,
+            None => {
+                warn!(limit = self.limits.max_concurrent_connections, "ipc connection limit reached; refusing client");
+                return;
+            }
+        };
+        let server = Arc::clone(self);
+        tokio::spawn(async move {
+            if let Err(err) = server.serve_connection(stream).await {
+                debug!(error = %err, "ipc connection closed");
+            }
+            drop(slot);
+        });
+    }
+
+    /// Run the handshake then route length-prefixed envelopes until the client disconnects
+    /// or stays silent for longer than the idle timeout.
+    pub async fn serve_connection<S>(&self, mut stream: S) -> Result<(), String>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let idle = Duration::from_millis(self.limits.idle_timeout_ms);
+        let max_frame = self.max_payload_bytes.saturating_add(FRAME_OVERHEAD_BYTES);
+        let mut session = self.open_session().map_err(|err| err.to_string())?;
+        write_frame(&mut stream, &session.challenge().encode_to_vec()).await?;
+
+        let response = read_frame(&mut stream, max_frame, idle).await?.ok_or("closed before handshake")?;
+        let response = AuthResponse::decode(response.as_slice()).map_err(|err| format!("invalid handshake: {err}"))?;
+        if !session.is_authenticated() && !self.authenticate(&mut session, &response) {
+            return Err("handshake refused".to_string());
+        }
+
+        while let Some(frame) = read_frame(&mut stream, max_frame, idle).await? {
+            let accepted = match Envelope::decode(frame.as_slice()) {
+                Ok(envelope) => self.handle_session_proto(&session, &envelope),
+                Err(_) => {
+                    self.metrics.envelopes_rejected.inc("invalid_envelope");
+                    false
+                }
+            };
+            if response.wants_acks {
+                write_frame(&mut stream, &EnvelopeAck { accepted }.encode_to_vec()).await?;
+            }
+        }
+        Ok(())
     }
 
     pub fn validate_envelope(&self, envelope: &EnvelopeMeta) -> bool {
         if !validate_schema_version(envelope.schema_version, IPC_SCHEMA_VERSION) {
+            self.metrics.envelopes_rejected.inc("schema_version");
             return false;
         }
 
         if !validate_payload_size(envelope.payload_bytes, self.max_payload_bytes) {
+            self.metrics.envelopes_rejected.inc("payload_size");
             return false;
         }
 
-        let mut limiter = self.rate_limiter.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
-        limiter.allow()
+        let mut limiter = match self.rate_limiter.lock() {
+            Ok(limiter) => limiter,
+            Err(poisoned) => {
+                // A handler panicked while holding the limiter; report it rather than carry on
+                // silently, then repair the state and clear the flag so it is reported once.
+                self.metrics.lock_poison_recoveries.inc("ipc_rate_limiter");
+                warn!("ipc rate limiter lock was poisoned by a panicked handler; repaired and continuing");
+                self.rate_limiter.clear_poison();
+                let mut limiter = poisoned.into_inner();
+                limiter.repair();
+                limiter
+            }
+        };
+        if !limiter.allow() {
+            self.metrics.rate_limit_hits.inc();
+            self.metrics.envelopes_rejected.inc("rate_limited");
+            return false;
+        }
+        true
+    }
+
+    /// Start the handshake for a newly accepted connection.
+    pub fn open_session(&self) -> Result<IpcSession, IpcAuthError> {
+        self.auth.open_session()
+    }
+
+    pub fn authenticate(&self, session: &mut IpcSession, response: &AuthResponse) -> bool {
+        match self.auth.authenticate(session, response) {
+            Ok(()) => true,
+            Err(_) => {
+                self.metrics.envelopes_rejected.inc("auth_failed");
+                false
+            }
+        }
     }
 
-    pub fn start(&self) {
-        // TODO: Bind to named pipe, accept only authorised clients, and decode protobuf messages.
-        // TODO: Validate schema version, size, and required fields before routing.
+    /// Route an envelope received on `session`; nothing is routed before the handshake. An
+    /// `envelope_id` this client already had accepted is acknowledged again without routing,
+    /// since the client only repeats one when it lost the first acknowledgement.
+    pub fn handle_session_proto(&self, session: &IpcSession, envelope: &Envelope) -> bool {
+        if !session.is_authenticated() {
+            self.metrics.envelopes_rejected.inc("unauthenticated");
+            return false;
+        }
+        if envelope.envelope_id.is_empty() {
+            return self.handle_proto_from(session.client_id(), envelope);
+        }
+        let key = format!("{}\0{}", session.client_id().unwrap_or_default(), envelope.envelope_id);
+        // Held across routing so a resend racing the original is not routed as well.
+        let mut recent = self
+            .recent_envelopes
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if recent.contains(&key) {
+            debug!(envelope_id = %envelope.envelope_id, "acknowledging resent ipc envelope without routing it");
+            return true;
+        }
+        let accepted = self.handle_proto_from(session.client_id(), envelope);
+        if accepted {
+            recent.insert(key);
+        }
+        accepted
     }
 
-    pub fn validate_proto(&self, envelope: &crate::proto::agent_ipc::Envelope) -> bool {
+    pub fn validate_proto(&self, envelope: &Envelope) -> bool {
         validate_proto_envelope(envelope, IPC_SCHEMA_VERSION, self.max_payload_bytes)
     }
 
-    pub fn handle_proto(&self, envelope: &crate::proto::agent_ipc::Envelope) -> bool {
+    pub fn handle_proto(&self, envelope: &Envelope) -> bool {
+        self.handle_proto_from(None, envelope)
+    }
+
+    /// Route an envelope sent by `client_id`. Compliance assertions are only accepted from
+    /// an identified client, since the report records who asserted them.
+    fn handle_proto_from(&self, client_id: Option<&str>, envelope: &Envelope) -> bool {
         if !self.validate_proto(envelope) {
+            self.metrics.envelopes_rejected.inc("invalid_envelope");
             return false;
         }
+        if let Some(Payload::ComplianceAssertion(assertion)) = &envelope.payload {
+            let Some(source_service) = client_id else {
+                self.metrics.envelopes_rejected.inc("unattributed_assertion");
+                return false;
+            };
+            let recorded = self
+                .compliance_assertions
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .record(ExternalAssertion {
+                    control_id: assertion.control_id.clone(),
+                    passed: assertion.passed,
+                    evidence_ref: assertion.evidence_ref.clone(),
+                    evaluated_at_unix_ms: assertion.evaluated_unix_time_ms,
+                    source_service: source_service.to_string(),
+                });
+            return match recorded {
+                Ok(()) => {
+                    self.metrics.envelopes_accepted.inc();
+                    true
+                }
+                Err(rejection) => {
+                    debug!(control_id = %assertion.control_id, reason = %rejection, "compliance assertion rejected");
+                    self.metrics.envelopes_rejected.inc(rejection.metric_label());
+                    false
+                }
+            };
+        }
+        if let Some(Payload::EvidencePackage(package)) = &envelope.payload {
+            if !package.staged_path.is_empty() {
+                let Some(source_service) = client_id else {
+                    self.metrics.envelopes_rejected.inc("unattributed_evidence");
+                    return false;
+                };
+                if let Err(rejection) = check_package(package, &self.sensor_evidence) {
+                    warn!(
+                        evidence_id = %package.evidence_id,
+                        source = source_service,
+                        reason = %rejection,
+                        "sensor evidence package rejected"
+                    );
+                    self.metrics.envelopes_rejected.inc(rejection.metric_label());
+                    return false;
+                }
+                // Copying and hashing reads up to the size limit, so it runs on the blocking
+                // pool; a copy that fails its hash check is logged and counted from there.
+                let (package, asset_id, source) = (package.clone(), envelope.asset_id.clone(), source_service.to_string());
+                let (config, metrics) = (self.sensor_evidence.clone(), self.metrics.clone());
+                tokio::task::spawn_blocking(move || {
+                    if let Err(rejection) = register_package(&package, &asset_id, &source, &config) {
+                        warn!(
+                            evidence_id = %package.evidence_id,
+                            source = %source,
+                            reason = %rejection,
+                            "sensor evidence package rejected"
+                        );
+                        metrics.envelopes_rejected.inc(rejection.metric_label());
+                    }
+                });
+                self.metrics.envelopes_accepted.inc();
+                return true;
+            }
+        }
         let now_unix_time_ms = crate::time::unix_time_ms();
-        route_proto_envelope(envelope, &self.policy, now_unix_time_ms)
+        // Held across routing so the same command id racing in on two connections is only
+        // accepted once.
+        let mut seen_commands = self
+            .seen_commands
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let command = match &envelope.payload {
+            Some(Payload::ExecutionCommand(command)) => Some(command),
+            _ => None,
+        };
+        if let Some(command) = command {
+            if let Err(rejection) = seen_commands.check(&command.command_id, command.not_after_unix_time_ms, now_unix_time_ms) {
+                let label = match rejection {
+                    SeenCommandRejection::Replayed => "replayed_command",
+                    SeenCommandRejection::Full => "seen_commands_full",
+                    SeenCommandRejection::ValidityTooLong => "command_validity_too_long",
+                };
+                self.metrics.envelopes_rejected.inc(label);
+                audit::record(AuditEvent::CommandRejected {
+                    command_id: command.command_id.clone(),
+                    reason: label.to_string(),
+                });
+                return false;
+            }
+        }
+        let mut telemetry_router = self
+            .telemetry_router
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let routed = route_proto_envelope(envelope, &self.policy, &self.command_route, &mut telemetry_router, now_unix_time_ms);
+        if let Some(command) = command {
+            if routed {
+                let _ = seen_commands.insert(&command.command_id, command.not_after_unix_time_ms, now_unix_time_ms);
+                audit::record(AuditEvent::ExecutionAccepted {
+                    command_id: command.command_id.clone(),
+                    action: command.action.clone(),
+                });
+            } else {
+                audit::record(AuditEvent::CommandRejected {
+                    command_id: command.command_id.clone(),
+                    reason: "routing_rejected".to_string(),
+                });
+            }
+        }
+        if routed {
+            self.metrics.envelopes_accepted.inc();
+        } else {
+            self.metrics.envelopes_rejected.inc("routing_rejected");
+        }
+        routed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hmac::Mac;
+
+    #[cfg(unix)]
+    use std::sync::{Arc, Mutex};
+    #[cfg(unix)]
+    use std::time::Duration;
+
+    #[cfg(unix)]
+    use agent_ipc::client::{ConnectionState, IpcClient, IpcClientConfig};
+    #[cfg(unix)]
+    use agent_ipc::frame::read_frame;
+    use agent_ipc::handshake_mac;
+    use agent_ipc::proto::agent_ipc::envelope::Payload;
+    #[cfg(unix)]
+    use agent_ipc::proto::agent_ipc::SensorEvent;
+    use agent_ipc::proto::agent_ipc::{AuthResponse, Envelope, ExecutionCommand, HealthHeartbeat};
+    #[cfg(unix)]
+    use tokio::net::{UnixListener, UnixStream};
+    #[cfg(unix)]
+    use tokio_util::sync::CancellationToken;
+
+    use super::{IpcServer, RecentEnvelopes};
+    use crate::command_router::{command_signing_message, CommandRouteConfig, SignedCommand};
+    use crate::crypto::{HmacSha256, Signer};
+    #[cfg(unix)]
+    use super::IpcConnectionLimits;
+    use crate::ipc_auth::{IpcAuthConfig, IpcAuthenticator};
+    use crate::ipc_validation::EnvelopeMeta;
+    use crate::metrics::AgentMetrics;
+    use crate::policy::PolicyBundle;
+    use crate::rate_limit::RateLimiter;
+    use crate::seen_commands::SeenCommandCache;
+    use crate::state_dir::StatePaths;
+
+    const COMMAND_KEY: &[u8] = b"unit-test-command-key";
+
+    fn build_server(metrics: &crate::metrics::MetricsHandle) -> IpcServer {
+        let mut server = IpcServer::new(
+            "test-pipe".to_string(),
+            1024,
+            RateLimiter::new(600),
+            PolicyBundle::placeholder(),
+            metrics.clone(),
+            &StatePaths::under(&std::env::temp_dir().join("ipc-server-state")),
+        );
+        server.command_route =
+            CommandRouteConfig::from_env().with_verifier(std::sync::Arc::new(HmacSha256::new(COMMAND_KEY)));
+        server
+    }
+
+    #[test]
+    fn refuses_envelopes_until_handshake_succeeds() {
+        let metrics = AgentMetrics::new_handle();
+        let mut server = build_server(&metrics);
+        server.auth = IpcAuthenticator::new(IpcAuthConfig {
+            key: Some(b"shared-key".to_vec()),
+            allow_anonymous: false,
+        });
+        let mut session = server.open_session().expect("session");
+        let envelope = Envelope::default();
+
+        assert!(!server.handle_session_proto(&session, &envelope));
+        assert_eq!(metrics.envelopes_rejected.get("unauthenticated"), 1);
+        assert_eq!(metrics.envelopes_rejected.get("invalid_envelope"), 0);
+
+        assert!(!server.authenticate(&mut session, &AuthResponse::default()));
+        assert_eq!(metrics.envelopes_rejected.get("auth_failed"), 1);
+
+        let nonce = session.challenge().nonce.clone();
+        let response = AuthResponse {
+            client_id: "agent-sensor".to_string(),
+            mac: handshake_mac(b"shared-key", "agent-sensor", &nonce).finalize().into_bytes().to_vec(),
+            nonce,
+            wants_acks: false,
+        };
+        assert!(server.authenticate(&mut session, &response));
+        server.handle_session_proto(&session, &envelope);
+        assert_eq!(metrics.envelopes_rejected.get("unauthenticated"), 1);
+        assert_eq!(metrics.envelopes_rejected.get("invalid_envelope"), 1);
+    }
+
+    #[test]
+    fn acknowledges_resent_envelopes_without_routing_them_again() {
+        let metrics = AgentMetrics::new_handle();
+        let mut server = build_server(&metrics);
+        server.auth = IpcAuthenticator::new(IpcAuthConfig {
+            key: None,
+            allow_anonymous: true,
+        });
+        let session = server.open_session().expect("anonymous session");
+        let heartbeat = |envelope_id: &str| Envelope {
+            schema_version: 1,
+            asset_id: "asset".to_string(),
+            agent_id: "agent".to_string(),
+            unix_time_ms: 1,
+            envelope_id: envelope_id.to_string(),
+            payload: Some(Payload::HealthHeartbeat(HealthHeartbeat {
+                service_name: "agent-sensor".to_string(),
+                unix_time_ms: 1,
+            })),
+        };
+
+        assert!(server.handle_session_proto(&session, &heartbeat("env-1")));
+        assert!(server.handle_session_proto(&session, &heartbeat("env-1")));
+        assert_eq!(metrics.envelopes_accepted.get(), 1);
+        assert!(server.handle_session_proto(&session, &heartbeat("env-2")));
+        assert!(server.handle_session_proto(&session, &heartbeat("")));
+        assert!(server.handle_session_proto(&session, &heartbeat("")));
+        assert_eq!(metrics.envelopes_accepted.get(), 4);
+
+        let mut recent = RecentEnvelopes::new(2);
+        recent.insert("a".to_string());
+        recent.insert("b".to_string());
+        recent.insert("c".to_string());
+        assert!(!recent.contains("a") && recent.contains("b") && recent.contains("c"));
+    }
+
+    #[test]
+    fn reports_and_repairs_a_poisoned_rate_limiter() {
+        let metrics = AgentMetrics::new_handle();
+        let server = build_server(&metrics);
+        let limiter = std::sync::Arc::clone(&server.rate_limiter);
+        let panicked = std::thread::spawn(move || {
+            let _guard = limiter.lock().expect("limiter");
+            panic!("handler panicked while holding the limiter");
+        })
+        .join();
+        assert!(panicked.is_err());
+        assert!(server.rate_limiter.is_poisoned());
+
+        let envelope = EnvelopeMeta {
+            schema_version: 1,
+            payload_bytes: 16,
+        };
+        assert!(server.validate_envelope(&envelope));
+        assert_eq!(metrics.lock_poison_recoveries.get("ipc_rate_limiter"), 1);
+        assert!(!server.rate_limiter.is_poisoned());
+        assert!(metrics
+            .render()
+            .contains("agent_lock_poison_recoveries_total{lock=\"ipc_rate_limiter\"} 1"));
+
+        assert!(server.validate_envelope(&envelope));
+        assert_eq!(metrics.lock_poison_recoveries.get("ipc_rate_limiter"), 1);
+    }
+
+    fn command_envelope(command_id: &str, not_after_unix_time_ms: u64) -> Envelope {
+        let signature = HmacSha256::new(COMMAND_KEY).sign(
+            command_signing_message(&SignedCommand {
+                command_id: command_id.to_string(),
+                signed_payload: "signed".to_string(),
+                signature: String::new(),
+                action: "script-run".to_string(),
+                arguments: vec!["-version".to_string()],
+                not_before_unix_time_ms: 0,
+                not_after_unix_time_ms,
+            })
+            .as_bytes(),
+        );
+        Envelope {
+            schema_version: 1,
+            asset_id: "asset".to_string(),
+            agent_id: "agent".to_string(),
+            unix_time_ms: 1,
+            envelope_id: String::new(),
+            payload: Some(Payload::ExecutionCommand(ExecutionCommand {
+                command_id: command_id.to_string(),
+                signed_blob: "signed".to_string(),
+                action: "script-run".to_string(),
+                arguments: vec!["-version".to_string()],
+                not_before_unix_time_ms: 0,
+                not_after_unix_time_ms,
+                signature,
+            })),
+        }
+    }
+
+    #[test]
+    fn rejects_replayed_command_across_calls() {
+        let metrics = AgentMetrics::new_handle();
+        let server = build_server(&metrics);
+        let not_after = crate::time::unix_time_ms() + 60_000;
+
+        assert!(server.handle_proto(&command_envelope("cmd-replay", not_after)));
+        assert!(!server.handle_proto(&command_envelope("cmd-replay", not_after)));
+        assert_eq!(metrics.envelopes_rejected.get("replayed_command"), 1);
+        assert!(server.handle_proto(&command_envelope("cmd-other", not_after)));
+        assert_eq!(metrics.envelopes_accepted.get(), 2);
+    }
+
+    #[test]
+    fn remembers_only_accepted_commands() {
+        let metrics = AgentMetrics::new_handle();
+        let mut server = build_server(&metrics);
+        server.seen_commands = std::sync::Arc::new(std::sync::Mutex::new(SeenCommandCache::new(1, 0)));
+
+        assert!(!server.handle_proto(&command_envelope("cmd-expired", 1)));
+        assert_eq!(metrics.envelopes_rejected.get("routing_rejected"), 1);
+        assert!(server.handle_proto(&command_envelope("cmd-live", crate::time::unix_time_ms() + 60_000)));
+        assert!(!server.handle_proto(&command_envelope("cmd-next", crate::time::unix_time_ms() + 60_000)));
+        assert_eq!(metrics.envelopes_rejected.get("seen_commands_full"), 1);
+    }
+
+    #[cfg(unix)]
+    fn socket_path(label: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!(
+            "ipc-{}-{}-{}.sock",
+            label,
+            std::process::id(),
+            crate::time::unix_time_ms()
+        ))
+    }
+
+    #[cfg(unix)]
+    fn serve_at(path: &std::path::Path, server: IpcServer) -> CancellationToken {
+        let listener = UnixListener::bind(path).expect("bind socket");
+        let shutdown = CancellationToken::new();
+        tokio::spawn(Arc::new(server).serve_unix(listener, shutdown.clone()));
+        shutdown
+    }
+
+    #[cfg(unix)]
+    fn listen(label: &str, server: IpcServer) -> (std::path::PathBuf, CancellationToken) {
+        let path = socket_path(label);
+        let shutdown = serve_at(&path, server);
+        (path, shutdown)
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn run_replaces_a_stale_socket() {
+        let path = std::env::temp_dir().join(format!("ipc-stale-{}-{}.sock", std::process::id(), crate::time::unix_time_ms()));
+        drop(std::os::unix::net::UnixListener::bind(&path).expect("bind stale socket"));
+        let metrics = AgentMetrics::new_handle();
+        let mut server = build_server(&metrics);
+        server.pipe_name = path.to_string_lossy().to_string();
+        assert!(server.endpoint_configured());
+        let shutdown = CancellationToken::new();
+        tokio::spawn(Arc::new(server).run(shutdown.clone()));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let (_client, challenge) = connect(&path).await;
+        assert!(challenge.is_some());
+        assert_eq!(metrics.ipc_listener_bound.get(), 1);
+        assert!(!build_server(&metrics).endpoint_configured());
+
+        shutdown.cancel();
+        let _ = std::fs::remove_file(path);
+    }
+
+    /// Connect and read the first frame; `None` means the server closed without a challenge.
+    #[cfg(unix)]
+    async fn connect(path: &std::path::Path) -> (UnixStream, Option<Vec<u8>>) {
+        let mut stream = UnixStream::connect(path).await.expect("connect");
+        let challenge = read_frame(&mut stream, 1024, Duration::from_secs(2)).await.unwrap_or(None);
+        (stream, challenge)
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn refuses_connections_beyond_limit() {
+        let metrics = AgentMetrics::new_handle();
+        let server = build_server(&metrics).with_limits(IpcConnectionLimits {
+            max_concurrent_connections: 2,
+            idle_timeout_ms: 60_000,
+        });
+        let (path, shutdown) = listen("limit", server);
+
+        let (first, challenge) = connect(&path).await;
+        assert!(challenge.is_some());
+        let (_second, challenge) = connect(&path).await;
+        assert!(challenge.is_some());
+        assert_eq!(metrics.ipc_active_connections.get(), 2);
+
+        let (_third, challenge) = connect(&path).await;
+        assert!(challenge.is_none());
+        assert_eq!(metrics.envelopes_rejected.get("connection_limit"), 1);
+
+        drop(first);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let (_fourth, challenge) = connect(&path).await;
+        assert!(challenge.is_some());
+
+        shutdown.cancel();
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn closes_idle_connections_and_frees_slot() {
+        let metrics = AgentMetrics::new_handle();
+        let server = build_server(&metrics).with_limits(IpcConnectionLimits {
+            max_concurrent_connections: 1,
+            idle_timeout_ms: 100,
+        });
+        let (path, shutdown) = listen("idle", server);
+
+        let (mut idle, challenge) = connect(&path).await;
+        assert!(challenge.is_some());
+        let closed = read_frame(&mut idle, 1024, Duration::from_secs(2)).await;
+        assert!(matches!(closed, Ok(None)));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(metrics.ipc_active_connections.get(), 0);
+
+        let (_next, challenge) = connect(&path).await;
+        assert!(challenge.is_some());
+
+        shutdown.cancel();
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[cfg(unix)]
+    fn keyed_server(metrics: &crate::metrics::MetricsHandle, idle_timeout_ms: u64) -> IpcServer {
+        let mut server = build_server(metrics).with_limits(IpcConnectionLimits {
+            max_concurrent_connections: 4,
+            idle_timeout_ms,
+        });
+        server.auth = IpcAuthenticator::new(IpcAuthConfig {
+            key: Some(b"shared-key".to_vec()),
+            allow_anonymous: false,
+        });
+        server
+    }
+
+    #[cfg(unix)]
+    fn client_config() -> IpcClientConfig {
+        IpcClientConfig {
+            client_id: "agent-watchdog".to_string(),
+            auth_key: Some(b"shared-key".to_vec()),
+            asset_id: "asset-1".to_string(),
+            agent_id: "agent-1".to_string(),
+            max_payload_bytes: 1024,
+            max_send_attempts: 3,
+            initial_backoff_ms: 20,
+            max_backoff_ms: 80,
+            give_up_after_ms: Some(5_000),
+            io_timeout_ms: 2_000,
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn client_connects_once_server_appears_and_sends_envelope() {
+        let path = socket_path("client-appears");
+        let metrics = AgentMetrics::new_handle();
+        let server_path = path.clone();
+        let server = keyed_server(&metrics, 60_000);
+        let (ready_tx, ready_rx) = tokio::sync::oneshot::channel();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            let _ = ready_tx.send(serve_at(&server_path, server));
+        });
+
+        let config = client_config();
+        let mut client = IpcClient::connect_with_config(path.to_str().expect("utf-8 path"), config.clone())
+            .await
+            .expect("connects after server starts");
+        let error = client.send_envelope(&Envelope::default()).await.expect_err("rejected");
+        assert_eq!(error, "core rejected envelope");
+        assert_eq!(metrics.envelopes_rejected.get("auth_failed"), 0);
+        assert_eq!(metrics.envelopes_rejected.get("invalid_envelope"), 1);
+
+        let oversized = Envelope {
+            asset_id: "x".repeat(2_048),
+            ..Envelope::default()
+        };
+        let error = client.send_envelope(&oversized).await.expect_err("too large");
+        assert!(error.contains("exceeds 1024"), "{}", error);
+        assert_eq!(metrics.envelopes_rejected.get("invalid_envelope"), 1);
+
+        ready_rx.await.expect("server started").cancel();
+        let _ = std::fs::remove_file(&path);
+
+        let unreachable = IpcClientConfig {
+            give_up_after_ms: Some(100),
+            ..config
+        };
+        let error = IpcClient::connect_with_config(path.to_str().expect("utf-8 path"), unreachable)
+            .await
+            .expect_err("no server");
+        assert!(error.contains("gave up connecting"), "{}", error);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn client_reconnects_after_server_restart() {
+        let path = socket_path("client-restart");
+        let metrics = AgentMetrics::new_handle();
+        let first = serve_at(&path, keyed_server(&metrics, 200));
+
+        let states = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&states);
+        let mut client = IpcClient::new(path.to_str().expect("utf-8 path"), client_config())
+            .on_state_change(move |state| recorded.lock().expect("states").push(state));
+        client.send_heartbeat("agent-sensor").await.expect("first heartbeat");
+        assert_eq!(metrics.envelopes_accepted.get(), 1);
+        let events = vec![SensorEvent::default(); 3];
+        client.send_sensor_events(events).await.expect("sensor batch");
+        assert_eq!(metrics.envelopes_accepted.get(), 2, "three events travel in one batch envelope");
+
+        // Stop the first server and let the idle timeout drop the open connection.
+        first.cancel();
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        let _ = std::fs::remove_file(&path);
+        let second = serve_at(&path, keyed_server(&metrics, 60_000));
+
+        client.send_heartbeat("agent-sensor").await.expect("heartbeat after restart");
+        assert_eq!(metrics.envelopes_accepted.get(), 3);
+        assert_eq!(
+            *states.lock().expect("states"),
+            vec![
+                ConnectionState::Connected,
+                ConnectionState::Disconnected,
+                ConnectionState::Connected,
+            ]
+        );
+
+        second.cancel();
+        let _ = std::fs::remove_file(&path);
     }
 }
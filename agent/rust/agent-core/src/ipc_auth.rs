@@ -0,0 +1,208 @@
+use std::env;
+use std::fmt;
+
+use agent_ipc::proto::agent_ipc::{AuthChallenge, AuthResponse};
+use agent_ipc::{handshake_mac, IPC_SCHEMA_VERSION};
+use hmac::Mac;
+
+const NONCE_BYTES: usize = 32;
+const MAX_CLIENT_ID_LEN: usize = 128;
+
+#[derive(Clone)]
+pub struct IpcAuthConfig {
+    pub key: Option<Vec<u8>>,
+    pub allow_anonymous: bool,
+}
+
+impl fmt::Debug for IpcAuthConfig {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_struct("IpcAuthConfig")
+            .field("key", &self.key.as_ref().map(|_| "<redacted>"))
+            .field("allow_anonymous", &self.allow_anonymous)
+            .finish()
+    }
+}
+
+impl IpcAuthConfig {
+    pub fn from_env() -> Self {
+        let key = env::var("AGENT_IPC_AUTH_KEY")
+            .ok()
+            .filter(|value| !value.is_empty())
+            .map(String::into_bytes);
+        let allow_anonymous = env::var("AGENT_IPC_ALLOW_ANON")
+            .ok()
+            .map(|value| value.eq_ignore_ascii_case("true"))
+            .unwrap_or(false);
+        Self { key, allow_anonymous }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IpcAuthError {
+    /// No key is configured and anonymous clients are not allowed.
+    NotConfigured,
+    ChallengeUnavailable,
+    NonceMismatch,
+    InvalidClientId,
+    BadMac,
+}
+
+impl fmt::Display for IpcAuthError {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self {
+            Self::NotConfigured => "no IPC auth key configured",
+            Self::ChallengeUnavailable => "unable to generate handshake challenge",
+            Self::NonceMismatch => "response does not answer the issued challenge",
+            Self::InvalidClientId => "client id missing or too long",
+            Self::BadMac => "handshake MAC did not verify",
+        };
+        formatter.write_str(message)
+    }
+}
+
+/// Per-connection handshake state. A connection starts unauthenticated (unless anonymous
+/// clients are allowed) and must not have any envelope routed until `authenticate` succeeds.
+#[derive(Debug)]
+pub struct IpcSession {
+    challenge: AuthChallenge,
+    client_id: Option<String>,
+}
+
+impl IpcSession {
+    /// First frame to write to the client.
+    pub fn challenge(&self) -> &AuthChallenge {
+        &self.challenge
+    }
+
+    pub fn is_authenticated(&self) -> bool {
+        self.client_id.is_some()
+    }
+
+    pub fn client_id(&self) -> Option<&str> {
+        self.client_id.as_deref()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct IpcAuthenticator {
+    config: IpcAuthConfig,
+}
+
+impl IpcAuthenticator {
+    pub fn new(config: IpcAuthConfig) -> Self {
+        Self { config }
+    }
+
+    pub fn open_session(&self) -> Result<IpcSession, IpcAuthError> {
+        let mut nonce = vec![0_u8; NONCE_BYTES];
+        getrandom::getrandom(&mut nonce).map_err(|_| IpcAuthError::ChallengeUnavailable)?;
+        let client_id = if self.config.allow_anonymous && self.config.key.is_none() {
+            Some("anonymous".to_string())
+        } else {
+            None
+        };
+        Ok(IpcSession {
+            challenge: AuthChallenge {
+                schema_version: IPC_SCHEMA_VERSION,
+                nonce,
+                sensor_event_batches: true,
+            },
+            client_id,
+        })
+    }
+
+    pub fn authenticate(&self, session: &mut IpcSession, response: &AuthResponse) -> Result<(), IpcAuthError> {
+        let key = self.config.key.as_deref().ok_or(IpcAuthError::NotConfigured)?;
+        if response.nonce != session.challenge.nonce {
+            return Err(IpcAuthError::NonceMismatch);
+        }
+        if response.client_id.is_empty() || response.client_id.len() > MAX_CLIENT_ID_LEN {
+            return Err(IpcAuthError::InvalidClientId);
+        }
+        handshake_mac(key, &response.client_id, &response.nonce)
+            .verify_slice(&response.mac)
+            .map_err(|_| IpcAuthError::BadMac)?;
+        session.client_id = Some(response.client_id.clone());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use agent_ipc::handshake_mac;
+    use agent_ipc::proto::agent_ipc::AuthResponse;
+    use hmac::Mac;
+
+    use super::{IpcAuthConfig, IpcAuthError, IpcAuthenticator};
+
+    fn authenticator(key: Option<&str>, allow_anonymous: bool) -> IpcAuthenticator {
+        IpcAuthenticator::new(IpcAuthConfig {
+            key: key.map(|value| value.as_bytes().to_vec()),
+            allow_anonymous,
+        })
+    }
+
+    fn answer(key: &str, client_id: &str, nonce: &[u8]) -> AuthResponse {
+        AuthResponse {
+            client_id: client_id.to_string(),
+            nonce: nonce.to_vec(),
+            mac: handshake_mac(key.as_bytes(), client_id, nonce).finalize().into_bytes().to_vec(),
+            wants_acks: false,
+        }
+    }
+
+    #[test]
+    fn accepts_client_holding_shared_key() {
+        let auth = authenticator(Some("shared-key"), false);
+        let mut session = auth.open_session().expect("session");
+        assert!(!session.is_authenticated());
+        assert_eq!(session.challenge().nonce.len(), 32);
+
+        let stale = answer("shared-key", "agent-sensor", &[0_u8; 32]);
+        assert_eq!(auth.authenticate(&mut session, &stale), Err(IpcAuthError::NonceMismatch));
+
+        let nonce = session.challenge().nonce.clone();
+        auth.authenticate(&mut session, &answer("shared-key", "agent-sensor", &nonce))
+            .expect("handshake succeeds");
+        assert!(session.is_authenticated());
+        assert_eq!(session.client_id(), Some("agent-sensor"));
+    }
+
+    #[test]
+    fn refuses_forged_replayed_or_missing_handshake() {
+        let auth = authenticator(Some("shared-key"), false);
+        let mut session = auth.open_session().expect("session");
+        let nonce = session.challenge().nonce.clone();
+
+        let forged = answer("wrong-key", "agent-sensor", &nonce);
+        assert_eq!(auth.authenticate(&mut session, &forged), Err(IpcAuthError::BadMac));
+
+        let mut tampered = answer("shared-key", "agent-sensor", &nonce);
+        tampered.client_id = "agent-exec".to_string();
+        assert_eq!(auth.authenticate(&mut session, &tampered), Err(IpcAuthError::BadMac));
+
+        let other = auth.open_session().expect("second session");
+        let replayed = answer("shared-key", "agent-sensor", &other.challenge().nonce);
+        assert_eq!(auth.authenticate(&mut session, &replayed), Err(IpcAuthError::NonceMismatch));
+
+        let absent = AuthResponse::default();
+        assert!(auth.authenticate(&mut session, &absent).is_err());
+        assert!(!session.is_authenticated());
+    }
+
+    #[test]
+    fn anonymous_sessions_only_with_escape_hatch() {
+        let closed = authenticator(None, false);
+        let mut session = closed.open_session().expect("session");
+        assert!(!session.is_authenticated());
+        let nonce = session.challenge().nonce.clone();
+        assert_eq!(
+            closed.authenticate(&mut session, &answer("any", "agent-sensor", &nonce)),
+            Err(IpcAuthError::NotConfigured)
+        );
+
+        let open = authenticator(None, true);
+        assert!(open.open_session().expect("session").is_authenticated());
+    }
+}
@@ -1,42 +1,195 @@
-use crate::command_router::{route_command, SignedCommand};
+use agent_ipc::sensor_batch_checksum_input;
+
+use crate::command_router::{route_command_with_config, CommandRouteConfig, SignedCommand};
 use crate::policy::PolicyBundle;
-use crate::telemetry_router::{route_telemetry, TelemetryPayload};
+use crate::telemetry_router::{sha256_hex, TelemetryPayload, TelemetryRouter};
 
 pub fn route_proto_envelope(
-    envelope: &crate::proto::agent_ipc::Envelope,
+    envelope: &agent_ipc::proto::agent_ipc::Envelope,
     policy: &PolicyBundle,
+    command_route: &CommandRouteConfig,
+    telemetry_router: &mut TelemetryRouter,
     now_unix_time_ms: u64,
 ) -> bool {
     match &envelope.payload {
-        Some(crate::proto::agent_ipc::envelope::Payload::ExecutionCommand(command)) => {
-            route_command(SignedCommand {
+        Some(agent_ipc::proto::agent_ipc::envelope::Payload::ExecutionCommand(command)) => {
+            route_command_with_config(SignedCommand {
                 command_id: command.command_id.clone(),
                 signed_payload: command.signed_blob.clone(),
+                signature: command.signature.clone(),
                 action: command.action.clone(),
                 arguments: command.arguments.clone(),
                 not_before_unix_time_ms: command.not_before_unix_time_ms,
                 not_after_unix_time_ms: command.not_after_unix_time_ms,
-            }, policy, now_unix_time_ms)
+            }, policy, command_route, now_unix_time_ms)
         }
-        Some(crate::proto::agent_ipc::envelope::Payload::SensorEvent(_)) => {
-            route_telemetry(TelemetryPayload {
+        Some(agent_ipc::proto::agent_ipc::envelope::Payload::SensorEvent(event)) => {
+            let raw_payload = prost::Message::encode_to_vec(event);
+            telemetry_router.route_at(TelemetryPayload {
                 stream: "sensor".to_string(),
                 payload_bytes: prost::Message::encoded_len(envelope),
                 event_count: 1,
-                checksum_sha256: None,
-            }, policy)
+                checksum_sha256: Some(sha256_hex(&raw_payload)),
+                raw_payload: Some(raw_payload),
+                category: sensor_category(event).map(str::to_string),
+            }, policy, now_unix_time_ms).accepted
+        }
+        Some(agent_ipc::proto::agent_ipc::envelope::Payload::SensorEventBatch(batch)) => {
+            if !batch
+                .events
+                .iter()
+                .all(|event| policy.allows_category("sensor", sensor_category(event)))
+            {
+                return false;
+            }
+            telemetry_router
+                .route_at(sensor_batch_payload(envelope, batch), policy, now_unix_time_ms)
+                .accepted
         }
-        Some(crate::proto::agent_ipc::envelope::Payload::ExecutionResult(_))
-        | Some(crate::proto::agent_ipc::envelope::Payload::EvidencePackage(_))
-        | Some(crate::proto::agent_ipc::envelope::Payload::ComplianceAssertion(_))
-        | Some(crate::proto::agent_ipc::envelope::Payload::HealthHeartbeat(_)) => {
-            route_telemetry(TelemetryPayload {
+        // Recorded against the local compliance checks by the IPC server, not routed as telemetry.
+        Some(agent_ipc::proto::agent_ipc::envelope::Payload::ComplianceAssertion(_)) => false,
+        // Staged evidence files are verified and queued for uplink by the IPC server.
+        Some(agent_ipc::proto::agent_ipc::envelope::Payload::EvidencePackage(package)) if !package.staged_path.is_empty() => false,
+        Some(payload @ agent_ipc::proto::agent_ipc::envelope::Payload::ExecutionResult(_))
+        | Some(payload @ agent_ipc::proto::agent_ipc::envelope::Payload::EvidencePackage(_))
+        | Some(payload @ agent_ipc::proto::agent_ipc::envelope::Payload::HealthHeartbeat(_)) => {
+            telemetry_router.route_at(TelemetryPayload {
                 stream: "agent".to_string(),
                 payload_bytes: prost::Message::encoded_len(envelope),
                 event_count: 1,
                 checksum_sha256: None,
-            }, policy)
+                raw_payload: None,
+                category: agent_category(payload).map(str::to_string),
+            }, policy, now_unix_time_ms).accepted
         }
         None => false,
     }
 }
+
+/// One telemetry payload for a whole batch. The router rejects it when the batch checksum
+/// does not match its events.
+fn sensor_batch_payload(
+    envelope: &agent_ipc::proto::agent_ipc::Envelope,
+    batch: &agent_ipc::proto::agent_ipc::SensorEventBatch,
+) -> TelemetryPayload {
+    TelemetryPayload {
+        stream: "sensor".to_string(),
+        payload_bytes: prost::Message::encoded_len(envelope),
+        event_count: batch.events.len(),
+        checksum_sha256: Some(batch.checksum_sha256.clone()),
+        raw_payload: Some(sensor_batch_checksum_input(&batch.events)),
+        category: batch.events.first().and_then(sensor_category).map(str::to_string),
+    }
+}
+
+/// Category a sensor event is routed under, for the policy's per-stream category allowlist.
+fn sensor_category(event: &agent_ipc::proto::agent_ipc::SensorEvent) -> Option<&'static str> {
+    use agent_ipc::proto::agent_ipc::sensor_event::Details;
+
+    match event.details.as_ref()? {
+        Details::ProcessStart(_) | Details::ProcessStop(_) => Some("process"),
+        Details::FileWrite(_) => Some("file"),
+        Details::RegistryChange(_) => Some("registry"),
+        Details::NetworkConnection(_) => Some("network"),
+    }
+}
+
+fn agent_category(payload: &agent_ipc::proto::agent_ipc::envelope::Payload) -> Option<&'static str> {
+    use agent_ipc::proto::agent_ipc::envelope::Payload;
+
+    match payload {
+        Payload::ExecutionResult(_) => Some("execution"),
+        Payload::EvidencePackage(_) => Some("evidence"),
+        Payload::HealthHeartbeat(_) => Some("health"),
+        Payload::ExecutionCommand(_)
+        | Payload::SensorEvent(_)
+        | Payload::SensorEventBatch(_)
+        | Payload::ComplianceAssertion(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use agent_ipc::proto::agent_ipc::envelope::Payload;
+    use agent_ipc::proto::agent_ipc::sensor_event::Details;
+    use agent_ipc::proto::agent_ipc::{Envelope, FileWrite, SensorEvent, SensorEventBatch};
+    use agent_ipc::sensor_event_batch;
+
+    use super::{route_proto_envelope, sensor_batch_payload};
+    use crate::command_router::CommandRouteConfig;
+    use crate::identity::AgentIdentity;
+    use crate::policy::PolicyBundle;
+    use crate::telemetry_router::{TelemetryRouteConfig, TelemetryRouter};
+
+    fn file_write(path: &str) -> SensorEvent {
+        SensorEvent {
+            event_type: 3,
+            details: Some(Details::FileWrite(FileWrite {
+                path: path.to_string(),
+                process_image: "writer.exe".to_string(),
+                pid: 42,
+                unix_time_ms: 1,
+            })),
+        }
+    }
+
+    fn batch_envelope(batch: SensorEventBatch) -> Envelope {
+        Envelope {
+            schema_version: 1,
+            asset_id: "asset-1".to_string(),
+            agent_id: "agent-1".to_string(),
+            unix_time_ms: 1,
+            envelope_id: String::new(),
+            payload: Some(Payload::SensorEventBatch(batch)),
+        }
+    }
+
+    fn router() -> TelemetryRouter {
+        TelemetryRouter::new(
+            AgentIdentity::new("asset-1".to_string(), "agent-1".to_string()),
+            TelemetryRouteConfig {
+                max_payload_bytes: 64 * 1024,
+                min_payload_bytes: 1,
+                max_event_count: 3,
+                require_checksum: true,
+                stream_quotas: HashMap::new(),
+                require_valid_policy: false,
+            },
+        )
+    }
+
+    #[test]
+    fn routes_sensor_batch_as_one_payload_with_event_count() {
+        let policy = PolicyBundle::placeholder();
+        let mut router = router();
+        let batch = sensor_event_batch(vec![file_write("a.txt"), file_write("b.txt"), file_write("c.txt")]);
+        let envelope = batch_envelope(batch.clone());
+
+        let payload = sensor_batch_payload(&envelope, &batch);
+        assert_eq!(payload.event_count, 3);
+        assert_eq!(payload.category.as_deref(), Some("file"));
+        assert!(route_proto_envelope(&envelope, &policy, &CommandRouteConfig::from_env(), &mut router, 1));
+
+        // Four events exceed the router's max_event_count, so the count must be carried.
+        let oversized = sensor_event_batch(vec![file_write("a.txt"); 4]);
+        assert!(!route_proto_envelope(&batch_envelope(oversized), &policy, &CommandRouteConfig::from_env(), &mut router, 1));
+    }
+
+    #[test]
+    fn rejects_whole_batch_on_checksum_mismatch() {
+        let policy = PolicyBundle::placeholder();
+        let mut router = router();
+        let mut batch = sensor_event_batch(vec![file_write("a.txt"), file_write("b.txt")]);
+        batch.events[1] = file_write("tampered.txt");
+        assert!(!route_proto_envelope(&batch_envelope(batch), &policy, &CommandRouteConfig::from_env(), &mut router, 1));
+
+        let mut restricted = PolicyBundle::placeholder();
+        restricted
+            .stream_categories
+            .insert("sensor".to_string(), vec!["process".to_string()]);
+        let batch = sensor_event_batch(vec![file_write("a.txt")]);
+        assert!(!route_proto_envelope(&batch_envelope(batch), &restricted, &CommandRouteConfig::from_env(), &mut router, 1));
+    }
+}
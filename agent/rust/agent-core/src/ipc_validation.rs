@@ -1,3 +1,8 @@
+use agent_ipc::MAX_SENSOR_BATCH_EVENTS;
+
+/// Longest `envelope_id` the server keeps for recognising resent envelopes.
+pub const MAX_ENVELOPE_ID_LEN: usize = 128;
+
 #[derive(Debug, Clone)]
 pub struct EnvelopeMeta {
     pub schema_version: u32,
@@ -13,20 +18,33 @@ pub fn validate_payload_size(payload_bytes: usize, max_payload_bytes: usize) ->
 }
 
 pub fn validate_proto_envelope(
-    envelope: &crate::proto::agent_ipc::Envelope,
+    envelope: &agent_ipc::proto::agent_ipc::Envelope,
     expected_version: u32,
     max_payload_bytes: usize,
 ) -> bool {
     let schema_ok = validate_schema_version(envelope.schema_version, expected_version);
-    let payload_ok = envelope.payload.is_some();
+    let payload_ok = match &envelope.payload {
+        Some(agent_ipc::proto::agent_ipc::envelope::Payload::SensorEventBatch(batch)) => validate_sensor_batch(batch),
+        Some(_) => true,
+        None => false,
+    };
+    let id_ok = envelope.envelope_id.len() <= MAX_ENVELOPE_ID_LEN;
     let encoded_len = prost::Message::encoded_len(envelope);
     let size_ok = validate_payload_size(encoded_len, max_payload_bytes);
-    schema_ok && payload_ok && size_ok
+    schema_ok && payload_ok && id_ok && size_ok
+}
+
+/// A batch must hold between one and `MAX_SENSOR_BATCH_EVENTS` events and name its checksum;
+/// its total size is bounded with the rest of the envelope.
+fn validate_sensor_batch(batch: &agent_ipc::proto::agent_ipc::SensorEventBatch) -> bool {
+    (1..=MAX_SENSOR_BATCH_EVENTS).contains(&batch.events.len()) && !batch.checksum_sha256.trim().is_empty()
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{validate_payload_size, validate_proto_envelope, validate_schema_version};
+    use agent_ipc::MAX_SENSOR_BATCH_EVENTS;
+
+    use super::{validate_payload_size, validate_proto_envelope, validate_schema_version, MAX_ENVELOPE_ID_LEN};
 
     #[test]
     fn validates_schema_version() {
@@ -42,13 +60,14 @@ mod tests {
 
     #[test]
     fn validates_proto_envelope() {
-        let envelope = crate::proto::agent_ipc::Envelope {
+        let envelope = agent_ipc::proto::agent_ipc::Envelope {
             schema_version: 1,
             asset_id: "asset".to_string(),
             agent_id: "agent".to_string(),
             unix_time_ms: 1,
-            payload: Some(crate::proto::agent_ipc::envelope::Payload::HealthHeartbeat(
-                crate::proto::agent_ipc::HealthHeartbeat {
+            envelope_id: String::new(),
+            payload: Some(agent_ipc::proto::agent_ipc::envelope::Payload::HealthHeartbeat(
+                agent_ipc::proto::agent_ipc::HealthHeartbeat {
                     service_name: "agent-core".to_string(),
                     unix_time_ms: 1,
                 },
@@ -58,5 +77,36 @@ mod tests {
         assert!(validate_proto_envelope(&envelope, 1, 1024));
         assert!(!validate_proto_envelope(&envelope, 2, 1024));
         assert!(!validate_proto_envelope(&envelope, 1, 1));
+
+        let long_id = agent_ipc::proto::agent_ipc::Envelope {
+            envelope_id: "e".repeat(MAX_ENVELOPE_ID_LEN + 1),
+            ..envelope
+        };
+        assert!(!validate_proto_envelope(&long_id, 1, 1024));
+    }
+
+    #[test]
+    fn bounds_sensor_event_batches() {
+        use agent_ipc::proto::agent_ipc::envelope::Payload;
+        use agent_ipc::proto::agent_ipc::{Envelope, SensorEvent, SensorEventBatch};
+
+        let batch_envelope = |count: usize, checksum: &str| Envelope {
+            schema_version: 1,
+            asset_id: "asset".to_string(),
+            agent_id: "agent".to_string(),
+            unix_time_ms: 1,
+            envelope_id: String::new(),
+            payload: Some(Payload::SensorEventBatch(SensorEventBatch {
+                events: vec![SensorEvent::default(); count],
+                checksum_sha256: checksum.to_string(),
+            })),
+        };
+
+        assert!(validate_proto_envelope(&batch_envelope(3, "abc"), 1, 64 * 1024));
+        assert!(validate_proto_envelope(&batch_envelope(MAX_SENSOR_BATCH_EVENTS, "abc"), 1, 64 * 1024));
+        assert!(!validate_proto_envelope(&batch_envelope(MAX_SENSOR_BATCH_EVENTS + 1, "abc"), 1, 64 * 1024));
+        assert!(!validate_proto_envelope(&batch_envelope(0, "abc"), 1, 64 * 1024));
+        assert!(!validate_proto_envelope(&batch_envelope(3, " "), 1, 64 * 1024));
+        assert!(!validate_proto_envelope(&batch_envelope(MAX_SENSOR_BATCH_EVENTS, "abc"), 1, 1024));
     }
 }
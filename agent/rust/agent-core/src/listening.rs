@@ -0,0 +1,377 @@
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    Tcp,
+    Udp,
+}
+
+impl Protocol {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Protocol::Tcp => "tcp",
+            Protocol::Udp => "udp",
+        }
+    }
+}
+
+/// A socket accepting traffic on this host and, when the platform lets us see it, the
+/// process that owns it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ListeningService {
+    pub port: u16,
+    pub protocol: Protocol,
+    pub bind_address: IpAddr,
+    pub pid: Option<u32>,
+    pub process_name: Option<String>,
+}
+
+impl ListeningService {
+    /// Bound to every interface (`0.0.0.0` or `::`).
+    pub fn is_wildcard(&self) -> bool {
+        self.bind_address.is_unspecified()
+    }
+}
+
+/// Where listening sockets come from: procfs on Linux, `netstat -ano` on Windows.
+pub trait ListeningSocketSource {
+    fn listening(&self) -> Result<Vec<ListeningService>, String>;
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PlatformSocketSource;
+
+#[cfg(target_os = "linux")]
+impl ListeningSocketSource for PlatformSocketSource {
+    fn listening(&self) -> Result<Vec<ListeningService>, String> {
+        let tables = [
+            ("/proc/net/tcp", Protocol::Tcp),
+            ("/proc/net/tcp6", Protocol::Tcp),
+            ("/proc/net/udp", Protocol::Udp),
+            ("/proc/net/udp6", Protocol::Udp),
+        ];
+        let mut sockets = Vec::new();
+        let mut readable = false;
+        for (path, protocol) in tables {
+            if let Ok(raw) = std::fs::read_to_string(path) {
+                readable = true;
+                sockets.extend(parse_proc_net(&raw, protocol));
+            }
+        }
+        if !readable {
+            return Err("no /proc/net socket table is readable".to_string());
+        }
+        let owners = socket_owners();
+        Ok(sockets
+            .into_iter()
+            .map(|(mut service, inode)| {
+                if let Some((pid, name)) = owners.get(&inode) {
+                    service.pid = Some(*pid);
+                    service.process_name = Some(name.clone());
+                }
+                service
+            })
+            .collect())
+    }
+}
+
+/// Socket inode to owning `(pid, comm)`, from `/proc/<pid>/fd`. Processes we may not
+/// inspect are skipped, leaving their sockets without an owner.
+#[cfg(target_os = "linux")]
+fn socket_owners() -> HashMap<u64, (u32, String)> {
+    let mut owners = HashMap::new();
+    let Ok(processes) = std::fs::read_dir("/proc") else {
+        return owners;
+    };
+    for process in processes.flatten() {
+        let Some(pid) = process.file_name().to_str().and_then(|name| name.parse::<u32>().ok()) else {
+            continue;
+        };
+        let Ok(fds) = std::fs::read_dir(process.path().join("fd")) else {
+            continue;
+        };
+        let name = std::fs::read_to_string(process.path().join("comm"))
+            .map(|comm| comm.trim().to_string())
+            .unwrap_or_default();
+        for fd in fds.flatten() {
+            let Ok(target) = std::fs::read_link(fd.path()) else {
+                continue;
+            };
+            let inode = target
+                .to_str()
+                .and_then(|target| target.strip_prefix("socket:["))
+                .and_then(|target| target.strip_suffix(']'))
+                .and_then(|inode| inode.parse::<u64>().ok());
+            if let Some(inode) = inode {
+                owners.entry(inode).or_insert_with(|| (pid, name.clone()));
+            }
+        }
+    }
+    owners
+}
+
+#[cfg(windows)]
+impl ListeningSocketSource for PlatformSocketSource {
+    fn listening(&self) -> Result<Vec<ListeningService>, String> {
+        let netstat = run_capture("netstat", &["-ano"])?;
+        let names = run_capture("tasklist", &["/FO", "CSV", "/NH"])
+            .map(|raw| parse_tasklist(&raw))
+            .unwrap_or_default();
+        Ok(parse_netstat(&netstat)
+            .into_iter()
+            .map(|mut service| {
+                service.process_name = service.pid.and_then(|pid| names.get(&pid).cloned());
+                service
+            })
+            .collect())
+    }
+}
+
+/// Longest `netstat` or `tasklist` may run before it is killed.
+#[cfg(windows)]
+const CAPTURE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+#[cfg(windows)]
+fn run_capture(program: &str, args: &[&str]) -> Result<String, String> {
+    use std::io::Read;
+    use std::process::{Command, Stdio};
+    use std::time::Instant;
+
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|err| format!("failed to run {}: {}", program, err))?;
+    let mut stdout = child.stdout.take().ok_or_else(|| format!("{} has no stdout", program))?;
+    // Drain stdout on its own thread so a full pipe cannot stall the child.
+    let reader = std::thread::spawn(move || {
+        let mut raw = Vec::new();
+        stdout.read_to_end(&mut raw).map(|_| raw)
+    });
+    let deadline = Instant::now() + CAPTURE_TIMEOUT;
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if Instant::now() >= deadline => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!("{} timed out after {} s", program, CAPTURE_TIMEOUT.as_secs()));
+            }
+            Ok(None) => std::thread::sleep(std::time::Duration::from_millis(50)),
+            Err(err) => return Err(format!("failed to wait for {}: {}", program, err)),
+        }
+    };
+    let raw = reader
+        .join()
+        .map_err(|_| format!("{} output reader panicked", program))?
+        .map_err(|err| format!("failed to read {} output: {}", program, err))?;
+    if !status.success() {
+        return Err(format!("{} exited with {:?}", program, status.code()));
+    }
+    Ok(String::from_utf8_lossy(&raw).into_owned())
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+impl ListeningSocketSource for PlatformSocketSource {
+    fn listening(&self) -> Result<Vec<ListeningService>, String> {
+        Ok(Vec::new())
+    }
+}
+
+/// Listening sockets in a `/proc/net/{tcp,tcp6,udp,udp6}` table with their socket inodes.
+/// TCP sockets count in the `LISTEN` state; UDP sockets when they have no remote peer.
+pub fn parse_proc_net(raw: &str, protocol: Protocol) -> Vec<(ListeningService, u64)> {
+    raw.lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields = line.split_whitespace().collect::<Vec<&str>>();
+            let (local, remote, state, inode) = (fields.get(1)?, fields.get(2)?, fields.get(3)?, fields.get(9)?);
+            let listening = match protocol {
+                Protocol::Tcp => *state == "0A",
+                Protocol::Udp => remote.ends_with(":0000"),
+            };
+            if !listening {
+                return None;
+            }
+            let (address, port) = local.split_once(':')?;
+            let service = ListeningService {
+                port: u16::from_str_radix(port, 16).ok()?,
+                protocol,
+                bind_address: parse_proc_address(address)?,
+                pid: None,
+                process_name: None,
+            };
+            Some((service, inode.parse::<u64>().ok()?))
+        })
+        .collect()
+}
+
+/// procfs prints addresses as 32-bit words in host byte order.
+fn parse_proc_address(hex: &str) -> Option<IpAddr> {
+    let words = (0..hex.len() / 8)
+        .map(|index| u32::from_str_radix(hex.get(index * 8..index * 8 + 8)?, 16).ok())
+        .collect::<Option<Vec<u32>>>()?;
+    match words.as_slice() {
+        [word] => Some(IpAddr::V4(Ipv4Addr::from(word.to_ne_bytes()))),
+        [a, b, c, d] => {
+            let mut octets = [0u8; 16];
+            for (chunk, word) in octets.chunks_mut(4).zip([a, b, c, d]) {
+                chunk.copy_from_slice(&word.to_ne_bytes());
+            }
+            Some(IpAddr::V6(Ipv6Addr::from(octets)))
+        }
+        _ => None,
+    }
+}
+
+/// Listening rows of `netstat -ano`: TCP rows in `LISTENING` and every UDP row.
+pub fn parse_netstat(raw: &str) -> Vec<ListeningService> {
+    raw.lines()
+        .filter_map(|line| {
+            let fields = line.split_whitespace().collect::<Vec<&str>>();
+            let (protocol, local, pid) = match fields.as_slice() {
+                [proto, local, _, state, pid] if proto.eq_ignore_ascii_case("tcp") && *state == "LISTENING" => {
+                    (Protocol::Tcp, *local, *pid)
+                }
+                [proto, local, _, pid] if proto.eq_ignore_ascii_case("udp") => (Protocol::Udp, *local, *pid),
+                _ => return None,
+            };
+            let (address, port) = local.rsplit_once(':')?;
+            let address = address.trim_start_matches('[').trim_end_matches(']');
+            let address = address.split('%').next().unwrap_or(address);
+            Some(ListeningService {
+                port: port.parse::<u16>().ok()?,
+                protocol,
+                bind_address: address.parse::<IpAddr>().ok()?,
+                pid: pid.parse::<u32>().ok(),
+                process_name: None,
+            })
+        })
+        .collect()
+}
+
+/// Image name by pid from `tasklist /FO CSV /NH`.
+pub fn parse_tasklist(raw: &str) -> HashMap<u32, String> {
+    raw.lines()
+        .filter_map(|line| {
+            let mut fields = line.split("\",\"").map(|field| field.trim_matches('"'));
+            let name = fields.next()?.to_string();
+            let pid = fields.next()?.parse::<u32>().ok()?;
+            Some((pid, name))
+        })
+        .collect()
+}
+
+/// One entry per `(port, protocol)`, preferring a wildcard binding and a known owner, in
+/// protocol then port order. At most `max_services` distinct entries are kept.
+pub fn dedup_services(services: Vec<ListeningService>, max_services: usize) -> Vec<ListeningService> {
+    let mut unique: BTreeMap<(Protocol, u16), ListeningService> = BTreeMap::new();
+    let rank = |service: &ListeningService| (service.is_wildcard(), service.process_name.is_some());
+    for service in services {
+        let key = (service.protocol, service.port);
+        let full = unique.len() >= max_services;
+        match unique.get_mut(&key) {
+            Some(existing) if rank(&service) > rank(existing) => *existing = service,
+            Some(_) => {}
+            None if !full => {
+                unique.insert(key, service);
+            }
+            None => {}
+        }
+    }
+    unique.into_values().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use super::{dedup_services, parse_netstat, parse_proc_net, parse_tasklist, Protocol};
+
+    const PROC_NET_TCP: &str = "\
+  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode
+   0: 00000000:0D3D 00000000:0000 0A 00000000:00000000 00:00000000 00000000     0        0 1001 1 0000000000000000 100 0 0 10 0
+   1: 0100007F:1538 00000000:0000 0A 00000000:00000000 00:00000000 00000000   999        0 1002 1 0000000000000000 100 0 0 10 0
+   2: 0F02000A:0016 0A02000A:D431 01 00000000:00000000 02:0000A0B3 00000000     0        0 1003 4 0000000000000000 20 4 30 10 -1
+";
+
+    const PROC_NET_UDP6: &str = "\
+  sl  local_address                         remote_address                        st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode ref pointer drops
+  10: 00000000000000000000000000000000:14E9 00000000000000000000000000000000:0000 07 00000000:00000000 00:00000000 00000000   104        0 2001 2 0000000000000000 0
+  11: 00000000000000000000000001000000:0035 00000000000000000000000000000000:0000 07 00000000:00000000 00:00000000 00000000   101        0 2002 2 0000000000000000 0
+";
+
+    const NETSTAT: &str = "
+Active Connections
+
+  Proto  Local Address          Foreign Address        State           PID
+  TCP    0.0.0.0:3389           0.0.0.0:0              LISTENING       1048
+  TCP    127.0.0.1:5939         0.0.0.0:0              LISTENING       3120
+  TCP    10.0.0.5:49712         52.1.1.1:443           ESTABLISHED     4410
+  TCP    [::]:5985              [::]:0                 LISTENING       4
+  UDP    0.0.0.0:5353           *:*                                    2200
+  UDP    [fe80::1%4]:1900       *:*                                    3000
+";
+
+    #[test]
+    fn parses_proc_net_tables() {
+        let tcp = parse_proc_net(PROC_NET_TCP, Protocol::Tcp);
+        let listening = tcp
+            .iter()
+            .map(|(service, inode)| (service.bind_address.to_string(), service.port, *inode))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            listening,
+            vec![("0.0.0.0".to_string(), 3389, 1001), ("127.0.0.1".to_string(), 5432, 1002)]
+        );
+        assert!(tcp[0].0.is_wildcard());
+
+        let udp = parse_proc_net(PROC_NET_UDP6, Protocol::Udp);
+        assert_eq!(udp.len(), 2);
+        assert_eq!(udp[0].0.bind_address, "::".parse::<IpAddr>().expect("ipv6 any"));
+        assert_eq!(udp[0].0.port, 5353);
+        assert_eq!(udp[1].0.bind_address, "::1".parse::<IpAddr>().expect("ipv6 loopback"));
+        assert_eq!(udp[1].0.port, 53);
+    }
+
+    #[test]
+    fn parses_netstat_and_tasklist_output() {
+        let services = parse_netstat(NETSTAT);
+        let summary = services
+            .iter()
+            .map(|service| (service.protocol, service.bind_address.to_string(), service.port, service.pid))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                (Protocol::Tcp, "0.0.0.0".to_string(), 3389, Some(1048)),
+                (Protocol::Tcp, "127.0.0.1".to_string(), 5939, Some(3120)),
+                (Protocol::Tcp, "::".to_string(), 5985, Some(4)),
+                (Protocol::Udp, "0.0.0.0".to_string(), 5353, Some(2200)),
+                (Protocol::Udp, "fe80::1".to_string(), 1900, Some(3000)),
+            ]
+        );
+
+        let names = parse_tasklist("\"System\",\"4\",\"Services\",\"0\",\"144 K\"\r\n\"svchost.exe\",\"1048\",\"Services\",\"0\",\"9,812 K\"\r\n");
+        assert_eq!(names.get(&4).map(String::as_str), Some("System"));
+        assert_eq!(names.get(&1048).map(String::as_str), Some("svchost.exe"));
+    }
+
+    #[test]
+    fn dedups_per_port_and_protocol_within_bound() {
+        let mut services = parse_netstat(NETSTAT);
+        services.extend(parse_netstat("  TCP    127.0.0.1:3389    0.0.0.0:0    LISTENING    77\n  TCP    0.0.0.0:8080    0.0.0.0:0    LISTENING    78"));
+        let unique = dedup_services(services.clone(), 16);
+        assert_eq!(unique.len(), 6);
+        let rdp = unique.iter().find(|service| service.port == 3389).expect("rdp");
+        assert!(rdp.is_wildcard());
+        assert_eq!(rdp.pid, Some(1048));
+        assert_eq!(dedup_services(services, 3).len(), 3);
+    }
+}
@@ -1,61 +1,163 @@
+use std::env;
+use std::path::Path;
+use std::sync::Arc;
 use std::time::Duration;
 
 use tokio::signal;
 use tracing::{info, warn};
 
+mod audit;
+mod certificates;
+mod circuit_breaker;
+mod clock_drift;
+mod command_log;
 mod command_router;
 mod compliance;
+mod compression;
 mod config;
+mod connectivity;
+mod crypto;
+mod dead_letter;
+mod detection_response;
 mod edr;
+mod enrichment;
 mod evidence;
+mod executor;
+mod file_place;
+mod heartbeat;
+mod host_facts;
 mod identity;
+mod intake_payload;
 mod ipc;
+mod ipc_auth;
 mod ipc_router;
 mod ipc_validation;
+mod listening;
+mod metrics;
+#[cfg(feature = "otlp")]
+mod otlp;
+mod patch;
+mod path_guard;
 mod pipeline;
 mod policy;
-mod proto;
 mod rate_limit;
+mod redaction;
 mod rmm;
+mod rmm_dispatch;
 mod security;
+mod seen_commands;
+mod self_monitor;
+mod self_telemetry;
+mod sensor_evidence;
 mod service_registry;
+mod shutdown;
 mod siem;
+mod state_dir;
+mod telemetry_buffer;
+mod telemetry_chain;
+mod telemetry_format;
 mod telemetry_router;
 mod time;
 mod uplink;
 mod update;
+mod update_orchestrator;
+mod vuln_feed;
 mod vulnerability;
 
-use crate::command_router::{route_command, SignedCommand};
-use crate::compliance::run_self_audit;
+use crate::audit::{AuditConfig, AuditEvent, AuditLog};
+use crate::command_log::CommandLog;
+use crate::command_router::{route_command, CommandRouteConfig, SignedCommand};
+use crate::clock_drift::drift_compliance_result;
+use crate::compliance::{run_compliance_loop, ComplianceConfig, ComplianceSource};
 use crate::config::CoreConfig;
-use crate::edr::evaluate_rules;
+use crate::detection_response::{run_edr_loop, DetectionResponder, EdrCycle};
+use crate::edr::{load_rules, DetectionTracker, EdrConfig};
+use crate::enrichment::Enricher;
+use crate::heartbeat::{write_liveness_file, HeartbeatConfig, HeartbeatSender};
 use crate::identity::{verify_trust_bundle, AgentIdentity};
 use crate::ipc::IpcServer;
-use crate::pipeline::PipelineStatus;
+use crate::metrics::{serve_metrics, AgentMetrics, MetricsConfig};
+use crate::pipeline::{freshness, ComponentHealth, PipelineHealth};
 use crate::policy::PolicyBundle;
 use crate::rate_limit::RateLimiter;
-use crate::rmm::queue_execution_request;
+use crate::rmm::{explain_execution_request, load_execution_requests, RmmCommandQueue, RmmConfig};
+use crate::rmm_dispatch::{run_rmm_loop, RmmDispatcher};
 use crate::service_registry::{ServiceDescriptor, ServiceRegistry};
-use crate::siem::prepare_telemetry_batch;
+use crate::shutdown::{ShutdownConfig, ShutdownCoordinator, ShutdownOutcome};
+use crate::self_monitor::{run_self_monitor, PlatformSampler, SelfMonitorConfig, SharedDegradation};
+use crate::self_telemetry::{SelfTelemetryConfig, SelfTelemetrySink};
+use crate::siem::{prepare_telemetry_batch, TelemetryConfig};
+use crate::state_dir::{AgentStateDir, StatePaths};
+use crate::telemetry_buffer::buffer_batch;
+use crate::telemetry_format::LocalSyslogForwarder;
 use crate::telemetry_router::{route_telemetry, TelemetryPayload};
-use crate::time::unix_time_ms;
-use crate::uplink::{process_uplink_queue, run_uplink_worker};
-use crate::vulnerability::assess_exposure;
+use crate::time::{clock_status, unix_time_ms, ClockStatus};
+use crate::update::verify_update;
+use crate::update_orchestrator::{run_pending_update, OrchestratorConfig};
+use crate::uplink::{build_client, process_uplink_queue_with_config, run_uplink_worker, UplinkConfig, UplinkWorkerConfig};
+use crate::vuln_feed::{refresh_feed, FeedConfig};
+use crate::vulnerability::{assess_exposure, assess_exposure_with_feed, parse_cve_feed};
 
 #[tokio::main]
 async fn main() {
-    tracing_subscriber::fmt()
-        .with_env_filter("info")
-        .init();
+    let self_telemetry = SelfTelemetrySink::new(SelfTelemetryConfig::from_env());
+    agent_logging::init_with_layers("agent-core", vec![Box::new(self_telemetry.layer())]);
 
-    let config = CoreConfig::from_env();
+    let started_at_unix_ms = unix_time_ms();
+    let config = match env::var("AGENT_CORE_CONFIG_PATH").ok().filter(|value| !value.trim().is_empty()) {
+        Some(path) => match CoreConfig::from_file(Path::new(&path)) {
+            Ok(config) => config,
+            Err(err) => {
+                warn!(error = %err, "agent config file rejected; refusing to start");
+                return;
+            }
+        },
+        None => CoreConfig::from_env(),
+    };
     let identity = AgentIdentity::new(config.asset_id.clone(), config.agent_id.clone());
+    let enricher = Enricher::from_env(&identity);
+    let metrics = AgentMetrics::new_handle();
+    let shutdown = ShutdownCoordinator::new();
+    if let Some(addr) = MetricsConfig::from_env().bind_addr {
+        shutdown.spawn(serve_metrics(addr, metrics.clone(), shutdown.token()));
+    }
 
     info!(asset_id = %identity.asset_id, agent_id = %identity.agent_id, "agent core starting");
 
+    let state_dir = match AgentStateDir::acquire(StatePaths::from_env()) {
+        Ok(state_dir) => state_dir,
+        Err(err) => {
+            warn!(error = %err, "agent state directory unavailable; refusing to start services");
+            return;
+        }
+    };
+
+    let audit_config = AuditConfig::from_env();
+    if audit_config.key.is_none() {
+        warn!("AGENT_AUDIT_KEY is not set; the audit chain is unkeyed and can be rewritten by anyone with write access");
+    }
+    match AuditLog::open(audit_config.clone()) {
+        Ok(log) => audit::install(log),
+        Err(err) => warn!(error = %err, "audit log unavailable; security decisions will not be recorded"),
+    }
+    match audit::verify_chain(&audit_config) {
+        Ok(records) => info!(records, "audit chain verified"),
+        Err(err) => warn!(error = %err, "audit chain failed verification"),
+    }
+
+    let uplink_config = match UplinkConfig::from_env(state_dir.paths()) {
+        Ok(uplink_config) => uplink_config,
+        Err(err) => {
+            warn!(error = %err, "uplink endpoints rejected; refusing to start services");
+            return;
+        }
+    };
+
     let trust_report = verify_trust_bundle();
     if !trust_report.verified {
+        audit::record(AuditEvent::TrustBundleFailed {
+            failures: trust_report.failures.clone(),
+        });
         warn!("trust bundle verification failed; refusing to start services");
         return;
     }
@@ -63,19 +165,40 @@ async fn main() {
     let policy = PolicyBundle::from_env();
     let policy_now = unix_time_ms();
     let validation_options = crate::policy::PolicyValidationOptions::from_env();
-    if !policy.validate(policy_now, &validation_options) {
-        warn!("policy validation failed; refusing to start services");
+    let migrated_policy = match policy.migrate() {
+        Ok(migrated) => migrated,
+        Err(err) => {
+            audit::record(AuditEvent::PolicyRejected { reason: err.to_string() });
+            warn!(error = %err, "policy schema not usable; refusing to start services");
+            return;
+        }
+    };
+    if let Err(err) = policy.check(policy_now, &validation_options) {
+        audit::record(AuditEvent::PolicyRejected { reason: err.to_string() });
+        warn!(error = %err, "policy validation failed; refusing to start services");
         return;
     }
+    let policy = migrated_policy;
+    audit::record(AuditEvent::PolicyLoaded {
+        version: policy.version.clone(),
+    });
+    metrics.policy_last_reload_unix_ms.set(policy_now);
 
     let rate_limiter = RateLimiter::new(600);
-    let ipc_server = IpcServer::new(
+    let ipc_server = Arc::new(IpcServer::new(
         config.ipc_pipe_name.clone(),
         config.max_payload_bytes,
         rate_limiter,
         policy.clone(),
-    );
-    ipc_server.start();
+        metrics.clone(),
+        state_dir.paths(),
+    ));
+    let ipc_configured = ipc_server.endpoint_configured();
+    if ipc_configured {
+        shutdown.spawn(Arc::clone(&ipc_server).run(shutdown.token()));
+    } else {
+        info!(pipe = %ipc_server.pipe_name, "ipc endpoint not usable on this platform; listener not started");
+    }
 
     let mut registry = ServiceRegistry::new();
     registry.register(ServiceDescriptor {
@@ -89,35 +212,215 @@ async fn main() {
         ipc_endpoint: "exec-pipe".to_string(),
     });
 
-    let _compliance_results = run_self_audit();
-    let _detections = evaluate_rules();
-    let _execution_request = queue_execution_request(&policy);
-    let _telemetry_batch = prepare_telemetry_batch();
-    let _vulnerability_findings = assess_exposure();
+    let edr_config = EdrConfig::from_env();
+    let edr_rules = load_rules(&edr_config);
+    match &edr_rules {
+        Ok(rules) => {
+            let cycle = EdrCycle::new(
+                edr_config,
+                rules.clone(),
+                DetectionTracker::from_env(),
+                DetectionResponder::from_env(&identity.asset_id, &uplink_config.queue_dir, state_dir.paths()),
+            );
+            shutdown.spawn(run_edr_loop(cycle, policy.clone(), metrics.clone(), shutdown.token()));
+        }
+        Err(err) => warn!(error = %err, "edr rules failed to load; detections disabled"),
+    }
+    let siem_config = TelemetryConfig::from_env().validate();
+    if RmmConfig::from_env().dry_run {
+        if let Some(decision) = explain_execution_request(&policy) {
+            info!(
+                command_id = %decision.command_id,
+                allowed = decision.allowed(),
+                failed_checks = ?decision.failed_checks(),
+                "rmm dry-run evaluated pending command"
+            );
+        }
+    } else if CommandRouteConfig::from_env().verifier.is_none() {
+        warn!("AGENT_POLICY_SIGNING_KEY is not set; rmm commands cannot be verified and will not be dispatched");
+    } else {
+        let loaded = load_execution_requests(&policy, &CommandRouteConfig::from_env());
+        let mut dispatcher = RmmDispatcher::from_env(policy.clone(), metrics.clone(), uplink_config.queue_dir.clone());
+        let execution_requests = match CommandLog::from_env() {
+            Some(mut command_log) => {
+                let requests = command_log
+                    .reconcile(loaded, &ipc_server.seen_commands, &policy, &uplink_config.queue_dir)
+                    .await;
+                dispatcher = dispatcher.with_command_log(command_log, Arc::clone(&ipc_server.seen_commands));
+                requests
+            }
+            None => loaded,
+        };
+        shutdown.spawn(run_rmm_loop(dispatcher, execution_requests, RmmCommandQueue::from_env(), shutdown.token()));
+    }
+    let telemetry_batch = prepare_telemetry_batch(&enricher);
+    metrics.record_telemetry_batch(&telemetry_batch);
+    if let Some(forwarder) = LocalSyslogForwarder::from_env() {
+        if let Err(err) = forwarder.forward(&telemetry_batch.events) {
+            warn!(error = %err, "syslog forwarding failed");
+        }
+    }
+    if telemetry_batch.event_count > 0 {
+        buffer_batch(&telemetry_batch, &uplink_config.telemetry_buffer_dir, &uplink_config.queue_dir);
+    }
+    #[cfg(feature = "otlp")]
+    let _otlp_exported = crate::otlp::export_batch(&telemetry_batch).await;
+    let mut compliance_sources: Vec<ComplianceSource> = vec![Box::new(drift_compliance_result)];
+    let feed_config = FeedConfig::from_env();
+    let _vulnerability_findings = if feed_config.url.is_some() {
+        let feed = refresh_feed(&build_client(&uplink_config), &feed_config, unix_time_ms()).await;
+        let findings = assess_exposure_with_feed(feed.body().map(parse_cve_feed).unwrap_or_default());
+        let staleness_config = feed_config.clone();
+        compliance_sources.push(Box::new(move |now| feed.staleness_result(&staleness_config, now)));
+        findings
+    } else {
+        assess_exposure()
+    };
+    shutdown.spawn(run_compliance_loop(
+        ComplianceConfig::from_env(),
+        Arc::clone(&ipc_server.compliance_assertions),
+        compliance_sources,
+        uplink_config.queue_dir.clone(),
+        identity.asset_id.clone(),
+        shutdown.token(),
+    ));
 
     let _telemetry_routed = route_telemetry(TelemetryPayload {
         stream: "sensor".to_string(),
         payload_bytes: 1,
         event_count: 1,
         checksum_sha256: Some("checksum-placeholder".to_string()),
+        raw_payload: None,
+        category: None,
     }, &policy);
-    let _uplink_summary = process_uplink_queue().await;
-    tokio::spawn(run_uplink_worker());
+    let uplink_summary = process_uplink_queue_with_config(&uplink_config).await;
+    metrics.record_uplink_summary(&uplink_summary);
+    shutdown.spawn(run_uplink_worker(uplink_config.clone(), metrics.clone(), shutdown.token()));
     let _command_routed = route_command(SignedCommand {
         command_id: "cmd-placeholder".to_string(),
         signed_payload: "payload-placeholder".to_string(),
+        signature: String::new(),
         action: "script-run".to_string(),
         arguments: vec!["-version".to_string()],
         not_before_unix_time_ms: unix_time_ms().saturating_sub(1_000),
         not_after_unix_time_ms: unix_time_ms().saturating_add(60_000),
     }, &policy, unix_time_ms());
 
-    let mut pipeline_status = PipelineStatus::new();
-    pipeline_status.mark_edr_ready();
-    pipeline_status.mark_siem_ready();
-    pipeline_status.mark_rmm_ready();
-    pipeline_status.mark_vulnerability_ready();
-    info!(ready = pipeline_status.is_fully_ready(), "pipeline status initialised");
+    let heartbeat_config = HeartbeatConfig::from_env();
+    let mut pipeline_health = PipelineHealth::new();
+    let policy_expires_at = policy.expires_at_unix_time_ms;
+    pipeline_health.register("policy", move |now| {
+        if now > policy_expires_at {
+            ComponentHealth::failed(format!("policy expired at {}", policy_expires_at))
+        } else {
+            ComponentHealth::ready()
+        }
+    });
+    pipeline_health.register("trust_bundle", |_| {
+        let report = verify_trust_bundle();
+        if report.verified {
+            ComponentHealth::ready()
+        } else {
+            ComponentHealth::failed(report.failures.join("; "))
+        }
+    });
+    let uplink_max_age_ms = UplinkWorkerConfig::from_env().interval_secs.saturating_mul(2_000);
+    let uplink_metrics = metrics.clone();
+    pipeline_health.register("uplink", move |now| {
+        freshness(
+            "uplink cycle",
+            uplink_metrics.uplink_last_cycle_unix_ms.get(),
+            started_at_unix_ms,
+            uplink_max_age_ms,
+            now,
+        )
+    });
+    let ipc_metrics = metrics.clone();
+    pipeline_health.register("ipc", move |_| {
+        if !ipc_configured {
+            ComponentHealth::not_configured("ipc endpoint not configured")
+        } else if ipc_metrics.ipc_listener_bound.get() == 1 {
+            ComponentHealth::ready()
+        } else {
+            ComponentHealth::failed("ipc listener not bound")
+        }
+    });
+    let heartbeat_max_age_ms = heartbeat_config.interval_secs.saturating_mul(2_000);
+    let heartbeat_metrics = metrics.clone();
+    pipeline_health.register("heartbeat", move |now| {
+        freshness(
+            "heartbeat delivered",
+            heartbeat_metrics.heartbeat_last_delivered_unix_ms.get(),
+            started_at_unix_ms,
+            heartbeat_max_age_ms,
+            now,
+        )
+    });
+    let edr_health = ComponentHealth::from_result(&edr_rules);
+    pipeline_health.register("edr", move |_| edr_health.clone());
+    let siem_health = ComponentHealth::from_result(&siem_config);
+    pipeline_health.register("siem", move |_| siem_health.clone());
+    // Evaluated on every report, which also keeps the clock monitor sampling between commands.
+    pipeline_health.register("clock", |_| match clock_status() {
+        ClockStatus::Synced => ComponentHealth::ready(),
+        status => ComponentHealth::degraded(format!("system clock jumped by {} ms: {:?}", status.delta_ms(), status)),
+    });
+    let resources = SharedDegradation::default();
+    shutdown.spawn(run_self_monitor(
+        SelfMonitorConfig::from_env(),
+        Box::new(PlatformSampler),
+        uplink_config.queue_dir.clone(),
+        uplink_config.telemetry_buffer_dir.clone(),
+        resources.clone(),
+        shutdown.token(),
+    ));
+    let resources_health = resources.clone();
+    pipeline_health.register("resources", move |_| {
+        resources_health
+            .lock()
+            .map(|state| state.health())
+            .unwrap_or_else(|_| ComponentHealth::degraded("resource monitor state poisoned"))
+    });
+    let health_report = pipeline_health.report(unix_time_ms());
+    metrics.record_health(&health_report);
+    info!(overall = ?health_report.overall, ready = health_report.is_fully_ready(), "pipeline health initialised");
+    let liveness_path = state_dir.paths().heartbeat_file();
+    if let Err(err) = write_liveness_file(&liveness_path, &health_report, unix_time_ms()) {
+        warn!(error = %err, path = %liveness_path.display(), "failed to write liveness file");
+    }
+    let not_ready = health_report.not_ready_reasons();
+    if !not_ready.is_empty() {
+        warn!(reasons = ?not_ready, "pipeline components not ready at startup");
+    }
+    let update_metrics = metrics.clone();
+    let orchestrator_config = OrchestratorConfig::from_env(state_dir.paths());
+    shutdown.spawn(async move {
+        let components = orchestrator_config.health_components.clone();
+        let probe = move || {
+            let metrics = update_metrics.clone();
+            let components = components.clone();
+            async move { metrics.health().and_then(|report| report.state_of(&components)) }
+        };
+        if orchestrator_config.verify_only {
+            let verification = verify_update(&orchestrator_config.update);
+            for artifact in verification.artifacts.iter().filter(|artifact| !artifact.verified) {
+                warn!(artifact = %artifact.name, error = ?artifact.error, "update artifact failed verification");
+            }
+            info!(
+                version = %verification.manifest_version,
+                verified = verification.is_verified(),
+                warnings = ?verification.warnings,
+                "update verified without applying"
+            );
+            return;
+        }
+        if let Some(state) = run_pending_update(&orchestrator_config, probe).await {
+            info!(phase = ?state.phase, version = %state.manifest_version, "update resolved");
+        }
+    });
+
+    let heartbeat =
+        HeartbeatSender::new(identity, uplink_config.clone(), metrics.clone(), started_at_unix_ms).with_resources(resources);
 
     loop {
         tokio::select! {
@@ -125,11 +428,33 @@ async fn main() {
                 info!("shutdown signal received");
                 break;
             }
-            _ = tokio::time::sleep(Duration::from_secs(30)) => {
-                info!("heartbeat tick");
+            _ = tokio::time::sleep(Duration::from_secs(heartbeat_config.interval_secs)) => {
+                let health_report = pipeline_health.report(unix_time_ms());
+                metrics.record_health(&health_report);
+                if let Err(err) = write_liveness_file(&liveness_path, &health_report, unix_time_ms()) {
+                    warn!(error = %err, path = %liveness_path.display(), "failed to write liveness file");
+                }
+                let delivered = heartbeat.send(&health_report).await;
+                info!(delivered, "heartbeat sent");
+                if let Some(batch) = self_telemetry.prepare_batch(&TelemetryConfig::from_env()) {
+                    metrics.record_telemetry_batch(&batch);
+                    buffer_batch(&batch, &uplink_config.telemetry_buffer_dir, &uplink_config.queue_dir);
+                }
             }
         }
     }
 
     info!("agent core stopping");
+    let shutdown_config = ShutdownConfig::from_env();
+    match shutdown.shutdown(shutdown_config.drain_timeout()).await {
+        ShutdownOutcome::Drained => info!("background tasks drained"),
+        ShutdownOutcome::TimedOut { remaining_tasks } => {
+            warn!(
+                remaining_tasks,
+                drain_timeout_secs = shutdown_config.drain_timeout_secs,
+                "background tasks did not drain in time; forcing exit"
+            );
+            std::process::exit(1);
+        }
+    }
 }
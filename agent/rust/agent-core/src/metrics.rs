@@ -0,0 +1,522 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use crate::edr::DetectionSummary;
+use crate::pipeline::HealthReport;
+use crate::siem::TelemetryBatch;
+use crate::uplink::UplinkSummary;
+
+/// Shared handle passed to each subsystem at construction.
+pub type MetricsHandle = Arc<AgentMetrics>;
+
+#[derive(Debug, Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, value: u64) {
+        self.0.fetch_add(value, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Gauge(AtomicU64);
+
+impl Gauge {
+    pub fn set(&self, value: u64) {
+        self.0.store(value, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Counter family keyed by a single label value.
+#[derive(Debug, Default)]
+pub struct LabeledCounter(Mutex<BTreeMap<String, u64>>);
+
+impl LabeledCounter {
+    pub fn inc(&self, label: &str) {
+        self.add(label, 1);
+    }
+
+    pub fn add(&self, label: &str, value: u64) {
+        let mut values = self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        *values.entry(label.to_string()).or_insert(0) += value;
+    }
+
+    pub fn get(&self, label: &str) -> u64 {
+        let values = self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        values.get(label).copied().unwrap_or(0)
+    }
+
+    fn snapshot(&self) -> BTreeMap<String, u64> {
+        self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+    }
+}
+
+/// Gauge family keyed by a single label value.
+#[derive(Debug, Default)]
+pub struct LabeledGauge(Mutex<BTreeMap<String, u64>>);
+
+impl LabeledGauge {
+    pub fn set(&self, label: &str, value: u64) {
+        let mut values = self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        values.insert(label.to_string(), value);
+    }
+
+    pub fn get(&self, label: &str) -> Option<u64> {
+        let values = self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        values.get(label).copied()
+    }
+
+    fn snapshot(&self) -> BTreeMap<String, u64> {
+        self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+    }
+}
+
+/// Process-wide agent-core counters and gauges, rendered in Prometheus text format.
+#[derive(Debug, Default)]
+pub struct AgentMetrics {
+    pub envelopes_accepted: Counter,
+    pub envelopes_rejected: LabeledCounter,
+    pub rate_limit_hits: Counter,
+    /// Poisoned locks recovered after a panic while they were held, by lock name.
+    pub lock_poison_recoveries: LabeledCounter,
+    pub ipc_active_connections: Gauge,
+    pub ipc_listener_bound: Gauge,
+    pub telemetry_events_accepted: Counter,
+    pub telemetry_events_dropped: Counter,
+    pub telemetry_events_deduplicated: Counter,
+    pub telemetry_events_sampled: Counter,
+    pub uplink_items_succeeded: Counter,
+    pub uplink_items_failed: Counter,
+    pub uplink_items_dead_lettered: Counter,
+    pub uplink_items_quarantined: Counter,
+    pub uplink_items_deferred: Counter,
+    pub uplink_circuit_state: LabeledGauge,
+    pub uplink_last_success_unix_ms: Gauge,
+    pub uplink_last_cycle_unix_ms: Gauge,
+    pub heartbeat_last_delivered_unix_ms: Gauge,
+    pub detections: LabeledCounter,
+    pub policy_last_reload_unix_ms: Gauge,
+    pub rmm_schedule_decisions: LabeledCounter,
+    health: Mutex<Option<HealthReport>>,
+}
+
+impl AgentMetrics {
+    pub fn new_handle() -> MetricsHandle {
+        Arc::new(Self::default())
+    }
+
+    pub fn record_telemetry_batch(&self, batch: &TelemetryBatch) {
+        self.telemetry_events_accepted.add(batch.event_count as u64);
+        self.telemetry_events_dropped.add(batch.dropped_count as u64);
+        self.telemetry_events_deduplicated.add(batch.deduplicated_count as u64);
+        self.telemetry_events_sampled.add(batch.sampled_count as u64);
+    }
+
+    pub fn record_uplink_summary(&self, summary: &UplinkSummary) {
+        self.uplink_items_succeeded.add(summary.succeeded as u64);
+        self.uplink_items_failed.add(summary.failed as u64);
+        self.uplink_items_quarantined.add(summary.quarantined as u64);
+        self.uplink_items_dead_lettered.add(summary.quarantined as u64);
+        self.uplink_items_deferred.add(summary.deferred as u64);
+        for (endpoint, state) in &summary.circuits {
+            self.uplink_circuit_state.set(endpoint, state.as_gauge());
+        }
+        self.uplink_last_cycle_unix_ms.set(summary.completed_at_unix_ms);
+        if summary.failed == 0 {
+            self.uplink_last_success_unix_ms.set(summary.completed_at_unix_ms);
+        }
+    }
+
+    pub fn record_detections(&self, detections: &[DetectionSummary]) {
+        for detection in detections {
+            self.detections.inc(&detection.severity.to_string());
+        }
+    }
+
+    /// Keep the latest pipeline health report for `/health` and the health gauges.
+    pub fn record_health(&self, report: &HealthReport) {
+        *self.health.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(report.clone());
+    }
+
+    pub fn health(&self) -> Option<HealthReport> {
+        self.health.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+    }
+
+    pub fn render(&self) -> String {
+        let mut output = String::new();
+        render_counter(
+            &mut output,
+            "agent_ipc_envelopes_accepted_total",
+            "IPC envelopes accepted for routing.",
+            self.envelopes_accepted.get(),
+        );
+        render_labeled(
+            &mut output,
+            "agent_ipc_envelopes_rejected_total",
+            "IPC envelopes rejected, by reason.",
+            "reason",
+            &self.envelopes_rejected.snapshot(),
+        );
+        render_counter(
+            &mut output,
+            "agent_ipc_rate_limit_hits_total",
+            "IPC envelopes refused by the rate limiter.",
+            self.rate_limit_hits.get(),
+        );
+        render_labeled(
+            &mut output,
+            "agent_lock_poison_recoveries_total",
+            "Locks found poisoned by a panicked holder and repaired, by lock.",
+            "lock",
+            &self.lock_poison_recoveries.snapshot(),
+        );
+        render_gauge(
+            &mut output,
+            "agent_ipc_active_connections",
+            "IPC client connections currently open.",
+            self.ipc_active_connections.get(),
+        );
+        render_gauge(
+            &mut output,
+            "agent_ipc_listener_bound",
+            "1 while the IPC listener is bound and accepting clients.",
+            self.ipc_listener_bound.get(),
+        );
+        render_counter(
+            &mut output,
+            "agent_telemetry_events_accepted_total",
+            "Telemetry events admitted into batches.",
+            self.telemetry_events_accepted.get(),
+        );
+        render_counter(
+            &mut output,
+            "agent_telemetry_events_dropped_total",
+            "Telemetry events dropped during batching.",
+            self.telemetry_events_dropped.get(),
+        );
+        render_counter(
+            &mut output,
+            "agent_telemetry_events_deduplicated_total",
+            "Telemetry events suppressed as duplicates.",
+            self.telemetry_events_deduplicated.get(),
+        );
+        render_counter(
+            &mut output,
+            "agent_telemetry_events_sampled_total",
+            "Telemetry events thinned out by sampling.",
+            self.telemetry_events_sampled.get(),
+        );
+        render_counter(
+            &mut output,
+            "agent_uplink_items_succeeded_total",
+            "Uplink queue items delivered.",
+            self.uplink_items_succeeded.get(),
+        );
+        render_counter(
+            &mut output,
+            "agent_uplink_items_failed_total",
+            "Uplink queue item delivery attempts that failed.",
+            self.uplink_items_failed.get(),
+        );
+        render_counter(
+            &mut output,
+            "agent_uplink_items_dead_lettered_total",
+            "Uplink queue items moved to the dead-letter (quarantine) directory, including by queue migration.",
+            self.uplink_items_dead_lettered.get(),
+        );
+        render_counter(
+            &mut output,
+            "agent_uplink_items_quarantined_total",
+            "Malformed uplink queue items moved to the quarantine directory.",
+            self.uplink_items_quarantined.get(),
+        );
+        render_counter(
+            &mut output,
+            "agent_uplink_items_deferred_total",
+            "Uplink queue items held back by an open endpoint circuit breaker.",
+            self.uplink_items_deferred.get(),
+        );
+        let circuits = self.uplink_circuit_state.snapshot();
+        if !circuits.is_empty() {
+            render_labeled_gauge(
+                &mut output,
+                "agent_uplink_circuit_state",
+                "Uplink endpoint circuit breaker state (0 closed, 1 half-open, 2 open).",
+                "endpoint",
+                &circuits,
+            );
+        }
+        render_gauge(
+            &mut output,
+            "agent_uplink_last_success_timestamp_ms",
+            "Unix time in milliseconds of the last uplink cycle without delivery failures.",
+            self.uplink_last_success_unix_ms.get(),
+        );
+        render_gauge(
+            &mut output,
+            "agent_uplink_last_cycle_timestamp_ms",
+            "Unix time in milliseconds the last uplink cycle completed.",
+            self.uplink_last_cycle_unix_ms.get(),
+        );
+        render_gauge(
+            &mut output,
+            "agent_heartbeat_last_delivered_timestamp_ms",
+            "Unix time in milliseconds of the last heartbeat accepted by the backend.",
+            self.heartbeat_last_delivered_unix_ms.get(),
+        );
+        render_labeled(
+            &mut output,
+            "agent_edr_detections_total",
+            "EDR detections raised, by rule severity.",
+            "severity",
+            &self.detections.snapshot(),
+        );
+        render_labeled(
+            &mut output,
+            "agent_rmm_schedule_decisions_total",
+            "RMM scheduling decisions, by outcome.",
+            "decision",
+            &self.rmm_schedule_decisions.snapshot(),
+        );
+        render_gauge(
+            &mut output,
+            "agent_policy_last_reload_timestamp_ms",
+            "Unix time in milliseconds the policy bundle was last loaded.",
+            self.policy_last_reload_unix_ms.get(),
+        );
+        if let Some(report) = self.health() {
+            render_gauge(
+                &mut output,
+                "agent_pipeline_health_state",
+                "Overall pipeline health (0 ready, 1 degraded, 2 failed).",
+                report.overall.as_gauge(),
+            );
+            let components = report
+                .components
+                .iter()
+                .map(|component| (component.component.clone(), component.health.state.as_gauge()))
+                .collect::<BTreeMap<String, u64>>();
+            render_labeled_gauge(
+                &mut output,
+                "agent_pipeline_component_state",
+                "Pipeline component health (0 ready, 1 degraded, 2 failed).",
+                "component",
+                &components,
+            );
+        }
+        output
+    }
+}
+
+fn render_counter(output: &mut String, name: &str, help: &str, value: u64) {
+    let _ = writeln!(output, "# HELP {} {}\n# TYPE {} counter\n{} {}", name, help, name, name, value);
+}
+
+fn render_gauge(output: &mut String, name: &str, help: &str, value: u64) {
+    let _ = writeln!(output, "# HELP {} {}\n# TYPE {} gauge\n{} {}", name, help, name, name, value);
+}
+
+fn render_labeled(output: &mut String, name: &str, help: &str, label: &str, values: &BTreeMap<String, u64>) {
+    let _ = writeln!(output, "# HELP {} {}\n# TYPE {} counter", name, help, name);
+    render_label_values(output, name, label, values);
+}
+
+fn render_labeled_gauge(output: &mut String, name: &str, help: &str, label: &str, values: &BTreeMap<String, u64>) {
+    let _ = writeln!(output, "# HELP {} {}\n# TYPE {} gauge", name, help, name);
+    render_label_values(output, name, label, values);
+}
+
+fn render_label_values(output: &mut String, name: &str, label: &str, values: &BTreeMap<String, u64>) {
+    for (label_value, value) in values {
+        let _ = writeln!(output, "{}{{{}=\"{}\"}} {}", name, label, escape_label(label_value), value);
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[derive(Debug, Clone)]
+pub struct MetricsConfig {
+    pub bind_addr: Option<SocketAddr>,
+}
+
+impl MetricsConfig {
+    pub fn from_env() -> Self {
+        let bind_addr = env::var("AGENT_METRICS_ADDR")
+            .ok()
+            .and_then(|value| value.trim().parse::<SocketAddr>().ok());
+        Self { bind_addr }
+    }
+}
+
+/// Serve `GET /metrics` (and the latest pipeline report as JSON on `GET /health`) on the
+/// configured address until `shutdown` is cancelled.
+pub async fn serve_metrics(addr: SocketAddr, metrics: MetricsHandle, shutdown: CancellationToken) {
+    let listener = match TcpListener::bind(addr).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            warn!(error = %err, %addr, "metrics listener bind failed");
+            return;
+        }
+    };
+    info!(%addr, "metrics listener started");
+    serve_on(listener, metrics, shutdown).await;
+    info!("metrics listener stopped");
+}
+
+async fn serve_on(listener: TcpListener, metrics: MetricsHandle, shutdown: CancellationToken) {
+    loop {
+        let accepted = tokio::select! {
+            _ = shutdown.cancelled() => return,
+            accepted = listener.accept() => accepted,
+        };
+        match accepted {
+            Ok((stream, _)) => {
+                let metrics = Arc::clone(&metrics);
+                tokio::spawn(async move {
+                    if let Err(err) = answer(stream, &metrics).await {
+                        warn!(error = %err, "metrics request failed");
+                    }
+                });
+            }
+            Err(err) => warn!(error = %err, "metrics accept failed"),
+        }
+    }
+}
+
+async fn answer(mut stream: TcpStream, metrics: &AgentMetrics) -> std::io::Result<()> {
+    let mut buffer = [0_u8; 1024];
+    let read = stream.read(&mut buffer).await?;
+    let request = String::from_utf8_lossy(&buffer[..read]);
+    let response = if request.starts_with("GET /metrics ") {
+        let body = metrics.render();
+        format!(
+            "HTTP/1.1 200 OK\r\ncontent-type: text/plain; version=0.0.4\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        )
+    } else if request.starts_with("GET /health ") {
+        match metrics.health().and_then(|report| serde_json::to_string(&report).ok()) {
+            Some(body) => format!(
+                "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            ),
+            None => "HTTP/1.1 503 Service Unavailable\r\ncontent-length: 0\r\nconnection: close\r\n\r\n".to_string(),
+        }
+    } else {
+        "HTTP/1.1 404 Not Found\r\ncontent-length: 0\r\nconnection: close\r\n\r\n".to_string()
+    };
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_util::sync::CancellationToken;
+
+    use super::{serve_on, AgentMetrics};
+    use crate::pipeline::{ComponentHealth, PipelineHealth};
+
+    #[test]
+    fn renders_prometheus_text_format() {
+        let metrics = AgentMetrics::default();
+        metrics.envelopes_accepted.add(3);
+        metrics.envelopes_rejected.inc("rate_limited");
+        metrics.envelopes_rejected.inc("schema_version");
+        metrics.envelopes_rejected.inc("rate_limited");
+        metrics.detections.inc("8");
+        metrics.policy_last_reload_unix_ms.set(1_700_000_000_000);
+
+        let output = metrics.render();
+        assert!(output.contains(
+            "# HELP agent_ipc_envelopes_accepted_total IPC envelopes accepted for routing.\n# TYPE agent_ipc_envelopes_accepted_total counter\nagent_ipc_envelopes_accepted_total 3\n"
+        ));
+        assert!(output.contains(
+            "agent_ipc_envelopes_rejected_total{reason=\"rate_limited\"} 2\nagent_ipc_envelopes_rejected_total{reason=\"schema_version\"} 1\n"
+        ));
+        assert!(output.contains("agent_edr_detections_total{severity=\"8\"} 1\n"));
+        assert!(output.contains("# TYPE agent_policy_last_reload_timestamp_ms gauge\nagent_policy_last_reload_timestamp_ms 1700000000000\n"));
+        assert!(output.contains("agent_uplink_items_dead_lettered_total 0\n"));
+        assert!(!output.contains("agent_pipeline_health_state"));
+
+        let mut health = PipelineHealth::new();
+        health.register("policy", |_| ComponentHealth::ready());
+        health.register("ipc", |_| ComponentHealth::failed("ipc listener not bound"));
+        metrics.record_health(&health.report(1));
+        let output = metrics.render();
+        assert!(output.contains("# TYPE agent_pipeline_health_state gauge\nagent_pipeline_health_state 1\n"));
+        assert!(output.contains(
+            "agent_pipeline_component_state{component=\"ipc\"} 2\nagent_pipeline_component_state{component=\"policy\"} 0\n"
+        ));
+        assert!(output.lines().all(|line| line.starts_with('#') || line.split(' ').count() == 2));
+    }
+
+    #[test]
+    fn counts_concurrent_increments() {
+        let metrics = AgentMetrics::new_handle();
+        let workers = (0..8)
+            .map(|_| {
+                let metrics = Arc::clone(&metrics);
+                std::thread::spawn(move || {
+                    for _ in 0..1_000 {
+                        metrics.rate_limit_hits.inc();
+                        metrics.envelopes_rejected.inc("rate_limited");
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for worker in workers {
+            worker.join().expect("worker thread");
+        }
+        assert_eq!(metrics.rate_limit_hits.get(), 8_000);
+        assert_eq!(metrics.envelopes_rejected.get("rate_limited"), 8_000);
+    }
+
+    #[tokio::test]
+    async fn serves_metrics_over_http() {
+        let metrics = AgentMetrics::new_handle();
+        metrics.uplink_items_succeeded.add(5);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = listener.local_addr().expect("local addr");
+        tokio::spawn(serve_on(listener, Arc::clone(&metrics), CancellationToken::new()));
+
+        let mut stream = tokio::net::TcpStream::connect(addr).await.expect("connect");
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nhost: localhost\r\n\r\n")
+            .await
+            .expect("send request");
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.expect("read response");
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("agent_uplink_items_succeeded_total 5\n"));
+    }
+}
@@ -0,0 +1,210 @@
+use std::env;
+use std::time::Duration;
+
+use reqwest::header::{HeaderMap, HeaderValue, CONTENT_TYPE, USER_AGENT};
+use serde_json::{json, Value};
+use tracing::{info, warn};
+
+use crate::siem::{TelemetryBatch, TelemetryEvent, TelemetrySeverity};
+
+/// OTLP/HTTP log exporter settings, sourced from environment variables.
+#[derive(Debug, Clone)]
+pub struct OtlpConfig {
+    pub endpoint: Option<String>,
+    pub service_name: String,
+    pub timeout_secs: u64,
+}
+
+impl OtlpConfig {
+    pub fn from_env() -> Self {
+        let endpoint = env::var("OTLP_ENDPOINT")
+            .ok()
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty());
+        let service_name = env::var("OTLP_SERVICE_NAME")
+            .ok()
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+            .unwrap_or_else(|| "agent-core".to_string());
+        let timeout_secs = env::var("OTLP_TIMEOUT_SECS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .unwrap_or(10);
+
+        Self {
+            endpoint,
+            service_name,
+            timeout_secs,
+        }
+    }
+}
+
+pub async fn export_batch(batch: &TelemetryBatch) -> bool {
+    let config = OtlpConfig::from_env();
+    export_batch_with_config(batch, &config).await
+}
+
+/// POST a telemetry batch to the configured OTLP/HTTP logs endpoint as OTLP JSON.
+pub async fn export_batch_with_config(batch: &TelemetryBatch, config: &OtlpConfig) -> bool {
+    let endpoint = match &config.endpoint {
+        Some(value) => value,
+        None => return false,
+    };
+    if batch.events.is_empty() {
+        return true;
+    }
+
+    let mut headers = HeaderMap::new();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    headers.insert(USER_AGENT, HeaderValue::from_static("TamsilAgent/1.0"));
+    let client = match reqwest::Client::builder()
+        .default_headers(headers)
+        .timeout(Duration::from_secs(config.timeout_secs))
+        .build()
+    {
+        Ok(client) => client,
+        Err(err) => {
+            warn!(error = %err, "failed to build otlp http client");
+            return false;
+        }
+    };
+
+    let payload = build_logs_payload(batch, &config.service_name);
+    match client.post(endpoint).body(payload.to_string()).send().await {
+        Ok(response) if response.status().is_success() => {
+            info!(batch_id = %batch.batch_id, events = batch.events.len(), "otlp batch exported");
+            true
+        }
+        Ok(response) => {
+            warn!(status = %response.status(), endpoint, "otlp export returned non-success status");
+            false
+        }
+        Err(err) => {
+            warn!(error = %err, endpoint, "otlp export failed");
+            false
+        }
+    }
+}
+
+/// Map a batch to an OTLP `ExportLogsServiceRequest` in its JSON encoding. One resource and
+/// one scope are emitted per batch so the batch remains the unit of grouping downstream.
+pub fn build_logs_payload(batch: &TelemetryBatch, service_name: &str) -> Value {
+    let log_records = batch.events.iter().map(build_log_record).collect::<Vec<Value>>();
+
+    json!({
+        "resourceLogs": [{
+            "resource": {
+                "attributes": [
+                    string_attribute("service.name", service_name),
+                    string_attribute("telemetry.stream", &batch.stream),
+                ]
+            },
+            "scopeLogs": [{
+                "scope": {
+                    "name": "tamsil.agent.siem",
+                    "attributes": [
+                        string_attribute("batch.id", &batch.batch_id),
+                        string_attribute("batch.checksum_sha256", &batch.checksum_sha256),
+                    ]
+                },
+                "logRecords": log_records
+            }]
+        }]
+    })
+}
+
+fn build_log_record(event: &TelemetryEvent) -> Value {
+    let (severity_number, severity_text) = map_severity(event.severity);
+    let time_unix_nano = event.timestamp_unix_ms.saturating_mul(1_000_000).to_string();
+    let mut attributes = vec![
+        string_attribute("event.id", &event.event_id),
+        string_attribute("event.category", &event.category),
+    ];
+    attributes.extend(
+        event
+            .fields
+            .iter()
+            .map(|field| string_attribute(&field.key, &field.value)),
+    );
+
+    json!({
+        "timeUnixNano": time_unix_nano,
+        "observedTimeUnixNano": time_unix_nano,
+        "severityNumber": severity_number,
+        "severityText": severity_text,
+        "body": { "stringValue": event.message },
+        "attributes": attributes
+    })
+}
+
+fn map_severity(severity: TelemetrySeverity) -> (u8, &'static str) {
+    match severity {
+        TelemetrySeverity::Informational => (9, "INFO"),
+        TelemetrySeverity::Low => (13, "WARN"),
+        TelemetrySeverity::Medium => (14, "WARN2"),
+        TelemetrySeverity::High => (17, "ERROR"),
+        TelemetrySeverity::Critical => (21, "FATAL"),
+    }
+}
+
+fn string_attribute(key: &str, value: &str) -> Value {
+    json!({ "key": key, "value": { "stringValue": value } })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::build_logs_payload;
+    use crate::siem::{
+        BatchManifest, SeverityBreakdown, TelemetryBatch, TelemetryEvent, TelemetryField, TelemetrySeverity,
+    };
+
+    fn build_batch() -> TelemetryBatch {
+        TelemetryBatch {
+            batch_id: "siem-sensor-1".to_string(),
+            stream: "sensor".to_string(),
+            event_count: 1,
+            dropped_count: 0,
+            deduplicated_count: 0,
+            sampled_count: 0,
+            malformed_count: 0,
+            total_payload_bytes: 32,
+            checksum_sha256: "abc".to_string(),
+            prev_checksum: None,
+            created_at_unix_ms: 1,
+            severity_counts: SeverityBreakdown::default(),
+            manifest: BatchManifest::default(),
+            events: vec![TelemetryEvent {
+                event_id: "evt-1".to_string(),
+                stream: "sensor".to_string(),
+                category: "process".to_string(),
+                severity: TelemetrySeverity::High,
+                timestamp_unix_ms: 1_700_000_000_000,
+                message: "suspicious process".to_string(),
+                fields: vec![TelemetryField {
+                    key: "image".to_string(),
+                    value: "cmd.exe".to_string(),
+                }],
+            }],
+        }
+    }
+
+    #[test]
+    fn maps_event_to_otlp_log_record() {
+        let payload = build_logs_payload(&build_batch(), "agent-core");
+        let resource_logs = &payload["resourceLogs"][0];
+        assert_eq!(resource_logs["resource"]["attributes"][0]["key"], "service.name");
+        assert_eq!(resource_logs["resource"]["attributes"][0]["value"]["stringValue"], "agent-core");
+
+        let record = &resource_logs["scopeLogs"][0]["logRecords"][0];
+        assert_eq!(record["timeUnixNano"], "1700000000000000000");
+        assert_eq!(record["severityNumber"], 17);
+        assert_eq!(record["severityText"], "ERROR");
+        assert_eq!(record["body"]["stringValue"], "suspicious process");
+
+        let attributes = record["attributes"].as_array().expect("attributes array");
+        assert_eq!(attributes[0]["key"], "event.id");
+        assert_eq!(attributes[1]["value"]["stringValue"], "process");
+        assert_eq!(attributes[2]["key"], "image");
+        assert_eq!(attributes[2]["value"]["stringValue"], "cmd.exe");
+    }
+}
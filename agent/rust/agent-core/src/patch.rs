@@ -0,0 +1,666 @@
+use std::env;
+use std::ffi::OsStr;
+use std::fmt;
+use std::io::Read;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::command_router::{route_command_explain_with_config, CommandRouteConfig};
+use crate::policy::PolicyBundle;
+use crate::rmm::{truncate_output, ExecutionRequest};
+use crate::time::{format_rfc3339_ms, unix_time_ms};
+use crate::uplink::enqueue_patch_item;
+
+pub const PATCH_APPLY_ACTION: &str = "patch-apply";
+/// Per-stream cap on package manager output kept in a result.
+const MAX_PACKAGE_OUTPUT_BYTES: usize = 16 * 1024;
+const MAX_PACKAGES_PER_JOB: usize = 64;
+/// Longest one package install may run before it is killed and reported failed.
+const INSTALL_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+/// Longest a reboot check or reboot request may run.
+const REBOOT_COMMAND_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Job carried in the signed payload of a `patch-apply` command.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PatchJob {
+    pub tenant_id: String,
+    pub asset_id: String,
+    pub plan_id: String,
+    /// Package names, or winget package ids on Windows.
+    pub packages: Vec<String>,
+    #[serde(default)]
+    pub maintenance_window: Option<MaintenanceWindow>,
+    #[serde(default)]
+    pub reboot_policy: RebootPolicy,
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MaintenanceWindow {
+    pub start_unix_ms: u64,
+    pub end_unix_ms: u64,
+}
+
+/// Whether the agent may reboot after installing; the default never reboots.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RebootPolicy {
+    #[default]
+    Never,
+    IfRequired,
+}
+
+/// What a package manager reported for one install (or simulated install).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackageOutput {
+    pub exit_code: Option<i32>,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+/// Platform package manager used by patch jobs.
+pub trait PatchBackend {
+    fn name(&self) -> &'static str;
+    /// Refuse package ids this backend cannot install; checked for the whole job before
+    /// anything runs.
+    fn check_package(&self, _package: &str) -> Result<(), String> {
+        Ok(())
+    }
+    /// Install or upgrade one package; with `dry_run` only simulate the transaction.
+    fn install(&self, package: &str, dry_run: bool) -> Result<PackageOutput, String>;
+    fn reboot_required(&self) -> bool;
+    fn reboot(&self) -> Result<(), String>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PackageManager {
+    AptGet,
+    Dnf,
+    Winget,
+}
+
+impl PackageManager {
+    /// `PATCH_BACKEND` (`apt`, `dnf` or `winget`); defaults to winget on Windows and apt
+    /// elsewhere.
+    pub fn from_env() -> Self {
+        match env::var("PATCH_BACKEND").ok().as_deref().map(str::trim) {
+            Some("dnf") => Self::Dnf,
+            Some("winget") => Self::Winget,
+            Some("apt") | Some("apt-get") => Self::AptGet,
+            _ if cfg!(windows) => Self::Winget,
+            _ => Self::AptGet,
+        }
+    }
+
+    /// Argument vector for one install. `None` when the manager cannot simulate, in which
+    /// case dry-run jobs skip the package without running anything.
+    pub fn install_command(self, package: &str, dry_run: bool) -> Option<Vec<String>> {
+        let argv: Vec<&str> = match (self, dry_run) {
+            (Self::AptGet, false) => vec!["apt-get", "install", "-y", "--only-upgrade", package],
+            (Self::AptGet, true) => vec!["apt-get", "install", "--simulate", "--only-upgrade", package],
+            (Self::Dnf, false) => vec!["dnf", "upgrade", "-y", package],
+            (Self::Dnf, true) => vec!["dnf", "upgrade", "--assumeno", package],
+            (Self::Winget, false) => vec![
+                "winget",
+                "upgrade",
+                "--id",
+                package,
+                "--exact",
+                "--silent",
+                "--accept-package-agreements",
+                "--accept-source-agreements",
+            ],
+            (Self::Winget, true) => return None,
+        };
+        Some(argv.into_iter().map(str::to_string).collect())
+    }
+}
+
+impl PatchBackend for PackageManager {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::AptGet => "apt-get",
+            Self::Dnf => "dnf",
+            Self::Winget => "winget",
+        }
+    }
+
+    fn check_package(&self, package: &str) -> Result<(), String> {
+        if *self == Self::Winget && is_kb_id(package) {
+            return Err(format!("winget cannot install Windows Update {}", package));
+        }
+        Ok(())
+    }
+
+    fn install(&self, package: &str, dry_run: bool) -> Result<PackageOutput, String> {
+        let argv = match self.install_command(package, dry_run) {
+            Some(argv) => argv,
+            None => {
+                return Ok(PackageOutput {
+                    exit_code: Some(0),
+                    stdout: format!("{} has no simulation mode; {} not run", self.name(), package).into_bytes(),
+                    stderr: Vec::new(),
+                })
+            }
+        };
+        run_with_timeout(&argv[0], &argv[1..], INSTALL_TIMEOUT)
+    }
+
+    fn reboot_required(&self) -> bool {
+        match self {
+            Self::AptGet => Path::new("/var/run/reboot-required").exists(),
+            Self::Dnf => run_with_timeout("needs-restarting", &["-r"], REBOOT_COMMAND_TIMEOUT)
+                .map(|output| output.exit_code == Some(1))
+                .unwrap_or(false),
+            // The key only exists while Windows Update is waiting for a reboot.
+            Self::Winget if cfg!(windows) => run_with_timeout(
+                "reg",
+                &["query", r"HKLM\SOFTWARE\Microsoft\Windows\CurrentVersion\WindowsUpdate\Auto Update\RebootRequired"],
+                REBOOT_COMMAND_TIMEOUT,
+            )
+            .map(|output| output.exit_code == Some(0))
+            .unwrap_or(false),
+            Self::Winget => false,
+        }
+    }
+
+    fn reboot(&self) -> Result<(), String> {
+        let args: [&str; 3] = match self {
+            Self::Winget if cfg!(windows) => ["/r", "/t", "60"],
+            Self::AptGet | Self::Dnf if !cfg!(windows) => ["-r", "+1", "tamsil patch job"],
+            _ => return Err(format!("{} cannot reboot this platform", self.name())),
+        };
+        let output = run_with_timeout("shutdown", &args, REBOOT_COMMAND_TIMEOUT)
+            .map_err(|err| format!("failed to schedule reboot: {}", err))?;
+        if output.exit_code == Some(0) {
+            Ok(())
+        } else {
+            Err(format!("reboot command exited with {:?}", output.exit_code))
+        }
+    }
+}
+
+/// Run `program` to completion, killing it once `timeout` passes. Output is drained on
+/// separate threads so a full pipe cannot stall the child.
+fn run_with_timeout<S: AsRef<OsStr>>(program: &str, args: &[S], timeout: Duration) -> Result<PackageOutput, String> {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| format!("failed to run {}: {}", program, err))?;
+    let stdout = drain(child.stdout.take());
+    let stderr = drain(child.stderr.take());
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            // The readers are left behind: a grandchild may still hold the pipes open.
+            Ok(None) if Instant::now() >= deadline => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!("{} timed out after {} s", program, timeout.as_secs()));
+            }
+            Ok(None) => std::thread::sleep(Duration::from_millis(100)),
+            Err(err) => return Err(format!("failed to wait for {}: {}", program, err)),
+        }
+    };
+    Ok(PackageOutput {
+        exit_code: status.code(),
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    })
+}
+
+fn drain<R: Read + Send + 'static>(pipe: Option<R>) -> std::thread::JoinHandle<Vec<u8>> {
+    std::thread::spawn(move || {
+        let mut raw = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut raw);
+        }
+        raw
+    })
+}
+
+/// One entry of the patch service's `ExecutionResult` list.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PackageResult {
+    pub patch_id: String,
+    pub status: PackageStatus,
+    pub stdout: Option<String>,
+    pub stderr: Option<String>,
+    pub exit_code: Option<i32>,
+    pub failure_type: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PackageStatus {
+    Completed,
+    Failed,
+    Skipped,
+}
+
+/// Body posted to the patch results endpoint (`ExecutionResultRequest` in the patch service).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PatchReport {
+    pub tenant_id: String,
+    pub asset_id: String,
+    pub plan_id: String,
+    pub started_at: String,
+    pub finished_at: String,
+    pub results: Vec<PackageResult>,
+    pub reboot_confirmed: bool,
+    pub verification_status: String,
+    pub verification_notes: Option<String>,
+    pub reboot_required: bool,
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PatchJobError {
+    UnsupportedAction(String),
+    NotPermitted(String),
+    InvalidJob(String),
+    OutsideMaintenanceWindow { start_unix_ms: u64, end_unix_ms: u64 },
+}
+
+impl fmt::Display for PatchJobError {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnsupportedAction(action) => write!(formatter, "action {} is not a patch job", action),
+            Self::NotPermitted(reasons) => write!(formatter, "command not permitted by policy: {}", reasons),
+            Self::InvalidJob(reason) => write!(formatter, "patch job invalid: {}", reason),
+            Self::OutsideMaintenanceWindow { start_unix_ms, end_unix_ms } => write!(
+                formatter,
+                "outside maintenance window {}..{}",
+                start_unix_ms, end_unix_ms
+            ),
+        }
+    }
+}
+
+/// Parse and validate the job carried by a `patch-apply` request.
+pub fn parse_patch_job(request: &ExecutionRequest) -> Result<PatchJob, PatchJobError> {
+    let job = serde_json::from_str::<PatchJob>(&request.signed_payload)
+        .map_err(|err| PatchJobError::InvalidJob(err.to_string()))?;
+    if job.packages.is_empty() || job.packages.len() > MAX_PACKAGES_PER_JOB {
+        return Err(PatchJobError::InvalidJob(format!(
+            "expected 1 to {} packages, got {}",
+            MAX_PACKAGES_PER_JOB,
+            job.packages.len()
+        )));
+    }
+    if let Some(package) = job.packages.iter().find(|package| !is_valid_package_id(package)) {
+        return Err(PatchJobError::InvalidJob(format!("package id {:?} is not allowed", package)));
+    }
+    if let Some(window) = job.maintenance_window {
+        if window.start_unix_ms > window.end_unix_ms {
+            return Err(PatchJobError::InvalidJob("maintenance window is inverted".to_string()));
+        }
+    }
+    Ok(job)
+}
+
+/// Package names and winget ids only: no leading dash (option injection), no separators or
+/// shell syntax.
+/// `KB` followed by digits: a Windows Update article, which no package manager here installs.
+fn is_kb_id(package: &str) -> bool {
+    package.len() > 2
+        && package[..2].eq_ignore_ascii_case("kb")
+        && package[2..].chars().all(|ch| ch.is_ascii_digit())
+}
+
+fn is_valid_package_id(package: &str) -> bool {
+    !package.is_empty()
+        && package.len() <= 200
+        && !package.starts_with('-')
+        && package
+            .chars()
+            .all(|ch| ch.is_ascii_alphanumeric() || matches!(ch, '.' | '_' | '+' | '-' | ':'))
+}
+
+/// Run a `patch-apply` request through `backend`, one package at a time. Installs continue
+/// past individual failures; a reboot happens only when the job's policy allows it, every
+/// package succeeded and the backend reports one is needed.
+pub fn run_patch_job(
+    request: &ExecutionRequest,
+    policy: &PolicyBundle,
+    route: &CommandRouteConfig,
+    backend: &dyn PatchBackend,
+    now_unix_ms: u64,
+) -> Result<PatchReport, PatchJobError> {
+    if request.action != PATCH_APPLY_ACTION {
+        return Err(PatchJobError::UnsupportedAction(request.action.clone()));
+    }
+    let decision = route_command_explain_with_config(&request.signed_command(), policy, route, now_unix_ms);
+    if !decision.allowed() {
+        let reasons = decision
+            .results
+            .iter()
+            .filter_map(|result| result.reason.clone())
+            .collect::<Vec<String>>();
+        return Err(PatchJobError::NotPermitted(reasons.join("; ")));
+    }
+    let job = parse_patch_job(request)?;
+    for package in &job.packages {
+        backend.check_package(package).map_err(PatchJobError::InvalidJob)?;
+    }
+    if let Some(window) = job.maintenance_window {
+        if now_unix_ms < window.start_unix_ms || now_unix_ms > window.end_unix_ms {
+            return Err(PatchJobError::OutsideMaintenanceWindow {
+                start_unix_ms: window.start_unix_ms,
+                end_unix_ms: window.end_unix_ms,
+            });
+        }
+    }
+
+    let started_at = unix_time_ms();
+    let results = job
+        .packages
+        .iter()
+        .map(|package| install_package(backend, package, job.dry_run))
+        .collect::<Vec<PackageResult>>();
+    let any_failed = results.iter().any(|result| result.status == PackageStatus::Failed);
+
+    let reboot_required = !job.dry_run && backend.reboot_required();
+    let mut notes = Vec::new();
+    let mut reboot_confirmed = false;
+    if reboot_required {
+        if job.reboot_policy == RebootPolicy::IfRequired && !any_failed {
+            match backend.reboot() {
+                Ok(()) => reboot_confirmed = true,
+                Err(err) => notes.push(format!("reboot failed: {}", err)),
+            }
+        } else {
+            notes.push("reboot required; not rebooted".to_string());
+        }
+    }
+    let verification_status = if job.dry_run {
+        notes.push(format!("dry run via {}", backend.name()));
+        "pending"
+    } else if any_failed {
+        "failed"
+    } else {
+        "passed"
+    };
+
+    Ok(PatchReport {
+        tenant_id: job.tenant_id,
+        asset_id: job.asset_id,
+        plan_id: job.plan_id,
+        started_at: format_rfc3339_ms(started_at),
+        finished_at: format_rfc3339_ms(unix_time_ms()),
+        results,
+        reboot_confirmed,
+        verification_status: verification_status.to_string(),
+        verification_notes: if notes.is_empty() { None } else { Some(notes.join("; ")) },
+        reboot_required,
+        dry_run: job.dry_run,
+    })
+}
+
+fn install_package(backend: &dyn PatchBackend, package: &str, dry_run: bool) -> PackageResult {
+    match backend.install(package, dry_run) {
+        Ok(output) => {
+            let succeeded = output.exit_code == Some(0);
+            let status = match (dry_run, succeeded) {
+                (true, _) => PackageStatus::Skipped,
+                (false, true) => PackageStatus::Completed,
+                (false, false) => PackageStatus::Failed,
+            };
+            PackageResult {
+                patch_id: package.to_string(),
+                status,
+                stdout: Some(truncate_output(&output.stdout, MAX_PACKAGE_OUTPUT_BYTES).0),
+                stderr: Some(truncate_output(&output.stderr, MAX_PACKAGE_OUTPUT_BYTES).0),
+                exit_code: output.exit_code,
+                failure_type: (status == PackageStatus::Failed).then(|| "install_failure".to_string()),
+            }
+        }
+        Err(err) => PackageResult {
+            patch_id: package.to_string(),
+            status: if dry_run { PackageStatus::Skipped } else { PackageStatus::Failed },
+            stdout: None,
+            stderr: Some(err),
+            exit_code: None,
+            failure_type: (!dry_run).then(|| "unknown".to_string()),
+        },
+    }
+}
+
+/// Queue `report` as a `patch` uplink item for the patch results endpoint.
+pub async fn record_patch_report(report: &PatchReport, queue_dir: &Path) -> Result<(), String> {
+    let payload_json =
+        serde_json::to_string(report).map_err(|err| format!("failed to encode patch report: {err}"))?;
+    let plan_id = report
+        .plan_id
+        .chars()
+        .map(|ch| if ch.is_ascii_alphanumeric() || ch == '-' || ch == '_' { ch } else { '_' })
+        .collect::<String>();
+    enqueue_patch_item(queue_dir, &payload_json, &format!("patch-result-{}-{}", plan_id, unix_time_ms())).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::sync::Arc;
+
+    use super::{
+        parse_patch_job, record_patch_report, run_patch_job, run_with_timeout, PackageManager, PackageOutput,
+        PackageStatus, PatchBackend, PatchJobError,
+    };
+    use crate::command_router::{command_signing_message, CommandRouteConfig};
+    use crate::crypto::{HmacSha256, Signer};
+    use crate::policy::PolicyBundle;
+    use crate::rmm::ExecutionRequest;
+    use crate::time::unix_time_ms;
+
+    #[derive(Default)]
+    struct FakeBackend {
+        failing: Vec<&'static str>,
+        reboot_required: bool,
+        calls: RefCell<Vec<(String, bool)>>,
+        reboots: RefCell<u32>,
+    }
+
+    impl PatchBackend for FakeBackend {
+        fn name(&self) -> &'static str {
+            "fake"
+        }
+
+        fn install(&self, package: &str, dry_run: bool) -> Result<PackageOutput, String> {
+            self.calls.borrow_mut().push((package.to_string(), dry_run));
+            let failed = self.failing.contains(&package);
+            Ok(PackageOutput {
+                exit_code: Some(if failed { 100 } else { 0 }),
+                stdout: format!("installing {}", package).into_bytes(),
+                stderr: if failed { b"E: broken dependency".to_vec() } else { Vec::new() },
+            })
+        }
+
+        fn reboot_required(&self) -> bool {
+            self.reboot_required
+        }
+
+        fn reboot(&self) -> Result<(), String> {
+            *self.reboots.borrow_mut() += 1;
+            Ok(())
+        }
+    }
+
+    const KEY: &[u8] = b"unit-test-command-key";
+
+    fn route() -> CommandRouteConfig {
+        CommandRouteConfig::from_env().with_verifier(Arc::new(HmacSha256::new(KEY)))
+    }
+
+    fn build_request(job: &str) -> ExecutionRequest {
+        let mut request = ExecutionRequest {
+            command_id: "cmd-patch".to_string(),
+            signed_payload: job.to_string(),
+            signature: String::new(),
+            action: "patch-apply".to_string(),
+            arguments: Vec::new(),
+            requested_at_unix_ms: 0,
+            expires_at_unix_ms: u64::MAX,
+            earliest_start_unix_ms: None,
+            latest_start_unix_ms: None,
+            source: "test".to_string(),
+        };
+        request.signature = HmacSha256::new(KEY).sign(command_signing_message(&request.signed_command()).as_bytes());
+        request
+    }
+
+    const THREE_PACKAGES: &str = r#"{"tenant_id":"tenant-1","asset_id":"asset-1","plan_id":"plan-1","packages":["openssl","curl","KB5034441"]}"#;
+
+    #[test]
+    fn reports_partial_failure_without_rebooting() {
+        let backend = FakeBackend {
+            failing: vec!["curl"],
+            reboot_required: true,
+            ..FakeBackend::default()
+        };
+        let report = run_patch_job(&build_request(THREE_PACKAGES), &PolicyBundle::placeholder(), &route(), &backend, 1_000)
+            .expect("job runs");
+
+        let statuses = report.results.iter().map(|result| result.status).collect::<Vec<PackageStatus>>();
+        assert_eq!(statuses, vec![PackageStatus::Completed, PackageStatus::Failed, PackageStatus::Completed]);
+        assert_eq!(report.results[1].failure_type.as_deref(), Some("install_failure"));
+        assert_eq!(report.results[1].stderr.as_deref(), Some("E: broken dependency"));
+        assert_eq!(report.verification_status, "failed");
+        assert!(report.reboot_required);
+        assert!(!report.reboot_confirmed);
+        assert_eq!(*backend.reboots.borrow(), 0);
+        assert_eq!(backend.calls.borrow().len(), 3);
+
+        let payload = serde_json::to_value(&report).expect("report json");
+        assert_eq!(payload["plan_id"], "plan-1");
+        assert_eq!(payload["results"][1]["status"], "failed");
+        assert!(payload["started_at"].as_str().expect("started_at").ends_with('Z'));
+    }
+
+    #[test]
+    fn reboots_only_when_job_allows_it() {
+        let job = r#"{"tenant_id":"tenant-1","asset_id":"asset-1","plan_id":"plan-1","packages":["openssl"],"reboot_policy":"if_required"}"#;
+        let backend = FakeBackend {
+            reboot_required: true,
+            ..FakeBackend::default()
+        };
+        let report = run_patch_job(&build_request(job), &PolicyBundle::placeholder(), &route(), &backend, 1_000).expect("job runs");
+        assert_eq!(report.verification_status, "passed");
+        assert!(report.reboot_confirmed);
+        assert_eq!(*backend.reboots.borrow(), 1);
+    }
+
+    #[test]
+    fn dry_run_simulates_every_package() {
+        let job = r#"{"tenant_id":"tenant-1","asset_id":"asset-1","plan_id":"plan-1","packages":["openssl","curl"],"dry_run":true,"reboot_policy":"if_required"}"#;
+        let backend = FakeBackend {
+            reboot_required: true,
+            ..FakeBackend::default()
+        };
+        let report = run_patch_job(&build_request(job), &PolicyBundle::placeholder(), &route(), &backend, 1_000).expect("job runs");
+
+        assert!(report.dry_run);
+        assert!(report.results.iter().all(|result| result.status == PackageStatus::Skipped));
+        assert_eq!(report.verification_status, "pending");
+        assert!(!report.reboot_required);
+        assert_eq!(*backend.reboots.borrow(), 0);
+        assert!(backend.calls.borrow().iter().all(|(_, dry_run)| *dry_run));
+        assert_eq!(
+            PackageManager::AptGet.install_command("openssl", true),
+            Some(vec![
+                "apt-get".to_string(),
+                "install".to_string(),
+                "--simulate".to_string(),
+                "--only-upgrade".to_string(),
+                "openssl".to_string()
+            ])
+        );
+        assert_eq!(PackageManager::Winget.install_command("Git.Git", true), None);
+    }
+
+    #[test]
+    fn refuses_invalid_or_unscheduled_jobs() {
+        let backend = FakeBackend::default();
+        let policy = PolicyBundle::placeholder();
+        let injected = r#"{"tenant_id":"t","asset_id":"a","plan_id":"p","packages":["--allow-downgrades"]}"#;
+        assert!(matches!(parse_patch_job(&build_request(injected)), Err(PatchJobError::InvalidJob(_))));
+        assert!(matches!(parse_patch_job(&build_request("not json")), Err(PatchJobError::InvalidJob(_))));
+
+        let windowed = r#"{"tenant_id":"t","asset_id":"a","plan_id":"p","packages":["openssl"],"maintenance_window":{"start_unix_ms":5000,"end_unix_ms":9000}}"#;
+        assert_eq!(
+            run_patch_job(&build_request(windowed), &policy, &route(), &backend, 1_000),
+            Err(PatchJobError::OutsideMaintenanceWindow {
+                start_unix_ms: 5_000,
+                end_unix_ms: 9_000
+            })
+        );
+        assert!(run_patch_job(&build_request(windowed), &policy, &route(), &backend, 6_000).is_ok());
+
+        let mut revoked = PolicyBundle::placeholder();
+        revoked.execution.allowed_actions = vec!["script-run".to_string()];
+        assert!(matches!(
+            run_patch_job(&build_request(THREE_PACKAGES), &revoked, &route(), &backend, 1_000),
+            Err(PatchJobError::NotPermitted(_))
+        ));
+        assert_eq!(backend.calls.borrow().len(), 1);
+    }
+
+    #[test]
+    fn winget_refuses_kb_ids_and_foreign_reboots() {
+        let policy = PolicyBundle::placeholder();
+        assert!(matches!(
+            run_patch_job(&build_request(THREE_PACKAGES), &policy, &route(), &PackageManager::Winget, 1_000),
+            Err(PatchJobError::InvalidJob(reason)) if reason.contains("KB5034441")
+        ));
+        assert!(PackageManager::Winget.check_package("kb5034441").is_err());
+        assert!(PackageManager::Winget.check_package("Git.Git").is_ok());
+        assert!(PackageManager::AptGet.check_package("openssl").is_ok());
+
+        let foreign = if cfg!(windows) { PackageManager::AptGet } else { PackageManager::Winget };
+        assert!(foreign.reboot().is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn hung_package_manager_is_killed() {
+        let started = std::time::Instant::now();
+        let err = run_with_timeout("sleep", &["30"], std::time::Duration::from_millis(200)).expect_err("times out");
+        assert!(err.contains("timed out"));
+        assert!(started.elapsed() < std::time::Duration::from_secs(10));
+
+        let output = run_with_timeout("sh", &["-c", "echo out; echo err >&2; exit 3"], std::time::Duration::from_secs(10))
+            .expect("runs");
+        assert_eq!(output.exit_code, Some(3));
+        assert_eq!(output.stdout, b"out\n");
+        assert_eq!(output.stderr, b"err\n");
+    }
+
+    #[tokio::test]
+    async fn queues_report_as_patch_item() {
+        let backend = FakeBackend::default();
+        let report = run_patch_job(&build_request(THREE_PACKAGES), &PolicyBundle::placeholder(), &route(), &backend, 1_000)
+            .expect("job runs");
+        let queue_dir = std::env::temp_dir().join(format!("patch-queue-{}-{}", std::process::id(), unix_time_ms()));
+
+        record_patch_report(&report, &queue_dir).await.expect("queued");
+        let item = std::fs::read_dir(&queue_dir).expect("queue").flatten().next().expect("item");
+        let value: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(item.path()).expect("read")).expect("json");
+        assert_eq!(value["kind"], "patch");
+        let payload: serde_json::Value =
+            serde_json::from_str(value["payload_json"].as_str().expect("payload")).expect("payload json");
+        assert_eq!(payload["verification_status"], "passed");
+
+        let _ = std::fs::remove_dir_all(queue_dir);
+    }
+}
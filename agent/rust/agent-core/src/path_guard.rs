@@ -0,0 +1,184 @@
+use std::env;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Bounds applied to operator-supplied paths before and after they are canonicalised, so a
+/// deep or symlink-expanded path is refused instead of walked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PathLimits {
+    pub max_components: usize,
+    pub max_len: usize,
+}
+
+impl PathLimits {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let max_components = env::var("AGENT_PATH_MAX_COMPONENTS")
+            .ok()
+            .and_then(|value| value.parse::<usize>().ok())
+            .filter(|value| *value > 0)
+            .unwrap_or(defaults.max_components);
+        let max_len = env::var("AGENT_PATH_MAX_LEN")
+            .ok()
+            .and_then(|value| value.parse::<usize>().ok())
+            .filter(|value| *value > 0)
+            .unwrap_or(defaults.max_len);
+        Self {
+            max_components,
+            max_len,
+        }
+    }
+}
+
+impl Default for PathLimits {
+    fn default() -> Self {
+        Self {
+            max_components: 64,
+            max_len: 4096,
+        }
+    }
+}
+
+/// Why a path was not resolved. Callers skip the item and report the reason.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathRejection {
+    TooLong { len: usize, max: usize },
+    TooDeep { components: usize, max: usize },
+    /// Canonicalisation failed: missing target, dangling link, symlink loop or permissions.
+    Unresolvable(String),
+    OutsideRoot,
+}
+
+impl fmt::Display for PathRejection {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PathRejection::TooLong { len, max } => write!(formatter, "Path is {} bytes, over the {} byte limit", len, max),
+            PathRejection::TooDeep { components, max } => {
+                write!(formatter, "Path has {} components, over the {} component limit", components, max)
+            }
+            PathRejection::Unresolvable(reason) => write!(formatter, "Path could not be resolved: {}", reason),
+            PathRejection::OutsideRoot => write!(formatter, "Path outside allowed root"),
+        }
+    }
+}
+
+pub fn check_limits(path: &Path, limits: &PathLimits) -> Result<(), PathRejection> {
+    let len = path.as_os_str().len();
+    if len > limits.max_len {
+        return Err(PathRejection::TooLong {
+            len,
+            max: limits.max_len,
+        });
+    }
+    let components = path.components().count();
+    if components > limits.max_components {
+        return Err(PathRejection::TooDeep {
+            components,
+            max: limits.max_components,
+        });
+    }
+    Ok(())
+}
+
+/// Canonicalise `path`, checking the limits on both the input and the resolved path.
+pub fn canonicalize_bounded(path: &Path, limits: &PathLimits) -> Result<PathBuf, PathRejection> {
+    check_limits(path, limits)?;
+    let resolved = path.canonicalize().map_err(|err| {
+        PathRejection::Unresolvable(if is_symlink_loop(&err) {
+            "symlink loop".to_string()
+        } else if err.kind() == io::ErrorKind::NotFound {
+            "no such file or dangling symlink".to_string()
+        } else {
+            err.to_string()
+        })
+    })?;
+    check_limits(&resolved, limits)?;
+    Ok(resolved)
+}
+
+fn is_symlink_loop(err: &io::Error) -> bool {
+    #[cfg(unix)]
+    {
+        err.raw_os_error() == Some(libc::ELOOP)
+    }
+    #[cfg(windows)]
+    {
+        // ERROR_CANT_RESOLVE_FILENAME
+        err.raw_os_error() == Some(1921)
+    }
+    #[cfg(not(any(unix, windows)))]
+    {
+        let _ = err;
+        false
+    }
+}
+
+/// Resolve `path` (relative paths against `canonical_root`) and require the result to stay
+/// under `canonical_root`.
+pub fn resolve_under(path: &Path, canonical_root: &Path, limits: &PathLimits) -> Result<PathBuf, PathRejection> {
+    let candidate = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        canonical_root.join(path)
+    };
+    let resolved = canonicalize_bounded(&candidate, limits)?;
+    if resolved.starts_with(canonical_root) {
+        Ok(resolved)
+    } else {
+        Err(PathRejection::OutsideRoot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::{resolve_under, PathLimits, PathRejection};
+    use crate::time::unix_time_ms;
+
+    fn temp_root(label: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("path-guard-{}-{}-{}", label, std::process::id(), unix_time_ms()));
+        fs::create_dir_all(&dir).expect("create root");
+        dir.canonicalize().expect("canonical root")
+    }
+
+    #[test]
+    fn rejects_over_long_and_over_deep_paths() {
+        let root = temp_root("limits");
+        let limits = PathLimits {
+            max_components: 16,
+            max_len: 256,
+        };
+        let long_name = "a".repeat(300);
+        assert!(matches!(
+            resolve_under(&PathBuf::from(&long_name), &root, &limits),
+            Err(PathRejection::TooLong { max: 256, .. })
+        ));
+        let deep = (0..20).map(|_| "d").collect::<Vec<_>>().join("/");
+        assert!(matches!(
+            resolve_under(&PathBuf::from(deep), &root, &limits),
+            Err(PathRejection::TooDeep { max: 16, .. })
+        ));
+        fs::write(root.join("ok.log"), "ok").expect("write file");
+        assert_eq!(resolve_under(&PathBuf::from("ok.log"), &root, &limits), Ok(root.join("ok.log")));
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn reports_broken_and_looping_symlinks() {
+        let root = temp_root("links");
+        let limits = PathLimits::default();
+        std::os::unix::fs::symlink(root.join("missing.log"), root.join("broken.log")).expect("broken link");
+        std::os::unix::fs::symlink(root.join("loop-b"), root.join("loop-a")).expect("loop link a");
+        std::os::unix::fs::symlink(root.join("loop-a"), root.join("loop-b")).expect("loop link b");
+
+        let broken = resolve_under(&PathBuf::from("broken.log"), &root, &limits).expect_err("broken link");
+        assert_eq!(broken, PathRejection::Unresolvable("no such file or dangling symlink".to_string()));
+        let looped = resolve_under(&PathBuf::from("loop-a"), &root, &limits).expect_err("symlink loop");
+        assert_eq!(looped, PathRejection::Unresolvable("symlink loop".to_string()));
+        let _ = fs::remove_dir_all(root);
+    }
+}
@@ -1,38 +1,264 @@
-#[derive(Debug, Clone)]
-pub struct PipelineStatus {
-    pub edr_ready: bool,
-    pub siem_ready: bool,
-    pub rmm_ready: bool,
-    pub vulnerability_ready: bool,
+use std::fmt;
+
+use serde::Serialize;
+
+/// Ordered from best to worst.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthState {
+    Ready,
+    Degraded,
+    Failed,
 }
 
-impl PipelineStatus {
-    pub fn new() -> Self {
+impl HealthState {
+    /// Numeric value exported as a Prometheus gauge (0 ready, 1 degraded, 2 failed).
+    pub fn as_gauge(self) -> u64 {
+        match self {
+            Self::Ready => 0,
+            Self::Degraded => 1,
+            Self::Failed => 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ComponentHealth {
+    pub state: HealthState,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+impl ComponentHealth {
+    pub fn ready() -> Self {
         Self {
-            edr_ready: false,
-            siem_ready: false,
-            rmm_ready: false,
-            vulnerability_ready: false,
+            state: HealthState::Ready,
+            reason: None,
         }
     }
 
-    pub fn mark_edr_ready(&mut self) {
-        self.edr_ready = true;
+    pub fn degraded(reason: impl Into<String>) -> Self {
+        Self {
+            state: HealthState::Degraded,
+            reason: Some(reason.into()),
+        }
     }
 
-    pub fn mark_siem_ready(&mut self) {
-        self.siem_ready = true;
+    pub fn failed(reason: impl Into<String>) -> Self {
+        Self {
+            state: HealthState::Failed,
+            reason: Some(reason.into()),
+        }
     }
 
-    pub fn mark_rmm_ready(&mut self) {
-        self.rmm_ready = true;
+    /// A component switched off by configuration. It is Ready, so it does not hold the
+    /// pipeline back, and the reason keeps it visible in the report.
+    pub fn not_configured(reason: impl Into<String>) -> Self {
+        Self {
+            state: HealthState::Ready,
+            reason: Some(reason.into()),
+        }
     }
 
-    pub fn mark_vulnerability_ready(&mut self) {
-        self.vulnerability_ready = true;
+    /// Ready for a successful initialisation step, Failed with the error as the reason
+    /// otherwise.
+    pub fn from_result<T, E: fmt::Display>(result: &Result<T, E>) -> Self {
+        match result {
+            Ok(_) => Self::ready(),
+            Err(err) => Self::failed(err.to_string()),
+        }
     }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ComponentReport {
+    pub component: String,
+    #[serde(flatten)]
+    pub health: ComponentHealth,
+}
+
+/// Point-in-time pipeline health, sent in the heartbeat payload and served by the metrics
+/// endpoint.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HealthReport {
+    pub overall: HealthState,
+    pub components: Vec<ComponentReport>,
+    pub generated_at_unix_ms: u64,
+}
 
+impl HealthReport {
     pub fn is_fully_ready(&self) -> bool {
-        self.edr_ready && self.siem_ready && self.rmm_ready && self.vulnerability_ready
+        self.overall == HealthState::Ready
+    }
+
+    /// Worst state among `components`; `None` while any of them is missing from the report.
+    pub fn state_of(&self, components: &[String]) -> Option<HealthState> {
+        components
+            .iter()
+            .map(|name| {
+                self.components
+                    .iter()
+                    .find(|report| &report.component == name)
+                    .map(|report| report.health.state)
+            })
+            .try_fold(HealthState::Ready, |worst, state| Some(worst.max(state?)))
+    }
+
+    /// `component: reason` for every component that is not ready, in registration order.
+    pub fn not_ready_reasons(&self) -> Vec<String> {
+        self.components
+            .iter()
+            .filter(|report| report.health.state != HealthState::Ready)
+            .map(|report| {
+                format!(
+                    "{}: {}",
+                    report.component,
+                    report.health.reason.as_deref().unwrap_or("no reason given")
+                )
+            })
+            .collect()
+    }
+}
+
+/// Check run on every report; receives the report time in unix milliseconds.
+pub type HealthCheck = Box<dyn Fn(u64) -> ComponentHealth + Send + Sync>;
+
+/// Pipeline readiness computed from registered subsystem checks rather than set by hand.
+#[derive(Default)]
+pub struct PipelineHealth {
+    checks: Vec<(String, HealthCheck)>,
+}
+
+impl fmt::Debug for PipelineHealth {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_struct("PipelineHealth")
+            .field("components", &self.checks.iter().map(|(name, _)| name).collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl PipelineHealth {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register<F>(&mut self, component: &str, check: F)
+    where
+        F: Fn(u64) -> ComponentHealth + Send + Sync + 'static,
+    {
+        self.checks.push((component.to_string(), Box::new(check)));
+    }
+
+    pub fn report(&self, now_unix_ms: u64) -> HealthReport {
+        let components = self
+            .checks
+            .iter()
+            .map(|(component, check)| ComponentReport {
+                component: component.clone(),
+                health: check(now_unix_ms),
+            })
+            .collect::<Vec<ComponentReport>>();
+        HealthReport {
+            overall: aggregate(&components),
+            components,
+            generated_at_unix_ms: now_unix_ms,
+        }
+    }
+}
+
+/// Ready only when every component is ready; Failed when every component has failed (or
+/// none are registered); anything in between is Degraded.
+fn aggregate(components: &[ComponentReport]) -> HealthState {
+    if components.is_empty() || components.iter().all(|report| report.health.state == HealthState::Failed) {
+        HealthState::Failed
+    } else if components.iter().all(|report| report.health.state == HealthState::Ready) {
+        HealthState::Ready
+    } else {
+        HealthState::Degraded
+    }
+}
+
+/// Shared freshness rule: Ready while `last_unix_ms` (or process start, before the first
+/// event) is within `max_age_ms`, Degraded once it falls behind.
+pub fn freshness(what: &str, last_unix_ms: u64, started_at_unix_ms: u64, max_age_ms: u64, now_unix_ms: u64) -> ComponentHealth {
+    let reference = last_unix_ms.max(started_at_unix_ms);
+    let age_ms = now_unix_ms.saturating_sub(reference);
+    if age_ms <= max_age_ms {
+        ComponentHealth::ready()
+    } else if last_unix_ms == 0 {
+        ComponentHealth::degraded(format!("no {} since start ({} ms)", what, age_ms))
+    } else {
+        ComponentHealth::degraded(format!("last {} {} ms ago", what, age_ms))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{freshness, ComponentHealth, HealthState, PipelineHealth};
+
+    fn pipeline(states: &[ComponentHealth]) -> PipelineHealth {
+        let mut health = PipelineHealth::new();
+        for (index, state) in states.iter().enumerate() {
+            let state = state.clone();
+            health.register(&format!("component-{}", index), move |_| state.clone());
+        }
+        health
+    }
+
+    #[test]
+    fn aggregates_component_states() {
+        let ready = pipeline(&[ComponentHealth::ready(), ComponentHealth::ready()]).report(1);
+        assert_eq!(ready.overall, HealthState::Ready);
+        assert!(ready.is_fully_ready());
+
+        let degraded = pipeline(&[ComponentHealth::ready(), ComponentHealth::degraded("stale")]).report(1);
+        assert_eq!(degraded.overall, HealthState::Degraded);
+
+        let one_failed = pipeline(&[ComponentHealth::ready(), ComponentHealth::failed("expired")]).report(1);
+        assert_eq!(one_failed.overall, HealthState::Degraded);
+        assert!(!one_failed.is_fully_ready());
+        assert_eq!(one_failed.state_of(&["component-0".to_string()]), Some(HealthState::Ready));
+        assert_eq!(
+            one_failed.state_of(&["component-0".to_string(), "component-1".to_string()]),
+            Some(HealthState::Failed)
+        );
+        assert_eq!(one_failed.state_of(&["missing".to_string()]), None);
+
+        let all_failed = pipeline(&[ComponentHealth::failed("a"), ComponentHealth::failed("b")]).report(1);
+        assert_eq!(all_failed.overall, HealthState::Failed);
+
+        assert_eq!(PipelineHealth::new().report(1).overall, HealthState::Failed);
+
+        let switched_off = pipeline(&[ComponentHealth::ready(), ComponentHealth::not_configured("no endpoint")]).report(1);
+        assert!(switched_off.is_fully_ready());
+        assert!(switched_off.not_ready_reasons().is_empty());
+        assert_eq!(switched_off.components[1].health.reason.as_deref(), Some("no endpoint"));
+    }
+
+    #[test]
+    fn serialises_report_with_reasons() {
+        let mut health = PipelineHealth::new();
+        health.register("policy", |_| ComponentHealth::ready());
+        health.register("uplink", |now| freshness("uplink cycle", 1_000, 0, 60_000, now));
+
+        let report = health.report(120_000);
+        let value = serde_json::to_value(&report).expect("report json");
+        assert_eq!(value["overall"], "degraded");
+        assert_eq!(value["generated_at_unix_ms"], 120_000);
+        assert_eq!(value["components"][0]["component"], "policy");
+        assert_eq!(value["components"][0]["state"], "ready");
+        assert!(value["components"][0].get("reason").is_none());
+        assert_eq!(value["components"][1]["state"], "degraded");
+        assert_eq!(value["components"][1]["reason"], "last uplink cycle 119000 ms ago");
+    }
+
+    #[test]
+    fn freshness_allows_grace_after_start() {
+        assert_eq!(freshness("heartbeat", 0, 10_000, 60_000, 30_000).state, HealthState::Ready);
+        let stale = freshness("heartbeat", 0, 10_000, 60_000, 90_000);
+        assert_eq!(stale.state, HealthState::Degraded);
+        assert_eq!(stale.reason.as_deref(), Some("no heartbeat since start (80000 ms)"));
+        assert_eq!(freshness("heartbeat", 85_000, 10_000, 60_000, 90_000).state, HealthState::Ready);
     }
 }
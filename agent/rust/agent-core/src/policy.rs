@@ -1,14 +1,103 @@
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
 use std::env;
-use std::fs;
+use std::fmt;
+use std::path::{Component, Path};
 
-use base64::engine::general_purpose::STANDARD as BASE64_STANDARD;
-use base64::Engine as _;
-use hmac::{Hmac, Mac};
 use serde::Deserialize;
-use sha2::Sha256;
 
+use crate::compression::read_file_bounded;
+use crate::crypto::{HmacSha256, Signer, Verifier};
 use crate::security::{validate_bounded_string, ValidationLimits};
+use crate::time::{clock_skew_tolerance_ms_from_env, not_before_widening_ms, within_window};
+
+/// Upper bound on a policy bundle file, applied both before and after gzip decompression.
+const MAX_POLICY_BYTES: u64 = 1024 * 1024;
+
+/// Oldest policy schema this agent can migrate forward.
+pub const MIN_POLICY_SCHEMA_VERSION: u32 = 1;
+/// Schema the in-memory `PolicyBundle` layout corresponds to. Version 2 introduced
+/// `execution.argument_rules`. Fields added since (`stream_categories`,
+/// `execution.file_destinations`, `evidence_profiles`) are optional and default to empty, so
+/// any schema may carry them; a field older agents must refuse needs a new version and a
+/// `migrate` step.
+pub const CURRENT_POLICY_SCHEMA_VERSION: u32 = 2;
+
+/// A `PolicyBundle` that has been migrated to `CURRENT_POLICY_SCHEMA_VERSION`.
+pub type PolicyBundleV2 = PolicyBundle;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PolicySchemaError {
+    /// The bundle predates the oldest schema this agent can migrate.
+    Unsupported { found: u32 },
+    /// The bundle was written for a newer agent; the agent needs updating.
+    NewerThanAgent { found: u32, supported: u32 },
+    /// The bundle carries fields its declared schema version does not define.
+    Invalid(String),
+}
+
+impl fmt::Display for PolicySchemaError {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unsupported { found } => write!(
+                formatter,
+                "policy schema_version {} is not supported (minimum {})",
+                found, MIN_POLICY_SCHEMA_VERSION
+            ),
+            Self::NewerThanAgent { found, supported } => write!(
+                formatter,
+                "policy schema_version {} is newer than this agent supports ({}); update the agent",
+                found, supported
+            ),
+            Self::Invalid(reason) => write!(formatter, "policy schema invalid: {}", reason),
+        }
+    }
+}
+
+/// Why `PolicyBundle::check` refused a bundle.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PolicyValidationError {
+    Schema(PolicySchemaError),
+    /// A field is empty, too long, unsorted, duplicated or otherwise malformed.
+    InvalidField(&'static str),
+    KeyIdMismatch { expected: String, found: String },
+    NotYetValid { issued_at_unix_time_ms: u64 },
+    Expired { expires_at_unix_time_ms: u64 },
+    /// No signing key is configured and unsigned bundles are not allowed.
+    Unsigned,
+    BadSignature,
+}
+
+impl fmt::Display for PolicyValidationError {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Schema(err) => write!(formatter, "{}", err),
+            Self::InvalidField(field) => write!(formatter, "policy field {} is invalid", field),
+            Self::KeyIdMismatch { expected, found } => write!(
+                formatter,
+                "policy signed with key {} but {} is expected",
+                found, expected
+            ),
+            Self::NotYetValid { issued_at_unix_time_ms } => write!(
+                formatter,
+                "policy is not valid until {}",
+                issued_at_unix_time_ms
+            ),
+            Self::Expired { expires_at_unix_time_ms } => {
+                write!(formatter, "policy expired at {}", expires_at_unix_time_ms)
+            }
+            Self::Unsigned => write!(formatter, "policy is unsigned and no signing key is configured"),
+            Self::BadSignature => write!(formatter, "policy signature did not verify"),
+        }
+    }
+}
+
+/// Where `now` falls relative to a bundle's validity window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PolicyExpiryState {
+    NotYetValid,
+    Valid,
+    Expired,
+}
 
 #[derive(Debug, Clone, Deserialize)]
 #[serde(deny_unknown_fields)]
@@ -16,6 +105,54 @@ pub struct ExecutionPolicy {
     pub allowed_actions: Vec<String>,
     pub max_arguments: usize,
     pub max_argument_length: usize,
+    #[serde(default)]
+    pub argument_rules: BTreeMap<String, ArgumentRules>,
+    /// Absolute directories `file-place` may write into; empty refuses every destination.
+    #[serde(default)]
+    pub file_destinations: Vec<String>,
+}
+
+/// Per-action relaxations of the argument safety scan. Actions without an entry get the
+/// strict defaults, which reject every hazard class.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ArgumentRules {
+    pub allow_shell_metacharacters: bool,
+    pub allow_env_expansion: bool,
+    pub allow_path_traversal: bool,
+    /// Arguments may carry secrets; they are masked in reported execution results.
+    pub sensitive: bool,
+    /// Heavy or service-affecting; only runs inside the agent's maintenance windows.
+    pub disruptive: bool,
+}
+
+/// Evidence captured automatically when a qualifying EDR detection fires.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EvidenceProfile {
+    /// Rules that trigger the profile; empty matches any rule at or above `min_severity`.
+    #[serde(default)]
+    pub rule_ids: Vec<String>,
+    #[serde(default = "default_profile_min_severity")]
+    pub min_severity: u8,
+    /// Files to capture, resolved under the agent's evidence roots like `EVIDENCE_PATHS`.
+    pub paths: Vec<String>,
+    pub max_item_bytes: u64,
+    pub max_total_bytes: u64,
+    /// Minimum gap between collections triggered by the same rule.
+    #[serde(default = "default_profile_cooldown_secs")]
+    pub cooldown_secs: u64,
+    /// Also capture the detection's process image or file, held to the same roots.
+    #[serde(default)]
+    pub include_target: bool,
+}
+
+fn default_profile_min_severity() -> u8 {
+    8
+}
+
+fn default_profile_cooldown_secs() -> u64 {
+    900
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -29,6 +166,12 @@ pub struct PolicyBundle {
     pub signature: String,
     pub execution: ExecutionPolicy,
     pub telemetry_streams: Vec<String>,
+    /// Optional per-stream category allowlist; streams without an entry carry any category.
+    #[serde(default)]
+    pub stream_categories: BTreeMap<String, Vec<String>>,
+    /// Optional detection-triggered evidence collection, keyed by profile name.
+    #[serde(default)]
+    pub evidence_profiles: BTreeMap<String, EvidenceProfile>,
 }
 
 #[derive(Debug, Clone)]
@@ -36,6 +179,9 @@ pub struct PolicyValidationOptions {
     pub signing_key: Option<String>,
     pub expected_key_id: Option<String>,
     pub allow_unsigned: bool,
+    pub clock_skew_tolerance_ms: u64,
+    /// Added to the tolerance before `issued_at` only, after the clock was set back.
+    pub not_before_widening_ms: u64,
 }
 
 impl PolicyValidationOptions {
@@ -45,11 +191,14 @@ impl PolicyValidationOptions {
         let allow_unsigned = env::var("AGENT_POLICY_ALLOW_UNSIGNED")
             .map(|value| value.eq_ignore_ascii_case("true"))
             .unwrap_or(false);
+        let clock_skew_tolerance_ms = clock_skew_tolerance_ms_from_env();
 
         Self {
             signing_key,
             expected_key_id,
             allow_unsigned,
+            clock_skew_tolerance_ms,
+            not_before_widening_ms: not_before_widening_ms(),
         }
     }
 }
@@ -64,25 +213,31 @@ impl PolicyBundle {
             signing_key_id: "signing-key-placeholder".to_string(),
             signature: "signature-placeholder".to_string(),
             execution: ExecutionPolicy {
-                allowed_actions: vec!["script-run".to_string(), "patch-apply".to_string()],
+                allowed_actions: vec![
+                    "script-run".to_string(),
+                    "patch-apply".to_string(),
+                    "file-place".to_string(),
+                ],
                 max_arguments: 8,
                 max_argument_length: 256,
+                argument_rules: BTreeMap::new(),
+                file_destinations: Vec::new(),
             },
             telemetry_streams: vec!["sensor".to_string(), "agent".to_string()],
+            stream_categories: BTreeMap::new(),
+            evidence_profiles: BTreeMap::new(),
         }
     }
 
     pub fn from_env() -> Self {
         if let Ok(path) = env::var("AGENT_POLICY_PATH") {
-            if let Ok(raw) = fs::read_to_string(path) {
-                if let Ok(policy) = serde_json::from_str::<PolicyBundle>(&raw) {
-                    return policy;
-                }
+            if let Some(policy) = Self::from_path(Path::new(&path)) {
+                return policy;
             }
         }
 
         if let Ok(raw) = env::var("AGENT_POLICY_JSON") {
-            if let Ok(policy) = serde_json::from_str::<PolicyBundle>(&raw) {
+            if let Some(policy) = Self::from_json(&raw) {
                 return policy;
             }
         }
@@ -90,90 +245,262 @@ impl PolicyBundle {
         Self::placeholder()
     }
 
+    /// Parse an inline policy bundle, refusing anything over `MAX_POLICY_BYTES`.
+    pub fn from_json(raw: &str) -> Option<Self> {
+        if raw.len() as u64 > MAX_POLICY_BYTES {
+            return None;
+        }
+        serde_json::from_str::<PolicyBundle>(raw).ok()
+    }
+
+    /// Load a policy bundle from a JSON or gzip-compressed JSON (`.json.gz`) file.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let raw = read_file_bounded(path, MAX_POLICY_BYTES).ok()?;
+        serde_json::from_slice::<PolicyBundle>(&raw).ok()
+    }
+
+    /// Upgrade the bundle to the current schema, defaulting fields older versions lack.
+    /// Signatures cover the bundle as issued, so verify with `validate` before migrating.
+    pub fn migrate(&self) -> Result<PolicyBundleV2, PolicySchemaError> {
+        match self.schema_version {
+            version if version < MIN_POLICY_SCHEMA_VERSION => {
+                Err(PolicySchemaError::Unsupported { found: version })
+            }
+            1 => {
+                if !self.execution.argument_rules.is_empty() {
+                    return Err(PolicySchemaError::Invalid(
+                        "execution.argument_rules requires schema_version 2".to_string(),
+                    ));
+                }
+                let mut migrated = self.clone();
+                migrated.schema_version = CURRENT_POLICY_SCHEMA_VERSION;
+                Ok(migrated)
+            }
+            CURRENT_POLICY_SCHEMA_VERSION => Ok(self.clone()),
+            version => Err(PolicySchemaError::NewerThanAgent {
+                found: version,
+                supported: CURRENT_POLICY_SCHEMA_VERSION,
+            }),
+        }
+    }
+
     pub fn validate(&self, now_unix_time_ms: u64, options: &PolicyValidationOptions) -> bool {
+        self.check(now_unix_time_ms, options).is_ok()
+    }
+
+    /// Same checks as `validate`, reporting the first one that fails.
+    pub fn check(&self, now_unix_time_ms: u64, options: &PolicyValidationOptions) -> Result<(), PolicyValidationError> {
         // Signature validation is enforced when AGENT_POLICY_SIGNING_KEY is set.
         let limits = ValidationLimits::default_limits();
-        if self.schema_version == 0 {
-            return false;
-        }
-        if !validate_bounded_string(&self.version, 64) {
-            return false;
-        }
-        if !validate_bounded_string(&self.signing_key_id, 128) {
-            return false;
-        }
+        self.check_contents()?;
         if !validate_bounded_string(&self.signature, limits.max_payload_len) {
-            return false;
+            return Err(PolicyValidationError::InvalidField("signature"));
         }
         if let Some(expected_key_id) = &options.expected_key_id {
             if &self.signing_key_id != expected_key_id {
-                return false;
+                return Err(PolicyValidationError::KeyIdMismatch {
+                    expected: expected_key_id.clone(),
+                    found: self.signing_key_id.clone(),
+                });
             }
         }
-        if self.issued_at_unix_time_ms > self.expires_at_unix_time_ms {
-            return false;
+        if !within_window(
+            now_unix_time_ms,
+            self.issued_at_unix_time_ms.saturating_sub(options.not_before_widening_ms),
+            self.expires_at_unix_time_ms,
+            options.clock_skew_tolerance_ms,
+        ) {
+            return Err(if now_unix_time_ms < self.issued_at_unix_time_ms {
+                PolicyValidationError::NotYetValid {
+                    issued_at_unix_time_ms: self.issued_at_unix_time_ms,
+                }
+            } else {
+                PolicyValidationError::Expired {
+                    expires_at_unix_time_ms: self.expires_at_unix_time_ms,
+                }
+            });
         }
-        if now_unix_time_ms < self.issued_at_unix_time_ms
-            || now_unix_time_ms > self.expires_at_unix_time_ms
-        {
-            return false;
+
+        if let Some(signing_key) = &options.signing_key {
+            if !self.verify_signature(signing_key) {
+                return Err(PolicyValidationError::BadSignature);
+            }
+        } else if !options.allow_unsigned {
+            return Err(PolicyValidationError::Unsigned);
+        }
+
+        Ok(())
+    }
+
+    /// Checks on the signed content alone, independent of time, key and signature. `check`
+    /// runs them before anything else and `sign_with_key` refuses a bundle that fails them.
+    fn check_contents(&self) -> Result<(), PolicyValidationError> {
+        let limits = ValidationLimits::default_limits();
+        self.migrate().map_err(PolicyValidationError::Schema)?;
+        if !validate_bounded_string(&self.version, 64) || self.version.contains('|') {
+            return Err(PolicyValidationError::InvalidField("version"));
+        }
+        if !validate_bounded_string(&self.signing_key_id, 128) || self.signing_key_id.contains('|') {
+            return Err(PolicyValidationError::InvalidField("signing_key_id"));
+        }
+        if self.issued_at_unix_time_ms > self.expires_at_unix_time_ms {
+            return Err(PolicyValidationError::InvalidField("issued_at_unix_time_ms"));
         }
 
         if self.execution.allowed_actions.is_empty()
             || self.execution.max_arguments == 0
             || self.execution.max_argument_length == 0
         {
-            return false;
+            return Err(PolicyValidationError::InvalidField("execution"));
         }
 
         let mut unique_actions = HashSet::new();
         for action in &self.execution.allowed_actions {
-            if !validate_bounded_string(action, limits.max_command_id_len) {
-                return false;
-            }
-            if !is_valid_action_name(action) {
-                return false;
-            }
-            if !unique_actions.insert(action) {
-                return false;
+            if !validate_bounded_string(action, limits.max_command_id_len)
+                || !is_valid_action_name(action)
+                || !unique_actions.insert(action)
+            {
+                return Err(PolicyValidationError::InvalidField("execution.allowed_actions"));
             }
         }
         if !is_sorted(&self.execution.allowed_actions) {
-            return false;
+            return Err(PolicyValidationError::InvalidField("execution.allowed_actions"));
+        }
+        if !self
+            .execution
+            .argument_rules
+            .keys()
+            .all(|action| unique_actions.contains(action))
+        {
+            return Err(PolicyValidationError::InvalidField("execution.argument_rules"));
+        }
+        if !self.file_destinations_valid() {
+            return Err(PolicyValidationError::InvalidField("execution.file_destinations"));
         }
 
         if self.telemetry_streams.is_empty() {
-            return false;
+            return Err(PolicyValidationError::InvalidField("telemetry_streams"));
         }
 
         let mut unique_streams = HashSet::new();
         for stream in &self.telemetry_streams {
-            if !validate_bounded_string(stream, limits.max_stream_len) {
-                return false;
-            }
-            if !unique_streams.insert(stream) {
-                return false;
+            if !validate_bounded_string(stream, limits.max_stream_len)
+                || !is_payload_token(stream)
+                || !unique_streams.insert(stream)
+            {
+                return Err(PolicyValidationError::InvalidField("telemetry_streams"));
             }
         }
         if !is_sorted(&self.telemetry_streams) {
-            return false;
+            return Err(PolicyValidationError::InvalidField("telemetry_streams"));
         }
-
-        if let Some(signing_key) = &options.signing_key {
-            if !self.verify_signature(signing_key) {
-                return false;
-            }
-        } else if !options.allow_unsigned {
-            return false;
+        if !self.stream_categories_valid(&unique_streams) {
+            return Err(PolicyValidationError::InvalidField("stream_categories"));
+        }
+        if !self.evidence_profiles_valid() {
+            return Err(PolicyValidationError::InvalidField("evidence_profiles"));
         }
 
-        true
+        Ok(())
     }
 
     pub fn allows_action(&self, action: &str) -> bool {
         self.execution.allowed_actions.iter().any(|item| item == action)
     }
 
+    /// Validity of the bundle at `now`, allowing `tolerance_ms` of clock skew either side.
+    pub fn expiry_state(&self, now: u64, tolerance_ms: u64) -> PolicyExpiryState {
+        if within_window(now, self.issued_at_unix_time_ms, self.expires_at_unix_time_ms, tolerance_ms) {
+            PolicyExpiryState::Valid
+        } else if now < self.issued_at_unix_time_ms {
+            PolicyExpiryState::NotYetValid
+        } else {
+            PolicyExpiryState::Expired
+        }
+    }
+
+    /// Whether `stream` may carry `category`. Streams without a `stream_categories` entry are
+    /// unrestricted; restricted streams refuse payloads with no category.
+    pub fn allows_category(&self, stream: &str, category: Option<&str>) -> bool {
+        match self.stream_categories.get(stream) {
+            Some(categories) => category
+                .map(|category| categories.iter().any(|item| item == category))
+                .unwrap_or(false),
+            None => true,
+        }
+    }
+
+    /// Each entry must name a listed stream and hold a non-empty, sorted, unique set of
+    /// categories free of signing-payload delimiters.
+    fn stream_categories_valid(&self, streams: &HashSet<&String>) -> bool {
+        let limits = ValidationLimits::default_limits();
+        self.stream_categories.iter().all(|(stream, categories)| {
+            let mut unique_categories = HashSet::new();
+            streams.contains(stream)
+                && !categories.is_empty()
+                && categories.iter().all(|category| {
+                    validate_bounded_string(category, limits.max_stream_len)
+                        && is_payload_token(category)
+                        && unique_categories.insert(category)
+                })
+                && is_sorted(categories)
+        })
+    }
+
+    /// Profiles need a valid name, a severity in 1-10, a positive item limit within the
+    /// total limit and sorted, unique rule ids and paths that cannot be mistaken for payload
+    /// delimiters. At most one profile may claim a given rule id.
+    fn evidence_profiles_valid(&self) -> bool {
+        let limits = ValidationLimits::default_limits();
+        let mut claimed_rules = HashSet::new();
+        self.evidence_profiles.iter().all(|(name, profile)| {
+            let mut unique_paths = HashSet::new();
+            validate_bounded_string(name, limits.max_command_id_len)
+                && is_valid_action_name(name)
+                && (1..=10).contains(&profile.min_severity)
+                && profile.max_item_bytes > 0
+                && profile.max_total_bytes >= profile.max_item_bytes
+                && (!profile.paths.is_empty() || profile.include_target)
+                && profile.paths.iter().all(|path| {
+                    validate_bounded_string(path, limits.max_payload_len)
+                        && is_payload_path(path)
+                        && unique_paths.insert(path)
+                })
+                && is_sorted(&profile.paths)
+                && profile.rule_ids.iter().all(|rule_id| {
+                    validate_bounded_string(rule_id, limits.max_command_id_len)
+                        && is_payload_token(rule_id)
+                        && claimed_rules.insert(rule_id)
+                })
+                && is_sorted(&profile.rule_ids)
+        })
+    }
+
+    /// Destinations must be sorted, unique, absolute and free of `.`/`..` components so
+    /// prefix checks on them cannot be sidestepped.
+    fn file_destinations_valid(&self) -> bool {
+        let limits = ValidationLimits::default_limits();
+        let mut unique_destinations = HashSet::new();
+        self.execution.file_destinations.iter().all(|destination| {
+            let path = Path::new(destination);
+            validate_bounded_string(destination, limits.max_payload_len)
+                && is_payload_path(destination)
+                && path.is_absolute()
+                && path
+                    .components()
+                    .all(|component| !matches!(component, Component::CurDir | Component::ParentDir))
+                && unique_destinations.insert(destination)
+        }) && is_sorted(&self.execution.file_destinations)
+    }
+
+    pub fn argument_rules_for(&self, action: &str) -> ArgumentRules {
+        self.execution
+            .argument_rules
+            .get(action)
+            .cloned()
+            .unwrap_or_default()
+    }
+
     fn signing_payload(&self) -> String {
         let mut payload = String::new();
         payload.push_str("schema_version=");
@@ -194,87 +521,97 @@ impl PolicyBundle {
         payload.push_str(&self.execution.max_argument_length.to_string());
         payload.push_str("|telemetry_streams=");
         payload.push_str(&self.telemetry_streams.join(","));
+        if !self.execution.argument_rules.is_empty() {
+            payload.push_str("|argument_rules=");
+            payload.push_str(&self.argument_rules_payload());
+        }
+        if !self.stream_categories.is_empty() {
+            payload.push_str("|stream_categories=");
+            payload.push_str(&self.stream_categories_payload());
+        }
+        if !self.execution.file_destinations.is_empty() {
+            payload.push_str("|file_destinations=");
+            payload.push_str(&self.execution.file_destinations.join(","));
+        }
+        if !self.evidence_profiles.is_empty() {
+            payload.push_str("|evidence_profiles=");
+            payload.push_str(&self.evidence_profiles_payload());
+        }
         payload
     }
 
+    /// `name:rules=a,b,min_severity=N,max_item=N,max_total=N,cooldown=N,paths=p,q` entries
+    /// joined by `;` in name order. `target=1,` goes before `paths` only when `include_target` is
+    /// set, so existing signatures stay valid.
+    fn evidence_profiles_payload(&self) -> String {
+        self.evidence_profiles
+            .iter()
+            .map(|(name, profile)| {
+                format!(
+                    "{}:rules={},min_severity={},max_item={},max_total={},cooldown={},{}paths={}",
+                    name,
+                    profile.rule_ids.join(","),
+                    profile.min_severity,
+                    profile.max_item_bytes,
+                    profile.max_total_bytes,
+                    profile.cooldown_secs,
+                    if profile.include_target { "target=1," } else { "" },
+                    profile.paths.join(",")
+                )
+            })
+            .collect::<Vec<String>>()
+            .join(";")
+    }
+
+    /// `stream:category,category` entries joined by `;` in stream order.
+    fn stream_categories_payload(&self) -> String {
+        self.stream_categories
+            .iter()
+            .map(|(stream, categories)| format!("{}:{}", stream, categories.join(",")))
+            .collect::<Vec<String>>()
+            .join(";")
+    }
+
+    /// `action:shell=<0|1>,env=<0|1>,traversal=<0|1>[,sensitive=1][,disruptive=1]` entries
+    /// joined by `;` in action order.
+    fn argument_rules_payload(&self) -> String {
+        self.execution
+            .argument_rules
+            .iter()
+            .map(|(action, rules)| {
+                let mut entry = format!(
+                    "{}:shell={},env={},traversal={}",
+                    action,
+                    rules.allow_shell_metacharacters as u8,
+                    rules.allow_env_expansion as u8,
+                    rules.allow_path_traversal as u8
+                );
+                if rules.sensitive {
+                    entry.push_str(",sensitive=1");
+                }
+                if rules.disruptive {
+                    entry.push_str(",disruptive=1");
+                }
+                entry
+            })
+            .collect::<Vec<String>>()
+            .join(";")
+    }
+
     fn verify_signature(&self, signing_key: &str) -> bool {
-        let payload = self.signing_payload();
-        let mut mac = match Hmac::<Sha256>::new_from_slice(signing_key.as_bytes()) {
-            Ok(value) => value,
-            Err(_) => return false,
-        };
-        mac.update(payload.as_bytes());
-        let signature_bytes = mac.finalize().into_bytes();
-        let expected = BASE64_STANDARD.encode(signature_bytes);
-        constant_time_eq(self.signature.as_bytes(), expected.as_bytes())
+        HmacSha256::new(signing_key.as_bytes()).verify(self.signing_payload().as_bytes(), &self.signature)
     }
 
     pub fn sign_with_key(&mut self, signing_key: &str) -> bool {
         if !self.validate_for_signing() {
             return false;
         }
-        let payload = self.signing_payload();
-        let mut mac = match Hmac::<Sha256>::new_from_slice(signing_key.as_bytes()) {
-            Ok(value) => value,
-            Err(_) => return false,
-        };
-        mac.update(payload.as_bytes());
-        let signature_bytes = mac.finalize().into_bytes();
-        self.signature = BASE64_STANDARD.encode(signature_bytes);
+        self.signature = HmacSha256::new(signing_key.as_bytes()).sign(self.signing_payload().as_bytes());
         true
     }
 
     fn validate_for_signing(&self) -> bool {
-        let limits = ValidationLimits::default_limits();
-        if self.schema_version == 0 {
-            return false;
-        }
-        if !validate_bounded_string(&self.version, 64) {
-            return false;
-        }
-        if !validate_bounded_string(&self.signing_key_id, 128) {
-            return false;
-        }
-        if self.issued_at_unix_time_ms > self.expires_at_unix_time_ms {
-            return false;
-        }
-        if self.execution.allowed_actions.is_empty()
-            || self.execution.max_arguments == 0
-            || self.execution.max_argument_length == 0
-        {
-            return false;
-        }
-        let mut unique_actions = HashSet::new();
-        for action in &self.execution.allowed_actions {
-            if !validate_bounded_string(action, limits.max_command_id_len) {
-                return false;
-            }
-            if !is_valid_action_name(action) {
-                return false;
-            }
-            if !unique_actions.insert(action) {
-                return false;
-            }
-        }
-        if !is_sorted(&self.execution.allowed_actions) {
-            return false;
-        }
-        if self.telemetry_streams.is_empty() {
-            return false;
-        }
-        let mut unique_streams = HashSet::new();
-        for stream in &self.telemetry_streams {
-            if !validate_bounded_string(stream, limits.max_stream_len) {
-                return false;
-            }
-            if !unique_streams.insert(stream) {
-                return false;
-            }
-        }
-        if !is_sorted(&self.telemetry_streams) {
-            return false;
-        }
-        true
+        self.check_contents().is_ok()
     }
 }
 
@@ -284,15 +621,15 @@ fn is_valid_action_name(action: &str) -> bool {
         .all(|ch| ch.is_ascii_lowercase() || ch == '-' || ch == '_')
 }
 
-fn constant_time_eq(left: &[u8], right: &[u8]) -> bool {
-    if left.len() != right.len() {
-        return false;
-    }
-    let mut diff = 0u8;
-    for (lhs, rhs) in left.iter().zip(right.iter()) {
-        diff |= lhs ^ rhs;
-    }
-    diff == 0
+/// Names joined into the signing payload must not contain its separators, otherwise a
+/// signature over one policy would also verify a re-split one.
+fn is_payload_token(value: &str) -> bool {
+    !value.contains(['|', ',', ';', ':', '='])
+}
+
+/// Paths keep `:` for drive letters; they are always the last field of their segment.
+fn is_payload_path(value: &str) -> bool {
+    !value.contains(['|', ',', ';'])
 }
 
 fn is_sorted(values: &[String]) -> bool {
@@ -301,7 +638,13 @@ fn is_sorted(values: &[String]) -> bool {
 
 #[cfg(test)]
 mod tests {
-    use super::{PolicyBundle, PolicyValidationOptions};
+    use base64::engine::general_purpose::STANDARD as BASE64_STANDARD;
+    use base64::Engine as _;
+    use hmac::{Hmac, Mac};
+    use sha2::Sha256;
+
+    use super::{PolicyBundle, PolicySchemaError, PolicyValidationOptions, CURRENT_POLICY_SCHEMA_VERSION, MAX_POLICY_BYTES};
+    use crate::crypto::{HmacSha256, Signer, Verifier};
 
     fn build_valid_policy() -> PolicyBundle {
         PolicyBundle {
@@ -315,8 +658,12 @@ mod tests {
                 allowed_actions: vec!["patch-apply".to_string(), "script-run".to_string()],
                 max_arguments: 4,
                 max_argument_length: 64,
+                argument_rules: std::collections::BTreeMap::new(),
+                file_destinations: Vec::new(),
             },
             telemetry_streams: vec!["agent".to_string(), "sensor".to_string()],
+            stream_categories: std::collections::BTreeMap::new(),
+            evidence_profiles: std::collections::BTreeMap::new(),
         }
     }
 
@@ -327,6 +674,8 @@ mod tests {
             signing_key: None,
             expected_key_id: None,
             allow_unsigned: true,
+            clock_skew_tolerance_ms: 0,
+            not_before_widening_ms: 0,
         };
         assert!(policy.validate(1, &options));
     }
@@ -338,6 +687,8 @@ mod tests {
             signing_key: None,
             expected_key_id: None,
             allow_unsigned: false,
+            clock_skew_tolerance_ms: 0,
+            not_before_widening_ms: 0,
         };
         assert!(!policy.validate(1, &options));
     }
@@ -350,6 +701,8 @@ mod tests {
             signing_key: None,
             expected_key_id: None,
             allow_unsigned: true,
+            clock_skew_tolerance_ms: 0,
+            not_before_widening_ms: 0,
         };
         assert!(!policy.validate(1, &options));
     }
@@ -363,6 +716,8 @@ mod tests {
             signing_key: Some(signing_key.to_string()),
             expected_key_id: None,
             allow_unsigned: false,
+            clock_skew_tolerance_ms: 0,
+            not_before_widening_ms: 0,
         };
         assert!(policy.validate(1, &options));
     }
@@ -374,6 +729,20 @@ mod tests {
         assert!(!policy.sign_with_key("unit-test-key"));
     }
 
+    #[test]
+    fn signature_matches_shared_hmac_backend() {
+        let mut policy = build_valid_policy();
+        assert!(policy.sign_with_key("unit-test-key"));
+        let payload = policy.signing_payload();
+
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"unit-test-key").expect("hmac key");
+        mac.update(payload.as_bytes());
+        assert_eq!(policy.signature, BASE64_STANDARD.encode(mac.finalize().into_bytes()));
+        let backend = HmacSha256::new(b"unit-test-key");
+        assert_eq!(policy.signature, backend.sign(payload.as_bytes()));
+        assert!(backend.verify(payload.as_bytes(), &policy.signature));
+    }
+
     #[test]
     fn rejects_when_signature_mismatch() {
         let mut policy = build_valid_policy();
@@ -382,7 +751,255 @@ mod tests {
             signing_key: Some("other-key".to_string()),
             expected_key_id: None,
             allow_unsigned: false,
+            clock_skew_tolerance_ms: 0,
+            not_before_widening_ms: 0,
         };
         assert!(!policy.validate(1, &options));
     }
+
+    #[test]
+    fn signs_and_validates_file_destinations() {
+        let signing_key = "unit-test-key";
+        let options = PolicyValidationOptions {
+            signing_key: Some(signing_key.to_string()),
+            expected_key_id: None,
+            allow_unsigned: false,
+            clock_skew_tolerance_ms: 0,
+            not_before_widening_ms: 0,
+        };
+        let mut policy = build_valid_policy();
+        policy.execution.file_destinations = vec!["/etc/tamsil".to_string(), "/opt/tamsil/conf".to_string()];
+        assert!(policy.sign_with_key(signing_key));
+        assert!(policy.validate(1, &options));
+
+        let mut widened = policy.clone();
+        widened.execution.file_destinations.push("/usr".to_string());
+        assert!(!widened.validate(1, &options));
+
+        for invalid in [
+            vec!["/opt/tamsil/conf".to_string(), "/etc/tamsil".to_string()],
+            vec!["etc/tamsil".to_string()],
+            vec!["/etc/tamsil/../shadow".to_string()],
+        ] {
+            let mut policy = build_valid_policy();
+            policy.execution.file_destinations = invalid;
+            assert!(!policy.sign_with_key(signing_key));
+        }
+    }
+
+    #[test]
+    fn rejects_names_that_would_re_split_the_signing_payload() {
+        let options = PolicyValidationOptions {
+            signing_key: None,
+            expected_key_id: None,
+            allow_unsigned: true,
+            clock_skew_tolerance_ms: 0,
+            not_before_widening_ms: 0,
+        };
+        let mut joined = build_valid_policy();
+        joined.telemetry_streams = vec!["agent".to_string(), "sensor:process".to_string()];
+        assert!(!joined.validate(1, &options));
+        assert!(!joined.sign_with_key("unit-test-key"));
+
+        let mut categories = build_valid_policy();
+        categories
+            .stream_categories
+            .insert("sensor".to_string(), vec!["process;agent:file".to_string()]);
+        assert!(!categories.validate(1, &options));
+
+        let mut destinations = build_valid_policy();
+        destinations.execution.file_destinations = vec!["/etc/tamsil,/usr".to_string()];
+        assert!(!destinations.validate(1, &options));
+
+        let mut version = build_valid_policy();
+        version.version = "policy-1|telemetry_streams=agent".to_string();
+        assert!(!version.validate(1, &options));
+    }
+
+    #[test]
+    fn signs_and_validates_stream_categories() {
+        let signing_key = "unit-test-key";
+        let options = PolicyValidationOptions {
+            signing_key: Some(signing_key.to_string()),
+            expected_key_id: None,
+            allow_unsigned: false,
+            clock_skew_tolerance_ms: 0,
+            not_before_widening_ms: 0,
+        };
+        let mut policy = build_valid_policy();
+        policy.stream_categories.insert(
+            "sensor".to_string(),
+            vec!["file".to_string(), "network".to_string(), "process".to_string()],
+        );
+        assert!(policy.sign_with_key(signing_key));
+        assert!(policy.validate(1, &options));
+        assert!(policy.allows_category("sensor", Some("network")));
+        assert!(!policy.allows_category("sensor", Some("registry")));
+        assert!(!policy.allows_category("sensor", None));
+        assert!(policy.allows_category("agent", Some("anything")));
+
+        let mut widened = policy.clone();
+        widened
+            .stream_categories
+            .insert("sensor".to_string(), vec!["file".to_string(), "registry".to_string()]);
+        assert!(!widened.validate(1, &options));
+
+        let mut unknown_stream = build_valid_policy();
+        unknown_stream
+            .stream_categories
+            .insert("audit".to_string(), vec!["login".to_string()]);
+        assert!(!unknown_stream.sign_with_key(signing_key));
+
+        let mut unsorted = build_valid_policy();
+        unsorted
+            .stream_categories
+            .insert("sensor".to_string(), vec!["process".to_string(), "file".to_string()]);
+        assert!(!unsorted.sign_with_key(signing_key));
+    }
+
+    #[test]
+    fn signs_evidence_profiles_and_rejects_tampering() {
+        let signing_key = "unit-test-key";
+        let options = PolicyValidationOptions {
+            signing_key: Some(signing_key.to_string()),
+            expected_key_id: None,
+            allow_unsigned: false,
+            clock_skew_tolerance_ms: 0,
+            not_before_widening_ms: 0,
+        };
+        let mut policy: PolicyBundle = serde_json::from_str(
+            r#"{"schema_version":2,"version":"policy-1","issued_at_unix_time_ms":0,"expires_at_unix_time_ms":1000,"signing_key_id":"key-1","signature":"","execution":{"allowed_actions":["script-run"],"max_arguments":4,"max_argument_length":64},"telemetry_streams":["sensor"],"evidence_profiles":{"credential-theft":{"rule_ids":["lsass-access"],"paths":["auth.log","security.evtx"],"max_item_bytes":4096,"max_total_bytes":8192}}}"#,
+        )
+        .expect("policy with evidence profiles");
+        let profile = &policy.evidence_profiles["credential-theft"];
+        assert_eq!((profile.min_severity, profile.cooldown_secs), (8, 900));
+        assert!(policy.sign_with_key(signing_key));
+        assert!(policy.validate(1, &options));
+
+        let mut widened = policy.clone();
+        if let Some(profile) = widened.evidence_profiles.get_mut("credential-theft") {
+            profile.paths.push("shadow".to_string());
+        }
+        assert!(!widened.validate(1, &options));
+
+        let mut targeted = policy.clone();
+        if let Some(profile) = targeted.evidence_profiles.get_mut("credential-theft") {
+            profile.include_target = true;
+        }
+        assert!(!targeted.validate(1, &options));
+
+        let mut unsorted = policy.clone();
+        if let Some(profile) = unsorted.evidence_profiles.get_mut("credential-theft") {
+            profile.paths.reverse();
+        }
+        assert!(!unsorted.sign_with_key(signing_key));
+    }
+
+    #[test]
+    fn loads_gzipped_policy_file() {
+        use std::io::Write;
+
+        let policy = build_valid_policy();
+        let json = format!(
+            r#"{{"schema_version":1,"version":"{}","issued_at_unix_time_ms":0,"expires_at_unix_time_ms":{},"signing_key_id":"{}","signature":"{}","execution":{{"allowed_actions":["patch-apply","script-run"],"max_arguments":4,"max_argument_length":64}},"telemetry_streams":["agent","sensor"]}}"#,
+            policy.version,
+            u64::MAX,
+            policy.signing_key_id,
+            policy.signature
+        );
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(json.as_bytes()).expect("compress policy");
+        let path = std::env::temp_dir().join(format!("policy-{}.json.gz", std::process::id()));
+        std::fs::write(&path, encoder.finish().expect("finish gzip")).expect("write policy");
+
+        let loaded = PolicyBundle::from_path(&path).expect("gzipped policy loads");
+        assert_eq!(loaded.version, "policy-1");
+        assert_eq!(loaded.execution.allowed_actions, policy.execution.allowed_actions);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn rejects_oversized_policy_file_and_inline_json() {
+        let path = std::env::temp_dir().join(format!("policy-oversized-{}.json", std::process::id()));
+        let padded = format!("{{\"version\":\"{}\"}}", "a".repeat(MAX_POLICY_BYTES as usize));
+        std::fs::write(&path, &padded).expect("write policy");
+
+        assert!(PolicyBundle::from_path(&path).is_none());
+        assert!(PolicyBundle::from_json(&padded).is_none());
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn migrates_v1_bundle_to_current_schema() {
+        let mut policy = build_valid_policy();
+        assert!(policy.sign_with_key("unit-test-key"));
+        let options = PolicyValidationOptions {
+            signing_key: Some("unit-test-key".to_string()),
+            expected_key_id: None,
+            allow_unsigned: false,
+            clock_skew_tolerance_ms: 0,
+            not_before_widening_ms: 0,
+        };
+        assert!(policy.validate(1, &options));
+
+        let migrated = policy.migrate().expect("v1 migrates");
+        assert_eq!(migrated.schema_version, CURRENT_POLICY_SCHEMA_VERSION);
+        assert!(migrated.execution.argument_rules.is_empty());
+        assert_eq!(migrated.execution.allowed_actions, policy.execution.allowed_actions);
+    }
+
+    #[test]
+    fn rejects_v1_bundle_carrying_v2_fields() {
+        let mut policy = build_valid_policy();
+        policy
+            .execution
+            .argument_rules
+            .insert("script-run".to_string(), super::ArgumentRules::default());
+        assert!(matches!(policy.migrate(), Err(PolicySchemaError::Invalid(_))));
+
+        policy.schema_version = 2;
+        assert!(policy.migrate().is_ok());
+    }
+
+    #[test]
+    fn rejects_schema_newer_than_agent() {
+        let mut policy = build_valid_policy();
+        policy.schema_version = 99;
+        assert_eq!(
+            policy.migrate().err(),
+            Some(PolicySchemaError::NewerThanAgent {
+                found: 99,
+                supported: CURRENT_POLICY_SCHEMA_VERSION,
+            })
+        );
+        let options = PolicyValidationOptions {
+            signing_key: None,
+            expected_key_id: None,
+            allow_unsigned: true,
+            clock_skew_tolerance_ms: 0,
+            not_before_widening_ms: 0,
+        };
+        assert!(!policy.validate(1, &options));
+
+        policy.schema_version = 0;
+        assert_eq!(policy.migrate().err(), Some(PolicySchemaError::Unsupported { found: 0 }));
+    }
+
+    #[test]
+    fn tolerates_clock_skew_around_policy_window() {
+        let mut policy = build_valid_policy();
+        policy.issued_at_unix_time_ms = 1_000_000;
+        policy.expires_at_unix_time_ms = 2_000_000;
+        let options = PolicyValidationOptions {
+            signing_key: None,
+            expected_key_id: None,
+            allow_unsigned: true,
+            clock_skew_tolerance_ms: 5_000,
+            not_before_widening_ms: 0,
+        };
+        assert!(policy.validate(999_000, &options));
+        assert!(policy.validate(2_004_000, &options));
+        assert!(!policy.validate(940_000, &options));
+        assert!(!policy.validate(2_060_000, &options));
+    }
 }
@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::time::{Duration, Instant};
 
 #[derive(Debug)]
@@ -25,6 +26,12 @@ impl RateLimiter {
         true
     }
 
+    /// Restore the invariants after a holder panicked mid-update. The worst outcome is one
+    /// window with a full budget, never an unbounded one.
+    pub fn repair(&mut self) {
+        self.tokens = self.tokens.min(self.max_per_minute);
+    }
+
     fn refill(&mut self) {
         let elapsed = self.last_refill.elapsed();
         if elapsed < Duration::from_secs(60) {
@@ -35,3 +42,141 @@ impl RateLimiter {
         self.last_refill = Instant::now();
     }
 }
+
+/// Fixed-window limiter tracking usage per key, where each key has its own budget of units
+/// per window. Time is supplied by the caller in milliseconds so windows are testable.
+#[derive(Debug)]
+pub struct KeyedRateLimiter {
+    window_ms: u64,
+    windows: HashMap<String, KeyWindow>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct KeyWindow {
+    started_at_ms: u64,
+    used: u64,
+}
+
+impl KeyedRateLimiter {
+    pub fn new(window_ms: u64) -> Self {
+        Self {
+            window_ms,
+            windows: HashMap::new(),
+        }
+    }
+
+    /// Check whether `units` fit in the key's remaining budget without consuming them.
+    /// On failure returns the milliseconds until the current window resets.
+    pub fn check(&mut self, key: &str, units: u64, limit: u64, now_ms: u64) -> Result<(), u64> {
+        let window_ms = self.window_ms;
+        let window = *self.window_mut(key, now_ms);
+        if window.used.saturating_add(units) > limit {
+            let resets_at = window.started_at_ms.saturating_add(window_ms);
+            return Err(resets_at.saturating_sub(now_ms).max(1));
+        }
+        Ok(())
+    }
+
+    pub fn consume(&mut self, key: &str, units: u64, now_ms: u64) {
+        let window = self.window_mut(key, now_ms);
+        window.used = window.used.saturating_add(units);
+    }
+
+    pub fn try_acquire(&mut self, key: &str, units: u64, limit: u64, now_ms: u64) -> Result<(), u64> {
+        self.check(key, units, limit, now_ms)?;
+        self.consume(key, units, now_ms);
+        Ok(())
+    }
+
+    fn window_mut(&mut self, key: &str, now_ms: u64) -> &mut KeyWindow {
+        let window_ms = self.window_ms;
+        let window = self.windows.entry(key.to_string()).or_insert(KeyWindow {
+            started_at_ms: now_ms,
+            used: 0,
+        });
+        if now_ms.saturating_sub(window.started_at_ms) >= window_ms {
+            *window = KeyWindow {
+                started_at_ms: now_ms,
+                used: 0,
+            };
+        }
+        window
+    }
+}
+
+/// Token bucket metering bytes per second. Refills continuously up to `burst_bytes`; a
+/// request larger than the burst waits for a full bucket and then leaves it in debt, so
+/// the long-run rate still holds. Time is supplied by the caller in milliseconds.
+#[derive(Debug)]
+pub struct ByteTokenBucket {
+    bytes_per_sec: u64,
+    burst_bytes: u64,
+    tokens: f64,
+    updated_at_ms: u64,
+}
+
+impl ByteTokenBucket {
+    pub fn new(bytes_per_sec: u64, burst_bytes: u64, now_ms: u64) -> Self {
+        let burst_bytes = burst_bytes.max(1);
+        Self {
+            bytes_per_sec: bytes_per_sec.max(1),
+            burst_bytes,
+            tokens: burst_bytes as f64,
+            updated_at_ms: now_ms,
+        }
+    }
+
+    /// Reserve `bytes` and return how many milliseconds the caller must wait before
+    /// sending. Reservations queue behind each other, so concurrent callers share the rate.
+    pub fn reserve(&mut self, bytes: u64, now_ms: u64) -> u64 {
+        if now_ms > self.updated_at_ms {
+            let refill = (now_ms - self.updated_at_ms) as f64 * self.bytes_per_sec as f64 / 1000.0;
+            self.tokens = (self.tokens + refill).min(self.burst_bytes as f64);
+            self.updated_at_ms = now_ms;
+        }
+        let needed = bytes.min(self.burst_bytes) as f64;
+        let deficit = needed - self.tokens;
+        self.tokens -= bytes as f64;
+        if deficit <= 0.0 {
+            0
+        } else {
+            (deficit * 1000.0 / self.bytes_per_sec as f64).ceil() as u64
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ByteTokenBucket;
+
+    #[test]
+    fn meters_bytes_at_configured_rate() {
+        let mut now_ms = 0;
+        let mut waited_ms = 0;
+        let mut bucket = ByteTokenBucket::new(100_000, 100_000, now_ms);
+
+        // 1 MB in 10 KB posts: the first 100 KB ride the initial burst, the rest waits.
+        for _ in 0..100 {
+            let wait = bucket.reserve(10_000, now_ms);
+            waited_ms += wait;
+            now_ms += wait;
+        }
+        assert!((8_900..=10_000).contains(&waited_ms), "waited {} ms", waited_ms);
+
+        // Reservations made at the same instant queue behind each other.
+        let mut shared = ByteTokenBucket::new(100_000, 100_000, 0);
+        assert_eq!(shared.reserve(100_000, 0), 0);
+        assert_eq!(shared.reserve(50_000, 0), 500);
+        assert_eq!(shared.reserve(50_000, 0), 1_000);
+    }
+
+    #[test]
+    fn oversized_body_waits_for_full_burst_then_leaves_debt() {
+        let mut bucket = ByteTokenBucket::new(100_000, 100_000, 0);
+        assert_eq!(bucket.reserve(60_000, 0), 0);
+        // 1 MB waits only until the bucket is full again, 0.6 s later.
+        assert_eq!(bucket.reserve(1_000_000, 0), 600);
+        // The next request pays off the remaining 900 KB first.
+        assert_eq!(bucket.reserve(1, 600), 9_001);
+    }
+}
@@ -0,0 +1,285 @@
+use std::collections::HashSet;
+use std::env;
+
+use sha2::{Digest, Sha256};
+
+pub const MASK: &str = "***";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RedactionMode {
+    /// Replace redacted values with `***`.
+    Mask,
+    /// Replace redacted values with a short SHA-256 so equal values still correlate.
+    Hash,
+}
+
+/// PII redaction applied to telemetry before it is batched: values of listed field keys
+/// are replaced outright, and emails, card-like numbers and bearer tokens are masked
+/// wherever they appear in messages and field values.
+#[derive(Debug, Clone)]
+pub struct RedactionConfig {
+    pub enabled: bool,
+    pub keys: HashSet<String>,
+    pub mode: RedactionMode,
+}
+
+impl RedactionConfig {
+    pub fn from_env() -> Self {
+        let enabled = env::var("TELEMETRY_REDACT")
+            .ok()
+            .map(|value| !value.eq_ignore_ascii_case("false"))
+            .unwrap_or(true);
+        let keys = env::var("TELEMETRY_REDACT_KEYS")
+            .ok()
+            .map(|value| parse_keys(&value))
+            .unwrap_or_default();
+        let mode = env::var("TELEMETRY_REDACT_MODE")
+            .ok()
+            .map(|value| {
+                if value.trim().eq_ignore_ascii_case("hash") {
+                    RedactionMode::Hash
+                } else {
+                    RedactionMode::Mask
+                }
+            })
+            .unwrap_or(RedactionMode::Mask);
+        Self { enabled, keys, mode }
+    }
+
+    pub fn disabled() -> Self {
+        Self {
+            enabled: false,
+            keys: HashSet::new(),
+            mode: RedactionMode::Mask,
+        }
+    }
+
+    pub fn redact_field(&self, key: &str, value: &str) -> String {
+        if !self.enabled {
+            return value.to_string();
+        }
+        if self.keys.contains(&key.to_ascii_lowercase()) {
+            return match self.mode {
+                RedactionMode::Mask => MASK.to_string(),
+                RedactionMode::Hash => hash_value(value),
+            };
+        }
+        mask_sensitive(value)
+    }
+
+    pub fn redact_text(&self, value: &str) -> String {
+        if self.enabled {
+            mask_sensitive(value)
+        } else {
+            value.to_string()
+        }
+    }
+}
+
+/// Parse a comma-separated key list; keys are matched case-insensitively.
+fn parse_keys(raw: &str) -> HashSet<String> {
+    raw.split(',')
+        .map(|key| key.trim().to_ascii_lowercase())
+        .filter(|key| !key.is_empty())
+        .collect()
+}
+
+fn hash_value(value: &str) -> String {
+    let digest = Sha256::digest(value.as_bytes());
+    let prefix = digest
+        .iter()
+        .take(8)
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
+    format!("sha256:{}", prefix)
+}
+
+pub fn mask_sensitive(value: &str) -> String {
+    let masked = mask_bearer_tokens(value);
+    let masked = mask_card_numbers(&masked);
+    mask_emails(&masked)
+}
+
+fn mask_bearer_tokens(value: &str) -> String {
+    let lower = value.to_ascii_lowercase();
+    let mut output = String::with_capacity(value.len());
+    let mut cursor = 0;
+    while let Some(offset) = lower[cursor..].find("bearer ") {
+        let token_start = cursor + offset + "bearer ".len();
+        let token_start = token_start + value[token_start..].len() - value[token_start..].trim_start().len();
+        let token_end = value[token_start..]
+            .find(|ch: char| ch.is_whitespace() || ch == '"' || ch == '\'' || ch == ',')
+            .map(|end| token_start + end)
+            .unwrap_or(value.len());
+        output.push_str(&value[cursor..token_start]);
+        if token_end > token_start {
+            output.push_str(MASK);
+        }
+        cursor = token_end;
+    }
+    output.push_str(&value[cursor..]);
+    output
+}
+
+/// Mask runs of 13-19 digits (optionally separated by spaces or dashes) that pass the Luhn
+/// check, which keeps ordinary ids and timestamps intact.
+fn mask_card_numbers(value: &str) -> String {
+    let chars = value.char_indices().collect::<Vec<(usize, char)>>();
+    let mut output = String::with_capacity(value.len());
+    let mut cursor = 0;
+    let mut index = 0;
+    while index < chars.len() {
+        let (start, ch) = chars[index];
+        let preceded_by_digit = index > 0 && chars[index - 1].1.is_ascii_digit();
+        if !ch.is_ascii_digit() || preceded_by_digit {
+            index += 1;
+            continue;
+        }
+        let mut digits = Vec::new();
+        let mut end_index = index;
+        let mut scan = index;
+        while scan < chars.len() && (chars[scan].1.is_ascii_digit() || chars[scan].1 == ' ' || chars[scan].1 == '-') {
+            if chars[scan].1.is_ascii_digit() {
+                digits.push(chars[scan].1 as u8 - b'0');
+                end_index = scan;
+            }
+            scan += 1;
+        }
+        if (13..=19).contains(&digits.len()) && luhn_valid(&digits) {
+            let end = chars[end_index].0 + 1;
+            output.push_str(&value[cursor..start]);
+            output.push_str(MASK);
+            cursor = end;
+        }
+        index = end_index + 1;
+    }
+    output.push_str(&value[cursor..]);
+    output
+}
+
+fn luhn_valid(digits: &[u8]) -> bool {
+    let sum = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(position, digit)| {
+            let digit = u32::from(*digit);
+            if position % 2 == 1 {
+                let doubled = digit * 2;
+                if doubled > 9 {
+                    doubled - 9
+                } else {
+                    doubled
+                }
+            } else {
+                digit
+            }
+        })
+        .sum::<u32>();
+    sum % 10 == 0
+}
+
+fn mask_emails(value: &str) -> String {
+    let mut output = String::with_capacity(value.len());
+    let mut word_start = 0;
+    for (index, ch) in value.char_indices() {
+        if is_word_delimiter(ch) {
+            push_word(&mut output, &value[word_start..index]);
+            output.push(ch);
+            word_start = index + ch.len_utf8();
+        }
+    }
+    push_word(&mut output, &value[word_start..]);
+    output
+}
+
+fn is_word_delimiter(ch: char) -> bool {
+    ch.is_whitespace() || matches!(ch, '"' | '\'' | '<' | '>' | '(' | ')' | '[' | ']' | ',' | ';' | '=' | ':')
+}
+
+fn push_word(output: &mut String, word: &str) {
+    let trimmed = word.trim_end_matches('.');
+    if is_email(trimmed) {
+        output.push_str(MASK);
+        output.push_str(&word[trimmed.len()..]);
+    } else {
+        output.push_str(word);
+    }
+}
+
+fn is_email(word: &str) -> bool {
+    let (local, domain) = match word.split_once('@') {
+        Some(parts) => parts,
+        None => return false,
+    };
+    if local.is_empty() || domain.contains('@') {
+        return false;
+    }
+    if !local
+        .chars()
+        .all(|ch| ch.is_ascii_alphanumeric() || matches!(ch, '.' | '_' | '%' | '+' | '-'))
+    {
+        return false;
+    }
+    let labels = domain.split('.').collect::<Vec<&str>>();
+    if labels.len() < 2 {
+        return false;
+    }
+    let tld = labels[labels.len() - 1];
+    labels
+        .iter()
+        .all(|label| !label.is_empty() && label.chars().all(|ch| ch.is_ascii_alphanumeric() || ch == '-'))
+        && tld.len() >= 2
+        && tld.chars().all(|ch| ch.is_ascii_alphabetic())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{mask_sensitive, parse_keys, RedactionConfig, RedactionMode};
+
+    fn build_config(keys: &str, mode: RedactionMode) -> RedactionConfig {
+        RedactionConfig {
+            enabled: true,
+            keys: parse_keys(keys),
+            mode,
+        }
+    }
+
+    #[test]
+    fn masks_values_of_listed_keys() {
+        let config = build_config("password, User.Name", RedactionMode::Mask);
+        assert_eq!(config.redact_field("password", "hunter2"), "***");
+        assert_eq!(config.redact_field("user.name", "alice"), "***");
+
+        let hashed = build_config("user.name", RedactionMode::Hash);
+        let first = hashed.redact_field("USER.NAME", "alice");
+        assert!(first.starts_with("sha256:"));
+        assert_eq!(first, hashed.redact_field("user.name", "alice"));
+        assert_ne!(first, hashed.redact_field("user.name", "bob"));
+    }
+
+    #[test]
+    fn masks_sensitive_patterns_in_values() {
+        assert_eq!(mask_sensitive("login by alice@example.com."), "login by ***.");
+        assert_eq!(mask_sensitive("to=<bob.smith+x@corp.example.org>"), "to=<***>");
+        assert_eq!(mask_sensitive("card 4111 1111 1111 1111 declined"), "card *** declined");
+        assert_eq!(mask_sensitive("card=4111-1111-1111-1111"), "card=***");
+        assert_eq!(mask_sensitive("Authorization: Bearer eyJhbGciOi.abc.def next"), "Authorization: Bearer *** next");
+    }
+
+    #[test]
+    fn leaves_unrelated_values_untouched() {
+        let config = build_config("password", RedactionMode::Mask);
+        for value in [
+            "C:\\Windows\\System32\\cmd.exe",
+            "pid 4242 started at 1700000000000",
+            "1234567890123",
+            "user@localhost",
+            "retry @ 10:42 by svc",
+        ] {
+            assert_eq!(config.redact_field("image", value), value);
+        }
+        assert_eq!(RedactionConfig::disabled().redact_field("password", "hunter2"), "hunter2");
+        assert_eq!(RedactionConfig::disabled().redact_text("alice@example.com"), "alice@example.com");
+    }
+}
@@ -1,25 +1,75 @@
+use std::collections::HashMap;
 use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use crate::policy::PolicyBundle;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Notify, OwnedSemaphorePermit, Semaphore};
+use tracing::{info, warn};
+
+use crate::audit::{self, AuditEvent};
+use crate::command_router::{route_command_explain, route_command_explain_with_config, CommandDecision, CommandRouteConfig, SignedCommand};
+use crate::compression::read_file_bounded;
+use crate::crypto::{HmacSha256, Signer, Verifier};
+use crate::metrics::MetricsHandle;
+use crate::policy::{PolicyBundle, PolicyValidationOptions};
+use crate::redaction::MASK;
 use crate::security::{validate_bounded_string, ValidationLimits};
 use crate::time::unix_time_ms;
+use crate::uplink::enqueue_rmm_item;
 
-#[derive(Debug, Clone)]
+/// Upper bound on a single queued command file.
+const MAX_COMMAND_FILE_BYTES: u64 = 64 * 1024;
+const PROCESSING_DIR: &str = "processing";
+const REJECTED_DIR: &str = "rejected";
+const CANCEL_DIR: &str = "cancel";
+/// Path under `TAMSIL_RMM_BASE_ENDPOINT` that receives execution outcomes.
+const COMMAND_RESULTS_PATH: &str = "/command-results";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ExecutionRequest {
     pub command_id: String,
     pub signed_payload: String,
+    #[serde(default)]
+    pub signature: String,
     pub action: String,
     pub arguments: Vec<String>,
     pub requested_at_unix_ms: u64,
     pub expires_at_unix_ms: u64,
+    /// Optional start window set by the operator; the scheduler defers the command before
+    /// `earliest_start_unix_ms` and expires it after `latest_start_unix_ms`.
+    pub earliest_start_unix_ms: Option<u64>,
+    pub latest_start_unix_ms: Option<u64>,
     pub source: String,
 }
 
+impl ExecutionRequest {
+    /// The request as the router checks it; executors re-check it before running anything.
+    pub fn signed_command(&self) -> SignedCommand {
+        SignedCommand {
+            command_id: self.command_id.clone(),
+            signed_payload: self.signed_payload.clone(),
+            signature: self.signature.clone(),
+            action: self.action.clone(),
+            arguments: self.arguments.clone(),
+            not_before_unix_time_ms: self.requested_at_unix_ms,
+            not_after_unix_time_ms: self.expires_at_unix_ms,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct RmmConfig {
     pub max_payload_len: usize,
     pub max_command_id_len: usize,
     pub max_request_lifetime_ms: u64,
+    pub dry_run: bool,
+    pub max_output_bytes: usize,
+    /// How often `RMM_COMMAND_DIR` is checked for new commands.
+    pub poll_interval_secs: u64,
 }
 
 impl RmmConfig {
@@ -37,11 +87,27 @@ impl RmmConfig {
             .ok()
             .and_then(|value| value.parse::<u64>().ok())
             .unwrap_or(300_000);
+        let dry_run = env::var("RMM_DRY_RUN")
+            .ok()
+            .map(|value| value.eq_ignore_ascii_case("true"))
+            .unwrap_or(false);
+        let max_output_bytes = env::var("RMM_MAX_OUTPUT_BYTES")
+            .ok()
+            .and_then(|value| value.parse::<usize>().ok())
+            .unwrap_or(64 * 1024);
+        let poll_interval_secs = env::var("RMM_POLL_INTERVAL_SECS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .filter(|value| *value > 0)
+            .unwrap_or(10);
 
         Self {
             max_payload_len,
             max_command_id_len,
             max_request_lifetime_ms,
+            dry_run,
+            max_output_bytes,
+            poll_interval_secs,
         }
     }
 }
@@ -50,36 +116,35 @@ impl RmmConfig {
 struct RmmPendingCommand {
     command_id: String,
     signed_payload: String,
+    signature: String,
     action: String,
     arguments: Vec<String>,
     expires_at_unix_ms: Option<u64>,
+    earliest_start_unix_ms: Option<u64>,
+    latest_start_unix_ms: Option<u64>,
     source: String,
 }
 
-pub fn queue_execution_request(policy: &PolicyBundle) -> Option<ExecutionRequest> {
-    let config = RmmConfig::from_env();
+pub fn queue_execution_request(policy: &PolicyBundle, route: &CommandRouteConfig) -> Option<ExecutionRequest> {
     let pending = RmmPendingCommand::from_env()?;
-    let now = unix_time_ms();
+    pending_request(pending, policy, route, &RmmConfig::from_env(), unix_time_ms())
+}
 
+/// The env-supplied command as a request, once it passes the same router checks as queued
+/// commands (signature, action, arguments and their hazard scan, validity window).
+fn pending_request(
+    pending: RmmPendingCommand,
+    policy: &PolicyBundle,
+    route: &CommandRouteConfig,
+    config: &RmmConfig,
+    now: u64,
+) -> Option<ExecutionRequest> {
     if !validate_bounded_string(&pending.command_id, config.max_command_id_len) {
         return None;
     }
     if pending.signed_payload.len() > config.max_payload_len {
         return None;
     }
-    if !policy.allows_action(&pending.action) {
-        return None;
-    }
-    if pending.arguments.len() > policy.execution.max_arguments {
-        return None;
-    }
-    if pending
-        .arguments
-        .iter()
-        .any(|arg| !validate_bounded_string(arg, policy.execution.max_argument_length))
-    {
-        return None;
-    }
 
     let expires_at_unix_ms = pending
         .expires_at_unix_ms
@@ -88,21 +153,609 @@ pub fn queue_execution_request(policy: &PolicyBundle) -> Option<ExecutionRequest
         return None;
     }
 
-    Some(ExecutionRequest {
+    let request = ExecutionRequest {
         command_id: pending.command_id,
         signed_payload: pending.signed_payload,
+        signature: pending.signature,
         action: pending.action,
         arguments: pending.arguments,
         requested_at_unix_ms: now,
         expires_at_unix_ms,
+        earliest_start_unix_ms: pending.earliest_start_unix_ms,
+        latest_start_unix_ms: pending.latest_start_unix_ms,
         source: pending.source,
-    })
+    };
+    let decision = route_command_explain_with_config(&request.signed_command(), policy, route, now);
+    if !decision.allowed() {
+        warn!(
+            command_id = %request.command_id,
+            failed_checks = ?decision.failed_checks(),
+            "rejected rmm command from environment"
+        );
+        return None;
+    }
+    Some(request)
+}
+
+/// How an execution ended: on its own, or killed by the executor for overrunning its
+/// wall-clock timeout or output cap.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Termination {
+    #[default]
+    Exited,
+    TimedOut,
+    OutputLimit,
+    /// Stopped by a cancel request, before starting or while running.
+    Cancelled,
+    /// The agent stopped before the command finished, and it expired before it could resume.
+    Interrupted,
+    /// The executor refused the command or could not start it; `stderr` says why.
+    Rejected,
+    /// The scheduler could not start the command before its deadline.
+    Expired,
+}
+
+/// Result of running an `ExecutionRequest`, reported to the backend through the uplink.
+/// Output beyond the configured cap is cut at a character boundary and the number of bytes
+/// dropped is recorded next to it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExecutionOutcome {
+    pub command_id: String,
+    pub action: String,
+    pub arguments: Vec<String>,
+    pub exit_code: Option<i32>,
+    pub started_at_unix_ms: u64,
+    pub finished_at_unix_ms: u64,
+    pub stdout: String,
+    pub stdout_truncated_bytes: u64,
+    pub stderr: String,
+    pub stderr_truncated_bytes: u64,
+    #[serde(default)]
+    pub termination: Termination,
+    pub success: bool,
+}
+
+impl ExecutionOutcome {
+    pub fn new(
+        request: &ExecutionRequest,
+        exit_code: Option<i32>,
+        started_at_unix_ms: u64,
+        finished_at_unix_ms: u64,
+        stdout: &[u8],
+        stderr: &[u8],
+        max_output_bytes: usize,
+    ) -> Self {
+        let (stdout, stdout_truncated_bytes) = truncate_output(stdout, max_output_bytes);
+        let (stderr, stderr_truncated_bytes) = truncate_output(stderr, max_output_bytes);
+        Self {
+            command_id: request.command_id.clone(),
+            action: request.action.clone(),
+            arguments: request.arguments.clone(),
+            exit_code,
+            started_at_unix_ms,
+            finished_at_unix_ms,
+            stdout,
+            stdout_truncated_bytes,
+            stderr,
+            stderr_truncated_bytes,
+            termination: Termination::Exited,
+            success: exit_code == Some(0),
+        }
+    }
+
+    /// Outcome for a command cancelled before it ran.
+    pub fn cancelled(request: &ExecutionRequest, now_unix_ms: u64) -> Self {
+        let mut outcome = Self::new(request, None, now_unix_ms, now_unix_ms, b"", b"", 0);
+        outcome.termination = Termination::Cancelled;
+        outcome
+    }
+
+    /// Outcome for a command the command log found unfinished after a restart.
+    pub fn interrupted(request: &ExecutionRequest, now_unix_ms: u64) -> Self {
+        let mut outcome = Self::new(request, None, now_unix_ms, now_unix_ms, b"", b"", 0);
+        outcome.termination = Termination::Interrupted;
+        outcome
+    }
+
+    /// Outcome for an action whose executor returns a structured report rather than a process
+    /// exit; the report JSON goes to `stdout`.
+    pub fn reported(
+        request: &ExecutionRequest,
+        started_at_unix_ms: u64,
+        finished_at_unix_ms: u64,
+        report_json: &str,
+        success: bool,
+        max_output_bytes: usize,
+    ) -> Self {
+        let mut outcome = Self::new(
+            request,
+            None,
+            started_at_unix_ms,
+            finished_at_unix_ms,
+            report_json.as_bytes(),
+            b"",
+            max_output_bytes,
+        );
+        outcome.success = success;
+        outcome
+    }
+
+    /// Outcome for a command its executor refused or could not start.
+    pub fn rejected(request: &ExecutionRequest, reason: &str, now_unix_ms: u64) -> Self {
+        let mut outcome = Self::new(request, None, now_unix_ms, now_unix_ms, b"", reason.as_bytes(), reason.len());
+        outcome.termination = Termination::Rejected;
+        outcome
+    }
+
+    /// Outcome for a command the scheduler could not start in time.
+    pub fn expired(request: &ExecutionRequest, reason: &str, now_unix_ms: u64) -> Self {
+        let mut outcome = Self::rejected(request, reason, now_unix_ms);
+        outcome.termination = Termination::Expired;
+        outcome
+    }
+
+    /// Copy suitable for reporting: arguments are masked when the policy marks the action
+    /// sensitive.
+    pub fn redacted(&self, policy: &PolicyBundle) -> Self {
+        let mut outcome = self.clone();
+        if policy.argument_rules_for(&self.action).sensitive {
+            outcome.arguments = outcome.arguments.iter().map(|_| MASK.to_string()).collect();
+        }
+        outcome
+    }
+}
+
+/// Lossily decode `output` and keep at most `max_bytes`, returning the bytes dropped.
+pub fn truncate_output(output: &[u8], max_bytes: usize) -> (String, u64) {
+    let text = String::from_utf8_lossy(output);
+    if text.len() <= max_bytes {
+        return (text.into_owned(), 0);
+    }
+    let mut end = max_bytes;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    (text[..end].to_string(), (text.len() - end) as u64)
+}
+
+/// Outcome as delivered to the backend: the reported outcome, a single-use nonce and a
+/// signature over both, so the control plane can tell the result came from this agent and
+/// was not altered or replayed on the way.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedExecutionOutcome {
+    #[serde(flatten)]
+    pub outcome: ExecutionOutcome,
+    pub nonce: String,
+    pub signature_algorithm: String,
+    pub signature: String,
+}
+
+impl SignedExecutionOutcome {
+    pub fn sign(outcome: ExecutionOutcome, nonce: String, signer: &dyn Signer) -> Result<Self, String> {
+        let payload = result_signing_payload(&outcome, &nonce)?;
+        Ok(Self {
+            signature: signer.sign(payload.as_bytes()),
+            signature_algorithm: signer.algorithm().to_string(),
+            outcome,
+            nonce,
+        })
+    }
+
+    pub fn verify(&self, verifier: &dyn Verifier) -> bool {
+        self.signature_algorithm == verifier.algorithm()
+            && result_signing_payload(&self.outcome, &self.nonce)
+                .map(|payload| verifier.verify(payload.as_bytes(), &self.signature))
+                .unwrap_or(false)
+    }
+}
+
+/// `command_id=<id>|nonce=<nonce>|outcome=<outcome JSON>`; the command id is repeated
+/// outside the JSON so a result cannot be re-attributed to another command.
+fn result_signing_payload(outcome: &ExecutionOutcome, nonce: &str) -> Result<String, String> {
+    let outcome_json =
+        serde_json::to_string(outcome).map_err(|err| format!("failed to encode execution outcome: {err}"))?;
+    Ok(format!("command_id={}|nonce={}|outcome={}", outcome.command_id, nonce, outcome_json))
+}
+
+/// Results are signed with `AGENT_POLICY_SIGNING_KEY`, the key that authorises commands;
+/// without it they are reported unsigned.
+pub fn result_signer_from_env() -> Option<HmacSha256> {
+    PolicyValidationOptions::from_env()
+        .signing_key
+        .map(|key| HmacSha256::new(key.as_bytes()))
+}
+
+fn result_nonce() -> Result<String, String> {
+    let mut bytes = [0_u8; 16];
+    getrandom::getrandom(&mut bytes).map_err(|err| format!("failed to generate result nonce: {err}"))?;
+    Ok(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// Queue `outcome` as an `rmm` uplink item for `<TAMSIL_RMM_BASE_ENDPOINT>/command-results`,
+/// signed by `signer` when one is given.
+pub async fn record_outcome(
+    outcome: &ExecutionOutcome,
+    policy: &PolicyBundle,
+    signer: Option<&dyn Signer>,
+    queue_dir: &Path,
+) -> Result<(), String> {
+    let reported = outcome.redacted(policy);
+    let payload_json = match signer {
+        Some(signer) => serde_json::to_string(&SignedExecutionOutcome::sign(reported, result_nonce()?, signer)?),
+        None => serde_json::to_string(&reported),
+    }
+    .map_err(|err| format!("failed to encode execution outcome: {err}"))?;
+    let command_id = outcome
+        .command_id
+        .chars()
+        .map(|ch| if ch.is_ascii_alphanumeric() || ch == '-' || ch == '_' { ch } else { '_' })
+        .collect::<String>();
+    let item_name = format!("rmm-result-{}-{}", command_id, outcome.finished_at_unix_ms);
+    enqueue_rmm_item(queue_dir, COMMAND_RESULTS_PATH, &payload_json, &item_name).await
+}
+
+/// Pending commands from `RMM_COMMAND_DIR` when it is set, otherwise the single command
+/// described by the `RMM_*` env vars.
+pub fn load_execution_requests(policy: &PolicyBundle, route: &CommandRouteConfig) -> Vec<ExecutionRequest> {
+    match RmmCommandQueue::from_env() {
+        Some(queue) => queue.load(policy, route, unix_time_ms()),
+        None => queue_execution_request(policy, route).into_iter().collect(),
+    }
+}
+
+/// On-disk shape of a queued command: a `SignedCommand` plus optional queue metadata.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct QueuedCommandFile {
+    command_id: String,
+    signed_payload: String,
+    /// Missing signatures parse, so the command is rejected with a reason rather than as
+    /// malformed.
+    #[serde(default)]
+    signature: String,
+    action: String,
+    #[serde(default)]
+    arguments: Vec<String>,
+    not_before_unix_time_ms: u64,
+    not_after_unix_time_ms: u64,
+    #[serde(default)]
+    requested_at_unix_ms: Option<u64>,
+    #[serde(default)]
+    earliest_start_unix_ms: Option<u64>,
+    #[serde(default)]
+    latest_start_unix_ms: Option<u64>,
+    #[serde(default)]
+    source: Option<String>,
+}
+
+impl QueuedCommandFile {
+    fn into_request(self) -> ExecutionRequest {
+        ExecutionRequest {
+            requested_at_unix_ms: self.requested_at_unix_ms.unwrap_or(self.not_before_unix_time_ms),
+            expires_at_unix_ms: self.not_after_unix_time_ms,
+            earliest_start_unix_ms: self.earliest_start_unix_ms,
+            latest_start_unix_ms: self.latest_start_unix_ms,
+            command_id: self.command_id,
+            signed_payload: self.signed_payload,
+            signature: self.signature,
+            action: self.action,
+            arguments: self.arguments,
+            source: self
+                .source
+                .filter(|value| !value.trim().is_empty())
+                .unwrap_or_else(|| "command-queue".to_string()),
+        }
+    }
+}
+
+/// Body of a file in the queue's `cancel/` directory.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CancelRequestFile {
+    command_id: String,
+}
+
+/// Set once a command is cancelled; executors select on [`CancelSignal::cancelled`].
+#[derive(Debug, Clone, Default)]
+pub struct CancelSignal {
+    inner: Arc<(AtomicBool, Notify)>,
+}
+
+impl CancelSignal {
+    pub fn cancel(&self) {
+        self.inner.0.store(true, Ordering::SeqCst);
+        self.inner.1.notify_waiters();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.0.load(Ordering::SeqCst)
+    }
+
+    /// Resolves once `cancel` has been called, including before this was awaited.
+    pub async fn cancelled(&self) {
+        let notified = self.inner.1.notified();
+        tokio::pin!(notified);
+        notified.as_mut().enable();
+        if self.is_cancelled() {
+            return;
+        }
+        notified.await;
+    }
+}
+
+/// Commands claimed from the queue and not yet finished, so a cancel request can reach
+/// them whether they are waiting for a slot or running.
+#[derive(Debug, Clone, Default)]
+pub struct CommandRegistry {
+    active: Arc<Mutex<HashMap<String, CancelSignal>>>,
+}
+
+impl CommandRegistry {
+    pub fn register(&self, command_id: &str) -> CancelSignal {
+        let mut active = self.active.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        active.entry(command_id.to_string()).or_default().clone()
+    }
+
+    pub fn finish(&self, command_id: &str) {
+        let mut active = self.active.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        active.remove(command_id);
+    }
+
+    /// Signal `command_id`; false when no such command is active.
+    pub fn cancel(&self, command_id: &str) -> bool {
+        let active = self.active.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        match active.get(command_id) {
+            Some(signal) => {
+                signal.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CancelStatus {
+    /// Removed from the queue before it was claimed; the outcome reports it cancelled.
+    Dequeued,
+    /// Signalled to a claimed command; its executor reports the outcome.
+    Signalled,
+    /// No queued or active command has this id; acknowledged and ignored.
+    NotFound,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CancelAck {
+    pub command_id: String,
+    pub status: CancelStatus,
+    pub outcome: Option<ExecutionOutcome>,
+}
+
+/// Directory of pending commands, one JSON file each. Loading claims every file: accepted
+/// commands move to `processing/` and invalid ones to `rejected/` next to a `.reason` file,
+/// so nothing is picked up twice.
+#[derive(Debug, Clone)]
+pub struct RmmCommandQueue {
+    pub dir: PathBuf,
+}
+
+impl RmmCommandQueue {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    pub fn from_env() -> Option<Self> {
+        env::var("RMM_COMMAND_DIR")
+            .ok()
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+            .map(|value| Self::new(PathBuf::from(value)))
+    }
+
+    pub fn processing_dir(&self) -> PathBuf {
+        self.dir.join(PROCESSING_DIR)
+    }
+
+    pub fn rejected_dir(&self) -> PathBuf {
+        self.dir.join(REJECTED_DIR)
+    }
+
+    /// Validate and claim every queued command with the same checks as `route_command`,
+    /// returning accepted ones ordered by requested time.
+    pub fn load(&self, policy: &PolicyBundle, route: &CommandRouteConfig, now_unix_ms: u64) -> Vec<ExecutionRequest> {
+        if fs::create_dir_all(self.processing_dir()).is_err() || fs::create_dir_all(self.rejected_dir()).is_err() {
+            return Vec::new();
+        }
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(_) => return Vec::new(),
+        };
+        let mut paths = entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file() && path.extension().map(|ext| ext == "json").unwrap_or(false))
+            .collect::<Vec<PathBuf>>();
+        paths.sort();
+
+        let mut accepted = Vec::new();
+        for path in paths {
+            match self.evaluate(&path, policy, route, now_unix_ms) {
+                Ok(request) => {
+                    if self.claim(&path) {
+                        accepted.push(request);
+                    }
+                }
+                Err(reason) => self.reject(&path, &reason),
+            }
+        }
+        accepted.sort_by(|left, right| {
+            left.requested_at_unix_ms
+                .cmp(&right.requested_at_unix_ms)
+                .then_with(|| left.command_id.cmp(&right.command_id))
+        });
+        accepted
+    }
+
+    fn evaluate(
+        &self,
+        path: &Path,
+        policy: &PolicyBundle,
+        route: &CommandRouteConfig,
+        now_unix_ms: u64,
+    ) -> Result<ExecutionRequest, String> {
+        let raw = read_file_bounded(path, MAX_COMMAND_FILE_BYTES)?;
+        let queued = serde_json::from_slice::<QueuedCommandFile>(&raw)
+            .map_err(|err| format!("Command file is not a valid command: {}", err))?;
+        let command = SignedCommand {
+            command_id: queued.command_id.clone(),
+            signed_payload: queued.signed_payload.clone(),
+            signature: queued.signature.clone(),
+            action: queued.action.clone(),
+            arguments: queued.arguments.clone(),
+            not_before_unix_time_ms: queued.not_before_unix_time_ms,
+            not_after_unix_time_ms: queued.not_after_unix_time_ms,
+        };
+        let decision = route_command_explain_with_config(&command, policy, route, now_unix_ms);
+        if !decision.allowed() {
+            let reasons = decision
+                .results
+                .iter()
+                .filter_map(|result| result.reason.clone())
+                .collect::<Vec<String>>();
+            return Err(reasons.join("; "));
+        }
+        if self.processing_dir().join(file_name(path)).exists() {
+            return Err(format!("Command {} is already being processed", command.command_id));
+        }
+
+        Ok(queued.into_request())
+    }
+
+    pub fn cancel_dir(&self) -> PathBuf {
+        self.dir.join(CANCEL_DIR)
+    }
+
+    /// Apply every cancel request in `cancel/`, consuming the request files. Unclaimed
+    /// commands are removed from the queue; claimed ones are signalled through `registry`.
+    pub fn apply_cancellations(&self, registry: &CommandRegistry, now_unix_ms: u64) -> Vec<CancelAck> {
+        let entries = match fs::read_dir(self.cancel_dir()) {
+            Ok(entries) => entries,
+            Err(_) => return Vec::new(),
+        };
+        let mut paths = entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file() && path.extension().map(|ext| ext == "json").unwrap_or(false))
+            .collect::<Vec<PathBuf>>();
+        paths.sort();
+
+        let mut acks = Vec::new();
+        for path in paths {
+            let request = read_file_bounded(&path, MAX_COMMAND_FILE_BYTES)
+                .ok()
+                .and_then(|raw| serde_json::from_slice::<CancelRequestFile>(&raw).ok());
+            let _ = fs::remove_file(&path);
+            let Some(request) = request else {
+                warn!(path = %path.display(), "ignoring malformed rmm cancel request");
+                continue;
+            };
+            let ack = self.cancel(&request.command_id, registry, now_unix_ms);
+            info!(command_id = %ack.command_id, status = ?ack.status, "rmm cancel request applied");
+            acks.push(ack);
+        }
+        acks
+    }
+
+    fn cancel(&self, command_id: &str, registry: &CommandRegistry, now_unix_ms: u64) -> CancelAck {
+        if let Some((path, queued)) = self.find_queued(command_id) {
+            if fs::remove_file(&path).is_ok() {
+                let request = queued.into_request();
+                return CancelAck {
+                    command_id: command_id.to_string(),
+                    status: CancelStatus::Dequeued,
+                    outcome: Some(ExecutionOutcome::cancelled(&request, now_unix_ms)),
+                };
+            }
+        }
+        let status = if registry.cancel(command_id) {
+            CancelStatus::Signalled
+        } else {
+            CancelStatus::NotFound
+        };
+        CancelAck {
+            command_id: command_id.to_string(),
+            status,
+            outcome: None,
+        }
+    }
+
+    fn find_queued(&self, command_id: &str) -> Option<(PathBuf, QueuedCommandFile)> {
+        fs::read_dir(&self.dir)
+            .ok()?
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file() && path.extension().map(|ext| ext == "json").unwrap_or(false))
+            .find_map(|path| {
+                let raw = read_file_bounded(&path, MAX_COMMAND_FILE_BYTES).ok()?;
+                let queued = serde_json::from_slice::<QueuedCommandFile>(&raw).ok()?;
+                (queued.command_id == command_id).then_some((path, queued))
+            })
+    }
+
+    /// Move an accepted file into `processing/`; a failed rename means another loader
+    /// claimed it first.
+    fn claim(&self, path: &Path) -> bool {
+        fs::rename(path, self.processing_dir().join(file_name(path))).is_ok()
+    }
+
+    fn reject(&self, path: &Path, reason: &str) {
+        let name = file_name(path);
+        audit::record(AuditEvent::CommandRejected {
+            command_id: name.trim_end_matches(".json").to_string(),
+            reason: reason.to_string(),
+        });
+        let target = self.rejected_dir().join(&name);
+        if fs::rename(path, &target).is_ok() {
+            let _ = fs::write(self.rejected_dir().join(format!("{}.reason", name)), reason);
+        }
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+/// Dry-run the pending command against policy without queueing it. Returns `None` when no
+/// command is pending.
+pub fn explain_execution_request(policy: &PolicyBundle) -> Option<CommandDecision> {
+    let config = RmmConfig::from_env();
+    let pending = RmmPendingCommand::from_env()?;
+    let now = unix_time_ms();
+    let expires_at_unix_ms = pending
+        .expires_at_unix_ms
+        .unwrap_or_else(|| now.saturating_add(config.max_request_lifetime_ms));
+
+    let command = SignedCommand {
+        command_id: pending.command_id,
+        signed_payload: pending.signed_payload,
+        signature: pending.signature,
+        action: pending.action,
+        arguments: pending.arguments,
+        not_before_unix_time_ms: now,
+        not_after_unix_time_ms: expires_at_unix_ms,
+    };
+    Some(route_command_explain(&command, policy, now))
 }
 
 impl RmmPendingCommand {
     fn from_env() -> Option<Self> {
         let command_id = env::var("RMM_COMMAND_ID").ok()?.trim().to_string();
         let signed_payload = env::var("RMM_SIGNED_PAYLOAD").ok()?.trim().to_string();
+        let signature = env::var("RMM_SIGNATURE").map(|value| value.trim().to_string()).unwrap_or_default();
         let action = env::var("RMM_ACTION").ok()?.trim().to_string();
         let arguments = env::var("RMM_ARGS")
             .ok()
@@ -111,6 +764,12 @@ impl RmmPendingCommand {
         let expires_at_unix_ms = env::var("RMM_EXPIRES_AT_UNIX_MS")
             .ok()
             .and_then(|value| value.parse::<u64>().ok());
+        let earliest_start_unix_ms = env::var("RMM_EARLIEST_START_UNIX_MS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok());
+        let latest_start_unix_ms = env::var("RMM_LATEST_START_UNIX_MS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok());
         let source = env::var("RMM_SOURCE")
             .ok()
             .map(|value| value.trim().to_string())
@@ -124,9 +783,12 @@ impl RmmPendingCommand {
         Some(Self {
             command_id,
             signed_payload,
+            signature,
             action,
             arguments,
             expires_at_unix_ms,
+            earliest_start_unix_ms,
+            latest_start_unix_ms,
             source,
         })
     }
@@ -139,3 +801,705 @@ fn parse_csv(value: String) -> Vec<String> {
         .filter(|entry| !entry.is_empty())
         .collect()
 }
+
+/// Source of the current time for scheduling decisions, injectable in tests.
+pub trait Clock: Send + Sync {
+    fn now_unix_ms(&self) -> u64;
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_unix_ms(&self) -> u64 {
+        unix_time_ms()
+    }
+}
+
+const MINUTES_PER_DAY: u32 = 1440;
+const MINUTES_PER_WEEK: u32 = 7 * MINUTES_PER_DAY;
+const WEEKDAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+/// Weekly UTC range `[start, end)`; an end before the start runs past midnight into the next
+/// day.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WeeklyWindow {
+    /// 0 is Monday.
+    pub weekday: u32,
+    pub start_minute: u32,
+    pub end_minute: u32,
+}
+
+impl WeeklyWindow {
+    fn contains(&self, minute_of_week: u32) -> bool {
+        let start = self.weekday * MINUTES_PER_DAY + self.start_minute;
+        let mut end = self.weekday * MINUTES_PER_DAY + self.end_minute;
+        if self.end_minute <= self.start_minute {
+            end += MINUTES_PER_DAY;
+        }
+        // Windows opening late on Sunday run on into Monday of the next week.
+        [minute_of_week, minute_of_week + MINUTES_PER_WEEK]
+            .iter()
+            .any(|minute| (start..end).contains(minute))
+    }
+}
+
+/// Maintenance windows outside which actions marked `disruptive` in the policy are held.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MaintenanceSchedule {
+    pub windows: Vec<WeeklyWindow>,
+}
+
+impl MaintenanceSchedule {
+    /// `RMM_MAINTENANCE_WINDOWS`, e.g. `sat 22:00-02:00;daily 03:00-04:00`. Unset means
+    /// disruptive actions are unrestricted; an unparsable value opens no window at all.
+    pub fn from_env() -> Option<Self> {
+        let raw = env::var("RMM_MAINTENANCE_WINDOWS").ok().filter(|value| !value.trim().is_empty())?;
+        match Self::parse(&raw) {
+            Ok(schedule) => Some(schedule),
+            Err(err) => {
+                warn!(error = %err, "invalid RMM_MAINTENANCE_WINDOWS; disruptive actions are blocked");
+                Some(Self::default())
+            }
+        }
+    }
+
+    /// Parse `;`-separated `<day> HH:MM-HH:MM` entries, where day is `mon`..`sun` or `daily`.
+    pub fn parse(raw: &str) -> Result<Self, String> {
+        let mut windows = Vec::new();
+        for entry in raw.split(';').map(str::trim).filter(|entry| !entry.is_empty()) {
+            let (day, range) = entry
+                .split_once(' ')
+                .ok_or_else(|| format!("window {:?} needs a day and a time range", entry))?;
+            let (start, end) = range
+                .trim()
+                .split_once('-')
+                .ok_or_else(|| format!("window {:?} needs a start-end range", entry))?;
+            let start_minute = parse_clock_minute(start)?;
+            let end_minute = parse_clock_minute(end)?;
+            let day = day.trim().to_ascii_lowercase();
+            let weekdays = if day == "daily" {
+                (0..7).collect::<Vec<u32>>()
+            } else {
+                let weekday = WEEKDAYS
+                    .iter()
+                    .position(|name| *name == day)
+                    .ok_or_else(|| format!("unknown day {:?}", day))?;
+                vec![weekday as u32]
+            };
+            windows.extend(weekdays.into_iter().map(|weekday| WeeklyWindow {
+                weekday,
+                start_minute,
+                end_minute,
+            }));
+        }
+        Ok(Self { windows })
+    }
+
+    pub fn is_open(&self, now_unix_ms: u64) -> bool {
+        let minutes = now_unix_ms / 60_000;
+        // 1970-01-01 was a Thursday (weekday 3).
+        let minute_of_week = ((minutes + 3 * u64::from(MINUTES_PER_DAY)) % u64::from(MINUTES_PER_WEEK)) as u32;
+        self.windows.iter().any(|window| window.contains(minute_of_week))
+    }
+}
+
+fn parse_clock_minute(value: &str) -> Result<u32, String> {
+    let (hours, minutes) = value
+        .trim()
+        .split_once(':')
+        .ok_or_else(|| format!("time {:?} is not HH:MM", value))?;
+    let hours = hours.parse::<u32>().ok().filter(|hours| *hours < 24);
+    let minutes = minutes.parse::<u32>().ok().filter(|minutes| *minutes < 60);
+    match (hours, minutes) {
+        (Some(hours), Some(minutes)) => Ok(hours * 60 + minutes),
+        _ => Err(format!("time {:?} is not HH:MM", value)),
+    }
+}
+
+/// What the scheduler decided for a request.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "decision", rename_all = "snake_case")]
+pub enum ScheduleDecision {
+    Started,
+    /// Not runnable yet; the request stays claimed and can be offered again later.
+    Deferred { reason: String },
+    /// Can no longer start before its deadline and must not be retried.
+    Expired { reason: String },
+}
+
+impl ScheduleDecision {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Started => "started",
+            Self::Deferred { .. } => "deferred",
+            Self::Expired { .. } => "expired",
+        }
+    }
+}
+
+/// Reportable record of one scheduling decision.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ScheduleRecord {
+    pub command_id: String,
+    pub action: String,
+    #[serde(flatten)]
+    pub decision: ScheduleDecision,
+    pub decided_at_unix_ms: u64,
+}
+
+/// Held while a command runs; dropping it frees the execution slot.
+#[derive(Debug)]
+pub struct ExecutionPermit {
+    pub record: ScheduleRecord,
+    _slot: OwnedSemaphorePermit,
+}
+
+/// Gate in front of every executor: honours request start windows and maintenance windows,
+/// and serialises execution through a fixed number of slots.
+pub struct ExecutionScheduler {
+    schedule: Option<MaintenanceSchedule>,
+    slots: Arc<Semaphore>,
+    clock: Arc<dyn Clock>,
+    metrics: MetricsHandle,
+}
+
+impl std::fmt::Debug for ExecutionScheduler {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        formatter
+            .debug_struct("ExecutionScheduler")
+            .field("schedule", &self.schedule)
+            .field("available_slots", &self.slots.available_permits())
+            .finish()
+    }
+}
+
+impl ExecutionScheduler {
+    pub fn new(schedule: Option<MaintenanceSchedule>, slot_count: usize, clock: Arc<dyn Clock>, metrics: MetricsHandle) -> Self {
+        Self {
+            schedule,
+            slots: Arc::new(Semaphore::new(slot_count.max(1))),
+            clock,
+            metrics,
+        }
+    }
+
+    /// `RMM_EXECUTION_SLOTS` (default 1) concurrent executions and `RMM_MAINTENANCE_WINDOWS`.
+    pub fn from_env(metrics: MetricsHandle) -> Self {
+        let slot_count = env::var("RMM_EXECUTION_SLOTS")
+            .ok()
+            .and_then(|value| value.parse::<usize>().ok())
+            .filter(|value| *value > 0)
+            .unwrap_or(1);
+        Self::new(MaintenanceSchedule::from_env(), slot_count, Arc::new(SystemClock), metrics)
+    }
+
+    /// Decide whether `request` may start now, ignoring slot availability.
+    pub fn check(&self, request: &ExecutionRequest, policy: &PolicyBundle) -> Result<(), ScheduleDecision> {
+        let now = self.clock.now_unix_ms();
+        let deadline = start_deadline(request);
+        if now > deadline {
+            return Err(ScheduleDecision::Expired {
+                reason: format!("start deadline {} has passed", deadline),
+            });
+        }
+        if let Some(earliest) = request.earliest_start_unix_ms.filter(|earliest| *earliest > now) {
+            return Err(ScheduleDecision::Deferred {
+                reason: format!("not before {}", earliest),
+            });
+        }
+        if let Some(schedule) = &self.schedule {
+            if policy.argument_rules_for(&request.action).disruptive && !schedule.is_open(now) {
+                return Err(ScheduleDecision::Deferred {
+                    reason: "disruptive action outside maintenance window".to_string(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Wait for an execution slot, giving up once the request's start deadline passes.
+    /// Every outcome is logged and counted in `agent_rmm_schedule_decisions_total`.
+    pub async fn admit(&self, request: &ExecutionRequest, policy: &PolicyBundle) -> Result<ExecutionPermit, ScheduleRecord> {
+        if let Err(decision) = self.check(request, policy) {
+            return Err(self.record(request, decision));
+        }
+        let wait_ms = start_deadline(request).saturating_sub(self.clock.now_unix_ms());
+        let slot = match tokio::time::timeout(
+            Duration::from_millis(wait_ms),
+            Arc::clone(&self.slots).acquire_owned(),
+        )
+        .await
+        {
+            Ok(Ok(slot)) => slot,
+            Ok(Err(_)) | Err(_) => {
+                return Err(self.record(
+                    request,
+                    ScheduleDecision::Expired {
+                        reason: "no execution slot freed before the start deadline".to_string(),
+                    },
+                ))
+            }
+        };
+        // Time has passed while queued; the window may have closed.
+        if let Err(decision) = self.check(request, policy) {
+            return Err(self.record(request, decision));
+        }
+        Ok(ExecutionPermit {
+            record: self.record(request, ScheduleDecision::Started),
+            _slot: slot,
+        })
+    }
+
+    fn record(&self, request: &ExecutionRequest, decision: ScheduleDecision) -> ScheduleRecord {
+        self.metrics.rmm_schedule_decisions.inc(decision.label());
+        match &decision {
+            ScheduleDecision::Started => info!(command_id = %request.command_id, action = %request.action, "rmm command started"),
+            ScheduleDecision::Deferred { reason } => {
+                info!(command_id = %request.command_id, action = %request.action, reason = %reason, "rmm command deferred")
+            }
+            ScheduleDecision::Expired { reason } => {
+                warn!(command_id = %request.command_id, action = %request.action, reason = %reason, "rmm command expired")
+            }
+        }
+        ScheduleRecord {
+            command_id: request.command_id.clone(),
+            action: request.action.clone(),
+            decision,
+            decided_at_unix_ms: self.clock.now_unix_ms(),
+        }
+    }
+}
+
+/// Latest time a request may start: its expiry, or an earlier `latest_start_unix_ms`.
+fn start_deadline(request: &ExecutionRequest) -> u64 {
+    request
+        .latest_start_unix_ms
+        .map(|latest| latest.min(request.expires_at_unix_ms))
+        .unwrap_or(request.expires_at_unix_ms)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    use super::{
+        pending_request, record_outcome, RmmConfig, RmmPendingCommand, Clock, ExecutionOutcome, ExecutionRequest, ExecutionScheduler, MaintenanceSchedule, RmmCommandQueue,
+        CancelStatus, CommandRegistry, ScheduleDecision, SignedExecutionOutcome, Termination,
+    };
+    use crate::command_router::{command_signing_message, CommandRouteConfig, SignedCommand};
+    use crate::crypto::{HmacSha256, Signer};
+    use crate::metrics::AgentMetrics;
+    use crate::policy::{ArgumentRules, PolicyBundle};
+    use crate::time::unix_time_ms;
+
+    fn temp_queue(label: &str) -> RmmCommandQueue {
+        let dir = std::env::temp_dir().join(format!("rmm-queue-{}-{}-{}", label, std::process::id(), unix_time_ms()));
+        std::fs::create_dir_all(&dir).expect("create queue dir");
+        RmmCommandQueue::new(dir)
+    }
+
+    const COMMAND_KEY: &[u8] = b"unit-test-command-key";
+
+    fn route() -> CommandRouteConfig {
+        CommandRouteConfig::from_env().with_verifier(Arc::new(HmacSha256::new(COMMAND_KEY)))
+    }
+
+    fn write_command(dir: &Path, name: &str, action: &str, requested_at: u64) {
+        let command = SignedCommand {
+            command_id: name.to_string(),
+            signed_payload: "signed".to_string(),
+            signature: String::new(),
+            action: action.to_string(),
+            arguments: vec!["-version".to_string()],
+            not_before_unix_time_ms: 0,
+            not_after_unix_time_ms: u64::MAX,
+        };
+        let signature = HmacSha256::new(COMMAND_KEY).sign(command_signing_message(&command).as_bytes());
+        let json = format!(
+            r#"{{"command_id":"{}","signed_payload":"signed","signature":"{}","action":"{}","arguments":["-version"],"not_before_unix_time_ms":0,"not_after_unix_time_ms":{},"requested_at_unix_ms":{}}}"#,
+            name,
+            signature,
+            action,
+            u64::MAX,
+            requested_at
+        );
+        std::fs::write(dir.join(format!("{}.json", name)), json).expect("write command");
+    }
+
+    fn names(dir: &PathBuf) -> Vec<String> {
+        let mut names = std::fs::read_dir(dir)
+            .expect("read dir")
+            .flatten()
+            .filter(|entry| entry.path().is_file())
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .collect::<Vec<String>>();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn loads_commands_ordered_by_requested_time() {
+        let queue = temp_queue("order");
+        write_command(&queue.dir, "cmd-a", "script-run", 300);
+        write_command(&queue.dir, "cmd-b", "patch-apply", 100);
+        write_command(&queue.dir, "cmd-c", "script-run", 200);
+
+        let requests = queue.load(&PolicyBundle::placeholder(), &route(), 1_000);
+        let ids = requests.iter().map(|request| request.command_id.as_str()).collect::<Vec<&str>>();
+        assert_eq!(ids, vec!["cmd-b", "cmd-c", "cmd-a"]);
+        assert_eq!(requests[0].source, "command-queue");
+        assert_eq!(names(&queue.processing_dir()), vec!["cmd-a.json", "cmd-b.json", "cmd-c.json"]);
+
+        let _ = std::fs::remove_dir_all(&queue.dir);
+    }
+
+    #[test]
+    fn moves_invalid_commands_to_rejected_with_reason() {
+        let queue = temp_queue("reject");
+        write_command(&queue.dir, "cmd-ok", "script-run", 100);
+        write_command(&queue.dir, "cmd-bad", "format-disk", 100);
+        std::fs::write(queue.dir.join("garbage.json"), "{not json").expect("write garbage");
+
+        let requests = queue.load(&PolicyBundle::placeholder(), &route(), 1_000);
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].command_id, "cmd-ok");
+        assert_eq!(
+            names(&queue.rejected_dir()),
+            vec!["cmd-bad.json", "cmd-bad.json.reason", "garbage.json", "garbage.json.reason"]
+        );
+        let reason = std::fs::read_to_string(queue.rejected_dir().join("cmd-bad.json.reason")).expect("reason");
+        assert!(reason.contains("Action format-disk not permitted by policy"));
+        assert!(names(&queue.dir).is_empty());
+
+        let _ = std::fs::remove_dir_all(&queue.dir);
+    }
+
+    #[test]
+    fn never_returns_a_command_twice() {
+        let queue = temp_queue("once");
+        write_command(&queue.dir, "cmd-a", "script-run", 100);
+        let policy = PolicyBundle::placeholder();
+
+        assert_eq!(queue.load(&policy, &route(), 1_000).len(), 1);
+        assert!(queue.load(&policy, &route(), 1_000).is_empty());
+
+        write_command(&queue.dir, "cmd-a", "script-run", 100);
+        assert!(queue.load(&policy, &route(), 1_000).is_empty());
+        assert!(queue.rejected_dir().join("cmd-a.json.reason").exists());
+
+        let _ = std::fs::remove_dir_all(&queue.dir);
+    }
+
+    fn pending(arguments: &[&str]) -> RmmPendingCommand {
+        let arguments = arguments.iter().map(|argument| argument.to_string()).collect::<Vec<String>>();
+        let command = SignedCommand {
+            command_id: "cmd-env".to_string(),
+            signed_payload: "signed".to_string(),
+            signature: String::new(),
+            action: "script-run".to_string(),
+            arguments: arguments.clone(),
+            not_before_unix_time_ms: 0,
+            not_after_unix_time_ms: 0,
+        };
+        RmmPendingCommand {
+            command_id: command.command_id.clone(),
+            signed_payload: command.signed_payload.clone(),
+            signature: HmacSha256::new(COMMAND_KEY).sign(command_signing_message(&command).as_bytes()),
+            action: command.action.clone(),
+            arguments,
+            expires_at_unix_ms: None,
+            earliest_start_unix_ms: None,
+            latest_start_unix_ms: None,
+            source: "policy-queue".to_string(),
+        }
+    }
+
+    #[test]
+    fn env_command_gets_the_router_argument_scan() {
+        let policy = PolicyBundle::placeholder();
+        let config = RmmConfig::from_env();
+        let accepted = pending_request(pending(&["-version"]), &policy, &route(), &config, 1_000).expect("benign command");
+        assert_eq!(accepted.command_id, "cmd-env");
+
+        for argument in ["a; rm -rf /", "$(id)", "`id`", "../../etc/shadow"] {
+            assert!(pending_request(pending(&[argument]), &policy, &route(), &config, 1_000).is_none(), "{}", argument);
+        }
+        let mut unsigned = pending(&["-version"]);
+        unsigned.signature.clear();
+        assert!(pending_request(unsigned, &policy, &route(), &config, 1_000).is_none());
+    }
+
+    fn write_cancel(queue: &RmmCommandQueue, name: &str, command_id: &str) {
+        std::fs::create_dir_all(queue.cancel_dir()).expect("create cancel dir");
+        std::fs::write(
+            queue.cancel_dir().join(format!("{}.json", name)),
+            format!(r#"{{"command_id":"{}"}}"#, command_id),
+        )
+        .expect("write cancel");
+    }
+
+    #[test]
+    fn cancels_queued_and_active_commands_and_acks_unknown_ids() {
+        let queue = temp_queue("cancel");
+        write_command(&queue.dir, "cmd-queued", "script-run", 100);
+        write_command(&queue.dir, "cmd-kept", "script-run", 200);
+        write_cancel(&queue, "1", "cmd-queued");
+        write_cancel(&queue, "2", "cmd-running");
+        write_cancel(&queue, "3", "cmd-unknown");
+        let registry = CommandRegistry::default();
+        let running = registry.register("cmd-running");
+
+        let acks = queue.apply_cancellations(&registry, 5_000);
+        let statuses = acks
+            .iter()
+            .map(|ack| (ack.command_id.as_str(), ack.status))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            statuses,
+            vec![
+                ("cmd-queued", CancelStatus::Dequeued),
+                ("cmd-running", CancelStatus::Signalled),
+                ("cmd-unknown", CancelStatus::NotFound),
+            ]
+        );
+        let outcome = acks[0].outcome.as_ref().expect("dequeued outcome");
+        assert_eq!(outcome.termination, Termination::Cancelled);
+        assert_eq!(outcome.action, "script-run");
+        assert_eq!(outcome.exit_code, None);
+        assert!(!outcome.success);
+        assert!(acks[1].outcome.is_none() && acks[2].outcome.is_none());
+        assert!(running.is_cancelled());
+        assert!(names(&queue.cancel_dir()).is_empty());
+
+        let remaining = queue.load(&PolicyBundle::placeholder(), &route(), 5_000);
+        let ids = remaining.iter().map(|request| request.command_id.as_str()).collect::<Vec<&str>>();
+        assert_eq!(ids, vec!["cmd-kept"]);
+
+        registry.finish("cmd-running");
+        write_cancel(&queue, "4", "cmd-running");
+        assert_eq!(queue.apply_cancellations(&registry, 6_000)[0].status, CancelStatus::NotFound);
+
+        let _ = std::fs::remove_dir_all(&queue.dir);
+    }
+
+    fn build_request(action: &str) -> ExecutionRequest {
+        ExecutionRequest {
+            command_id: "cmd/42".to_string(),
+            signed_payload: "signed".to_string(),
+            signature: String::new(),
+            action: action.to_string(),
+            arguments: vec!["--token".to_string(), "s3cret".to_string()],
+            requested_at_unix_ms: 100,
+            expires_at_unix_ms: 10_000,
+            earliest_start_unix_ms: None,
+            latest_start_unix_ms: None,
+            source: "command-queue".to_string(),
+        }
+    }
+
+    #[test]
+    fn truncates_output_and_records_dropped_bytes() {
+        let stdout = "é".repeat(10);
+        let outcome = ExecutionOutcome::new(&build_request("script-run"), Some(0), 1_000, 2_000, stdout.as_bytes(), b"warn", 5);
+        assert_eq!(outcome.stdout, "éé");
+        assert_eq!(outcome.stdout_truncated_bytes, 16);
+        assert_eq!(outcome.stderr, "warn");
+        assert_eq!(outcome.stderr_truncated_bytes, 0);
+        assert!(outcome.success);
+
+        let failed = ExecutionOutcome::new(&build_request("script-run"), Some(2), 1_000, 2_000, b"", b"", 5);
+        assert!(!failed.success);
+        assert!(!ExecutionOutcome::new(&build_request("script-run"), None, 1_000, 2_000, b"", b"", 5).success);
+    }
+
+    #[test]
+    fn masks_arguments_of_sensitive_actions() {
+        let mut policy = PolicyBundle::placeholder();
+        policy.execution.argument_rules.insert(
+            "script-run".to_string(),
+            ArgumentRules {
+                sensitive: true,
+                ..ArgumentRules::default()
+            },
+        );
+        let sensitive = ExecutionOutcome::new(&build_request("script-run"), Some(0), 1, 2, b"", b"", 64);
+        assert_eq!(sensitive.redacted(&policy).arguments, vec!["***", "***"]);
+
+        let plain = ExecutionOutcome::new(&build_request("patch-apply"), Some(0), 1, 2, b"", b"", 64);
+        assert_eq!(plain.redacted(&policy).arguments, vec!["--token", "s3cret"]);
+    }
+
+    #[tokio::test]
+    async fn queues_outcome_as_rmm_item() {
+        let queue_dir = std::env::temp_dir().join(format!("rmm-outcome-{}-{}", std::process::id(), unix_time_ms()));
+        let outcome = ExecutionOutcome::new(&build_request("script-run"), Some(1), 1_000, 2_500, b"out", b"err", 64);
+        record_outcome(&outcome, &PolicyBundle::placeholder(), None, &queue_dir)
+            .await
+            .expect("queue outcome");
+
+        let raw = std::fs::read_to_string(queue_dir.join("hi-rmm-result-cmd_42-2500.json")).expect("queued item");
+        let item: serde_json::Value = serde_json::from_str(&raw).expect("item json");
+        assert_eq!(item["kind"], "rmm");
+        assert_eq!(item["path"], "/command-results");
+        let payload: serde_json::Value =
+            serde_json::from_str(item["payload_json"].as_str().expect("payload")).expect("payload json");
+        assert_eq!(payload["command_id"], "cmd/42");
+        assert_eq!(payload["action"], "script-run");
+        assert_eq!(payload["exit_code"], 1);
+        assert_eq!(payload["started_at_unix_ms"], 1_000);
+        assert_eq!(payload["finished_at_unix_ms"], 2_500);
+        assert_eq!(payload["stdout"], "out");
+        assert_eq!(payload["stdout_truncated_bytes"], 0);
+        assert_eq!(payload["stderr"], "err");
+        assert_eq!(payload["success"], false);
+
+        let _ = std::fs::remove_dir_all(&queue_dir);
+    }
+
+    #[test]
+    fn signed_outcome_verifies_and_detects_tampering() {
+        let backend = HmacSha256::new(b"unit-test-key");
+        let outcome = ExecutionOutcome::new(&build_request("script-run"), Some(1), 1_000, 2_000, b"out", b"", 64);
+        let signed = SignedExecutionOutcome::sign(outcome, "nonce-1".to_string(), &backend).expect("sign");
+        assert!(signed.verify(&backend));
+        assert!(!signed.verify(&HmacSha256::new(b"other-key")));
+
+        let json = serde_json::to_string(&signed).expect("signed json");
+        let parsed: SignedExecutionOutcome = serde_json::from_str(&json).expect("parse signed");
+        assert!(parsed.verify(&backend));
+
+        let mut tampered = parsed.clone();
+        tampered.outcome.exit_code = Some(0);
+        tampered.outcome.success = true;
+        assert!(!tampered.verify(&backend));
+        let mut reattributed = parsed.clone();
+        reattributed.outcome.command_id = "cmd/other".to_string();
+        assert!(!reattributed.verify(&backend));
+        let mut replayed = parsed;
+        replayed.nonce = "nonce-2".to_string();
+        assert!(!replayed.verify(&backend));
+    }
+
+    #[tokio::test]
+    async fn queues_signed_outcome_when_signer_configured() {
+        let queue_dir = std::env::temp_dir().join(format!("rmm-signed-{}-{}", std::process::id(), unix_time_ms()));
+        let backend = HmacSha256::new(b"unit-test-key");
+        let outcome = ExecutionOutcome::new(&build_request("script-run"), Some(0), 1_000, 3_000, b"ok", b"", 64);
+        record_outcome(&outcome, &PolicyBundle::placeholder(), Some(&backend), &queue_dir)
+            .await
+            .expect("queue outcome");
+
+        let raw = std::fs::read_to_string(queue_dir.join("hi-rmm-result-cmd_42-3000.json")).expect("queued item");
+        let item: serde_json::Value = serde_json::from_str(&raw).expect("item json");
+        let signed: SignedExecutionOutcome =
+            serde_json::from_str(item["payload_json"].as_str().expect("payload")).expect("signed payload");
+        assert_eq!(signed.outcome.command_id, "cmd/42");
+        assert_eq!(signed.signature_algorithm, "hmac-sha256");
+        assert_eq!(signed.nonce.len(), 32);
+        assert!(signed.verify(&backend));
+
+        let _ = std::fs::remove_dir_all(&queue_dir);
+    }
+
+    struct FixedClock(AtomicU64);
+
+    impl Clock for FixedClock {
+        fn now_unix_ms(&self) -> u64 {
+            self.0.load(Ordering::SeqCst)
+        }
+    }
+
+    // 1970-01-03 was a Saturday.
+    fn saturday_at(hours: u64, minutes: u64) -> u64 {
+        (2 * 86_400 + hours * 3_600 + minutes * 60) * 1_000
+    }
+
+    fn disruptive_policy() -> PolicyBundle {
+        let mut policy = PolicyBundle::placeholder();
+        policy.execution.argument_rules.insert(
+            "patch-apply".to_string(),
+            ArgumentRules {
+                disruptive: true,
+                ..ArgumentRules::default()
+            },
+        );
+        policy
+    }
+
+    #[test]
+    fn maintenance_windows_open_on_half_open_boundaries() {
+        let schedule = MaintenanceSchedule::parse("sat 22:00-02:00; sun 23:30-00:30").expect("parse");
+        assert!(!schedule.is_open(saturday_at(21, 59)));
+        assert!(schedule.is_open(saturday_at(22, 0)));
+        assert!(schedule.is_open(saturday_at(25, 59)));
+        assert!(!schedule.is_open(saturday_at(26, 0)));
+        // Sunday's window runs past the end of the week into Monday.
+        assert!(schedule.is_open(saturday_at(47, 45)));
+        assert!(schedule.is_open(saturday_at(48, 29)));
+        assert!(!schedule.is_open(saturday_at(48, 30)));
+
+        let daily = MaintenanceSchedule::parse("daily 03:00-04:00").expect("parse");
+        assert_eq!(daily.windows.len(), 7);
+        assert!(daily.is_open(saturday_at(3, 0)) && daily.is_open(saturday_at(27, 30)));
+        assert!(MaintenanceSchedule::parse("someday 01:00-02:00").is_err());
+        assert!(MaintenanceSchedule::parse("mon 24:00-02:00").is_err());
+    }
+
+    #[test]
+    fn defers_and_expires_against_injected_clock() {
+        let clock = Arc::new(FixedClock(AtomicU64::new(saturday_at(21, 0))));
+        let schedule = MaintenanceSchedule::parse("sat 22:00-02:00").expect("parse");
+        let scheduler = ExecutionScheduler::new(Some(schedule), 1, clock.clone(), AgentMetrics::new_handle());
+        let policy = disruptive_policy();
+
+        let mut patch = build_request("patch-apply");
+        patch.expires_at_unix_ms = saturday_at(23, 0);
+        assert!(matches!(scheduler.check(&patch, &policy), Err(ScheduleDecision::Deferred { .. })));
+        let mut script = build_request("script-run");
+        script.expires_at_unix_ms = saturday_at(23, 0);
+        assert_eq!(scheduler.check(&script, &policy), Ok(()));
+
+        clock.0.store(saturday_at(22, 0), Ordering::SeqCst);
+        assert_eq!(scheduler.check(&patch, &policy), Ok(()));
+
+        patch.earliest_start_unix_ms = Some(saturday_at(22, 30));
+        assert!(matches!(scheduler.check(&patch, &policy), Err(ScheduleDecision::Deferred { .. })));
+        patch.earliest_start_unix_ms = None;
+        patch.latest_start_unix_ms = Some(saturday_at(21, 59));
+        assert!(matches!(scheduler.check(&patch, &policy), Err(ScheduleDecision::Expired { .. })));
+    }
+
+    #[tokio::test]
+    async fn request_expires_while_waiting_for_a_slot() {
+        let now = unix_time_ms();
+        let clock = Arc::new(FixedClock(AtomicU64::new(now)));
+        let metrics = AgentMetrics::new_handle();
+        let scheduler = ExecutionScheduler::new(None, 1, clock, metrics.clone());
+        let policy = PolicyBundle::placeholder();
+
+        let mut first = build_request("script-run");
+        first.expires_at_unix_ms = now + 60_000;
+        let permit = scheduler.admit(&first, &policy).await.expect("first admitted");
+        assert_eq!(permit.record.decision, ScheduleDecision::Started);
+
+        let mut second = build_request("script-run");
+        second.command_id = "cmd/43".to_string();
+        second.expires_at_unix_ms = now + 60_000;
+        second.latest_start_unix_ms = Some(now + 100);
+        let record = scheduler.admit(&second, &policy).await.expect_err("no slot before deadline");
+        assert_eq!(record.command_id, "cmd/43");
+        assert!(matches!(record.decision, ScheduleDecision::Expired { .. }));
+        let reported: serde_json::Value = serde_json::to_value(&record).expect("record json");
+        assert_eq!(reported["decision"], "expired");
+
+        drop(permit);
+        let mut third = build_request("script-run");
+        third.expires_at_unix_ms = now + 60_000;
+        assert!(scheduler.admit(&third, &policy).await.is_ok());
+        assert_eq!(metrics.rmm_schedule_decisions.get("started"), 2);
+        assert_eq!(metrics.rmm_schedule_decisions.get("expired"), 1);
+    }
+}
@@ -0,0 +1,464 @@
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::task::JoinSet;
+use tokio::time::MissedTickBehavior;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use crate::audit::{self, AuditEvent};
+use crate::command_log::CommandLog;
+use crate::command_router::{verify_command_signature, CommandRouteConfig};
+use crate::crypto::{HmacSha256, Signer};
+use crate::executor::{run_script, ScriptExecutorConfig, SCRIPT_RUN_ACTION};
+use crate::file_place::{place_file, FilePlaceConfig, StdPlacementFs, FILE_PLACE_ACTION};
+use crate::metrics::MetricsHandle;
+use crate::patch::{record_patch_report, run_patch_job, PackageManager, PATCH_APPLY_ACTION};
+use crate::policy::PolicyBundle;
+use crate::rmm::{
+    record_outcome, result_signer_from_env, CancelSignal, CommandRegistry, ExecutionOutcome, ExecutionRequest, ExecutionScheduler,
+    RmmCommandQueue, RmmConfig, ScheduleDecision, Termination,
+};
+use crate::seen_commands::SeenCommandCache;
+use crate::time::unix_time_ms;
+
+/// How long a deferred command waits before it is offered to the scheduler again.
+const DEFERRED_RETRY: Duration = Duration::from_secs(60);
+
+/// Runs claimed commands: each waits for the scheduler, goes to the executor for its
+/// action, and has its outcome queued for `/command-results`.
+#[derive(Clone)]
+pub struct RmmDispatcher {
+    policy: Arc<PolicyBundle>,
+    route: CommandRouteConfig,
+    scheduler: Arc<ExecutionScheduler>,
+    script_config: ScriptExecutorConfig,
+    file_place_config: FilePlaceConfig,
+    patch_backend: PackageManager,
+    signer: Option<HmacSha256>,
+    queue_dir: PathBuf,
+    max_output_bytes: usize,
+    journal: Option<CommandJournal>,
+}
+
+/// The command log and the seen-command cache it shares with the IPC server.
+#[derive(Clone)]
+struct CommandJournal {
+    log: Arc<Mutex<CommandLog>>,
+    seen: Arc<Mutex<SeenCommandCache>>,
+}
+
+impl RmmDispatcher {
+    pub fn from_env(policy: PolicyBundle, metrics: MetricsHandle, queue_dir: PathBuf) -> Self {
+        Self {
+            policy: Arc::new(policy),
+            route: CommandRouteConfig::from_env(),
+            scheduler: Arc::new(ExecutionScheduler::from_env(metrics)),
+            script_config: ScriptExecutorConfig::from_env(),
+            file_place_config: FilePlaceConfig::from_env(),
+            patch_backend: PackageManager::from_env(),
+            signer: result_signer_from_env(),
+            queue_dir,
+            max_output_bytes: RmmConfig::from_env().max_output_bytes,
+            journal: None,
+        }
+    }
+
+    /// Record every command's transitions in `log`. Commands claimed later are accepted
+    /// through it, so one the log or `seen` already holds is not run twice.
+    pub fn with_command_log(mut self, log: CommandLog, seen: Arc<Mutex<SeenCommandCache>>) -> Self {
+        self.journal = Some(CommandJournal {
+            log: Arc::new(Mutex::new(log)),
+            seen,
+        });
+        self
+    }
+
+    /// Log `request` as accepted; false for a re-delivered command, which is dropped.
+    pub fn accept(&self, request: &ExecutionRequest) -> bool {
+        let Some(journal) = &self.journal else { return true };
+        let mut log = journal.log.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut seen = journal.seen.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        match log.accept(request, &mut seen, unix_time_ms()) {
+            Ok(true) => true,
+            Ok(false) => {
+                info!(command_id = %request.command_id, "dropping re-delivered rmm command");
+                false
+            }
+            Err(err) => {
+                warn!(error = %err, command_id = %request.command_id, "failed to log accepted rmm command");
+                true
+            }
+        }
+    }
+
+    /// Drop finished commands whose validity window has closed from the command log.
+    pub fn compact_log(&self) {
+        let Some(journal) = &self.journal else { return };
+        let mut log = journal.log.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Err(err) = log.compact(unix_time_ms()) {
+            warn!(error = %err, "rmm command log compaction failed");
+        }
+    }
+
+    fn log_transition(&self, command_id: &str, transition: impl FnOnce(&mut CommandLog) -> Result<(), String>) {
+        let Some(journal) = &self.journal else { return };
+        let mut log = journal.log.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Err(err) = transition(&mut log) {
+            warn!(error = %err, command_id = %command_id, "failed to log rmm command transition");
+        }
+    }
+
+    /// Wait for a start, run `request` and queue its outcome, logging each transition.
+    /// Deferred commands are offered again every `DEFERRED_RETRY` until they start or expire.
+    pub async fn run(&self, request: ExecutionRequest, cancel: CancelSignal) -> ExecutionOutcome {
+        let outcome = loop {
+            let admitted = tokio::select! {
+                admitted = self.scheduler.admit(&request, &self.policy) => admitted,
+                _ = cancel.cancelled() => break ExecutionOutcome::cancelled(&request, unix_time_ms()),
+            };
+            match admitted {
+                Ok(_permit) => {
+                    audit::record(AuditEvent::ExecutionAccepted {
+                        command_id: request.command_id.clone(),
+                        action: request.action.clone(),
+                    });
+                    self.log_transition(&request.command_id, |log| log.mark_executing(&request.command_id, unix_time_ms()));
+                    break self.execute(&request, &cancel).await;
+                }
+                Err(record) => match record.decision {
+                    ScheduleDecision::Deferred { .. } => tokio::select! {
+                        _ = tokio::time::sleep(DEFERRED_RETRY) => {}
+                        _ = cancel.cancelled() => break ExecutionOutcome::cancelled(&request, unix_time_ms()),
+                    },
+                    ScheduleDecision::Expired { reason } => break ExecutionOutcome::expired(&request, &reason, unix_time_ms()),
+                    ScheduleDecision::Started => unreachable!("admit only refuses with deferred or expired"),
+                },
+            }
+        };
+        if matches!(outcome.termination, Termination::Rejected | Termination::Expired) {
+            audit::record(AuditEvent::CommandRejected {
+                command_id: outcome.command_id.clone(),
+                reason: outcome.stderr.clone(),
+            });
+        }
+        self.log_transition(&outcome.command_id, |log| log.mark_finished(&outcome, unix_time_ms()));
+        self.report(&outcome).await;
+        outcome
+    }
+
+    /// Apply the cancel requests in `queue`. Commands removed before they were claimed never
+    /// reach `run`, so their cancelled outcomes are queued here.
+    pub async fn apply_cancellations(&self, queue: &RmmCommandQueue, registry: &CommandRegistry) {
+        for ack in queue.apply_cancellations(registry, unix_time_ms()) {
+            if let Some(outcome) = ack.outcome {
+                self.report(&outcome).await;
+            }
+        }
+    }
+
+    async fn report(&self, outcome: &ExecutionOutcome) {
+        let signer = self.signer.as_ref().map(|signer| signer as &dyn Signer);
+        if let Err(err) = record_outcome(outcome, &self.policy, signer, &self.queue_dir).await {
+            warn!(error = %err, command_id = %outcome.command_id, "failed to queue rmm command outcome");
+        }
+    }
+
+    async fn execute(&self, request: &ExecutionRequest, cancel: &CancelSignal) -> ExecutionOutcome {
+        // Checked here as well as in each executor, so an action without one is covered too.
+        if let Err(reason) = verify_command_signature(&request.signed_command(), &self.route) {
+            return ExecutionOutcome::rejected(request, &reason, unix_time_ms());
+        }
+        match request.action.as_str() {
+            SCRIPT_RUN_ACTION => match run_script(request, &self.policy, &self.route, &self.script_config, cancel, unix_time_ms()).await {
+                Ok(outcome) => outcome,
+                Err(err) => ExecutionOutcome::rejected(request, &err.to_string(), unix_time_ms()),
+            },
+            PATCH_APPLY_ACTION => self.apply_patch(request).await,
+            FILE_PLACE_ACTION => self.place(request).await,
+            action => ExecutionOutcome::rejected(request, &format!("no executor for action {}", action), unix_time_ms()),
+        }
+    }
+
+    /// Package managers are driven through blocking process calls, so the job runs on the
+    /// blocking pool. Its report also goes to the patch results endpoint.
+    async fn apply_patch(&self, request: &ExecutionRequest) -> ExecutionOutcome {
+        let started_at_unix_ms = unix_time_ms();
+        let job_request = request.clone();
+        let policy = Arc::clone(&self.policy);
+        let route = self.route.clone();
+        let backend = self.patch_backend;
+        let report = match tokio::task::spawn_blocking(move || run_patch_job(&job_request, &policy, &route, &backend, unix_time_ms())).await {
+            Ok(Ok(report)) => report,
+            Ok(Err(err)) => return ExecutionOutcome::rejected(request, &err.to_string(), unix_time_ms()),
+            Err(err) => return ExecutionOutcome::rejected(request, &format!("patch job did not complete: {}", err), unix_time_ms()),
+        };
+        if let Err(err) = record_patch_report(&report, &self.queue_dir).await {
+            warn!(error = %err, command_id = %request.command_id, "failed to queue patch report");
+        }
+        let report_json = serde_json::to_string(&report).unwrap_or_default();
+        ExecutionOutcome::reported(
+            request,
+            started_at_unix_ms,
+            unix_time_ms(),
+            &report_json,
+            report.verification_status != "failed",
+            self.max_output_bytes,
+        )
+    }
+
+    async fn place(&self, request: &ExecutionRequest) -> ExecutionOutcome {
+        let started_at_unix_ms = unix_time_ms();
+        match place_file(request, &self.policy, &self.route, &self.file_place_config, &StdPlacementFs, started_at_unix_ms).await {
+            Ok(report) => ExecutionOutcome::reported(
+                request,
+                started_at_unix_ms,
+                unix_time_ms(),
+                &serde_json::to_string(&report).unwrap_or_default(),
+                true,
+                self.max_output_bytes,
+            ),
+            Err(err) => ExecutionOutcome::rejected(request, &err.to_string(), unix_time_ms()),
+        }
+    }
+}
+
+/// Run `pending` and every command later claimed from `queue`, polling it every
+/// `RMM_POLL_INTERVAL_SECS`. Cancel requests are applied on each poll, before new commands
+/// are claimed, and the command log is compacted after it. On shutdown, commands still waiting or running are dropped; their child
+/// processes are killed with them.
+pub async fn run_rmm_loop(
+    dispatcher: RmmDispatcher,
+    pending: Vec<ExecutionRequest>,
+    queue: Option<RmmCommandQueue>,
+    shutdown: CancellationToken,
+) {
+    let registry = CommandRegistry::default();
+    let mut running = JoinSet::new();
+    for request in pending {
+        spawn_command(&mut running, &dispatcher, &registry, request);
+    }
+    let mut poll = tokio::time::interval(Duration::from_secs(RmmConfig::from_env().poll_interval_secs));
+    poll.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    info!(queued = running.len(), "rmm dispatcher started");
+
+    loop {
+        tokio::select! {
+            _ = shutdown.cancelled() => break,
+            Some(joined) = running.join_next() => match joined {
+                Ok(outcome) => info!(
+                    command_id = %outcome.command_id,
+                    action = %outcome.action,
+                    termination = ?outcome.termination,
+                    success = outcome.success,
+                    "rmm command finished"
+                ),
+                Err(err) => warn!(error = %err, "rmm command task failed"),
+            },
+            _ = poll.tick() => {
+                let Some(queue) = &queue else { continue };
+                dispatcher.apply_cancellations(queue, &registry).await;
+                for request in queue.load(&dispatcher.policy, &dispatcher.route, unix_time_ms()) {
+                    if dispatcher.accept(&request) {
+                        spawn_command(&mut running, &dispatcher, &registry, request);
+                    }
+                }
+                dispatcher.compact_log();
+            }
+        }
+    }
+    running.shutdown().await;
+    info!("rmm dispatcher stopped");
+}
+
+/// Register `request` so cancel requests can reach it, and run it until it finishes.
+fn spawn_command(
+    running: &mut JoinSet<ExecutionOutcome>,
+    dispatcher: &RmmDispatcher,
+    registry: &CommandRegistry,
+    request: ExecutionRequest,
+) {
+    let dispatcher = dispatcher.clone();
+    let registry = registry.clone();
+    let cancel = registry.register(&request.command_id);
+    running.spawn(async move {
+        let outcome = dispatcher.run(request, cancel).await;
+        registry.finish(&outcome.command_id);
+        outcome
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::path::PathBuf;
+    use std::sync::{Arc, Mutex};
+
+    use super::RmmDispatcher;
+    use crate::command_log::{CommandLog, CommandStatus};
+    use crate::command_router::{command_signing_message, CommandRouteConfig};
+    use crate::crypto::{HmacSha256, Signer};
+    use crate::executor::ScriptExecutorConfig;
+    use crate::file_place::FilePlaceConfig;
+    use crate::metrics::AgentMetrics;
+    use crate::patch::PackageManager;
+    use crate::policy::{ArgumentRules, PolicyBundle};
+    use crate::rmm::{CancelSignal, CommandRegistry, ExecutionRequest, ExecutionScheduler, SystemClock, Termination};
+    use crate::seen_commands::SeenCommandCache;
+    use crate::time::unix_time_ms;
+
+    const KEY: &[u8] = b"unit-test-command-key";
+
+    fn build_dispatcher(label: &str) -> (RmmDispatcher, PathBuf) {
+        let root = std::env::temp_dir().join(format!("rmm-dispatch-{}-{}-{}", label, std::process::id(), unix_time_ms()));
+        let mut policy = PolicyBundle::placeholder();
+        policy.execution.argument_rules = BTreeMap::from([(
+            "script-run".to_string(),
+            ArgumentRules {
+                allow_shell_metacharacters: true,
+                ..ArgumentRules::default()
+            },
+        )]);
+        let dispatcher = RmmDispatcher {
+            policy: Arc::new(policy),
+            route: CommandRouteConfig::from_env().with_verifier(Arc::new(HmacSha256::new(KEY))),
+            scheduler: Arc::new(ExecutionScheduler::new(None, 1, Arc::new(SystemClock), AgentMetrics::new_handle())),
+            script_config: ScriptExecutorConfig {
+                interpreter: Some(PathBuf::from("/bin/sh")),
+                working_dir: root.join("work"),
+                timeout_ms: 10_000,
+                max_output_bytes: 1024,
+                env_allowlist: vec!["PATH".to_string()],
+                run_as_uid: None,
+                run_as_gid: None,
+                cancel_grace_ms: 200,
+            },
+            file_place_config: FilePlaceConfig {
+                max_bytes: 1024,
+                download_timeout_ms: 1_000,
+            },
+            patch_backend: PackageManager::AptGet,
+            signer: None,
+            queue_dir: root.join("queue"),
+            max_output_bytes: 1024,
+            journal: None,
+        };
+        (dispatcher, root)
+    }
+
+    fn build_request(action: &str, arguments: &[&str]) -> ExecutionRequest {
+        let mut request = ExecutionRequest {
+            command_id: format!("cmd-{}", action),
+            signed_payload: "signed".to_string(),
+            signature: String::new(),
+            action: action.to_string(),
+            arguments: arguments.iter().map(|argument| argument.to_string()).collect(),
+            requested_at_unix_ms: 0,
+            expires_at_unix_ms: unix_time_ms() + 3_600_000,
+            earliest_start_unix_ms: None,
+            latest_start_unix_ms: None,
+            source: "test".to_string(),
+        };
+        request.signature = HmacSha256::new(KEY).sign(command_signing_message(&request.signed_command()).as_bytes());
+        request
+    }
+
+    fn queued_items(root: &std::path::Path) -> usize {
+        std::fs::read_dir(root.join("queue")).map(|entries| entries.count()).unwrap_or(0)
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn runs_admitted_script_and_queues_its_outcome() {
+        let (dispatcher, root) = build_dispatcher("script");
+        let outcome = dispatcher
+            .run(build_request("script-run", &["-c", "echo done"]), CancelSignal::default())
+            .await;
+
+        assert!(outcome.success, "{:?}", outcome);
+        assert_eq!(outcome.stdout, "done\n");
+        assert_eq!(queued_items(&root), 1);
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn logs_transitions_and_drops_re_delivered_commands() {
+        let (dispatcher, root) = build_dispatcher("journal");
+        let log = CommandLog::open(&root.join("commands.log")).expect("open command log");
+        let dispatcher = dispatcher.with_command_log(log, Arc::new(Mutex::new(SeenCommandCache::new(16, 0))));
+        let request = build_request("script-run", &["-c", "exit 3"]);
+
+        assert!(dispatcher.accept(&request));
+        assert!(!dispatcher.accept(&request));
+        dispatcher.run(request, CancelSignal::default()).await;
+
+        let reopened = CommandLog::open(&root.join("commands.log")).expect("reopen command log");
+        let logged = reopened.get("cmd-script-run").expect("logged command");
+        assert_eq!(logged.status, CommandStatus::Failed);
+        assert_eq!(logged.reason.as_deref(), Some("exited with code 3"));
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn cancel_request_reaches_a_running_command() {
+        let (dispatcher, root) = build_dispatcher("cancel");
+        let registry = CommandRegistry::default();
+        let request = build_request("script-run", &["-c", "sleep 5"]);
+        let cancel = registry.register(&request.command_id);
+        let running = tokio::spawn(async move { dispatcher.run(request, cancel).await });
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+        assert!(registry.cancel("cmd-script-run"));
+        let outcome = running.await.expect("join dispatcher");
+        assert_eq!(outcome.termination, Termination::Cancelled);
+        assert_eq!(queued_items(&root), 1);
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn never_executes_unsigned_or_wrongly_signed_commands() {
+        let (dispatcher, root) = build_dispatcher("unsigned");
+        std::fs::create_dir_all(&root).expect("create root");
+        let marker = root.join("executed");
+        let script = format!("touch {}", marker.display());
+
+        let mut unsigned = build_request("script-run", &["-c", &script]);
+        unsigned.signature.clear();
+        let mut forged = build_request("script-run", &["-c", &script]);
+        forged.signature = HmacSha256::new(b"other-key").sign(command_signing_message(&forged.signed_command()).as_bytes());
+        let mut keyless = dispatcher.clone();
+        keyless.route.verifier = None;
+
+        for (dispatcher, request) in [
+            (&dispatcher, unsigned),
+            (&dispatcher, forged),
+            (&keyless, build_request("script-run", &["-c", &script])),
+        ] {
+            let outcome = dispatcher.run(request, CancelSignal::default()).await;
+            assert_eq!(outcome.termination, Termination::Rejected);
+            assert!(outcome.stderr.contains("signing key") || outcome.stderr.contains("signature"), "{}", outcome.stderr);
+        }
+        assert!(!marker.exists());
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn reports_expired_and_unsupported_commands() {
+        let (dispatcher, root) = build_dispatcher("refused");
+        let mut late = build_request("script-run", &["-c", "true"]);
+        late.latest_start_unix_ms = Some(1);
+        let expired = dispatcher.run(late, CancelSignal::default()).await;
+        assert_eq!(expired.termination, Termination::Expired);
+        assert!(!expired.success);
+
+        let unsupported = dispatcher.run(build_request("reboot", &[]), CancelSignal::default()).await;
+        assert_eq!(unsupported.termination, Termination::Rejected);
+        assert_eq!(unsupported.stderr, "no executor for action reboot");
+        assert_eq!(queued_items(&root), 2);
+        let _ = std::fs::remove_dir_all(root);
+    }
+}
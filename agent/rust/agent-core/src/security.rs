@@ -18,3 +18,43 @@ impl ValidationLimits {
 pub fn validate_bounded_string(value: &str, max_len: usize) -> bool {
     !value.is_empty() && value.len() <= max_len
 }
+
+/// Classes of risky content an execution argument can carry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgumentHazard {
+    ShellMetacharacter,
+    EnvExpansion,
+    PathTraversal,
+}
+
+const SHELL_METACHARACTERS: [char; 8] = [';', '|', '&', '`', '<', '>', '\n', '\r'];
+
+/// Scan an argument for shell metacharacters (including `$(` substitution), environment
+/// variable expansion (`$VAR`, `${VAR}`, `%VAR%`, `!VAR!`), and `..` path components.
+pub fn argument_hazards(value: &str) -> Vec<ArgumentHazard> {
+    let mut hazards = Vec::new();
+    if value.contains(SHELL_METACHARACTERS) || value.contains("$(") {
+        hazards.push(ArgumentHazard::ShellMetacharacter);
+    }
+    if has_env_expansion(value) {
+        hazards.push(ArgumentHazard::EnvExpansion);
+    }
+    if value
+        .split(['/', '\\', '=', ':'])
+        .any(|component| component.trim() == "..")
+    {
+        hazards.push(ArgumentHazard::PathTraversal);
+    }
+    hazards
+}
+
+fn has_env_expansion(value: &str) -> bool {
+    let bytes = value.as_bytes();
+    let unix_style = bytes.windows(2).any(|pair| {
+        pair[0] == b'$' && (pair[1] == b'{' || pair[1] == b'_' || pair[1].is_ascii_alphabetic())
+    });
+    // cmd.exe pairs `%` (and `!` under delayed expansion) across the whole line, so any two
+    // of them can enclose a variable name; a single one, as in `50%`, cannot.
+    let windows_style = value.matches('%').count() >= 2 || value.matches('!').count() >= 2;
+    unix_style || windows_style
+}
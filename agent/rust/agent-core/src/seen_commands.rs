@@ -0,0 +1,166 @@
+use std::collections::{BTreeSet, HashMap};
+use std::env;
+use std::fmt;
+
+use crate::time::clock_skew_tolerance_ms_from_env;
+
+/// Default for `AGENT_SEEN_COMMANDS_MAX_TTL_MS`: one day.
+const DEFAULT_MAX_TTL_MS: u64 = 24 * 60 * 60 * 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeenCommandRejection {
+    /// The command id was already accepted and its validity window has not closed.
+    Replayed,
+    /// Every slot holds a command that is still valid; new commands are refused rather than
+    /// forgetting one that could then be replayed.
+    Full,
+    /// The command stays valid for longer than the cache keeps ids, so a replay after its
+    /// entry is dropped could not be refused.
+    ValidityTooLong,
+}
+
+impl fmt::Display for SeenCommandRejection {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Replayed => write!(formatter, "command id already seen"),
+            Self::Full => write!(formatter, "seen-command cache is full"),
+            Self::ValidityTooLong => write!(formatter, "command validity exceeds the seen-command retention"),
+        }
+    }
+}
+
+/// Command ids accepted in this process, each kept until its `not_after` (plus clock-skew
+/// tolerance) has passed so a replay inside the validity window is refused. Retention is
+/// capped at `max_ttl_ms`, and commands valid for longer are refused outright.
+#[derive(Debug, Clone)]
+pub struct SeenCommandCache {
+    capacity: usize,
+    tolerance_ms: u64,
+    max_ttl_ms: u64,
+    expiries: HashMap<String, u64>,
+    by_expiry: BTreeSet<(u64, String)>,
+}
+
+impl SeenCommandCache {
+    pub fn new(capacity: usize, tolerance_ms: u64) -> Self {
+        Self {
+            capacity,
+            tolerance_ms,
+            max_ttl_ms: DEFAULT_MAX_TTL_MS,
+            expiries: HashMap::new(),
+            by_expiry: BTreeSet::new(),
+        }
+    }
+
+    pub fn from_env() -> Self {
+        let capacity = env::var("AGENT_SEEN_COMMANDS_CAPACITY")
+            .ok()
+            .and_then(|value| value.parse::<usize>().ok())
+            .filter(|value| *value > 0)
+            .unwrap_or(4096);
+        let max_ttl_ms = env::var("AGENT_SEEN_COMMANDS_MAX_TTL_MS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .filter(|value| *value > 0)
+            .unwrap_or(DEFAULT_MAX_TTL_MS);
+        Self::new(capacity, clock_skew_tolerance_ms_from_env()).with_max_ttl_ms(max_ttl_ms)
+    }
+
+    pub fn with_max_ttl_ms(mut self, max_ttl_ms: u64) -> Self {
+        self.max_ttl_ms = max_ttl_ms;
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.expiries.len()
+    }
+
+    pub fn contains(&self, command_id: &str, now_unix_ms: u64) -> bool {
+        self.expiries
+            .get(command_id)
+            .map(|expiry| *expiry >= now_unix_ms)
+            .unwrap_or(false)
+    }
+
+    /// Reject `command_id` if it is already live, or if `not_after_unix_ms` lies beyond the
+    /// retention cap; nothing is recorded.
+    pub fn check(&mut self, command_id: &str, not_after_unix_ms: u64, now_unix_ms: u64) -> Result<(), SeenCommandRejection> {
+        self.evict_expired(now_unix_ms);
+        if self.expiries.contains_key(command_id) {
+            return Err(SeenCommandRejection::Replayed);
+        }
+        if not_after_unix_ms > now_unix_ms.saturating_add(self.max_ttl_ms) {
+            return Err(SeenCommandRejection::ValidityTooLong);
+        }
+        if self.expiries.len() >= self.capacity {
+            return Err(SeenCommandRejection::Full);
+        }
+        Ok(())
+    }
+
+    /// Record an accepted command until `not_after_unix_ms` plus the skew tolerance, and no
+    /// longer than the retention cap.
+    pub fn insert(&mut self, command_id: &str, not_after_unix_ms: u64, now_unix_ms: u64) -> Result<(), SeenCommandRejection> {
+        self.check(command_id, not_after_unix_ms, now_unix_ms)?;
+        let expiry = not_after_unix_ms
+            .min(now_unix_ms.saturating_add(self.max_ttl_ms))
+            .saturating_add(self.tolerance_ms);
+        self.expiries.insert(command_id.to_string(), expiry);
+        self.by_expiry.insert((expiry, command_id.to_string()));
+        Ok(())
+    }
+
+    fn evict_expired(&mut self, now_unix_ms: u64) {
+        while let Some((expiry, command_id)) = self.by_expiry.first().cloned() {
+            if expiry >= now_unix_ms {
+                break;
+            }
+            self.by_expiry.pop_first();
+            self.expiries.remove(&command_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SeenCommandCache, SeenCommandRejection};
+
+    #[test]
+    fn rejects_replay_until_window_closes() {
+        let mut cache = SeenCommandCache::new(8, 500);
+        assert_eq!(cache.insert("cmd-1", 10_000, 1_000), Ok(()));
+        assert_eq!(cache.check("cmd-1", 10_000, 5_000), Err(SeenCommandRejection::Replayed));
+        assert_eq!(cache.insert("cmd-1", 20_000, 10_400), Err(SeenCommandRejection::Replayed));
+        assert!(cache.contains("cmd-1", 10_500));
+
+        assert_eq!(cache.check("cmd-1", 20_000, 10_501), Ok(()));
+        assert_eq!(cache.len(), 0);
+    }
+
+    #[test]
+    fn evicts_expired_entries_and_refuses_when_full_of_live_ones() {
+        let mut cache = SeenCommandCache::new(2, 0);
+        cache.insert("cmd-short", 2_000, 1_000).expect("insert");
+        cache.insert("cmd-long", 50_000, 1_000).expect("insert");
+        assert_eq!(cache.insert("cmd-new", 60_000, 1_500), Err(SeenCommandRejection::Full));
+
+        assert_eq!(cache.insert("cmd-new", 60_000, 2_001), Ok(()));
+        assert_eq!(cache.len(), 2);
+        assert!(!cache.contains("cmd-short", 2_001));
+        assert!(cache.contains("cmd-long", 2_001));
+    }
+
+    #[test]
+    fn refuses_commands_valid_beyond_the_retention_cap() {
+        let mut cache = SeenCommandCache::new(8, 0).with_max_ttl_ms(60_000);
+        assert_eq!(
+            cache.insert("cmd-far", u64::MAX, 1_000),
+            Err(SeenCommandRejection::ValidityTooLong)
+        );
+        assert_eq!(cache.len(), 0);
+
+        cache.insert("cmd-edge", 61_000, 1_000).expect("insert at the cap");
+        assert!(cache.contains("cmd-edge", 61_000));
+        assert_eq!(cache.check("cmd-edge", 61_000, 61_001), Ok(()));
+    }
+}
@@ -0,0 +1,316 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::Serialize;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use crate::pipeline::ComponentHealth;
+use crate::time::unix_time_ms;
+use crate::uplink::pending_item_count;
+
+/// Thresholds the agent holds its own footprint to. A breach marks the `resources`
+/// component degraded; it never stops the agent.
+#[derive(Debug, Clone)]
+pub struct SelfMonitorConfig {
+    pub interval_secs: u64,
+    pub max_rss_bytes: u64,
+    pub max_open_handles: u64,
+    pub max_queue_depth: usize,
+    pub max_buffer_bytes: u64,
+}
+
+impl SelfMonitorConfig {
+    pub fn from_env() -> Self {
+        Self {
+            interval_secs: env_u64("AGENT_SELF_MONITOR_INTERVAL_SECS").unwrap_or(30),
+            max_rss_bytes: env_u64("AGENT_MAX_RSS_BYTES").unwrap_or(512 * 1024 * 1024),
+            max_open_handles: env_u64("AGENT_MAX_OPEN_HANDLES").unwrap_or(1024),
+            max_queue_depth: env_u64("AGENT_MAX_QUEUE_DEPTH").unwrap_or(5_000) as usize,
+            max_buffer_bytes: env_u64("AGENT_MAX_TELEMETRY_BUFFER_BYTES").unwrap_or(48 * 1024 * 1024),
+        }
+    }
+}
+
+fn env_u64(name: &str) -> Option<u64> {
+    env::var(name)
+        .ok()
+        .and_then(|value| value.parse::<u64>().ok())
+        .filter(|value| *value > 0)
+}
+
+/// Per-process figures the platform can report. `None` when the platform has no cheap way
+/// to read them; unknown values are never counted as breaches.
+pub trait ProcessSampler: Send + Sync {
+    fn rss_bytes(&self) -> Option<u64>;
+    fn open_handles(&self) -> Option<u64>;
+}
+
+/// Reads procfs on Linux and the process memory/handle counters on Windows.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PlatformSampler;
+
+#[cfg(target_os = "linux")]
+impl ProcessSampler for PlatformSampler {
+    fn rss_bytes(&self) -> Option<u64> {
+        let status = fs::read_to_string("/proc/self/status").ok()?;
+        let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+        let kib = line.split_whitespace().nth(1)?.parse::<u64>().ok()?;
+        Some(kib.saturating_mul(1024))
+    }
+
+    fn open_handles(&self) -> Option<u64> {
+        Some(fs::read_dir("/proc/self/fd").ok()?.count() as u64)
+    }
+}
+
+#[cfg(windows)]
+impl ProcessSampler for PlatformSampler {
+    fn rss_bytes(&self) -> Option<u64> {
+        use windows_sys::Win32::System::ProcessStatus::{GetProcessMemoryInfo, PROCESS_MEMORY_COUNTERS};
+        use windows_sys::Win32::System::Threading::GetCurrentProcess;
+
+        let mut counters: PROCESS_MEMORY_COUNTERS = unsafe { std::mem::zeroed() };
+        let size = std::mem::size_of::<PROCESS_MEMORY_COUNTERS>() as u32;
+        // SAFETY: the pseudo-handle needs no closing and `counters` is sized for the call.
+        let ok = unsafe { GetProcessMemoryInfo(GetCurrentProcess(), &mut counters, size) };
+        (ok != 0).then_some(counters.WorkingSetSize as u64)
+    }
+
+    fn open_handles(&self) -> Option<u64> {
+        use windows_sys::Win32::System::Threading::{GetCurrentProcess, GetProcessHandleCount};
+
+        let mut count = 0u32;
+        // SAFETY: the pseudo-handle needs no closing and `count` outlives the call.
+        let ok = unsafe { GetProcessHandleCount(GetCurrentProcess(), &mut count) };
+        (ok != 0).then_some(u64::from(count))
+    }
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+impl ProcessSampler for PlatformSampler {
+    fn rss_bytes(&self) -> Option<u64> {
+        None
+    }
+
+    fn open_handles(&self) -> Option<u64> {
+        None
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct ResourceSample {
+    pub rss_bytes: Option<u64>,
+    pub open_handles: Option<u64>,
+    pub uplink_queue_depth: usize,
+    pub telemetry_buffer_bytes: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ResourceBreach {
+    pub metric: &'static str,
+    pub value: u64,
+    pub threshold: u64,
+}
+
+/// Latest self-monitoring verdict, shared with the pipeline health check and the heartbeat.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct DegradationState {
+    pub sample: ResourceSample,
+    pub breaches: Vec<ResourceBreach>,
+    pub sampled_at_unix_ms: u64,
+}
+
+pub type SharedDegradation = Arc<Mutex<DegradationState>>;
+
+impl DegradationState {
+    pub fn is_degraded(&self) -> bool {
+        !self.breaches.is_empty()
+    }
+
+    pub fn health(&self) -> ComponentHealth {
+        if !self.is_degraded() {
+            return ComponentHealth::ready();
+        }
+        let reasons = self
+            .breaches
+            .iter()
+            .map(|breach| format!("{} {} over {}", breach.metric, breach.value, breach.threshold))
+            .collect::<Vec<String>>();
+        ComponentHealth::degraded(reasons.join("; "))
+    }
+
+    fn breaches(&self, metric: &str) -> bool {
+        self.breaches.iter().any(|breach| breach.metric == metric)
+    }
+}
+
+pub fn evaluate(sample: ResourceSample, config: &SelfMonitorConfig, now_unix_ms: u64) -> DegradationState {
+    let checks = [
+        ("rss_bytes", sample.rss_bytes, config.max_rss_bytes),
+        ("open_handles", sample.open_handles, config.max_open_handles),
+        ("uplink_queue_depth", Some(sample.uplink_queue_depth as u64), config.max_queue_depth as u64),
+        ("telemetry_buffer_bytes", Some(sample.telemetry_buffer_bytes), config.max_buffer_bytes),
+    ];
+    let breaches = checks
+        .into_iter()
+        .filter_map(|(metric, value, threshold)| {
+            value
+                .filter(|value| *value > threshold)
+                .map(|value| ResourceBreach { metric, value, threshold })
+        })
+        .collect();
+    DegradationState {
+        sample,
+        breaches,
+        sampled_at_unix_ms: now_unix_ms,
+    }
+}
+
+pub fn sample(sampler: &dyn ProcessSampler, queue_dir: &Path, buffer_dir: &Path) -> ResourceSample {
+    ResourceSample {
+        rss_bytes: sampler.rss_bytes(),
+        open_handles: sampler.open_handles(),
+        uplink_queue_depth: pending_item_count(queue_dir),
+        telemetry_buffer_bytes: dir_bytes(buffer_dir),
+    }
+}
+
+fn dir_bytes(dir: &Path) -> u64 {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return 0;
+    };
+    entries
+        .flatten()
+        .filter_map(|entry| entry.metadata().ok())
+        .filter(|metadata| metadata.is_file())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+/// Take one sample, publish it and log each metric that starts or stops breaching. The
+/// warnings are picked up by self-telemetry like any other agent log.
+pub fn record_sample(state: &SharedDegradation, next: DegradationState) {
+    let Ok(mut current) = state.lock() else {
+        return;
+    };
+    for breach in &next.breaches {
+        if !current.breaches(breach.metric) {
+            warn!(
+                metric = breach.metric,
+                value = breach.value,
+                threshold = breach.threshold,
+                "agent resource threshold breached"
+            );
+        }
+    }
+    for breach in &current.breaches {
+        if !next.breaches(breach.metric) {
+            info!(metric = breach.metric, "agent resource back under threshold");
+        }
+    }
+    *current = next;
+}
+
+pub async fn run_self_monitor(
+    config: SelfMonitorConfig,
+    sampler: Box<dyn ProcessSampler>,
+    queue_dir: PathBuf,
+    buffer_dir: PathBuf,
+    state: SharedDegradation,
+    token: CancellationToken,
+) {
+    loop {
+        let current = sample(sampler.as_ref(), &queue_dir, &buffer_dir);
+        record_sample(&state, evaluate(current, &config, unix_time_ms()));
+        tokio::select! {
+            _ = token.cancelled() => break,
+            _ = tokio::time::sleep(Duration::from_secs(config.interval_secs)) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::sync::{Arc, Mutex};
+
+    use super::{evaluate, record_sample, sample, DegradationState, ProcessSampler, SelfMonitorConfig};
+    use crate::pipeline::HealthState;
+    use crate::time::unix_time_ms;
+
+    struct FixtureSampler {
+        rss_bytes: Option<u64>,
+        open_handles: Option<u64>,
+    }
+
+    impl ProcessSampler for FixtureSampler {
+        fn rss_bytes(&self) -> Option<u64> {
+            self.rss_bytes
+        }
+
+        fn open_handles(&self) -> Option<u64> {
+            self.open_handles
+        }
+    }
+
+    fn config() -> SelfMonitorConfig {
+        SelfMonitorConfig {
+            interval_secs: 30,
+            max_rss_bytes: 1_000,
+            max_open_handles: 10,
+            max_queue_depth: 1,
+            max_buffer_bytes: 8,
+        }
+    }
+
+    #[test]
+    fn flags_each_metric_over_its_threshold() {
+        let dir = std::env::temp_dir().join(format!("self-monitor-{}-{}", std::process::id(), unix_time_ms()));
+        let queue_dir = dir.join("queue");
+        let buffer_dir = dir.join("buffer");
+        fs::create_dir_all(&queue_dir).expect("create queue dir");
+        fs::create_dir_all(&buffer_dir).expect("create buffer dir");
+        fs::write(queue_dir.join("a.json"), "{}").expect("write queue item");
+        fs::write(queue_dir.join("b.json"), "{}").expect("write queue item");
+        fs::write(buffer_dir.join("batch.json"), "0123456789").expect("write buffered batch");
+
+        let sampler = FixtureSampler {
+            rss_bytes: Some(2_000),
+            open_handles: None,
+        };
+        let state = evaluate(sample(&sampler, &queue_dir, &buffer_dir), &config(), 1_000);
+
+        let metrics = state.breaches.iter().map(|breach| breach.metric).collect::<Vec<_>>();
+        assert_eq!(metrics, vec!["rss_bytes", "uplink_queue_depth", "telemetry_buffer_bytes"]);
+        let health = state.health();
+        assert_eq!(health.state, HealthState::Degraded);
+        assert!(health.reason.as_deref().is_some_and(|reason| reason.contains("rss_bytes 2000 over 1000")));
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn recovers_once_samples_fall_back_under_thresholds() {
+        let missing = std::env::temp_dir().join("self-monitor-missing-dir");
+        let shared = Arc::new(Mutex::new(DegradationState::default()));
+        let busy = FixtureSampler {
+            rss_bytes: Some(500),
+            open_handles: Some(50),
+        };
+        record_sample(&shared, evaluate(sample(&busy, &missing, &missing), &config(), 1_000));
+        assert!(shared.lock().expect("state").is_degraded());
+
+        let idle = FixtureSampler {
+            rss_bytes: Some(500),
+            open_handles: Some(5),
+        };
+        record_sample(&shared, evaluate(sample(&idle, &missing, &missing), &config(), 2_000));
+        let state = shared.lock().expect("state").clone();
+        assert!(!state.is_degraded());
+        assert_eq!(state.health().state, HealthState::Ready);
+        assert_eq!(state.sampled_at_unix_ms, 2_000);
+    }
+}
@@ -0,0 +1,280 @@
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::env;
+use std::fmt::{self, Write as _};
+use std::sync::{Arc, Mutex};
+
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::Layer;
+
+use crate::rate_limit::KeyedRateLimiter;
+use crate::siem::{prepare_telemetry_batch_from_events, TelemetryBatch, TelemetryConfig, TelemetryEvent, TelemetryField, TelemetrySeverity};
+use crate::time::{monotonic_ms, unix_time_ms};
+
+const SELF_TELEMETRY_STREAM: &str = "agent";
+const OWN_TARGET_PREFIXES: [&str; 3] = ["agent_core", "agent_watchdog", "agent_logging"];
+const MAX_CAPTURED_FIELDS: usize = 8;
+
+thread_local! {
+    static CAPTURING: Cell<bool> = const { Cell::new(false) };
+}
+
+#[derive(Debug, Clone)]
+pub struct SelfTelemetryConfig {
+    pub max_events_per_minute: u64,
+    pub max_pending_events: usize,
+}
+
+impl SelfTelemetryConfig {
+    pub fn from_env() -> Self {
+        let max_events_per_minute = env::var("AGENT_SELF_TELEMETRY_MAX_PER_MINUTE")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .unwrap_or(30);
+        let max_pending_events = env::var("AGENT_SELF_TELEMETRY_MAX_PENDING")
+            .ok()
+            .and_then(|value| value.parse::<usize>().ok())
+            .unwrap_or(256);
+        Self {
+            max_events_per_minute,
+            max_pending_events,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct SinkState {
+    config: SelfTelemetryConfig,
+    limiter: KeyedRateLimiter,
+    pending: VecDeque<TelemetryEvent>,
+    sequence: u64,
+    suppressed: u64,
+}
+
+/// Shared queue of captured agent warnings and errors, drained into telemetry batches by the
+/// main loop.
+#[derive(Debug, Clone)]
+pub struct SelfTelemetrySink {
+    state: Arc<Mutex<SinkState>>,
+}
+
+impl SelfTelemetrySink {
+    pub fn new(config: SelfTelemetryConfig) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(SinkState {
+                config,
+                limiter: KeyedRateLimiter::new(60_000),
+                pending: VecDeque::new(),
+                sequence: 0,
+                suppressed: 0,
+            })),
+        }
+    }
+
+    pub fn layer(&self) -> SelfTelemetryLayer {
+        SelfTelemetryLayer { sink: self.clone() }
+    }
+
+    /// Events dropped by the per-minute cap or the pending-queue bound.
+    pub fn suppressed(&self) -> u64 {
+        self.lock().suppressed
+    }
+
+    pub fn drain(&self) -> Vec<TelemetryEvent> {
+        self.lock().pending.drain(..).collect()
+    }
+
+    /// Drain captured events into an `agent` stream batch, applying the usual sanitisation
+    /// and limits. `None` when nothing was captured.
+    pub fn prepare_batch(&self, config: &TelemetryConfig) -> Option<TelemetryBatch> {
+        let events = self.drain();
+        if events.is_empty() {
+            return None;
+        }
+        let mut config = config.clone();
+        config.stream = SELF_TELEMETRY_STREAM.to_string();
+        Some(prepare_telemetry_batch_from_events(&events, &config))
+    }
+
+    /// The rate window runs on monotonic time so a clock set backwards cannot reopen it.
+    fn record(&self, level: &Level, target: &str, visitor: FieldCapture, now_unix_ms: u64, now_monotonic_ms: u64) {
+        let mut state = self.lock();
+        let limit = state.config.max_events_per_minute;
+        if state.limiter.try_acquire(SELF_TELEMETRY_STREAM, 1, limit, now_monotonic_ms).is_err()
+            || state.pending.len() >= state.config.max_pending_events
+        {
+            state.suppressed = state.suppressed.saturating_add(1);
+            return;
+        }
+        state.sequence = state.sequence.wrapping_add(1);
+        let mut fields = vec![
+            field("log.level", level.as_str()),
+            field("log.target", target),
+        ];
+        fields.extend(visitor.fields);
+        let event = TelemetryEvent {
+            event_id: format!("agent-log-{}-{}", now_unix_ms, state.sequence),
+            stream: SELF_TELEMETRY_STREAM.to_string(),
+            category: "agent.log".to_string(),
+            severity: if *level == Level::ERROR {
+                TelemetrySeverity::High
+            } else {
+                TelemetrySeverity::Medium
+            },
+            timestamp_unix_ms: now_unix_ms,
+            message: visitor.message,
+            fields,
+        };
+        state.pending.push_back(event);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, SinkState> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Tracing layer that turns WARN and ERROR events from the agent's own crates into
+/// telemetry. Anything logged while an event is being captured is ignored, so the layer
+/// cannot feed on itself.
+#[derive(Debug, Clone)]
+pub struct SelfTelemetryLayer {
+    sink: SelfTelemetrySink,
+}
+
+impl<S: Subscriber> Layer<S> for SelfTelemetryLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        if *metadata.level() > Level::WARN || !is_own_target(metadata.target()) {
+            return;
+        }
+        if CAPTURING.with(|capturing| capturing.replace(true)) {
+            return;
+        }
+        let mut visitor = FieldCapture::default();
+        event.record(&mut visitor);
+        self.sink.record(metadata.level(), metadata.target(), visitor, unix_time_ms(), monotonic_ms());
+        CAPTURING.with(|capturing| capturing.set(false));
+    }
+}
+
+fn is_own_target(target: &str) -> bool {
+    OWN_TARGET_PREFIXES
+        .iter()
+        .any(|prefix| target == *prefix || target.starts_with(&format!("{}::", prefix)))
+}
+
+#[derive(Debug, Default)]
+struct FieldCapture {
+    message: String,
+    fields: Vec<TelemetryField>,
+}
+
+impl Visit for FieldCapture {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.push(field, value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        let mut rendered = String::new();
+        let _ = write!(rendered, "{:?}", value);
+        self.push(field, rendered);
+    }
+}
+
+impl FieldCapture {
+    fn push(&mut self, field: &Field, value: String) {
+        if field.name() == "message" {
+            self.message = value;
+        } else if self.fields.len() < MAX_CAPTURED_FIELDS {
+            self.fields.push(TelemetryField {
+                key: format!("log.{}", field.name()),
+                value,
+            });
+        }
+    }
+}
+
+fn field(key: &str, value: &str) -> TelemetryField {
+    TelemetryField {
+        key: key.to_string(),
+        value: value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing::{info, warn};
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::{SelfTelemetryConfig, SelfTelemetrySink, CAPTURING};
+    use crate::siem::TelemetrySeverity;
+
+    fn build_sink(max_events_per_minute: u64) -> SelfTelemetrySink {
+        SelfTelemetrySink::new(SelfTelemetryConfig {
+            max_events_per_minute,
+            max_pending_events: 16,
+        })
+    }
+
+    fn value_of<'a>(event: &'a crate::siem::TelemetryEvent, key: &str) -> Option<&'a str> {
+        event
+            .fields
+            .iter()
+            .find(|field| field.key == key)
+            .map(|field| field.value.as_str())
+    }
+
+    #[test]
+    fn converts_own_warnings_into_agent_events() {
+        let sink = build_sink(30);
+        let subscriber = tracing_subscriber::registry().with(sink.layer());
+        tracing::subscriber::with_default(subscriber, || {
+            warn!(queue_dir = "uplink_queue", attempts = 5, "uplink item dead-lettered");
+            info!("routine progress");
+            tracing::warn!(target: "reqwest::connect", "third-party warning");
+        });
+
+        let events = sink.drain();
+        assert_eq!(events.len(), 1);
+        let event = &events[0];
+        assert_eq!(event.stream, "agent");
+        assert_eq!(event.category, "agent.log");
+        assert_eq!(event.severity, TelemetrySeverity::Medium);
+        assert_eq!(event.message, "uplink item dead-lettered");
+        assert_eq!(value_of(event, "log.level"), Some("WARN"));
+        assert_eq!(value_of(event, "log.target"), Some("agent_core::self_telemetry::tests"));
+        assert_eq!(value_of(event, "log.queue_dir"), Some("uplink_queue"));
+        assert_eq!(value_of(event, "log.attempts"), Some("5"));
+    }
+
+    #[test]
+    fn caps_events_per_minute() {
+        let sink = build_sink(3);
+        let subscriber = tracing_subscriber::registry().with(sink.layer());
+        tracing::subscriber::with_default(subscriber, || {
+            for attempt in 0..10 {
+                warn!(attempt, "log storm");
+            }
+        });
+        assert_eq!(sink.drain().len(), 3);
+        assert_eq!(sink.suppressed(), 7);
+    }
+
+    #[test]
+    fn drops_events_raised_while_capturing() {
+        let sink = build_sink(30);
+        let subscriber = tracing_subscriber::registry().with(sink.layer());
+        tracing::subscriber::with_default(subscriber, || {
+            CAPTURING.with(|capturing| capturing.set(true));
+            warn!("emitted from inside the capture path");
+            CAPTURING.with(|capturing| capturing.set(false));
+            warn!("emitted normally");
+        });
+
+        let events = sink.drain();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].message, "emitted normally");
+    }
+}
@@ -0,0 +1,319 @@
+use std::env;
+use std::fmt;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use agent_ipc::proto::agent_ipc::EvidencePackage;
+use sha2::{Digest, Sha256};
+
+use crate::state_dir::StatePaths;
+use crate::time::{format_rfc3339_ms, unix_time_ms};
+use crate::uplink::{enqueue_evidence_content_item, enqueue_evidence_item, evidence_content_uri, EvidenceUpload};
+
+const SENSOR_EVIDENCE_TYPE: &str = "sensor_artifact";
+const MAX_EVIDENCE_ID_CHARS: usize = 128;
+
+/// Where sensors may stage evidence files and how large those files may be.
+#[derive(Debug, Clone)]
+pub struct SensorEvidenceConfig {
+    pub staging_root: PathBuf,
+    /// Where verified copies wait for upload. It must sit beneath the uplink file staging root.
+    pub upload_stage: PathBuf,
+    pub max_bytes: u64,
+    pub tenant_id: String,
+    pub queue_dir: PathBuf,
+}
+
+impl SensorEvidenceConfig {
+    pub fn from_env(paths: &StatePaths) -> Self {
+        let staging_root = env::var("SENSOR_EVIDENCE_STAGING_DIR")
+            .ok()
+            .filter(|value| !value.trim().is_empty())
+            .map(PathBuf::from)
+            .unwrap_or_else(|| paths.evidence_stage.join("sensor"));
+        let max_bytes = env::var("SENSOR_EVIDENCE_MAX_BYTES")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .filter(|value| *value > 0)
+            .unwrap_or(100 * 1024 * 1024);
+        let tenant_id = env::var("AGENT_TENANT_ID").unwrap_or_default();
+        Self {
+            staging_root,
+            upload_stage: paths.evidence_stage.join("sensor-upload"),
+            max_bytes,
+            tenant_id,
+            queue_dir: paths.uplink_queue.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EvidenceRejection {
+    InvalidId,
+    MalformedHash,
+    OutsideStagingRoot,
+    MissingFile,
+    SizeOutOfBounds(u64),
+    HashMismatch { actual: String },
+    QueueFailed(String),
+}
+
+impl EvidenceRejection {
+    pub fn metric_label(&self) -> &'static str {
+        match self {
+            Self::InvalidId => "evidence_invalid_id",
+            Self::MalformedHash => "evidence_malformed_hash",
+            Self::OutsideStagingRoot => "evidence_outside_staging_root",
+            Self::MissingFile => "evidence_missing_file",
+            Self::SizeOutOfBounds(_) => "evidence_size_out_of_bounds",
+            Self::HashMismatch { .. } => "evidence_hash_mismatch",
+            Self::QueueFailed(_) => "evidence_queue_failed",
+        }
+    }
+}
+
+impl fmt::Display for EvidenceRejection {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidId => write!(formatter, "evidence id is empty or too long"),
+            Self::MalformedHash => write!(formatter, "sha256 is not 64 hex characters"),
+            Self::OutsideStagingRoot => write!(formatter, "staged path is outside the sensor staging root"),
+            Self::MissingFile => write!(formatter, "staged file does not exist"),
+            Self::SizeOutOfBounds(size) => write!(formatter, "staged file size {} is out of bounds", size),
+            Self::HashMismatch { actual } => write!(formatter, "staged file hash {} does not match", actual),
+            Self::QueueFailed(err) => write!(formatter, "unable to queue evidence: {}", err),
+        }
+    }
+}
+
+/// Copy a staged evidence package into the upload stage, check the copy's SHA-256 and queue
+/// the record and the copy for uplink, so what is uploaded is exactly what was verified.
+/// Reads up to `max_bytes`; call it from the blocking pool. `source` names the service that
+/// sent it.
+pub fn register_package(
+    package: &EvidencePackage,
+    asset_id: &str,
+    source: &str,
+    config: &SensorEvidenceConfig,
+) -> Result<EvidenceUpload, EvidenceRejection> {
+    let path = check_package(package, config)?;
+    let hash = package.sha256.to_ascii_lowercase();
+    let copy = stage_verified_copy(&path, &hash, config)?;
+    let captured_unix_time_ms = if package.captured_unix_time_ms > 0 {
+        package.captured_unix_time_ms
+    } else {
+        unix_time_ms()
+    };
+    let upload = EvidenceUpload {
+        evidence_id: package.evidence_id.trim().to_string(),
+        tenant_id: config.tenant_id.clone(),
+        asset_id: asset_id.to_string(),
+        source: source.to_string(),
+        evidence_type: SENSOR_EVIDENCE_TYPE.to_string(),
+        related_id: package.case_id.clone(),
+        storage_uri: evidence_content_uri(&hash),
+        hash,
+        captured_at: format_rfc3339_ms(captured_unix_time_ms),
+    };
+    let queued = enqueue_evidence_item(&config.queue_dir, &upload)
+        .and_then(|()| enqueue_evidence_content_item(&config.queue_dir, &upload.hash, &copy, true));
+    if let Err(err) = queued {
+        let _ = std::fs::remove_file(&copy);
+        return Err(EvidenceRejection::QueueFailed(err));
+    }
+    Ok(upload)
+}
+
+/// Validate the package metadata and return the canonical path of its staged file when the
+/// file exists within the staging root and its size is in bounds. Does not read the file.
+pub fn check_package(package: &EvidencePackage, config: &SensorEvidenceConfig) -> Result<PathBuf, EvidenceRejection> {
+    let evidence_id = package.evidence_id.trim();
+    if evidence_id.is_empty() || evidence_id.chars().count() > MAX_EVIDENCE_ID_CHARS {
+        return Err(EvidenceRejection::InvalidId);
+    }
+    if package.sha256.len() != 64 || !package.sha256.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return Err(EvidenceRejection::MalformedHash);
+    }
+    let path = resolve_staged_path(Path::new(&package.staged_path), &config.staging_root)?;
+    let metadata = std::fs::metadata(&path).map_err(|_| EvidenceRejection::MissingFile)?;
+    if !metadata.is_file() {
+        return Err(EvidenceRejection::MissingFile);
+    }
+    if metadata.len() == 0 || metadata.len() > config.max_bytes {
+        return Err(EvidenceRejection::SizeOutOfBounds(metadata.len()));
+    }
+    Ok(path)
+}
+
+/// Copy `path` into the upload stage and keep the copy only when its SHA-256 is `hash`.
+fn stage_verified_copy(path: &Path, hash: &str, config: &SensorEvidenceConfig) -> Result<PathBuf, EvidenceRejection> {
+    let stage_failed = |err: std::io::Error| EvidenceRejection::QueueFailed(format!("unable to stage evidence copy: {err}"));
+    std::fs::create_dir_all(&config.upload_stage).map_err(stage_failed)?;
+    let copy = config.upload_stage.join(format!("{}-{}", hash, unix_time_ms()));
+    let partial = copy.with_extension("partial");
+    let actual = match copy_and_hash(path, &partial, config.max_bytes) {
+        Ok(actual) => actual,
+        Err(err) => {
+            let _ = std::fs::remove_file(&partial);
+            return Err(stage_failed(err));
+        }
+    };
+    if actual != hash {
+        let _ = std::fs::remove_file(&partial);
+        return Err(EvidenceRejection::HashMismatch { actual });
+    }
+    std::fs::rename(&partial, &copy).map_err(stage_failed)?;
+    Ok(copy)
+}
+
+/// Relative paths are taken from the staging root. Both sides are canonicalised so `..`
+/// segments and symlinks cannot lead out of the root.
+fn resolve_staged_path(staged_path: &Path, staging_root: &Path) -> Result<PathBuf, EvidenceRejection> {
+    if staged_path.as_os_str().is_empty() {
+        return Err(EvidenceRejection::MissingFile);
+    }
+    let root = staging_root
+        .canonicalize()
+        .map_err(|_| EvidenceRejection::OutsideStagingRoot)?;
+    let candidate = if staged_path.is_absolute() {
+        staged_path.to_path_buf()
+    } else {
+        root.join(staged_path)
+    };
+    let resolved = candidate.canonicalize().map_err(|_| {
+        if candidate.starts_with(&root) {
+            EvidenceRejection::MissingFile
+        } else {
+            EvidenceRejection::OutsideStagingRoot
+        }
+    })?;
+    if !resolved.starts_with(&root) {
+        return Err(EvidenceRejection::OutsideStagingRoot);
+    }
+    Ok(resolved)
+}
+
+/// Copy at most `max_bytes` of `source` to `target` and return the SHA-256 of what was
+/// copied, so a file that grows after the size check cannot keep the handler busy.
+fn copy_and_hash(source: &Path, target: &Path, max_bytes: u64) -> std::io::Result<String> {
+    let mut reader = File::open(source)?.take(max_bytes);
+    let mut writer = File::create(target)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = [0u8; 64 * 1024];
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        writer.write_all(&buffer[..read])?;
+    }
+    writer.sync_all()?;
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    use agent_ipc::proto::agent_ipc::EvidencePackage;
+
+    use super::{register_package, EvidenceRejection, SensorEvidenceConfig};
+    use crate::telemetry_router::sha256_hex;
+    use crate::time::unix_time_ms;
+    use crate::uplink::{evidence_content_uri, pending_item_count};
+
+    fn temp_root(label: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("sensor-evidence-{}-{}-{}", label, std::process::id(), unix_time_ms()));
+        fs::create_dir_all(root.join("staging")).expect("create staging root");
+        root
+    }
+
+    fn config(root: &Path) -> SensorEvidenceConfig {
+        SensorEvidenceConfig {
+            staging_root: root.join("staging"),
+            upload_stage: root.join("upload"),
+            max_bytes: 1024,
+            tenant_id: "tenant".to_string(),
+            queue_dir: root.join("queue"),
+        }
+    }
+
+    fn package(staged_path: &str, sha256: &str) -> EvidencePackage {
+        EvidencePackage {
+            evidence_id: "ev-1".to_string(),
+            case_id: "case-1".to_string(),
+            payload: Vec::new(),
+            sha256: sha256.to_string(),
+            captured_unix_time_ms: 1_700_000_000_000,
+            staged_path: staged_path.to_string(),
+        }
+    }
+
+    #[test]
+    fn queues_staged_evidence_with_matching_hash() {
+        let root = temp_root("match");
+        let config = config(&root);
+        fs::write(config.staging_root.join("dump.bin"), b"captured bytes").expect("write staged file");
+
+        let upload = register_package(&package("dump.bin", &sha256_hex(b"captured bytes")), "asset-1", "agent-sensor", &config)
+            .expect("package accepted");
+
+        assert_eq!(upload.related_id, "case-1");
+        assert_eq!(upload.captured_at, "2023-11-14T22:13:20.000Z");
+        assert_eq!(pending_item_count(&config.queue_dir), 2, "record and content");
+        assert_eq!(upload.storage_uri, evidence_content_uri(&sha256_hex(b"captured bytes")));
+        let queued = fs::read_dir(&config.queue_dir)
+            .expect("queue")
+            .flatten()
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .collect::<Vec<String>>();
+        assert!(queued.iter().all(|name| name.starts_with("hi-")), "{:?}", queued);
+
+        // The upload reads the agent's own copy, so the sensor may clear its staging file.
+        fs::remove_file(config.staging_root.join("dump.bin")).expect("remove staged file");
+        let copies = fs::read_dir(&config.upload_stage).expect("upload stage").flatten().collect::<Vec<_>>();
+        assert_eq!(copies.len(), 1);
+        assert_eq!(fs::read(copies[0].path()).expect("copy"), b"captured bytes");
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn rejects_hash_mismatch_and_missing_files() {
+        let root = temp_root("mismatch");
+        let config = config(&root);
+        fs::write(config.staging_root.join("dump.bin"), b"captured bytes").expect("write staged file");
+
+        let mismatch = register_package(&package("dump.bin", &sha256_hex(b"other bytes")), "asset-1", "agent-sensor", &config);
+        assert!(matches!(mismatch, Err(EvidenceRejection::HashMismatch { .. })));
+        let missing = register_package(&package("gone.bin", &sha256_hex(b"captured bytes")), "asset-1", "agent-sensor", &config);
+        assert_eq!(missing, Err(EvidenceRejection::MissingFile));
+        let malformed = register_package(&package("dump.bin", "not-a-hash"), "asset-1", "agent-sensor", &config);
+        assert_eq!(malformed, Err(EvidenceRejection::MalformedHash));
+        assert_eq!(pending_item_count(&config.queue_dir), 0);
+        assert_eq!(fs::read_dir(&config.upload_stage).map(|entries| entries.count()).unwrap_or(0), 0);
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn rejects_paths_outside_the_staging_root() {
+        let root = temp_root("outside");
+        let config = config(&root);
+        let outside = root.join("outside.bin");
+        fs::write(&outside, b"captured bytes").expect("write outside file");
+        let hash = sha256_hex(b"captured bytes");
+
+        let absolute = register_package(&package(&outside.display().to_string(), &hash), "asset-1", "agent-sensor", &config);
+        assert_eq!(absolute, Err(EvidenceRejection::OutsideStagingRoot));
+        let traversal = register_package(&package("../outside.bin", &hash), "asset-1", "agent-sensor", &config);
+        assert_eq!(traversal, Err(EvidenceRejection::OutsideStagingRoot));
+        assert_eq!(pending_item_count(&config.queue_dir), 0);
+        let _ = fs::remove_dir_all(root);
+    }
+}
@@ -0,0 +1,115 @@
+use std::env;
+use std::future::Future;
+use std::time::Duration;
+
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+
+#[derive(Debug, Clone)]
+pub struct ShutdownConfig {
+    pub drain_timeout_secs: u64,
+}
+
+impl ShutdownConfig {
+    pub fn from_env() -> Self {
+        let drain_timeout_secs = env::var("AGENT_SHUTDOWN_DRAIN_SECS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .unwrap_or(10);
+        Self { drain_timeout_secs }
+    }
+
+    pub fn drain_timeout(&self) -> Duration {
+        Duration::from_secs(self.drain_timeout_secs)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownOutcome {
+    Drained,
+    TimedOut { remaining_tasks: usize },
+}
+
+/// Owns the cancellation token handed to every background loop and tracks the tasks it
+/// spawned, so shutdown can wait for each loop to finish its current unit of work.
+#[derive(Debug, Clone, Default)]
+pub struct ShutdownCoordinator {
+    token: CancellationToken,
+    tracker: TaskTracker,
+}
+
+impl ShutdownCoordinator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn token(&self) -> CancellationToken {
+        self.token.clone()
+    }
+
+    /// Spawn a tracked task. The task is expected to watch `token()` and return once it has
+    /// finished whatever it was doing when cancellation was requested.
+    pub fn spawn<F>(&self, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.tracker.spawn(task);
+    }
+
+    /// Cancel every task and wait up to `drain_timeout` for them to exit.
+    pub async fn shutdown(&self, drain_timeout: Duration) -> ShutdownOutcome {
+        self.token.cancel();
+        self.tracker.close();
+        match tokio::time::timeout(drain_timeout, self.tracker.wait()).await {
+            Ok(()) => ShutdownOutcome::Drained,
+            Err(_) => ShutdownOutcome::TimedOut {
+                remaining_tasks: self.tracker.len(),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::{ShutdownCoordinator, ShutdownOutcome};
+
+    #[tokio::test]
+    async fn drains_tasks_after_current_unit_of_work() {
+        let coordinator = ShutdownCoordinator::new();
+        let started = Arc::new(AtomicUsize::new(0));
+        let finished = Arc::new(AtomicUsize::new(0));
+        let token = coordinator.token();
+        let (started_units, finished_units) = (Arc::clone(&started), Arc::clone(&finished));
+        coordinator.spawn(async move {
+            while !token.is_cancelled() {
+                started_units.fetch_add(1, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                finished_units.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+
+        tokio::time::sleep(Duration::from_millis(75)).await;
+        let outcome = coordinator.shutdown(Duration::from_secs(2)).await;
+
+        assert_eq!(outcome, ShutdownOutcome::Drained);
+        assert!(finished.load(Ordering::SeqCst) >= 2);
+        assert_eq!(started.load(Ordering::SeqCst), finished.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn reports_tasks_still_running_after_drain_timeout() {
+        let coordinator = ShutdownCoordinator::new();
+        coordinator.spawn(async {
+            tokio::time::sleep(Duration::from_secs(30)).await;
+        });
+
+        let outcome = coordinator.shutdown(Duration::from_millis(100)).await;
+
+        assert_eq!(outcome, ShutdownOutcome::TimedOut { remaining_tasks: 1 });
+        assert!(coordinator.token().is_cancelled());
+    }
+}
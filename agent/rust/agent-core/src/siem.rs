@@ -1,12 +1,27 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::env;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
 
+use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 
+use crate::enrichment::Enricher;
+use crate::host_facts::current_host_facts;
+use crate::redaction::RedactionConfig;
 use crate::security::{validate_bounded_string, ValidationLimits};
 use crate::time::unix_time_ms;
 
+/// Checksum prefix length used for content-derived batch ids (64 bits).
+const BATCH_ID_CHECKSUM_CHARS: usize = 16;
+
+/// Categories listed in a batch manifest, most frequent first.
+const MANIFEST_TOP_CATEGORIES: usize = 5;
+
 /// Normalised telemetry event prepared for SIEM delivery.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TelemetryEvent {
     pub event_id: String,
     pub stream: String,
@@ -17,13 +32,14 @@ pub struct TelemetryEvent {
     pub fields: Vec<TelemetryField>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TelemetryField {
     pub key: String,
     pub value: String,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum TelemetrySeverity {
     Informational,
     Low,
@@ -33,15 +49,235 @@ pub enum TelemetrySeverity {
 }
 
 /// Prepared SIEM batch with integrity metadata.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TelemetryBatch {
     pub batch_id: String,
     pub stream: String,
     pub event_count: usize,
     pub dropped_count: usize,
+    pub deduplicated_count: usize,
+    /// Informational and low events thinned out by sampling; not part of `dropped_count`.
+    #[serde(default)]
+    pub sampled_count: usize,
+    pub malformed_count: usize,
     pub total_payload_bytes: u64,
     pub checksum_sha256: String,
+    /// Checksum of the previous batch when telemetry chaining is on; `checksum_sha256` then
+    /// covers it as well as the events.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prev_checksum: Option<String>,
     pub created_at_unix_ms: u64,
+    pub severity_counts: SeverityBreakdown,
+    /// Summary of the accepted events; not covered by the checksum.
+    #[serde(default)]
+    pub manifest: BatchManifest,
+    pub events: Vec<TelemetryEvent>,
+}
+
+/// What the backend needs to triage a batch without reading its events.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BatchManifest {
+    pub earliest_event_unix_ms: Option<u64>,
+    pub latest_event_unix_ms: Option<u64>,
+    /// Accepted events per severity; severities with none are omitted.
+    pub severity_histogram: BTreeMap<TelemetrySeverity, usize>,
+    pub top_categories: Vec<CategoryCount>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CategoryCount {
+    pub category: String,
+    pub count: usize,
+}
+
+impl BatchManifest {
+    /// One pass over `events`; categories are ranked by count, then name.
+    pub fn from_events(events: &[TelemetryEvent]) -> Self {
+        let mut manifest = BatchManifest::default();
+        let mut categories: HashMap<&str, usize> = HashMap::new();
+        for event in events {
+            let timestamp = event.timestamp_unix_ms;
+            manifest.earliest_event_unix_ms = Some(manifest.earliest_event_unix_ms.map_or(timestamp, |min| min.min(timestamp)));
+            manifest.latest_event_unix_ms = Some(manifest.latest_event_unix_ms.map_or(timestamp, |max| max.max(timestamp)));
+            *manifest.severity_histogram.entry(event.severity).or_default() += 1;
+            *categories.entry(event.category.as_str()).or_default() += 1;
+        }
+        let mut ranked = categories.into_iter().collect::<Vec<(&str, usize)>>();
+        ranked.sort_by(|left, right| right.1.cmp(&left.1).then_with(|| left.0.cmp(right.0)));
+        manifest.top_categories = ranked
+            .into_iter()
+            .take(MANIFEST_TOP_CATEGORIES)
+            .map(|(category, count)| CategoryCount {
+                category: category.to_string(),
+                count,
+            })
+            .collect();
+        manifest
+    }
+}
+
+/// Accepted and dropped event counts for a single severity band.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SeverityCounts {
+    pub accepted: usize,
+    pub dropped: usize,
+}
+
+/// Per-severity admission outcome for a prepared batch.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SeverityBreakdown {
+    pub informational: SeverityCounts,
+    pub low: SeverityCounts,
+    pub medium: SeverityCounts,
+    pub high: SeverityCounts,
+    pub critical: SeverityCounts,
+}
+
+impl SeverityBreakdown {
+    pub fn get(&self, severity: TelemetrySeverity) -> SeverityCounts {
+        match severity {
+            TelemetrySeverity::Informational => self.informational,
+            TelemetrySeverity::Low => self.low,
+            TelemetrySeverity::Medium => self.medium,
+            TelemetrySeverity::High => self.high,
+            TelemetrySeverity::Critical => self.critical,
+        }
+    }
+
+    /// Most severe band with at least one accepted event.
+    pub fn highest_accepted(&self) -> Option<TelemetrySeverity> {
+        [
+            TelemetrySeverity::Critical,
+            TelemetrySeverity::High,
+            TelemetrySeverity::Medium,
+            TelemetrySeverity::Low,
+            TelemetrySeverity::Informational,
+        ]
+        .into_iter()
+        .find(|severity| self.get(*severity).accepted > 0)
+    }
+
+    fn get_mut(&mut self, severity: TelemetrySeverity) -> &mut SeverityCounts {
+        match severity {
+            TelemetrySeverity::Informational => &mut self.informational,
+            TelemetrySeverity::Low => &mut self.low,
+            TelemetrySeverity::Medium => &mut self.medium,
+            TelemetrySeverity::High => &mut self.high,
+            TelemetrySeverity::Critical => &mut self.critical,
+        }
+    }
+
+    fn record_accepted(&mut self, severity: TelemetrySeverity) {
+        self.get_mut(severity).accepted += 1;
+    }
+
+    fn record_dropped(&mut self, severity: TelemetrySeverity) {
+        self.get_mut(severity).dropped += 1;
+    }
+}
+
+/// Maximum share of `max_events` each lower severity band may occupy. Critical and high
+/// events are not share-limited and are admitted up to the batch byte cap.
+#[derive(Debug, Clone)]
+pub struct SeverityShares {
+    pub medium: f64,
+    pub low: f64,
+    pub informational: f64,
+}
+
+impl SeverityShares {
+    pub fn unrestricted() -> Self {
+        Self {
+            medium: 1.0,
+            low: 1.0,
+            informational: 1.0,
+        }
+    }
+
+    fn parse(value: &str) -> Self {
+        let mut shares = Self::unrestricted();
+        for entry in value.split(',') {
+            let mut parts = entry.splitn(2, '=');
+            let band = parts.next().unwrap_or("").trim().to_lowercase();
+            let share = match parts.next().and_then(|value| value.trim().parse::<f64>().ok()) {
+                Some(value) => value.clamp(0.0, 1.0),
+                None => continue,
+            };
+            match band.as_str() {
+                "medium" => shares.medium = share,
+                "low" => shares.low = share,
+                "informational" => shares.informational = share,
+                _ => {}
+            }
+        }
+        shares
+    }
+
+    fn share_for(&self, severity: TelemetrySeverity) -> f64 {
+        match severity {
+            TelemetrySeverity::Medium => self.medium,
+            TelemetrySeverity::Low => self.low,
+            TelemetrySeverity::Informational => self.informational,
+            TelemetrySeverity::High | TelemetrySeverity::Critical => 1.0,
+        }
+    }
+}
+
+/// When informational telemetry is sampled. `Severity`, the default, thins informational and
+/// low events at `informational_sample_rate` before admission; `Fill` only samples
+/// informational events once the batch is `sample_after_fill_ratio` full. Either way the
+/// sampled events are counted in `sampled_count`, not as drops.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SamplingMode {
+    Severity,
+    Fill,
+}
+
+impl SamplingMode {
+    fn parse(value: &str) -> Self {
+        if value.trim().eq_ignore_ascii_case("fill") {
+            Self::Fill
+        } else {
+            Self::Severity
+        }
+    }
+}
+
+/// How `batch_id` is derived. `Content` ids are stable across re-preparations of the same
+/// accepted events, so the backend can treat a resent batch as a duplicate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BatchIdMode {
+    Timestamp,
+    Content,
+}
+
+impl BatchIdMode {
+    fn parse(value: &str) -> Self {
+        if value.trim().eq_ignore_ascii_case("content") {
+            Self::Content
+        } else {
+            Self::Timestamp
+        }
+    }
+}
+
+/// Line format of `TELEMETRY_EVENTS`. `Jsonl` lines are JSON objects with `category`,
+/// `severity`, `message`, `fields` and `timestamp_unix_ms`, so messages may contain `|` and
+/// field values may be nested JSON.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventsFormat {
+    Pipe,
+    Jsonl,
+}
+
+impl EventsFormat {
+    fn parse(value: &str) -> Self {
+        if value.trim().eq_ignore_ascii_case("jsonl") {
+            Self::Jsonl
+        } else {
+            Self::Pipe
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -53,6 +289,21 @@ pub struct TelemetryConfig {
     pub max_field_count: usize,
     pub max_field_key_len: usize,
     pub max_field_value_len: usize,
+    /// Longest field list (the `key=value;...` section of a line) parsed per event; the
+    /// rest of the list is ignored.
+    pub max_fields_raw_len: usize,
+    pub severity_shares: SeverityShares,
+    pub sampling_mode: SamplingMode,
+    pub informational_sample_rate: f64,
+    pub sample_after_fill_ratio: f64,
+    pub sampling_seed: u64,
+    pub sort_by_timestamp: bool,
+    pub dedup_window_ms: u64,
+    pub dedup_max_entries: usize,
+    pub events_path: Option<PathBuf>,
+    pub batch_id_mode: BatchIdMode,
+    pub events_format: EventsFormat,
+    pub redaction: RedactionConfig,
 }
 
 impl TelemetryConfig {
@@ -87,6 +338,57 @@ impl TelemetryConfig {
             .ok()
             .and_then(|value| value.parse::<usize>().ok())
             .unwrap_or(limits.max_payload_len);
+        let max_fields_raw_len = env::var("TELEMETRY_MAX_FIELDS_RAW_LEN")
+            .ok()
+            .and_then(|value| value.parse::<usize>().ok())
+            .unwrap_or(16 * 1024);
+        let severity_shares = env::var("TELEMETRY_SEVERITY_SHARES")
+            .ok()
+            .map(|value| SeverityShares::parse(&value))
+            .unwrap_or_else(SeverityShares::unrestricted);
+        let sampling_mode = env::var("TELEMETRY_SAMPLING_MODE")
+            .ok()
+            .map(|value| SamplingMode::parse(&value))
+            .unwrap_or(SamplingMode::Severity);
+        let informational_sample_rate = env::var("TELEMETRY_INFO_SAMPLE_RATE")
+            .ok()
+            .and_then(|value| value.parse::<f64>().ok())
+            .map(|value| value.clamp(0.0, 1.0))
+            .unwrap_or(1.0);
+        let sample_after_fill_ratio = env::var("TELEMETRY_SAMPLE_AFTER_FILL")
+            .ok()
+            .and_then(|value| value.parse::<f64>().ok())
+            .map(|value| value.clamp(0.0, 1.0))
+            .unwrap_or(0.5);
+        let sampling_seed = env::var("TELEMETRY_SAMPLING_SEED")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .unwrap_or(0);
+        let sort_by_timestamp = env::var("TELEMETRY_SORT")
+            .ok()
+            .map(|value| value.eq_ignore_ascii_case("true"))
+            .unwrap_or(false);
+        let dedup_window_ms = env::var("TELEMETRY_DEDUP_WINDOW_MS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .unwrap_or(0);
+        let dedup_max_entries = env::var("TELEMETRY_DEDUP_MAX_ENTRIES")
+            .ok()
+            .and_then(|value| value.parse::<usize>().ok())
+            .unwrap_or(4096);
+        let events_path = env::var("TELEMETRY_EVENTS_PATH")
+            .ok()
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+            .map(PathBuf::from);
+        let batch_id_mode = env::var("TELEMETRY_BATCH_ID_MODE")
+            .ok()
+            .map(|value| BatchIdMode::parse(&value))
+            .unwrap_or(BatchIdMode::Timestamp);
+        let events_format = env::var("TELEMETRY_EVENTS_FORMAT")
+            .ok()
+            .map(|value| EventsFormat::parse(&value))
+            .unwrap_or(EventsFormat::Pipe);
 
         Self {
             stream,
@@ -96,77 +398,472 @@ impl TelemetryConfig {
             max_field_count,
             max_field_key_len,
             max_field_value_len,
+            max_fields_raw_len,
+            severity_shares,
+            sampling_mode,
+            informational_sample_rate,
+            sample_after_fill_ratio,
+            sampling_seed,
+            sort_by_timestamp,
+            dedup_window_ms,
+            dedup_max_entries,
+            events_path,
+            batch_id_mode,
+            events_format,
+            redaction: RedactionConfig::from_env(),
+        }
+    }
+}
+
+impl TelemetryConfig {
+    /// Reject limit combinations under which no event could ever be batched.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_events == 0 {
+            return Err("TELEMETRY_MAX_EVENTS must be greater than zero".to_string());
+        }
+        if self.max_field_count == 0 {
+            return Err("TELEMETRY_MAX_FIELDS must be greater than zero".to_string());
+        }
+        if self.max_fields_raw_len == 0 {
+            return Err("TELEMETRY_MAX_FIELDS_RAW_LEN must be greater than zero".to_string());
+        }
+        if self.max_event_bytes == 0 || self.max_event_bytes > self.max_batch_bytes {
+            return Err(format!(
+                "TELEMETRY_MAX_EVENT_BYTES ({}) must be between 1 and TELEMETRY_MAX_BATCH_BYTES ({})",
+                self.max_event_bytes, self.max_batch_bytes
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Time-bounded record of recently seen event content, carried across batches so sensor
+/// re-emissions after a reconnect are suppressed. Event timestamps drive expiry, and the
+/// store is capped at `max_entries` with oldest-first eviction.
+#[derive(Debug, Clone)]
+pub struct DedupWindow {
+    window_ms: u64,
+    max_entries: usize,
+    seen: HashMap<[u8; 32], u64>,
+    order: VecDeque<([u8; 32], u64)>,
+    latest_unix_ms: u64,
+}
+
+impl DedupWindow {
+    pub fn new(window_ms: u64, max_entries: usize) -> Self {
+        Self {
+            window_ms,
+            max_entries,
+            seen: HashMap::new(),
+            order: VecDeque::new(),
+            latest_unix_ms: 0,
+        }
+    }
+
+    pub fn from_config(config: &TelemetryConfig) -> Self {
+        Self::new(config.dedup_window_ms, config.dedup_max_entries)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.window_ms > 0 && self.max_entries > 0
+    }
+
+    pub fn len(&self) -> usize {
+        self.seen.len()
+    }
+
+    /// Returns true when equivalent content was already seen within the window; otherwise
+    /// records the event and returns false.
+    pub fn check_and_record(&mut self, event: &TelemetryEvent) -> bool {
+        if !self.is_enabled() {
+            return false;
+        }
+
+        let at_unix_ms = event.timestamp_unix_ms;
+        self.latest_unix_ms = self.latest_unix_ms.max(at_unix_ms);
+        self.evict_expired();
+
+        let key = content_hash(event);
+        if let Some(seen_at) = self.seen.get(&key) {
+            if at_unix_ms.abs_diff(*seen_at) <= self.window_ms {
+                return true;
+            }
+        }
+
+        self.seen.insert(key, at_unix_ms);
+        self.order.push_back((key, at_unix_ms));
+        while self.seen.len() > self.max_entries {
+            self.evict_oldest();
+        }
+        false
+    }
+
+    fn evict_expired(&mut self) {
+        let cutoff = self.latest_unix_ms.saturating_sub(self.window_ms);
+        while let Some((_, seen_at)) = self.order.front() {
+            if *seen_at >= cutoff {
+                break;
+            }
+            self.evict_oldest();
+        }
+    }
+
+    fn evict_oldest(&mut self) {
+        if let Some((key, seen_at)) = self.order.pop_front() {
+            if self.seen.get(&key) == Some(&seen_at) {
+                self.seen.remove(&key);
+            }
         }
     }
 }
 
-pub fn prepare_telemetry_batch() -> TelemetryBatch {
+/// Events read from a JSON Lines spool directory, with the files they came from.
+#[derive(Debug, Clone, Default)]
+pub struct JsonlIngest {
+    pub events: Vec<TelemetryEvent>,
+    pub malformed_lines: usize,
+    pub files: Vec<PathBuf>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonlEventLine {
+    category: String,
+    #[serde(default)]
+    severity: Option<String>,
+    #[serde(default)]
+    message: String,
+    #[serde(default)]
+    fields: BTreeMap<String, serde_json::Value>,
+    #[serde(default)]
+    timestamp_unix_ms: Option<u64>,
+}
+
+pub fn prepare_telemetry_batch(enricher: &Enricher) -> TelemetryBatch {
     let config = TelemetryConfig::from_env();
-    let events = ingest_events_from_env(&config);
+    let host = current_host_facts();
+
+    if let Some(dir) = &config.events_path {
+        let mut ingest = ingest_events_from_dir(dir, &config);
+        enricher.enrich(&mut ingest.events, &host, config.max_field_count);
+        let mut batch = prepare_telemetry_batch_from_events(&ingest.events, &config);
+        batch.malformed_count = ingest.malformed_lines;
+        complete_ingest(&ingest);
+        return batch;
+    }
+
+    let mut events = ingest_events_from_env(&config);
+    enricher.enrich(&mut events, &host, config.max_field_count);
     prepare_telemetry_batch_from_events(&events, &config)
 }
 
+/// Build a batch using severity-aware admission: higher severities are considered first so a
+/// flood of informational events cannot crowd out critical ones. Delivery order follows
+/// ingestion order unless `sort_by_timestamp` is set.
 pub fn prepare_telemetry_batch_from_events(
     events: &[TelemetryEvent],
     config: &TelemetryConfig,
+) -> TelemetryBatch {
+    let mut dedup = DedupWindow::from_config(config);
+    prepare_telemetry_batch_with_dedup(events, config, &mut dedup)
+}
+
+/// Same as `prepare_telemetry_batch_from_events`, but suppresses duplicates against a
+/// caller-owned window so suppression spans consecutive batches.
+pub fn prepare_telemetry_batch_with_dedup(
+    events: &[TelemetryEvent],
+    config: &TelemetryConfig,
+    dedup: &mut DedupWindow,
 ) -> TelemetryBatch {
     let created_at_unix_ms = unix_time_ms();
-    let mut accepted = Vec::new();
     let mut dropped_count = 0;
-    let mut total_payload_bytes = 0_u64;
+    let mut deduplicated_count = 0;
+    let mut sampled_count = 0;
+    let mut severity_counts = SeverityBreakdown::default();
+    let mut candidates = Vec::new();
+    let mut rng = SamplingRng::new(config.sampling_seed);
 
-    for event in events.iter().take(config.max_events) {
+    for (index, event) in events.iter().enumerate() {
         match sanitise_event(event, config) {
             Some(sanitised) => {
                 let size = estimate_event_bytes(&sanitised);
                 if size > config.max_event_bytes {
                     dropped_count += 1;
+                    severity_counts.record_dropped(event.severity);
                     continue;
                 }
-                if total_payload_bytes.saturating_add(size) > config.max_batch_bytes {
-                    dropped_count += 1;
+                if dedup.check_and_record(&sanitised) {
+                    deduplicated_count += 1;
                     continue;
                 }
-                total_payload_bytes = total_payload_bytes.saturating_add(size);
-                accepted.push(sanitised);
+                if config.sampling_mode == SamplingMode::Severity
+                    && matches!(sanitised.severity, TelemetrySeverity::Informational | TelemetrySeverity::Low)
+                    && rng.next_unit() >= config.informational_sample_rate
+                {
+                    sampled_count += 1;
+                    continue;
+                }
+                candidates.push((index, size, sanitised));
+            }
+            None => {
+                dropped_count += 1;
+                severity_counts.record_dropped(event.severity);
             }
-            None => dropped_count += 1,
         }
     }
 
+    candidates.sort_by_key(|(index, _, event)| (Reverse(severity_rank(event.severity)), *index));
+
+    let mut admitted = Vec::new();
+    let mut total_payload_bytes = 0_u64;
+
+    for (index, size, event) in candidates {
+        let band_count = severity_counts.get(event.severity).accepted;
+        if !admit_event(event.severity, size, admitted.len(), band_count, total_payload_bytes, config) {
+            dropped_count += 1;
+            severity_counts.record_dropped(event.severity);
+            continue;
+        }
+        if config.sampling_mode == SamplingMode::Fill
+            && event.severity == TelemetrySeverity::Informational
+            && batch_fill(admitted.len(), total_payload_bytes, config) >= config.sample_after_fill_ratio
+            && rng.next_unit() >= config.informational_sample_rate
+        {
+            sampled_count += 1;
+            continue;
+        }
+        total_payload_bytes = total_payload_bytes.saturating_add(size);
+        severity_counts.record_accepted(event.severity);
+        admitted.push((index, event));
+    }
+
+    admitted.sort_by_key(|(index, _)| *index);
+    let mut accepted = admitted
+        .into_iter()
+        .map(|(_, event)| event)
+        .collect::<Vec<TelemetryEvent>>();
+    if config.sort_by_timestamp {
+        accepted.sort_by(|left, right| {
+            left.timestamp_unix_ms
+                .cmp(&right.timestamp_unix_ms)
+                .then_with(|| left.event_id.cmp(&right.event_id))
+        });
+    }
+
     let checksum_sha256 = hash_batch(&accepted);
+    let batch_id = match config.batch_id_mode {
+        BatchIdMode::Timestamp => format!("siem-{}-{}", config.stream, created_at_unix_ms),
+        BatchIdMode::Content => format!("siem-{}-{}", config.stream, &checksum_sha256[..BATCH_ID_CHECKSUM_CHARS]),
+    };
     TelemetryBatch {
-        batch_id: format!("siem-{}-{}", config.stream, created_at_unix_ms),
+        batch_id,
         stream: config.stream.clone(),
         event_count: accepted.len(),
         dropped_count,
+        deduplicated_count,
+        sampled_count,
+        malformed_count: 0,
         total_payload_bytes,
         checksum_sha256,
+        prev_checksum: None,
         created_at_unix_ms,
+        severity_counts,
+        manifest: BatchManifest::from_events(&accepted),
+        events: accepted,
+    }
+}
+
+fn admit_event(
+    severity: TelemetrySeverity,
+    size: u64,
+    accepted_count: usize,
+    band_count: usize,
+    total_payload_bytes: u64,
+    config: &TelemetryConfig,
+) -> bool {
+    if total_payload_bytes.saturating_add(size) > config.max_batch_bytes {
+        return false;
+    }
+    if matches!(severity, TelemetrySeverity::Critical | TelemetrySeverity::High) {
+        return true;
+    }
+    if accepted_count >= config.max_events {
+        return false;
+    }
+
+    let band_cap = (config.max_events as f64 * config.severity_shares.share_for(severity)).floor() as usize;
+    band_count < band_cap
+}
+
+/// Fraction of the batch already used, by event count or bytes, whichever is fuller.
+fn batch_fill(accepted_count: usize, total_payload_bytes: u64, config: &TelemetryConfig) -> f64 {
+    let count_fill = accepted_count as f64 / config.max_events.max(1) as f64;
+    let byte_fill = total_payload_bytes as f64 / config.max_batch_bytes.max(1) as f64;
+    count_fill.max(byte_fill)
+}
+
+fn severity_rank(severity: TelemetrySeverity) -> u8 {
+    match severity {
+        TelemetrySeverity::Informational => 0,
+        TelemetrySeverity::Low => 1,
+        TelemetrySeverity::Medium => 2,
+        TelemetrySeverity::High => 3,
+        TelemetrySeverity::Critical => 4,
+    }
+}
+
+/// Deterministic splitmix64 generator so sampling decisions are reproducible for a given seed.
+#[derive(Debug, Clone)]
+struct SamplingRng {
+    state: u64,
+}
+
+impl SamplingRng {
+    fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut value = self.state;
+        value = (value ^ (value >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        value = (value ^ (value >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        value ^ (value >> 31)
+    }
+
+    fn next_unit(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1_u64 << 53) as f64
     }
 }
 
 fn ingest_events_from_env(config: &TelemetryConfig) -> Vec<TelemetryEvent> {
+    match env::var("TELEMETRY_EVENTS") {
+        Ok(raw) => parse_events(&raw, config, unix_time_ms()),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Parse `TELEMETRY_EVENTS` text in the configured format, skipping lines that do not parse.
+fn parse_events(raw: &str, config: &TelemetryConfig, now: u64) -> Vec<TelemetryEvent> {
+    raw.lines()
+        .enumerate()
+        .filter_map(|(index, line)| match config.events_format {
+            EventsFormat::Pipe => parse_event_line(line, index, config, now),
+            EventsFormat::Jsonl if line.trim().is_empty() => None,
+            EventsFormat::Jsonl => parse_jsonl_line(line, index, config, now),
+        })
+        .collect()
+}
+
+/// Read `.jsonl` files from `dir` in name order. Each file is read up to `max_batch_bytes`
+/// (anything beyond is discarded along with a partial trailing line), and reading stops once
+/// the combined byte budget is spent. Malformed lines are counted and skipped.
+pub fn ingest_events_from_dir(dir: &Path, config: &TelemetryConfig) -> JsonlIngest {
     let now = unix_time_ms();
-    let raw = env::var("TELEMETRY_EVENTS").ok();
-    let mut events = Vec::new();
+    let mut ingest = JsonlIngest::default();
+    let mut paths = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| is_jsonl_file(path))
+            .collect::<Vec<PathBuf>>(),
+        Err(_) => return ingest,
+    };
+    paths.sort();
+
+    let mut bytes_read = 0_u64;
+    for path in paths {
+        if bytes_read >= config.max_batch_bytes {
+            break;
+        }
+        let raw = match read_bounded(&path, config.max_batch_bytes) {
+            Some(raw) => raw,
+            None => continue,
+        };
+        bytes_read = bytes_read.saturating_add(raw.len() as u64);
+
+        let truncated = raw.len() as u64 >= config.max_batch_bytes;
+        let text = String::from_utf8_lossy(&raw);
+        let mut lines = text.lines().collect::<Vec<&str>>();
+        if truncated && !text.ends_with('\n') {
+            lines.pop();
+        }
 
-    if let Some(raw) = raw {
-        for (index, line) in raw.lines().enumerate() {
-            if let Some(event) = parse_event_line(line, index, &config.stream, now) {
-                events.push(event);
+        for line in lines {
+            if line.trim().is_empty() {
+                continue;
             }
+            let index = ingest.events.len() + ingest.malformed_lines;
+            match parse_jsonl_line(line, index, config, now) {
+                Some(event) => ingest.events.push(event),
+                None => ingest.malformed_lines += 1,
+            }
+        }
+        ingest.files.push(path);
+    }
+
+    ingest
+}
+
+/// Rename ingested files to `<name>.done` so they are not batched again.
+pub fn complete_ingest(ingest: &JsonlIngest) {
+    for path in &ingest.files {
+        let mut done = path.as_os_str().to_os_string();
+        done.push(".done");
+        let _ = fs::rename(path, PathBuf::from(done));
+    }
+}
+
+fn read_bounded(path: &Path, max_bytes: u64) -> Option<Vec<u8>> {
+    let file = File::open(path).ok()?;
+    let mut raw = Vec::new();
+    file.take(max_bytes).read_to_end(&mut raw).ok()?;
+    Some(raw)
+}
+
+fn is_jsonl_file(path: &Path) -> bool {
+    path.is_file()
+        && path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.eq_ignore_ascii_case("jsonl"))
+            .unwrap_or(false)
+}
+
+fn parse_jsonl_line(line: &str, index: usize, config: &TelemetryConfig, now: u64) -> Option<TelemetryEvent> {
+    let parsed = serde_json::from_str::<JsonlEventLine>(line).ok()?;
+    let category = parsed.category.trim();
+    if category.is_empty() {
+        return None;
+    }
+    // Same budget as `parse_fields`, counting each entry as if written `key=value;`.
+    let mut fields = Vec::new();
+    let mut consumed = 0_usize;
+    for (key, value) in parsed.fields {
+        let value = match value {
+            serde_json::Value::String(text) => text,
+            other => other.to_string(),
+        };
+        consumed = consumed.saturating_add(key.len() + value.len() + 2);
+        if fields.len() >= config.max_field_count || consumed > config.max_fields_raw_len.saturating_add(1) {
+            break;
         }
-        return events;
+        fields.push(TelemetryField { key, value });
     }
 
-    Vec::new()
+    Some(TelemetryEvent {
+        event_id: format!("evt-{}-{}", now, index),
+        stream: config.stream.clone(),
+        category: category.to_string(),
+        severity: parse_severity(parsed.severity.as_deref().unwrap_or("informational").trim()),
+        timestamp_unix_ms: parsed.timestamp_unix_ms.unwrap_or(now),
+        message: parsed.message.trim().to_string(),
+        fields,
+    })
 }
 
 fn parse_event_line(
     line: &str,
     index: usize,
-    stream: &str,
+    config: &TelemetryConfig,
     now: u64,
 ) -> Option<TelemetryEvent> {
     let trimmed = line.trim();
@@ -180,11 +877,11 @@ fn parse_event_line(
     let fields_raw = parts.next().unwrap_or("");
 
     let severity = parse_severity(severity_raw);
-    let fields = parse_fields(fields_raw);
+    let fields = parse_fields(fields_raw, config.max_field_count, config.max_fields_raw_len);
 
     Some(TelemetryEvent {
         event_id: format!("evt-{}-{}", now, index),
-        stream: stream.to_string(),
+        stream: config.stream.clone(),
         category: category.to_string(),
         severity,
         timestamp_unix_ms: now,
@@ -203,22 +900,29 @@ fn parse_severity(value: &str) -> TelemetrySeverity {
     }
 }
 
-fn parse_fields(value: &str) -> Vec<TelemetryField> {
-    value
-        .split(';')
-        .filter_map(|entry| {
-            let mut parts = entry.splitn(2, '=');
-            let key = parts.next()?.trim();
-            let value = parts.next()?.trim();
-            if key.is_empty() || value.is_empty() {
-                return None;
-            }
-            Some(TelemetryField {
-                key: key.to_string(),
-                value: value.to_string(),
-            })
-        })
-        .collect()
+/// Stops at `max_fields` fields or once `max_raw_len` bytes of the list have been read, so a
+/// hostile line cannot allocate more than the sanitiser would keep anyway.
+fn parse_fields(value: &str, max_fields: usize, max_raw_len: usize) -> Vec<TelemetryField> {
+    let mut fields = Vec::new();
+    let mut consumed = 0_usize;
+    for entry in value.split(';') {
+        consumed = consumed.saturating_add(entry.len() + 1);
+        if fields.len() >= max_fields || consumed > max_raw_len.saturating_add(1) {
+            break;
+        }
+        let mut parts = entry.splitn(2, '=');
+        let (Some(key), Some(value)) = (parts.next().map(str::trim), parts.next().map(str::trim)) else {
+            continue;
+        };
+        if key.is_empty() || value.is_empty() {
+            continue;
+        }
+        fields.push(TelemetryField {
+            key: key.to_string(),
+            value: value.to_string(),
+        });
+    }
+    fields
 }
 
 fn sanitise_event(event: &TelemetryEvent, config: &TelemetryConfig) -> Option<TelemetryEvent> {
@@ -231,14 +935,17 @@ fn sanitise_event(event: &TelemetryEvent, config: &TelemetryConfig) -> Option<Te
     if !validate_bounded_string(&event.category, 128) {
         return None;
     }
-    let message = sanitise_text(&event.message, config.max_field_value_len);
+    let message = sanitise_text(&config.redaction.redact_text(&event.message), config.max_field_value_len);
     let mut fields = Vec::new();
 
     for field in event.fields.iter().take(config.max_field_count) {
         if !validate_bounded_string(&field.key, config.max_field_key_len) {
             continue;
         }
-        let value = sanitise_text(&field.value, config.max_field_value_len);
+        let value = sanitise_text(
+            &config.redaction.redact_field(&field.key, &field.value),
+            config.max_field_value_len,
+        );
         if value.is_empty() {
             continue;
         }
@@ -278,22 +985,46 @@ fn estimate_event_bytes(event: &TelemetryEvent) -> u64 {
     total as u64
 }
 
-fn hash_batch(events: &[TelemetryEvent]) -> String {
+/// Checksum over the accepted events only, with every component delimited so that distinct
+/// event sets cannot hash alike by shifting bytes between adjacent fields.
+pub fn hash_batch(events: &[TelemetryEvent]) -> String {
     let mut hasher = Sha256::new();
     for event in events {
         hasher.update(event.event_id.as_bytes());
+        hasher.update([0]);
         hasher.update(event.stream.as_bytes());
+        hasher.update([0]);
         hasher.update(event.category.as_bytes());
+        hasher.update([0, severity_rank(event.severity)]);
         hasher.update(event.timestamp_unix_ms.to_le_bytes());
         hasher.update(event.message.as_bytes());
         for field in &event.fields {
+            hasher.update([0]);
             hasher.update(field.key.as_bytes());
+            hasher.update([b'=']);
             hasher.update(field.value.as_bytes());
         }
+        hasher.update([b'\n']);
     }
     hex_encode(hasher.finalize())
 }
 
+fn content_hash(event: &TelemetryEvent) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(event.stream.as_bytes());
+    hasher.update([0]);
+    hasher.update(event.category.as_bytes());
+    hasher.update([0]);
+    hasher.update(event.message.as_bytes());
+    for field in &event.fields {
+        hasher.update([0]);
+        hasher.update(field.key.as_bytes());
+        hasher.update([b'=']);
+        hasher.update(field.value.as_bytes());
+    }
+    hasher.finalize().into()
+}
+
 fn hex_encode(bytes: impl AsRef<[u8]>) -> String {
     bytes
         .as_ref()
@@ -302,3 +1033,417 @@ fn hex_encode(bytes: impl AsRef<[u8]>) -> String {
         .collect::<Vec<String>>()
         .join("")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        complete_ingest, ingest_events_from_dir, parse_event_line, parse_events, parse_fields,
+        prepare_telemetry_batch_from_events, prepare_telemetry_batch_with_dedup, BatchIdMode, DedupWindow,
+        EventsFormat, SamplingMode, SeverityShares, TelemetryConfig, TelemetryEvent, TelemetrySeverity,
+    };
+    use crate::redaction::RedactionConfig;
+    use crate::time::unix_time_ms;
+
+    fn build_config() -> TelemetryConfig {
+        TelemetryConfig {
+            stream: "sensor".to_string(),
+            max_events: 512,
+            max_event_bytes: 16 * 1024,
+            max_batch_bytes: 512 * 1024,
+            max_field_count: 32,
+            max_field_key_len: 128,
+            max_field_value_len: 8192,
+            max_fields_raw_len: 16 * 1024,
+            severity_shares: SeverityShares::unrestricted(),
+            sampling_mode: SamplingMode::Fill,
+            informational_sample_rate: 0.25,
+            sample_after_fill_ratio: 0.5,
+            sampling_seed: 7,
+            sort_by_timestamp: false,
+            dedup_window_ms: 0,
+            dedup_max_entries: 4096,
+            events_path: None,
+            batch_id_mode: BatchIdMode::Timestamp,
+            events_format: EventsFormat::Pipe,
+            redaction: RedactionConfig::disabled(),
+        }
+    }
+
+    #[test]
+    fn parse_fields_stops_at_the_field_and_length_caps() {
+        let flood = (0..10_000).map(|index| format!("k{}=v{}", index, index)).collect::<Vec<String>>().join(";");
+        let mut config = build_config();
+        config.max_field_count = 8;
+        let event = parse_event_line(&format!("process|high|flood|{}", flood), 0, &config, 1_000).expect("event parsed");
+        assert_eq!(event.fields.len(), 8);
+        assert_eq!(event.fields[7].key, "k7");
+
+        // "a=1;" and "b=2;" fit in 8 bytes; "ccc=3" would end past the cap.
+        let fields = parse_fields("a=1;b=2;ccc=3;d=4", 32, 8);
+        assert_eq!(fields.iter().map(|field| field.key.as_str()).collect::<Vec<_>>(), vec!["a", "b"]);
+        let fields = parse_fields(";junk;a=1;=2;b=", 32, 1024);
+        assert_eq!(fields.len(), 1);
+    }
+
+    #[test]
+    fn jsonl_events_keep_pipes_in_messages() {
+        let line = r#"{"category":"process","severity":"high","message":"cmd.exe /c dir | findstr secret","fields":{"pid":4242,"parent":{"name":"explorer.exe"}}}"#;
+        let mut config = build_config();
+
+        let legacy = parse_events("process|high|cmd.exe /c dir | findstr secret|pid=4242", &config, 1_000);
+        assert_eq!(legacy[0].message, "cmd.exe /c dir");
+        assert!(legacy[0].fields.is_empty(), "the message tail is misread as the field list");
+
+        config.events_format = EventsFormat::Jsonl;
+        let events = parse_events(&format!("{}\n\nnot json\n", line), &config, 1_000);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].message, "cmd.exe /c dir | findstr secret");
+        assert_eq!(events[0].severity, TelemetrySeverity::High);
+        let parent = events[0].fields.iter().find(|field| field.key == "parent").expect("nested field");
+        assert_eq!(parent.value, r#"{"name":"explorer.exe"}"#);
+
+        config.max_field_count = 1;
+        let events = parse_events(line, &config, 1_000);
+        assert_eq!(events[0].fields.len(), 1);
+        config.max_field_count = 32;
+        config.max_fields_raw_len = 8;
+        let events = parse_events(r#"{"category":"process","fields":{"a":"1","b":"2","ccc":"3","d":"4"}}"#, &config, 1_000);
+        assert_eq!(events[0].fields.iter().map(|field| field.key.as_str()).collect::<Vec<_>>(), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn validate_rejects_unusable_limits() {
+        assert_eq!(build_config().validate(), Ok(()));
+        let mut config = build_config();
+        config.max_events = 0;
+        assert!(config.validate().is_err());
+        let mut config = build_config();
+        config.max_event_bytes = config.max_batch_bytes + 1;
+        assert!(config.validate().expect_err("event larger than batch").contains("TELEMETRY_MAX_EVENT_BYTES"));
+    }
+
+    #[test]
+    fn manifest_summarises_severities_time_range_and_categories() {
+        let fixture = [
+            ("process", TelemetrySeverity::Informational, 1_700_000_000_300),
+            ("network", TelemetrySeverity::High, 1_700_000_000_100),
+            ("process", TelemetrySeverity::Critical, 1_700_000_000_900),
+            ("auth", TelemetrySeverity::Low, 1_700_000_000_200),
+            ("network", TelemetrySeverity::High, 1_700_000_000_050),
+            ("process", TelemetrySeverity::Medium, 1_700_000_000_400),
+        ];
+        let events = fixture
+            .iter()
+            .enumerate()
+            .map(|(index, (category, severity, timestamp))| TelemetryEvent {
+                category: category.to_string(),
+                timestamp_unix_ms: *timestamp,
+                ..build_event(index, *severity)
+            })
+            .collect::<Vec<TelemetryEvent>>();
+        let config = build_config();
+
+        let batch = prepare_telemetry_batch_from_events(&events, &config);
+        let manifest = &batch.manifest;
+        assert_eq!(manifest.earliest_event_unix_ms, Some(1_700_000_000_050));
+        assert_eq!(manifest.latest_event_unix_ms, Some(1_700_000_000_900));
+        assert_eq!(
+            manifest.severity_histogram.iter().map(|(severity, count)| (*severity, *count)).collect::<Vec<_>>(),
+            vec![
+                (TelemetrySeverity::Informational, 1),
+                (TelemetrySeverity::Low, 1),
+                (TelemetrySeverity::Medium, 1),
+                (TelemetrySeverity::High, 2),
+                (TelemetrySeverity::Critical, 1),
+            ]
+        );
+        let categories = manifest
+            .top_categories
+            .iter()
+            .map(|entry| (entry.category.as_str(), entry.count))
+            .collect::<Vec<_>>();
+        assert_eq!(categories, vec![("process", 3), ("network", 2), ("auth", 1)]);
+
+        // The checksum still covers the events only: same value as before manifests existed.
+        assert_eq!(batch.checksum_sha256, "e4ed9fae1e30993d9734978fecef6d13781e1ce16ce2d213cb8a2ae218c2370b");
+        let json = serde_json::to_value(&batch).expect("batch json");
+        assert_eq!(json["manifest"]["severity_histogram"]["high"], 2);
+
+        let empty = prepare_telemetry_batch_from_events(&[], &config);
+        assert_eq!(empty.manifest, super::BatchManifest::default());
+    }
+
+    fn build_event(index: usize, severity: TelemetrySeverity) -> TelemetryEvent {
+        TelemetryEvent {
+            event_id: format!("evt-{}", index),
+            stream: "sensor".to_string(),
+            category: "process".to_string(),
+            severity,
+            timestamp_unix_ms: 1_700_000_000_000 + index as u64,
+            message: "process started".to_string(),
+            fields: Vec::new(),
+        }
+    }
+
+    fn build_flood() -> Vec<TelemetryEvent> {
+        (0..1003)
+            .map(|index| match index {
+                600 | 801 | 1002 => build_event(index, TelemetrySeverity::Critical),
+                _ => build_event(index, TelemetrySeverity::Informational),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn admits_all_critical_events_under_informational_flood() {
+        let config = build_config();
+        let batch = prepare_telemetry_batch_from_events(&build_flood(), &config);
+
+        assert_eq!(batch.severity_counts.critical.accepted, 3);
+        assert_eq!(batch.severity_counts.critical.dropped, 0);
+        assert!(batch.event_count <= config.max_events);
+        assert!(batch.sampled_count > 0);
+        assert_eq!(batch.event_count + batch.dropped_count + batch.sampled_count, 1003);
+    }
+
+    #[test]
+    fn severity_sampling_keeps_critical_and_thins_informational_and_low() {
+        let mut config = build_config();
+        config.sampling_mode = SamplingMode::Severity;
+        config.max_events = 5_000;
+        config.max_batch_bytes = 16 * 1024 * 1024;
+        let events = (0..4_020)
+            .map(|index| match index % 402 {
+                0 => build_event(index, TelemetrySeverity::Critical),
+                1 => build_event(index, TelemetrySeverity::High),
+                n if n % 2 == 0 => build_event(index, TelemetrySeverity::Informational),
+                _ => build_event(index, TelemetrySeverity::Low),
+            })
+            .collect::<Vec<TelemetryEvent>>();
+
+        let batch = prepare_telemetry_batch_from_events(&events, &config);
+        assert_eq!(batch.severity_counts.critical.accepted, 10);
+        assert_eq!(batch.severity_counts.high.accepted, 10);
+        assert_eq!(batch.dropped_count, 0);
+        for band in [batch.severity_counts.informational, batch.severity_counts.low] {
+            assert_eq!(band.dropped, 0);
+            assert!((400..=600).contains(&band.accepted), "kept {} of 2000", band.accepted);
+        }
+        assert_eq!(batch.event_count + batch.sampled_count, 4_020);
+
+        config.informational_sample_rate = 0.0;
+        let batch = prepare_telemetry_batch_from_events(&events, &config);
+        assert_eq!(batch.event_count, 20);
+        assert_eq!(batch.sampled_count, 4_000);
+
+        config.informational_sample_rate = 1.0;
+        let batch = prepare_telemetry_batch_from_events(&events, &config);
+        assert_eq!(batch.event_count, 4_020);
+        assert_eq!(batch.sampled_count, 0);
+    }
+
+    #[test]
+    fn sampling_is_deterministic_for_seed() {
+        let config = build_config();
+        let events = build_flood();
+        let first = prepare_telemetry_batch_from_events(&events, &config);
+        let second = prepare_telemetry_batch_from_events(&events, &config);
+
+        assert_eq!(first.checksum_sha256, second.checksum_sha256);
+        assert_eq!(first.severity_counts.informational, second.severity_counts.informational);
+    }
+
+    #[test]
+    fn limits_band_to_configured_share() {
+        let mut config = build_config();
+        config.severity_shares = SeverityShares::parse("informational=0.1");
+        let batch = prepare_telemetry_batch_from_events(&build_flood(), &config);
+
+        assert_eq!(batch.severity_counts.informational.accepted, 51);
+        assert_eq!(batch.severity_counts.critical.accepted, 3);
+    }
+
+    #[test]
+    fn sorts_events_by_timestamp_then_event_id() {
+        let mut config = build_config();
+        config.sort_by_timestamp = true;
+        let mut late = build_event(0, TelemetrySeverity::Low);
+        late.timestamp_unix_ms = 300;
+        let mut tie_b = build_event(1, TelemetrySeverity::Low);
+        tie_b.event_id = "evt-b".to_string();
+        tie_b.timestamp_unix_ms = 100;
+        let mut tie_a = build_event(2, TelemetrySeverity::Low);
+        tie_a.event_id = "evt-a".to_string();
+        tie_a.timestamp_unix_ms = 100;
+
+        let sorted = prepare_telemetry_batch_from_events(&[late.clone(), tie_b.clone(), tie_a.clone()], &config);
+        let presorted = prepare_telemetry_batch_from_events(&[tie_a.clone(), tie_b.clone(), late.clone()], &config);
+        assert_eq!(sorted.checksum_sha256, presorted.checksum_sha256);
+
+        config.sort_by_timestamp = false;
+        let unsorted = prepare_telemetry_batch_from_events(&[late, tie_b, tie_a], &config);
+        assert_ne!(unsorted.checksum_sha256, sorted.checksum_sha256);
+    }
+
+    #[test]
+    fn content_batch_id_is_stable_across_preparations() {
+        let mut config = build_config();
+        config.informational_sample_rate = 1.0;
+        let events = vec![build_event(0, TelemetrySeverity::High), build_event(1, TelemetrySeverity::Low)];
+
+        let first = prepare_telemetry_batch_from_events(&events, &config);
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        let second = prepare_telemetry_batch_from_events(&events, &config);
+        assert_ne!(first.batch_id, second.batch_id);
+        assert_eq!(first.checksum_sha256, second.checksum_sha256);
+
+        config.batch_id_mode = BatchIdMode::Content;
+        let first = prepare_telemetry_batch_from_events(&events, &config);
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        let second = prepare_telemetry_batch_from_events(&events, &config);
+        assert_eq!(first.batch_id, second.batch_id);
+        assert_eq!(first.batch_id, format!("siem-sensor-{}", &first.checksum_sha256[..16]));
+
+        let other = prepare_telemetry_batch_from_events(&events[..1], &config);
+        assert_ne!(other.batch_id, first.batch_id);
+    }
+
+    #[test]
+    fn redacts_event_fields_before_batching() {
+        let mut config = build_config();
+        config.redaction = RedactionConfig {
+            enabled: true,
+            keys: ["user.name".to_string()].into_iter().collect(),
+            mode: crate::redaction::RedactionMode::Mask,
+        };
+        let mut event = build_event(0, TelemetrySeverity::High);
+        event.message = "login by alice@example.com".to_string();
+        event.fields = vec![
+            super::TelemetryField {
+                key: "user.name".to_string(),
+                value: "alice".to_string(),
+            },
+            super::TelemetryField {
+                key: "process.path".to_string(),
+                value: "/usr/bin/ssh".to_string(),
+            },
+        ];
+
+        let batch = prepare_telemetry_batch_from_events(&[event], &config);
+        let prepared = &batch.events[0];
+        assert_eq!(prepared.message, "login by ***");
+        assert_eq!(prepared.fields[0].value, "***");
+        assert_eq!(prepared.fields[1].value, "/usr/bin/ssh");
+    }
+
+    #[test]
+    fn sorted_checksum_is_independent_of_arrival_order() {
+        let mut config = build_config();
+        config.sort_by_timestamp = true;
+        let events = (0..16)
+            .map(|index| build_event(index, TelemetrySeverity::Medium))
+            .collect::<Vec<TelemetryEvent>>();
+        let reversed = events.iter().rev().cloned().collect::<Vec<TelemetryEvent>>();
+
+        let forward = prepare_telemetry_batch_from_events(&events, &config);
+        let backward = prepare_telemetry_batch_from_events(&reversed, &config);
+        assert_eq!(forward.checksum_sha256, backward.checksum_sha256);
+    }
+
+    #[test]
+    fn suppresses_duplicate_within_window_across_batches() {
+        let mut config = build_config();
+        config.dedup_window_ms = 1_000;
+        let mut dedup = DedupWindow::from_config(&config);
+
+        let original = build_event(1, TelemetrySeverity::Medium);
+        let mut replay = build_event(2, TelemetrySeverity::Medium);
+        replay.timestamp_unix_ms = original.timestamp_unix_ms + 500;
+
+        let first = prepare_telemetry_batch_with_dedup(&[original.clone(), replay], &config, &mut dedup);
+        assert_eq!(first.event_count, 1);
+        assert_eq!(first.deduplicated_count, 1);
+        assert_eq!(first.dropped_count, 0);
+
+        let mut expired = build_event(3, TelemetrySeverity::Medium);
+        expired.timestamp_unix_ms = original.timestamp_unix_ms + 5_000;
+        let second = prepare_telemetry_batch_with_dedup(&[expired], &config, &mut dedup);
+        assert_eq!(second.event_count, 1);
+        assert_eq!(second.deduplicated_count, 0);
+    }
+
+    #[test]
+    fn dedup_window_is_bounded() {
+        let mut dedup = DedupWindow::new(60_000, 4);
+        for index in 0..10 {
+            let mut event = build_event(index, TelemetrySeverity::Low);
+            event.message = format!("message {}", index);
+            assert!(!dedup.check_and_record(&event));
+        }
+        assert_eq!(dedup.len(), 4);
+    }
+
+    #[test]
+    fn ingests_jsonl_directory_with_malformed_lines() {
+        let dir = std::env::temp_dir().join(format!("siem-jsonl-{}-{}", std::process::id(), unix_time_ms()));
+        std::fs::create_dir_all(&dir).expect("create spool dir");
+        std::fs::write(
+            dir.join("a.jsonl"),
+            concat!(
+                r#"{"category":"process","severity":"high","message":"started","fields":{"pid":42,"image":"cmd.exe"},"timestamp_unix_ms":5}"#,
+                "\n",
+                r#"{"category":"network","message":"connect"}"#,
+                "\n"
+            ),
+        )
+        .expect("write good file");
+        std::fs::write(
+            dir.join("b.jsonl"),
+            concat!(
+                "not json\n",
+                r#"{"category":"file","severity":"low","message":"write"}"#,
+                "\n",
+                r#"{"message":"missing category"}"#,
+                "\n"
+            ),
+        )
+        .expect("write malformed file");
+        std::fs::write(dir.join("ignored.txt"), "{}").expect("write ignored file");
+
+        let config = build_config();
+        let ingest = ingest_events_from_dir(&dir, &config);
+        assert_eq!(ingest.events.len(), 3);
+        assert_eq!(ingest.malformed_lines, 2);
+        assert_eq!(ingest.events[0].severity, TelemetrySeverity::High);
+        assert_eq!(ingest.events[0].timestamp_unix_ms, 5);
+        assert!(ingest.events[0].fields.iter().any(|field| field.key == "pid" && field.value == "42"));
+
+        let batch = prepare_telemetry_batch_from_events(&ingest.events, &config);
+        assert_eq!(batch.event_count, 3);
+        complete_ingest(&ingest);
+        assert!(dir.join("a.jsonl.done").exists());
+        assert!(dir.join("b.jsonl.done").exists());
+        assert!(!dir.join("a.jsonl").exists());
+        assert!(dir.join("ignored.txt").exists());
+        assert!(ingest_events_from_dir(&dir, &config).events.is_empty());
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn truncates_oversized_jsonl_file() {
+        let dir = std::env::temp_dir().join(format!("siem-jsonl-big-{}-{}", std::process::id(), unix_time_ms()));
+        std::fs::create_dir_all(&dir).expect("create spool dir");
+        let line = r#"{"category":"process","message":"started"}"#;
+        let contents = (0..10).map(|_| format!("{}\n", line)).collect::<String>();
+        std::fs::write(dir.join("big.jsonl"), contents).expect("write big file");
+
+        let mut config = build_config();
+        config.max_batch_bytes = (line.len() as u64 + 1) * 3 + 5;
+        let ingest = ingest_events_from_dir(&dir, &config);
+        assert_eq!(ingest.events.len(), 3);
+        assert_eq!(ingest.malformed_lines, 0);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
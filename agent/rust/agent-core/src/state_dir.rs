@@ -0,0 +1,278 @@
+use std::env;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+const LOCK_FILE_NAME: &str = "agent-core.lock";
+
+/// Locations of agent-core's on-disk state. Every directory defaults to a subdirectory of
+/// `AGENT_STATE_DIR`; the per-module env vars still override individual paths.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatePaths {
+    pub root: PathBuf,
+    pub uplink_queue: PathBuf,
+    pub staging: PathBuf,
+    pub evidence_stage: PathBuf,
+    pub buffers: PathBuf,
+}
+
+impl StatePaths {
+    pub fn under(root: &Path) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            uplink_queue: root.join("uplink_queue"),
+            staging: root.join("staging"),
+            evidence_stage: root.join("evidence_stage"),
+            buffers: root.join("buffers"),
+        }
+    }
+
+    pub fn from_env() -> Self {
+        let root = env::var("AGENT_STATE_DIR")
+            .ok()
+            .filter(|value| !value.trim().is_empty())
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from("."));
+        let defaults = Self::under(&root);
+        Self {
+            root,
+            uplink_queue: path_override("RUST_UPLINK_QUEUE_DIR").unwrap_or(defaults.uplink_queue),
+            staging: path_override("UPDATE_STAGE_DIR").unwrap_or(defaults.staging),
+            evidence_stage: path_override("EVIDENCE_STAGE_DIR").unwrap_or(defaults.evidence_stage),
+            buffers: path_override("AGENT_BUFFER_DIR").unwrap_or(defaults.buffers),
+        }
+    }
+
+    pub fn telemetry_buffer(&self) -> PathBuf {
+        path_override("TELEMETRY_BUFFER_DIR").unwrap_or_else(|| self.buffers.join("telemetry"))
+    }
+
+    /// Liveness file read by agent-watchdog.
+    pub fn heartbeat_file(&self) -> PathBuf {
+        path_override("AGENT_HEARTBEAT_FILE").unwrap_or_else(|| self.root.join("heartbeat.json"))
+    }
+
+    fn directories(&self) -> [&Path; 5] {
+        [
+            &self.root,
+            &self.uplink_queue,
+            &self.staging,
+            &self.evidence_stage,
+            &self.buffers,
+        ]
+    }
+}
+
+fn path_override(name: &str) -> Option<PathBuf> {
+    env::var(name)
+        .ok()
+        .filter(|value| !value.trim().is_empty())
+        .map(PathBuf::from)
+}
+
+#[derive(Debug)]
+pub enum StateDirError {
+    Io(String),
+    /// Another live agent-core holds the state directory lock.
+    Locked { lock_path: PathBuf, holder_pid: Option<u32> },
+}
+
+impl fmt::Display for StateDirError {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(message) => write!(formatter, "{}", message),
+            Self::Locked {
+                lock_path,
+                holder_pid: Some(pid),
+            } => write!(
+                formatter,
+                "state directory is locked by running agent-core pid {} ({})",
+                pid,
+                lock_path.display()
+            ),
+            Self::Locked { lock_path, holder_pid: None } => write!(
+                formatter,
+                "state directory is locked by another agent-core instance ({})",
+                lock_path.display()
+            ),
+        }
+    }
+}
+
+/// Exclusive owner of the agent state directory for the life of the process. The lock is
+/// held by the OS on the open lock file, so it is released automatically if the holder
+/// crashes and a leftover lock file with a dead pid is simply reclaimed.
+#[derive(Debug)]
+pub struct AgentStateDir {
+    paths: StatePaths,
+    _lock: File,
+}
+
+impl AgentStateDir {
+    pub fn acquire(paths: StatePaths) -> Result<Self, StateDirError> {
+        for dir in paths.directories() {
+            fs::create_dir_all(dir)
+                .map_err(|err| StateDirError::Io(format!("Unable to create {}: {}", dir.display(), err)))?;
+        }
+
+        let lock_path = paths.root.join(LOCK_FILE_NAME);
+        let mut lock = match lock::try_lock(&lock_path) {
+            Ok(Some(file)) => file,
+            Ok(None) => {
+                return Err(StateDirError::Locked {
+                    holder_pid: read_holder_pid(&lock_path),
+                    lock_path,
+                })
+            }
+            Err(err) => {
+                return Err(StateDirError::Io(format!(
+                    "Unable to lock {}: {}",
+                    lock_path.display(),
+                    err
+                )))
+            }
+        };
+        record_pid(&mut lock)
+            .map_err(|err| StateDirError::Io(format!("Unable to write {}: {}", lock_path.display(), err)))?;
+
+        Ok(Self { paths, _lock: lock })
+    }
+
+    pub fn paths(&self) -> &StatePaths {
+        &self.paths
+    }
+}
+
+fn record_pid(lock: &mut File) -> std::io::Result<()> {
+    lock.set_len(0)?;
+    lock.seek(SeekFrom::Start(0))?;
+    write!(lock, "{}", std::process::id())?;
+    lock.sync_all()
+}
+
+fn read_holder_pid(lock_path: &Path) -> Option<u32> {
+    let mut raw = String::new();
+    File::open(lock_path).ok()?.take(32).read_to_string(&mut raw).ok()?;
+    raw.trim().parse::<u32>().ok()
+}
+
+#[cfg(unix)]
+mod lock {
+    use std::fs::{File, OpenOptions};
+    use std::io;
+    use std::os::unix::io::AsRawFd;
+    use std::path::Path;
+
+    /// `flock(LOCK_EX | LOCK_NB)` on the lock file; `None` when another open file holds it.
+    pub fn try_lock(path: &Path) -> io::Result<Option<File>> {
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+        // SAFETY: the descriptor is owned by `file`, which outlives the call.
+        let result = unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) };
+        if result == 0 {
+            return Ok(Some(file));
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() == io::ErrorKind::WouldBlock {
+            Ok(None)
+        } else {
+            Err(err)
+        }
+    }
+}
+
+#[cfg(windows)]
+mod lock {
+    use std::fs::{File, OpenOptions};
+    use std::io;
+    use std::os::windows::fs::OpenOptionsExt;
+    use std::path::Path;
+
+    const ERROR_SHARING_VIOLATION: i32 = 32;
+
+    /// Open the lock file with no sharing, which gives the same exclusivity as `LockFileEx`
+    /// and is released by the OS when the holding process exits.
+    pub fn try_lock(path: &Path) -> io::Result<Option<File>> {
+        let mut options = OpenOptions::new();
+        options.read(true).write(true).create(true).truncate(false).share_mode(0);
+        match options.open(path) {
+            Ok(file) => Ok(Some(file)),
+            Err(err) if err.raw_os_error() == Some(ERROR_SHARING_VIOLATION) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+mod lock {
+    use std::fs::{File, OpenOptions};
+    use std::io;
+    use std::path::Path;
+
+    /// Create-new fallback: the lock file's existence is the lock. A file left by a pid
+    /// other than ours cannot be checked for liveness here, so it is treated as held.
+    pub fn try_lock(path: &Path) -> io::Result<Option<File>> {
+        match OpenOptions::new().read(true).write(true).create_new(true).open(path) {
+            Ok(file) => Ok(Some(file)),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{AgentStateDir, StateDirError, StatePaths, LOCK_FILE_NAME};
+    use crate::time::unix_time_ms;
+
+    fn temp_root(label: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "state-dir-{}-{}-{}",
+            label,
+            std::process::id(),
+            unix_time_ms()
+        ))
+    }
+
+    #[test]
+    fn second_handle_is_refused_until_first_is_dropped() {
+        let root = temp_root("contention");
+        let first = AgentStateDir::acquire(StatePaths::under(&root)).expect("first lock");
+        assert!(first.paths().uplink_queue.is_dir());
+        assert!(first.paths().staging.is_dir());
+        assert!(first.paths().evidence_stage.is_dir());
+        assert!(first.paths().buffers.is_dir());
+
+        match AgentStateDir::acquire(StatePaths::under(&root)) {
+            Err(StateDirError::Locked { holder_pid, .. }) => assert_eq!(holder_pid, Some(std::process::id())),
+            other => panic!("expected lock contention, got {:?}", other),
+        }
+
+        drop(first);
+        assert!(AgentStateDir::acquire(StatePaths::under(&root)).is_ok());
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn reclaims_lock_left_by_dead_process() {
+        let root = temp_root("stale");
+        std::fs::create_dir_all(&root).expect("create root");
+        let mut child = std::process::Command::new(std::env::current_exe().expect("test binary"))
+            .arg("--list")
+            .stdout(std::process::Stdio::null())
+            .spawn()
+            .expect("spawn child");
+        let dead_pid = child.id();
+        child.wait().expect("child exits");
+        std::fs::write(root.join(LOCK_FILE_NAME), dead_pid.to_string()).expect("write stale lock");
+
+        let state = AgentStateDir::acquire(StatePaths::under(&root)).expect("stale lock reclaimed");
+        let recorded = std::fs::read_to_string(root.join(LOCK_FILE_NAME)).expect("lock file");
+        assert_eq!(recorded, std::process::id().to_string());
+
+        drop(state);
+        let _ = std::fs::remove_dir_all(root);
+    }
+}
@@ -0,0 +1,435 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use tracing::warn;
+
+use crate::compression::read_bounded;
+use crate::connectivity;
+use crate::siem::{TelemetryBatch, TelemetrySeverity};
+use crate::telemetry_chain::chain_payload;
+use crate::uplink::{pending_item_count, queue_file_name, UplinkPriority, QUEUE_FORMAT_VERSION};
+
+/// Next sequence number to hand out, kept beside the batches so names are never reused
+/// once the ring drains or the agent restarts.
+const SEQUENCE_FILE: &str = "next-sequence";
+
+#[derive(Debug, Clone)]
+pub struct TelemetryBufferConfig {
+    pub dir: PathBuf,
+    pub max_files: usize,
+    pub max_total_bytes: u64,
+    pub max_pending_uplink_items: usize,
+}
+
+impl TelemetryBufferConfig {
+    /// Limits from the env for the buffer kept in `dir`, normally `StatePaths::telemetry_buffer`.
+    pub fn from_env(dir: &Path) -> Self {
+        let dir = dir.to_path_buf();
+        let max_files = env::var("TELEMETRY_BUFFER_MAX_FILES")
+            .ok()
+            .and_then(|value| value.parse::<usize>().ok())
+            .unwrap_or(256);
+        let max_total_bytes = env::var("TELEMETRY_BUFFER_MAX_BYTES")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .unwrap_or(64 * 1024 * 1024);
+        let max_pending_uplink_items = env::var("TELEMETRY_BUFFER_MAX_PENDING")
+            .ok()
+            .and_then(|value| value.parse::<usize>().ok())
+            .unwrap_or(16);
+
+        Self {
+            dir,
+            max_files,
+            max_total_bytes,
+            max_pending_uplink_items,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct BufferedBatch {
+    path: PathBuf,
+    sequence: u64,
+    bytes: u64,
+    batch_id: String,
+    highest_severity: Option<TelemetrySeverity>,
+}
+
+/// Bounded on-disk ring of prepared batches waiting for room in the uplink queue. When the
+/// ring is full the batch whose most severe event is lowest is evicted first, oldest first
+/// within a severity.
+#[derive(Debug)]
+pub struct TelemetryBuffer {
+    config: TelemetryBufferConfig,
+    entries: Vec<BufferedBatch>,
+    next_sequence: u64,
+}
+
+impl TelemetryBuffer {
+    /// Open the buffer directory and index any batches left by a previous run.
+    pub fn open(config: TelemetryBufferConfig) -> Result<Self, String> {
+        fs::create_dir_all(&config.dir)
+            .map_err(|err| format!("Unable to create telemetry buffer {}: {}", config.dir.display(), err))?;
+        let mut entries = Vec::new();
+        let listing = fs::read_dir(&config.dir)
+            .map_err(|err| format!("Unable to read telemetry buffer {}: {}", config.dir.display(), err))?;
+        for entry in listing.flatten() {
+            let path = entry.path();
+            let sequence = match parse_sequence(&path) {
+                Some(value) => value,
+                None => continue,
+            };
+            match load_entry(&path, sequence, config.max_total_bytes) {
+                Ok(buffered) => entries.push(buffered),
+                Err(err) => warn!(error = %err, path = %path.display(), "skipping unreadable buffered batch"),
+            }
+        }
+        entries.sort_by_key(|entry| entry.sequence);
+        let persisted = fs::read_to_string(config.dir.join(SEQUENCE_FILE))
+            .ok()
+            .and_then(|value| value.trim().parse::<u64>().ok())
+            .unwrap_or(0);
+        let next_sequence = entries
+            .last()
+            .map(|entry| entry.sequence + 1)
+            .unwrap_or(0)
+            .max(persisted);
+
+        Ok(Self {
+            config,
+            entries,
+            next_sequence,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn total_bytes(&self) -> u64 {
+        self.entries.iter().map(|entry| entry.bytes).sum()
+    }
+
+    pub fn batch_ids(&self) -> Vec<String> {
+        self.entries.iter().map(|entry| entry.batch_id.clone()).collect()
+    }
+
+    /// Persist a batch, then evict until the ring is back within its limits. Returns the
+    /// IDs of evicted batches.
+    pub fn push(&mut self, batch: &TelemetryBatch) -> Result<Vec<String>, String> {
+        let raw = serde_json::to_vec(batch).map_err(|err| format!("Unable to serialise batch: {}", err))?;
+        if raw.len() as u64 > self.config.max_total_bytes {
+            return Err(format!("Batch {} exceeds buffer capacity", batch.batch_id));
+        }
+
+        let sequence = self.next_sequence;
+        let path = self.config.dir.join(format!("{:020}.json", sequence));
+        self.next_sequence += 1;
+        write_atomic(
+            &self.config.dir.join(SEQUENCE_FILE),
+            self.next_sequence.to_string().as_bytes(),
+        )?;
+        write_new(&path, &raw)?;
+        self.entries.push(BufferedBatch {
+            path,
+            sequence,
+            bytes: raw.len() as u64,
+            batch_id: batch.batch_id.clone(),
+            highest_severity: batch.severity_counts.highest_accepted(),
+        });
+
+        let mut evicted = Vec::new();
+        while self.entries.len() > self.config.max_files || self.total_bytes() > self.config.max_total_bytes {
+            match self.evict_one() {
+                Some(batch_id) => evicted.push(batch_id),
+                None => break,
+            }
+        }
+        Ok(evicted)
+    }
+
+    /// Move buffered batches, oldest first, into the uplink queue while it holds fewer than
+    /// `max_pending_uplink_items` items. Returns how many were replayed.
+    pub fn replay_into_queue(&mut self, queue_dir: &Path) -> Result<usize, String> {
+        let pending = pending_item_count(queue_dir);
+        let capacity = self.config.max_pending_uplink_items.saturating_sub(pending);
+        if capacity == 0 || self.entries.is_empty() {
+            return Ok(0);
+        }
+        fs::create_dir_all(queue_dir)
+            .map_err(|err| format!("Unable to create uplink queue {}: {}", queue_dir.display(), err))?;
+
+        let mut replayed = 0;
+        while replayed < capacity && !self.entries.is_empty() {
+            let entry = self.entries[0].clone();
+            let payload_json = fs::read_to_string(&entry.path)
+                .map_err(|err| format!("Unable to read {}: {}", entry.path.display(), err))?;
+            let payload_json = chain_payload(payload_json);
+            let item = serde_json::json!({
+                "format_version": QUEUE_FORMAT_VERSION,
+                "kind": "telemetry",
+                "payload_json": payload_json,
+            });
+            let target = queue_dir.join(queue_file_name(&format!("telemetry-{:020}", entry.sequence), UplinkPriority::Low));
+            write_new(&target, item.to_string().as_bytes())?;
+            remove_file(&entry.path);
+            self.entries.remove(0);
+            replayed += 1;
+        }
+        Ok(replayed)
+    }
+
+    /// Buffer the batch and immediately replay whatever the uplink queue has room for, so
+    /// batches reach the queue in order whether or not the uplink is keeping up.
+    pub fn submit(&mut self, batch: &TelemetryBatch, queue_dir: &Path) -> Result<usize, String> {
+        self.push_reporting_evictions(batch)?;
+        self.replay_into_queue(queue_dir)
+    }
+
+    fn push_reporting_evictions(&mut self, batch: &TelemetryBatch) -> Result<(), String> {
+        let evicted = self.push(batch)?;
+        if !evicted.is_empty() {
+            warn!(evicted = ?evicted, "telemetry buffer full; evicted lowest-severity batches");
+        }
+        Ok(())
+    }
+
+    fn evict_one(&mut self) -> Option<String> {
+        let index = self
+            .entries
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, entry)| (entry.highest_severity, entry.sequence))
+            .map(|(index, _)| index)?;
+        let entry = self.entries.remove(index);
+        remove_file(&entry.path);
+        Some(entry.batch_id)
+    }
+}
+
+/// Open the buffer in `buffer_dir` and submit `batch` to it, logging rather than returning
+/// errors. While the uplink is offline the batch is only buffered; the queue is left for the
+/// worker to refill once it reconnects.
+pub fn buffer_batch(batch: &TelemetryBatch, buffer_dir: &Path, queue_dir: &Path) {
+    match TelemetryBuffer::open(TelemetryBufferConfig::from_env(buffer_dir)) {
+        Ok(mut buffer) => {
+            let submitted = if connectivity::is_offline() {
+                buffer.push_reporting_evictions(batch).map(|_| 0)
+            } else {
+                buffer.submit(batch, queue_dir)
+            };
+            if let Err(err) = submitted {
+                warn!(error = %err, "telemetry batch buffering failed");
+            }
+        }
+        Err(err) => warn!(error = %err, "telemetry buffer unavailable"),
+    }
+}
+
+/// Move whatever the uplink queue has room for out of the buffer in `buffer_dir`; used by the
+/// worker's catch-up cycle after a reconnect.
+pub fn replay_buffered(buffer_dir: &Path, queue_dir: &Path) -> usize {
+    let replayed = TelemetryBuffer::open(TelemetryBufferConfig::from_env(buffer_dir))
+        .and_then(|mut buffer| buffer.replay_into_queue(queue_dir));
+    match replayed {
+        Ok(replayed) => replayed,
+        Err(err) => {
+            warn!(error = %err, "telemetry buffer replay failed");
+            0
+        }
+    }
+}
+
+/// A single batch larger than the whole ring could never have been buffered, so anything
+/// over `max_bytes` is treated as corrupt rather than read into memory.
+fn load_entry(path: &Path, sequence: u64, max_bytes: u64) -> Result<BufferedBatch, String> {
+    let file = fs::File::open(path).map_err(|err| err.to_string())?;
+    let raw = read_bounded(file, max_bytes).map_err(|err| err.to_string())?;
+    let batch = serde_json::from_slice::<TelemetryBatch>(&raw).map_err(|err| err.to_string())?;
+    Ok(BufferedBatch {
+        path: path.to_path_buf(),
+        sequence,
+        bytes: raw.len() as u64,
+        batch_id: batch.batch_id,
+        highest_severity: batch.severity_counts.highest_accepted(),
+    })
+}
+
+fn parse_sequence(path: &Path) -> Option<u64> {
+    if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+        return None;
+    }
+    path.file_stem()?.to_str()?.parse::<u64>().ok()
+}
+
+fn write_atomic(path: &Path, bytes: &[u8]) -> Result<(), String> {
+    let staging = path.with_extension("tmp");
+    fs::write(&staging, bytes).map_err(|err| format!("Unable to write {}: {}", staging.display(), err))?;
+    fs::rename(&staging, path).map_err(|err| format!("Unable to move {} into place: {}", path.display(), err))
+}
+
+/// Like `write_atomic`, but fails instead of replacing a file already at `path`.
+fn write_new(path: &Path, bytes: &[u8]) -> Result<(), String> {
+    let staging = path.with_extension("tmp");
+    fs::write(&staging, bytes).map_err(|err| format!("Unable to write {}: {}", staging.display(), err))?;
+    let linked = fs::hard_link(&staging, path);
+    let _ = fs::remove_file(&staging);
+    linked.map_err(|err| format!("Unable to move {} into place: {}", path.display(), err))
+}
+
+fn remove_file(path: &Path) {
+    if let Err(err) = fs::remove_file(path) {
+        warn!(error = %err, path = %path.display(), "failed to remove buffered batch");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::{TelemetryBuffer, TelemetryBufferConfig};
+    use crate::siem::{BatchManifest, SeverityBreakdown, SeverityCounts, TelemetryBatch, TelemetrySeverity};
+    use crate::time::unix_time_ms;
+    use crate::uplink::pending_item_count;
+
+    fn temp_dir(label: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "telemetry-buffer-{}-{}-{}",
+            label,
+            std::process::id(),
+            unix_time_ms()
+        ));
+        std::fs::create_dir_all(&dir).expect("create temp dir");
+        dir
+    }
+
+    fn build_config(dir: &Path, max_files: usize) -> TelemetryBufferConfig {
+        TelemetryBufferConfig {
+            dir: dir.join("buffer"),
+            max_files,
+            max_total_bytes: 1024 * 1024,
+            max_pending_uplink_items: 2,
+        }
+    }
+
+    fn build_batch(batch_id: &str, severity: TelemetrySeverity) -> TelemetryBatch {
+        let mut severity_counts = SeverityBreakdown::default();
+        let accepted = SeverityCounts { accepted: 1, dropped: 0 };
+        match severity {
+            TelemetrySeverity::Informational => severity_counts.informational = accepted,
+            TelemetrySeverity::Low => severity_counts.low = accepted,
+            TelemetrySeverity::Medium => severity_counts.medium = accepted,
+            TelemetrySeverity::High => severity_counts.high = accepted,
+            TelemetrySeverity::Critical => severity_counts.critical = accepted,
+        }
+        TelemetryBatch {
+            batch_id: batch_id.to_string(),
+            stream: "sensor".to_string(),
+            event_count: 0,
+            dropped_count: 0,
+            deduplicated_count: 0,
+            sampled_count: 0,
+            malformed_count: 0,
+            total_payload_bytes: 0,
+            checksum_sha256: "abc".to_string(),
+            prev_checksum: None,
+            created_at_unix_ms: 1,
+            severity_counts,
+            manifest: BatchManifest::default(),
+            events: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn evicts_lowest_severity_then_oldest() {
+        let dir = temp_dir("evict");
+        let mut buffer = TelemetryBuffer::open(build_config(&dir, 3)).expect("open buffer");
+
+        assert!(buffer.push(&build_batch("high-1", TelemetrySeverity::High)).expect("push").is_empty());
+        assert!(buffer.push(&build_batch("info-1", TelemetrySeverity::Informational)).expect("push").is_empty());
+        assert!(buffer.push(&build_batch("info-2", TelemetrySeverity::Informational)).expect("push").is_empty());
+        assert_eq!(buffer.push(&build_batch("critical-1", TelemetrySeverity::Critical)).expect("push"), vec!["info-1"]);
+        assert_eq!(buffer.push(&build_batch("low-1", TelemetrySeverity::Low)).expect("push"), vec!["info-2"]);
+        assert_eq!(buffer.push(&build_batch("medium-1", TelemetrySeverity::Medium)).expect("push"), vec!["low-1"]);
+
+        assert_eq!(buffer.batch_ids(), vec!["high-1", "critical-1", "medium-1"]);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn survives_restart_and_replays_after_recovery() {
+        let dir = temp_dir("replay");
+        let queue_dir = dir.join("queue");
+        std::fs::create_dir_all(&queue_dir).expect("create queue");
+        for index in 0..2 {
+            std::fs::write(queue_dir.join(format!("stuck-{}.json", index)), "{}").expect("write stuck item");
+        }
+
+        {
+            let mut buffer = TelemetryBuffer::open(build_config(&dir, 8)).expect("open buffer");
+            for batch_id in ["batch-a", "batch-b", "batch-c"] {
+                let replayed = buffer
+                    .submit(&build_batch(batch_id, TelemetrySeverity::Low), &queue_dir)
+                    .expect("submit");
+                assert_eq!(replayed, 0);
+            }
+            assert_eq!(buffer.len(), 3);
+        }
+
+        let mut reopened = TelemetryBuffer::open(build_config(&dir, 8)).expect("reopen buffer");
+        assert_eq!(reopened.batch_ids(), vec!["batch-a", "batch-b", "batch-c"]);
+
+        for index in 0..2 {
+            std::fs::remove_file(queue_dir.join(format!("stuck-{}.json", index))).expect("drain queue");
+        }
+        assert_eq!(reopened.replay_into_queue(&queue_dir).expect("replay"), 2);
+        assert_eq!(reopened.batch_ids(), vec!["batch-c"]);
+        assert_eq!(pending_item_count(&queue_dir), 2);
+
+        let first = std::fs::read_to_string(queue_dir.join(format!("lo-telemetry-{:020}.json", 0))).expect("queued item");
+        let item: serde_json::Value = serde_json::from_str(&first).expect("queue item json");
+        assert_eq!(item["kind"], "telemetry");
+        let batch: TelemetryBatch =
+            serde_json::from_str(item["payload_json"].as_str().expect("payload")).expect("batch json");
+        assert_eq!(batch.batch_id, "batch-a");
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn keeps_sequence_numbers_after_the_ring_drains() {
+        let dir = temp_dir("sequence");
+        let queue_dir = dir.join("queue");
+        let config = || TelemetryBufferConfig {
+            max_pending_uplink_items: 8,
+            ..build_config(&dir, 8)
+        };
+        {
+            let mut buffer = TelemetryBuffer::open(config()).expect("open buffer");
+            buffer.submit(&build_batch("batch-a", TelemetrySeverity::Low), &queue_dir).expect("submit");
+            assert_eq!(buffer.len(), 0);
+        }
+
+        // The queued item from before the restart is still waiting; its name must not be reused.
+        let mut reopened = TelemetryBuffer::open(config()).expect("reopen buffer");
+        reopened.submit(&build_batch("batch-b", TelemetrySeverity::Low), &queue_dir).expect("submit");
+        let read_batch = |sequence: u64| {
+            let raw = std::fs::read_to_string(queue_dir.join(format!("lo-telemetry-{:020}.json", sequence))).expect("item");
+            let item: serde_json::Value = serde_json::from_str(&raw).expect("queue item json");
+            serde_json::from_str::<TelemetryBatch>(item["payload_json"].as_str().expect("payload")).expect("batch").batch_id
+        };
+        assert_eq!(read_batch(0), "batch-a");
+        assert_eq!(read_batch(1), "batch-b");
+
+        // An existing queue file is never replaced.
+        std::fs::write(dir.join("buffer").join(super::SEQUENCE_FILE), "1").expect("rewind sequence");
+        let mut rewound = TelemetryBuffer::open(config()).expect("reopen buffer");
+        assert!(rewound.submit(&build_batch("batch-c", TelemetrySeverity::Low), &queue_dir).is_err());
+        assert_eq!(read_batch(1), "batch-b");
+        assert_eq!(rewound.batch_ids(), vec!["batch-c"]);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,257 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::warn;
+
+use crate::crypto::HmacSha256;
+use crate::siem::{hash_batch, TelemetryBatch};
+use crate::state_dir::StatePaths;
+
+/// Optional chaining of telemetry batches: each batch names its predecessor's checksum and
+/// its own checksum covers it, so the control plane can spot dropped or reordered batches.
+#[derive(Debug, Clone)]
+pub struct ChainConfig {
+    pub enabled: bool,
+    pub state_path: PathBuf,
+    /// Keys the chain with HMAC-SHA256; without it links are plain SHA-256.
+    pub key: Option<Vec<u8>>,
+}
+
+impl ChainConfig {
+    pub fn from_env() -> Self {
+        let enabled = env::var("TELEMETRY_CHAIN")
+            .map(|value| matches!(value.trim().to_ascii_lowercase().as_str(), "1" | "true" | "yes"))
+            .unwrap_or(false);
+        let state_path = env::var("TELEMETRY_CHAIN_STATE_PATH")
+            .ok()
+            .filter(|value| !value.trim().is_empty())
+            .map(PathBuf::from)
+            .unwrap_or_else(|| StatePaths::from_env().root.join("telemetry_chain.json"));
+        let key = env::var("TELEMETRY_CHAIN_KEY")
+            .ok()
+            .filter(|value| !value.is_empty())
+            .map(String::into_bytes);
+        Self {
+            enabled,
+            state_path,
+            key,
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ChainState {
+    last_checksum: Option<String>,
+}
+
+/// Link checksum over the previous link and the batch's own event checksum.
+pub fn chain_checksum(key: Option<&[u8]>, prev_checksum: Option<&str>, content_checksum: &str) -> String {
+    let payload = format!("{}\n{}", prev_checksum.unwrap_or_default(), content_checksum);
+    let digest = match key {
+        Some(key) => HmacSha256::new(key).mac(payload.as_bytes()),
+        None => Sha256::digest(payload.as_bytes()).to_vec(),
+    };
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Chain position, persisted after every link so a restart continues the same chain.
+#[derive(Debug)]
+pub struct TelemetryChain {
+    state_path: PathBuf,
+    key: Option<Vec<u8>>,
+    last_checksum: Option<String>,
+}
+
+impl TelemetryChain {
+    /// A missing state file starts a new chain; an unreadable one is an error rather than a
+    /// silent restart that would look like a gap.
+    pub fn open(state_path: &Path, key: Option<Vec<u8>>) -> Result<Self, String> {
+        let last_checksum = match fs::read(state_path) {
+            Ok(raw) => serde_json::from_slice::<ChainState>(&raw)
+                .map_err(|err| format!("Unable to parse {}: {}", state_path.display(), err))?
+                .last_checksum,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+            Err(err) => return Err(format!("Unable to read {}: {}", state_path.display(), err)),
+        };
+        Ok(Self {
+            state_path: state_path.to_path_buf(),
+            key,
+            last_checksum,
+        })
+    }
+
+    pub fn last_checksum(&self) -> Option<&str> {
+        self.last_checksum.as_deref()
+    }
+
+    /// Set `prev_checksum` and replace `checksum_sha256` with the link checksum. The state
+    /// is written before the batch is released so a crash cannot reuse a link.
+    pub fn link(&mut self, batch: &mut TelemetryBatch) -> Result<(), String> {
+        let content_checksum = hash_batch(&batch.events);
+        let checksum = chain_checksum(self.key.as_deref(), self.last_checksum.as_deref(), &content_checksum);
+        let state = ChainState {
+            last_checksum: Some(checksum.clone()),
+        };
+        let raw = serde_json::to_vec(&state).map_err(|err| err.to_string())?;
+        if let Some(parent) = self.state_path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent).map_err(|err| format!("Unable to create {}: {}", parent.display(), err))?;
+        }
+        let staging = self.state_path.with_extension("tmp");
+        fs::write(&staging, raw).map_err(|err| format!("Unable to write {}: {}", staging.display(), err))?;
+        fs::rename(&staging, &self.state_path)
+            .map_err(|err| format!("Unable to move {} into place: {}", self.state_path.display(), err))?;
+
+        batch.prev_checksum = self.last_checksum.replace(checksum.clone());
+        batch.checksum_sha256 = checksum;
+        Ok(())
+    }
+
+    /// Link a serialised batch on its way into the uplink queue and return the new JSON.
+    pub fn link_payload(&mut self, payload_json: &str) -> Result<String, String> {
+        let mut batch = serde_json::from_str::<TelemetryBatch>(payload_json)
+            .map_err(|err| format!("Unable to parse telemetry batch: {}", err))?;
+        self.link(&mut batch)?;
+        serde_json::to_string(&batch).map_err(|err| format!("Unable to serialise batch: {}", err))
+    }
+}
+
+/// Check a delivered sequence: every batch must hash to its checksum and name the batch
+/// before it. The first batch's predecessor is not checked.
+pub fn verify_chain(batches: &[TelemetryBatch], key: Option<&[u8]>) -> Result<(), String> {
+    let mut previous: Option<&str> = None;
+    for (index, batch) in batches.iter().enumerate() {
+        if let Some(expected) = previous {
+            if batch.prev_checksum.as_deref() != Some(expected) {
+                return Err(format!("batch {} ({}) does not follow the previous batch", index, batch.batch_id));
+            }
+        }
+        let recomputed = chain_checksum(key, batch.prev_checksum.as_deref(), &hash_batch(&batch.events));
+        if recomputed != batch.checksum_sha256 {
+            return Err(format!("batch {} ({}) checksum does not match its contents", index, batch.batch_id));
+        }
+        previous = Some(&batch.checksum_sha256);
+    }
+    Ok(())
+}
+
+fn system_chain() -> &'static Mutex<Option<TelemetryChain>> {
+    static CHAIN: OnceLock<Mutex<Option<TelemetryChain>>> = OnceLock::new();
+    CHAIN.get_or_init(|| {
+        let config = ChainConfig::from_env();
+        if !config.enabled {
+            return Mutex::new(None);
+        }
+        match TelemetryChain::open(&config.state_path, config.key) {
+            Ok(chain) => Mutex::new(Some(chain)),
+            Err(err) => {
+                warn!(error = %err, "telemetry chain state unavailable; chaining disabled");
+                Mutex::new(None)
+            }
+        }
+    })
+}
+
+/// Link a buffered batch into the agent-wide chain as it moves to the uplink queue, past
+/// the point where the buffer could still evict it. Returns the payload unchanged unless
+/// `TELEMETRY_CHAIN` is set.
+pub fn chain_payload(payload_json: String) -> String {
+    let Ok(mut chain) = system_chain().lock() else {
+        return payload_json;
+    };
+    match chain.as_mut() {
+        Some(chain) => match chain.link_payload(&payload_json) {
+            Ok(linked) => linked,
+            Err(err) => {
+                warn!(error = %err, "failed to chain telemetry batch");
+                payload_json
+            }
+        },
+        None => payload_json,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{verify_chain, TelemetryChain};
+    use crate::siem::{prepare_telemetry_batch_from_events, TelemetryBatch, TelemetryConfig, TelemetryEvent, TelemetrySeverity};
+    use crate::time::unix_time_ms;
+
+    fn batch(message: &str) -> TelemetryBatch {
+        let event = TelemetryEvent {
+            event_id: format!("evt-{}", message),
+            stream: "sensor".to_string(),
+            category: "process".to_string(),
+            severity: TelemetrySeverity::Medium,
+            timestamp_unix_ms: 1_000,
+            message: message.to_string(),
+            fields: Vec::new(),
+        };
+        prepare_telemetry_batch_from_events(&[event], &TelemetryConfig::from_env())
+    }
+
+    #[test]
+    fn chain_survives_restart_and_detects_a_missing_batch() {
+        let dir = std::env::temp_dir().join(format!("telemetry-chain-{}-{}", std::process::id(), unix_time_ms()));
+        let state_path = dir.join("chain.json");
+        let key = Some(b"chain-key".to_vec());
+
+        let mut batches = Vec::new();
+        let mut chain = TelemetryChain::open(&state_path, key.clone()).expect("new chain");
+        for message in ["one", "two"] {
+            let mut next = batch(message);
+            chain.link(&mut next).expect("link");
+            batches.push(next);
+        }
+        assert!(batches[0].prev_checksum.is_none());
+
+        // A restarted agent picks the chain up where it stopped.
+        let mut chain = TelemetryChain::open(&state_path, key.clone()).expect("reopen chain");
+        assert_eq!(chain.last_checksum(), Some(batches[1].checksum_sha256.as_str()));
+        for message in ["three", "four"] {
+            let mut next = batch(message);
+            chain.link(&mut next).expect("link");
+            batches.push(next);
+        }
+
+        assert_eq!(verify_chain(&batches, key.as_deref()), Ok(()));
+        assert!(verify_chain(&batches, Some(b"other-key")).is_err());
+
+        let mut gapped = batches.clone();
+        gapped.remove(2);
+        let err = verify_chain(&gapped, key.as_deref()).expect_err("gap detected");
+        assert!(err.contains("does not follow"), "{}", err);
+
+        let mut reordered = batches.clone();
+        reordered.swap(1, 2);
+        assert!(verify_chain(&reordered, key.as_deref()).is_err());
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn batches_evicted_before_linking_leave_no_gap() {
+        let dir = std::env::temp_dir().join(format!("telemetry-chain-payload-{}-{}", std::process::id(), unix_time_ms()));
+        let mut chain = TelemetryChain::open(&dir.join("chain.json"), None).expect("new chain");
+        let buffered = ["one", "evicted", "three"]
+            .iter()
+            .map(|message| serde_json::to_string(&batch(message)).expect("batch json"))
+            .collect::<Vec<String>>();
+
+        let delivered = [&buffered[0], &buffered[2]]
+            .iter()
+            .map(|payload| {
+                let linked = chain.link_payload(payload).expect("link payload");
+                serde_json::from_str::<TelemetryBatch>(&linked).expect("linked batch")
+            })
+            .collect::<Vec<TelemetryBatch>>();
+
+        assert_eq!(delivered[1].prev_checksum.as_deref(), Some(delivered[0].checksum_sha256.as_str()));
+        assert_eq!(verify_chain(&delivered, None), Ok(()));
+        let _ = fs::remove_dir_all(dir);
+    }
+}
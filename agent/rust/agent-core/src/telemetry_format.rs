@@ -0,0 +1,362 @@
+use std::env;
+use std::fs::OpenOptions;
+use std::io::{Result as IoResult, Write};
+use std::net::{TcpStream, UdpSocket};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::host_facts::current_host_facts;
+use crate::siem::{TelemetryEvent, TelemetrySeverity};
+use crate::time::format_rfc3339_ms;
+
+const CEF_VENDOR: &str = "Tamsil";
+const CEF_PRODUCT: &str = "agent-core";
+// RFC 5612 documentation enterprise number until a private enterprise number is assigned.
+const SD_ID: &str = "tamsil@32473";
+// RFC 5424 facility local0.
+const SYSLOG_FACILITY: u8 = 16;
+
+/// Render an event as an ArcSight CEF record.
+pub fn to_cef(event: &TelemetryEvent) -> String {
+    let mut extension = vec![
+        format!("rt={}", event.timestamp_unix_ms),
+        format!("externalId={}", escape_cef_extension(&event.event_id)),
+        format!("cat={}", escape_cef_extension(&event.category)),
+        format!("msg={}", escape_cef_extension(&event.message)),
+    ];
+    for field in &event.fields {
+        let key = cef_extension_key(&field.key);
+        if key.is_empty() {
+            continue;
+        }
+        extension.push(format!("{}={}", key, escape_cef_extension(&field.value)));
+    }
+
+    format!(
+        "CEF:0|{}|{}|{}|{}|{}|{}|{}",
+        escape_cef_header(CEF_VENDOR),
+        escape_cef_header(CEF_PRODUCT),
+        escape_cef_header(env!("CARGO_PKG_VERSION")),
+        escape_cef_header(&event.category),
+        escape_cef_header(&event.message),
+        cef_severity(event.severity),
+        extension.join(" ")
+    )
+}
+
+/// Render an event as an RFC 5424 syslog message with fields carried as structured data.
+pub fn to_syslog_rfc5424(event: &TelemetryEvent, hostname: &str, app_name: &str) -> String {
+    let priority = SYSLOG_FACILITY * 8 + syslog_severity(event.severity);
+    let mut params = vec![
+        format!("event_id=\"{}\"", escape_sd_value(&event.event_id)),
+        format!("stream=\"{}\"", escape_sd_value(&event.stream)),
+    ];
+    for field in &event.fields {
+        let name = sd_param_name(&field.key);
+        if name.is_empty() {
+            continue;
+        }
+        params.push(format!("{}=\"{}\"", name, escape_sd_value(&field.value)));
+    }
+
+    format!(
+        "<{}>1 {} {} {} - {} [{} {}] {}",
+        priority,
+        format_rfc3339_ms(event.timestamp_unix_ms),
+        header_token(hostname, 255),
+        header_token(app_name, 48),
+        header_token(&event.category, 32),
+        SD_ID,
+        params.join(" "),
+        event.message
+    )
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyslogTarget {
+    Udp(String),
+    Tcp(String),
+    File(PathBuf),
+}
+
+impl SyslogTarget {
+    /// Parse `udp://host:port`, `tcp://host:port`, `file:///path`, or a bare file path.
+    pub fn parse(value: &str) -> Option<Self> {
+        let trimmed = value.trim();
+        if trimmed.is_empty() {
+            return None;
+        }
+        if let Some(address) = trimmed.strip_prefix("udp://") {
+            return Some(SyslogTarget::Udp(address.to_string()));
+        }
+        if let Some(address) = trimmed.strip_prefix("tcp://") {
+            return Some(SyslogTarget::Tcp(address.to_string()));
+        }
+        let path = trimmed.strip_prefix("file://").unwrap_or(trimmed);
+        Some(SyslogTarget::File(PathBuf::from(path)))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyslogFormat {
+    Cef,
+    Rfc5424,
+}
+
+#[derive(Debug, Clone)]
+pub struct SyslogForwarderConfig {
+    pub target: SyslogTarget,
+    pub format: SyslogFormat,
+    pub app_name: String,
+    pub hostname: String,
+}
+
+impl SyslogForwarderConfig {
+    pub fn from_env() -> Option<Self> {
+        let target = env::var("TELEMETRY_SYSLOG_ENDPOINT")
+            .ok()
+            .and_then(|value| SyslogTarget::parse(&value))?;
+        let format = match env::var("TELEMETRY_SYSLOG_FORMAT")
+            .ok()
+            .map(|value| value.trim().to_lowercase())
+            .as_deref()
+        {
+            Some("cef") => SyslogFormat::Cef,
+            _ => SyslogFormat::Rfc5424,
+        };
+        let app_name = env::var("TELEMETRY_SYSLOG_APP_NAME")
+            .ok()
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+            .unwrap_or_else(|| "agent-core".to_string());
+
+        Some(Self {
+            target,
+            format,
+            app_name,
+            hostname: current_host_facts().hostname,
+        })
+    }
+}
+
+/// Forwards formatted events to a syslog collector or a local file.
+#[derive(Debug, Clone)]
+pub struct LocalSyslogForwarder {
+    config: SyslogForwarderConfig,
+}
+
+impl LocalSyslogForwarder {
+    pub fn new(config: SyslogForwarderConfig) -> Self {
+        Self { config }
+    }
+
+    pub fn from_env() -> Option<Self> {
+        SyslogForwarderConfig::from_env().map(Self::new)
+    }
+
+    pub fn format_event(&self, event: &TelemetryEvent) -> String {
+        match self.config.format {
+            SyslogFormat::Cef => to_cef(event),
+            SyslogFormat::Rfc5424 => to_syslog_rfc5424(event, &self.config.hostname, &self.config.app_name),
+        }
+    }
+
+    /// Send each event as one message and return the number forwarded.
+    pub fn forward(&self, events: &[TelemetryEvent]) -> IoResult<usize> {
+        let messages = events.iter().map(|event| self.format_event(event));
+        match &self.config.target {
+            SyslogTarget::Udp(address) => {
+                let socket = UdpSocket::bind("0.0.0.0:0")?;
+                socket.connect(address)?;
+                let mut sent = 0;
+                for message in messages {
+                    socket.send(message.as_bytes())?;
+                    sent += 1;
+                }
+                Ok(sent)
+            }
+            SyslogTarget::Tcp(address) => {
+                let mut stream = TcpStream::connect(address)?;
+                stream.set_write_timeout(Some(Duration::from_secs(5)))?;
+                let mut sent = 0;
+                for message in messages {
+                    // RFC 6587 octet-counting framing.
+                    write!(stream, "{} {}", message.len(), message)?;
+                    sent += 1;
+                }
+                stream.flush()?;
+                Ok(sent)
+            }
+            SyslogTarget::File(path) => {
+                let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+                let mut sent = 0;
+                for message in messages {
+                    writeln!(file, "{}", message)?;
+                    sent += 1;
+                }
+                Ok(sent)
+            }
+        }
+    }
+}
+
+fn cef_severity(severity: TelemetrySeverity) -> u8 {
+    match severity {
+        TelemetrySeverity::Informational => 1,
+        TelemetrySeverity::Low => 3,
+        TelemetrySeverity::Medium => 5,
+        TelemetrySeverity::High => 8,
+        TelemetrySeverity::Critical => 10,
+    }
+}
+
+fn syslog_severity(severity: TelemetrySeverity) -> u8 {
+    match severity {
+        TelemetrySeverity::Informational => 6,
+        TelemetrySeverity::Low => 5,
+        TelemetrySeverity::Medium => 4,
+        TelemetrySeverity::High => 3,
+        TelemetrySeverity::Critical => 2,
+    }
+}
+
+fn escape_cef_header(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for ch in value.chars() {
+        match ch {
+            '\\' => escaped.push_str("\\\\"),
+            '|' => escaped.push_str("\\|"),
+            '\r' | '\n' => escaped.push(' '),
+            _ => escaped.push(ch),
+        }
+    }
+    escaped
+}
+
+fn escape_cef_extension(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for ch in value.chars() {
+        match ch {
+            '\\' => escaped.push_str("\\\\"),
+            '=' => escaped.push_str("\\="),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            _ => escaped.push(ch),
+        }
+    }
+    escaped
+}
+
+fn cef_extension_key(value: &str) -> String {
+    value.chars().filter(|ch| ch.is_ascii_alphanumeric()).collect()
+}
+
+fn escape_sd_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for ch in value.chars() {
+        if matches!(ch, '"' | '\\' | ']') {
+            escaped.push('\\');
+        }
+        escaped.push(ch);
+    }
+    escaped
+}
+
+fn sd_param_name(value: &str) -> String {
+    value
+        .chars()
+        .filter(|ch| ch.is_ascii_graphic() && !matches!(ch, '=' | ']' | '"'))
+        .take(32)
+        .collect()
+}
+
+fn header_token(value: &str, max_len: usize) -> String {
+    let token = value
+        .chars()
+        .filter(|ch| ch.is_ascii_graphic())
+        .take(max_len)
+        .collect::<String>();
+    if token.is_empty() {
+        "-".to_string()
+    } else {
+        token
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::UdpSocket;
+    use std::time::Duration;
+
+    use super::{to_cef, to_syslog_rfc5424, LocalSyslogForwarder, SyslogForwarderConfig, SyslogFormat, SyslogTarget};
+    use crate::siem::{TelemetryEvent, TelemetryField, TelemetrySeverity};
+
+    fn build_event() -> TelemetryEvent {
+        TelemetryEvent {
+            event_id: "evt-1".to_string(),
+            stream: "sensor".to_string(),
+            category: "proc|start".to_string(),
+            severity: TelemetrySeverity::High,
+            timestamp_unix_ms: 1_700_000_000_123,
+            message: "ran C:\\tmp\\a.exe | b=c".to_string(),
+            fields: vec![
+                TelemetryField {
+                    key: "cmd".to_string(),
+                    value: "x=\"y\" [z]".to_string(),
+                },
+                TelemetryField {
+                    key: "user.name".to_string(),
+                    value: "svc\\admin".to_string(),
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn formats_cef_with_escaping() {
+        let expected = format!(
+            "CEF:0|Tamsil|agent-core|{}|proc\\|start|ran C:\\\\tmp\\\\a.exe \\| b=c|8|rt=1700000000123 externalId=evt-1 cat=proc|start msg=ran C:\\\\tmp\\\\a.exe | b\\=c cmd=x\\=\"y\" [z] username=svc\\\\admin",
+            env!("CARGO_PKG_VERSION")
+        );
+        assert_eq!(to_cef(&build_event()), expected);
+    }
+
+    #[test]
+    fn formats_rfc5424_with_structured_data() {
+        let expected = "<131>1 2023-11-14T22:13:20.123Z host-1 agent-core - proc|start [tamsil@32473 event_id=\"evt-1\" stream=\"sensor\" cmd=\"x=\\\"y\\\" [z\\]\" user.name=\"svc\\\\admin\"] ran C:\\tmp\\a.exe | b=c";
+        assert_eq!(to_syslog_rfc5424(&build_event(), "host-1", "agent-core"), expected);
+    }
+
+    #[test]
+    fn parses_syslog_targets() {
+        assert_eq!(SyslogTarget::parse("udp://127.0.0.1:514"), Some(SyslogTarget::Udp("127.0.0.1:514".to_string())));
+        assert_eq!(SyslogTarget::parse("tcp://collector:601"), Some(SyslogTarget::Tcp("collector:601".to_string())));
+        assert_eq!(
+            SyslogTarget::parse("file:///var/log/agent.log"),
+            Some(SyslogTarget::File("/var/log/agent.log".into()))
+        );
+        assert_eq!(SyslogTarget::parse("  "), None);
+    }
+
+    #[test]
+    fn forwards_over_udp() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").expect("bind receiver");
+        receiver.set_read_timeout(Some(Duration::from_secs(2))).expect("set timeout");
+        let address = receiver.local_addr().expect("local addr").to_string();
+        let forwarder = LocalSyslogForwarder::new(SyslogForwarderConfig {
+            target: SyslogTarget::Udp(address),
+            format: SyslogFormat::Rfc5424,
+            app_name: "agent-core".to_string(),
+            hostname: "host-1".to_string(),
+        });
+        let event = build_event();
+
+        assert_eq!(forwarder.forward(std::slice::from_ref(&event)).expect("forward"), 1);
+        let mut buffer = [0_u8; 2048];
+        let received = receiver.recv(&mut buffer).expect("receive datagram");
+        assert_eq!(
+            std::str::from_utf8(&buffer[..received]).expect("utf8"),
+            to_syslog_rfc5424(&event, "host-1", "agent-core")
+        );
+    }
+}
@@ -1,7 +1,11 @@
+use std::collections::HashMap;
 use std::env;
 
+use sha2::{Digest, Sha256};
+
 use crate::identity::AgentIdentity;
-use crate::policy::PolicyBundle;
+use crate::policy::{PolicyBundle, PolicyExpiryState};
+use crate::rate_limit::KeyedRateLimiter;
 use crate::security::{validate_bounded_string, ValidationLimits};
 use crate::time::unix_time_ms;
 
@@ -11,6 +15,9 @@ pub struct TelemetryPayload {
     pub payload_bytes: usize,
     pub event_count: usize,
     pub checksum_sha256: Option<String>,
+    pub raw_payload: Option<Vec<u8>>,
+    /// Event category, checked against the policy's `stream_categories` for the stream.
+    pub category: Option<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -20,6 +27,23 @@ pub struct TelemetryRouteDecision {
     pub routed_at_unix_ms: u64,
     pub stream: String,
     pub payload_bytes: usize,
+    pub rejection: Option<TelemetryRejection>,
+}
+
+/// Structured rejection detail for callers that need more than the reason string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TelemetryRejection {
+    QuotaExceeded { stream: String, retry_after_ms: u64 },
+    CategoryNotPermitted { stream: String, category: Option<String> },
+    PolicyExpired { expires_at_unix_time_ms: u64 },
+}
+
+/// Per-stream budgets enforced over a rolling one-minute window. `None` leaves that
+/// dimension unlimited.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StreamQuota {
+    pub max_bytes_per_minute: Option<u64>,
+    pub max_events_per_minute: Option<u64>,
 }
 
 #[derive(Debug, Clone)]
@@ -28,6 +52,9 @@ pub struct TelemetryRouteConfig {
     pub min_payload_bytes: usize,
     pub max_event_count: usize,
     pub require_checksum: bool,
+    pub stream_quotas: HashMap<String, StreamQuota>,
+    /// Refuse all telemetry while the loaded policy is expired.
+    pub require_valid_policy: bool,
 }
 
 impl TelemetryRouteConfig {
@@ -49,21 +76,128 @@ impl TelemetryRouteConfig {
             .ok()
             .map(|value| value.eq_ignore_ascii_case("true"))
             .unwrap_or(false);
+        let stream_quotas = env::var("TELEMETRY_STREAM_QUOTAS")
+            .ok()
+            .map(|value| parse_stream_quotas(&value))
+            .unwrap_or_default();
+        let require_valid_policy = env::var("TELEMETRY_REQUIRE_VALID_POLICY")
+            .ok()
+            .map(|value| value.eq_ignore_ascii_case("true"))
+            .unwrap_or(false);
 
         Self {
             max_payload_bytes,
             min_payload_bytes,
             max_event_count,
             require_checksum,
+            stream_quotas,
+            require_valid_policy,
         }
     }
 }
 
+/// Parse `stream=bytes:events` pairs separated by commas, e.g. `sensor=1048576:500`.
+/// Either side may be empty to leave it unlimited; malformed entries are skipped.
+pub fn parse_stream_quotas(raw: &str) -> HashMap<String, StreamQuota> {
+    let mut quotas = HashMap::new();
+    for entry in raw.split(',') {
+        let (stream, limits) = match entry.split_once('=') {
+            Some((stream, limits)) => (stream.trim(), limits.trim()),
+            None => continue,
+        };
+        if stream.is_empty() {
+            continue;
+        }
+        let (bytes, events) = limits.split_once(':').unwrap_or((limits, ""));
+        let max_bytes_per_minute = bytes.trim().parse::<u64>().ok();
+        let max_events_per_minute = events.trim().parse::<u64>().ok();
+        if max_bytes_per_minute.is_none() && max_events_per_minute.is_none() {
+            continue;
+        }
+        quotas.insert(
+            stream.to_string(),
+            StreamQuota {
+                max_bytes_per_minute,
+                max_events_per_minute,
+            },
+        );
+    }
+    quotas
+}
+
+const QUOTA_WINDOW_MS: u64 = 60_000;
+
+/// Telemetry router that keeps per-stream quota usage across calls.
+#[derive(Debug)]
+pub struct TelemetryRouter {
+    identity: AgentIdentity,
+    config: TelemetryRouteConfig,
+    quotas: KeyedRateLimiter,
+}
+
+impl TelemetryRouter {
+    pub fn new(identity: AgentIdentity, config: TelemetryRouteConfig) -> Self {
+        Self {
+            identity,
+            config,
+            quotas: KeyedRateLimiter::new(QUOTA_WINDOW_MS),
+        }
+    }
+
+    pub fn from_env() -> Self {
+        let identity = AgentIdentity::new("asset-placeholder".to_string(), "agent-core".to_string());
+        Self::new(identity, TelemetryRouteConfig::from_env())
+    }
+
+    pub fn route(&mut self, payload: TelemetryPayload, policy: &PolicyBundle) -> TelemetryRouteDecision {
+        self.route_at(payload, policy, unix_time_ms())
+    }
+
+    pub fn route_at(&mut self, payload: TelemetryPayload, policy: &PolicyBundle, now: u64) -> TelemetryRouteDecision {
+        // A sensor batch counts every event it carries against the stream's event budget.
+        let event_count = payload.event_count as u64;
+        let decision = validate_telemetry(payload, policy, &self.identity, &self.config, now);
+        if !decision.accepted {
+            return decision;
+        }
+        match self.enforce_quota(&decision.stream, decision.payload_bytes as u64, event_count, now) {
+            Ok(()) => decision,
+            Err(retry_after_ms) => TelemetryRouteDecision {
+                accepted: false,
+                reason: "Telemetry stream quota exceeded".to_string(),
+                rejection: Some(TelemetryRejection::QuotaExceeded {
+                    stream: decision.stream.clone(),
+                    retry_after_ms,
+                }),
+                ..decision
+            },
+        }
+    }
+
+    /// Both budgets are checked before either is consumed so a rejected payload does not
+    /// eat into the stream's remaining allowance.
+    fn enforce_quota(&mut self, stream: &str, bytes: u64, events: u64, now: u64) -> Result<(), u64> {
+        let quota = match self.config.stream_quotas.get(stream) {
+            Some(quota) => quota,
+            None => return Ok(()),
+        };
+        let bytes_key = format!("{}:bytes", stream);
+        let events_key = format!("{}:events", stream);
+        if let Some(limit) = quota.max_bytes_per_minute {
+            self.quotas.check(&bytes_key, bytes, limit, now)?;
+        }
+        if let Some(limit) = quota.max_events_per_minute {
+            self.quotas.check(&events_key, events, limit, now)?;
+        }
+        self.quotas.consume(&bytes_key, bytes, now);
+        self.quotas.consume(&events_key, events, now);
+        Ok(())
+    }
+}
+
 pub fn route_telemetry(payload: TelemetryPayload, policy: &PolicyBundle) -> bool {
-    let identity = AgentIdentity::new("asset-placeholder".to_string(), "agent-core".to_string());
-    let config = TelemetryRouteConfig::from_env();
-    let decision = route_telemetry_with_context(payload, policy, &identity, &config);
-    decision.accepted
+    let mut router = TelemetryRouter::from_env();
+    router.route(payload, policy).accepted
 }
 
 pub fn route_telemetry_with_context(
@@ -71,9 +205,32 @@ pub fn route_telemetry_with_context(
     policy: &PolicyBundle,
     identity: &AgentIdentity,
     config: &TelemetryRouteConfig,
+) -> TelemetryRouteDecision {
+    let mut router = TelemetryRouter::new(identity.clone(), config.clone());
+    router.route(payload, policy)
+}
+
+fn validate_telemetry(
+    payload: TelemetryPayload,
+    policy: &PolicyBundle,
+    identity: &AgentIdentity,
+    config: &TelemetryRouteConfig,
+    now: u64,
 ) -> TelemetryRouteDecision {
     let limits = ValidationLimits::default_limits();
-    let now = unix_time_ms();
+
+    if config.require_valid_policy && policy.expiry_state(now, 0) == PolicyExpiryState::Expired {
+        return TelemetryRouteDecision {
+            accepted: false,
+            reason: "Telemetry policy expired".to_string(),
+            routed_at_unix_ms: now,
+            stream: payload.stream,
+            payload_bytes: payload.payload_bytes,
+            rejection: Some(TelemetryRejection::PolicyExpired {
+                expires_at_unix_time_ms: policy.expires_at_unix_time_ms,
+            }),
+        };
+    }
 
     if !validate_bounded_string(&payload.stream, limits.max_stream_len) {
         return TelemetryRouteDecision {
@@ -82,6 +239,7 @@ pub fn route_telemetry_with_context(
             routed_at_unix_ms: now,
             stream: payload.stream,
             payload_bytes: payload.payload_bytes,
+            rejection: None,
         };
     }
 
@@ -96,6 +254,21 @@ pub fn route_telemetry_with_context(
             routed_at_unix_ms: now,
             stream: payload.stream,
             payload_bytes: payload.payload_bytes,
+            rejection: None,
+        };
+    }
+
+    if !policy.allows_category(&payload.stream, payload.category.as_deref()) {
+        return TelemetryRouteDecision {
+            accepted: false,
+            reason: "Telemetry category not permitted for stream".to_string(),
+            routed_at_unix_ms: now,
+            rejection: Some(TelemetryRejection::CategoryNotPermitted {
+                stream: payload.stream.clone(),
+                category: payload.category,
+            }),
+            stream: payload.stream,
+            payload_bytes: payload.payload_bytes,
         };
     }
 
@@ -106,6 +279,7 @@ pub fn route_telemetry_with_context(
             routed_at_unix_ms: now,
             stream: payload.stream,
             payload_bytes: payload.payload_bytes,
+            rejection: None,
         };
     }
 
@@ -116,6 +290,7 @@ pub fn route_telemetry_with_context(
             routed_at_unix_ms: now,
             stream: payload.stream,
             payload_bytes: payload.payload_bytes,
+            rejection: None,
         };
     }
 
@@ -126,6 +301,7 @@ pub fn route_telemetry_with_context(
             routed_at_unix_ms: now,
             stream: payload.stream,
             payload_bytes: payload.payload_bytes,
+            rejection: None,
         };
     }
 
@@ -136,9 +312,26 @@ pub fn route_telemetry_with_context(
             routed_at_unix_ms: now,
             stream: payload.stream,
             payload_bytes: payload.payload_bytes,
+            rejection: None,
         };
     }
 
+    if let (Some(expected), Some(raw_payload)) = (
+        payload.checksum_sha256.as_ref().filter(|value| !value.trim().is_empty()),
+        payload.raw_payload.as_ref(),
+    ) {
+        if !sha256_hex(raw_payload).eq_ignore_ascii_case(expected.trim()) {
+            return TelemetryRouteDecision {
+                accepted: false,
+                reason: "Telemetry checksum mismatch".to_string(),
+                routed_at_unix_ms: now,
+                stream: payload.stream,
+                payload_bytes: payload.payload_bytes,
+                rejection: None,
+            };
+        }
+    }
+
     let _identity_tag = format!("{}:{}", identity.asset_id, identity.agent_id);
 
     TelemetryRouteDecision {
@@ -147,12 +340,31 @@ pub fn route_telemetry_with_context(
         routed_at_unix_ms: now,
         stream: payload.stream,
         payload_bytes: payload.payload_bytes,
+        rejection: None,
     }
 }
 
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    hex_encode(Sha256::digest(bytes))
+}
+
+fn hex_encode(bytes: impl AsRef<[u8]>) -> String {
+    bytes
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<Vec<String>>()
+        .join("")
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{route_telemetry, route_telemetry_with_context, TelemetryPayload, TelemetryRouteConfig};
+    use std::collections::HashMap;
+
+    use super::{
+        parse_stream_quotas, route_telemetry, route_telemetry_with_context, sha256_hex, StreamQuota,
+        TelemetryPayload, TelemetryRejection, TelemetryRouteConfig, TelemetryRouter,
+    };
     use crate::identity::AgentIdentity;
     use crate::policy::{ExecutionPolicy, PolicyBundle};
 
@@ -168,8 +380,12 @@ mod tests {
                 allowed_actions: vec!["patch-apply".to_string(), "script-run".to_string()],
                 max_arguments: 2,
                 max_argument_length: 8,
+                argument_rules: std::collections::BTreeMap::new(),
+                file_destinations: Vec::new(),
             },
             telemetry_streams: vec!["agent".to_string(), "sensor".to_string()],
+            stream_categories: std::collections::BTreeMap::new(),
+            evidence_profiles: std::collections::BTreeMap::new(),
         }
     }
 
@@ -181,6 +397,8 @@ mod tests {
             payload_bytes: 12,
             event_count: 1,
             checksum_sha256: Some("hash".to_string()),
+            raw_payload: None,
+            category: None,
         };
         assert!(route_telemetry(payload, &policy));
     }
@@ -193,6 +411,8 @@ mod tests {
             payload_bytes: 12,
             event_count: 1,
             checksum_sha256: Some("hash".to_string()),
+            raw_payload: None,
+            category: None,
         };
         assert!(!route_telemetry(payload, &policy));
     }
@@ -205,6 +425,8 @@ mod tests {
             payload_bytes: 0,
             event_count: 1,
             checksum_sha256: Some("hash".to_string()),
+            raw_payload: None,
+            category: None,
         };
         assert!(!route_telemetry(payload, &policy));
     }
@@ -217,15 +439,254 @@ mod tests {
             payload_bytes: 12,
             event_count: 1,
             checksum_sha256: None,
+            raw_payload: None,
+            category: None,
         };
         let config = TelemetryRouteConfig {
             max_payload_bytes: 128,
             min_payload_bytes: 1,
             max_event_count: 10,
             require_checksum: true,
+            stream_quotas: HashMap::new(),
+            require_valid_policy: false,
         };
         let identity = AgentIdentity::new("asset-1".to_string(), "agent-1".to_string());
         let decision = route_telemetry_with_context(payload, &policy, &identity, &config);
         assert!(!decision.accepted);
     }
+
+    fn build_config(require_checksum: bool) -> TelemetryRouteConfig {
+        TelemetryRouteConfig {
+            max_payload_bytes: 128,
+            min_payload_bytes: 1,
+            max_event_count: 10,
+            require_checksum,
+            stream_quotas: HashMap::new(),
+            require_valid_policy: false,
+        }
+    }
+
+    #[test]
+    fn accepts_matching_checksum() {
+        let policy = build_policy();
+        let raw = b"sensor-event".to_vec();
+        let payload = TelemetryPayload {
+            stream: "sensor".to_string(),
+            payload_bytes: raw.len(),
+            event_count: 1,
+            checksum_sha256: Some(sha256_hex(&raw).to_uppercase()),
+            raw_payload: Some(raw),
+            category: None,
+        };
+        let identity = AgentIdentity::new("asset-1".to_string(), "agent-1".to_string());
+        let decision = route_telemetry_with_context(payload, &policy, &identity, &build_config(true));
+        assert!(decision.accepted);
+    }
+
+    #[test]
+    fn rejects_mismatched_checksum() {
+        let policy = build_policy();
+        let raw = b"sensor-event".to_vec();
+        let payload = TelemetryPayload {
+            stream: "sensor".to_string(),
+            payload_bytes: raw.len(),
+            event_count: 1,
+            checksum_sha256: Some(sha256_hex(b"tampered")),
+            raw_payload: Some(raw),
+            category: None,
+        };
+        let identity = AgentIdentity::new("asset-1".to_string(), "agent-1".to_string());
+        let decision = route_telemetry_with_context(payload, &policy, &identity, &build_config(false));
+        assert!(!decision.accepted);
+        assert_eq!(decision.reason, "Telemetry checksum mismatch");
+    }
+
+    #[test]
+    fn accepts_missing_checksum_when_not_required() {
+        let policy = build_policy();
+        let raw = b"sensor-event".to_vec();
+        let payload = TelemetryPayload {
+            stream: "sensor".to_string(),
+            payload_bytes: raw.len(),
+            event_count: 1,
+            checksum_sha256: None,
+            raw_payload: Some(raw),
+            category: None,
+        };
+        let identity = AgentIdentity::new("asset-1".to_string(), "agent-1".to_string());
+        let decision = route_telemetry_with_context(payload, &policy, &identity, &build_config(false));
+        assert!(decision.accepted);
+    }
+
+    #[test]
+    fn enforces_policy_stream_categories() {
+        let mut policy = build_policy();
+        policy.stream_categories.insert(
+            "sensor".to_string(),
+            vec!["file".to_string(), "network".to_string(), "process".to_string()],
+        );
+        let identity = AgentIdentity::new("asset-1".to_string(), "agent-1".to_string());
+        let mut router = TelemetryRouter::new(identity, build_config(false));
+
+        let mut permitted = build_payload("sensor", 10);
+        permitted.category = Some("process".to_string());
+        assert!(router.route_at(permitted, &policy, 1).accepted);
+
+        let mut forbidden = build_payload("sensor", 10);
+        forbidden.category = Some("registry".to_string());
+        let decision = router.route_at(forbidden, &policy, 1);
+        assert!(!decision.accepted);
+        assert_eq!(
+            decision.rejection,
+            Some(TelemetryRejection::CategoryNotPermitted {
+                stream: "sensor".to_string(),
+                category: Some("registry".to_string()),
+            })
+        );
+
+        assert!(!router.route_at(build_payload("sensor", 10), &policy, 1).accepted);
+        assert!(router.route_at(build_payload("agent", 10), &policy, 1).accepted);
+    }
+
+    #[test]
+    fn rejects_telemetry_under_expired_policy_when_required() {
+        let mut policy = build_policy();
+        policy.expires_at_unix_time_ms = 1_000;
+        let identity = AgentIdentity::new("asset-1".to_string(), "agent-1".to_string());
+
+        let mut lenient = TelemetryRouter::new(identity.clone(), build_config(false));
+        assert!(lenient.route_at(build_payload("sensor", 10), &policy, 2_000).accepted);
+
+        let mut config = build_config(false);
+        config.require_valid_policy = true;
+        let mut strict = TelemetryRouter::new(identity, config);
+        assert!(strict.route_at(build_payload("sensor", 10), &policy, 1_000).accepted);
+        let decision = strict.route_at(build_payload("sensor", 10), &policy, 1_001);
+        assert!(!decision.accepted);
+        assert_eq!(decision.reason, "Telemetry policy expired");
+        assert_eq!(
+            decision.rejection,
+            Some(TelemetryRejection::PolicyExpired {
+                expires_at_unix_time_ms: 1_000
+            })
+        );
+    }
+
+    fn build_payload(stream: &str, payload_bytes: usize) -> TelemetryPayload {
+        TelemetryPayload {
+            stream: stream.to_string(),
+            payload_bytes,
+            event_count: 1,
+            checksum_sha256: None,
+            raw_payload: None,
+            category: None,
+        }
+    }
+
+    #[test]
+    fn parses_stream_quotas() {
+        let quotas = parse_stream_quotas("sensor=1024:10, agent=:5, bad, =1:1, empty=:");
+        assert_eq!(quotas.len(), 2);
+        assert_eq!(
+            quotas.get("sensor"),
+            Some(&StreamQuota {
+                max_bytes_per_minute: Some(1024),
+                max_events_per_minute: Some(10),
+            })
+        );
+        assert_eq!(quotas.get("agent").and_then(|quota| quota.max_bytes_per_minute), None);
+        assert_eq!(quotas.get("agent").and_then(|quota| quota.max_events_per_minute), Some(5));
+    }
+
+    #[test]
+    fn enforces_quota_per_stream_and_recovers_after_window() {
+        let policy = build_policy();
+        let mut config = build_config(false);
+        config.stream_quotas.insert(
+            "sensor".to_string(),
+            StreamQuota {
+                max_bytes_per_minute: Some(100),
+                max_events_per_minute: Some(3),
+            },
+        );
+        let identity = AgentIdentity::new("asset-1".to_string(), "agent-1".to_string());
+        let mut router = TelemetryRouter::new(identity, config);
+        let start = 1_000_000;
+
+        for offset in 0..3 {
+            assert!(router.route_at(build_payload("sensor", 10), &policy, start + offset).accepted);
+        }
+        let rejected = router.route_at(build_payload("sensor", 10), &policy, start + 10_000);
+        assert!(!rejected.accepted);
+        assert_eq!(
+            rejected.rejection,
+            Some(TelemetryRejection::QuotaExceeded {
+                stream: "sensor".to_string(),
+                retry_after_ms: 50_000,
+            })
+        );
+
+        for offset in 0..5 {
+            assert!(router.route_at(build_payload("agent", 100), &policy, start + offset).accepted);
+        }
+
+        let recovered = router.route_at(build_payload("sensor", 10), &policy, start + 60_000);
+        assert!(recovered.accepted);
+        assert_eq!(recovered.rejection, None);
+    }
+
+    #[test]
+    fn byte_quota_rejection_does_not_consume_event_budget() {
+        let policy = build_policy();
+        let mut config = build_config(false);
+        config.stream_quotas.insert(
+            "sensor".to_string(),
+            StreamQuota {
+                max_bytes_per_minute: Some(50),
+                max_events_per_minute: Some(2),
+            },
+        );
+        let identity = AgentIdentity::new("asset-1".to_string(), "agent-1".to_string());
+        let mut router = TelemetryRouter::new(identity, config);
+
+        assert!(!router.route_at(build_payload("sensor", 80), &policy, 0).accepted);
+        assert!(router.route_at(build_payload("sensor", 20), &policy, 1).accepted);
+        assert!(router.route_at(build_payload("sensor", 20), &policy, 2).accepted);
+        assert!(!router.route_at(build_payload("sensor", 1), &policy, 3).accepted);
+    }
+
+    #[test]
+    fn batches_consume_one_event_of_quota_per_event() {
+        let policy = build_policy();
+        let mut config = build_config(false);
+        config.stream_quotas.insert(
+            "sensor".to_string(),
+            StreamQuota {
+                max_bytes_per_minute: None,
+                max_events_per_minute: Some(5),
+            },
+        );
+        let identity = AgentIdentity::new("asset-1".to_string(), "agent-1".to_string());
+        let mut router = TelemetryRouter::new(identity, config);
+        let batch = |event_count| TelemetryPayload {
+            event_count,
+            ..build_payload("sensor", 10)
+        };
+
+        assert!(router.route_at(batch(4), &policy, 0).accepted);
+        assert!(!router.route_at(batch(2), &policy, 1).accepted);
+        assert!(router.route_at(batch(1), &policy, 2).accepted);
+    }
+
+    #[test]
+    fn leaves_streams_without_quota_unlimited() {
+        let mut config = build_config(false);
+        config.stream_quotas = HashMap::new();
+        let policy = build_policy();
+        let identity = AgentIdentity::new("asset-1".to_string(), "agent-1".to_string());
+        let mut router = TelemetryRouter::new(identity, config);
+        for offset in 0..50 {
+            assert!(router.route_at(build_payload("sensor", 100), &policy, offset).accepted);
+        }
+    }
 }
@@ -1,4 +1,11 @@
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::env;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use tracing::warn;
+
+/// Default allowance for clock skew between the control plane and the agent.
+pub const DEFAULT_CLOCK_SKEW_TOLERANCE_MS: u64 = 5_000;
 
 pub fn unix_time_ms() -> u64 {
     SystemTime::now()
@@ -6,3 +13,326 @@ pub fn unix_time_ms() -> u64 {
         .unwrap_or_default()
         .as_millis() as u64
 }
+
+/// Milliseconds since the first call in this process. Never goes backwards, so it suits
+/// rate-limit and suppression windows that must not reopen when the wall clock is set back.
+pub fn monotonic_ms() -> u64 {
+    static START: OnceLock<Instant> = OnceLock::new();
+    START.get_or_init(Instant::now).elapsed().as_millis() as u64
+}
+
+/// Clock-skew tolerance from `AGENT_CLOCK_SKEW_TOLERANCE_MS`, shared by policy and command
+/// time-window checks.
+pub fn clock_skew_tolerance_ms_from_env() -> u64 {
+    env::var("AGENT_CLOCK_SKEW_TOLERANCE_MS")
+        .ok()
+        .and_then(|value| value.parse::<u64>().ok())
+        .unwrap_or(DEFAULT_CLOCK_SKEW_TOLERANCE_MS)
+}
+
+/// Extra allowance on the start of a time window while the wall clock is recovering from
+/// a backward jump (see [`ClockMonitorConfig`]). A clock set back makes valid documents
+/// look not-yet-valid; a clock running ahead never earns extra time past an expiry.
+pub fn not_before_widening_ms() -> u64 {
+    observe_system_clock().1
+}
+
+/// Source of wall-clock time, replaceable so tests can simulate jumps.
+pub trait WallClock: Send {
+    fn unix_time_ms(&self) -> u64;
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl WallClock for SystemClock {
+    fn unix_time_ms(&self) -> u64 {
+        unix_time_ms()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClockStatus {
+    Synced,
+    /// The wall clock fell behind the monotonic clock by `delta_ms`.
+    JumpedBackwards { delta_ms: u64 },
+    /// The wall clock ran ahead of the monotonic clock by `delta_ms`, as after a VM resume
+    /// or a large NTP step.
+    Suspect { delta_ms: u64 },
+}
+
+impl ClockStatus {
+    /// Size of the jump in either direction; 0 while synced.
+    pub fn delta_ms(&self) -> u64 {
+        match self {
+            Self::Synced => 0,
+            Self::JumpedBackwards { delta_ms } | Self::Suspect { delta_ms } => *delta_ms,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ClockMonitorConfig {
+    /// Wall/monotonic disagreement between two readings that counts as a jump.
+    pub jump_threshold_ms: u64,
+    /// How long a jump keeps tolerances widened.
+    pub settle_ms: u64,
+    /// Upper bound on the widening, so a wildly wrong clock cannot open every window.
+    pub max_widening_ms: u64,
+}
+
+impl ClockMonitorConfig {
+    pub fn from_env() -> Self {
+        let read = |name: &str, default: u64| {
+            env::var(name)
+                .ok()
+                .and_then(|value| value.parse::<u64>().ok())
+                .unwrap_or(default)
+        };
+        Self {
+            jump_threshold_ms: read("AGENT_CLOCK_JUMP_THRESHOLD_MS", 5_000).max(1),
+            settle_ms: read("AGENT_CLOCK_JUMP_SETTLE_MS", 300_000),
+            max_widening_ms: read("AGENT_CLOCK_MAX_WIDENING_MS", 900_000),
+        }
+    }
+}
+
+/// Compares successive wall-clock readings with the monotonic time elapsed between them.
+/// A disagreement above the threshold is a jump; the status stays non-`Synced` for the
+/// settle period after the last jump.
+#[derive(Debug)]
+pub struct ClockMonitor<C: WallClock> {
+    clock: C,
+    config: ClockMonitorConfig,
+    baseline: Option<(u64, u64)>,
+    status: ClockStatus,
+    status_since_monotonic_ms: u64,
+    jumps: u64,
+}
+
+impl<C: WallClock> ClockMonitor<C> {
+    pub fn new(clock: C, config: ClockMonitorConfig) -> Self {
+        Self {
+            clock,
+            config,
+            baseline: None,
+            status: ClockStatus::Synced,
+            status_since_monotonic_ms: 0,
+            jumps: 0,
+        }
+    }
+
+    pub fn jumps(&self) -> u64 {
+        self.jumps
+    }
+
+    /// Extra tolerance on the start of time windows, capped by the config. Only a backward
+    /// jump widens; a `Suspect` forward jump is reported but earns nothing.
+    pub fn widening_ms(&self) -> u64 {
+        match self.status {
+            ClockStatus::JumpedBackwards { delta_ms } => delta_ms.min(self.config.max_widening_ms),
+            ClockStatus::Synced | ClockStatus::Suspect { .. } => 0,
+        }
+    }
+
+    pub fn observe(&mut self, monotonic_now_ms: u64) -> ClockStatus {
+        let wall = self.clock.unix_time_ms();
+        if let Some((base_wall, base_monotonic)) = self.baseline {
+            let expected = base_wall.saturating_add(monotonic_now_ms.saturating_sub(base_monotonic));
+            let jump = if wall.saturating_add(self.config.jump_threshold_ms) < expected {
+                Some(ClockStatus::JumpedBackwards { delta_ms: expected - wall })
+            } else if wall > expected.saturating_add(self.config.jump_threshold_ms) {
+                Some(ClockStatus::Suspect { delta_ms: wall - expected })
+            } else {
+                None
+            };
+            if let Some(jump) = jump {
+                self.status = jump;
+                self.status_since_monotonic_ms = monotonic_now_ms;
+                self.jumps = self.jumps.saturating_add(1);
+            } else if self.status != ClockStatus::Synced
+                && monotonic_now_ms.saturating_sub(self.status_since_monotonic_ms) >= self.config.settle_ms
+            {
+                self.status = ClockStatus::Synced;
+            }
+        }
+        self.baseline = Some((wall, monotonic_now_ms));
+        self.status
+    }
+}
+
+fn system_monitor() -> &'static Mutex<ClockMonitor<SystemClock>> {
+    static MONITOR: OnceLock<Mutex<ClockMonitor<SystemClock>>> = OnceLock::new();
+    MONITOR.get_or_init(|| Mutex::new(ClockMonitor::new(SystemClock, ClockMonitorConfig::from_env())))
+}
+
+/// Current status of the system clock. Each call is also an observation, and a newly
+/// detected jump is logged (and so reported through self-telemetry).
+pub fn clock_status() -> ClockStatus {
+    observe_system_clock().0
+}
+
+fn observe_system_clock() -> (ClockStatus, u64) {
+    let Ok(mut monitor) = system_monitor().lock() else {
+        return (ClockStatus::Synced, 0);
+    };
+    let jumps_before = monitor.jumps();
+    let status = monitor.observe(monotonic_ms());
+    let widening = monitor.widening_ms();
+    let jumped = monitor.jumps() != jumps_before;
+    drop(monitor);
+    if jumped {
+        warn!(status = ?status, delta_ms = status.delta_ms(), widening_ms = widening, "system clock jump detected");
+    }
+    (status, widening)
+}
+
+/// True when `now` falls within `[start, end]` widened by `tolerance_ms` on both sides.
+pub fn within_window(now_unix_ms: u64, start_unix_ms: u64, end_unix_ms: u64, tolerance_ms: u64) -> bool {
+    now_unix_ms.saturating_add(tolerance_ms) >= start_unix_ms
+        && now_unix_ms <= end_unix_ms.saturating_add(tolerance_ms)
+}
+
+/// Format milliseconds since the Unix epoch as an RFC 3339 UTC timestamp with millisecond precision.
+pub fn format_rfc3339_ms(unix_ms: u64) -> String {
+    let seconds = unix_ms / 1000;
+    let millis = unix_ms % 1000;
+    let days = (seconds / 86_400) as i64;
+    let seconds_of_day = seconds % 86_400;
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        seconds_of_day / 3600,
+        (seconds_of_day % 3600) / 60,
+        seconds_of_day % 60,
+        millis
+    )
+}
+
+/// Parse an IMF-fixdate HTTP date (`Sun, 06 Nov 1994 08:49:37 GMT`) into milliseconds since
+/// the Unix epoch.
+pub fn parse_http_date_ms(value: &str) -> Option<u64> {
+    let (_weekday, rest) = value.trim().split_once(", ")?;
+    let parts = rest.split(' ').collect::<Vec<&str>>();
+    if parts.len() != 5 || parts[4] != "GMT" {
+        return None;
+    }
+    let day = parts[0].parse::<u32>().ok().filter(|day| (1..=31).contains(day))?;
+    let month = MONTHS.iter().position(|name| *name == parts[1])? as u32 + 1;
+    let year = parts[2].parse::<i64>().ok().filter(|year| *year >= 1970)?;
+    let clock = parts[3].split(':').map(|part| part.parse::<u64>().ok()).collect::<Option<Vec<u64>>>()?;
+    if clock.len() != 3 || clock[0] > 23 || clock[1] > 59 || clock[2] > 60 {
+        return None;
+    }
+    let days = u64::try_from(days_from_civil(year, month, day)).ok()?;
+    let seconds = days * 86_400 + clock[0] * 3600 + clock[1] * 60 + clock[2];
+    seconds.checked_mul(1000)
+}
+
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/// `YYYY-MM-DD` for Unix seconds.
+pub fn format_date(unix_secs: i64) -> String {
+    let (year, month, day) = civil_from_days(unix_secs.div_euclid(86_400));
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Days since 1970-01-01 for a proleptic Gregorian date; inverse of `civil_from_days`.
+pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month = i64::from(month);
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+// Howard Hinnant's days-to-civil conversion for the proleptic Gregorian calendar.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    use super::{ClockMonitor, ClockMonitorConfig, ClockStatus, WallClock};
+
+    #[derive(Clone, Default)]
+    struct FakeClock(Arc<AtomicU64>);
+
+    impl WallClock for FakeClock {
+        fn unix_time_ms(&self) -> u64 {
+            self.0.load(Ordering::SeqCst)
+        }
+    }
+
+    fn monitor(clock: &FakeClock) -> ClockMonitor<FakeClock> {
+        ClockMonitor::new(
+            clock.clone(),
+            ClockMonitorConfig {
+                jump_threshold_ms: 1_000,
+                settle_ms: 60_000,
+                max_widening_ms: 120_000,
+            },
+        )
+    }
+
+    #[test]
+    fn detects_backwards_jump_and_settles() {
+        let clock = FakeClock::default();
+        clock.0.store(1_700_000_000_000, Ordering::SeqCst);
+        let mut monitor = monitor(&clock);
+        assert_eq!(monitor.observe(0), ClockStatus::Synced);
+
+        clock.0.store(1_700_000_010_000, Ordering::SeqCst);
+        assert_eq!(monitor.observe(10_000), ClockStatus::Synced);
+
+        // Ten seconds of monotonic time pass, but the wall clock is set back an hour.
+        clock.0.store(1_699_996_420_000, Ordering::SeqCst);
+        assert_eq!(
+            monitor.observe(20_000),
+            ClockStatus::JumpedBackwards { delta_ms: 3_600_000 }
+        );
+        assert_eq!(monitor.widening_ms(), 120_000);
+        assert_eq!(monitor.jumps(), 1);
+
+        clock.0.store(1_699_996_450_000, Ordering::SeqCst);
+        assert!(matches!(monitor.observe(50_000), ClockStatus::JumpedBackwards { .. }));
+        clock.0.store(1_699_996_480_000, Ordering::SeqCst);
+        assert_eq!(monitor.observe(80_000), ClockStatus::Synced);
+        assert_eq!(monitor.widening_ms(), 0);
+    }
+
+    #[test]
+    fn flags_forward_jumps_as_suspect() {
+        let clock = FakeClock::default();
+        clock.0.store(1_700_000_000_000, Ordering::SeqCst);
+        let mut monitor = monitor(&clock);
+        monitor.observe(0);
+
+        // Small drift stays under the threshold.
+        clock.0.store(1_700_000_005_500, Ordering::SeqCst);
+        assert_eq!(monitor.observe(5_000), ClockStatus::Synced);
+
+        clock.0.store(1_700_000_100_000, Ordering::SeqCst);
+        let status = monitor.observe(6_000);
+        assert_eq!(status, ClockStatus::Suspect { delta_ms: 93_500 });
+        assert_eq!(status.delta_ms(), 93_500);
+        assert_eq!(monitor.widening_ms(), 0);
+    }
+}
@@ -1,10 +1,14 @@
 use std::env;
 use std::fs;
+use std::io;
 use std::path::{Path, PathBuf};
 
 use serde::Deserialize;
 use sha2::{Digest, Sha256};
 
+use crate::compression::read_file_bounded;
+use crate::crypto::{Ed25519Verifier, Verifier};
+use crate::state_dir::StatePaths;
 use crate::time::unix_time_ms;
 
 #[derive(Debug, Clone)]
@@ -30,6 +34,35 @@ pub struct StagedArtifact {
     pub verified: bool,
 }
 
+/// Result of checking a manifest and its artifacts without staging anything.
+#[derive(Debug, Clone)]
+pub struct UpdateVerification {
+    pub manifest_version: String,
+    pub manifest_checksum: String,
+    pub channel: String,
+    pub verified_at_unix_ms: u64,
+    pub total_bytes: u64,
+    pub artifacts: Vec<ArtifactVerification>,
+    pub warnings: Vec<String>,
+}
+
+impl UpdateVerification {
+    /// True when the manifest passed every check and each artifact matched its hash.
+    pub fn is_verified(&self) -> bool {
+        self.warnings.is_empty() && self.artifacts.iter().all(|artifact| artifact.verified)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ArtifactVerification {
+    pub name: String,
+    pub path: String,
+    pub expected_sha256: String,
+    pub size_bytes: u64,
+    pub verified: bool,
+    pub error: Option<String>,
+}
+
 #[derive(Debug, Clone)]
 pub struct RollbackPlan {
     pub previous_version: Option<String>,
@@ -47,15 +80,19 @@ pub struct UpdateConfig {
     pub required_channel: Option<String>,
     pub allow_prerelease: bool,
     pub expected_manifest_sha256: Option<String>,
+    pub max_manifest_bytes: u64,
+    /// Base64 Ed25519 public key artifact signatures are verified with; signatures are
+    /// ignored without one.
+    pub publisher_public_key: Option<String>,
+    /// Reject artifacts that carry no signature.
+    pub require_artifact_signatures: bool,
 }
 
 impl UpdateConfig {
-    pub fn from_env() -> Self {
+    pub fn from_env(paths: &StatePaths) -> Self {
         let manifest_path = env::var("UPDATE_MANIFEST_PATH").ok().map(PathBuf::from);
         let manifest_json = env::var("UPDATE_MANIFEST_JSON").ok();
-        let stage_dir = env::var("UPDATE_STAGE_DIR")
-            .map(PathBuf::from)
-            .unwrap_or_else(|_| PathBuf::from("./staging"));
+        let stage_dir = paths.staging.clone();
         let max_payload_bytes = env::var("UPDATE_MAX_PAYLOAD_BYTES")
             .ok()
             .and_then(|value| value.parse::<u64>().ok())
@@ -72,6 +109,17 @@ impl UpdateConfig {
         let expected_manifest_sha256 = env::var("UPDATE_MANIFEST_SHA256")
             .ok()
             .filter(|value| !value.trim().is_empty());
+        let max_manifest_bytes = env::var("UPDATE_MAX_MANIFEST_BYTES")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .unwrap_or(4 * 1024 * 1024);
+        let publisher_public_key = env::var("UPDATE_PUBLISHER_PUBLIC_KEY")
+            .ok()
+            .filter(|value| !value.trim().is_empty());
+        let require_artifact_signatures = env::var("UPDATE_REQUIRE_SIGNATURES")
+            .ok()
+            .map(|value| value.eq_ignore_ascii_case("true"))
+            .unwrap_or(false);
 
         Self {
             manifest_path,
@@ -82,6 +130,9 @@ impl UpdateConfig {
             required_channel,
             allow_prerelease,
             expected_manifest_sha256,
+            max_manifest_bytes,
+            publisher_public_key,
+            require_artifact_signatures,
         }
     }
 }
@@ -102,10 +153,13 @@ pub struct UpdateArtifact {
     pub name: String,
     pub path: String,
     pub sha256: String,
+    /// Publisher signature over `artifact_signing_payload(name, sha256)`.
+    #[serde(default)]
+    pub signature: Option<String>,
 }
 
-pub fn stage_update() -> UpdatePlan {
-    let config = UpdateConfig::from_env();
+pub fn stage_update(paths: &StatePaths) -> UpdatePlan {
+    let config = UpdateConfig::from_env(paths);
     stage_update_with_config(&config)
 }
 
@@ -137,21 +191,7 @@ pub fn stage_update_with_config(config: &UpdateConfig) -> UpdatePlan {
         }
     };
 
-    if let Some(expected) = &config.expected_manifest_sha256 {
-        if !expected.eq_ignore_ascii_case(&manifest_checksum) {
-            warnings.push("Manifest checksum mismatch detected.".to_string());
-        }
-    }
-
-    if let Some(required_channel) = &config.required_channel {
-        if &manifest.channel != required_channel {
-            warnings.push("Manifest channel does not match required channel.".to_string());
-        }
-    }
-
-    if manifest.prerelease && !config.allow_prerelease {
-        warnings.push("Prerelease manifest supplied but not permitted.".to_string());
-    }
+    warnings.extend(manifest_warnings(&manifest, &manifest_checksum, config));
 
     for artifact in manifest.artifacts.iter().take(config.max_artifacts) {
         match stage_artifact(artifact, config) {
@@ -184,8 +224,99 @@ pub fn stage_update_with_config(config: &UpdateConfig) -> UpdatePlan {
     }
 }
 
+/// Version of the configured manifest; `None` when it cannot be loaded.
+pub fn manifest_version(config: &UpdateConfig) -> Option<String> {
+    load_manifest(config).ok().map(|(manifest, _)| manifest.version)
+}
+
+/// Run the manifest and artifact checks of `stage_update_with_config` without computing
+/// staged paths or writing anything, for CI and pre-flight checks. Unlike staging, every
+/// artifact is reported, including the ones that fail.
+pub fn verify_update(config: &UpdateConfig) -> UpdateVerification {
+    let verified_at_unix_ms = unix_time_ms();
+    let (manifest, manifest_checksum) = match load_manifest(config) {
+        Ok(result) => result,
+        Err(err) => {
+            return UpdateVerification {
+                manifest_version: "unknown".to_string(),
+                manifest_checksum: empty_hash(),
+                channel: "unknown".to_string(),
+                verified_at_unix_ms,
+                total_bytes: 0,
+                artifacts: Vec::new(),
+                warnings: vec![format!("Failed to load manifest: {}", err)],
+            };
+        }
+    };
+
+    let mut warnings = manifest_warnings(&manifest, &manifest_checksum, config);
+    let mut total_bytes = 0_u64;
+    let mut artifacts = Vec::new();
+    for artifact in manifest.artifacts.iter().take(config.max_artifacts) {
+        let outcome = check_artifact(artifact, config);
+        let size_bytes = outcome.as_ref().map(|(_, _, size_bytes)| *size_bytes).unwrap_or(0);
+        total_bytes = total_bytes.saturating_add(size_bytes);
+        artifacts.push(ArtifactVerification {
+            name: artifact.name.clone(),
+            path: artifact.path.clone(),
+            expected_sha256: artifact.sha256.clone(),
+            size_bytes,
+            verified: outcome.is_ok(),
+            error: outcome.err(),
+        });
+    }
+    if total_bytes > config.max_payload_bytes {
+        warnings.push("Staged payload exceeds maximum allowed size.".to_string());
+    }
+
+    UpdateVerification {
+        manifest_version: manifest.version,
+        manifest_checksum,
+        channel: manifest.channel,
+        verified_at_unix_ms,
+        total_bytes,
+        artifacts,
+        warnings,
+    }
+}
+
+/// Artifact count, checksum pin, channel and prerelease checks shared by staging and
+/// verification. Artifacts past `max_artifacts` are never checked, so a manifest listing
+/// more is refused rather than trimmed.
+fn manifest_warnings(manifest: &UpdateManifest, manifest_checksum: &str, config: &UpdateConfig) -> Vec<String> {
+    let mut warnings = Vec::new();
+    if manifest.artifacts.is_empty() {
+        warnings.push("Manifest lists no artifacts.".to_string());
+    } else if manifest.artifacts.len() > config.max_artifacts {
+        warnings.push(format!(
+            "Manifest lists {} artifacts; at most {} are allowed.",
+            manifest.artifacts.len(),
+            config.max_artifacts
+        ));
+    }
+    if let Some(expected) = &config.expected_manifest_sha256 {
+        if !expected.eq_ignore_ascii_case(manifest_checksum) {
+            warnings.push("Manifest checksum mismatch detected.".to_string());
+        }
+    }
+
+    if let Some(required_channel) = &config.required_channel {
+        if &manifest.channel != required_channel {
+            warnings.push("Manifest channel does not match required channel.".to_string());
+        }
+    }
+
+    if manifest.prerelease && !config.allow_prerelease {
+        warnings.push("Prerelease manifest supplied but not permitted.".to_string());
+    }
+    warnings
+}
+
 fn load_manifest(config: &UpdateConfig) -> Result<(UpdateManifest, String), String> {
     if let Some(raw) = &config.manifest_json {
+        if raw.len() as u64 > config.max_manifest_bytes {
+            return Err(format!("Manifest JSON exceeds {} bytes", config.max_manifest_bytes));
+        }
         let manifest = serde_json::from_str::<UpdateManifest>(raw)
             .map_err(|err| format!("Manifest JSON invalid: {}", err))?;
         let checksum = hash_bytes(raw.as_bytes());
@@ -196,14 +327,32 @@ fn load_manifest(config: &UpdateConfig) -> Result<(UpdateManifest, String), Stri
         .manifest_path
         .clone()
         .ok_or_else(|| "UPDATE_MANIFEST_PATH or UPDATE_MANIFEST_JSON required".to_string())?;
-    let raw = fs::read_to_string(&path).map_err(|err| format!("Unable to read manifest: {}", err))?;
-    let manifest = serde_json::from_str::<UpdateManifest>(&raw)
+    // Gzipped manifests are checksummed over the decompressed bytes so the pinned hash does
+    // not depend on how the manifest was delivered.
+    let raw = read_file_bounded(&path, config.max_manifest_bytes)
+        .map_err(|err| format!("Unable to read manifest: {}", err))?;
+    let manifest = serde_json::from_slice::<UpdateManifest>(&raw)
         .map_err(|err| format!("Manifest JSON invalid: {}", err))?;
-    let checksum = hash_bytes(raw.as_bytes());
+    let checksum = hash_bytes(&raw);
     Ok((manifest, checksum))
 }
 
 fn stage_artifact(artifact: &UpdateArtifact, config: &UpdateConfig) -> Result<StagedArtifact, String> {
+    let (resolved, sha256, size_bytes) = check_artifact(artifact, config)?;
+    let staged_path = config.stage_dir.join(&artifact.name);
+    Ok(StagedArtifact {
+        name: artifact.name.clone(),
+        source_path: resolved,
+        staged_path,
+        sha256,
+        size_bytes,
+        verified: true,
+    })
+}
+
+/// Resolve the artifact and check its hash and signature, returning the resolved path, hash
+/// and size.
+fn check_artifact(artifact: &UpdateArtifact, config: &UpdateConfig) -> Result<(PathBuf, String, u64), String> {
     if artifact.name.trim().is_empty() {
         return Err("Artifact name missing".to_string());
     }
@@ -216,20 +365,40 @@ fn stage_artifact(artifact: &UpdateArtifact, config: &UpdateConfig) -> Result<St
 
     let size_bytes = metadata.len();
     let sha256 = hash_file(&resolved).map_err(|_| "Failed to hash artifact".to_string())?;
-    let verified = sha256.eq_ignore_ascii_case(&artifact.sha256);
-    if !verified {
+    if !sha256.eq_ignore_ascii_case(&artifact.sha256) {
         return Err("Artifact hash mismatch".to_string());
     }
+    verify_artifact_signature(artifact, &sha256, config)?;
+    Ok((resolved, sha256, size_bytes))
+}
 
-    let staged_path = config.stage_dir.join(&artifact.name);
-    Ok(StagedArtifact {
-        name: artifact.name.clone(),
-        source_path: resolved,
-        staged_path,
-        sha256,
-        size_bytes,
-        verified,
-    })
+/// Check the publisher signature against the hash of the file on disk, so rewriting the
+/// manifest's `sha256` to match a tampered artifact is not enough to pass.
+fn verify_artifact_signature(artifact: &UpdateArtifact, sha256: &str, config: &UpdateConfig) -> Result<(), String> {
+    let signature = match artifact.signature.as_deref().map(str::trim).filter(|value| !value.is_empty()) {
+        Some(signature) => signature,
+        None if config.require_artifact_signatures => return Err("Artifact signature missing".to_string()),
+        None => return Ok(()),
+    };
+    let key = match &config.publisher_public_key {
+        Some(key) => key,
+        None if config.require_artifact_signatures => {
+            return Err("Artifact signature cannot be verified without UPDATE_PUBLISHER_PUBLIC_KEY".to_string())
+        }
+        None => return Ok(()),
+    };
+    let verifier = Ed25519Verifier::from_base64(key)?;
+    let payload = artifact_signing_payload(&artifact.name, sha256);
+    if verifier.verify(payload.as_bytes(), signature) {
+        Ok(())
+    } else {
+        Err("Artifact signature verification failed".to_string())
+    }
+}
+
+/// Bytes a publisher signs for an artifact: its name and lowercase hex SHA-256.
+pub fn artifact_signing_payload(name: &str, sha256: &str) -> String {
+    format!("{}|{}", name, sha256.to_ascii_lowercase())
 }
 
 fn resolve_path(path: &Path) -> Result<PathBuf, String> {
@@ -237,9 +406,12 @@ fn resolve_path(path: &Path) -> Result<PathBuf, String> {
         .map_err(|_| "Unable to resolve artifact path".to_string())
 }
 
-fn hash_file(path: &Path) -> Result<String, String> {
-    let data = fs::read(path).map_err(|_| "Unable to read artifact".to_string())?;
-    Ok(hash_bytes(&data))
+/// Hash the artifact in chunks so large payloads are never held in memory.
+pub fn hash_file(path: &Path) -> Result<String, String> {
+    let mut file = fs::File::open(path).map_err(|_| "Unable to read artifact".to_string())?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher).map_err(|_| "Unable to read artifact".to_string())?;
+    Ok(hex_encode(hasher.finalize()))
 }
 
 fn hash_bytes(bytes: &[u8]) -> String {
@@ -260,3 +432,224 @@ fn hex_encode(bytes: impl AsRef<[u8]>) -> String {
         .collect::<Vec<String>>()
         .join("")
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    use super::{
+        artifact_signing_payload, hash_bytes, load_manifest, stage_update_with_config, verify_update, UpdateConfig,
+    };
+    use base64::engine::general_purpose::STANDARD as BASE64_STANDARD;
+    use base64::Engine as _;
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+
+    #[test]
+    fn loads_gzipped_manifest_with_decompressed_checksum() {
+        let json = r#"{"version":"1.2.3","channel":"stable","prerelease":false,"artifacts":[],"previous_version":"1.2.2"}"#;
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(json.as_bytes()).expect("compress manifest");
+        let path = std::env::temp_dir().join(format!("manifest-{}.json.gz", std::process::id()));
+        std::fs::write(&path, encoder.finish().expect("finish gzip")).expect("write manifest");
+
+        let config = UpdateConfig {
+            manifest_path: Some(path.clone()),
+            manifest_json: None,
+            stage_dir: PathBuf::from("./staging"),
+            max_payload_bytes: 1024,
+            max_artifacts: 4,
+            required_channel: None,
+            allow_prerelease: false,
+            expected_manifest_sha256: None,
+            max_manifest_bytes: 64 * 1024,
+            publisher_public_key: None,
+            require_artifact_signatures: false,
+        };
+        let (manifest, checksum) = load_manifest(&config).expect("gzipped manifest loads");
+        assert_eq!(manifest.version, "1.2.3");
+        assert_eq!(manifest.previous_version.as_deref(), Some("1.2.2"));
+        assert_eq!(checksum, hash_bytes(json.as_bytes()));
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn rejects_inline_manifest_over_limit() {
+        let config = UpdateConfig {
+            manifest_path: None,
+            manifest_json: Some(format!(r#"{{"version":"{}"}}"#, "1".repeat(2048))),
+            stage_dir: PathBuf::from("./staging"),
+            max_payload_bytes: 1024,
+            max_artifacts: 4,
+            required_channel: None,
+            allow_prerelease: false,
+            expected_manifest_sha256: None,
+            max_manifest_bytes: 1024,
+            publisher_public_key: None,
+            require_artifact_signatures: false,
+        };
+        let err = load_manifest(&config).expect_err("oversized manifest rejected");
+        assert!(err.contains("exceeds 1024 bytes"));
+    }
+
+    fn artifact_config(label: &str, artifacts: &[(&str, &[u8], Option<&str>)]) -> (PathBuf, UpdateConfig) {
+        let dir = std::env::temp_dir().join(format!(
+            "update-verify-{}-{}-{}",
+            label,
+            std::process::id(),
+            crate::time::unix_time_ms()
+        ));
+        std::fs::create_dir_all(&dir).expect("create artifact dir");
+        let entries = artifacts
+            .iter()
+            .map(|(name, contents, declared_hash)| {
+                let path = dir.join(name);
+                std::fs::write(&path, contents).expect("write artifact");
+                serde_json::json!({
+                    "name": name,
+                    "path": path.display().to_string(),
+                    "sha256": declared_hash.map(str::to_string).unwrap_or_else(|| hash_bytes(contents)),
+                })
+            })
+            .collect::<Vec<serde_json::Value>>();
+        let manifest = serde_json::json!({
+            "version": "2.0.0",
+            "channel": "stable",
+            "prerelease": false,
+            "artifacts": entries,
+            "previous_version": "1.9.0",
+        });
+        let config = UpdateConfig {
+            manifest_path: None,
+            manifest_json: Some(manifest.to_string()),
+            stage_dir: dir.join("staging"),
+            max_payload_bytes: 1024,
+            max_artifacts: 4,
+            required_channel: Some("stable".to_string()),
+            allow_prerelease: false,
+            expected_manifest_sha256: None,
+            max_manifest_bytes: 64 * 1024,
+            publisher_public_key: None,
+            require_artifact_signatures: false,
+        };
+        (dir, config)
+    }
+
+    #[test]
+    fn verifies_all_artifacts_without_staging() {
+        let (dir, config) = artifact_config(
+            "ok",
+            &[("agent-core.bin", b"core build", None), ("agent-watchdog.bin", b"watchdog build", None)],
+        );
+
+        let verification = verify_update(&config);
+        assert!(verification.is_verified(), "{:?}", verification);
+        assert_eq!(verification.manifest_version, "2.0.0");
+        assert_eq!(verification.artifacts.len(), 2);
+        assert_eq!(verification.total_bytes, 24);
+        assert!(!config.stage_dir.exists(), "verification must not create the stage directory");
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn refuses_empty_and_over_limit_manifests() {
+        let (dir, config) = artifact_config("empty", &[]);
+        let empty = verify_update(&config);
+        assert!(!empty.is_verified());
+        assert_eq!(empty.warnings, vec!["Manifest lists no artifacts.".to_string()]);
+        let _ = std::fs::remove_dir_all(dir);
+
+        let builds: Vec<(String, Vec<u8>)> = (0..5).map(|index| (format!("part-{}.bin", index), vec![b'x'; 4])).collect();
+        let entries = builds
+            .iter()
+            .map(|(name, contents)| (name.as_str(), contents.as_slice(), None))
+            .collect::<Vec<_>>();
+        let (dir, config) = artifact_config("too-many", &entries);
+        let too_many = verify_update(&config);
+        assert!(!too_many.is_verified());
+        assert_eq!(too_many.warnings, vec!["Manifest lists 5 artifacts; at most 4 are allowed.".to_string()]);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn reports_single_artifact_hash_mismatch() {
+        let wrong_hash = hash_bytes(b"different build");
+        let (dir, config) = artifact_config(
+            "mismatch",
+            &[("agent-core.bin", b"core build", None), ("agent-watchdog.bin", b"watchdog build", Some(&wrong_hash))],
+        );
+
+        let verification = verify_update(&config);
+        assert!(!verification.is_verified());
+        assert!(verification.warnings.is_empty());
+        assert!(verification.artifacts[0].verified);
+        assert!(!verification.artifacts[1].verified);
+        assert_eq!(verification.artifacts[1].error.as_deref(), Some("Artifact hash mismatch"));
+        assert!(!config.stage_dir.exists());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn verifies_artifact_signatures_against_publisher_key() {
+        let publisher = Ed25519KeyPair::from_seed_unchecked(&[7u8; 32]).expect("publisher key");
+        let signed = BASE64_STANDARD.encode(
+            publisher
+                .sign(artifact_signing_payload("agent-core.bin", &hash_bytes(b"core build")).as_bytes())
+                .as_ref(),
+        );
+        let (dir, mut config) = artifact_config("signed", &[("agent-core.bin", b"core build", None)]);
+        config.publisher_public_key = Some(BASE64_STANDARD.encode(publisher.public_key().as_ref()));
+        config.require_artifact_signatures = true;
+        let manifest = |signature: Option<&str>, contents: &[u8]| {
+            serde_json::json!({
+                "version": "2.0.0",
+                "channel": "stable",
+                "prerelease": false,
+                "artifacts": [{
+                    "name": "agent-core.bin",
+                    "path": dir.join("agent-core.bin").display().to_string(),
+                    "sha256": hash_bytes(contents),
+                    "signature": signature,
+                }],
+                "previous_version": "1.9.0",
+            })
+            .to_string()
+        };
+
+        config.manifest_json = Some(manifest(Some(&signed), b"core build"));
+        let plan = stage_update_with_config(&config);
+        assert!(plan.warnings.is_empty(), "{:?}", plan.warnings);
+        assert_eq!(plan.artifacts.len(), 1);
+
+        // The attacker replaces the artifact and rewrites its manifest hash to match.
+        std::fs::write(dir.join("agent-core.bin"), b"evil build").expect("tamper artifact");
+        config.manifest_json = Some(manifest(Some(&signed), b"evil build"));
+        let plan = stage_update_with_config(&config);
+        assert!(plan.artifacts.is_empty());
+        assert_eq!(
+            plan.warnings,
+            vec!["Artifact agent-core.bin skipped: Artifact signature verification failed".to_string()]
+        );
+
+        // A signature from any other key, such as one held by a compromised agent, is refused.
+        let other = Ed25519KeyPair::from_seed_unchecked(&[9u8; 32]).expect("other key");
+        let forged = BASE64_STANDARD.encode(
+            other
+                .sign(artifact_signing_payload("agent-core.bin", &hash_bytes(b"evil build")).as_bytes())
+                .as_ref(),
+        );
+        config.manifest_json = Some(manifest(Some(&forged), b"evil build"));
+        assert_eq!(
+            verify_update(&config).artifacts[0].error.as_deref(),
+            Some("Artifact signature verification failed")
+        );
+
+        config.manifest_json = Some(manifest(None, b"evil build"));
+        let verification = verify_update(&config);
+        assert_eq!(verification.artifacts[0].error.as_deref(), Some("Artifact signature missing"));
+        config.require_artifact_signatures = false;
+        assert!(verify_update(&config).is_verified());
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,572 @@
+use std::env;
+use std::fs;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::audit::{self, AuditEvent};
+use crate::pipeline::HealthState;
+use crate::state_dir::StatePaths;
+use crate::time::unix_time_ms;
+use crate::update::{hash_file, manifest_version, stage_update_with_config, UpdateConfig};
+
+const STATE_FILE_NAME: &str = "update_state.json";
+
+/// Where a self-update stands. Persisted after every transition so a restart mid-update can
+/// pick up where it left off.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UpdatePhase {
+    Staged,
+    Verified,
+    /// Backups are taken and artifacts are being swapped in; an interruption here rolls back.
+    Applying,
+    /// Artifacts are in place and the agent has not yet reported healthy.
+    Applied,
+    Committed,
+    RolledBack,
+    Failed,
+}
+
+impl UpdatePhase {
+    pub fn is_terminal(self) -> bool {
+        matches!(self, Self::Committed | Self::RolledBack | Self::Failed)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArtifactState {
+    pub name: String,
+    pub sha256: String,
+    pub staged_path: PathBuf,
+    pub install_path: PathBuf,
+    /// Copy of the file the artifact replaced; `None` when the artifact is new.
+    pub backup_path: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UpdateState {
+    pub phase: UpdatePhase,
+    pub manifest_version: String,
+    pub previous_version: Option<String>,
+    pub artifacts: Vec<ArtifactState>,
+    pub applied_at_unix_ms: Option<u64>,
+    pub updated_at_unix_ms: u64,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct OrchestratorConfig {
+    pub update: UpdateConfig,
+    pub install_dir: PathBuf,
+    pub backup_dir: PathBuf,
+    pub state_path: PathBuf,
+    pub health_timeout_ms: u64,
+    pub health_poll_ms: u64,
+    /// Pipeline components that must all report ready before an update is committed.
+    pub health_components: Vec<String>,
+    /// Check the manifest and artifacts with `verify_update` and report the result instead
+    /// of applying the update.
+    pub verify_only: bool,
+}
+
+impl OrchestratorConfig {
+    pub fn from_env(paths: &StatePaths) -> Self {
+        let update = UpdateConfig::from_env(paths);
+        let install_dir = env::var("UPDATE_INSTALL_DIR")
+            .ok()
+            .filter(|value| !value.trim().is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::current_exe().ok().and_then(|exe| exe.parent().map(Path::to_path_buf)))
+            .unwrap_or_else(|| PathBuf::from("."));
+        let health_timeout_ms = env::var("UPDATE_HEALTH_TIMEOUT_MS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .filter(|value| *value > 0)
+            .unwrap_or(300_000);
+        let health_poll_ms = env::var("UPDATE_HEALTH_POLL_MS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .filter(|value| *value > 0)
+            .unwrap_or(5_000);
+        let health_components = env::var("UPDATE_HEALTH_COMPONENTS")
+            .ok()
+            .map(|value| {
+                value
+                    .split(',')
+                    .map(|component| component.trim().to_string())
+                    .filter(|component| !component.is_empty())
+                    .collect::<Vec<String>>()
+            })
+            .filter(|components| !components.is_empty())
+            .unwrap_or_else(|| vec!["policy".to_string(), "trust_bundle".to_string(), "uplink".to_string()]);
+        let verify_only = env::var("UPDATE_VERIFY_ONLY")
+            .map(|value| value.eq_ignore_ascii_case("true"))
+            .unwrap_or(false);
+        Self {
+            install_dir,
+            backup_dir: update.stage_dir.join("rollback"),
+            state_path: update.stage_dir.join(STATE_FILE_NAME),
+            update,
+            health_timeout_ms,
+            health_poll_ms,
+            health_components,
+            verify_only,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HealthDecision {
+    Commit,
+    Wait,
+    RollBack(String),
+}
+
+/// Commit once the agent reports ready; roll back when the deadline passes without that.
+pub fn health_decision(
+    reported: Option<HealthState>,
+    applied_at_unix_ms: u64,
+    now_unix_ms: u64,
+    timeout_ms: u64,
+) -> HealthDecision {
+    if reported == Some(HealthState::Ready) {
+        return HealthDecision::Commit;
+    }
+    if now_unix_ms.saturating_sub(applied_at_unix_ms) < timeout_ms {
+        return HealthDecision::Wait;
+    }
+    let last = match reported {
+        Some(state) => format!("{:?}", state).to_ascii_lowercase(),
+        None => "no report".to_string(),
+    };
+    HealthDecision::RollBack(format!("agent not healthy within {} ms (last: {})", timeout_ms, last))
+}
+
+/// Resume an interrupted update, or run the configured one unless its version was already
+/// attempted. Returns `None` when there is nothing to do.
+pub async fn run_pending_update<F, Fut>(config: &OrchestratorConfig, probe: F) -> Option<UpdateState>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Option<HealthState>>,
+{
+    if config.update.manifest_path.is_none() && config.update.manifest_json.is_none() {
+        return resume_update(config, probe).await;
+    }
+    if let Some(previous) = load_state(&config.state_path) {
+        if !previous.phase.is_terminal() {
+            return resume_update(config, probe).await;
+        }
+        // A committed, rolled back or failed version is not retried on every start.
+        if manifest_version(&config.update).as_deref() == Some(previous.manifest_version.as_str()) {
+            return None;
+        }
+    }
+    Some(run_update(config, probe).await)
+}
+
+/// Stage, verify and apply the configured update, then wait for `probe` to report the agent
+/// ready and roll back if it does not within the timeout. `probe` reads the pipeline health
+/// the agent serves on `/health`.
+pub async fn run_update<F, Fut>(config: &OrchestratorConfig, probe: F) -> UpdateState
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Option<HealthState>>,
+{
+    let mut state = match stage(config) {
+        Ok(state) => state,
+        Err(reason) => {
+            let state = failed_state(reason);
+            persist_logged(&state, &config.state_path);
+            return state;
+        }
+    };
+    persist_logged(&state, &config.state_path);
+    continue_update(config, &mut state, probe).await;
+    state
+}
+
+/// Finish or undo an update interrupted by a restart. Returns `None` when no update is in
+/// flight.
+pub async fn resume_update<F, Fut>(config: &OrchestratorConfig, probe: F) -> Option<UpdateState>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Option<HealthState>>,
+{
+    let mut state = load_state(&config.state_path)?;
+    if state.phase.is_terminal() {
+        return None;
+    }
+    info!(phase = ?state.phase, version = %state.manifest_version, "resuming interrupted update");
+    if state.phase == UpdatePhase::Applying {
+        roll_back(config, &mut state, "update interrupted while applying".to_string());
+        return Some(state);
+    }
+    continue_update(config, &mut state, probe).await;
+    Some(state)
+}
+
+pub fn load_state(path: &Path) -> Option<UpdateState> {
+    let raw = fs::read(path).ok()?;
+    match serde_json::from_slice::<UpdateState>(&raw) {
+        Ok(state) => Some(state),
+        Err(err) => {
+            warn!(error = %err, path = %path.display(), "update state unreadable; ignoring");
+            None
+        }
+    }
+}
+
+async fn continue_update<F, Fut>(config: &OrchestratorConfig, state: &mut UpdateState, mut probe: F)
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Option<HealthState>>,
+{
+    if matches!(state.phase, UpdatePhase::Staged | UpdatePhase::Verified) {
+        // Staged copies are checked again on resume in case they changed while the agent was down.
+        if let Err(reason) = verify_staged(state) {
+            fail(config, state, reason);
+            return;
+        }
+        transition(config, state, UpdatePhase::Verified);
+        // Nothing is installed yet, so a failed backup abandons the update rather than
+        // rolling back.
+        if let Err(reason) = back_up(config, state) {
+            fail(config, state, reason);
+            return;
+        }
+        if let Err(reason) = apply(config, state) {
+            roll_back(config, state, reason);
+            return;
+        }
+    }
+    if state.phase != UpdatePhase::Applied {
+        return;
+    }
+    let applied_at_unix_ms = state.applied_at_unix_ms.unwrap_or_else(unix_time_ms);
+    loop {
+        let reported = probe().await;
+        match health_decision(reported, applied_at_unix_ms, unix_time_ms(), config.health_timeout_ms) {
+            HealthDecision::Commit => {
+                info!(version = %state.manifest_version, "update committed after healthy report");
+                transition(config, state, UpdatePhase::Committed);
+                return;
+            }
+            HealthDecision::Wait => tokio::time::sleep(Duration::from_millis(config.health_poll_ms)).await,
+            HealthDecision::RollBack(reason) => {
+                roll_back(config, state, reason);
+                return;
+            }
+        }
+    }
+}
+
+/// Copy each artifact the update module accepted into the stage directory.
+fn stage(config: &OrchestratorConfig) -> Result<UpdateState, String> {
+    let plan = stage_update_with_config(&config.update);
+    if !plan.warnings.is_empty() {
+        return Err(plan.warnings.join("; "));
+    }
+    if plan.artifacts.is_empty() {
+        return Err("Update manifest has no artifacts".to_string());
+    }
+    fs::create_dir_all(&plan.stage_dir).map_err(|err| format!("Unable to create stage directory: {}", err))?;
+    let mut artifacts = Vec::with_capacity(plan.artifacts.len());
+    for artifact in plan.artifacts {
+        if Path::new(&artifact.name).file_name().and_then(|name| name.to_str()) != Some(artifact.name.as_str()) {
+            return Err(format!("Artifact name {} is not a plain file name", artifact.name));
+        }
+        fs::copy(&artifact.source_path, &artifact.staged_path)
+            .map_err(|err| format!("Unable to stage {}: {}", artifact.name, err))?;
+        artifacts.push(ArtifactState {
+            install_path: config.install_dir.join(&artifact.name),
+            name: artifact.name,
+            sha256: artifact.sha256,
+            staged_path: artifact.staged_path,
+            backup_path: None,
+        });
+    }
+    Ok(UpdateState {
+        phase: UpdatePhase::Staged,
+        manifest_version: plan.manifest_version,
+        previous_version: plan.rollback.previous_version,
+        artifacts,
+        applied_at_unix_ms: None,
+        updated_at_unix_ms: unix_time_ms(),
+        reason: None,
+    })
+}
+
+fn verify_staged(state: &UpdateState) -> Result<(), String> {
+    for artifact in &state.artifacts {
+        let sha256 = hash_file(&artifact.staged_path)?;
+        if !sha256.eq_ignore_ascii_case(&artifact.sha256) {
+            return Err(format!("Staged artifact {} no longer matches its hash", artifact.name));
+        }
+    }
+    Ok(())
+}
+
+/// Copy every file about to be replaced into the backup directory. Backup paths are recorded
+/// only once every copy has been written.
+fn back_up(config: &OrchestratorConfig, state: &mut UpdateState) -> Result<(), String> {
+    fs::create_dir_all(&config.backup_dir).map_err(|err| format!("Unable to create backup directory: {}", err))?;
+    let mut backups = Vec::with_capacity(state.artifacts.len());
+    for artifact in &state.artifacts {
+        if artifact.install_path.is_file() {
+            let backup_path = config.backup_dir.join(&artifact.name);
+            fs::copy(&artifact.install_path, &backup_path)
+                .map_err(|err| format!("Unable to back up {}: {}", artifact.name, err))?;
+            backups.push(Some(backup_path));
+        } else {
+            backups.push(None);
+        }
+    }
+    for (artifact, backup_path) in state.artifacts.iter_mut().zip(backups) {
+        artifact.backup_path = backup_path;
+    }
+    Ok(())
+}
+
+/// Move each staged artifact into place with a rename so the install directory never holds a
+/// partly written file.
+fn apply(config: &OrchestratorConfig, state: &mut UpdateState) -> Result<(), String> {
+    transition(config, state, UpdatePhase::Applying);
+    for artifact in &state.artifacts {
+        replace_file(&artifact.staged_path, &artifact.install_path)
+            .map_err(|err| format!("Unable to install {}: {}", artifact.name, err))?;
+    }
+    state.applied_at_unix_ms = Some(unix_time_ms());
+    transition(config, state, UpdatePhase::Applied);
+    info!(version = %state.manifest_version, "update applied; waiting for healthy report");
+    Ok(())
+}
+
+fn roll_back(config: &OrchestratorConfig, state: &mut UpdateState, reason: String) {
+    warn!(version = %state.manifest_version, reason = %reason, "rolling back update");
+    let mut failures = Vec::new();
+    for artifact in &state.artifacts {
+        let restored = match &artifact.backup_path {
+            Some(backup_path) => replace_file(backup_path, &artifact.install_path),
+            None if artifact.install_path.exists() => fs::remove_file(&artifact.install_path),
+            None => Ok(()),
+        };
+        if let Err(err) = restored {
+            failures.push(format!("{}: {}", artifact.name, err));
+        }
+    }
+    if failures.is_empty() {
+        state.reason = Some(reason);
+        transition(config, state, UpdatePhase::RolledBack);
+    } else {
+        state.reason = Some(format!("{}; rollback incomplete: {}", reason, failures.join("; ")));
+        transition(config, state, UpdatePhase::Failed);
+    }
+}
+
+fn fail(config: &OrchestratorConfig, state: &mut UpdateState, reason: String) {
+    warn!(version = %state.manifest_version, reason = %reason, "update abandoned");
+    state.reason = Some(reason);
+    transition(config, state, UpdatePhase::Failed);
+}
+
+fn failed_state(reason: String) -> UpdateState {
+    warn!(reason = %reason, "update could not be staged");
+    UpdateState {
+        phase: UpdatePhase::Failed,
+        manifest_version: "unknown".to_string(),
+        previous_version: None,
+        artifacts: Vec::new(),
+        applied_at_unix_ms: None,
+        updated_at_unix_ms: unix_time_ms(),
+        reason: Some(reason),
+    }
+}
+
+fn transition(config: &OrchestratorConfig, state: &mut UpdateState, phase: UpdatePhase) {
+    state.phase = phase;
+    state.updated_at_unix_ms = unix_time_ms();
+    persist_logged(state, &config.state_path);
+    let audited = match phase {
+        UpdatePhase::Applied => Some("applied"),
+        UpdatePhase::Committed => Some("committed"),
+        UpdatePhase::RolledBack => Some("rolled_back"),
+        UpdatePhase::Failed => Some("failed"),
+        _ => None,
+    };
+    if let Some(phase) = audited {
+        audit::record(AuditEvent::UpdatePhase {
+            version: state.manifest_version.clone(),
+            phase: phase.to_string(),
+            reason: state.reason.clone(),
+        });
+    }
+}
+
+fn persist_logged(state: &UpdateState, path: &Path) {
+    if let Err(err) = persist_state(state, path) {
+        warn!(error = %err, phase = ?state.phase, "unable to persist update state");
+    }
+}
+
+fn persist_state(state: &UpdateState, path: &Path) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|err| format!("Unable to create {}: {}", parent.display(), err))?;
+    }
+    let bytes = serde_json::to_vec_pretty(state).map_err(|err| format!("Unable to encode update state: {}", err))?;
+    let staging = path.with_extension("json.tmp");
+    fs::write(&staging, bytes).map_err(|err| format!("Unable to write {}: {}", staging.display(), err))?;
+    fs::rename(&staging, path).map_err(|err| format!("Unable to move {} into place: {}", path.display(), err))
+}
+
+fn replace_file(source: &Path, target: &Path) -> std::io::Result<()> {
+    let mut temp_name = target.file_name().map(|name| name.to_os_string()).unwrap_or_default();
+    temp_name.push(".update-tmp");
+    let temp_path = target.with_file_name(temp_name);
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::copy(source, &temp_path)?;
+    fs::rename(&temp_path, target)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::{
+        health_decision, load_state, persist_state, resume_update, run_pending_update, run_update, HealthDecision,
+        OrchestratorConfig, UpdatePhase,
+    };
+    use crate::pipeline::HealthState;
+    use crate::telemetry_router::sha256_hex;
+    use crate::time::unix_time_ms;
+    use crate::update::UpdateConfig;
+
+    fn orchestrator_config(label: &str, contents: &[u8]) -> (PathBuf, OrchestratorConfig) {
+        let dir = std::env::temp_dir().join(format!("update-orchestrator-{}-{}-{}", label, std::process::id(), unix_time_ms()));
+        std::fs::create_dir_all(dir.join("install")).expect("create install dir");
+        std::fs::write(dir.join("install").join("agent-core.bin"), b"old build").expect("write installed build");
+        let source = dir.join("agent-core.bin");
+        std::fs::write(&source, contents).expect("write artifact");
+        let manifest = serde_json::json!({
+            "version": "2.0.0",
+            "channel": "stable",
+            "prerelease": false,
+            "artifacts": [{"name": "agent-core.bin", "path": source.display().to_string(), "sha256": sha256_hex(contents)}],
+            "previous_version": "1.9.0",
+        });
+        let update = UpdateConfig {
+            manifest_path: None,
+            manifest_json: Some(manifest.to_string()),
+            stage_dir: dir.join("staging"),
+            max_payload_bytes: 1024,
+            max_artifacts: 4,
+            required_channel: None,
+            allow_prerelease: false,
+            expected_manifest_sha256: None,
+            max_manifest_bytes: 64 * 1024,
+            publisher_public_key: None,
+            require_artifact_signatures: false,
+        };
+        let config = OrchestratorConfig {
+            install_dir: dir.join("install"),
+            backup_dir: dir.join("staging").join("rollback"),
+            state_path: dir.join("staging").join("update_state.json"),
+            update,
+            health_timeout_ms: 50,
+            health_poll_ms: 5,
+            health_components: vec!["policy".to_string()],
+            verify_only: false,
+        };
+        (dir, config)
+    }
+
+    fn installed(config: &OrchestratorConfig) -> Vec<u8> {
+        std::fs::read(Path::new(&config.install_dir).join("agent-core.bin")).expect("read installed build")
+    }
+
+    #[tokio::test]
+    async fn commits_update_once_agent_reports_ready() {
+        let (dir, config) = orchestrator_config("commit", b"new build");
+
+        let state = run_update(&config, || async { Some(HealthState::Ready) }).await;
+
+        assert_eq!(state.phase, UpdatePhase::Committed, "{:?}", state.reason);
+        assert_eq!(installed(&config), b"new build");
+        assert_eq!(load_state(&config.state_path).map(|state| state.phase), Some(UpdatePhase::Committed));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn rolls_back_when_agent_stays_unhealthy() {
+        let (dir, config) = orchestrator_config("rollback", b"new build");
+
+        let state = run_update(&config, || async { Some(HealthState::Failed) }).await;
+
+        assert_eq!(state.phase, UpdatePhase::RolledBack);
+        assert!(state.reason.as_deref().unwrap_or_default().contains("last: failed"));
+        assert_eq!(installed(&config), b"old build");
+        assert_eq!(load_state(&config.state_path).map(|state| state.phase), Some(UpdatePhase::RolledBack));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn rolls_back_update_interrupted_while_applying() {
+        let (dir, config) = orchestrator_config("resume", b"new build");
+        let mut state = run_update(&config, || async { Some(HealthState::Ready) }).await;
+        std::fs::write(config.install_dir.join("agent-core.bin"), b"old build").expect("reset install");
+        // Simulate a crash after the backup was taken and the first artifact swapped in.
+        std::fs::write(config.backup_dir.join("agent-core.bin"), b"old build").expect("write backup");
+        state.artifacts[0].backup_path = Some(config.backup_dir.join("agent-core.bin"));
+        std::fs::write(config.install_dir.join("agent-core.bin"), b"new build").expect("swap in artifact");
+        state.phase = UpdatePhase::Applying;
+        persist_state(&state, &config.state_path).expect("persist applying state");
+
+        let resumed = resume_update(&config, || async { Some(HealthState::Ready) }).await.expect("update resumed");
+
+        assert_eq!(resumed.phase, UpdatePhase::RolledBack);
+        assert_eq!(installed(&config), b"old build");
+        assert!(resume_update(&config, || async { Some(HealthState::Ready) }).await.is_none());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn abandons_update_without_touching_install_when_backup_fails() {
+        let (dir, config) = orchestrator_config("backup", b"new build");
+        // A file where the backup directory should be makes every backup fail.
+        std::fs::create_dir_all(config.backup_dir.parent().expect("stage dir")).expect("create stage dir");
+        std::fs::write(&config.backup_dir, b"not a directory").expect("block backup dir");
+
+        let state = run_update(&config, || async { Some(HealthState::Ready) }).await;
+
+        assert_eq!(state.phase, UpdatePhase::Failed);
+        assert!(state.artifacts.iter().all(|artifact| artifact.backup_path.is_none()));
+        assert_eq!(installed(&config), b"old build");
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn runs_a_configured_version_once() {
+        let (dir, config) = orchestrator_config("pending", b"new build");
+
+        let first = run_pending_update(&config, || async { Some(HealthState::Ready) }).await;
+        assert_eq!(first.map(|state| state.phase), Some(UpdatePhase::Committed));
+        assert!(run_pending_update(&config, || async { Some(HealthState::Ready) }).await.is_none());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn waits_for_ready_until_deadline() {
+        assert_eq!(health_decision(Some(HealthState::Ready), 1_000, 1_010, 100), HealthDecision::Commit);
+        assert_eq!(health_decision(Some(HealthState::Degraded), 1_000, 1_050, 100), HealthDecision::Wait);
+        assert_eq!(health_decision(None, 1_000, 1_099, 100), HealthDecision::Wait);
+        assert!(matches!(
+            health_decision(Some(HealthState::Failed), 1_000, 1_100, 100),
+            HealthDecision::RollBack(reason) if reason.contains("last: failed")
+        ));
+    }
+}
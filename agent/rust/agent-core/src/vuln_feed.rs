@@ -0,0 +1,451 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use reqwest::header::{ETAG, IF_NONE_MATCH};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
+
+use crate::compliance::{ComplianceResult, ComplianceStatus};
+use crate::crypto::{HmacSha256, Verifier};
+use crate::policy::PolicyValidationOptions;
+use crate::state_dir::StatePaths;
+
+pub const FEED_CONTROL_ID: &str = "CMP-VULN-FEED-FRESH";
+/// Base64 HMAC-SHA256 of `<signed-at>\n<body>` under the policy signing key. A 304 carries
+/// one too, over the cached body, to confirm it is still current.
+pub const FEED_SIGNATURE_HEADER: &str = "X-Feed-Signature";
+/// Unix milliseconds at which the backend signed the response.
+pub const FEED_SIGNED_AT_HEADER: &str = "X-Feed-Signed-At";
+
+/// Download of the CVE feed from the backend, replacing a hand-shipped `VULN_FEED`.
+#[derive(Debug, Clone)]
+pub struct FeedConfig {
+    pub url: Option<String>,
+    pub cache_path: PathBuf,
+    /// Key the feed signature is checked with; feeds are refused without one.
+    pub signing_key: Option<String>,
+    /// A feed the backend has not confirmed for longer than this fails the freshness control.
+    pub max_age_ms: u64,
+    pub max_bytes: u64,
+}
+
+impl FeedConfig {
+    pub fn from_env() -> Self {
+        let url = env::var("VULN_FEED_URL").ok().filter(|value| !value.trim().is_empty());
+        let cache_path = env::var("VULN_FEED_CACHE_PATH")
+            .ok()
+            .filter(|value| !value.trim().is_empty())
+            .map(PathBuf::from)
+            .unwrap_or_else(|| StatePaths::from_env().root.join("vuln_feed.json"));
+        let max_age_ms = env::var("VULN_FEED_MAX_AGE_SECS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .filter(|value| *value > 0)
+            .unwrap_or(7 * 24 * 3600)
+            .saturating_mul(1000);
+        let max_bytes = env::var("VULN_FEED_MAX_BYTES")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .filter(|value| *value > 0)
+            .unwrap_or(8 * 1024 * 1024);
+
+        Self {
+            url,
+            cache_path,
+            signing_key: PolicyValidationOptions::from_env().signing_key,
+            max_age_ms,
+            max_bytes,
+        }
+    }
+}
+
+/// Last good feed. `verified_at_unix_ms` is the backend's signing time of the latest
+/// verified 200 or 304, never later than the local clock.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CachedFeed {
+    pub etag: Option<String>,
+    pub verified_at_unix_ms: u64,
+    pub body: String,
+}
+
+impl CachedFeed {
+    /// A missing or unreadable cache is treated as no cache.
+    pub fn load(path: &Path) -> Option<Self> {
+        let raw = fs::read(path).ok()?;
+        match serde_json::from_slice(&raw) {
+            Ok(cached) => Some(cached),
+            Err(err) => {
+                warn!(error = %err, path = %path.display(), "ignoring unreadable vulnerability feed cache");
+                None
+            }
+        }
+    }
+
+    fn store(&self, path: &Path) -> Result<(), String> {
+        let raw = serde_json::to_vec(self).map_err(|err| err.to_string())?;
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent).map_err(|err| format!("Unable to create {}: {}", parent.display(), err))?;
+        }
+        let staging = path.with_extension("tmp");
+        fs::write(&staging, raw).map_err(|err| format!("Unable to write {}: {}", staging.display(), err))?;
+        fs::rename(&staging, path).map_err(|err| format!("Unable to move {} into place: {}", path.display(), err))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FeedSource {
+    /// A new, signature-verified feed was downloaded.
+    Downloaded,
+    /// The backend answered 304; the cached feed is current.
+    NotModified,
+    /// The fetch failed and the last good cached feed is used.
+    Cached { reason: String },
+    /// The fetch failed and there is no cached feed.
+    Unavailable { reason: String },
+}
+
+#[derive(Debug, Clone)]
+pub struct FeedResult {
+    pub source: FeedSource,
+    pub feed: Option<CachedFeed>,
+}
+
+impl FeedResult {
+    pub fn body(&self) -> Option<&str> {
+        self.feed.as_ref().map(|feed| feed.body.as_str())
+    }
+
+    /// Failed freshness control when the feed is missing or older than `max_age_ms`.
+    pub fn staleness_result(&self, config: &FeedConfig, now_unix_ms: u64) -> Option<ComplianceResult> {
+        let finding = match &self.feed {
+            None => "no vulnerability feed has been fetched from the backend".to_string(),
+            Some(feed) => {
+                let age_ms = now_unix_ms.saturating_sub(feed.verified_at_unix_ms);
+                if age_ms <= config.max_age_ms {
+                    return None;
+                }
+                format!(
+                    "vulnerability feed last confirmed {} s ago (limit {} s)",
+                    age_ms / 1000,
+                    config.max_age_ms / 1000
+                )
+            }
+        };
+        Some(ComplianceResult {
+            control_id: FEED_CONTROL_ID.to_string(),
+            control_title: "Vulnerability feed is current".to_string(),
+            passed: false,
+            status: ComplianceStatus::Fail,
+            evidence_ref: Sha256::digest(finding.as_bytes())
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect(),
+            checked_at_unix_ms: now_unix_ms,
+            findings: vec![finding],
+            asserted_by: None,
+        })
+    }
+}
+
+/// Fetch the feed, sending the cached ETag so an unchanged feed is not downloaded again.
+/// A verified download replaces the cache; any failure falls back to the cached copy.
+pub async fn refresh_feed(client: &reqwest::Client, config: &FeedConfig, now_unix_ms: u64) -> FeedResult {
+    let cached = CachedFeed::load(&config.cache_path);
+    let reason = match fetch(client, config, cached.as_ref()).await {
+        Ok(Fetched::NotModified { signed_at_unix_ms }) => match cached.clone() {
+            Some(mut feed) => {
+                feed.verified_at_unix_ms = signed_at_unix_ms.min(now_unix_ms);
+                if let Err(err) = feed.store(&config.cache_path) {
+                    warn!(error = %err, "failed to update vulnerability feed cache");
+                }
+                return FeedResult {
+                    source: FeedSource::NotModified,
+                    feed: Some(feed),
+                };
+            }
+            None => "backend answered 304 but no feed is cached".to_string(),
+        },
+        Ok(Fetched::Body {
+            etag,
+            body,
+            signed_at_unix_ms,
+        }) => {
+            let feed = CachedFeed {
+                etag,
+                verified_at_unix_ms: signed_at_unix_ms.min(now_unix_ms),
+                body,
+            };
+            if let Err(err) = feed.store(&config.cache_path) {
+                warn!(error = %err, "failed to cache vulnerability feed");
+            }
+            info!(bytes = feed.body.len(), "downloaded vulnerability feed");
+            return FeedResult {
+                source: FeedSource::Downloaded,
+                feed: Some(feed),
+            };
+        }
+        Err(reason) => reason,
+    };
+
+    match cached {
+        Some(feed) => {
+            warn!(
+                error = %reason,
+                age_secs = now_unix_ms.saturating_sub(feed.verified_at_unix_ms) / 1000,
+                "vulnerability feed fetch failed; using cached feed"
+            );
+            FeedResult {
+                source: FeedSource::Cached { reason },
+                feed: Some(feed),
+            }
+        }
+        None => {
+            warn!(error = %reason, "vulnerability feed fetch failed and no cached feed is available");
+            FeedResult {
+                source: FeedSource::Unavailable { reason },
+                feed: None,
+            }
+        }
+    }
+}
+
+enum Fetched {
+    NotModified {
+        signed_at_unix_ms: u64,
+    },
+    Body {
+        etag: Option<String>,
+        body: String,
+        signed_at_unix_ms: u64,
+    },
+}
+
+async fn fetch(client: &reqwest::Client, config: &FeedConfig, cached: Option<&CachedFeed>) -> Result<Fetched, String> {
+    let url = config.url.as_deref().ok_or("VULN_FEED_URL is not set")?;
+    let signing_key = config
+        .signing_key
+        .as_deref()
+        .ok_or("AGENT_POLICY_SIGNING_KEY is not set; feed signature cannot be verified")?;
+    let mut request = client.get(url);
+    if let Some(etag) = cached.and_then(|feed| feed.etag.as_deref()) {
+        request = request.header(IF_NONE_MATCH, etag);
+    }
+    let mut response = request.send().await.map_err(|err| format!("request failed: {}", err))?;
+    let status = response.status();
+    if status != StatusCode::NOT_MODIFIED && !status.is_success() {
+        return Err(format!("backend answered {}", status));
+    }
+    let headers = response.headers();
+    let etag = headers.get(ETAG).and_then(|value| value.to_str().ok()).map(str::to_string);
+    let signature = headers
+        .get(FEED_SIGNATURE_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+        .ok_or("feed is not signed")?;
+    let signed_at_unix_ms = headers
+        .get(FEED_SIGNED_AT_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<u64>().ok())
+        .ok_or("feed signature has no signing time")?;
+    if cached.is_some_and(|feed| signed_at_unix_ms < feed.verified_at_unix_ms) {
+        return Err("feed signature is older than the cached feed".to_string());
+    }
+    let verifier = HmacSha256::new(signing_key.as_bytes());
+
+    if status == StatusCode::NOT_MODIFIED {
+        let cached = cached.ok_or("backend answered 304 but no feed is cached")?;
+        if !verifier.verify(&signed_message(signed_at_unix_ms, cached.body.as_bytes()), &signature) {
+            return Err("feed signature did not verify".to_string());
+        }
+        return Ok(Fetched::NotModified { signed_at_unix_ms });
+    }
+
+    if response.content_length().is_some_and(|length| length > config.max_bytes) {
+        return Err(format!("feed exceeds {} bytes", config.max_bytes));
+    }
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await.map_err(|err| format!("failed to read feed: {}", err))? {
+        if (body.len() + chunk.len()) as u64 > config.max_bytes {
+            return Err(format!("feed exceeds {} bytes", config.max_bytes));
+        }
+        body.extend_from_slice(&chunk);
+    }
+    if !verifier.verify(&signed_message(signed_at_unix_ms, &body), &signature) {
+        return Err("feed signature did not verify".to_string());
+    }
+    let body = String::from_utf8(body).map_err(|_| "feed is not UTF-8".to_string())?;
+    Ok(Fetched::Body {
+        etag,
+        body,
+        signed_at_unix_ms,
+    })
+}
+
+/// Bytes the feed signature covers: the signing time, a newline, then the body.
+fn signed_message(signed_at_unix_ms: u64, body: &[u8]) -> Vec<u8> {
+    let mut message = format!("{}\n", signed_at_unix_ms).into_bytes();
+    message.extend_from_slice(body);
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+
+    use super::{refresh_feed, CachedFeed, FeedConfig, FeedSource, FEED_CONTROL_ID};
+    use crate::crypto::{HmacSha256, Signer};
+    use crate::time::unix_time_ms;
+
+    const KEY: &str = "feed-signing-key";
+    const FEED: &str = "CVE-2024-0001|9.8|openssl remote code execution|Upgrade openssl|";
+
+    /// Serves `responses` in order and reports each request's head.
+    fn serve(responses: Vec<String>) -> (String, mpsc::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind listener");
+        let address = listener.local_addr().expect("local addr");
+        let (seen_tx, seen_rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            for (stream, response) in listener.incoming().zip(responses) {
+                let Ok(mut stream) = stream else { continue };
+                let mut buffer = [0_u8; 4096];
+                let read = stream.read(&mut buffer).unwrap_or(0);
+                let _ = seen_tx.send(String::from_utf8_lossy(&buffer[..read]).to_ascii_lowercase());
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+        (format!("http://{}/feed", address), seen_rx)
+    }
+
+    fn sign(signed_at: u64, body: &str) -> String {
+        HmacSha256::new(KEY.as_bytes()).sign(format!("{}\n{}", signed_at, body).as_bytes())
+    }
+
+    fn ok(body: &str, signed_at: u64, signature: &str) -> String {
+        format!(
+            "HTTP/1.1 200 OK\r\netag: \"v1\"\r\nx-feed-signature: {}\r\nx-feed-signed-at: {}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+            signature,
+            signed_at,
+            body.len(),
+            body
+        )
+    }
+
+    fn not_modified(headers: &str) -> String {
+        format!("HTTP/1.1 304 Not Modified\r\netag: \"v1\"\r\n{}connection: close\r\n\r\n", headers)
+    }
+
+    fn config(label: &str, url: String) -> FeedConfig {
+        let dir = std::env::temp_dir().join(format!("vuln-feed-{}-{}-{}", label, std::process::id(), unix_time_ms()));
+        FeedConfig {
+            url: Some(url),
+            cache_path: dir.join("vuln_feed.json"),
+            signing_key: Some(KEY.to_string()),
+            max_age_ms: 60_000,
+            max_bytes: 4096,
+        }
+    }
+
+    #[tokio::test]
+    async fn reuses_the_cached_feed_on_not_modified() {
+        let confirmed = format!("x-feed-signature: {}\r\nx-feed-signed-at: 1900\r\n", sign(1_900, FEED));
+        let (url, seen) = serve(vec![ok(FEED, 1_000, &sign(1_000, FEED)), not_modified(&confirmed)]);
+        let config = config("etag", url);
+        let client = reqwest::Client::new();
+
+        let first = refresh_feed(&client, &config, 1_000).await;
+        assert_eq!(first.source, FeedSource::Downloaded);
+        assert_eq!(first.body(), Some(FEED));
+        assert!(!seen.recv().expect("first request").contains("if-none-match"));
+
+        let second = refresh_feed(&client, &config, 2_000).await;
+        assert_eq!(second.source, FeedSource::NotModified);
+        assert_eq!(second.body(), Some(FEED));
+        assert!(seen.recv().expect("second request").contains("if-none-match: \"v1\""));
+        let cached = CachedFeed::load(&config.cache_path).expect("cache");
+        assert_eq!(cached.verified_at_unix_ms, 1_900);
+        assert!(second.staleness_result(&config, 3_000).is_none());
+        let _ = std::fs::remove_dir_all(config.cache_path.parent().expect("cache dir"));
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_cache_on_bad_signature_and_reports_staleness() {
+        let signature = sign(1_000, FEED);
+        let tampered = "CVE-2024-0001|0.1|nothing to see|";
+        let (url, _seen) = serve(vec![ok(FEED, 1_000, &signature), ok(tampered, 1_000, &signature)]);
+        let config = config("signature", url);
+        let client = reqwest::Client::new();
+
+        refresh_feed(&client, &config, 1_000).await;
+        let fallback = refresh_feed(&client, &config, 100_000).await;
+        assert_eq!(
+            fallback.source,
+            FeedSource::Cached {
+                reason: "feed signature did not verify".to_string()
+            }
+        );
+        assert_eq!(fallback.body(), Some(FEED));
+        assert_eq!(CachedFeed::load(&config.cache_path).expect("cache").verified_at_unix_ms, 1_000);
+
+        let stale = fallback.staleness_result(&config, 100_000).expect("stale feed");
+        assert_eq!(stale.control_id, FEED_CONTROL_ID);
+        assert!(!stale.passed);
+        assert_eq!(stale.findings, vec!["vulnerability feed last confirmed 99 s ago (limit 60 s)".to_string()]);
+        let _ = std::fs::remove_dir_all(config.cache_path.parent().expect("cache dir"));
+    }
+
+    #[tokio::test]
+    async fn unsigned_or_replayed_confirmations_do_not_refresh_the_feed() {
+        let replayed = format!("x-feed-signature: {}\r\nx-feed-signed-at: 500\r\n", sign(500, FEED));
+        let (url, _seen) = serve(vec![
+            ok(FEED, 1_000, &sign(1_000, FEED)),
+            not_modified(""),
+            not_modified(&replayed),
+        ]);
+        let config = config("replay", url);
+        let client = reqwest::Client::new();
+
+        refresh_feed(&client, &config, 1_000).await;
+        let unsigned = refresh_feed(&client, &config, 50_000).await;
+        assert_eq!(
+            unsigned.source,
+            FeedSource::Cached {
+                reason: "feed is not signed".to_string()
+            }
+        );
+        let replay = refresh_feed(&client, &config, 90_000).await;
+        assert_eq!(
+            replay.source,
+            FeedSource::Cached {
+                reason: "feed signature is older than the cached feed".to_string()
+            }
+        );
+        assert_eq!(CachedFeed::load(&config.cache_path).expect("cache").verified_at_unix_ms, 1_000);
+        assert!(replay.staleness_result(&config, 90_000).is_some());
+        let _ = std::fs::remove_dir_all(config.cache_path.parent().expect("cache dir"));
+    }
+
+    #[tokio::test]
+    async fn stops_reading_a_feed_past_the_size_cap() {
+        let body = "x".repeat(8192);
+        let response = format!(
+            "HTTP/1.1 200 OK\r\nx-feed-signature: {}\r\nx-feed-signed-at: 1000\r\nconnection: close\r\n\r\n{}",
+            sign(1_000, &body),
+            body
+        );
+        let (url, _seen) = serve(vec![response]);
+        let config = config("oversized", url);
+
+        let result = refresh_feed(&reqwest::Client::new(), &config, 1_000).await;
+        assert_eq!(
+            result.source,
+            FeedSource::Unavailable {
+                reason: "feed exceeds 4096 bytes".to_string()
+            }
+        );
+    }
+}
@@ -2,7 +2,17 @@ use std::env;
 
 use sha2::{Digest, Sha256};
 
-use crate::time::unix_time_ms;
+use crate::certificates::{certificate_issues, scan_certificates, CertificateConfig, CertificateIssue, CertificateRecord};
+use crate::config::CoreConfig;
+use crate::edr::EdrConfig;
+use crate::listening::{dedup_services, ListeningService, ListeningSocketSource, PlatformSocketSource};
+use crate::time::{format_date, unix_time_ms};
+
+/// Score given to a risky port listening on every interface.
+const EXPOSURE_SCORE: f32 = 7.5;
+const CERT_EXPIRED_SCORE: f32 = 7.0;
+const CERT_EXPIRING_SCORE: f32 = 5.0;
+const CERT_WEAK_KEY_SCORE: f32 = 5.5;
 
 #[derive(Debug, Clone)]
 pub struct VulnerabilityFinding {
@@ -16,6 +26,8 @@ pub struct VulnerabilityFinding {
     pub summary: String,
     pub remediation: String,
     pub references: Vec<String>,
+    /// The listening service behind an exposure finding; `None` for CVE matches.
+    pub exposure: Option<ListeningService>,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -58,6 +70,46 @@ impl VulnerabilityConfig {
     }
 }
 
+/// Check for risky ports listening on every interface, run alongside the CVE match.
+#[derive(Debug, Clone)]
+pub struct ExposureConfig {
+    pub enabled: bool,
+    /// Defaults to the EDR suspicious ports (`EDR_SUSPICIOUS_PORTS`).
+    pub risky_ports: Vec<u16>,
+    /// Distinct `(port, protocol)` pairs considered per run.
+    pub max_services: usize,
+    pub asset_id: String,
+}
+
+impl ExposureConfig {
+    pub fn from_env() -> Self {
+        let enabled = env::var("VULN_EXPOSURE_CHECK")
+            .map(|value| !matches!(value.trim().to_ascii_lowercase().as_str(), "0" | "false" | "no"))
+            .unwrap_or(true);
+        let risky_ports = env::var("VULN_EXPOSURE_RISKY_PORTS")
+            .ok()
+            .map(|value| {
+                parse_csv(&value)
+                    .iter()
+                    .filter_map(|entry| entry.parse::<u16>().ok())
+                    .collect::<Vec<u16>>()
+            })
+            .unwrap_or_else(|| EdrConfig::from_env().suspicious_ports);
+        let max_services = env::var("VULN_EXPOSURE_MAX_SERVICES")
+            .ok()
+            .and_then(|value| value.parse::<usize>().ok())
+            .filter(|value| *value > 0)
+            .unwrap_or(256);
+
+        Self {
+            enabled,
+            risky_ports,
+            max_services,
+            asset_id: CoreConfig::from_env().asset_id,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct InventoryItem {
     pub asset_id: String,
@@ -75,10 +127,153 @@ pub struct CveEntry {
 }
 
 pub fn assess_exposure() -> Vec<VulnerabilityFinding> {
+    assess_exposure_with_feed(load_cve_feed())
+}
+
+/// Same as [`assess_exposure`] with the CVE feed supplied by the caller, e.g. one fetched
+/// from the backend (see `vuln_feed`).
+pub fn assess_exposure_with_feed(cve_feed: Vec<CveEntry>) -> Vec<VulnerabilityFinding> {
     let config = VulnerabilityConfig::from_env();
     let inventory = load_inventory();
-    let cve_feed = load_cve_feed();
-    assess_exposure_with_data(&inventory, &cve_feed, &config)
+    let mut findings = assess_exposure_with_data(&inventory, &cve_feed, &config);
+    let exposure_config = ExposureConfig::from_env();
+    if exposure_config.enabled && findings.len() < config.max_findings {
+        findings.extend(assess_listening_exposure(&PlatformSocketSource, &exposure_config, &config));
+        findings.truncate(config.max_findings);
+    }
+    let certificate_config = CertificateConfig::from_env();
+    if !certificate_config.scan_paths.is_empty() && findings.len() < config.max_findings {
+        let records = scan_certificates(&certificate_config);
+        findings.extend(assess_certificate_findings(&records, &certificate_config, &exposure_config.asset_id, &config));
+        findings.truncate(config.max_findings);
+    }
+    findings
+}
+
+/// One finding per expired, soon-to-expire or weak-key certificate, identified by
+/// `CERT-EXPIRED`, `CERT-EXPIRING` or `CERT-WEAK-KEY`.
+pub fn assess_certificate_findings(
+    records: &[CertificateRecord],
+    certificate_config: &CertificateConfig,
+    asset_id: &str,
+    config: &VulnerabilityConfig,
+) -> Vec<VulnerabilityFinding> {
+    let source = select_source(&config.allowed_sources);
+    if source.is_empty() {
+        return Vec::new();
+    }
+    let detected_at_unix_ms = unix_time_ms();
+    let mut findings = Vec::new();
+    for record in records {
+        let certificate = &record.certificate;
+        for issue in certificate_issues(certificate, certificate_config, detected_at_unix_ms) {
+            let (cve_id, score, remediation) = match issue {
+                CertificateIssue::Expired => ("CERT-EXPIRED", CERT_EXPIRED_SCORE, "Renew the certificate and reload the service using it"),
+                CertificateIssue::ExpiringSoon { .. } => {
+                    ("CERT-EXPIRING", CERT_EXPIRING_SCORE, "Renew the certificate before it expires")
+                }
+                CertificateIssue::WeakKey { .. } => (
+                    "CERT-WEAK-KEY",
+                    CERT_WEAK_KEY_SCORE,
+                    "Reissue the certificate with an RSA key of at least 2048 bits or an EC key",
+                ),
+            };
+            if score < config.min_score {
+                continue;
+            }
+            findings.push(VulnerabilityFinding {
+                finding_id: build_finding_id(
+                    asset_id,
+                    &format!("{}:{}", cve_id, certificate.fingerprint_sha256),
+                    detected_at_unix_ms,
+                ),
+                cve_id: cve_id.to_string(),
+                severity: severity_from_score(score),
+                score,
+                affected_asset: asset_id.to_string(),
+                detected_at_unix_ms,
+                source: source.clone(),
+                summary: format!(
+                    "Certificate {} in {} (issuer {}, not after {}) {}",
+                    certificate.subject,
+                    record.path.display(),
+                    certificate.issuer,
+                    format_date(certificate.not_after_unix_secs),
+                    issue
+                ),
+                remediation: remediation.to_string(),
+                references: Vec::new(),
+                exposure: None,
+            });
+            if findings.len() >= config.max_findings {
+                return findings;
+            }
+        }
+    }
+    findings
+}
+
+/// One finding per risky port listening on every interface, deduplicated per
+/// `(port, protocol)` and bounded by `max_services` and `max_findings`. Only sockets on
+/// risky ports count towards `max_services`.
+pub fn assess_listening_exposure(
+    source: &dyn ListeningSocketSource,
+    exposure: &ExposureConfig,
+    config: &VulnerabilityConfig,
+) -> Vec<VulnerabilityFinding> {
+    if EXPOSURE_SCORE < config.min_score {
+        return Vec::new();
+    }
+    let source_name = select_source(&config.allowed_sources);
+    if source_name.is_empty() {
+        return Vec::new();
+    }
+    let services = match source.listening() {
+        Ok(services) => services,
+        Err(err) => {
+            tracing::warn!(error = %err, "listening socket collection failed");
+            return Vec::new();
+        }
+    };
+    let detected_at_unix_ms = unix_time_ms();
+    // Narrow to risky ports before capping, so sockets on other ports cannot push them out.
+    let risky = services
+        .into_iter()
+        .filter(|service| exposure.risky_ports.contains(&service.port))
+        .collect::<Vec<_>>();
+    dedup_services(risky, exposure.max_services)
+        .into_iter()
+        .filter(|service| service.is_wildcard())
+        .take(config.max_findings)
+        .map(|service| {
+            let cve_id = format!("EXPOSURE-{}-{}", service.protocol.as_str().to_ascii_uppercase(), service.port);
+            let owner = match (&service.process_name, service.pid) {
+                (Some(name), Some(pid)) => format!("{} (pid {})", name, pid),
+                (Some(name), None) => name.clone(),
+                (None, Some(pid)) => format!("pid {}", pid),
+                (None, None) => "an unknown process".to_string(),
+            };
+            VulnerabilityFinding {
+                finding_id: build_finding_id(&exposure.asset_id, &cve_id, detected_at_unix_ms),
+                cve_id,
+                severity: severity_from_score(EXPOSURE_SCORE),
+                score: EXPOSURE_SCORE,
+                affected_asset: exposure.asset_id.clone(),
+                detected_at_unix_ms,
+                source: source_name.clone(),
+                summary: format!(
+                    "{}/{} is listening on all interfaces ({}), owned by {}",
+                    service.protocol.as_str(),
+                    service.port,
+                    service.bind_address,
+                    owner
+                ),
+                remediation: "Bind the service to a specific interface or block the port at the host firewall".to_string(),
+                references: Vec::new(),
+                exposure: Some(service),
+            }
+        })
+        .collect()
 }
 
 pub fn assess_exposure_with_data(
@@ -114,6 +309,7 @@ pub fn assess_exposure_with_data(
                 summary: cve.summary.clone(),
                 remediation: cve.remediation.clone(),
                 references: cve.references.clone(),
+                exposure: None,
             });
 
             if findings.len() >= config.max_findings {
@@ -149,10 +345,11 @@ fn load_inventory() -> Vec<InventoryItem> {
 }
 
 fn load_cve_feed() -> Vec<CveEntry> {
-    let raw = env::var("VULN_FEED").ok().unwrap_or_default();
-    if raw.is_empty() {
-        return Vec::new();
-    }
+    parse_cve_feed(&env::var("VULN_FEED").ok().unwrap_or_default())
+}
+
+/// One `cve_id|score|summary|remediation|ref,ref` entry per line; malformed lines are skipped.
+pub fn parse_cve_feed(raw: &str) -> Vec<CveEntry> {
     raw.lines()
         .filter_map(|line| {
             let mut parts = line.split('|');
@@ -231,3 +428,73 @@ fn parse_csv(value: &str) -> Vec<String> {
         .filter(|entry| !entry.is_empty())
         .collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{assess_listening_exposure, ExposureConfig, VulnerabilityConfig};
+    use crate::listening::{parse_netstat, ListeningService, ListeningSocketSource};
+
+    struct FixtureSource(&'static str);
+
+    impl ListeningSocketSource for FixtureSource {
+        fn listening(&self) -> Result<Vec<ListeningService>, String> {
+            let mut services = parse_netstat(self.0);
+            for service in &mut services {
+                service.process_name = service.pid.map(|pid| format!("proc-{}", pid));
+            }
+            Ok(services)
+        }
+    }
+
+    #[test]
+    fn flags_risky_ports_listening_on_every_interface() {
+        let source = FixtureSource(
+            "  TCP    0.0.0.0:3389     0.0.0.0:0    LISTENING    1048
+  TCP    [::]:3389        [::]:0       LISTENING    1048
+  TCP    127.0.0.1:4444   0.0.0.0:0    LISTENING    900
+  TCP    0.0.0.0:8080     0.0.0.0:0    LISTENING    901
+  UDP    0.0.0.0:5985     *:*                       902
+  TCP    [::]:5985        [::]:0       LISTENING    4",
+        );
+        let exposure = ExposureConfig {
+            enabled: true,
+            risky_ports: vec![4444, 3389, 5985],
+            max_services: 64,
+            asset_id: "asset-1".to_string(),
+        };
+        let config = VulnerabilityConfig {
+            min_score: 0.0,
+            max_findings: 10,
+            allowed_sources: vec!["local-scan".to_string()],
+        };
+
+        let findings = assess_listening_exposure(&source, &exposure, &config);
+        let ids = findings.iter().map(|finding| finding.cve_id.as_str()).collect::<Vec<_>>();
+        assert_eq!(ids, vec!["EXPOSURE-TCP-3389", "EXPOSURE-TCP-5985", "EXPOSURE-UDP-5985"]);
+        let rdp = &findings[0];
+        assert_eq!(rdp.affected_asset, "asset-1");
+        assert!(rdp.summary.contains("proc-1048 (pid 1048)"), "{}", rdp.summary);
+        assert_eq!(rdp.exposure.as_ref().and_then(|service| service.pid), Some(1048));
+
+        let capped = VulnerabilityConfig {
+            max_findings: 1,
+            ..config.clone()
+        };
+        assert_eq!(assess_listening_exposure(&source, &exposure, &capped).len(), 1);
+        let one_service = ExposureConfig {
+            max_services: 1,
+            ..exposure.clone()
+        };
+        let busy = FixtureSource(
+            "  TCP    0.0.0.0:8080     0.0.0.0:0    LISTENING    901
+  TCP    0.0.0.0:3389     0.0.0.0:0    LISTENING    1048",
+        );
+        let ids = assess_listening_exposure(&busy, &one_service, &config)
+            .into_iter()
+            .map(|finding| finding.cve_id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec!["EXPOSURE-TCP-3389"]);
+        let strict = VulnerabilityConfig { min_score: 9.0, ..config };
+        assert!(assess_listening_exposure(&source, &exposure, &strict).is_empty());
+    }
+}
@@ -0,0 +1,433 @@
+use std::env;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use hmac::Mac;
+use prost::Message;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tracing::debug;
+
+use crate::frame::{read_frame, write_frame};
+use crate::proto::agent_ipc::envelope::Payload;
+use crate::proto::agent_ipc::{AuthChallenge, AuthResponse, Envelope, EnvelopeAck, HealthHeartbeat, SensorEvent};
+use crate::{handshake_mac, sensor_event_batch, IPC_SCHEMA_VERSION, MAX_SENSOR_BATCH_EVENTS};
+
+/// Largest frame a client accepts from the core.
+const MAX_CLIENT_FRAME_BYTES: usize = 64 * 1024;
+
+/// Byte stream to the core: a Unix socket, or a named pipe on Windows.
+trait IpcStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> IpcStream for T {}
+
+#[derive(Clone)]
+pub struct IpcClientConfig {
+    pub client_id: String,
+    pub auth_key: Option<Vec<u8>>,
+    /// Stamped on envelopes the client builds itself, such as heartbeats.
+    pub asset_id: String,
+    pub agent_id: String,
+    /// Envelopes larger than the core's limit are refused before sending.
+    pub max_payload_bytes: usize,
+    /// Times `send_envelope` writes an envelope before giving up on a dropped connection.
+    pub max_send_attempts: u32,
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    /// Stop retrying once this much time has passed; `None` retries until connected.
+    pub give_up_after_ms: Option<u64>,
+    /// Bound on reading the challenge and each acknowledgement.
+    pub io_timeout_ms: u64,
+}
+
+impl fmt::Debug for IpcClientConfig {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_struct("IpcClientConfig")
+            .field("client_id", &self.client_id)
+            .field("auth_key", &self.auth_key.as_ref().map(|_| "<redacted>"))
+            .field("asset_id", &self.asset_id)
+            .field("agent_id", &self.agent_id)
+            .field("max_payload_bytes", &self.max_payload_bytes)
+            .field("max_send_attempts", &self.max_send_attempts)
+            .field("initial_backoff_ms", &self.initial_backoff_ms)
+            .field("max_backoff_ms", &self.max_backoff_ms)
+            .field("give_up_after_ms", &self.give_up_after_ms)
+            .field("io_timeout_ms", &self.io_timeout_ms)
+            .finish()
+    }
+}
+
+impl IpcClientConfig {
+    /// Reads the identity and payload limit from the same variables, with the same
+    /// defaults, as agent-core's own config.
+    pub fn from_env() -> Self {
+        let asset_id = env::var("AGENT_ASSET_ID").unwrap_or_else(|_| "asset-placeholder".to_string());
+        let agent_id = env::var("AGENT_ID").unwrap_or_else(|_| "agent-core".to_string());
+        let max_payload_bytes = env::var("AGENT_MAX_PAYLOAD_BYTES")
+            .ok()
+            .and_then(|value| value.parse::<usize>().ok())
+            .unwrap_or(1024 * 1024);
+        let client_id = env::var("AGENT_IPC_CLIENT_ID")
+            .ok()
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+            .unwrap_or_else(|| "agent-watchdog".to_string());
+        let auth_key = env::var("AGENT_IPC_AUTH_KEY")
+            .ok()
+            .filter(|value| !value.is_empty())
+            .map(String::into_bytes);
+        let max_send_attempts = env::var("AGENT_IPC_SEND_ATTEMPTS")
+            .ok()
+            .and_then(|value| value.parse::<u32>().ok())
+            .filter(|value| *value > 0)
+            .unwrap_or(3);
+        let initial_backoff_ms = env::var("AGENT_IPC_RECONNECT_INITIAL_MS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .filter(|value| *value > 0)
+            .unwrap_or(100);
+        let max_backoff_ms = env::var("AGENT_IPC_RECONNECT_MAX_MS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .filter(|value| *value > 0)
+            .unwrap_or(10_000)
+            .max(initial_backoff_ms);
+        let give_up_after_ms = env::var("AGENT_IPC_CONNECT_DEADLINE_SECS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .filter(|value| *value > 0)
+            .map(|value| value.saturating_mul(1000));
+        Self {
+            client_id,
+            auth_key,
+            asset_id,
+            agent_id,
+            max_payload_bytes,
+            max_send_attempts,
+            initial_backoff_ms,
+            max_backoff_ms,
+            give_up_after_ms,
+            io_timeout_ms: 30_000,
+        }
+    }
+}
+
+/// Delay before reconnect attempt `attempt` (0-based): doubles from `initial_ms` up to
+/// `max_ms`.
+pub fn reconnect_backoff_ms(attempt: u32, initial_ms: u64, max_ms: u64) -> u64 {
+    initial_ms
+        .saturating_mul(1_u64.checked_shl(attempt.min(63)).unwrap_or(u64::MAX))
+        .min(max_ms)
+}
+
+/// `reconnect_backoff_ms` with its upper half drawn from `jitter_sample`, so clients that
+/// lost the core together do not reconnect in lockstep.
+pub fn jittered_backoff_ms(attempt: u32, initial_ms: u64, max_ms: u64, jitter_sample: u64) -> u64 {
+    let delay_ms = reconnect_backoff_ms(attempt, initial_ms, max_ms);
+    let floor_ms = delay_ms - delay_ms / 2;
+    floor_ms.saturating_add(jitter_sample % (delay_ms / 2 + 1))
+}
+
+fn jitter_sample() -> u64 {
+    let mut bytes = [0_u8; 8];
+    match getrandom::getrandom(&mut bytes) {
+        Ok(()) => u64::from_le_bytes(bytes),
+        Err(_) => unix_time_ms(),
+    }
+}
+
+/// Random hex id for an envelope the caller sent without one.
+fn new_envelope_id() -> String {
+    let mut bytes = [0_u8; 16];
+    if getrandom::getrandom(&mut bytes).is_err() {
+        bytes[..8].copy_from_slice(&unix_time_ms().to_le_bytes());
+        bytes[8..].copy_from_slice(&jitter_sample().to_le_bytes());
+    }
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn unix_time_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    Connected,
+    Disconnected,
+}
+
+type StateCallback = Arc<dyn Fn(ConnectionState) + Send + Sync>;
+
+/// Authenticated connection to the core's IPC endpoint carrying framed `Envelope`s. The
+/// connection is re-established transparently when it drops.
+pub struct IpcClient {
+    pipe_name: String,
+    config: IpcClientConfig,
+    stream: Option<Box<dyn IpcStream>>,
+    /// Whether the core advertised `SensorEventBatch` support in its last handshake.
+    sensor_event_batches: bool,
+    on_state_change: Option<StateCallback>,
+}
+
+impl fmt::Debug for IpcClient {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_struct("IpcClient")
+            .field("pipe_name", &self.pipe_name)
+            .field("config", &self.config)
+            .field("connected", &self.is_connected())
+            .finish_non_exhaustive()
+    }
+}
+
+impl IpcClient {
+    /// A client for `pipe_name` that connects on first use.
+    pub fn new(pipe_name: &str, config: IpcClientConfig) -> Self {
+        Self {
+            pipe_name: pipe_name.to_string(),
+            config,
+            stream: None,
+            sensor_event_batches: false,
+            on_state_change: None,
+        }
+    }
+
+    /// Call `callback` whenever the connection is established or lost.
+    pub fn on_state_change(mut self, callback: impl Fn(ConnectionState) + Send + Sync + 'static) -> Self {
+        self.on_state_change = Some(Arc::new(callback));
+        self
+    }
+
+    /// Connect to `pipe_name` with `IpcClientConfig::from_env`.
+    pub async fn connect(pipe_name: &str) -> Result<Self, String> {
+        Self::connect_with_config(pipe_name, IpcClientConfig::from_env()).await
+    }
+
+    pub async fn connect_with_config(pipe_name: &str, config: IpcClientConfig) -> Result<Self, String> {
+        let mut client = Self::new(pipe_name, config);
+        client.ensure_connected().await?;
+        Ok(client)
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    /// Connect and complete the handshake if not already connected, backing off with
+    /// jitter between failed attempts so a restarting core is not hammered with reconnects.
+    pub async fn ensure_connected(&mut self) -> Result<(), String> {
+        if self.stream.is_some() {
+            return Ok(());
+        }
+        let started = tokio::time::Instant::now();
+        let mut attempt = 0_u32;
+        loop {
+            let error = match connect_once(&self.pipe_name, &self.config).await {
+                Ok((stream, sensor_event_batches)) => {
+                    self.stream = Some(stream);
+                    self.sensor_event_batches = sensor_event_batches;
+                    self.notify(ConnectionState::Connected);
+                    return Ok(());
+                }
+                Err(err) => err,
+            };
+            let delay_ms = jittered_backoff_ms(
+                attempt,
+                self.config.initial_backoff_ms,
+                self.config.max_backoff_ms,
+                jitter_sample(),
+            );
+            if let Some(limit) = self.config.give_up_after_ms {
+                if started.elapsed().as_millis() as u64 + delay_ms > limit {
+                    return Err(format!(
+                        "gave up connecting to {} after {} attempts: {}",
+                        self.pipe_name,
+                        attempt + 1,
+                        error
+                    ));
+                }
+            }
+            debug!(pipe = %self.pipe_name, attempt, delay_ms, error = %error, "ipc connect failed; retrying");
+            tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+            attempt = attempt.saturating_add(1);
+        }
+    }
+
+    /// Send `envelope` and wait for the core's acknowledgement. A dropped connection is
+    /// re-established and the envelope resent, up to `max_send_attempts` writes; an
+    /// envelope the core rejects is not retried. An envelope without an `envelope_id` gets
+    /// one, so the core can tell a resend from a new envelope.
+    pub async fn send_envelope(&mut self, envelope: &Envelope) -> Result<(), String> {
+        let frame = if envelope.envelope_id.is_empty() {
+            Envelope {
+                envelope_id: new_envelope_id(),
+                ..envelope.clone()
+            }
+            .encode_to_vec()
+        } else {
+            envelope.encode_to_vec()
+        };
+        if frame.len() > self.config.max_payload_bytes {
+            return Err(format!(
+                "envelope of {} bytes exceeds {}",
+                frame.len(),
+                self.config.max_payload_bytes
+            ));
+        }
+        let attempts = self.config.max_send_attempts.max(1);
+        let mut last_error = String::new();
+        for attempt in 0..attempts {
+            self.ensure_connected().await?;
+            match self.exchange(&frame).await {
+                Ok(true) => return Ok(()),
+                Ok(false) => return Err("core rejected envelope".to_string()),
+                Err(err) => {
+                    debug!(pipe = %self.pipe_name, attempt, error = %err, "ipc connection lost while sending");
+                    self.disconnect();
+                    last_error = err;
+                }
+            }
+        }
+        Err(format!("envelope not acknowledged after {} attempts: {}", attempts, last_error))
+    }
+
+    /// Report `service_name` alive to the core.
+    pub async fn send_heartbeat(&mut self, service_name: &str) -> Result<(), String> {
+        let now = unix_time_ms();
+        let envelope = Envelope {
+            schema_version: IPC_SCHEMA_VERSION,
+            asset_id: self.config.asset_id.clone(),
+            agent_id: self.config.agent_id.clone(),
+            unix_time_ms: now,
+            envelope_id: String::new(),
+            payload: Some(Payload::HealthHeartbeat(HealthHeartbeat {
+                service_name: service_name.to_string(),
+                unix_time_ms: now,
+            })),
+        };
+        self.send_envelope(&envelope).await
+    }
+
+    /// Send sensor events in `SensorEventBatch` envelopes when the core supports them,
+    /// otherwise one envelope per event.
+    pub async fn send_sensor_events(&mut self, events: Vec<SensorEvent>) -> Result<(), String> {
+        self.ensure_connected().await?;
+        let payloads = if self.sensor_event_batches {
+            events
+                .chunks(MAX_SENSOR_BATCH_EVENTS)
+                .map(|chunk| Payload::SensorEventBatch(sensor_event_batch(chunk.to_vec())))
+                .collect::<Vec<Payload>>()
+        } else {
+            events.into_iter().map(Payload::SensorEvent).collect()
+        };
+        for payload in payloads {
+            let envelope = Envelope {
+                schema_version: IPC_SCHEMA_VERSION,
+                asset_id: self.config.asset_id.clone(),
+                agent_id: self.config.agent_id.clone(),
+                unix_time_ms: unix_time_ms(),
+                envelope_id: String::new(),
+                payload: Some(payload),
+            };
+            self.send_envelope(&envelope).await?;
+        }
+        Ok(())
+    }
+
+    async fn exchange(&mut self, frame: &[u8]) -> Result<bool, String> {
+        let io_timeout = Duration::from_millis(self.config.io_timeout_ms);
+        let stream = self.stream.as_mut().ok_or("not connected")?;
+        write_frame(stream, frame).await?;
+        let ack = read_frame(stream, MAX_CLIENT_FRAME_BYTES, io_timeout)
+            .await?
+            .ok_or("closed before acknowledgement")?;
+        let ack = EnvelopeAck::decode(ack.as_slice()).map_err(|err| format!("invalid acknowledgement: {err}"))?;
+        Ok(ack.accepted)
+    }
+
+    fn disconnect(&mut self) {
+        if self.stream.take().is_some() {
+            self.notify(ConnectionState::Disconnected);
+        }
+    }
+
+    fn notify(&self, state: ConnectionState) {
+        if let Some(callback) = &self.on_state_change {
+            callback(state);
+        }
+    }
+}
+
+/// Open the stream and complete the handshake, returning whether the core accepts batches.
+async fn connect_once(pipe_name: &str, config: &IpcClientConfig) -> Result<(Box<dyn IpcStream>, bool), String> {
+    let io_timeout = Duration::from_millis(config.io_timeout_ms);
+    let mut stream = open_stream(pipe_name).await?;
+    let challenge = read_frame(&mut stream, MAX_CLIENT_FRAME_BYTES, io_timeout)
+        .await?
+        .ok_or("closed before handshake")?;
+    let challenge =
+        AuthChallenge::decode(challenge.as_slice()).map_err(|err| format!("invalid handshake challenge: {err}"))?;
+    if challenge.schema_version != IPC_SCHEMA_VERSION {
+        return Err(format!(
+            "core speaks ipc schema {} but this client speaks {}",
+            challenge.schema_version, IPC_SCHEMA_VERSION
+        ));
+    }
+    let mac = config
+        .auth_key
+        .as_deref()
+        .map(|key| {
+            handshake_mac(key, &config.client_id, &challenge.nonce)
+                .finalize()
+                .into_bytes()
+                .to_vec()
+        })
+        .unwrap_or_default();
+    let sensor_event_batches = challenge.sensor_event_batches;
+    let response = AuthResponse {
+        client_id: config.client_id.clone(),
+        nonce: challenge.nonce,
+        mac,
+        wants_acks: true,
+    };
+    write_frame(&mut stream, &response.encode_to_vec()).await?;
+    Ok((stream, sensor_event_batches))
+}
+
+#[cfg(unix)]
+async fn open_stream(pipe_name: &str) -> Result<Box<dyn IpcStream>, String> {
+    let stream = tokio::net::UnixStream::connect(pipe_name)
+        .await
+        .map_err(|err| format!("connect {}: {}", pipe_name, err))?;
+    Ok(Box::new(stream))
+}
+
+#[cfg(windows)]
+async fn open_stream(pipe_name: &str) -> Result<Box<dyn IpcStream>, String> {
+    let stream = tokio::net::windows::named_pipe::ClientOptions::new()
+        .open(pipe_name)
+        .map_err(|err| format!("open {}: {}", pipe_name, err))?;
+    Ok(Box::new(stream))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{jittered_backoff_ms, reconnect_backoff_ms};
+
+    #[test]
+    fn backoff_doubles_up_to_cap() {
+        let delays = (0..6).map(|attempt| reconnect_backoff_ms(attempt, 100, 1_000)).collect::<Vec<u64>>();
+        assert_eq!(delays, vec![100, 200, 400, 800, 1_000, 1_000]);
+        assert_eq!(reconnect_backoff_ms(200, 100, 1_000), 1_000);
+        assert_eq!(reconnect_backoff_ms(u32::MAX, u64::MAX, u64::MAX), u64::MAX);
+
+        assert_eq!(jittered_backoff_ms(2, 100, 1_000, 0), 200);
+        assert_eq!(jittered_backoff_ms(2, 100, 1_000, 200), 400);
+        assert!((0..64).all(|sample| (200..=400).contains(&jittered_backoff_ms(2, 100, 1_000, sample * 7))));
+        assert_eq!(jittered_backoff_ms(u32::MAX, u64::MAX, u64::MAX, u64::MAX), u64::MAX);
+    }
+}
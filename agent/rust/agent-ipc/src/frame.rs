@@ -0,0 +1,51 @@
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Write one `u32` big-endian length-prefixed frame.
+pub async fn write_frame<S: AsyncWrite + Unpin>(stream: &mut S, frame: &[u8]) -> Result<(), String> {
+    let length = u32::try_from(frame.len()).map_err(|_| "frame too large".to_string())?;
+    stream.write_all(&length.to_be_bytes()).await.map_err(|err| err.to_string())?;
+    stream.write_all(frame).await.map_err(|err| err.to_string())?;
+    stream.flush().await.map_err(|err| err.to_string())
+}
+
+/// Read one `u32` big-endian length-prefixed frame; `None` on a clean disconnect.
+pub async fn read_frame<S: AsyncRead + Unpin>(stream: &mut S, max_frame: usize, idle: Duration) -> Result<Option<Vec<u8>>, String> {
+    let mut header = [0_u8; 4];
+    match tokio::time::timeout(idle, stream.read_exact(&mut header)).await {
+        Err(_) => return Err("idle timeout".to_string()),
+        Ok(Err(err)) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Ok(Err(err)) => return Err(err.to_string()),
+        Ok(Ok(_)) => {}
+    }
+    let length = u32::from_be_bytes(header) as usize;
+    if length > max_frame {
+        return Err(format!("frame of {} bytes exceeds {}", length, max_frame));
+    }
+    let mut frame = vec![0_u8; length];
+    tokio::time::timeout(idle, stream.read_exact(&mut frame))
+        .await
+        .map_err(|_| "idle timeout".to_string())?
+        .map_err(|err| err.to_string())?;
+    Ok(Some(frame))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{read_frame, write_frame};
+
+    #[tokio::test]
+    async fn round_trips_frames_and_bounds_their_length() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        write_frame(&mut client, b"hello").await.expect("write");
+        write_frame(&mut client, &[0_u8; 64]).await.expect("write");
+        drop(client);
+
+        let idle = Duration::from_secs(1);
+        assert_eq!(read_frame(&mut server, 16, idle).await, Ok(Some(b"hello".to_vec())));
+        assert_eq!(read_frame(&mut server, 16, idle).await, Err("frame of 64 bytes exceeds 16".to_string()));
+    }
+}
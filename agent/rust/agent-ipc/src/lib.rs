@@ -0,0 +1,42 @@
+//! IPC wire format shared by agent-core's server and the services that connect to it:
+//! the generated protocol types, framing, the handshake MAC and sensor event batches.
+
+pub mod client;
+pub mod frame;
+pub mod proto;
+
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+use crate::proto::agent_ipc::{SensorEvent, SensorEventBatch};
+
+pub const IPC_SCHEMA_VERSION: u32 = 1;
+
+/// Most sensor events a single `SensorEventBatch` envelope may carry.
+pub const MAX_SENSOR_BATCH_EVENTS: usize = 1024;
+
+/// MAC a client sends in `AuthResponse.mac`: HMAC-SHA256(key, client_id || 0x00 || nonce).
+pub fn handshake_mac(key: &[u8], client_id: &str, nonce: &[u8]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(client_id.as_bytes());
+    mac.update(&[0]);
+    mac.update(nonce);
+    mac
+}
+
+/// Bytes a batch checksum covers: the batch encoded with `checksum_sha256` left empty.
+pub fn sensor_batch_checksum_input(events: &[SensorEvent]) -> Vec<u8> {
+    prost::Message::encode_to_vec(&SensorEventBatch {
+        events: events.to_vec(),
+        checksum_sha256: String::new(),
+    })
+}
+
+/// Build a batch of `events` carrying its checksum.
+pub fn sensor_event_batch(events: Vec<SensorEvent>) -> SensorEventBatch {
+    let checksum_sha256 = Sha256::digest(sensor_batch_checksum_input(&events))
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
+    SensorEventBatch { events, checksum_sha256 }
+}
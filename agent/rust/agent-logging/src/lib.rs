@@ -0,0 +1,272 @@
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use tracing_subscriber::fmt::writer::{BoxMakeWriter, MakeWriterExt};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer, Registry};
+
+const DEFAULT_LEVEL: &str = "info";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl LogFormat {
+    fn parse(value: &str) -> Self {
+        if value.trim().eq_ignore_ascii_case("json") {
+            Self::Json
+        } else {
+            Self::Text
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LoggingConfig {
+    pub format: LogFormat,
+    pub level: String,
+    pub filter: Option<String>,
+    pub log_dir: Option<PathBuf>,
+    pub max_file_bytes: u64,
+    pub max_files: usize,
+}
+
+impl LoggingConfig {
+    pub fn from_env() -> Self {
+        let format = env::var("AGENT_LOG_FORMAT")
+            .ok()
+            .map(|value| LogFormat::parse(&value))
+            .unwrap_or(LogFormat::Text);
+        let level = env::var("AGENT_LOG_LEVEL")
+            .ok()
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+            .unwrap_or_else(|| DEFAULT_LEVEL.to_string());
+        let filter = env::var("AGENT_LOG_FILTER")
+            .ok()
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty());
+        let log_dir = env::var("AGENT_LOG_DIR")
+            .ok()
+            .filter(|value| !value.trim().is_empty())
+            .map(PathBuf::from);
+        let max_file_bytes = env::var("AGENT_LOG_MAX_BYTES")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .filter(|value| *value > 0)
+            .unwrap_or(10 * 1024 * 1024);
+        let max_files = env::var("AGENT_LOG_MAX_FILES")
+            .ok()
+            .and_then(|value| value.parse::<usize>().ok())
+            .unwrap_or(5);
+
+        Self {
+            format,
+            level,
+            filter,
+            log_dir,
+            max_file_bytes,
+            max_files,
+        }
+    }
+}
+
+/// Build the level filter: `AGENT_LOG_FILTER` directives win over the plain `AGENT_LOG_LEVEL`.
+pub fn build_filter(config: &LoggingConfig) -> Result<EnvFilter, String> {
+    let directives = config.filter.as_deref().unwrap_or(&config.level);
+    EnvFilter::try_new(directives).map_err(|err| format!("invalid log filter {:?}: {}", directives, err))
+}
+
+/// Layer type accepted by `init_with_layers`, e.g. agent-core's self-telemetry capture.
+pub type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync + 'static>;
+
+/// Install the global subscriber for `service` (used as the log file name). Any
+/// configuration problem is reported on stderr and logging falls back to plain text at
+/// `info` on stdout; this never panics.
+pub fn init(service: &str) {
+    init_with_layers(service, Vec::new());
+}
+
+/// Same as `init`, adding `extra` layers next to the output layer. The log filter applies
+/// to the output layer only, so extra layers see every event and filter for themselves.
+pub fn init_with_layers(service: &str, extra: Vec<BoxedLayer>) {
+    let config = LoggingConfig::from_env();
+    let output = match output_layer(service, &config) {
+        Ok(layer) => layer,
+        Err(err) => {
+            eprintln!("{}: logging configuration rejected ({}); using defaults", service, err);
+            tracing_subscriber::fmt::layer()
+                .with_filter(EnvFilter::new(DEFAULT_LEVEL))
+                .boxed()
+        }
+    };
+    let mut layers = vec![output];
+    layers.extend(extra);
+    let _ = tracing_subscriber::registry().with(layers).try_init();
+}
+
+fn output_layer(service: &str, config: &LoggingConfig) -> Result<BoxedLayer, String> {
+    let filter = build_filter(config)?;
+    let writer = match &config.log_dir {
+        Some(dir) => {
+            let file = RotatingFile::open(dir, &format!("{}.log", service), config.max_file_bytes, config.max_files)
+                .map_err(|err| format!("unable to open log file in {}: {}", dir.display(), err))?;
+            let file = Arc::new(Mutex::new(file));
+            BoxMakeWriter::new(io::stdout.and(move || RotatingFileWriter(Arc::clone(&file))))
+        }
+        None => BoxMakeWriter::new(io::stdout),
+    };
+
+    let layer = tracing_subscriber::fmt::layer().with_writer(writer);
+    Ok(match config.format {
+        LogFormat::Text => layer.with_filter(filter).boxed(),
+        LogFormat::Json => layer.json().with_filter(filter).boxed(),
+    })
+}
+
+/// Log file rotated by size: when the next write would take the active file past
+/// `max_bytes`, `<name>` becomes `<name>.1`, older files shift up, and files beyond
+/// `max_files` are deleted.
+#[derive(Debug)]
+pub struct RotatingFile {
+    path: PathBuf,
+    max_bytes: u64,
+    max_files: usize,
+    file: File,
+    written: u64,
+}
+
+impl RotatingFile {
+    pub fn open(dir: &Path, file_name: &str, max_bytes: u64, max_files: usize) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(file_name);
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let written = file.metadata()?.len();
+        Ok(Self {
+            path,
+            max_bytes,
+            max_files,
+            file,
+            written,
+        })
+    }
+
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut name = self.path.as_os_str().to_os_string();
+        name.push(format!(".{}", index));
+        PathBuf::from(name)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.max_files == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            let _ = fs::remove_file(self.rotated_path(self.max_files));
+            for index in (1..self.max_files).rev() {
+                let from = self.rotated_path(index);
+                if from.exists() {
+                    fs::rename(&from, self.rotated_path(index + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.rotated_path(1))?;
+        }
+        self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.written = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.written > 0 && self.written.saturating_add(buf.len() as u64) > self.max_bytes {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.written = self.written.saturating_add(written as u64);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+struct RotatingFileWriter(Arc<Mutex<RotatingFile>>);
+
+impl Write for RotatingFileWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    use super::{build_filter, LogFormat, LoggingConfig, RotatingFile};
+
+    fn build_config(level: &str, filter: Option<&str>) -> LoggingConfig {
+        LoggingConfig {
+            format: LogFormat::Text,
+            level: level.to_string(),
+            filter: filter.map(str::to_string),
+            log_dir: None,
+            max_file_bytes: 1024,
+            max_files: 2,
+        }
+    }
+
+    fn temp_dir(label: &str) -> PathBuf {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|duration| duration.as_nanos())
+            .unwrap_or(0);
+        std::env::temp_dir().join(format!("agent-logging-{}-{}-{}", label, std::process::id(), nanos))
+    }
+
+    #[test]
+    fn parses_filter_directives_and_level() {
+        let filter = build_filter(&build_config("info", Some("agent_core::uplink=debug,warn"))).expect("directives");
+        let rendered = filter.to_string();
+        assert!(rendered.contains("agent_core::uplink=debug"));
+        assert!(rendered.contains("warn"));
+
+        assert_eq!(build_filter(&build_config("debug", None)).expect("level").to_string(), "debug");
+        assert!(build_filter(&build_config("info", Some("agent_core=loud"))).is_err());
+        assert_eq!(LogFormat::parse("JSON"), LogFormat::Json);
+        assert_eq!(LogFormat::parse("pretty"), LogFormat::Text);
+    }
+
+    #[test]
+    fn rotates_when_next_write_exceeds_max_size() {
+        let dir = temp_dir("rotate");
+        let mut file = RotatingFile::open(&dir, "agent-core.log", 16, 2).expect("open log");
+
+        file.write_all(b"0123456789\n").expect("first write");
+        assert!(!dir.join("agent-core.log.1").exists());
+        file.write_all(b"abcdefghij\n").expect("second write");
+        file.write_all(b"ABCDEFGHIJ\n").expect("third write");
+        file.write_all(b"klmnopqrst\n").expect("fourth write");
+        file.flush().expect("flush");
+
+        let read = |name: &str| std::fs::read_to_string(dir.join(name)).expect("log file");
+        assert_eq!(read("agent-core.log"), "klmnopqrst\n");
+        assert_eq!(read("agent-core.log.1"), "ABCDEFGHIJ\n");
+        assert_eq!(read("agent-core.log.2"), "abcdefghij\n");
+        assert!(!dir.join("agent-core.log.3").exists());
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
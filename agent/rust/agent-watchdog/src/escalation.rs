@@ -0,0 +1,203 @@
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+/// What the watchdog does when agent-core keeps failing its health checks.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum EscalationAction {
+    RestartService,
+    /// Run a script from `WATCHDOG_SCRIPT_ALLOWLIST`.
+    RunScript { path: PathBuf },
+    /// Hand over to an operator (logged with the runbook link).
+    Escalate,
+}
+
+/// One rung of the ladder. It becomes due once `after_failures` consecutive checks have
+/// failed, runs at most `max_attempts` times (unbounded when absent) and waits
+/// `cooldown_secs` between its own runs.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct EscalationStep {
+    #[serde(flatten)]
+    pub action: EscalationAction,
+    pub after_failures: u32,
+    #[serde(default)]
+    pub cooldown_secs: u64,
+    #[serde(default)]
+    pub max_attempts: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EscalationLadder {
+    pub steps: Vec<EscalationStep>,
+}
+
+/// How far the ladder has progressed: runs and last run time per step, by index.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LadderProgress {
+    pub attempts: Vec<u32>,
+    pub last_run_unix_ms: Vec<Option<u64>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LadderDecision {
+    /// Not enough consecutive failures for the next step yet.
+    Wait,
+    /// The next step is due but still inside its cool-down.
+    CoolingDown { step: usize, remaining_ms: u64 },
+    Run { step: usize },
+    /// Every step has used up its attempts.
+    Exhausted,
+}
+
+impl EscalationLadder {
+    /// Restart once `grace_misses` is exceeded, up to `max_restart_attempts` times, then
+    /// escalate on every further failure.
+    pub fn default_ladder(grace_misses: u32, max_restart_attempts: u32) -> Self {
+        let after_failures = grace_misses.saturating_add(1);
+        Self {
+            steps: vec![
+                EscalationStep {
+                    action: EscalationAction::RestartService,
+                    after_failures,
+                    cooldown_secs: 0,
+                    max_attempts: Some(max_restart_attempts),
+                },
+                EscalationStep {
+                    action: EscalationAction::Escalate,
+                    after_failures,
+                    cooldown_secs: 0,
+                    max_attempts: None,
+                },
+            ],
+        }
+    }
+
+    /// Parse `WATCHDOG_ESCALATION_JSON`: a non-empty array of steps. Scripts must appear in
+    /// `script_allowlist`.
+    pub fn parse(raw: &str, script_allowlist: &[PathBuf]) -> Result<Self, String> {
+        let steps = serde_json::from_str::<Vec<EscalationStep>>(raw).map_err(|err| format!("invalid ladder: {}", err))?;
+        if steps.is_empty() {
+            return Err("ladder has no steps".to_string());
+        }
+        for step in &steps {
+            if let EscalationAction::RunScript { path } = &step.action {
+                if !is_allowlisted(path, script_allowlist) {
+                    return Err(format!("script {} is not in WATCHDOG_SCRIPT_ALLOWLIST", path.display()));
+                }
+            }
+        }
+        Ok(Self { steps })
+    }
+
+    /// Decide the next action. Steps are taken strictly in order: a step is only considered
+    /// once every step before it has used up its attempts.
+    pub fn decide(&self, progress: &LadderProgress, consecutive_failures: u32, now_unix_ms: u64) -> LadderDecision {
+        for (index, step) in self.steps.iter().enumerate() {
+            let attempts = progress.attempts.get(index).copied().unwrap_or(0);
+            if step.max_attempts.is_some_and(|max| attempts >= max) {
+                continue;
+            }
+            if consecutive_failures < step.after_failures {
+                return LadderDecision::Wait;
+            }
+            if let Some(last_run) = progress.last_run_unix_ms.get(index).copied().flatten() {
+                let cooldown_ms = step.cooldown_secs.saturating_mul(1000);
+                let elapsed = now_unix_ms.saturating_sub(last_run);
+                if elapsed < cooldown_ms {
+                    return LadderDecision::CoolingDown {
+                        step: index,
+                        remaining_ms: cooldown_ms - elapsed,
+                    };
+                }
+            }
+            return LadderDecision::Run { step: index };
+        }
+        LadderDecision::Exhausted
+    }
+}
+
+impl LadderProgress {
+    pub fn record(&mut self, step: usize, now_unix_ms: u64) {
+        if self.attempts.len() <= step {
+            self.fit(step + 1);
+        }
+        self.attempts[step] = self.attempts[step].saturating_add(1);
+        self.last_run_unix_ms[step] = Some(now_unix_ms);
+    }
+
+    /// Pad or trim to `steps` entries, for progress saved against a different ladder.
+    pub fn fit(&mut self, steps: usize) {
+        self.attempts.resize(steps, 0);
+        self.last_run_unix_ms.resize(steps, None);
+    }
+
+    pub fn latest_run_unix_ms(&self) -> Option<u64> {
+        self.last_run_unix_ms.iter().flatten().copied().max()
+    }
+}
+
+fn is_allowlisted(path: &Path, allowlist: &[PathBuf]) -> bool {
+    path.is_absolute() && allowlist.iter().any(|allowed| allowed == path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{EscalationAction, EscalationLadder, LadderDecision, LadderProgress};
+
+    #[test]
+    fn default_ladder_restarts_then_escalates() {
+        let ladder = EscalationLadder::default_ladder(2, 2);
+        let mut progress = LadderProgress::default();
+
+        assert_eq!(ladder.decide(&progress, 2, 0), LadderDecision::Wait);
+        assert_eq!(ladder.decide(&progress, 3, 0), LadderDecision::Run { step: 0 });
+        progress.record(0, 0);
+        assert_eq!(ladder.decide(&progress, 4, 1), LadderDecision::Run { step: 0 });
+        progress.record(0, 1);
+        assert_eq!(ladder.decide(&progress, 5, 2), LadderDecision::Run { step: 1 });
+        progress.record(1, 2);
+        assert_eq!(ladder.decide(&progress, 6, 3), LadderDecision::Run { step: 1 });
+    }
+
+    #[test]
+    fn honours_thresholds_and_cooldowns_in_order() {
+        let raw = r#"[
+            {"action":"restart_service","after_failures":2,"cooldown_secs":60,"max_attempts":2},
+            {"action":"run_script","path":"/opt/tamsil/reboot.sh","after_failures":5,"max_attempts":1},
+            {"action":"escalate","after_failures":6,"max_attempts":1}
+        ]"#;
+        let ladder = EscalationLadder::parse(raw, &[PathBuf::from("/opt/tamsil/reboot.sh")]).expect("ladder parses");
+        let mut progress = LadderProgress::default();
+
+        assert_eq!(ladder.decide(&progress, 2, 0), LadderDecision::Run { step: 0 });
+        progress.record(0, 0);
+        assert_eq!(
+            ladder.decide(&progress, 3, 30_000),
+            LadderDecision::CoolingDown { step: 0, remaining_ms: 30_000 }
+        );
+        assert_eq!(ladder.decide(&progress, 4, 60_000), LadderDecision::Run { step: 0 });
+        progress.record(0, 60_000);
+        // Restarts are used up, but the script waits for its own threshold.
+        assert_eq!(ladder.decide(&progress, 4, 61_000), LadderDecision::Wait);
+        assert_eq!(ladder.decide(&progress, 5, 62_000), LadderDecision::Run { step: 1 });
+        assert!(matches!(
+            ladder.steps[1].action,
+            EscalationAction::RunScript { ref path } if path == &PathBuf::from("/opt/tamsil/reboot.sh")
+        ));
+        progress.record(1, 62_000);
+        assert_eq!(ladder.decide(&progress, 6, 63_000), LadderDecision::Run { step: 2 });
+        progress.record(2, 63_000);
+        assert_eq!(ladder.decide(&progress, 7, 64_000), LadderDecision::Exhausted);
+    }
+
+    #[test]
+    fn rejects_scripts_outside_allowlist() {
+        let raw = r#"[{"action":"run_script","path":"/tmp/anything.sh","after_failures":1}]"#;
+        assert!(EscalationLadder::parse(raw, &[PathBuf::from("/opt/tamsil/reboot.sh")]).is_err());
+        assert!(EscalationLadder::parse("[]", &[]).is_err());
+        assert!(EscalationLadder::parse(r#"[{"action":"reboot","after_failures":1}]"#, &[]).is_err());
+    }
+}
@@ -0,0 +1,208 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+/// Expected agent-core executable, checked before every restart and once at startup.
+#[derive(Debug, Clone)]
+pub struct IntegrityConfig {
+    pub binary_path: Option<PathBuf>,
+    pub expected_sha256: Option<String>,
+    /// agent-core's uplink queue, where tamper evidence is dropped for delivery.
+    pub queue_dir: PathBuf,
+    pub asset_id: String,
+    pub tenant_id: String,
+}
+
+impl IntegrityConfig {
+    pub fn from_env() -> Self {
+        let binary_path = env::var("WATCHDOG_AGENT_BINARY_PATH")
+            .ok()
+            .filter(|value| !value.trim().is_empty())
+            .map(PathBuf::from);
+        let expected_sha256 = env::var("WATCHDOG_AGENT_BINARY_SHA256")
+            .ok()
+            .map(|value| value.trim().to_ascii_lowercase())
+            .filter(|value| !value.is_empty());
+        // Same resolution as agent-core's state paths.
+        let queue_dir = env::var("RUST_UPLINK_QUEUE_DIR")
+            .ok()
+            .filter(|value| !value.trim().is_empty())
+            .map(PathBuf::from)
+            .unwrap_or_else(|| {
+                env::var("AGENT_STATE_DIR")
+                    .ok()
+                    .filter(|value| !value.trim().is_empty())
+                    .map(PathBuf::from)
+                    .unwrap_or_else(|| PathBuf::from("."))
+                    .join("uplink_queue")
+            });
+        Self {
+            binary_path,
+            expected_sha256,
+            queue_dir,
+            asset_id: env::var("AGENT_ASSET_ID").unwrap_or_default(),
+            tenant_id: env::var("AGENT_TENANT_ID").unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IntegrityStatus {
+    /// No binary path or expected hash configured; restarts go ahead unchecked.
+    NotConfigured,
+    Verified,
+    Mismatch { actual: String },
+    Unreadable { reason: String },
+}
+
+impl IntegrityStatus {
+    /// Whether agent-core may be relaunched.
+    pub fn allows_restart(&self) -> bool {
+        matches!(self, Self::NotConfigured | Self::Verified)
+    }
+}
+
+pub fn check_binary(config: &IntegrityConfig) -> IntegrityStatus {
+    let (Some(path), Some(expected)) = (&config.binary_path, &config.expected_sha256) else {
+        return IntegrityStatus::NotConfigured;
+    };
+    match hash_file(path) {
+        Ok(actual) if actual.eq_ignore_ascii_case(expected) => IntegrityStatus::Verified,
+        Ok(actual) => IntegrityStatus::Mismatch { actual },
+        Err(err) => IntegrityStatus::Unreadable {
+            reason: format!("{}: {}", path.display(), err),
+        },
+    }
+}
+
+/// Queue a high-priority evidence item describing the mismatched binary for agent-core's
+/// uplink worker to deliver.
+pub fn write_tamper_record(config: &IntegrityConfig, actual_sha256: &str, now_ms: u64) -> io::Result<PathBuf> {
+    let binary_path = config.binary_path.as_deref().unwrap_or_else(|| Path::new(""));
+    let evidence_id = format!("watchdog-tamper-{}-{}", &actual_sha256[..actual_sha256.len().min(16)], now_ms);
+    let record = serde_json::json!({
+        "kind": "evidence",
+        "evidence_id": evidence_id,
+        "tenant_id": config.tenant_id,
+        "asset_id": config.asset_id,
+        "source": "agent-watchdog",
+        "type": "binary_tamper",
+        "related_id": config.expected_sha256.as_deref().unwrap_or_default(),
+        "hash": actual_sha256,
+        "storage_uri": format!("file://{}", binary_path.display()),
+        "captured_at": crate::state::format_rfc3339_ms(now_ms),
+        "idempotency_key": evidence_id,
+    });
+    fs::create_dir_all(&config.queue_dir)?;
+    let target = config.queue_dir.join(format!("hi-{}.json", evidence_id));
+    let staging = config.queue_dir.join(format!("{}.tmp", evidence_id));
+    fs::write(&staging, record.to_string())?;
+    fs::rename(&staging, &target)?;
+    Ok(target)
+}
+
+/// Hash in fixed-size chunks so a large executable is never held in memory.
+fn hash_file(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 8192];
+
+    loop {
+        let read_count = file.read(&mut buffer)?;
+        if read_count == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read_count]);
+    }
+
+    Ok(hex_encode(hasher.finalize()))
+}
+
+fn hex_encode(bytes: impl AsRef<[u8]>) -> String {
+    bytes
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<Vec<String>>()
+        .join("")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(label: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!(
+            "watchdog-integrity-{}-{}-{}",
+            label,
+            std::process::id(),
+            crate::state::unix_time_ms()
+        ));
+        fs::create_dir_all(&dir).expect("create temp dir");
+        dir
+    }
+
+    fn config(dir: &Path, expected: &[u8]) -> IntegrityConfig {
+        IntegrityConfig {
+            binary_path: Some(dir.join("agent-core")),
+            expected_sha256: Some(hex_encode(Sha256::digest(expected))),
+            queue_dir: dir.join("uplink_queue"),
+            asset_id: "asset-1".to_string(),
+            tenant_id: "tenant-1".to_string(),
+        }
+    }
+
+    #[test]
+    fn verifies_matching_binary() {
+        let dir = temp_dir("match");
+        fs::write(dir.join("agent-core"), b"agent-core build").expect("write binary");
+
+        let status = check_binary(&config(&dir, b"agent-core build"));
+        assert_eq!(status, IntegrityStatus::Verified);
+        assert!(status.allows_restart());
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn flags_tampered_binary_and_queues_evidence() {
+        let dir = temp_dir("mismatch");
+        fs::write(dir.join("agent-core"), b"patched build").expect("write binary");
+        let config = config(&dir, b"agent-core build");
+
+        let status = check_binary(&config);
+        let IntegrityStatus::Mismatch { actual } = &status else {
+            panic!("expected mismatch, got {:?}", status);
+        };
+        assert!(!status.allows_restart());
+        assert_eq!(actual, &hex_encode(Sha256::digest(b"patched build")));
+
+        let record_path = write_tamper_record(&config, actual, 1_700_000_000_000).expect("write record");
+        assert!(record_path.file_name().and_then(|name| name.to_str()).is_some_and(|name| name.starts_with("hi-")));
+        let record: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&record_path).expect("read record")).expect("record json");
+        assert_eq!(record["kind"], "evidence");
+        assert_eq!(record["type"], "binary_tamper");
+        assert_eq!(record["hash"], actual.as_str());
+        assert_eq!(record["captured_at"], "2023-11-14T22:13:20.000Z");
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn refuses_restart_when_binary_is_missing() {
+        let dir = temp_dir("missing");
+
+        let status = check_binary(&config(&dir, b"agent-core build"));
+        assert!(matches!(status, IntegrityStatus::Unreadable { .. }));
+        assert!(!status.allows_restart());
+
+        let unconfigured = IntegrityConfig {
+            expected_sha256: None,
+            ..config(&dir, b"agent-core build")
+        };
+        assert_eq!(check_binary(&unconfigured), IntegrityStatus::NotConfigured);
+        let _ = fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,118 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::HealthStatus;
+
+/// Where agent-core writes its liveness file and how old it may get.
+#[derive(Debug, Clone)]
+pub struct LivenessConfig {
+    pub path: PathBuf,
+    pub max_age_ms: u64,
+}
+
+impl LivenessConfig {
+    pub fn from_env() -> Self {
+        // Same resolution as agent-core's state paths.
+        let path = env::var("AGENT_HEARTBEAT_FILE")
+            .ok()
+            .filter(|value| !value.trim().is_empty())
+            .map(PathBuf::from)
+            .unwrap_or_else(|| {
+                env::var("AGENT_STATE_DIR")
+                    .ok()
+                    .filter(|value| !value.trim().is_empty())
+                    .map(PathBuf::from)
+                    .unwrap_or_else(|| PathBuf::from("."))
+                    .join("heartbeat.json")
+            });
+        let max_age_secs = env::var("WATCHDOG_HEARTBEAT_MAX_AGE_SECS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .filter(|value| *value > 0)
+            .unwrap_or(90);
+        Self {
+            path,
+            max_age_ms: max_age_secs.saturating_mul(1000),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct LivenessRecord {
+    unix_time_ms: u64,
+    pipeline_ready: bool,
+}
+
+/// Missing, unreadable or stale files mean agent-core is unreachable; a fresh file
+/// reporting a failed pipeline means it is degraded.
+pub fn read_liveness(path: &Path, max_age_ms: u64, now_ms: u64) -> HealthStatus {
+    let raw = match fs::read_to_string(path) {
+        Ok(raw) => raw,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            return HealthStatus::Unreachable {
+                reason: format!("no heartbeat file at {}", path.display()),
+            }
+        }
+        Err(err) => {
+            return HealthStatus::Unreachable {
+                reason: format!("heartbeat file unreadable: {}", err),
+            }
+        }
+    };
+    let record = match serde_json::from_str::<LivenessRecord>(&raw) {
+        Ok(record) => record,
+        Err(err) => {
+            return HealthStatus::Unreachable {
+                reason: format!("heartbeat file malformed: {}", err),
+            }
+        }
+    };
+    let age_ms = now_ms.saturating_sub(record.unix_time_ms);
+    if age_ms > max_age_ms {
+        return HealthStatus::Unreachable {
+            reason: format!("agent-core heartbeat is {} ms old", age_ms),
+        };
+    }
+    if !record.pipeline_ready {
+        return HealthStatus::Degraded {
+            reason: "agent-core reports a failed pipeline component".to_string(),
+        };
+    }
+    HealthStatus::Healthy
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::read_liveness;
+    use crate::HealthStatus;
+
+    #[test]
+    fn classifies_fresh_stale_and_missing_heartbeats() {
+        let dir = std::env::temp_dir().join(format!(
+            "watchdog-liveness-{}-{}",
+            std::process::id(),
+            crate::state::unix_time_ms()
+        ));
+        fs::create_dir_all(&dir).expect("create temp dir");
+        let path = dir.join("heartbeat.json");
+
+        assert!(matches!(read_liveness(&path, 90_000, 100_000), HealthStatus::Unreachable { .. }));
+
+        fs::write(&path, r#"{"unix_time_ms":100000,"pipeline_ready":true}"#).expect("write heartbeat");
+        assert!(matches!(read_liveness(&path, 90_000, 150_000), HealthStatus::Healthy));
+        assert!(matches!(
+            read_liveness(&path, 90_000, 200_000),
+            HealthStatus::Unreachable { reason } if reason.contains("100000 ms old")
+        ));
+
+        fs::write(&path, r#"{"unix_time_ms":100000,"pipeline_ready":false}"#).expect("write heartbeat");
+        assert!(matches!(read_liveness(&path, 90_000, 100_500), HealthStatus::Degraded { .. }));
+        let _ = fs::remove_dir_all(dir);
+    }
+}
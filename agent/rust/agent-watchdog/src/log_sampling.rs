@@ -0,0 +1,76 @@
+use tracing::info;
+
+/// Rate limit for the "heartbeat healthy" line, which would otherwise be logged every
+/// interval. A healthy tick right after any other state is always logged; repeats are
+/// logged at most once per `interval_ms`, with a count of the ticks left out since.
+#[derive(Debug, Clone)]
+pub struct HealthyLogSampler {
+    interval_ms: u64,
+    last_logged_unix_ms: Option<u64>,
+    suppressed: u64,
+}
+
+impl HealthyLogSampler {
+    pub fn new(interval_ms: u64) -> Self {
+        Self {
+            interval_ms,
+            last_logged_unix_ms: None,
+            suppressed: 0,
+        }
+    }
+
+    /// Record a healthy tick; `recovered` means the previous tick was not healthy. Returns
+    /// whether the tick was logged.
+    pub fn healthy(&mut self, recovered: bool, now_ms: u64) -> bool {
+        let due = self
+            .last_logged_unix_ms
+            .is_none_or(|last| now_ms.saturating_sub(last) >= self.interval_ms);
+        if !recovered && !due {
+            self.suppressed = self.suppressed.saturating_add(1);
+            return false;
+        }
+        info!(recovered, suppressed = self.suppressed, "watchdog heartbeat healthy");
+        self.last_logged_unix_ms = Some(now_ms);
+        self.suppressed = 0;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use tracing::{Event, Subscriber};
+    use tracing_subscriber::layer::{Context, SubscriberExt};
+    use tracing_subscriber::Layer;
+
+    use super::HealthyLogSampler;
+
+    struct CountEvents(Arc<AtomicUsize>);
+
+    impl<S: Subscriber> Layer<S> for CountEvents {
+        fn on_event(&self, _event: &Event<'_>, _context: Context<'_, S>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn logs_repeated_healthy_ticks_once_per_interval() {
+        let emitted = Arc::new(AtomicUsize::new(0));
+        let subscriber = tracing_subscriber::registry().with(CountEvents(emitted.clone()));
+        tracing::subscriber::with_default(subscriber, || {
+            let mut sampler = HealthyLogSampler::new(300_000);
+            // 100 healthy ticks, 15 s apart: logged at 0, 300, 600, 900 and 1200 s.
+            for tick in 0..100_u64 {
+                sampler.healthy(false, tick * 15_000);
+            }
+            assert_eq!(emitted.load(Ordering::SeqCst), 5);
+
+            // Coming back from a failure logs straight away, inside the interval.
+            assert!(sampler.healthy(true, 1_500_000));
+            assert!(!sampler.healthy(false, 1_515_000));
+        });
+        assert_eq!(emitted.load(Ordering::SeqCst), 6);
+    }
+}
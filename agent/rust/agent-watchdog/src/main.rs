@@ -1,15 +1,42 @@
+mod escalation;
+mod integrity;
+mod liveness;
+mod log_sampling;
+mod restart;
+mod state;
+
 use std::env;
+use std::path::PathBuf;
+use std::sync::Arc;
 use std::time::Duration;
 
+use agent_ipc::client::{IpcClient, IpcClientConfig};
 use tokio::signal;
 use tracing::{info, warn};
 
+use crate::escalation::{EscalationAction, EscalationLadder, LadderDecision, LadderProgress};
+use crate::integrity::{check_binary, write_tamper_record, IntegrityConfig, IntegrityStatus};
+use crate::liveness::{read_liveness, LivenessConfig};
+use crate::log_sampling::HealthyLogSampler;
+use crate::restart::{strategy_from_env, DryRunRestart, RestartOutcome, RestartStrategy};
+
 #[derive(Debug, Clone)]
 struct WatchdogConfig {
     interval_secs: u64,
     grace_misses: u32,
     max_restart_attempts: u32,
     runbook_url: Option<String>,
+    state_path: PathBuf,
+    state_max_age_secs: u64,
+    attempt_ttl_secs: u64,
+    ladder: EscalationLadder,
+    integrity: IntegrityConfig,
+    restart: Arc<dyn RestartStrategy>,
+    liveness: LivenessConfig,
+    /// Minimum gap between "heartbeat healthy" lines while agent-core stays healthy.
+    healthy_log_interval_secs: u64,
+    /// agent-core's IPC endpoint; when set, the watchdog reports itself alive there.
+    ipc_pipe: Option<String>,
 }
 
 impl WatchdogConfig {
@@ -29,12 +56,65 @@ impl WatchdogConfig {
         let runbook_url = env::var("WATCHDOG_RUNBOOK_URL")
             .ok()
             .filter(|value| !value.trim().is_empty());
+        let state_path = env::var("WATCHDOG_STATE_PATH")
+            .ok()
+            .filter(|value| !value.trim().is_empty())
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from("agent-watchdog.state"));
+        let state_max_age_secs = env::var("WATCHDOG_STATE_MAX_AGE_SECS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .unwrap_or(3600);
+        let attempt_ttl_secs = env::var("WATCHDOG_ATTEMPT_TTL_SECS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .unwrap_or(1800);
+        let script_allowlist = env::var("WATCHDOG_SCRIPT_ALLOWLIST")
+            .ok()
+            .map(|value| {
+                value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|entry| !entry.is_empty())
+                    .map(PathBuf::from)
+                    .collect::<Vec<PathBuf>>()
+            })
+            .unwrap_or_default();
+        let default_ladder = EscalationLadder::default_ladder(grace_misses, max_restart_attempts);
+        let ladder = match env::var("WATCHDOG_ESCALATION_JSON").ok().filter(|value| !value.trim().is_empty()) {
+            Some(raw) => EscalationLadder::parse(&raw, &script_allowlist).unwrap_or_else(|err| {
+                warn!(error = %err, "ignoring WATCHDOG_ESCALATION_JSON; using default escalation ladder");
+                default_ladder
+            }),
+            None => default_ladder,
+        };
+        let restart = strategy_from_env().unwrap_or_else(|err| {
+            warn!(error = %err, "ignoring WATCHDOG_RESTART_STRATEGY; restarts will only be logged");
+            Arc::new(DryRunRestart)
+        });
+        let healthy_log_interval_secs = env::var("WATCHDOG_HEALTHY_LOG_INTERVAL_SECS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .unwrap_or(300);
+        let ipc_pipe = env::var("AGENT_IPC_PIPE")
+            .ok()
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty());
 
         Self {
             interval_secs,
             grace_misses,
             max_restart_attempts,
             runbook_url,
+            state_path,
+            state_max_age_secs,
+            attempt_ttl_secs,
+            ladder,
+            integrity: IntegrityConfig::from_env(),
+            restart,
+            liveness: LivenessConfig::from_env(),
+            healthy_log_interval_secs,
+            ipc_pipe,
         }
     }
 }
@@ -43,7 +123,11 @@ impl WatchdogConfig {
 struct HealthProbe {
     consecutive_failures: u32,
     restart_attempts: u32,
+    /// Restarts the strategy reported as failed during this run; not persisted.
+    restart_failures: u32,
     last_status: Option<HealthStatus>,
+    last_restart_unix_ms: Option<u64>,
+    ladder: LadderProgress,
 }
 
 #[derive(Debug, Clone)]
@@ -58,28 +142,71 @@ impl HealthProbe {
         Self {
             consecutive_failures: 0,
             restart_attempts: 0,
+            restart_failures: 0,
             last_status: None,
+            last_restart_unix_ms: None,
+            ladder: LadderProgress::default(),
+        }
+    }
+
+    /// Probe saved by a previous watchdog run, so restart attempts survive upgrades of
+    /// the watchdog itself.
+    fn restore(config: &WatchdogConfig) -> Self {
+        let restored = state::load(
+            &config.state_path,
+            state::unix_time_ms(),
+            config.state_max_age_secs.saturating_mul(1000),
+            config.attempt_ttl_secs.saturating_mul(1000),
+        );
+        match restored {
+            Ok(Some(mut probe)) => {
+                probe.ladder.fit(config.ladder.steps.len());
+                info!(
+                    restart_attempts = probe.restart_attempts,
+                    consecutive_failures = probe.consecutive_failures,
+                    "restored watchdog state"
+                );
+                probe
+            }
+            Ok(None) => Self::new(),
+            Err(err) => {
+                warn!(error = %err, path = %config.state_path.display(), "ignoring unreadable watchdog state");
+                Self::new()
+            }
+        }
+    }
+
+    fn persist(&self, config: &WatchdogConfig) {
+        if let Err(err) = state::save(&config.state_path, self, state::unix_time_ms()) {
+            warn!(error = %err, path = %config.state_path.display(), "failed to persist watchdog state");
         }
     }
 }
 
 #[tokio::main]
 async fn main() {
-    tracing_subscriber::fmt()
-        .with_env_filter("info")
-        .init();
+    agent_logging::init("agent-watchdog");
 
     info!("agent watchdog starting");
 
     let config = WatchdogConfig::from_env();
-    let mut probe = HealthProbe::new();
+    let mut probe = HealthProbe::restore(&config);
+    let mut healthy_log = HealthyLogSampler::new(config.healthy_log_interval_secs.saturating_mul(1000));
 
     info!(
         interval_secs = config.interval_secs,
         grace_misses = config.grace_misses,
         max_restart_attempts = config.max_restart_attempts,
+        escalation_steps = config.ladder.steps.len(),
+        restart_strategy = config.restart.name(),
         "watchdog configuration loaded"
     );
+    if let Some(status) = verify_agent_binary(&config) {
+        warn!(status = ?status, "agent-core binary failed integrity check at startup");
+    }
+    if let Some(pipe_name) = config.ipc_pipe.clone() {
+        tokio::spawn(report_to_core(pipe_name, Duration::from_secs(config.interval_secs)));
+    }
 
     loop {
         tokio::select! {
@@ -88,8 +215,9 @@ async fn main() {
                 break;
             }
             _ = tokio::time::sleep(Duration::from_secs(config.interval_secs)) => {
-                let status = check_agent_core_health();
-                handle_status(&mut probe, &config, status);
+                let status = check_agent_core_health(&config);
+                handle_status(&mut probe, &config, status, &mut healthy_log).await;
+                probe.persist(&config);
             }
         }
     }
@@ -97,11 +225,29 @@ async fn main() {
     info!("agent watchdog stopping");
 }
 
-fn check_agent_core_health() -> HealthStatus {
-    let mode = env::var("WATCHDOG_HEALTH_MODE")
+/// Send a heartbeat to agent-core over IPC every `interval`. The client reconnects with
+/// backoff on its own, so an agent-core restart only delays the next heartbeat, and this
+/// runs apart from the health checks so a slow connection cannot stall them.
+async fn report_to_core(pipe_name: String, interval: Duration) {
+    let mut client = IpcClient::new(&pipe_name, IpcClientConfig::from_env())
+        .on_state_change(|state| info!(state = ?state, "agent-core ipc connection changed"));
+    loop {
+        if let Err(err) = client.send_heartbeat("agent-watchdog").await {
+            warn!(error = %err, "failed to send watchdog heartbeat to agent-core");
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
+
+/// Reads agent-core's liveness file. `WATCHDOG_HEALTH_MODE` forces a status for testing.
+fn check_agent_core_health(config: &WatchdogConfig) -> HealthStatus {
+    let Some(mode) = env::var("WATCHDOG_HEALTH_MODE")
         .ok()
-        .unwrap_or_else(|| "healthy".to_string())
-        .to_lowercase();
+        .map(|value| value.trim().to_lowercase())
+        .filter(|value| !value.is_empty())
+    else {
+        return read_liveness(&config.liveness.path, config.liveness.max_age_ms, state::unix_time_ms());
+    };
 
     match mode.as_str() {
         "degraded" => HealthStatus::Degraded {
@@ -114,13 +260,20 @@ fn check_agent_core_health() -> HealthStatus {
     }
 }
 
-fn handle_status(probe: &mut HealthProbe, config: &WatchdogConfig, status: HealthStatus) {
-    probe.last_status = Some(status.clone());
+/// Failures are always logged; healthy ticks go through `healthy_log`.
+async fn handle_status(
+    probe: &mut HealthProbe,
+    config: &WatchdogConfig,
+    status: HealthStatus,
+    healthy_log: &mut HealthyLogSampler,
+) {
+    let previous = probe.last_status.replace(status.clone());
 
     match status {
         HealthStatus::Healthy => {
             probe.consecutive_failures = 0;
-            info!("watchdog heartbeat healthy");
+            let recovered = previous.is_some_and(|previous| !matches!(previous, HealthStatus::Healthy));
+            healthy_log.healthy(recovered, state::unix_time_ms());
         }
         HealthStatus::Degraded { reason } => {
             probe.consecutive_failures = probe.consecutive_failures.saturating_add(1);
@@ -129,7 +282,7 @@ fn handle_status(probe: &mut HealthProbe, config: &WatchdogConfig, status: Healt
                 reason = %reason,
                 "watchdog detected degraded state"
             );
-            maybe_restart_agent_core(probe, config, "Degraded state");
+            maybe_restart_agent_core(probe, config, "Degraded state").await;
         }
         HealthStatus::Unreachable { reason } => {
             probe.consecutive_failures = probe.consecutive_failures.saturating_add(1);
@@ -138,28 +291,104 @@ fn handle_status(probe: &mut HealthProbe, config: &WatchdogConfig, status: Healt
                 reason = %reason,
                 "watchdog detected unreachable state"
             );
-            maybe_restart_agent_core(probe, config, "Unreachable state");
+            maybe_restart_agent_core(probe, config, "Unreachable state").await;
         }
     }
 }
 
-fn maybe_restart_agent_core(probe: &mut HealthProbe, config: &WatchdogConfig, reason: &str) {
-    if probe.consecutive_failures <= config.grace_misses {
-        return;
+/// Take the next due step of the escalation ladder, if any.
+async fn maybe_restart_agent_core(probe: &mut HealthProbe, config: &WatchdogConfig, reason: &str) {
+    let now_ms = state::unix_time_ms();
+    match config.ladder.decide(&probe.ladder, probe.consecutive_failures, now_ms) {
+        LadderDecision::Wait => {}
+        LadderDecision::CoolingDown { step, remaining_ms } => {
+            info!(step, remaining_ms, reason, "escalation step cooling down");
+        }
+        LadderDecision::Exhausted => {
+            warn!(
+                reason,
+                runbook = config.runbook_url.as_deref().unwrap_or("not-configured"),
+                "escalation ladder exhausted"
+            );
+        }
+        LadderDecision::Run { step } => {
+            probe.ladder.record(step, now_ms);
+            run_action(probe, config, &config.ladder.steps[step].action, reason, now_ms).await;
+        }
     }
-    if probe.restart_attempts >= config.max_restart_attempts {
-        warn!(
-            reason,
-            runbook = config.runbook_url.as_deref().unwrap_or("not-configured"),
-            "restart limit reached; escalation required"
-        );
-        return;
+}
+
+async fn run_action(
+    probe: &mut HealthProbe,
+    config: &WatchdogConfig,
+    action: &EscalationAction,
+    reason: &str,
+    now_ms: u64,
+) {
+    match action {
+        EscalationAction::RestartService => {
+            if let Some(status) = verify_agent_binary(config) {
+                warn!(status = ?status, reason, "agent-core binary failed integrity check; restart suppressed");
+                escalate(probe, config, "agent-core binary integrity check failed");
+                return;
+            }
+            probe.restart_attempts = probe.restart_attempts.saturating_add(1);
+            probe.last_restart_unix_ms = Some(now_ms);
+            info!(
+                attempt = probe.restart_attempts,
+                strategy = config.restart.name(),
+                reason,
+                "issuing agent-core restart request"
+            );
+            match config.restart.restart().await {
+                RestartOutcome::Restarted => info!(attempt = probe.restart_attempts, "agent-core restart issued"),
+                RestartOutcome::Simulated => {}
+                RestartOutcome::Failed { reason: failure } => {
+                    probe.restart_failures = probe.restart_failures.saturating_add(1);
+                    warn!(
+                        attempt = probe.restart_attempts,
+                        failures = probe.restart_failures,
+                        error = %failure,
+                        "agent-core restart failed"
+                    );
+                }
+            }
+        }
+        EscalationAction::RunScript { path } => {
+            info!(script = %path.display(), reason, "running escalation script");
+            let path = path.clone();
+            // Waited on off the check loop so a hung script cannot stall health checks.
+            std::thread::spawn(move || match std::process::Command::new(&path).status() {
+                Ok(status) if status.success() => info!(script = %path.display(), "escalation script finished"),
+                Ok(status) => warn!(script = %path.display(), %status, "escalation script failed"),
+                Err(err) => warn!(script = %path.display(), error = %err, "escalation script could not start"),
+            });
+        }
+        EscalationAction::Escalate => escalate(probe, config, reason),
     }
+}
 
-    probe.restart_attempts = probe.restart_attempts.saturating_add(1);
-    info!(
-        attempt = probe.restart_attempts,
+fn escalate(probe: &HealthProbe, config: &WatchdogConfig, reason: &str) {
+    warn!(
         reason,
-        "issuing agent-core restart request (placeholder)"
+        restart_attempts = probe.restart_attempts,
+        runbook = config.runbook_url.as_deref().unwrap_or("not-configured"),
+        "restart limit reached; escalation required"
     );
 }
+
+/// Check the agent-core executable, queueing tamper evidence on a hash mismatch. Returns the
+/// failing status when a restart must not go ahead.
+fn verify_agent_binary(config: &WatchdogConfig) -> Option<IntegrityStatus> {
+    let status = check_binary(&config.integrity);
+    if status.allows_restart() {
+        return None;
+    }
+    if let IntegrityStatus::Mismatch { actual } = &status {
+        match write_tamper_record(&config.integrity, actual, state::unix_time_ms()) {
+            Ok(path) => info!(path = %path.display(), "queued agent-core tamper evidence"),
+            Err(err) => warn!(error = %err, "failed to queue agent-core tamper evidence"),
+        }
+    }
+    Some(status)
+}
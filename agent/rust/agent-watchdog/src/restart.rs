@@ -0,0 +1,258 @@
+use std::env;
+use std::fmt;
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::process::Command;
+use tracing::{info, warn};
+
+/// What a restart request came to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RestartOutcome {
+    Restarted,
+    /// Logged only; nothing was restarted.
+    Simulated,
+    Failed { reason: String },
+}
+
+pub type RestartFuture<'a> = Pin<Box<dyn Future<Output = RestartOutcome> + Send + 'a>>;
+
+/// Platform-specific way of bringing agent-core back. Restarts are awaited on the check
+/// loop, so implementations must not block the runtime.
+pub trait RestartStrategy: fmt::Debug + Send + Sync {
+    fn name(&self) -> &'static str;
+    fn restart(&self) -> RestartFuture<'_>;
+}
+
+/// `systemctl restart --no-block <unit>`; the watchdog does not wait for the unit to start.
+#[derive(Debug, Clone)]
+pub struct SystemdRestart {
+    pub unit: String,
+    /// How long `systemctl` may run before it is killed and the restart counted as failed.
+    pub timeout: Duration,
+}
+
+impl RestartStrategy for SystemdRestart {
+    fn name(&self) -> &'static str {
+        "systemd"
+    }
+
+    fn restart(&self) -> RestartFuture<'_> {
+        Box::pin(async move {
+            run_to_completion(Command::new("systemctl").args(["restart", "--no-block", &self.unit]), self.timeout).await
+        })
+    }
+}
+
+/// Stops then starts the service through the Service Control Manager. `net` waits for each
+/// transition, unlike `sc.exe`.
+#[derive(Debug, Clone)]
+pub struct ScmRestart {
+    pub service: String,
+    /// Applied to `net stop` and `net start` separately.
+    pub timeout: Duration,
+}
+
+impl RestartStrategy for ScmRestart {
+    fn name(&self) -> &'static str {
+        "scm"
+    }
+
+    fn restart(&self) -> RestartFuture<'_> {
+        Box::pin(async move {
+            // A service that already stopped fails `net stop`; starting it is still the goal.
+            let stopped = run_to_completion(Command::new("net").args(["stop", &self.service]), self.timeout).await;
+            if let RestartOutcome::Failed { reason } = stopped {
+                warn!(service = %self.service, reason = %reason, "service stop failed; starting anyway");
+            }
+            run_to_completion(Command::new("net").args(["start", &self.service]), self.timeout).await
+        })
+    }
+}
+
+/// Launches agent-core directly, for containers without a service manager. The child is
+/// detached from the check loop and reaped on a background thread.
+#[derive(Debug, Clone)]
+pub struct ExecRestart {
+    pub program: PathBuf,
+    pub args: Vec<String>,
+}
+
+impl RestartStrategy for ExecRestart {
+    fn name(&self) -> &'static str {
+        "exec"
+    }
+
+    fn restart(&self) -> RestartFuture<'_> {
+        let spawned = std::process::Command::new(&self.program)
+            .args(&self.args)
+            .stdin(Stdio::null())
+            .spawn();
+        let outcome = match spawned {
+            Ok(mut child) => {
+                info!(pid = child.id(), program = %self.program.display(), "agent-core launched");
+                std::thread::spawn(move || child.wait());
+                RestartOutcome::Restarted
+            }
+            Err(err) => RestartOutcome::Failed {
+                reason: format!("{}: {}", self.program.display(), err),
+            },
+        };
+        Box::pin(std::future::ready(outcome))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DryRunRestart;
+
+impl RestartStrategy for DryRunRestart {
+    fn name(&self) -> &'static str {
+        "dry-run"
+    }
+
+    fn restart(&self) -> RestartFuture<'_> {
+        info!("dry-run restart strategy; agent-core left running");
+        Box::pin(std::future::ready(RestartOutcome::Simulated))
+    }
+}
+
+/// Pick the strategy named by `WATCHDOG_RESTART_STRATEGY` (default `dry-run`).
+/// `WATCHDOG_SERVICE_NAME` (default `tamsil-agent-core`) names the systemd unit or Windows
+/// service; `WATCHDOG_EXEC_COMMAND` is the whitespace-separated command for `exec`.
+/// `WATCHDOG_RESTART_TIMEOUT_SECS` (default 60) bounds each service manager call.
+pub fn strategy_from_env() -> Result<Arc<dyn RestartStrategy>, String> {
+    let name = env::var("WATCHDOG_RESTART_STRATEGY").unwrap_or_default();
+    let service = env::var("WATCHDOG_SERVICE_NAME")
+        .ok()
+        .filter(|value| !value.trim().is_empty())
+        .unwrap_or_else(|| "tamsil-agent-core".to_string());
+    let exec_command = env::var("WATCHDOG_EXEC_COMMAND").unwrap_or_default();
+    let timeout_secs = env::var("WATCHDOG_RESTART_TIMEOUT_SECS")
+        .ok()
+        .and_then(|value| value.parse::<u64>().ok())
+        .filter(|value| *value > 0)
+        .unwrap_or(60);
+    select_strategy(&name, &service, &exec_command, Duration::from_secs(timeout_secs))
+}
+
+pub fn select_strategy(
+    name: &str,
+    service: &str,
+    exec_command: &str,
+    timeout: Duration,
+) -> Result<Arc<dyn RestartStrategy>, String> {
+    match name.trim().to_ascii_lowercase().as_str() {
+        "" | "dry-run" => Ok(Arc::new(DryRunRestart)),
+        "systemd" => Ok(Arc::new(SystemdRestart {
+            unit: service.to_string(),
+            timeout,
+        })),
+        "scm" => Ok(Arc::new(ScmRestart {
+            service: service.to_string(),
+            timeout,
+        })),
+        "exec" => {
+            let mut parts = exec_command.split_whitespace();
+            let program = parts.next().ok_or("exec strategy needs WATCHDOG_EXEC_COMMAND")?;
+            Ok(Arc::new(ExecRestart {
+                program: PathBuf::from(program),
+                args: parts.map(str::to_string).collect(),
+            }))
+        }
+        other => Err(format!("unknown restart strategy {}", other)),
+    }
+}
+
+/// Run `command` to completion, killing it once `timeout` passes so a hung service manager
+/// cannot hold up the check loop.
+async fn run_to_completion(command: &mut Command, timeout: Duration) -> RestartOutcome {
+    let spawned = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .kill_on_drop(true)
+        .spawn();
+    let mut child = match spawned {
+        Ok(child) => child,
+        Err(err) => return RestartOutcome::Failed { reason: err.to_string() },
+    };
+    match tokio::time::timeout(timeout, child.wait()).await {
+        Ok(Ok(status)) if status.success() => RestartOutcome::Restarted,
+        Ok(Ok(status)) => RestartOutcome::Failed {
+            reason: format!("exited with {}", status),
+        },
+        Ok(Err(err)) => RestartOutcome::Failed { reason: err.to_string() },
+        Err(_) => {
+            if let Err(err) = child.kill().await {
+                warn!(error = %err, "failed to kill timed-out restart command");
+            }
+            RestartOutcome::Failed {
+                reason: format!("timed out after {} s", timeout.as_secs_f64()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    use tokio::process::Command;
+
+    use super::{run_to_completion, select_strategy, DryRunRestart, ExecRestart, RestartOutcome, RestartStrategy};
+
+    const TIMEOUT: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn selects_strategy_by_name() {
+        assert_eq!(select_strategy("", "agent", "", TIMEOUT).expect("default").name(), "dry-run");
+        assert_eq!(select_strategy("Systemd", "agent", "", TIMEOUT).expect("systemd").name(), "systemd");
+        assert_eq!(select_strategy("scm", "agent", "", TIMEOUT).expect("scm").name(), "scm");
+        assert_eq!(
+            select_strategy("exec", "agent", "/opt/tamsil/agent-core --foreground", TIMEOUT)
+                .expect("exec")
+                .name(),
+            "exec"
+        );
+        assert!(select_strategy("exec", "agent", "  ", TIMEOUT).is_err());
+        assert!(select_strategy("reboot", "agent", "", TIMEOUT).is_err());
+    }
+
+    #[tokio::test]
+    async fn dry_run_only_simulates() {
+        assert_eq!(DryRunRestart.restart().await, RestartOutcome::Simulated);
+    }
+
+    #[tokio::test]
+    async fn exec_launches_the_command_and_reports_spawn_failures() {
+        let launched = ExecRestart {
+            program: std::env::current_exe().expect("test binary"),
+            args: vec!["--exact".to_string(), "no_such_test".to_string()],
+        };
+        assert_eq!(launched.restart().await, RestartOutcome::Restarted);
+
+        let missing = ExecRestart {
+            program: PathBuf::from("/nonexistent/agent-core"),
+            args: Vec::new(),
+        };
+        assert!(matches!(missing.restart().await, RestartOutcome::Failed { .. }));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn hung_service_manager_call_times_out() {
+        let started = std::time::Instant::now();
+        let outcome = run_to_completion(Command::new("sleep").arg("30"), Duration::from_millis(200)).await;
+        assert!(matches!(outcome, RestartOutcome::Failed { reason } if reason.starts_with("timed out")));
+        assert!(started.elapsed() < Duration::from_secs(10));
+
+        let finished = run_to_completion(&mut Command::new("true"), TIMEOUT).await;
+        assert_eq!(finished, RestartOutcome::Restarted);
+        let failed = run_to_completion(&mut Command::new("false"), TIMEOUT).await;
+        assert!(matches!(failed, RestartOutcome::Failed { reason } if reason.starts_with("exited with")));
+    }
+}
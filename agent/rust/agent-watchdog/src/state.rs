@@ -0,0 +1,243 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::escalation::LadderProgress;
+use crate::{HealthProbe, HealthStatus};
+
+const STATE_VERSION: &str = "1";
+
+pub fn unix_time_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// RFC 3339 UTC timestamp with millisecond precision, matching agent-core's queue items.
+pub fn format_rfc3339_ms(unix_ms: u64) -> String {
+    let seconds = unix_ms / 1000;
+    let seconds_of_day = seconds % 86_400;
+    let (year, month, day) = civil_from_days((seconds / 86_400) as i64);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        seconds_of_day / 3600,
+        (seconds_of_day % 3600) / 60,
+        seconds_of_day % 60,
+        unix_ms % 1000
+    )
+}
+
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Write `probe` as `key=value` lines, replacing the previous file atomically.
+pub fn save(path: &Path, probe: &HealthProbe, now_ms: u64) -> io::Result<()> {
+    let (status, reason) = match &probe.last_status {
+        None => ("none", ""),
+        Some(HealthStatus::Healthy) => ("healthy", ""),
+        Some(HealthStatus::Degraded { reason }) => ("degraded", reason.as_str()),
+        Some(HealthStatus::Unreachable { reason }) => ("unreachable", reason.as_str()),
+    };
+    let contents = format!(
+        "version={}\nsaved_at_unix_ms={}\nconsecutive_failures={}\nrestart_attempts={}\nlast_restart_unix_ms={}\nlast_status={}\nlast_status_reason={}\nladder_attempts={}\nladder_last_run_unix_ms={}\n",
+        STATE_VERSION,
+        now_ms,
+        probe.consecutive_failures,
+        probe.restart_attempts,
+        probe.last_restart_unix_ms.map(|value| value.to_string()).unwrap_or_default(),
+        status,
+        reason.replace(['\r', '\n'], " "),
+        join(probe.ladder.attempts.iter().map(u32::to_string)),
+        join(
+            probe
+                .ladder
+                .last_run_unix_ms
+                .iter()
+                .map(|value| value.map(|value| value.to_string()).unwrap_or_default())
+        ),
+    );
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    let staging = path.with_extension("tmp");
+    fs::write(&staging, contents)?;
+    fs::rename(&staging, path)
+}
+
+/// Restore the probe saved at `path`. A missing file or one saved more than
+/// `max_age_ms` ago yields `None`; restart attempts only carry over while the last
+/// restart is younger than `attempt_ttl_ms`.
+pub fn load(path: &Path, now_ms: u64, max_age_ms: u64, attempt_ttl_ms: u64) -> Result<Option<HealthProbe>, String> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(format!("read {}: {}", path.display(), err)),
+    };
+    parse(&contents, now_ms, max_age_ms, attempt_ttl_ms)
+}
+
+fn parse(contents: &str, now_ms: u64, max_age_ms: u64, attempt_ttl_ms: u64) -> Result<Option<HealthProbe>, String> {
+    let field = |key: &str| {
+        contents
+            .lines()
+            .find_map(|line| line.strip_prefix(key).and_then(|rest| rest.strip_prefix('=')))
+    };
+    let number = |key: &str| -> Result<u64, String> {
+        field(key)
+            .ok_or_else(|| format!("missing {}", key))?
+            .parse::<u64>()
+            .map_err(|_| format!("invalid {}", key))
+    };
+
+    if field("version") != Some(STATE_VERSION) {
+        return Err("unsupported state version".to_string());
+    }
+    let saved_at = number("saved_at_unix_ms")?;
+    if now_ms.saturating_sub(saved_at) > max_age_ms {
+        return Ok(None);
+    }
+
+    let last_restart_unix_ms = match field("last_restart_unix_ms") {
+        None | Some("") => None,
+        Some(value) => Some(value.parse::<u64>().map_err(|_| "invalid last_restart_unix_ms")?),
+    };
+    let restart_attempts = match last_restart_unix_ms {
+        Some(at) if now_ms.saturating_sub(at) < attempt_ttl_ms => number("restart_attempts")? as u32,
+        _ => 0,
+    };
+    // Like restart attempts, ladder progress is forgotten once its last step is older than
+    // the TTL. Files from before the ladder existed carry no progress.
+    let mut ladder = LadderProgress {
+        attempts: split(field("ladder_attempts"))
+            .map(|value| value.parse::<u32>().map_err(|_| "invalid ladder_attempts"))
+            .collect::<Result<Vec<u32>, _>>()?,
+        last_run_unix_ms: split(field("ladder_last_run_unix_ms"))
+            .map(|value| match value {
+                "" => Ok(None),
+                value => value.parse::<u64>().map(Some).map_err(|_| "invalid ladder_last_run_unix_ms"),
+            })
+            .collect::<Result<Vec<Option<u64>>, _>>()?,
+    };
+    if ladder
+        .latest_run_unix_ms()
+        .is_none_or(|at| now_ms.saturating_sub(at) >= attempt_ttl_ms)
+    {
+        ladder = LadderProgress::default();
+    }
+    ladder.fit(ladder.attempts.len().max(ladder.last_run_unix_ms.len()));
+    let reason = field("last_status_reason").unwrap_or_default().to_string();
+    let last_status = match field("last_status") {
+        Some("healthy") => Some(HealthStatus::Healthy),
+        Some("degraded") => Some(HealthStatus::Degraded { reason }),
+        Some("unreachable") => Some(HealthStatus::Unreachable { reason }),
+        Some("none") | None => None,
+        Some(other) => return Err(format!("unknown last_status {}", other)),
+    };
+
+    Ok(Some(HealthProbe {
+        consecutive_failures: number("consecutive_failures")? as u32,
+        restart_attempts,
+        restart_failures: 0,
+        last_status,
+        last_restart_unix_ms,
+        ladder,
+    }))
+}
+
+fn join(values: impl Iterator<Item = String>) -> String {
+    values.collect::<Vec<String>>().join(",")
+}
+
+fn split(value: Option<&str>) -> impl Iterator<Item = &str> {
+    value.filter(|value| !value.is_empty()).into_iter().flat_map(|value| value.split(','))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state_path(label: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!(
+            "watchdog-state-{}-{}-{}",
+            label,
+            std::process::id(),
+            unix_time_ms()
+        ))
+    }
+
+    #[test]
+    fn round_trips_probe_state() {
+        let path = state_path("round-trip");
+        let probe = HealthProbe {
+            consecutive_failures: 5,
+            restart_attempts: 2,
+            restart_failures: 0,
+            last_status: Some(HealthStatus::Unreachable {
+                reason: "heartbeat\nmissing".to_string(),
+            }),
+            last_restart_unix_ms: Some(9_000),
+            ladder: LadderProgress {
+                attempts: vec![2, 1],
+                last_run_unix_ms: vec![Some(9_000), None],
+            },
+        };
+        save(&path, &probe, 10_000).expect("save state");
+
+        let restored = load(&path, 11_000, 60_000, 60_000)
+            .expect("load state")
+            .expect("fresh state");
+        assert_eq!(restored.consecutive_failures, 5);
+        assert_eq!(restored.restart_attempts, 2);
+        assert_eq!(restored.last_restart_unix_ms, Some(9_000));
+        assert_eq!(restored.ladder, probe.ladder);
+        assert!(matches!(
+            restored.last_status,
+            Some(HealthStatus::Unreachable { ref reason }) if reason == "heartbeat missing"
+        ));
+
+        // Attempts older than the TTL are forgotten; the rest of the state is kept.
+        let expired = load(&path, 11_000, 60_000, 1_000).expect("load state").expect("fresh state");
+        assert_eq!(expired.restart_attempts, 0);
+        assert_eq!(expired.ladder, LadderProgress::default());
+        assert_eq!(expired.consecutive_failures, 5);
+
+        let _ = fs::remove_file(&path);
+        assert!(load(&path, 11_000, 60_000, 60_000).expect("missing file").is_none());
+    }
+
+    #[test]
+    fn ignores_state_older_than_staleness_bound() {
+        let path = state_path("stale");
+        let probe = HealthProbe {
+            consecutive_failures: 1,
+            restart_attempts: 3,
+            restart_failures: 0,
+            last_status: Some(HealthStatus::Healthy),
+            last_restart_unix_ms: Some(1_000),
+            ladder: LadderProgress::default(),
+        };
+        save(&path, &probe, 1_000).expect("save state");
+
+        assert!(load(&path, 31_000, 30_000, u64::MAX).expect("load state").is_some());
+        assert!(load(&path, 31_001, 30_000, u64::MAX).expect("load state").is_none());
+
+        fs::write(&path, "version=9\n").expect("write state");
+        assert!(load(&path, 1_000, 30_000, u64::MAX).is_err());
+        let _ = fs::remove_file(&path);
+    }
+}